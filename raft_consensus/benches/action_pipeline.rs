@@ -0,0 +1,48 @@
+/// Measures how long a node takes to handle the events of its hot loop, with the action buffer
+/// reused across events as the Raft thread does and with a fresh buffer per event for comparison.
+///
+/// Run with `cargo bench -p raft_consensus --features bench_internals --bench action_pipeline`
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use raft_consensus::bench_internals::NodeBench;
+use tempfile::TempDir;
+
+const CLUSTER_SIZE: u64 = 5;
+
+fn leader_tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("leader_tick_sending_heartbeats");
+    for reuse_buffer in [true, false] {
+        let storage_dir = TempDir::new().unwrap();
+        let mut bench = NodeBench::leader(storage_dir.path(), CLUSTER_SIZE);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buffer_name(reuse_buffer)),
+            &reuse_buffer,
+            |b, reuse_buffer| b.iter(|| black_box(bench.handle_tick(*reuse_buffer))),
+        );
+    }
+    group.finish();
+}
+
+fn follower_heartbeat(c: &mut Criterion) {
+    let mut group = c.benchmark_group("follower_receiving_heartbeat");
+    for reuse_buffer in [true, false] {
+        let storage_dir = TempDir::new().unwrap();
+        let mut bench = NodeBench::follower(storage_dir.path(), CLUSTER_SIZE);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buffer_name(reuse_buffer)),
+            &reuse_buffer,
+            |b, reuse_buffer| b.iter(|| black_box(bench.handle_heartbeat(*reuse_buffer))),
+        );
+    }
+    group.finish();
+}
+
+fn buffer_name(reuse_buffer: bool) -> &'static str {
+    if reuse_buffer {
+        "reused_buffer"
+    } else {
+        "fresh_buffer"
+    }
+}
+
+criterion_group!(benches, leader_tick, follower_heartbeat);
+criterion_main!(benches);
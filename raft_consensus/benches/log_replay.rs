@@ -0,0 +1,56 @@
+/// Measures how long a restarting node takes to replay its log from disk, the bulk of its startup
+/// time once the log holds millions of entries. Set `LOG_REPLAY_ENTRIES` to try other log sizes.
+///
+/// Run with `cargo bench -p raft_consensus --features bench_internals --bench log_replay`
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use raft_consensus::bench_internals::DefaultPersistentStorage;
+use raft_consensus::{LogEntry, LogEntryCommand, LogIndex, PersistentStorage, TermIndex};
+use std::path::Path;
+use tempfile::TempDir;
+
+const DEFAULT_ENTRIES: u64 = 2_000_000;
+
+/// Entries are appended and synced in chunks so writing the log doesn't dominate the setup
+const WRITE_CHUNK_ENTRIES: u64 = 100_000;
+
+fn write_log(storage_path: &Path, entries: u64) {
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_path).unwrap();
+    for chunk_start in (1..=entries).step_by(WRITE_CHUNK_ENTRIES as usize) {
+        let chunk_end = (chunk_start + WRITE_CHUNK_ENTRIES).min(entries + 1);
+        let chunk = (chunk_start..chunk_end)
+            .map(|index| LogEntry {
+                index: LogIndex(index),
+                term: TermIndex(1 + index / 10_000),
+                command: LogEntryCommand::Application(index),
+            })
+            .collect();
+        storage.append(chunk).sync().unwrap();
+    }
+}
+
+fn log_replay(c: &mut Criterion) {
+    let entries = std::env::var("LOG_REPLAY_ENTRIES")
+        .ok()
+        .and_then(|entries| entries.parse().ok())
+        .unwrap_or(DEFAULT_ENTRIES);
+    let storage_dir = TempDir::new().unwrap();
+    write_log(storage_dir.path(), entries);
+
+    let mut group = c.benchmark_group("log_replay");
+    group.sample_size(10);
+    group.bench_with_input(
+        BenchmarkId::new("open_storage", entries),
+        &entries,
+        |b, &entries| {
+            b.iter(|| {
+                let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+                assert_eq!(storage.last_entry_index(), Some(LogIndex(entries)));
+                storage
+            })
+        },
+    );
+    group.finish();
+}
+
+criterion_group!(benches, log_replay);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+/// Measures `Node::next` for the events a leader and a follower handle in steady state, one at a
+/// time and mixed the way they arrive during a heartbeat round.
+///
+/// Run with `cargo bench -p raft_consensus --features bench_internals --bench node_events`
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use raft_consensus::bench_internals::NodeBench;
+use tempfile::TempDir;
+
+const CLUSTER_SIZE: u64 = 5;
+
+fn leader_events(c: &mut Criterion) {
+    let storage_dir = TempDir::new().unwrap();
+    let mut leader = NodeBench::leader(storage_dir.path(), CLUSTER_SIZE);
+
+    let mut group = c.benchmark_group("leader");
+    group.bench_function("tick", |b| b.iter(|| black_box(leader.handle_tick(true))));
+    group.bench_function("append_entries_ack", |b| {
+        b.iter(|| black_box(leader.handle_append_entries_ack(true)))
+    });
+    group.bench_function("stale_vote_request", |b| {
+        b.iter(|| black_box(leader.handle_stale_vote_request(true)))
+    });
+    // A heartbeat round: one tick fanning out to every follower, then an ack back from each
+    group.bench_function("heartbeat_round", |b| {
+        b.iter(|| {
+            let mut produced = leader.handle_tick(true);
+            for _ in 1..CLUSTER_SIZE {
+                produced += leader.handle_append_entries_ack(true);
+            }
+            black_box(produced)
+        })
+    });
+    group.finish();
+}
+
+fn follower_events(c: &mut Criterion) {
+    let storage_dir = TempDir::new().unwrap();
+    let mut follower = NodeBench::follower(storage_dir.path(), CLUSTER_SIZE);
+
+    let mut group = c.benchmark_group("follower");
+    group.bench_function("heartbeat", |b| {
+        b.iter(|| black_box(follower.handle_heartbeat(true)))
+    });
+    group.bench_function("tick", |b| b.iter(|| black_box(follower.handle_tick(true))));
+    // Heartbeats and ticks interleave, with the odd vote request from a partitioned candidate
+    group.bench_function("mixed", |b| {
+        b.iter(|| {
+            black_box(
+                follower.handle_heartbeat(true)
+                    + follower.handle_tick(true)
+                    + follower.handle_heartbeat(true)
+                    + follower.handle_stale_vote_request(true),
+            )
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, leader_events, follower_events);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+/// Measures persisting the election state, the write that sits on the critical path of every
+/// term change and vote. Log entry appends aren't persisted yet, bench them here once they are.
+///
+/// Run with `cargo bench -p raft_consensus --features bench_internals --bench storage_sync`
+use criterion::{criterion_group, criterion_main, Criterion};
+use raft_consensus::bench_internals::DefaultPersistentStorage;
+use raft_consensus::{PersistentStorage, ServerId};
+use tempfile::TempDir;
+
+fn election_state_sync(c: &mut Criterion) {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+
+    c.bench_function("storage_update_term_and_vote_then_sync", |b| {
+        b.iter(|| {
+            let next_term = storage.current_term().increment();
+            storage
+                .update_term(next_term)
+                .record_vote(ServerId(1))
+                .sync()
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, election_state_sync);
+criterion_main!(benches);
@@ -0,0 +1,205 @@
+use crate::common::{ApplicationThatNeedsConsensus, RaftTransportConnector};
+use crate::crash_reporting;
+use crate::diagnostics::RaftDiagnostics;
+use crate::events::{RaftStateEventCollector, RecentEvents};
+use crate::metrics::RaftMetrics;
+use crate::raft_thread::{
+    hosted_runtime, node_channels, EventPublisher, HostedExit, NodeLoop, NodeRuntime, NodeStatus,
+    NodeStorage, RaftNodeHandle, RECENT_EVENTS_CAPACITY,
+};
+use raft_core::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_core::system_clock::Clock;
+use raft_core::{LogCommand, RaftConfig, RaftTransportError, ServerId};
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{info_span, Instrument};
+
+/// The network transport of a node running as a task on a tokio runtime, see
+/// [`crate::RaftNodeBuilder::start_async`]. Like [`RaftTransportConnector`], except the node
+/// awaits incoming messages instead of blocking its thread on them.
+pub trait AsyncRaftTransport<C: LogCommand>: Send {
+    /// Waits for the next incoming message. The node drops the future once its timer runs out
+    /// first, so it has to be cancel safe, like receiving from a tokio channel is.
+    fn next_incoming_message(
+        &mut self,
+    ) -> impl Future<Output = Result<RpcMessage<C>, RaftTransportError>> + Send;
+
+    /// Enqueues a reply to be sent to the given server.
+    fn enqueue_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError>;
+
+    /// Enqueues a request to be sent to the given server.
+    fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError>;
+}
+
+/// Hands the node the message its task awaited last, the node never waits on it itself
+struct AsyncConnector<C: LogCommand, T: AsyncRaftTransport<C>> {
+    transport: T,
+    next_message: Option<Result<RpcMessage<C>, RaftTransportError>>,
+}
+impl<C: LogCommand, T: AsyncRaftTransport<C>> RaftTransportConnector<C> for AsyncConnector<C, T> {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        _max_wait: Duration,
+    ) -> Result<Option<RpcMessage<C>>, RaftTransportError> {
+        self.next_message.take().transpose()
+    }
+
+    fn enqueue_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        self.transport.enqueue_reply(reply)
+    }
+
+    fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError> {
+        self.transport.enqueue_outgoing_request(request)
+    }
+}
+
+/// Starts a node as a task on `runtime` with everything a [`crate::RaftNodeBuilder`] collected
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn spawn_raft_task<LC, A, T, E>(
+    runtime: &tokio::runtime::Handle,
+    server_id: ServerId,
+    other_servers: HashSet<ServerId>,
+    storage: NodeStorage<LC>,
+    audit_log_dir: Option<String>,
+    application: A,
+    config: RaftConfig,
+    rng: ChaCha8Rng,
+    clock: Box<dyn Clock>,
+    transport: T,
+    event_collector: E,
+    metrics: RaftMetrics,
+) -> RaftNodeHandle<A>
+where
+    LC: LogCommand + 'static,
+    A: ApplicationThatNeedsConsensus<Command = LC> + 'static,
+    T: AsyncRaftTransport<LC> + 'static,
+    E: RaftStateEventCollector + 'static,
+{
+    let max_entry_bytes = config.max_entry_bytes();
+    let (inbox, senders) = node_channels();
+    let diagnostics = RaftDiagnostics::default();
+    let recent_events = RecentEvents::new(RECENT_EVENTS_CAPACITY);
+    crash_reporting::install_panic_hook();
+    // The task has no thread of its own, the one starting it stands in for it
+    let (hosted, exit) = hosted_runtime(thread::current());
+
+    let event_publisher = EventPublisher::new(
+        event_collector,
+        metrics.clone(),
+        recent_events.clone(),
+        audit_log_dir.as_deref(),
+    );
+    let node_span = info_span!("raft_node", server_id = server_id.0);
+    let node_diagnostics = diagnostics.clone();
+    let _task = runtime.spawn(
+        async move {
+            // Opening storage reads it back from disk
+            let opened = tokio::task::spawn_blocking(move || {
+                NodeLoop::open(
+                    server_id,
+                    other_servers,
+                    storage,
+                    application,
+                    config,
+                    rng,
+                    clock,
+                    AsyncConnector {
+                        transport,
+                        next_message: None,
+                    },
+                    event_publisher,
+                    inbox,
+                    node_diagnostics,
+                )
+            })
+            .in_current_span()
+            .await;
+            match opened {
+                Ok(Ok(node_loop)) => run_node(server_id, node_loop, exit).await,
+                Ok(Err(error)) => exit.send(Ok(Err(error))),
+                Err(join_error) if join_error.is_panic() => exit.send(Err(join_error.into_panic())),
+                // The runtime shut down before the node got to open its storage
+                Err(_) => {}
+            }
+        }
+        .instrument(node_span),
+    );
+
+    RaftNodeHandle::new(
+        NodeRuntime::Hosted(hosted),
+        senders,
+        diagnostics,
+        recent_events,
+        metrics,
+        max_entry_bytes,
+    )
+}
+
+/// Awaits the next message or the node's timer, whichever comes first, and hands the node what
+/// came in until it's shut down, halts or crashes. The node handles it on one of the runtime's
+/// blocking threads, so storage syncs and applying entries don't hold up other tasks.
+async fn run_node<LC, A, T, E>(
+    server_id: ServerId,
+    mut node_loop: NodeLoop<LC, A, AsyncConnector<LC, T>, E>,
+    exit: HostedExit,
+) where
+    LC: LogCommand + 'static,
+    A: ApplicationThatNeedsConsensus<Command = LC> + 'static,
+    T: AsyncRaftTransport<LC> + 'static,
+    E: RaftStateEventCollector + 'static,
+{
+    loop {
+        let max_wait = node_loop
+            .wake_at()
+            .saturating_duration_since(node_loop.now());
+        if node_loop.is_paused() {
+            // Messages wait in the transport until the node is resumed
+            tokio::time::sleep(max_wait).await;
+        } else {
+            let connector = &mut node_loop.transport_connector;
+            tokio::select! {
+                message = connector.transport.next_incoming_message() => {
+                    connector.next_message = Some(message);
+                }
+                _ = tokio::time::sleep(max_wait) => {}
+            }
+        }
+
+        let pass = tokio::task::spawn_blocking(move || {
+            crash_reporting::mark_current_thread(server_id);
+            let run = panic::catch_unwind(AssertUnwindSafe(|| node_loop.run_once()));
+            // Other tasks run on this thread in between
+            crash_reporting::unmark_current_thread();
+            (node_loop, run)
+        })
+        .in_current_span()
+        .await;
+        // The runtime shut down while the node was waiting for a blocking thread
+        let Ok((returned, run)) = pass else {
+            return;
+        };
+        node_loop = returned;
+        let outcome = match run {
+            Ok(Ok(NodeStatus::Running | NodeStatus::Paused)) => None,
+            Ok(Ok(NodeStatus::ShutDown)) => Some(Ok(Ok(()))),
+            Ok(Err(error)) => {
+                node_loop.publish_halted(error);
+                Some(Ok(Err(error)))
+            }
+            Err(panic) => {
+                node_loop.publish_crashed(&*panic);
+                Some(Err(panic))
+            }
+        };
+        if let Some(outcome) = outcome {
+            exit.send(outcome);
+            return;
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use raft_core::{
+    LogCommand, LogEntry, LogIndex, PersistentStorage, PersistentStorageError, ServerId, Snapshot,
+    TermIndex,
+};
+
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// Storage of a node running as a task, see [`crate::RaftNodeBuilder::async_storage`]. Like
+/// [`PersistentStorage`], except what touches the disk is awaited instead of blocking the thread.
+/// What the node asks for most, its term, vote and the shape of its log, is expected to be at
+/// hand and is read directly.
+pub trait AsyncPersistentStorage<C: LogCommand>: Send {
+    /// See [`PersistentStorage::current_term`]
+    fn current_term(&self) -> TermIndex;
+    /// See [`PersistentStorage::vote_for_current_term`]
+    fn vote_for_current_term(&self) -> Option<ServerId>;
+    /// See [`PersistentStorage::last_entry_index`]
+    fn last_entry_index(&self) -> Option<LogIndex>;
+    /// See [`PersistentStorage::last_entry_term`]
+    fn last_entry_term(&self) -> Option<TermIndex>;
+    /// See [`PersistentStorage::entry_term`]
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex>;
+    /// See [`PersistentStorage::log_size_on_disk`]
+    fn log_size_on_disk(&self) -> Option<u64> {
+        None
+    }
+    /// See [`PersistentStorage::is_full`]
+    fn is_full(&self) -> bool {
+        false
+    }
+
+    /// Makes `term`, and the vote cast in it if any, durable
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send;
+
+    /// Appends `entries` like [`PersistentStorage::append`] and makes them durable
+    fn append(
+        &mut self,
+        entries: Vec<LogEntry<C>>,
+    ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send;
+
+    /// Reads entries like [`PersistentStorage::entries_within`]
+    fn entries(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> impl Future<Output = Vec<LogEntry<C>>> + Send;
+
+    /// The latest snapshot, if the log was ever compacted
+    fn snapshot(&self) -> impl Future<Output = Option<Snapshot>> + Send;
+
+    /// Installs `snapshot` like [`PersistentStorage::install_snapshot`] and makes it durable
+    fn install_snapshot(
+        &mut self,
+        snapshot: Snapshot,
+    ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send;
+}
+
+/// Runs a blocking [`PersistentStorage`], e.g. a [`crate::DefaultPersistentStorage`], on tokio's
+/// blocking threads, so awaiting it never blocks the runtime
+#[derive(Debug)]
+pub struct SpawnBlockingStorage<PS> {
+    storage: Arc<Mutex<PS>>,
+}
+impl<PS> SpawnBlockingStorage<PS> {
+    /// Wraps `storage`, which has to be used from within a tokio runtime from now on
+    pub fn new(storage: PS) -> Self {
+        SpawnBlockingStorage {
+            storage: Arc::new(Mutex::new(storage)),
+        }
+    }
+
+    /// The storage, which no blocking thread holds while the node waits on it
+    fn lock(&self) -> MutexGuard<'_, PS> {
+        // Whatever panicked while holding it, the storage only changes what's durable on sync
+        self.storage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `operation` on a blocking thread, a panic there counts as a failed write
+    async fn spawn_blocking<R: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut PS) -> Result<R, PersistentStorageError> + Send + 'static,
+    ) -> Result<R, PersistentStorageError>
+    where
+        PS: Send + 'static,
+    {
+        let storage = Arc::clone(&self.storage);
+        tokio::task::spawn_blocking(move || {
+            let mut storage = storage
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            operation(&mut storage)
+        })
+        .await
+        .unwrap_or(Err(PersistentStorageError::IoError))
+    }
+}
+impl<C, PS> AsyncPersistentStorage<C> for SpawnBlockingStorage<PS>
+where
+    C: LogCommand + 'static,
+    PS: PersistentStorage<C> + 'static,
+{
+    fn current_term(&self) -> TermIndex {
+        self.lock().current_term()
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.lock().vote_for_current_term()
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        self.lock().last_entry_index()
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        self.lock().last_entry_term()
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        self.lock().entry_term(index)
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        self.lock().log_size_on_disk()
+    }
+
+    fn is_full(&self) -> bool {
+        self.lock().is_full()
+    }
+
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send {
+        self.spawn_blocking(move |storage| storage.persist_term_and_vote(term, voted_for))
+    }
+
+    fn append(
+        &mut self,
+        entries: Vec<LogEntry<C>>,
+    ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send {
+        self.spawn_blocking(move |storage| storage.append(entries).sync())
+    }
+
+    fn entries(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> impl Future<Output = Vec<LogEntry<C>>> + Send {
+        let read = self.spawn_blocking(move |storage| {
+            Ok(storage.entries_within(from, max_entries, max_bytes))
+        });
+        async move { read.await.unwrap_or_default() }
+    }
+
+    fn snapshot(&self) -> impl Future<Output = Option<Snapshot>> + Send {
+        let read = self.spawn_blocking(|storage| Ok(storage.snapshot().cloned()));
+        async move { read.await.ok().flatten() }
+    }
+
+    fn install_snapshot(
+        &mut self,
+        snapshot: Snapshot,
+    ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send {
+        self.spawn_blocking(move |storage| storage.install_snapshot(snapshot).sync())
+    }
+}
+
+/// Lets the node's loop, which runs a pass at a time on a blocking thread, use
+/// [`AsyncPersistentStorage`]. Appends and snapshots are written right away, term and vote changes
+/// on the next sync. A write that fails is tried again on every append and sync until it succeeds,
+/// the log reads as if it had, and [`PersistentStorage::is_full`] tells a full disk meanwhile.
+pub(crate) struct BlockOnStorage<C: LogCommand, S: AsyncPersistentStorage<C>> {
+    storage: S,
+    current_term: TermIndex,
+    vote: Option<(TermIndex, ServerId)>,
+    election_unsynced: bool,
+    unwritten_entries: Vec<LogEntry<C>>,
+    unwritten_snapshot: Option<Snapshot>,
+    /// Why the last write failed, until one succeeds
+    write_error: Option<PersistentStorageError>,
+    /// Read on first use, the node only asks for it once it runs
+    snapshot: OnceLock<Option<Snapshot>>,
+    /// Only built for a node that doesn't run on a tokio runtime
+    own_runtime: OnceLock<tokio::runtime::Runtime>,
+}
+impl<C: LogCommand, S: AsyncPersistentStorage<C>> BlockOnStorage<C, S> {
+    pub(crate) fn new(storage: S) -> Self {
+        BlockOnStorage {
+            current_term: storage.current_term(),
+            vote: storage
+                .vote_for_current_term()
+                .map(|voted_for| (storage.current_term(), voted_for)),
+            storage,
+            election_unsynced: false,
+            unwritten_entries: Vec::new(),
+            unwritten_snapshot: None,
+            write_error: None,
+            snapshot: OnceLock::new(),
+            own_runtime: OnceLock::new(),
+        }
+    }
+
+    fn write_snapshot(&mut self) -> Result<(), PersistentStorageError> {
+        let Some(snapshot) = self.unwritten_snapshot.clone() else {
+            return Ok(());
+        };
+        block_on(&self.own_runtime, self.storage.install_snapshot(snapshot))?;
+        self.snapshot = OnceLock::from(self.unwritten_snapshot.take());
+        Ok(())
+    }
+
+    fn write_entries(&mut self) -> Result<(), PersistentStorageError> {
+        if self.unwritten_entries.is_empty() {
+            return Ok(());
+        }
+        let entries = self.unwritten_entries.clone();
+        block_on(&self.own_runtime, self.storage.append(entries))?;
+        self.unwritten_entries.clear();
+        Ok(())
+    }
+
+    /// Writes the snapshot, then the entries after it, keeping why it failed
+    fn write(&mut self) -> Result<(), PersistentStorageError> {
+        let written = self.write_snapshot().and_then(|_| self.write_entries());
+        self.write_error = written.err();
+        written
+    }
+}
+
+/// Waits for `future` on the runtime the node runs on, or on `own_runtime` without one. Never
+/// called from within an async task, the node's passes run on blocking threads.
+fn block_on<F: Future>(own_runtime: &OnceLock<tokio::runtime::Runtime>, future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => runtime.block_on(future),
+        Err(_) => own_runtime
+            .get_or_init(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("could not build a runtime for the node's storage")
+            })
+            .block_on(future),
+    }
+}
+
+impl<C: LogCommand, S: AsyncPersistentStorage<C>> PersistentStorage<C> for BlockOnStorage<C, S> {
+    fn current_term(&self) -> TermIndex {
+        self.current_term
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.vote
+            .filter(|(term, _)| *term == self.current_term)
+            .map(|(_, voted_for)| voted_for)
+    }
+
+    fn update_term(&mut self, term: TermIndex) -> &mut Self {
+        self.current_term = term;
+        self.election_unsynced = true;
+        self
+    }
+
+    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self {
+        self.vote = Some((self.current_term, voted_for));
+        self.election_unsynced = true;
+        self
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        match self.unwritten_entries.last() {
+            Some(entry) => Some(entry.index),
+            None => self.storage.last_entry_index(),
+        }
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        match self.unwritten_entries.last() {
+            Some(entry) => Some(entry.term),
+            None => self.storage.last_entry_term(),
+        }
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        match self.unwritten_entries.first() {
+            // Unwritten entries replace whatever the storage holds from the first of them on
+            Some(first) if index >= first.index => self
+                .unwritten_entries
+                .get((index.0 - first.index.0) as usize)
+                .map(|entry| entry.term),
+            _ => self.storage.entry_term(index),
+        }
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>> {
+        self.entries_within(from, max_entries, usize::MAX)
+    }
+
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>> {
+        block_on(
+            &self.own_runtime,
+            self.storage.entries(from, max_entries, max_bytes),
+        )
+    }
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot
+            .get_or_init(|| block_on(&self.own_runtime, self.storage.snapshot()))
+            .as_ref()
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self {
+        self.unwritten_snapshot = Some(snapshot);
+        // A failed write is kept for is_full and tried again by the next sync
+        let _ = self.write();
+        self
+    }
+
+    fn append(&mut self, mut entries: Vec<LogEntry<C>>) -> &mut Self {
+        // Entries the log already holds are skipped, the rest replace whatever follows them
+        let held = entries
+            .iter()
+            .take_while(|entry| self.entry_term(entry.index) == Some(entry.term))
+            .count();
+        let entries = entries.split_off(held);
+        if let Some(first) = entries.first() {
+            let first_index = first.index;
+            self.unwritten_entries
+                .retain(|entry| entry.index < first_index);
+        }
+        self.unwritten_entries.extend(entries);
+        // A failed write is kept for is_full and tried again by the next sync
+        let _ = self.write();
+        self
+    }
+
+    fn sync(&mut self) -> Result<(), PersistentStorageError> {
+        self.write()?;
+        if self.election_unsynced {
+            let voted_for = self.vote_for_current_term();
+            block_on(
+                &self.own_runtime,
+                self.storage
+                    .persist_term_and_vote(self.current_term, voted_for),
+            )?;
+            self.election_unsynced = false;
+        }
+        Ok(())
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        self.storage.log_size_on_disk()
+    }
+
+    fn is_full(&self) -> bool {
+        self.write_error == Some(PersistentStorageError::StorageFull) || self.storage.is_full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{self, Future};
+    use std::sync::{Arc, Mutex};
+
+    use raft_core::{
+        LogEntry, LogEntryCommand, LogIndex, PersistentStorage, PersistentStorageError, ServerId,
+        Snapshot, TermIndex,
+    };
+
+    use super::{AsyncPersistentStorage, BlockOnStorage};
+
+    /// Log kept in memory, whose writes fail with `fail_with` while it's set
+    #[derive(Default)]
+    struct FailingStorage {
+        entries: Arc<Mutex<Vec<LogEntry<u64>>>>,
+        fail_with: Arc<Mutex<Option<PersistentStorageError>>>,
+    }
+    impl FailingStorage {
+        fn entries(&self) -> std::sync::MutexGuard<'_, Vec<LogEntry<u64>>> {
+            self.entries
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        fn write(&self) -> Result<(), PersistentStorageError> {
+            match *self
+                .fail_with
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+            {
+                Some(error) => Err(error),
+                None => Ok(()),
+            }
+        }
+    }
+    impl AsyncPersistentStorage<u64> for FailingStorage {
+        fn current_term(&self) -> TermIndex {
+            TermIndex(0)
+        }
+
+        fn vote_for_current_term(&self) -> Option<ServerId> {
+            None
+        }
+
+        fn last_entry_index(&self) -> Option<LogIndex> {
+            self.entries().last().map(|entry| entry.index)
+        }
+
+        fn last_entry_term(&self) -> Option<TermIndex> {
+            self.entries().last().map(|entry| entry.term)
+        }
+
+        fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+            self.entries()
+                .iter()
+                .find(|entry| entry.index == index)
+                .map(|entry| entry.term)
+        }
+
+        fn persist_term_and_vote(
+            &mut self,
+            _term: TermIndex,
+            _voted_for: Option<ServerId>,
+        ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send {
+            future::ready(self.write())
+        }
+
+        fn append(
+            &mut self,
+            entries: Vec<LogEntry<u64>>,
+        ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send {
+            let written = self.write().map(|_| {
+                let mut log = self.entries();
+                if let Some(first) = entries.first() {
+                    log.retain(|entry| entry.index < first.index);
+                }
+                log.extend(entries);
+            });
+            future::ready(written)
+        }
+
+        fn entries(
+            &self,
+            from: LogIndex,
+            max_entries: usize,
+            _max_bytes: usize,
+        ) -> impl Future<Output = Vec<LogEntry<u64>>> + Send {
+            let entries = self
+                .entries()
+                .iter()
+                .filter(|entry| entry.index >= from)
+                .take(max_entries)
+                .cloned()
+                .collect();
+            future::ready(entries)
+        }
+
+        fn snapshot(&self) -> impl Future<Output = Option<Snapshot>> + Send {
+            future::ready(None)
+        }
+
+        fn install_snapshot(
+            &mut self,
+            _snapshot: Snapshot,
+        ) -> impl Future<Output = Result<(), PersistentStorageError>> + Send {
+            future::ready(self.write())
+        }
+    }
+
+    fn entry(index: u64, term: u64) -> LogEntry<u64> {
+        LogEntry {
+            index: LogIndex(index),
+            term: TermIndex(term),
+            command: LogEntryCommand::Application(index * 10),
+        }
+    }
+
+    #[test]
+    fn should_count_entries_whose_write_failed_and_report_the_failure_until_it_succeeds() {
+        let failing = FailingStorage::default();
+        let written = Arc::clone(&failing.entries);
+        let fail_with = Arc::clone(&failing.fail_with);
+        let mut storage = BlockOnStorage::new(failing);
+        storage
+            .append(vec![entry(1, 1), entry(2, 1)])
+            .sync()
+            .unwrap();
+
+        *fail_with.lock().unwrap() = Some(PersistentStorageError::StorageFull);
+        storage.append(vec![entry(3, 1), entry(4, 2)]);
+        assert_eq!(storage.last_entry_index(), Some(LogIndex(4)));
+        assert_eq!(storage.last_entry_term(), Some(TermIndex(2)));
+        assert_eq!(storage.entry_term(LogIndex(3)), Some(TermIndex(1)));
+        assert!(storage.is_full());
+
+        // An entry conflicting with an unwritten one replaces it, like a written one
+        storage.append(vec![entry(4, 3)]);
+        assert_eq!(storage.entry_term(LogIndex(4)), Some(TermIndex(3)));
+        assert_eq!(storage.sync(), Err(PersistentStorageError::StorageFull));
+        assert_eq!(written.lock().unwrap().len(), 2);
+
+        *fail_with.lock().unwrap() = None;
+        assert_eq!(storage.sync(), Ok(()));
+        assert!(!storage.is_full());
+        assert_eq!(
+            *written.lock().unwrap(),
+            vec![entry(1, 1), entry(2, 1), entry(3, 1), entry(4, 3)]
+        );
+        assert_eq!(storage.last_entry_index(), Some(LogIndex(4)));
+    }
+}
@@ -0,0 +1,86 @@
+use raft_core::RaftEvent;
+use raft_core::*;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Append only, human readable record of the terms a node went through, the votes it cast
+/// and the cluster configurations it used. Every record is fsynced before moving on so the file
+/// survives crashes and can be used to reconstruct what happened in a cluster after the fact.
+///
+/// Failing to write it is logged but does not stop the node, it's not needed for consensus.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    file: File,
+    last_term: Option<TermIndex>,
+}
+impl AuditLog {
+    pub(crate) fn open(path: &Path) -> Option<Self> {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(AuditLog {
+                file,
+                last_term: None,
+            }),
+            Err(e) => {
+                warn!("Could not open audit log {:?}, not auditing: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn observe_event(&mut self, event: &RaftEvent) {
+        let record = match event {
+            RaftEvent::StateChanged(state) => {
+                if self.last_term == Some(state.current_term) {
+                    return;
+                }
+                self.last_term = Some(state.current_term);
+                format!(
+                    "term_changed term={} role={:?} voted_for={} leader={}",
+                    state.current_term.0,
+                    state.current_state,
+                    format_server_id(state.voted_for),
+                    format_server_id(state.leader_for_term)
+                )
+            }
+            RaftEvent::ElectionStarted { server_id, term } => {
+                format!("election_started term={} voted_for={}", term.0, server_id.0)
+            }
+            RaftEvent::VoteGranted {
+                candidate_id, term, ..
+            } => format!("vote_granted term={} voted_for={}", term.0, candidate_id.0),
+            RaftEvent::BecameLeader { term, .. } => format!("became_leader term={}", term.0),
+            RaftEvent::MembershipChanged {
+                members, learners, ..
+            } => {
+                let mut members: Vec<u64> = members.iter().map(|member| member.0).collect();
+                members.sort_unstable();
+                let mut learners: Vec<u64> = learners.iter().map(|learner| learner.0).collect();
+                learners.sort_unstable();
+                format!(
+                    "membership_changed members={:?} learners={:?}",
+                    members, learners
+                )
+            }
+            _ => return,
+        };
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis())
+            .unwrap_or(0);
+        if let Err(e) =
+            writeln!(self.file, "{} {}", timestamp_ms, record).and_then(|_| self.file.sync_data())
+        {
+            warn!("Failed to write audit log record {:?}: {:?}", record, e);
+        }
+    }
+}
+
+fn format_server_id(maybe_server_id: Option<ServerId>) -> String {
+    maybe_server_id
+        .map(|server_id| server_id.0.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
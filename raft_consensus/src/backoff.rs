@@ -0,0 +1,66 @@
+use rand::Rng;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Wait after the first failure to reach a peer, doubled with every failure in a row
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+/// Longest wait between attempts, so a peer coming back is found out soon enough
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Jittered exponential backoff between attempts to reach one peer. Goes by real time rather than
+/// the node's clock, how long a peer takes to come back has nothing to do with a mocked clock.
+#[derive(Debug, Default)]
+pub(crate) struct Backoff {
+    /// Failed attempts in a row
+    failures: u32,
+    retry_after: Option<Instant>,
+}
+impl Backoff {
+    /// Records a failed attempt, returning how long to wait before the next one. Half the wait is
+    /// random, so servers that lost a peer together don't all hit it at once when it comes back.
+    pub(crate) fn failed(&mut self) -> Duration {
+        let wait = INITIAL_BACKOFF
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_BACKOFF);
+        self.failures = self.failures.saturating_add(1);
+        let half = wait / 2;
+        let wait = half + half.mul_f64(rand::thread_rng().gen::<f64>());
+        self.retry_after = Some(Instant::now() + wait);
+        wait
+    }
+
+    pub(crate) fn succeeded(&mut self) {
+        self.failures = 0;
+        self.retry_after = None;
+    }
+
+    /// Whether the last attempt failed
+    #[cfg_attr(not(feature = "http_transport"), allow(dead_code))]
+    pub(crate) fn failing(&self) -> bool {
+        self.failures > 0
+    }
+
+    /// Whether the peer is still being given time before it's tried again
+    pub(crate) fn waiting(&self) -> bool {
+        self.retry_after.is_some_and(|after| Instant::now() < after)
+    }
+}
+
+/// Whether `error` is one that trying again may get past, like a peer restarting
+pub(crate) fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+    )
+}
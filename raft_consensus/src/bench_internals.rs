@@ -0,0 +1,165 @@
+//! Drives a single node's state machine without a Raft thread or transport so benchmarks can
+//! measure event handling on its own. Only built with the `bench_internals` feature, not a stable API.
+pub use crate::default_storage::DefaultPersistentStorage;
+use raft_core::rpc_messages::*;
+use raft_core::system_clock::{self, SystemClock};
+use raft_core::*;
+use raft_core::{Action, Event, Node};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A node plus everything [`Node::next`] needs, handling one event per call
+#[derive(Debug)]
+pub struct NodeBench {
+    node: Option<Node>,
+    storage: DefaultPersistentStorage<u64>,
+    config: RaftConfig,
+    rng: ChaCha8Rng,
+    actions: Vec<Action<u64>>,
+    server_id: ServerId,
+    term: TermIndex,
+}
+impl NodeBench {
+    /// A leader of a `cluster_size` cluster that has a heartbeat due for every follower on each tick
+    pub fn leader(storage_path: &Path, cluster_size: u64) -> Self {
+        let config = RaftConfig::builder()
+            .leader_heartbeat_interval(HeartbeatInterval(Duration::from_nanos(1)))
+            .election_timeout(ElectionTimeoutRange::from_millis(1, 2))
+            .build()
+            .expect("BENCH: invalid leader config");
+        let mut bench = Self::new(storage_path, cluster_size, config);
+
+        std::thread::sleep(bench.config.election_timeout.max);
+        let _ = bench.handle(Event::Tick(system_clock::now()), true);
+        let term = bench.storage.current_term();
+        for voter in 1..cluster_size {
+            let _ = bench.handle(
+                Event::IncomingRpc(RpcMessage::vote(Vote {
+                    request_id: Uuid::new_v4(),
+                    from: ServerId(voter),
+                    to: bench.server_id,
+                    term,
+                    vote_granted: true,
+                })),
+                true,
+            );
+        }
+        assert!(
+            matches!(bench.node, Some(Node::Leader(_))),
+            "BENCH: node did not win the election"
+        );
+        bench.term = term;
+        bench
+    }
+
+    /// A follower of a `cluster_size` cluster, led by server 1 in term 1
+    pub fn follower(storage_path: &Path, cluster_size: u64) -> Self {
+        let config = RaftConfig::builder()
+            .build()
+            .expect("BENCH: invalid follower config");
+        let mut bench = Self::new(storage_path, cluster_size, config);
+        bench.term = TermIndex(1);
+        // Moves the follower into the leader's term so later events don't touch storage
+        let _ = bench.handle_heartbeat(true);
+        bench
+    }
+
+    fn new(storage_path: &Path, cluster_size: u64, config: RaftConfig) -> Self {
+        let server_id = ServerId(0);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let other_servers = (1..cluster_size).map(ServerId).collect();
+        let storage =
+            DefaultPersistentStorage::new(storage_path).expect("BENCH: could not open storage");
+        let (node, _) = Node::new(
+            server_id,
+            other_servers,
+            LogIndex(0),
+            &storage,
+            &config,
+            &mut rng,
+            &SystemClock,
+        );
+        NodeBench {
+            node: Some(node),
+            storage,
+            config,
+            rng,
+            actions: Vec::new(),
+            server_id,
+            term: TermIndex(0),
+        }
+    }
+
+    /// Handles a tick, returns the number of actions it produced. With `reuse_buffer` unset every
+    /// call allocates a fresh action buffer, the way the Raft thread used to.
+    pub fn handle_tick(&mut self, reuse_buffer: bool) -> usize {
+        self.handle(Event::Tick(system_clock::now()), reuse_buffer)
+    }
+
+    /// Handles an empty AppendEntries from the leader, returns the number of actions it produced
+    pub fn handle_heartbeat(&mut self, reuse_buffer: bool) -> usize {
+        let heartbeat = RpcMessage::append_entries(AppendEntries {
+            request_id: Uuid::new_v4(),
+            from: ServerId(1),
+            to: self.server_id,
+            term: self.term,
+            prev_log_index: LogIndex(0),
+            prev_log_term: TermIndex(0),
+            entries: SharedEntries::empty(),
+            leader_commit: LogIndex(0),
+        });
+        self.handle(Event::IncomingRpc(heartbeat), reuse_buffer)
+    }
+
+    /// Handles a successful AppendEntries reply from server 1, returns the number of actions it produced
+    pub fn handle_append_entries_ack(&mut self, reuse_buffer: bool) -> usize {
+        let ack = RpcMessage::ack_append_entries(AppendEntriesAck {
+            request_id: Uuid::new_v4(),
+            from: ServerId(1),
+            to: self.server_id,
+            term: self.term,
+            success: true,
+            conflict: None,
+        });
+        self.handle(Event::IncomingRpc(ack), reuse_buffer)
+    }
+
+    /// Handles a vote request from a candidate stuck in term 0, which is turned down without
+    /// touching storage. Returns the number of actions it produced.
+    pub fn handle_stale_vote_request(&mut self, reuse_buffer: bool) -> usize {
+        let vote_request = RpcMessage::request_vote(RequestVote {
+            request_id: Uuid::new_v4(),
+            from: ServerId(2),
+            to: self.server_id,
+            term: TermIndex(0),
+            last_log_index: LogIndex(0),
+            last_log_term: TermIndex(0),
+            leadership_transfer: false,
+        });
+        self.handle(Event::IncomingRpc(vote_request), reuse_buffer)
+    }
+
+    fn handle(&mut self, event: Event<u64>, reuse_buffer: bool) -> usize {
+        if !reuse_buffer {
+            self.actions = Vec::new();
+        }
+        let node = self.node.take().expect("BENCH: node missing");
+        self.node = Some(
+            node.next(
+                event,
+                &mut self.storage,
+                &self.config,
+                &mut self.rng,
+                &SystemClock,
+                &mut self.actions,
+            )
+            .expect("BENCH: storage error"),
+        );
+        let produced = self.actions.len();
+        self.actions.clear();
+        produced
+    }
+}
@@ -0,0 +1,229 @@
+use raft_core::{
+    LogCommand, LogEntry, LogIndex, PersistentStorage, PersistentStorageError, ServerId, Snapshot,
+    TermIndex,
+};
+
+use std::fmt::Debug;
+use std::path::Path;
+
+/// [`PersistentStorage`] without the `&mut Self` its mutating methods return, so it can be a trait
+/// object. Every [`PersistentStorage`] is one.
+pub(crate) trait ErasedStorage<C: LogCommand>: Send {
+    fn current_term(&self) -> TermIndex;
+    fn vote_for_current_term(&self) -> Option<ServerId>;
+    fn update_term(&mut self, term: TermIndex);
+    fn record_vote(&mut self, voted_for: ServerId);
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> Result<(), PersistentStorageError>;
+    fn last_entry_index(&self) -> Option<LogIndex>;
+    fn last_entry_term(&self) -> Option<TermIndex>;
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex>;
+    fn has_entry(&self, index: LogIndex, term: TermIndex) -> bool;
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>>;
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>>;
+    fn snapshot(&self) -> Option<&Snapshot>;
+    fn snapshot_index(&self) -> LogIndex;
+    fn install_snapshot(&mut self, snapshot: Snapshot);
+    fn append(&mut self, entries: Vec<LogEntry<C>>);
+    fn sync(&mut self) -> Result<(), PersistentStorageError>;
+    fn log_size_on_disk(&self) -> Option<u64>;
+    fn is_full(&self) -> bool;
+    fn backup_to(&mut self, path: &Path) -> Result<LogIndex, PersistentStorageError>;
+}
+impl<C: LogCommand, PS: PersistentStorage<C>> ErasedStorage<C> for PS {
+    fn current_term(&self) -> TermIndex {
+        PersistentStorage::current_term(self)
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        PersistentStorage::vote_for_current_term(self)
+    }
+
+    fn update_term(&mut self, term: TermIndex) {
+        let _ = PersistentStorage::update_term(self, term);
+    }
+
+    fn record_vote(&mut self, voted_for: ServerId) {
+        let _ = PersistentStorage::record_vote(self, voted_for);
+    }
+
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> Result<(), PersistentStorageError> {
+        PersistentStorage::persist_term_and_vote(self, term, voted_for)
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        PersistentStorage::last_entry_index(self)
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        PersistentStorage::last_entry_term(self)
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        PersistentStorage::entry_term(self, index)
+    }
+
+    fn has_entry(&self, index: LogIndex, term: TermIndex) -> bool {
+        PersistentStorage::has_entry(self, index, term)
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>> {
+        PersistentStorage::entries(self, from, max_entries)
+    }
+
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>> {
+        PersistentStorage::entries_within(self, from, max_entries, max_bytes)
+    }
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        PersistentStorage::snapshot(self)
+    }
+
+    fn snapshot_index(&self) -> LogIndex {
+        PersistentStorage::snapshot_index(self)
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) {
+        let _ = PersistentStorage::install_snapshot(self, snapshot);
+    }
+
+    fn append(&mut self, entries: Vec<LogEntry<C>>) {
+        let _ = PersistentStorage::append(self, entries);
+    }
+
+    fn sync(&mut self) -> Result<(), PersistentStorageError> {
+        PersistentStorage::sync(self)
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        PersistentStorage::log_size_on_disk(self)
+    }
+
+    fn is_full(&self) -> bool {
+        PersistentStorage::is_full(self)
+    }
+
+    fn backup_to(&mut self, path: &Path) -> Result<LogIndex, PersistentStorageError> {
+        PersistentStorage::backup_to(self, path)
+    }
+}
+/// Storage picked at runtime, either a [`crate::DefaultPersistentStorage`] or whatever the
+/// application handed to [`crate::RaftNodeBuilder::storage`]
+pub(crate) struct BoxedStorage<C: LogCommand>(Box<dyn ErasedStorage<C>>);
+impl<C: LogCommand> BoxedStorage<C> {
+    pub(crate) fn new(storage: impl PersistentStorage<C> + 'static) -> Self {
+        BoxedStorage(Box::new(storage))
+    }
+}
+impl<C: LogCommand> Debug for BoxedStorage<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BoxedStorage").finish_non_exhaustive()
+    }
+}
+impl<C: LogCommand> PersistentStorage<C> for BoxedStorage<C> {
+    fn current_term(&self) -> TermIndex {
+        self.0.current_term()
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.0.vote_for_current_term()
+    }
+
+    fn update_term(&mut self, term: TermIndex) -> &mut Self {
+        self.0.update_term(term);
+        self
+    }
+
+    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self {
+        self.0.record_vote(voted_for);
+        self
+    }
+
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> Result<(), PersistentStorageError> {
+        self.0.persist_term_and_vote(term, voted_for)
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        self.0.last_entry_index()
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        self.0.last_entry_term()
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        self.0.entry_term(index)
+    }
+
+    fn has_entry(&self, index: LogIndex, term: TermIndex) -> bool {
+        self.0.has_entry(index, term)
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>> {
+        self.0.entries(from, max_entries)
+    }
+
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>> {
+        self.0.entries_within(from, max_entries, max_bytes)
+    }
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        self.0.snapshot()
+    }
+
+    fn snapshot_index(&self) -> LogIndex {
+        self.0.snapshot_index()
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self {
+        self.0.install_snapshot(snapshot);
+        self
+    }
+
+    fn append(&mut self, entries: Vec<LogEntry<C>>) -> &mut Self {
+        self.0.append(entries);
+        self
+    }
+
+    fn sync(&mut self) -> Result<(), PersistentStorageError> {
+        self.0.sync()
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        self.0.log_size_on_disk()
+    }
+
+    fn is_full(&self) -> bool {
+        self.0.is_full()
+    }
+
+    fn backup_to(&mut self, path: &Path) -> Result<LogIndex, PersistentStorageError> {
+        self.0.backup_to(path)
+    }
+}
@@ -0,0 +1,115 @@
+use crate::common::{ApplicationThatNeedsConsensus, ApplyResult, ProposalError};
+use raft_core::{
+    ClientId, ClientSession, LogCommand, LogEntryCommand, LogIndex, PersistentStorage,
+};
+use std::collections::HashMap;
+
+/// Most entries read from storage at once while replaying sessions
+const ENTRIES_PER_REPLAY: usize = 1024;
+
+/// How far each client's session got, built up from the session commands applied in log order so
+/// every server skips the same retries. Sessions are kept for as long as the node runs.
+pub(crate) struct ClientSessions<A: ApplicationThatNeedsConsensus> {
+    by_client: HashMap<ClientId, SessionState<A>>,
+}
+
+struct SessionState<A: ApplicationThatNeedsConsensus> {
+    /// Highest sequence number applied for the client
+    last_sequence: u64,
+    /// What applying it gave back, `None` once the session came from a snapshot or a replay
+    response: Option<ApplyResult<A>>,
+}
+
+impl<A: ApplicationThatNeedsConsensus> ClientSessions<A> {
+    pub(crate) fn new() -> Self {
+        ClientSessions {
+            by_client: HashMap::new(),
+        }
+    }
+
+    /// Applies a command with `apply` unless its session already went past it. A retry of the last
+    /// command gets that command's response again.
+    pub(crate) fn apply(
+        &mut self,
+        session: ClientSession,
+        apply: impl FnOnce() -> ApplyResult<A>,
+    ) -> Result<ApplyResult<A>, ProposalError> {
+        match self.by_client.get(&session.client_id) {
+            Some(state) if session.sequence < state.last_sequence => {
+                Err(ProposalError::ResponseDiscarded)
+            }
+            Some(state) if session.sequence == state.last_sequence => state
+                .response
+                .clone()
+                .ok_or(ProposalError::ResponseDiscarded),
+            _ => {
+                let result = apply();
+                let _ = self.by_client.insert(
+                    session.client_id,
+                    SessionState {
+                        last_sequence: session.sequence,
+                        response: Some(result.clone()),
+                    },
+                );
+                Ok(result)
+            }
+        }
+    }
+
+    /// The last sequence number applied for each client, what a snapshot keeps of the sessions
+    pub(crate) fn sequences(&self) -> HashMap<ClientId, u64> {
+        self.by_client
+            .iter()
+            .map(|(client_id, state)| (*client_id, state.last_sequence))
+            .collect()
+    }
+
+    /// Replaces the sessions with the ones a snapshot kept, without their responses
+    pub(crate) fn restore(&mut self, sequences: &HashMap<ClientId, u64>) {
+        self.by_client = sequences
+            .iter()
+            .map(|(client_id, last_sequence)| {
+                (
+                    *client_id,
+                    SessionState {
+                        last_sequence: *last_sequence,
+                        response: None,
+                    },
+                )
+            })
+            .collect();
+    }
+
+    /// Catches the sessions up with the session commands after the snapshot up to and including
+    /// `through`, which the application applied before the node restarted
+    pub(crate) fn replay<C, PS>(&mut self, storage: &PS, through: LogIndex)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let mut next = storage.snapshot_index().next();
+        while next <= through {
+            let wanted = ((through.0 - next.0 + 1) as usize).min(ENTRIES_PER_REPLAY);
+            let entries = storage.entries(next, wanted);
+            let Some(last_entry) = entries.last() else {
+                break;
+            };
+            next = last_entry.index.next();
+            for entry in entries {
+                if let LogEntryCommand::SessionCommand(session, _) = entry.command {
+                    let state = self
+                        .by_client
+                        .entry(session.client_id)
+                        .or_insert(SessionState {
+                            last_sequence: 0,
+                            response: None,
+                        });
+                    if session.sequence > state.last_sequence {
+                        state.last_sequence = session.sequence;
+                        state.response = None;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use raft_core::LogCommand;
+
+use bincode::Options;
+
+/// Turns commands into the bytes written to the log file and sent to peers, and back again.
+/// Every node of a cluster has to use the same codec, and keep using it for as long as its log
+/// holds entries written with it.
+pub trait CommandCodec<C: LogCommand>: std::fmt::Debug + Send + Sync {
+    /// The bytes `command` is stored and sent as
+    fn encode(&self, command: &C) -> Result<Vec<u8>, CodecError>;
+
+    /// The command `bytes` were encoded from
+    fn decode(&self, bytes: &[u8]) -> Result<C, CodecError>;
+
+    /// Byte storage writes in the header of its files to tell the format apart, opening files
+    /// written in another format fails with [`raft_core::PersistentStorageError::FormatMismatch`].
+    /// [`BincodeCodec`] is 1 and `JsonCodec` 2, other codecs should pick their own, the default
+    /// only tells them apart from those two. Encrypting codecs set the top bit.
+    fn format(&self) -> u8 {
+        0
+    }
+
+    /// The bytes snapshot `data` is stored as, the data itself unless the codec encrypts
+    fn encode_snapshot_data(&self, data: Vec<u8>) -> Result<Vec<u8>, CodecError> {
+        Ok(data)
+    }
+
+    /// Snapshot data back from the bytes [`CommandCodec::encode_snapshot_data`] stored it as
+    fn decode_snapshot_data(&self, stored: Vec<u8>) -> Result<Vec<u8>, CodecError> {
+        Ok(stored)
+    }
+}
+
+/// A command couldn't be encoded, or bytes couldn't be decoded into one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecError(pub String);
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not encode or decode a command: {}", self.0)
+    }
+}
+impl std::error::Error for CodecError {}
+
+/// Compact binary commands with bincode's default options, what storage uses unless told otherwise
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+impl<C: LogCommand> CommandCodec<C> for BincodeCodec {
+    fn encode(&self, command: &C) -> Result<Vec<u8>, CodecError> {
+        bincode::DefaultOptions::new()
+            .serialize(command)
+            .map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<C, CodecError> {
+        bincode::DefaultOptions::new()
+            .deserialize(bytes)
+            .map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn format(&self) -> u8 {
+        1
+    }
+}
+
+/// Commands as JSON, larger and slower than bincode but readable when inspecting a log file
+#[cfg(feature = "json_codec")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+#[cfg(feature = "json_codec")]
+impl<C: LogCommand> CommandCodec<C> for JsonCodec {
+    fn encode(&self, command: &C) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(command).map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<C, CodecError> {
+        serde_json::from_slice(bytes).map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn format(&self) -> u8 {
+        2
+    }
+}
+
+/// 256-bit key [`EncryptingCodec`] encrypts with, kept by the application. Debug output leaves it
+/// out.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+#[cfg(feature = "encryption")]
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        EncryptionKey(bytes)
+    }
+}
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Encrypts what another codec encodes, and snapshot data, with XChaCha20-Poly1305 so the disk
+/// storage is on doesn't give commands away. Every command and snapshot gets a random nonce of its
+/// own, stored ahead of it. Indexes, terms and membership changes stay readable. Its format is the
+/// inner codec's with the top bit set, so storage written with it can't be opened without it, and
+/// opening it with the wrong key fails to decode.
+#[cfg(feature = "encryption")]
+pub struct EncryptingCodec<C: LogCommand> {
+    inner: std::sync::Arc<dyn CommandCodec<C>>,
+    cipher: chacha20poly1305::XChaCha20Poly1305,
+}
+#[cfg(feature = "encryption")]
+impl<C: LogCommand> EncryptingCodec<C> {
+    pub fn new(inner: impl CommandCodec<C> + 'static, key: EncryptionKey) -> Self {
+        use chacha20poly1305::KeyInit;
+
+        EncryptingCodec {
+            inner: std::sync::Arc::new(inner),
+            cipher: chacha20poly1305::XChaCha20Poly1305::new(&key.0.into()),
+        }
+    }
+
+    /// `plaintext` encrypted under a fresh nonce, which goes first
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CodecError> {
+        use chacha20poly1305::aead::Aead;
+        use rand::RngCore;
+
+        let mut nonce = chacha20poly1305::XNonce::default();
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CodecError("could not encrypt".to_string()))?;
+        let mut sealed = Vec::with_capacity(nonce.len() + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// What [`EncryptingCodec::seal`] encrypted, if `sealed` wasn't tampered with
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CodecError> {
+        use chacha20poly1305::aead::Aead;
+
+        const NONCE_LEN: usize = 24;
+        if sealed.len() < NONCE_LEN {
+            return Err(CodecError("encrypted bytes cut short".to_string()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(chacha20poly1305::XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CodecError("could not decrypt, wrong key or damaged bytes".to_string()))
+    }
+}
+#[cfg(feature = "encryption")]
+impl<C: LogCommand> std::fmt::Debug for EncryptingCodec<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptingCodec")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+#[cfg(feature = "encryption")]
+impl<C: LogCommand> CommandCodec<C> for EncryptingCodec<C> {
+    fn encode(&self, command: &C) -> Result<Vec<u8>, CodecError> {
+        self.seal(&self.inner.encode(command)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<C, CodecError> {
+        self.inner.decode(&self.open(bytes)?)
+    }
+
+    fn format(&self) -> u8 {
+        self.inner.format() | 0x80
+    }
+
+    fn encode_snapshot_data(&self, data: Vec<u8>) -> Result<Vec<u8>, CodecError> {
+        self.seal(&self.inner.encode_snapshot_data(data)?)
+    }
+
+    fn decode_snapshot_data(&self, stored: Vec<u8>) -> Result<Vec<u8>, CodecError> {
+        self.inner.decode_snapshot_data(self.open(&stored)?)
+    }
+}
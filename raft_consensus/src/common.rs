@@ -1,92 +1,144 @@
-use crate::rpc_messages::{ReplyTo, Request, RpcMessage};
-use serde::{Deserialize, Serialize};
+use crate::metrics::RaftMetrics;
+use raft_core::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_core::{
+    LogCommand, LogIndex, PersistentStorageError, RaftConfigError, RaftError, RaftTransportError,
+    ServerId, Snapshot, TermIndex,
+};
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
-/// A unique identifier for a server in the cluster.
-pub struct ServerId(pub u64);
-
-/// A trait that defines the interface for a log command.
-pub trait LogCommand: Debug + Clone + Send + Eq + PartialEq {}
-impl<T> LogCommand for T where T: Debug + Clone + Send + Eq + PartialEq {}
-
-#[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug)]
-/// The index of a log entry.
-pub struct LogIndex(pub u64);
-
-#[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
-/// The term of a log entry.
-pub struct TermIndex(pub u64);
-impl TermIndex {
-    /// Increments the term index by 1.
-    pub fn increment(&mut self) -> Self {
-        TermIndex(self.0 + 1)
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors handing a client command to a Raft node.
+pub enum ProposalError {
+    /// The Raft thread has exited and won't pick up new commands.
+    NodeStopped,
+    /// The node wasn't leading when it picked up the command, so it dropped it. Carries the leader
+    /// the node knows of, if any, to propose the command to instead.
+    NotLeader(Option<ServerId>),
+    /// The application fell too far behind the leader's log, see
+    /// [`raft_core::RaftConfig::max_unapplied_entries`]. Retry once it caught up.
+    Busy,
+    /// The leader's storage is full and [`raft_core::RaftConfig::storage_full_policy`] tells it to
+    /// turn proposals away. Retry once it has room again.
+    StorageFull,
+    /// The command's log entry is bigger than one message may carry, see
+    /// [`raft_core::RaftConfig::max_entry_bytes`]. It would never reach the followers, split it up.
+    TooLarge,
+    /// Another leader's entry took the command's place in the log, it will never be applied.
+    Superseded,
+    /// The command was applied, but its response isn't kept anymore: its session moved on to a
+    /// later command, or the node restored the session, or the command forwarded to the leader,
+    /// from a snapshot.
+    ResponseDiscarded,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-/// A log entry in the Raft log.
-pub struct LogEntry<T: LogCommand> {
-    /// The index of the log entry.
-    pub index: LogIndex,
-    /// The term of the log entry.
-    pub term: TermIndex,
-    /// The command that was applied to the state machine to produce this log entry.
-    pub command: T,
-}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The Raft thread exited before resolving a [`crate::Pending`] request, every request's error
+/// has a variant for it.
+pub struct NodeStopped;
 
-#[derive(Debug, Clone, Copy)]
-/// The configuration for a Raft node.
-pub struct RaftConfig {
-    /// The amount of time that a leader will wait before sending a heartbeat to its followers.
-    pub leader_heartbeat_interval: Duration,
-    /// The minimum amount of time that a follower will wait before becoming a candidate.
-    pub min_election_timeout_ms: u32,
-    /// The maximum amount of time that a follower will wait before becoming a candidate.
-    pub max_election_timeout_ms: u32,
+macro_rules! node_stopped_into {
+    ($($error:ty),*) => {
+        $(
+            impl From<NodeStopped> for $error {
+                fn from(_: NodeStopped) -> Self {
+                    <$error>::NodeStopped
+                }
+            }
+        )*
+    };
 }
+node_stopped_into!(
+    ProposalError,
+    raft_core::MembershipChangeError,
+    raft_core::StepDownError,
+    raft_core::ReadError,
+    IndexWaitError,
+    BackupError,
+    TimingUpdateError
+);
 
-#[derive(Debug, Clone, Copy)]
-/// Defines errors that can occur when interacting with the persistent storage layer.
-pub enum PersistentStorageError {
-    /// An error occurred while reading from/writing to disk.
-    IoError,
-    /// An error occurred while serializing/deserializing data.
-    SerdeError,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why waiting for a node to apply a log index ended without it.
+pub enum IndexWaitError {
+    /// The Raft thread has exited and won't apply the index anymore.
+    NodeStopped,
 }
 
-/// A trait that defines the interface for a persistent storage layer for Raft.
-pub trait PersistentStorage<C: LogCommand>: Send {
-    /// Returns the current term of the Raft node.
-    fn current_term(&self) -> TermIndex;
-    /// Returns the server that the Raft node voted for in the current term.
-    fn vote_for_current_term(&self) -> Option<ServerId>;
-
-    /// Updates the current term of the Raft node.
-    fn update_term(&mut self, term: TermIndex) -> &mut Self;
-    /// Updates the current term of the Raft node.
-    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a node didn't back up its storage.
+pub enum BackupError {
+    /// The storage couldn't write the copy, or can't make one at all.
+    Storage(PersistentStorageError),
+    /// The Raft thread has exited and won't pick up new requests.
+    NodeStopped,
+}
 
-    /// Returns the log index of the last entry in the log.
-    fn last_entry_index(&self) -> Option<LogIndex>;
-    /// Returns true if the log contains an entry with the given index and term.
-    fn has_entry(self, index: LogIndex, term: TermIndex) -> bool;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a node didn't take a pause or resume request.
+pub enum PauseError {
+    /// The Raft thread has exited, there's nothing left to pause or resume.
+    NodeStopped,
+}
 
-    /// Appends the given entries to the log.
-    fn append(&mut self, entries: Vec<LogEntry<C>>) -> &mut Self;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a node didn't take a [`raft_core::TimingUpdate`].
+pub enum TimingUpdateError {
+    /// The node's config with the update would keep the cluster from working, it kept the one it had.
+    InvalidConfig(RaftConfigError),
+    /// The Raft thread has exited and won't pick up new requests.
+    NodeStopped,
+}
 
-    /// Writes/fsyncs any pending changes to disk.
-    fn sync(&mut self) -> Result<(), PersistentStorageError>;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a node didn't shut down cleanly.
+pub enum ShutdownError {
+    /// The Raft thread didn't stop within the timeout, it still does once it wakes up.
+    TimedOut,
+    /// The Raft thread had already stopped because of this error.
+    Halted(RaftError),
+    /// The Raft thread panicked.
+    Crashed,
 }
 
-#[derive(Debug)]
-/// Enum of errors that can originate from the Raft transport code
-pub enum RaftTransportError {
-    /// The transport was shutdown.
-    TransportShutdown,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a [`crate::RaftNodeBuilder`] didn't start a node.
+pub enum NodeBuildError {
+    /// The node was given neither storage nor a storage directory.
+    MissingStoragePath,
+    /// The server is listed among its own peers.
+    ServerIsOwnPeer(ServerId),
+    /// The node's config would keep the cluster from working.
+    InvalidConfig(RaftConfigError),
+    /// The OS wouldn't start the thread the node, or a group manager's worker, runs on.
+    ThreadSpawnFailed,
+    /// The node was started as a task outside of a tokio runtime.
+    #[cfg(feature = "tokio")]
+    NoAsyncRuntime,
 }
+impl std::fmt::Display for NodeBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeBuildError::MissingStoragePath => {
+                write!(f, "a storage or a storage path is required")
+            }
+            NodeBuildError::ServerIsOwnPeer(server_id) => {
+                write!(f, "server {} is listed among its own peers", server_id.0)
+            }
+            NodeBuildError::InvalidConfig(error) => write!(f, "invalid config: {}", error),
+            NodeBuildError::ThreadSpawnFailed => write!(f, "could not spawn a thread for the node"),
+            #[cfg(feature = "tokio")]
+            NodeBuildError::NoAsyncRuntime => {
+                write!(
+                    f,
+                    "an async node has to be started from within a tokio runtime"
+                )
+            }
+        }
+    }
+}
+impl std::error::Error for NodeBuildError {}
 
 /// A trait that defines the interface for a network transport for Raft.
 /// This is used by the Raft node to send and receive messages from other nodes.
@@ -103,18 +155,111 @@ pub trait RaftTransportConnector<C: LogCommand>: Send {
 
     /// Enqueues a request to be sent to the given server.
     fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError>;
+
+    /// Hands over the node's metrics before the node starts, for transports that record into them.
+    /// Ignored unless implemented.
+    fn attach_metrics(&mut self, _metrics: RaftMetrics) {}
+
+    /// Most bytes of one encoded message the transport carries, `None` for no limit of its own.
+    /// The node keeps what the leader sends within it, see
+    /// [`raft_core::RaftConfig::max_message_size`].
+    fn message_size_limit(&self) -> Option<usize> {
+        None
+    }
 }
 
-/// A trait that defines the interface for a state machine that can be used with Raft.
-/// The state machine is responsible for applying commands to its state and returning
-/// an error if the command cannot be queued for applying to the application's state machine.
+/// The application whose state Raft keeps consistent across the cluster. The Raft thread applies
+/// committed commands to it one at a time, in log order.
 ///
-/// Additionally, the state machine must be able to return the index of the last command successfully applied.
-/// This is used by Raft to determine if it can commit a new entry
-trait ApplicationThatNeedsConsensus: Send {
+/// Additionally, the application must be able to return the index of the last command it applied.
+/// Commands up to that index aren't applied again when the node restarts.
+/// It also serializes its state into snapshots so the log can be compacted, and restores it from them.
+pub trait ApplicationThatNeedsConsensus: Send {
+    /// The commands replicated through the log.
     type Command: LogCommand;
-    type Error: Debug + Clone + Send + Eq + PartialEq;
+    /// What applying a command hands back to whoever proposed it, kept per client session for retries.
+    type Output: Debug + Clone + Send + 'static;
+    /// Why the application turned a command down. The command still counts as applied.
+    type Error: Debug + Clone + Send + Eq + PartialEq + 'static;
 
-    fn apply(&mut self, log_index: LogIndex, command: Self::Command) -> Result<(), Self::Error>;
+    /// Applies the committed `command` at `log_index` to the application's state.
+    fn apply(
+        &mut self,
+        log_index: LogIndex,
+        command: Self::Command,
+    ) -> Result<Self::Output, Self::Error>;
+    /// Returns the index of the last command applied, `LogIndex(0)` if there is none.
     fn last_applied_index(&self) -> LogIndex;
+    /// Serializes the application's state, with every command up to
+    /// [`ApplicationThatNeedsConsensus::last_applied_index`] applied.
+    fn snapshot(&self) -> Vec<u8>;
+    /// Replaces the application's state with `snapshot`, taken by this node or sent by the leader.
+    /// Afterwards the last applied index is the snapshot's last included index.
+    fn restore_snapshot(&mut self, snapshot: &Snapshot);
+    /// Hands out the application's state as it stands, for [`crate::RaftNodeHandle::state_view`].
+    /// Applies wait while it's taken, keep it cheap, e.g. an `Arc` of copy-on-write state. By
+    /// default it serializes the state with [`ApplicationThatNeedsConsensus::snapshot`] right away.
+    fn freeze(&self) -> Arc<dyn FrozenState> {
+        Arc::new(self.snapshot())
+    }
+}
+
+/// An immutable copy of the application's state, see [`ApplicationThatNeedsConsensus::freeze`].
+pub trait FrozenState: Send + Sync {
+    /// Serializes the state as [`ApplicationThatNeedsConsensus::snapshot`] would have when it was
+    /// frozen.
+    fn serialize(&self) -> Vec<u8>;
+}
+impl FrozenState for Vec<u8> {
+    fn serialize(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+/// What applying a command gave back, or why the application turned it down.
+pub type ApplyResult<A> = Result<
+    <A as ApplicationThatNeedsConsensus>::Output,
+    <A as ApplicationThatNeedsConsensus>::Error,
+>;
+
+/// A proposed command the node applied: where, and what the application gave back. The index
+/// is a token for [`crate::RaftNodeHandle::read_after`], reads handed it see the command on any
+/// server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Applied<T> {
+    /// Index of the command's entry. For a retry in a session, of the retry's entry, which comes
+    /// after the one the command was first applied at.
+    pub index: LogIndex,
+    /// What the application gave back for the command.
+    pub result: T,
 }
+
+/// What became of a proposed command: applied, with the application's result, or never to be.
+pub type ProposalOutcome<A> = Result<Applied<ApplyResult<A>>, ProposalError>;
+
+/// The application's state as of one applied index, see [`crate::RaftNodeHandle::state_view`].
+/// It never changes, clones share the state.
+#[derive(Clone)]
+pub struct StateView {
+    /// Index of the last command applied to the state
+    pub applied_index: LogIndex,
+    pub(crate) state: Arc<dyn FrozenState>,
+}
+impl StateView {
+    /// The state, serialized as [`ApplicationThatNeedsConsensus::snapshot`] would, on the calling
+    /// thread rather than the node's.
+    pub fn data(&self) -> Vec<u8> {
+        self.state.serialize()
+    }
+}
+impl Debug for StateView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateView")
+            .field("applied_index", &self.applied_index)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A client command the node applied, with the index and term of its entry, see
+/// [`crate::RaftNodeHandle::subscribe_applied`].
+pub type AppliedEntry<C> = (LogIndex, TermIndex, C);
@@ -0,0 +1,71 @@
+use raft_core::ServerId;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::panic;
+use std::sync::Once;
+use tracing::error;
+
+thread_local! {
+    /// Set on Raft threads so the panic hook knows which node crashed
+    static RAFT_NODE: Cell<Option<ServerId>> = const { Cell::new(None) };
+    /// Where the last panic on this thread happened, the unwind payload doesn't carry it
+    static LAST_PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+
+/// Wraps the process panic hook, once, so a panic on a Raft thread is logged as a structured
+/// error naming the node. Panics on other threads only go to the previous hook.
+pub(crate) fn install_panic_hook() {
+    INSTALL_PANIC_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(server_id) = RAFT_NODE.with(Cell::get) {
+                let location = info
+                    .location()
+                    .map(|location| format!("{}:{}", location.file(), location.line()));
+                error!(
+                    server_id = server_id.0,
+                    thread = thread_name().as_str(),
+                    location = location.as_deref().unwrap_or("unknown"),
+                    message = panic_message(info.payload()).as_str(),
+                    "Raft node crashed"
+                );
+                LAST_PANIC_LOCATION.with(|last| *last.borrow_mut() = location);
+            }
+            previous_hook(info);
+        }));
+    });
+}
+
+/// Mark the calling thread as running the Raft node `server_id`
+pub(crate) fn mark_current_thread(server_id: ServerId) {
+    RAFT_NODE.with(|node| node.set(Some(server_id)));
+}
+
+/// Stop treating the calling thread as a Raft thread, for threads that run a node only for a while
+#[cfg(feature = "tokio")]
+pub(crate) fn unmark_current_thread() {
+    RAFT_NODE.with(|node| node.set(None));
+}
+
+/// Location of the last panic on the calling Raft thread, if the hook saw one
+pub(crate) fn take_panic_location() -> Option<String> {
+    LAST_PANIC_LOCATION.with(|last| last.borrow_mut().take())
+}
+
+/// The message passed to `panic!`, when it's a string
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+fn thread_name() -> String {
+    std::thread::current()
+        .name()
+        .unwrap_or("unnamed")
+        .to_string()
+}
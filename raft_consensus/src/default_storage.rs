@@ -1,23 +1,54 @@
-use crate::PersistentStorageError;
+// The `maybe!` macro from fault_injection expands to a pointer transmute that clippy flags
+#![cfg_attr(feature = "fault_injection", allow(clippy::crosspointer_transmute))]
+use raft_core::PersistentStorageError;
 
-use super::common::{LogCommand, LogEntry, LogIndex, PersistentStorage, ServerId, TermIndex};
+use raft_core::{
+    LogCommand, LogEntry, LogEntryCommand, LogIndex, PersistentStorage, ServerId, Snapshot,
+    TermIndex,
+};
+use std::collections::HashSet;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Seek, Write};
-use std::marker::PhantomData;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::mem;
-use std::path::Path;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use bincode::Options;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::codec::{BincodeCodec, CommandCodec};
+use tracing::warn;
+
+#[cfg(feature = "fault_injection")]
 use fault_injection::maybe;
 
+/// Without fault injection storage I/O is never made to fail on purpose
+#[cfg(not(feature = "fault_injection"))]
+macro_rules! maybe {
+    ($e:expr) => {
+        $e
+    };
+}
+
+/// The term and the vote cast in it, or in an earlier term the node hasn't voted in since
 #[derive(Debug, Serialize, Deserialize)]
-struct Election {
-    current_term: TermIndex,
-    voted_for: Option<(TermIndex, ServerId)>,
+pub(crate) struct Election {
+    pub(crate) current_term: TermIndex,
+    pub(crate) voted_for: Option<(TermIndex, ServerId)>,
+}
+impl Election {
+    pub(crate) fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.voted_for.and_then(|(last_vote_term, server_id)| {
+            if last_vote_term == self.current_term {
+                Some(server_id)
+            } else {
+                None
+            }
+        })
+    }
 }
 
 type WALBincodeOptions = bincode::config::WithOtherEndian<
@@ -34,119 +65,1113 @@ type WALBincodeOptions = bincode::config::WithOtherEndian<
     bincode::config::LittleEndian,
 >;
 #[inline]
-fn get_election_bincode() -> WALBincodeOptions {
+pub(crate) fn get_election_bincode() -> WALBincodeOptions {
     bincode::DefaultOptions::new()
-        .with_limit(mem::size_of::<Election>().try_into().unwrap())
+        .with_limit(mem::size_of::<Election>() as u64)
         .reject_trailing_bytes()
         .with_varint_encoding()
         .with_little_endian()
 }
 
-fn bincode_to_io_error(error_kind: Box<bincode::ErrorKind>) -> std::io::Error {
-    std::io::Error::new(
-        std::io::ErrorKind::Other,
-        format!("Bincode error: {:?}", error_kind),
+/// What a snapshot kept in storage covers, read without its data, see
+/// [`DefaultPersistentStorage::snapshots`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMetadata {
+    /// Index of the last entry the snapshot covers
+    pub last_included_index: LogIndex,
+    /// Term of the last entry the snapshot covers
+    pub last_included_term: TermIndex,
+    /// The cluster's voting members as of `last_included_index`
+    pub members: HashSet<ServerId>,
+    /// The learners as of `last_included_index`
+    pub learners: HashSet<ServerId>,
+    /// Size of the snapshot's file
+    pub file_len: u64,
+}
+
+/// What [`DefaultPersistentStorage::verify`] found in a storage directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Log records read, up to the first one that doesn't match its checksum
+    pub records: usize,
+    /// Index of the last entry read from the log file
+    pub last_index: Option<LogIndex>,
+    /// What's wrong with the files, in the order it was found
+    pub problems: Vec<IntegrityProblem>,
+}
+impl IntegrityReport {
+    /// Nothing is wrong, not even a record a crash left incomplete
+    pub fn is_sound(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Something [`DefaultPersistentStorage::verify`] found wrong. Offsets are in the log file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityProblem {
+    /// The election file doesn't hold a term and vote that match their checksum
+    ElectionCorrupted,
+    /// The snapshot file can't be read
+    SnapshotUnreadable,
+    /// The log file doesn't start with the header storage writes
+    MissingLogFileHeader,
+    /// The log file ends partway through a record, or in zeros, as a crash mid-append leaves it.
+    /// Opening the storage cuts it off.
+    IncompleteRecord { offset: u64 },
+    /// The record doesn't match its checksum, nothing after it is read
+    ChecksumMismatch { offset: u64 },
+    /// The record matches its checksum but doesn't hold an entry
+    Undecodable { offset: u64 },
+    /// The entry isn't the one right after the entry or snapshot before it
+    IndexOutOfOrder {
+        offset: u64,
+        expected: LogIndex,
+        found: LogIndex,
+    },
+    /// The entry's term is older than the term of the entry before it
+    TermDecreased {
+        index: LogIndex,
+        previous: TermIndex,
+        term: TermIndex,
+    },
+    /// The entry's term is newer than the term in the election file
+    TermAheadOfElection { index: LogIndex, term: TermIndex },
+}
+
+/// The fields a snapshot file starts with, deserializing only these leaves the data unread
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    last_included_index: LogIndex,
+    last_included_term: TermIndex,
+    members: HashSet<ServerId>,
+    learners: HashSet<ServerId>,
+}
+
+/// Log records are a little endian `u32` length and CRC32 checksum followed by the bincoded entry,
+/// which holds its command as the bytes the storage's [`CommandCodec`] encoded it to. The election
+/// file holds a single record of the same shape.
+const LOG_RECORD_HEADER_LEN: usize = 2 * mem::size_of::<u32>();
+
+/// The log file starts with these bytes followed by the [`CommandCodec::format`] of its commands
+const LOG_FILE_MAGIC: &[u8; 7] = b"raftlog";
+const LOG_FILE_HEADER_LEN: usize = LOG_FILE_MAGIC.len() + 1;
+
+/// Every how many records the log file's offset is kept, finding any other record reads the
+/// headers of the ones between it and the one kept before it
+const LOG_RECORD_INDEX_INTERVAL: usize = 64;
+
+/// Bytes read from the log file at a time when replaying it on startup
+const LOG_REPLAY_READ_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+#[inline]
+fn get_log_entry_bincode() -> bincode::DefaultOptions {
+    bincode::DefaultOptions::new()
+}
+
+#[inline]
+pub(crate) fn get_snapshot_bincode() -> bincode::DefaultOptions {
+    bincode::DefaultOptions::new()
+}
+
+/// The header of a log file holding commands in `format`
+fn log_file_header(format: u8) -> [u8; LOG_FILE_HEADER_LEN] {
+    let mut header = [format; LOG_FILE_HEADER_LEN];
+    header[..LOG_FILE_MAGIC.len()].copy_from_slice(LOG_FILE_MAGIC);
+    header
+}
+
+/// Checksum of a record, covering its length so a flipped bit in the header is caught as well
+fn record_checksum(record: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&(record.len() as u32).to_le_bytes());
+    hasher.update(record);
+    hasher.finalize()
+}
+
+/// The length and checksum a record header holds
+fn parse_record_header(header: [u8; LOG_RECORD_HEADER_LEN]) -> (usize, u32) {
+    let [a, b, c, d, e, f, g, h] = header;
+    (
+        u32::from_le_bytes([a, b, c, d]) as usize,
+        u32::from_le_bytes([e, f, g, h]),
     )
 }
 
+/// [`PersistentStorageError::StorageFull`] for a disk out of space or a user over their disk
+/// quota, [`PersistentStorageError::IoError`] for anything else
+pub(crate) fn io_error(error: std::io::Error) -> PersistentStorageError {
+    match error.kind() {
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded => PersistentStorageError::StorageFull,
+        _ => PersistentStorageError::IoError,
+    }
+}
+
+/// The header written ahead of `record`
+fn record_header(record: &[u8]) -> Result<[u8; LOG_RECORD_HEADER_LEN], PersistentStorageError> {
+    let record_len = u32::try_from(record.len()).map_err(|_| PersistentStorageError::SerdeError)?;
+    let mut header = [0; LOG_RECORD_HEADER_LEN];
+    header[..4].copy_from_slice(&record_len.to_le_bytes());
+    header[4..].copy_from_slice(&record_checksum(record).to_le_bytes());
+    Ok(header)
+}
+
+/// Fails with [`PersistentStorageError::Corrupted`] unless `record` still matches its checksum
+fn verify_record(record: &[u8], checksum: u32) -> Result<(), PersistentStorageError> {
+    if record_checksum(record) == checksum {
+        Ok(())
+    } else {
+        Err(PersistentStorageError::Corrupted)
+    }
+}
+
+pub(crate) fn decode_log_record<C: LogCommand>(
+    codec: &dyn CommandCodec<C>,
+    record: &[u8],
+) -> Result<LogEntry<C>, PersistentStorageError> {
+    let entry: LogEntry<Vec<u8>> = get_log_entry_bincode()
+        .deserialize(record)
+        .map_err(|_| PersistentStorageError::SerdeError)?;
+    let decode = |bytes: &[u8]| {
+        codec
+            .decode(bytes)
+            .map_err(|_| PersistentStorageError::SerdeError)
+    };
+    let command = match entry.command {
+        LogEntryCommand::Application(bytes) => LogEntryCommand::Application(decode(&bytes)?),
+        LogEntryCommand::SessionCommand(session, bytes) => {
+            LogEntryCommand::SessionCommand(session, decode(&bytes)?)
+        }
+        LogEntryCommand::MembershipChange(change) => LogEntryCommand::MembershipChange(change),
+        LogEntryCommand::NoOp => LogEntryCommand::NoOp,
+    };
+    Ok(LogEntry {
+        index: entry.index,
+        term: entry.term,
+        command,
+    })
+}
+
+/// `entry` bincoded, with its command as the bytes `codec` encodes it to
+pub(crate) fn encode_log_record<C: LogCommand>(
+    codec: &dyn CommandCodec<C>,
+    entry: &LogEntry<C>,
+) -> Result<Vec<u8>, PersistentStorageError> {
+    let encode = |command: &C| {
+        codec
+            .encode(command)
+            .map_err(|_| PersistentStorageError::SerdeError)
+    };
+    let command = match &entry.command {
+        LogEntryCommand::Application(command) => LogEntryCommand::Application(encode(command)?),
+        LogEntryCommand::SessionCommand(session, command) => {
+            LogEntryCommand::SessionCommand(*session, encode(command)?)
+        }
+        LogEntryCommand::MembershipChange(change) => LogEntryCommand::MembershipChange(*change),
+        LogEntryCommand::NoOp => LogEntryCommand::NoOp,
+    };
+    get_log_entry_bincode()
+        .serialize(&LogEntry {
+            index: entry.index,
+            term: entry.term,
+            command,
+        })
+        .map_err(|_| PersistentStorageError::SerdeError)
+}
+
+/// `snapshot` with its data as the bytes `codec` stores it as
+pub(crate) fn encode_snapshot<C: LogCommand>(
+    codec: &dyn CommandCodec<C>,
+    snapshot: &Snapshot,
+) -> Result<Snapshot, PersistentStorageError> {
+    Ok(Snapshot {
+        last_included_index: snapshot.last_included_index,
+        last_included_term: snapshot.last_included_term,
+        members: snapshot.members.clone(),
+        learners: snapshot.learners.clone(),
+        sessions: snapshot.sessions.clone(),
+        data: codec
+            .encode_snapshot_data(snapshot.data.clone())
+            .map_err(|_| PersistentStorageError::SerdeError)?,
+    })
+}
+
+/// The snapshot [`encode_snapshot`] turned into `stored`
+pub(crate) fn decode_snapshot<C: LogCommand>(
+    codec: &dyn CommandCodec<C>,
+    stored: Snapshot,
+) -> Result<Snapshot, PersistentStorageError> {
+    Ok(Snapshot {
+        data: codec
+            .decode_snapshot_data(stored.data)
+            .map_err(|_| PersistentStorageError::SerdeError)?,
+        ..stored
+    })
+}
+
+/// Bytes `entries` take up once encoded, as [`truncate_to_bytes`] counts them
+pub(crate) fn encoded_len<C: LogCommand>(entries: &[LogEntry<C>]) -> u64 {
+    entries
+        .iter()
+        .map(|entry| get_log_entry_bincode().serialized_size(entry).unwrap_or(0))
+        .sum()
+}
+
+/// Cuts `entries` short before the entry that takes them past `max_bytes` once encoded, keeping
+/// the first whatever its size
+pub(crate) fn truncate_to_bytes<C: LogCommand>(entries: &mut Vec<LogEntry<C>>, max_bytes: usize) {
+    let mut total_bytes: u64 = 0;
+    let within = entries
+        .iter()
+        .position(|entry| {
+            // An entry that doesn't encode never goes out anyway, count it as empty
+            total_bytes += get_log_entry_bincode().serialized_size(entry).unwrap_or(0);
+            total_bytes > max_bytes as u64
+        })
+        .map(|past_limit| past_limit.max(1))
+        .unwrap_or(entries.len());
+    entries.truncate(within);
+}
+
+/// Appends `record` to the log file behind its header, returning the number of bytes written
+fn write_log_record(
+    writer: &mut BufWriter<File>,
+    record: &[u8],
+) -> Result<u64, PersistentStorageError> {
+    let header = record_header(record)?;
+    maybe!(writer
+        .write_all(&header)
+        .and_then(|_| writer.write_all(record)))
+    .map_err(io_error)?;
+    Ok((LOG_RECORD_HEADER_LEN + record.len()) as u64)
+}
+
+/// Copies the first `len` bytes of the file at `from` to a new file at `to`, syncing it
+fn copy_file_prefix(from: &Path, to: &Path, len: u64) -> Result<(), PersistentStorageError> {
+    maybe!(File::open(from).and_then(|from| {
+        let mut to = File::create(to)?;
+        std::io::copy(&mut from.take(len), &mut to)?;
+        to.sync_data()
+    }))
+    .map_err(io_error)
+}
+
+/// Replaces `path` with `tmp_path`, syncing the directory so the rename survives a crash
+fn replace_file(tmp_path: &Path, path: &Path) -> Result<(), PersistentStorageError> {
+    maybe!(fs::rename(tmp_path, path).and_then(|_| {
+        path.parent()
+            .map(|dir| File::open(dir).and_then(|dir| dir.sync_all()))
+            .unwrap_or(Ok(()))
+    }))
+    .map_err(io_error)
+}
+
 /// WAL, should only be used from one thread
+///
+/// The whole log is kept in memory, entries `log[..synced_entries]` are also in the log file.
+/// Appends and truncations only touch memory until [`PersistentStorage::sync`] writes them out.
+/// Entries covered by the snapshot are dropped from both, `log` starts right after the snapshot.
 #[derive(Debug)]
 pub struct DefaultPersistentStorage<C: LogCommand> {
+    log_path: PathBuf,
     election: Election,
-    election_writer: BufWriter<File>,
-    /// This is a placeholder so we can use the generic type parameters C:LogCommand
-    /// We will need this type param later when we implement the WAL
-    placeholder: PhantomData<C>,
+    /// The term or vote changed since they were last written to the election file
+    election_unsynced: bool,
+    snapshot: Option<Snapshot>,
+    /// The snapshot changed since it was last written to the snapshot file
+    snapshot_unsynced: bool,
+    /// Index the snapshot in the snapshot file was taken at
+    synced_snapshot_index: Option<LogIndex>,
+    /// Snapshots kept, the one in the snapshot file and older ones in `snapshot.<index>` files
+    snapshot_retention: NonZeroUsize,
+    /// The log file still holds entries the snapshot covers, the next sync writes it anew
+    log_file_needs_compaction: bool,
+    log: Vec<LogEntry<C>>,
+    /// Offset in the log file of every [`LOG_RECORD_INDEX_INTERVAL`]th record, starting with the
+    /// first. Kept sparse, only truncating synced entries has to find a record in the file.
+    log_record_index: Vec<u64>,
+    /// Number of records in the log file, the first `synced_entries` of them still match the log
+    log_file_records: usize,
+    /// Where the next record goes in the log file
+    log_file_len: u64,
+    synced_entries: usize,
+    log_writer: BufWriter<File>,
+    /// What the commands in the log file are encoded with
+    codec: Arc<dyn CommandCodec<C>>,
+    /// Most bytes the log file may take up
+    byte_quota: Option<u64>,
+    /// The last sync ran out of room
+    full: bool,
 }
 impl<C: LogCommand> DefaultPersistentStorage<C> {
-    pub fn new(log_path: &Path) -> Self {
-        // TODO: Currently we only care about persisting election data, will eventually need WAL
-        let (election, election_writer) = Self::open_election_file(log_path);
+    /// Opens the election and log files in `log_path`, creating them if they don't exist yet.
+    /// Commands are encoded with [`BincodeCodec`].
+    pub fn new(log_path: &Path) -> Result<Self, PersistentStorageError> {
+        Self::with_codec(log_path, Arc::new(BincodeCodec))
+    }
+
+    /// Like [`DefaultPersistentStorage::new`], but encodes commands with `codec`. A log file has
+    /// to be opened with a codec of the format it was written in, its header names that format
+    /// and opening it with another fails with [`PersistentStorageError::FormatMismatch`].
+    pub fn with_codec(
+        log_path: &Path,
+        codec: Arc<dyn CommandCodec<C>>,
+    ) -> Result<Self, PersistentStorageError> {
+        let election = Self::read_election_file(log_path)?;
+        let snapshot = Self::read_snapshot_file(&log_path.join("snapshot"), &*codec)?;
+        let (log, log_record_index, log_file_len, log_writer) =
+            Self::open_log_file(log_path, &*codec)?;
 
-        DefaultPersistentStorage {
+        let mut storage = DefaultPersistentStorage {
+            log_path: log_path.to_path_buf(),
             election,
-            election_writer,
-            placeholder: PhantomData,
+            election_unsynced: false,
+            snapshot: None,
+            snapshot_unsynced: false,
+            synced_snapshot_index: snapshot
+                .as_ref()
+                .map(|snapshot| snapshot.last_included_index),
+            snapshot_retention: NonZeroUsize::MIN,
+            log_file_needs_compaction: false,
+            synced_entries: log.len(),
+            log_file_records: log.len(),
+            log,
+            log_record_index,
+            log_file_len,
+            log_writer,
+            codec,
+            byte_quota: None,
+            full: false,
+        };
+        if let Some(snapshot) = snapshot {
+            // Entries the snapshot covers are left over if the node crashed between writing the
+            // snapshot and compacting the log file
+            let covered = storage
+                .log
+                .iter()
+                .take_while(|entry| entry.index <= snapshot.last_included_index)
+                .count();
+            if covered > 0 {
+                match storage.log.get(covered - 1) {
+                    Some(entry)
+                        if entry.index == snapshot.last_included_index
+                            && entry.term == snapshot.last_included_term =>
+                    {
+                        storage.log.drain(..covered);
+                    }
+                    _ => storage.log.clear(),
+                }
+                storage.log_file_needs_compaction = true;
+            }
+            storage.snapshot = Some(snapshot);
+        }
+        Ok(storage)
+    }
+
+    /// Reads the election, snapshot and log files in `log_path` and reports whatever is wrong with
+    /// them: records that fail their checksum or don't decode, and entries whose indexes don't
+    /// follow each other or whose terms go backwards. Only reads the files, so it also works on
+    /// storage that fails to open. Commands are left encoded, no codec is needed.
+    pub fn verify(log_path: &Path) -> Result<IntegrityReport, PersistentStorageError> {
+        let mut problems = Vec::new();
+        let election = match Self::read_election_file(log_path) {
+            Ok(election) => Some(election),
+            Err(PersistentStorageError::IoError) => return Err(PersistentStorageError::IoError),
+            Err(_) => {
+                problems.push(IntegrityProblem::ElectionCorrupted);
+                None
+            }
+        };
+        let snapshot_index = match maybe!(File::open(log_path.join("snapshot"))) {
+            Ok(file) => {
+                match get_snapshot_bincode()
+                    .deserialize_from::<_, SnapshotHeader>(BufReader::new(file))
+                {
+                    Ok(header) => Some(header.last_included_index),
+                    Err(_) => {
+                        problems.push(IntegrityProblem::SnapshotUnreadable);
+                        None
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(_) => return Err(PersistentStorageError::IoError),
+        };
+
+        let mut report = IntegrityReport {
+            records: 0,
+            last_index: None,
+            problems,
+        };
+        let file = match maybe!(File::open(log_path.join("log"))) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(report),
+            Err(_) => return Err(PersistentStorageError::IoError),
+        };
+        let file_len = maybe!(file.metadata())
+            .map_err(|_| PersistentStorageError::IoError)?
+            .len();
+        let mut reader = BufReader::with_capacity(LOG_REPLAY_READ_BATCH_BYTES, file);
+        let mut file_header = [0; LOG_FILE_HEADER_LEN];
+        match maybe!(reader.read_exact(&mut file_header)) {
+            Ok(()) if file_header.starts_with(LOG_FILE_MAGIC) => {}
+            Ok(()) => {
+                report.problems.push(IntegrityProblem::MissingLogFileHeader);
+                return Ok(report);
+            }
+            // A file that never got its header holds no records either
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(report),
+            Err(_) => return Err(PersistentStorageError::IoError),
+        }
+
+        let mut offset = LOG_FILE_HEADER_LEN as u64;
+        let mut previous: Option<(LogIndex, TermIndex)> = None;
+        let mut record = Vec::new();
+        loop {
+            let mut header = [0; LOG_RECORD_HEADER_LEN];
+            let (record_len, checksum) = match maybe!(reader.read_exact(&mut header)) {
+                Ok(()) => parse_record_header(header),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    if offset < file_len {
+                        report
+                            .problems
+                            .push(IntegrityProblem::IncompleteRecord { offset });
+                    }
+                    break;
+                }
+                Err(_) => return Err(PersistentStorageError::IoError),
+            };
+            record.resize(record_len, 0);
+            match maybe!(reader.read_exact(&mut record)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    report
+                        .problems
+                        .push(IntegrityProblem::IncompleteRecord { offset });
+                    break;
+                }
+                Err(_) => return Err(PersistentStorageError::IoError),
+            }
+            if verify_record(&record, checksum).is_err() {
+                let mut tail = Vec::new();
+                maybe!(reader.read_to_end(&mut tail))
+                    .map_err(|_| PersistentStorageError::IoError)?;
+                let zeroed = header
+                    .iter()
+                    .chain(&record)
+                    .chain(&tail)
+                    .all(|byte| *byte == 0);
+                report.problems.push(if zeroed {
+                    IntegrityProblem::IncompleteRecord { offset }
+                } else {
+                    IntegrityProblem::ChecksumMismatch { offset }
+                });
+                break;
+            }
+            report.records += 1;
+            let record_offset = offset;
+            offset += (LOG_RECORD_HEADER_LEN + record_len) as u64;
+            let Ok(entry) = get_log_entry_bincode().deserialize::<LogEntry<Vec<u8>>>(&record)
+            else {
+                report.problems.push(IntegrityProblem::Undecodable {
+                    offset: record_offset,
+                });
+                continue;
+            };
+
+            // The log file can still start with entries the snapshot covers, they follow on from
+            // each other but not from the snapshot
+            let expected = match previous {
+                Some((index, _)) => Some(LogIndex(index.0 + 1)),
+                None => snapshot_index
+                    .filter(|snapshot_index| entry.index > *snapshot_index)
+                    .map(|snapshot_index| LogIndex(snapshot_index.0 + 1)),
+            };
+            if let Some(expected) = expected.filter(|expected| *expected != entry.index) {
+                report.problems.push(IntegrityProblem::IndexOutOfOrder {
+                    offset: record_offset,
+                    expected,
+                    found: entry.index,
+                });
+            }
+            if let Some((_, previous_term)) = previous.filter(|(_, term)| *term > entry.term) {
+                report.problems.push(IntegrityProblem::TermDecreased {
+                    index: entry.index,
+                    previous: previous_term,
+                    term: entry.term,
+                });
+            }
+            if election
+                .as_ref()
+                .is_some_and(|election| entry.term > election.current_term)
+            {
+                report.problems.push(IntegrityProblem::TermAheadOfElection {
+                    index: entry.index,
+                    term: entry.term,
+                });
+            }
+            report.last_index = Some(entry.index);
+            previous = Some((entry.index, entry.term));
         }
+        Ok(report)
+    }
+
+    /// Keeps the latest `count` snapshots rather than only the latest one. Older ones are removed
+    /// once a newer one is synced, never the latest.
+    pub fn retain_snapshots(mut self, count: NonZeroUsize) -> Self {
+        self.snapshot_retention = count;
+        self
     }
 
-    fn open_election_file(log_path: &Path) -> (Election, BufWriter<File>) {
-        let file_size: usize = mem::size_of::<Election>();
-        let election_file_exists = log_path.join("election").exists();
-        let (reader, mut writer) = maybe!(File::options()
+    /// Counts storage as full once the log file takes up `bytes`, failing syncs that add entries
+    /// to it with [`PersistentStorageError::StorageFull`] like a full disk does. The sync that
+    /// takes the file past `bytes` still goes through. Compacting the log into a snapshot, or
+    /// truncating it, makes room again.
+    pub fn byte_quota(mut self, bytes: u64) -> Self {
+        self.byte_quota = Some(bytes);
+        self
+    }
+
+    /// The log file takes up all of the byte quota
+    fn over_byte_quota(&self) -> bool {
+        self.byte_quota
+            .is_some_and(|quota| self.log_file_len >= quota)
+    }
+
+    /// The snapshots kept, newest first. The first is the one the node runs from, the others
+    /// older ones kept as [`DefaultPersistentStorage::retain_snapshots`] asked for.
+    pub fn snapshots(&self) -> Result<Vec<SnapshotMetadata>, PersistentStorageError> {
+        let latest = self.log_path.join("snapshot");
+        let retained = self
+            .retained_snapshot_indexes()?
+            .into_iter()
+            .rev()
+            .map(|index| self.retained_snapshot_path(index));
+        let mut snapshots = Vec::new();
+        for path in std::iter::once(latest).chain(retained) {
+            let file = match maybe!(File::open(&path)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(_) => return Err(PersistentStorageError::IoError),
+            };
+            let file_len = maybe!(file.metadata())
+                .map_err(|_| PersistentStorageError::IoError)?
+                .len();
+            let header: SnapshotHeader = get_snapshot_bincode()
+                .deserialize_from(BufReader::new(file))
+                .map_err(|_| PersistentStorageError::SerdeError)?;
+            snapshots.push(SnapshotMetadata {
+                last_included_index: header.last_included_index,
+                last_included_term: header.last_included_term,
+                members: header.members,
+                learners: header.learners,
+                file_len,
+            });
+        }
+        Ok(snapshots)
+    }
+
+    /// Reads the kept snapshot taken at `last_included_index`, if there's one
+    pub fn read_snapshot(
+        &self,
+        last_included_index: LogIndex,
+    ) -> Result<Option<Snapshot>, PersistentStorageError> {
+        if self.synced_snapshot_index == Some(last_included_index) {
+            return Self::read_snapshot_file(&self.log_path.join("snapshot"), &*self.codec);
+        }
+        Self::read_snapshot_file(
+            &self.retained_snapshot_path(last_included_index),
+            &*self.codec,
+        )
+    }
+
+    /// The snapshot in the file at `path`, if it exists, its data decoded with `codec`
+    fn read_snapshot_file(
+        path: &Path,
+        codec: &dyn CommandCodec<C>,
+    ) -> Result<Option<Snapshot>, PersistentStorageError> {
+        let file = match maybe!(File::open(path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(_) => return Err(PersistentStorageError::IoError),
+        };
+        let stored = get_snapshot_bincode()
+            .deserialize_from(BufReader::new(file))
+            .map_err(|_| PersistentStorageError::SerdeError)?;
+        decode_snapshot(codec, stored).map(Some)
+    }
+
+    /// Index of the last entry the snapshot covers, `log[0]` is the entry right after it
+    fn compacted_through(&self) -> u64 {
+        self.snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.last_included_index.0)
+            .unwrap_or(0)
+    }
+
+    /// Position in `log` of the entry at `index`, `None` for `LogIndex(0)` and compacted entries
+    fn log_position(&self, index: LogIndex) -> Option<usize> {
+        index
+            .0
+            .checked_sub(self.compacted_through() + 1)
+            .map(|position| position as usize)
+    }
+
+    /// Replays every complete record in the log file, reading it in large batches rather than
+    /// all at once so the file's bytes and the decoded log aren't both held in memory. A record cut
+    /// short by a crash mid-write, or zeros the file was extended with before the crash, is cut off
+    /// the file, it was never synced so nobody relied on it. Replay stops with
+    /// [`PersistentStorageError::Corrupted`] at any other record that doesn't match its checksum.
+    /// A file that doesn't start with the header of `codec`'s format fails with
+    /// [`PersistentStorageError::FormatMismatch`] before any of it is read.
+    /// Returns the log, the sparse index of its records and the file length.
+    #[allow(clippy::type_complexity)]
+    fn open_log_file(
+        log_path: &Path,
+        codec: &dyn CommandCodec<C>,
+    ) -> Result<(Vec<LogEntry<C>>, Vec<u64>, u64, BufWriter<File>), PersistentStorageError> {
+        let mut file = maybe!(File::options()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
-            .open(log_path.join("election"))
-            .and_then(|f| f.set_len(file_size as u64).map(|_| f))
-            .and_then(|f| {
-                f.try_clone()
-                    .map(|f_cloned| (BufReader::new(f), BufWriter::new(f_cloned)))
-            }))
-        .expect(
-            format!(
-                "OPEN ELEC FILE: Could not open election file {:?} and set file size!",
-                log_path
-            )
-            .as_str(),
-        );
-
-        if election_file_exists {
-            let header = get_election_bincode()
-                .deserialize_from(reader)
-                .expect("OPEN ELEC FILE: Could not deserialize election file!");
-            (header, writer)
+            .open(log_path.join("log")))
+        .map_err(|_| PersistentStorageError::IoError)?;
+
+        let mut file_len = maybe!(file.metadata())
+            .map_err(|_| PersistentStorageError::IoError)?
+            .len();
+        let header = log_file_header(codec.format());
+        if file_len < LOG_FILE_HEADER_LEN as u64 {
+            // A new file, or one a crash left before its header was synced, holds no records yet
+            maybe!(file
+                .set_len(0)
+                .and_then(|_| file.write_all(&header))
+                .and_then(|_| file.sync_data()))
+            .map_err(|_| PersistentStorageError::IoError)?;
+            file_len = LOG_FILE_HEADER_LEN as u64;
         } else {
-            let election = Election {
-                current_term: TermIndex(0),
-                voted_for: None,
+            let mut found = [0; LOG_FILE_HEADER_LEN];
+            maybe!(file.read_exact(&mut found)).map_err(|_| PersistentStorageError::IoError)?;
+            if found != header {
+                return Err(PersistentStorageError::FormatMismatch);
+            }
+        }
+        let mut log = Vec::new();
+        let mut record_index = Vec::new();
+        let mut offset = LOG_FILE_HEADER_LEN as u64;
+        let mut reader = BufReader::with_capacity(LOG_REPLAY_READ_BATCH_BYTES, &file);
+        let mut record = Vec::new();
+        let mut corrupt_at = None;
+        loop {
+            let buffered =
+                maybe!(reader.fill_buf()).map_err(|_| PersistentStorageError::IoError)?;
+            if buffered.is_empty() {
+                break;
+            }
+            // Most records sit whole in the read buffer and are decoded right out of it,
+            // only the ones straddling two batches are copied out first
+            let decoded_in_place = match buffered {
+                [a, b, c, d, e, f, g, h, rest @ ..] => {
+                    let (record_len, checksum) =
+                        parse_record_header([*a, *b, *c, *d, *e, *f, *g, *h]);
+                    match rest.get(..record_len) {
+                        Some(record) => {
+                            if verify_record(record, checksum).is_err() {
+                                corrupt_at = Some(offset);
+                                break;
+                            }
+                            log.push(decode_log_record(codec, record)?);
+                            Some(record_len)
+                        }
+                        None => None,
+                    }
+                }
+                _ => None,
             };
-            Self::write_election_state(&election, &mut writer)
-                .expect("OPEN ELEC FILE: Could not write initial state to election file!");
-            maybe!(writer.flush()).expect("OPEN ELEC FILE: Could not fsync header to WAL!");
-            (election, writer)
+            let record_len = match decoded_in_place {
+                Some(record_len) => {
+                    reader.consume(LOG_RECORD_HEADER_LEN + record_len);
+                    record_len
+                }
+                None => {
+                    let mut header = [0; LOG_RECORD_HEADER_LEN];
+                    match maybe!(reader.read_exact(&mut header)) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                        Err(_) => return Err(PersistentStorageError::IoError),
+                    }
+                    let (record_len, checksum) = parse_record_header(header);
+                    // A length running past the end of the file is a torn tail, cut off below
+                    // rather than taken for how much to allocate
+                    let record_start = offset + LOG_RECORD_HEADER_LEN as u64;
+                    if record_len as u64 > file_len.saturating_sub(record_start) {
+                        break;
+                    }
+                    record.resize(record_len, 0);
+                    match maybe!(reader.read_exact(&mut record)) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                        Err(_) => return Err(PersistentStorageError::IoError),
+                    }
+                    if verify_record(&record, checksum).is_err() {
+                        corrupt_at = Some(offset);
+                        break;
+                    }
+                    log.push(decode_log_record(codec, &record)?);
+                    record.len()
+                }
+            };
+            if (log.len() - 1).is_multiple_of(LOG_RECORD_INDEX_INTERVAL) {
+                record_index.push(offset);
+            }
+            let record_end = offset + (LOG_RECORD_HEADER_LEN + record_len) as u64;
+            if log.len() == 1 {
+                // Entries tend to be about the same size, the first one tells how many to expect
+                log.reserve(((file_len - offset) / (record_end - offset)) as usize);
+            }
+            offset = record_end;
+        }
+        drop(reader);
+
+        if let Some(record_start) = corrupt_at {
+            // A crash mid-append can leave the file extended with zeros the record never made it into
+            if !Self::is_zeroed_from(&mut file, record_start)? {
+                return Err(PersistentStorageError::Corrupted);
+            }
         }
+        if offset < file_len {
+            warn!(
+                "Cutting {} bytes of a record left incomplete by a crash off the end of log file {:?}",
+                file_len - offset,
+                log_path.join("log")
+            );
+        }
+        maybe!(file
+            .set_len(offset)
+            .and_then(|_| file.seek(SeekFrom::Start(offset))))
+        .map_err(|_| PersistentStorageError::IoError)?;
+
+        Ok((log, record_index, offset, BufWriter::new(file)))
     }
 
-    fn write_election_state(
-        election: &Election,
-        election_writer: &mut BufWriter<File>,
-    ) -> Result<(), PersistentStorageError> {
-        maybe!(election_writer.rewind()).map_err(|_| PersistentStorageError::IoError)?;
-        maybe!(get_election_bincode()
-            .serialize_into(election_writer, election)
-            .map_err(bincode_to_io_error))
+    /// True if nothing but zeros follows `offset` in `file`
+    fn is_zeroed_from(file: &mut File, offset: u64) -> Result<bool, PersistentStorageError> {
+        let mut tail = Vec::new();
+        maybe!(file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_to_end(&mut tail)))
         .map_err(|_| PersistentStorageError::IoError)?;
+        Ok(tail.iter().all(|byte| *byte == 0))
+    }
+
+    /// Offset in the log file of the record at `position`, found from the closest record indexed
+    /// before it. Only reads the headers of the records in between, skipping over the entries.
+    fn log_record_offset(&mut self, position: usize) -> Result<u64, PersistentStorageError> {
+        let mut offset = self
+            .log_record_index
+            .get(position / LOG_RECORD_INDEX_INTERVAL)
+            .copied()
+            .ok_or(PersistentStorageError::IoError)?;
+        maybe!(self.log_writer.flush()).map_err(|_| PersistentStorageError::IoError)?;
+        let mut reader = BufReader::new(self.log_writer.get_ref());
+        maybe!(reader.seek(SeekFrom::Start(offset)))
+            .map_err(|_| PersistentStorageError::IoError)?;
+        for _ in 0..position % LOG_RECORD_INDEX_INTERVAL {
+            let mut header = [0; LOG_RECORD_HEADER_LEN];
+            maybe!(reader.read_exact(&mut header)).map_err(|_| PersistentStorageError::IoError)?;
+            let (record_len, _) = parse_record_header(header);
+            maybe!(reader.seek_relative(record_len as i64))
+                .map_err(|_| PersistentStorageError::IoError)?;
+            offset += (LOG_RECORD_HEADER_LEN + record_len) as u64;
+        }
+        Ok(offset)
+    }
+
+    /// Writes out the entries appended since the last sync, first cutting entries that were
+    /// truncated from memory off the file.
+    fn sync_log(&mut self) -> Result<(), PersistentStorageError> {
+        if self.synced_entries < self.log_file_records {
+            let truncate_at = self.log_record_offset(self.synced_entries)?;
+            maybe!(self.log_writer.flush().and_then(|_| {
+                let file = self.log_writer.get_mut();
+                file.set_len(truncate_at)
+                    .and_then(|_| file.seek(SeekFrom::Start(truncate_at)))
+            }))
+            .map_err(io_error)?;
+            self.log_record_index
+                .truncate(self.synced_entries.div_ceil(LOG_RECORD_INDEX_INTERVAL));
+            self.log_file_records = self.synced_entries;
+            self.log_file_len = truncate_at;
+        }
+        if self.synced_entries == self.log.len() {
+            return Ok(());
+        }
+
+        if self.over_byte_quota() {
+            return Err(PersistentStorageError::StorageFull);
+        }
+        for entry in &self.log[self.synced_entries..] {
+            let record_len = write_log_record(
+                &mut self.log_writer,
+                &encode_log_record(&*self.codec, entry)?,
+            )?;
+            if self
+                .log_file_records
+                .is_multiple_of(LOG_RECORD_INDEX_INTERVAL)
+            {
+                self.log_record_index.push(self.log_file_len);
+            }
+            self.log_file_len += record_len;
+            self.log_file_records += 1;
+        }
+        maybe!(self
+            .log_writer
+            .flush()
+            .and_then(|_| self.log_writer.get_ref().sync_data()))
+        .map_err(io_error)?;
+        self.synced_entries = self.log.len();
+        Ok(())
+    }
+
+    /// Writes whatever changed since the last sync
+    fn sync_changes(&mut self) -> Result<(), PersistentStorageError> {
+        if self.election_unsynced {
+            self.sync_election()?;
+        }
+        // The snapshot goes first, the entries it covers can only leave the log file once it's durable
+        if self.snapshot_unsynced {
+            self.sync_snapshot()?;
+        }
+        if self.log_file_needs_compaction {
+            self.compact_log_file()
+        } else {
+            self.sync_log()
+        }
+    }
+
+    /// Writes the log as it is in memory to a new log file that replaces the old one, which still
+    /// starts with entries the snapshot covers. Only happens once per snapshot.
+    fn compact_log_file(&mut self) -> Result<(), PersistentStorageError> {
+        let tmp_path = self.log_path.join("log.tmp");
+        let file = maybe!(File::options()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&tmp_path))
+        .map_err(io_error)?;
+        let mut writer = BufWriter::new(file);
+        maybe!(writer.write_all(&log_file_header(self.codec.format()))).map_err(io_error)?;
+        let mut log_file_len = LOG_FILE_HEADER_LEN as u64;
+        let mut log_record_index = Vec::new();
+        for (position, entry) in self.log.iter().enumerate() {
+            if position.is_multiple_of(LOG_RECORD_INDEX_INTERVAL) {
+                log_record_index.push(log_file_len);
+            }
+            log_file_len +=
+                write_log_record(&mut writer, &encode_log_record(&*self.codec, entry)?)?;
+        }
+        maybe!(writer.flush().and_then(|_| writer.get_ref().sync_data())).map_err(io_error)?;
+        replace_file(&tmp_path, &self.log_path.join("log"))?;
+
+        self.log_writer = writer;
+        self.log_record_index = log_record_index;
+        self.log_file_records = self.log.len();
+        self.log_file_len = log_file_len;
+        self.synced_entries = self.log.len();
+        self.log_file_needs_compaction = false;
+        Ok(())
+    }
+
+    /// Where an older snapshot taken at `last_included_index` is kept
+    fn retained_snapshot_path(&self, last_included_index: LogIndex) -> PathBuf {
+        self.log_path
+            .join(format!("snapshot.{}", last_included_index.0))
+    }
+
+    /// Indexes of the older snapshots kept, in ascending order
+    fn retained_snapshot_indexes(&self) -> Result<Vec<LogIndex>, PersistentStorageError> {
+        let mut indexes = Vec::new();
+        for dir_entry in
+            maybe!(fs::read_dir(&self.log_path)).map_err(|_| PersistentStorageError::IoError)?
+        {
+            let dir_entry = maybe!(dir_entry).map_err(|_| PersistentStorageError::IoError)?;
+            // Anything else, like `snapshot.tmp`, isn't a snapshot kept
+            let index = dir_entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("snapshot."))
+                .and_then(|index| index.parse().ok());
+            if let Some(index) = index {
+                indexes.push(LogIndex(index));
+            }
+        }
+        indexes.sort();
+        Ok(indexes)
+    }
+
+    /// Removes the oldest snapshots kept beyond the retention count
+    fn prune_snapshots(&self) -> Result<(), PersistentStorageError> {
+        let retained = self.retained_snapshot_indexes()?;
+        let kept = self.snapshot_retention.get() - 1;
+        for index in &retained[..retained.len().saturating_sub(kept)] {
+            maybe!(fs::remove_file(self.retained_snapshot_path(*index)))
+                .map_err(|_| PersistentStorageError::IoError)?;
+        }
         Ok(())
     }
+
+    /// Writes the snapshot to a new snapshot file that replaces the old one, which is kept as
+    /// `snapshot.<index>` if older snapshots are retained
+    fn sync_snapshot(&mut self) -> Result<(), PersistentStorageError> {
+        let Some(snapshot) = self.snapshot.as_ref() else {
+            return Ok(());
+        };
+        let stored = encode_snapshot(&*self.codec, snapshot)?;
+        let tmp_path = self.log_path.join("snapshot.tmp");
+        let mut writer = BufWriter::new(maybe!(File::create(&tmp_path)).map_err(io_error)?);
+        get_snapshot_bincode()
+            .serialize_into(&mut writer, &stored)
+            .map_err(|error| match *error {
+                bincode::ErrorKind::Io(error) => io_error(error),
+                _ => PersistentStorageError::SerdeError,
+            })?;
+        maybe!(writer.flush().and_then(|_| writer.get_ref().sync_data())).map_err(io_error)?;
+        let last_included_index = snapshot.last_included_index;
+        let path = self.log_path.join("snapshot");
+        if let Some(replaced_index) = self
+            .synced_snapshot_index
+            .filter(|_| self.snapshot_retention.get() > 1)
+        {
+            // Linked rather than moved, there's a snapshot file at all times
+            match maybe!(fs::hard_link(
+                &path,
+                self.retained_snapshot_path(replaced_index)
+            )) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(_) => return Err(PersistentStorageError::IoError),
+            }
+        }
+        replace_file(&tmp_path, &path)?;
+        self.synced_snapshot_index = Some(last_included_index);
+        self.snapshot_unsynced = false;
+        self.prune_snapshots()
+    }
+
+    /// Reads the term and vote back from the election file, term 0 without a vote if it doesn't
+    /// exist yet. Fails with [`PersistentStorageError::Corrupted`] if they don't match their
+    /// checksum.
+    fn read_election_file(log_path: &Path) -> Result<Election, PersistentStorageError> {
+        let mut reader = match maybe!(File::open(log_path.join("election"))) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Election {
+                    current_term: TermIndex(0),
+                    voted_for: None,
+                })
+            }
+            Err(_) => return Err(PersistentStorageError::IoError),
+        };
+        let mut header = [0; LOG_RECORD_HEADER_LEN];
+        maybe!(reader.read_exact(&mut header)).map_err(|_| PersistentStorageError::IoError)?;
+        let (record_len, checksum) = parse_record_header(header);
+        if record_len > mem::size_of::<Election>() {
+            return Err(PersistentStorageError::Corrupted);
+        }
+        let mut record = vec![0; record_len];
+        maybe!(reader.read_exact(&mut record)).map_err(|_| PersistentStorageError::IoError)?;
+        verify_record(&record, checksum)?;
+        get_election_bincode()
+            .deserialize(&record)
+            .map_err(|_| PersistentStorageError::SerdeError)
+    }
+
+    /// Writes the term and vote as one record to a new election file that replaces the old one,
+    /// so a crash leaves either of them whole rather than a record torn between the two
+    fn sync_election(&mut self) -> Result<(), PersistentStorageError> {
+        Self::write_election_file(&self.election, &self.log_path)?;
+        self.election_unsynced = false;
+        Ok(())
+    }
+
+    /// Replaces the election file in `dir` with one holding `election`
+    fn write_election_file(election: &Election, dir: &Path) -> Result<(), PersistentStorageError> {
+        let record = get_election_bincode()
+            .serialize(election)
+            .map_err(|_| PersistentStorageError::SerdeError)?;
+        let header = record_header(&record)?;
+        let tmp_path = dir.join("election.tmp");
+        let mut file = maybe!(File::create(&tmp_path)).map_err(io_error)?;
+        maybe!(file
+            .write_all(&header)
+            .and_then(|_| file.write_all(&record))
+            .and_then(|_| file.sync_data()))
+        .map_err(io_error)?;
+        replace_file(&tmp_path, &dir.join("election"))
+    }
 }
 
 impl<C: LogCommand> PersistentStorage<C> for DefaultPersistentStorage<C> {
     fn vote_for_current_term(&self) -> Option<ServerId> {
-        self.election
-            .voted_for
-            .and_then(|(last_vote_term, server_id)| {
-                if last_vote_term == self.election.current_term {
-                    Some(server_id)
-                } else {
-                    None
-                }
-            })
+        self.election.vote_for_current_term()
     }
 
     fn update_term(&mut self, term: TermIndex) -> &mut Self {
+        self.election_unsynced |= term != self.election.current_term;
         self.election.current_term = term;
         self
     }
 
     fn record_vote(&mut self, voted_for: ServerId) -> &mut Self {
-        self.election.voted_for = Some((self.current_term(), voted_for));
+        let vote = Some((self.current_term(), voted_for));
+        self.election_unsynced |= vote != self.election.voted_for;
+        self.election.voted_for = vote;
         self
     }
 
     fn sync(&mut self) -> Result<(), PersistentStorageError> {
-        Self::write_election_state(&self.election, &mut self.election_writer)?;
-        maybe!(self.election_writer.flush()).map_err(|_| PersistentStorageError::IoError)t
+        let synced = self.sync_changes();
+        self.full = synced == Err(PersistentStorageError::StorageFull);
+        synced
+    }
+
+    fn is_full(&self) -> bool {
+        self.full || self.over_byte_quota()
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        Some(self.log_file_len)
+    }
+
+    fn backup_to(&mut self, path: &Path) -> Result<LogIndex, PersistentStorageError> {
+        self.sync()?;
+        maybe!(fs::create_dir_all(path)).map_err(io_error)?;
+        Self::write_election_file(&self.election, path)?;
+        match self.synced_snapshot_index {
+            Some(_) => {
+                let snapshot_path = self.log_path.join("snapshot");
+                let snapshot_len = maybe!(fs::metadata(&snapshot_path))
+                    .map_err(|_| PersistentStorageError::IoError)?
+                    .len();
+                copy_file_prefix(&snapshot_path, &path.join("snapshot"), snapshot_len)?;
+            }
+            // An earlier backup's snapshot would cover entries this log still has
+            None => match maybe!(fs::remove_file(path.join("snapshot"))) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(_) => return Err(PersistentStorageError::IoError),
+            },
+        }
+        // Synced just now, so the log file holds the whole log
+        copy_file_prefix(
+            &self.log_path.join("log"),
+            &path.join("log"),
+            self.log_file_len,
+        )?;
+        maybe!(File::open(path).and_then(|dir| dir.sync_all()))
+            .map_err(|_| PersistentStorageError::IoError)?;
+        Ok(self.last_entry_index().unwrap_or(LogIndex(0)))
     }
 
     fn current_term(&self) -> TermIndex {
@@ -154,15 +1179,96 @@ impl<C: LogCommand> PersistentStorage<C> for DefaultPersistentStorage<C> {
     }
 
     fn last_entry_index(&self) -> Option<LogIndex> {
-        todo!()
+        self.log
+            .last()
+            .map(|entry| entry.index)
+            .or(self.snapshot.as_ref().map(|s| s.last_included_index))
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        self.log
+            .last()
+            .map(|entry| entry.term)
+            .or(self.snapshot.as_ref().map(|s| s.last_included_term))
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        match &self.snapshot {
+            _ if index.0 == 0 => Some(TermIndex(0)),
+            Some(snapshot) if snapshot.last_included_index == index => {
+                Some(snapshot.last_included_term)
+            }
+            _ => self
+                .log_position(index)
+                .and_then(|position| self.log.get(position))
+                .map(|entry| entry.term),
+        }
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>> {
+        if from.0 != 0 && from.0 <= self.compacted_through() {
+            return Vec::new();
+        }
+        let start = self.log_position(from).unwrap_or(0).min(self.log.len());
+        let end = start.saturating_add(max_entries).min(self.log.len());
+        self.log[start..end].to_vec()
     }
 
-    /// Checks if there is a log entry with matching log index & log term
-    fn has_entry(self, _index: LogIndex, _term: TermIndex) -> bool {
-        todo!()
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>> {
+        let mut entries = self.entries(from, max_entries);
+        truncate_to_bytes(&mut entries, max_bytes);
+        entries
     }
-    /// Appends new entries to log, first deleting any conflicting entries (same index but different terms)
-    fn append(&mut self, _entries: Vec<LogEntry<C>>) -> &mut Self {
-        todo!()
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self {
+        if snapshot.last_included_index.0 <= self.compacted_through() {
+            return self;
+        }
+        let last_included_position = self.log_position(snapshot.last_included_index);
+        match last_included_position.and_then(|position| self.log.get(position)) {
+            Some(entry) if entry.term == snapshot.last_included_term => {
+                let covered = last_included_position.unwrap_or(0) + 1;
+                self.log.drain(..covered);
+            }
+            _ => self.log.clear(),
+        }
+        self.snapshot = Some(snapshot);
+        self.snapshot_unsynced = true;
+        self.log_file_needs_compaction = true;
+        self
+    }
+
+    fn append(&mut self, entries: Vec<LogEntry<C>>) -> &mut Self {
+        for entry in entries {
+            // Compacted entries were committed, whoever sends them again has the same ones
+            let Some(position) = self.log_position(entry.index) else {
+                continue;
+            };
+            match self.log.get(position) {
+                Some(existing) if existing.term == entry.term => continue,
+                Some(_) => {
+                    self.log.truncate(position);
+                    self.synced_entries = self.synced_entries.min(position);
+                }
+                None => {}
+            }
+            assert_eq!(
+                position,
+                self.log.len(),
+                "BUG: appending {:?} would leave a gap in the log",
+                entry.index
+            );
+            self.log.push(entry);
+        }
+        self
     }
 }
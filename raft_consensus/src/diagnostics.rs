@@ -0,0 +1,106 @@
+use crate::metrics::ElectionStats;
+use raft_core::system_clock::Instant;
+use raft_core::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Everything needed to debug a node from the outside, refreshed by the Raft thread on every loop iteration
+#[derive(Debug, Clone)]
+pub struct NodeDiagnostics {
+    /// Role, term, vote and log progress of the node
+    pub state: RaftStateEvent,
+    /// The rest of the cluster as this node knows it
+    pub peers: HashSet<ServerId>,
+    /// The servers the log is replicated to that don't vote
+    pub learners: HashSet<ServerId>,
+    /// Whether the node votes or learns in the latest configuration in its log, not once it's
+    /// been removed or before a server joining the cluster is added
+    pub in_configuration: bool,
+    /// Replication progress per follower, empty unless the node is leader
+    pub replication_progress: HashMap<ServerId, PeerProgress>,
+    pub config: RaftConfig,
+    /// Client proposals received that are not committed yet
+    pub pending_proposals: usize,
+    /// Elections the node ran and votes it cast since it started
+    pub elections: ElectionStats,
+    /// When the Raft thread published these diagnostics
+    pub updated_at: Instant,
+    /// When the node opened its storage and started
+    pub started_at: Instant,
+}
+impl NodeDiagnostics {
+    /// The summary an admin tool shows of the node
+    pub fn status(&self) -> RaftStatus {
+        RaftStatus {
+            server_id: self.state.server_id,
+            role: self.state.current_state,
+            current_term: self.state.current_term,
+            leader_id: self.state.leader_for_term,
+            commit_index: self.state.commit_index,
+            last_applied: self.state.last_applied,
+            last_log_index: self.state.last_log_index,
+            last_log_term: self.state.last_log_term,
+            peers: self.peers.clone(),
+            replication_progress: self.replication_progress.clone(),
+            elections: self.elections.clone(),
+            uptime: self.started_at.elapsed(),
+        }
+    }
+
+    /// The Raft thread wakes up at least once per election timeout or heartbeat interval,
+    /// diagnostics older than twice that mean the thread is stuck or gone.
+    pub fn is_stale(&self) -> bool {
+        let max_loop_interval = self.config.leader_heartbeat_interval.0.max(
+            self.config
+                .election_jitter
+                .longest_timeout(self.config.election_timeout),
+        );
+        self.updated_at.elapsed() > max_loop_interval * 2
+    }
+}
+
+/// Where a node stands, as of its last pass, see [`crate::RaftNodeHandle::status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaftStatus {
+    pub server_id: ServerId,
+    pub role: RaftNodeState,
+    pub current_term: TermIndex,
+    /// The leader the node knows of in its current term
+    pub leader_id: Option<ServerId>,
+    pub commit_index: LogIndex,
+    pub last_applied: LogIndex,
+    pub last_log_index: LogIndex,
+    pub last_log_term: TermIndex,
+    /// The other voting members as the node knows them
+    pub peers: HashSet<ServerId>,
+    /// How far each follower caught up and when it last replied, empty unless the node is leader
+    pub replication_progress: HashMap<ServerId, PeerProgress>,
+    /// Elections started and split, pre-votes turned down and votes granted since the node started
+    pub elections: ElectionStats,
+    /// How long ago the node started
+    pub uptime: Duration,
+}
+
+/// Latest diagnostics published by a Raft thread, clones read the same value.
+/// The value stays readable after the thread stops or gets stuck, showing what it last looked like.
+#[derive(Debug, Clone, Default)]
+pub struct RaftDiagnostics {
+    latest: Arc<Mutex<Option<NodeDiagnostics>>>,
+}
+impl RaftDiagnostics {
+    /// The most recent diagnostics, `None` until the Raft thread has run its first loop iteration
+    pub fn latest(&self) -> Option<NodeDiagnostics> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub(crate) fn publish(&self, diagnostics: NodeDiagnostics) {
+        *self
+            .latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(diagnostics);
+    }
+}
@@ -0,0 +1,233 @@
+use crossbeam_queue::ArrayQueue;
+use raft_core::{RaftEvent, RaftStateEvent};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+/// Receives every event a node publishes.
+///
+/// `push_event` is called on the Raft thread, in the middle of handling the event that led to it,
+/// and nothing else happens on the node until it returns: heartbeats, votes and replication all
+/// wait. Keep it to a few microseconds, hand the event off rather than acting on it, and never
+/// block on a consumer. [`QueueRaftEventCollector`] does that for you.
+pub trait RaftStateEventCollector: Send {
+    fn push_event(&mut self, event: RaftEvent);
+}
+
+pub struct NoOpRaftEventCollector;
+impl RaftStateEventCollector for NoOpRaftEventCollector {
+    fn push_event(&mut self, _event: RaftEvent) {}
+}
+
+/// What a [`ChannelRaftEventCollector`] does when the channel is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOverflowPolicy {
+    /// Wait for the receiver to make room, this stalls the Raft thread until it does. Only for
+    /// tests and tools that read every event, see [`QueueRaftEventCollector`] otherwise.
+    Block,
+    /// Throw away the event that didn't fit and count it in [`ChannelRaftEventCollector::dropped_events`]
+    DropNewest,
+}
+
+/// Forwards events into a bounded channel so they can be consumed from another thread.
+/// Clones share the same channel and dropped event count.
+/// Once the receiver is dropped events are discarded silently.
+#[derive(Debug, Clone)]
+pub struct ChannelRaftEventCollector {
+    event_tx: SyncSender<RaftEvent>,
+    overflow_policy: ChannelOverflowPolicy,
+    dropped_events: Arc<AtomicU64>,
+}
+impl ChannelRaftEventCollector {
+    /// Create a collector with room for `capacity` events and the receiving end of its channel
+    pub fn new(
+        capacity: usize,
+        overflow_policy: ChannelOverflowPolicy,
+    ) -> (Self, Receiver<RaftEvent>) {
+        let (event_tx, event_rx) = mpsc::sync_channel(capacity);
+        (
+            Self {
+                event_tx,
+                overflow_policy,
+                dropped_events: Arc::new(AtomicU64::new(0)),
+            },
+            event_rx,
+        )
+    }
+
+    /// Number of events thrown away because the channel was full
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+}
+impl RaftStateEventCollector for ChannelRaftEventCollector {
+    fn push_event(&mut self, event: RaftEvent) {
+        match self.overflow_policy {
+            ChannelOverflowPolicy::Block => {
+                // The receiver went away, nobody is interested in events anymore
+                let _ = self.event_tx.send(event);
+            }
+            ChannelOverflowPolicy::DropNewest => {
+                if let Err(TrySendError::Full(_)) = self.event_tx.try_send(event) {
+                    self.dropped_events.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// What a [`QueueRaftEventCollector`] does with an event that finds its queue full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOverflowPolicy {
+    /// Throw away the event that didn't fit
+    DropNewest,
+    /// Make room by throwing away the oldest queued event
+    DropOldest,
+    /// Hold back the latest [`RaftEvent::StateChanged`], which supersedes the ones before it, until
+    /// there's room again. Other events that don't fit are thrown away.
+    CoalesceStateChanges,
+}
+
+/// Forwards events into a bounded lock-free queue, the Raft thread never waits on whoever reads
+/// them. Read them from any thread through the [`EventQueue`] it was created with.
+#[derive(Debug)]
+pub struct QueueRaftEventCollector {
+    queue: EventQueue,
+    overflow_policy: QueueOverflowPolicy,
+    /// The latest state that didn't fit, goes in before the next event that finds room
+    held_back_state: Option<RaftStateEvent>,
+}
+impl QueueRaftEventCollector {
+    /// Create a collector with room for `capacity` events and the queue to read them from.
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize, overflow_policy: QueueOverflowPolicy) -> (Self, EventQueue) {
+        let queue = EventQueue {
+            events: Arc::new(ArrayQueue::new(capacity)),
+            dropped_events: Arc::new(AtomicU64::new(0)),
+            coalesced_events: Arc::new(AtomicU64::new(0)),
+        };
+        (
+            Self {
+                queue: queue.clone(),
+                overflow_policy,
+                held_back_state: None,
+            },
+            queue,
+        )
+    }
+
+    /// Queues a held back state ahead of the next event, if there's room for it
+    fn push_held_back_state(&mut self) {
+        if let Some(state) = self.held_back_state.take() {
+            if self
+                .queue
+                .events
+                .push(RaftEvent::StateChanged(state))
+                .is_err()
+            {
+                self.held_back_state = Some(state);
+            }
+        }
+    }
+}
+impl RaftStateEventCollector for QueueRaftEventCollector {
+    fn push_event(&mut self, event: RaftEvent) {
+        match self.overflow_policy {
+            QueueOverflowPolicy::DropNewest => {
+                if self.queue.events.push(event).is_err() {
+                    self.queue.dropped_events.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            QueueOverflowPolicy::DropOldest => {
+                if self.queue.events.force_push(event).is_some() {
+                    self.queue.dropped_events.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            QueueOverflowPolicy::CoalesceStateChanges => {
+                self.push_held_back_state();
+                match event {
+                    // A newer state would come after the held back one anyway
+                    RaftEvent::StateChanged(state) if self.held_back_state.is_some() => {
+                        self.held_back_state = Some(state);
+                        self.queue.coalesced_events.fetch_add(1, Ordering::Relaxed);
+                    }
+                    event => match self.queue.events.push(event) {
+                        Ok(()) => {}
+                        Err(RaftEvent::StateChanged(state)) => self.held_back_state = Some(state),
+                        Err(_) => {
+                            self.queue.dropped_events.fetch_add(1, Ordering::Relaxed);
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// The reading end of a [`QueueRaftEventCollector`], clones share the same queue and counts
+#[derive(Debug, Clone)]
+pub struct EventQueue {
+    events: Arc<ArrayQueue<RaftEvent>>,
+    dropped_events: Arc<AtomicU64>,
+    coalesced_events: Arc<AtomicU64>,
+}
+impl EventQueue {
+    /// The oldest queued event, `None` if there are none
+    pub fn pop(&self) -> Option<RaftEvent> {
+        self.events.pop()
+    }
+
+    /// Every queued event, oldest first
+    pub fn drain(&self) -> Vec<RaftEvent> {
+        std::iter::from_fn(|| self.events.pop()).collect()
+    }
+
+    /// Number of events thrown away because the queue was full
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// Number of held back states replaced by a newer one before there was room for them
+    pub fn coalesced_events(&self) -> u64 {
+        self.coalesced_events.load(Ordering::Relaxed)
+    }
+}
+
+/// The last events a node published, oldest first. Kept in memory by the Raft thread regardless of the
+/// event collector in use, so what led up to the current state can be looked at after the fact.
+/// Clones share the same buffer.
+#[derive(Debug, Clone)]
+pub(crate) struct RecentEvents {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<RaftEvent>>>,
+}
+impl RecentEvents {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub(crate) fn push(&self, event: RaftEvent) {
+        let mut events = self
+            .events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Copy of the buffered events, oldest first
+    pub(crate) fn snapshot(&self) -> Vec<RaftEvent> {
+        self.events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,317 @@
+use raft_core::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_core::{LogCommand, RaftTransportError, ServerId};
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::backoff::Backoff;
+use crate::common::RaftTransportConnector;
+use crate::metrics::RaftMetrics;
+use crate::wire_codec::{JsonWireCodec, WireCodec};
+use tiny_http::{Method, Response, Server};
+use tracing::{debug, warn};
+
+/// Where peers POST their messages
+const MESSAGE_PATH: &str = "/raft";
+/// Most bytes a message's body may take up unless set otherwise
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+/// How long connecting to a peer may take before the attempt counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a peer may take to answer a POST
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts at posting a message to a peer that was up, before the message is dropped. Once the
+/// peer has been failing, messages get one attempt each, so they don't pile up behind it.
+const MAX_SEND_ATTEMPTS: u32 = 5;
+
+/// The HTTP server taking peers' messages, stopped when the transport is dropped
+struct Inbound {
+    closed: AtomicBool,
+    server: Server,
+    /// Most bytes of a message's body, sent or taken
+    max_message_size: AtomicUsize,
+}
+
+/// Network transport that POSTs every message to its peer, as JSON unless started with another
+/// [`WireCodec`]. With JSON, it's handy to watch a cluster with a proxy or to poke a node with curl:
+///
+/// ```text
+/// curl -X POST http://127.0.0.1:4001/raft -d '{"Request":{"TimeoutNow":{"request_id":"...","from":1,"to":2,"term":4}}}'
+/// ```
+///
+/// Requests and replies alike go to the server they're addressed to, which answers the POST with
+/// `204 No Content` as soon as it has the message. Every server of the cluster has to use the same
+/// codec. Posting a message to a peer that can't be reached is retried a few times with jittered
+/// exponential backoff, then the message is dropped, Raft sends it again. Messages are no bigger
+/// than [`HttpTransport::max_message_size`], which the node splits what the leader sends to fit.
+pub struct HttpTransport<C: LogCommand> {
+    codec: Arc<dyn WireCodec<C>>,
+    /// Messages POSTed by peers
+    incoming: mpsc::Receiver<RpcMessage<C>>,
+    /// Bodies for the thread POSTing to each peer
+    outgoing: HashMap<ServerId, mpsc::Sender<Vec<u8>>>,
+    local_addr: SocketAddr,
+    inbound: Arc<Inbound>,
+    /// Where messages that can't be sent are counted, once the node attached its metrics
+    metrics: Option<RaftMetrics>,
+}
+impl<C: LogCommand + 'static> HttpTransport<C> {
+    /// Serves peers' POSTs on `listener`, and POSTs to every peer at its address in `peers`.
+    /// Messages are encoded with [`JsonWireCodec`].
+    pub fn new(listener: TcpListener, peers: HashMap<ServerId, SocketAddr>) -> io::Result<Self> {
+        Self::with_codec(listener, peers, Arc::new(JsonWireCodec))
+    }
+
+    /// Like [`HttpTransport::new`], but encodes messages with `codec`
+    pub fn with_codec(
+        listener: TcpListener,
+        peers: HashMap<ServerId, SocketAddr>,
+        codec: Arc<dyn WireCodec<C>>,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let server = Server::from_listener(listener, None).map_err(io::Error::other)?;
+        let inbound = Arc::new(Inbound {
+            closed: AtomicBool::new(false),
+            server,
+            max_message_size: AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE),
+        });
+        let (incoming_tx, incoming) = mpsc::channel();
+        {
+            let inbound = inbound.clone();
+            let codec = codec.clone();
+            let _ = thread::Builder::new()
+                .name(format!("http-serve-{}", local_addr))
+                .spawn(move || serve(&inbound, incoming_tx, &*codec))?;
+        }
+        let mut outgoing = HashMap::new();
+        for (server_id, addr) in peers {
+            let (bodies_tx, bodies) = mpsc::channel();
+            let content_type = codec.content_type();
+            let _ = thread::Builder::new()
+                .name(format!("http-post-{}", addr))
+                .spawn(move || post_to_peer(addr, content_type, bodies))?;
+            let _ = outgoing.insert(server_id, bodies_tx);
+        }
+        Ok(HttpTransport {
+            codec,
+            incoming,
+            outgoing,
+            local_addr,
+            inbound,
+            metrics: None,
+        })
+    }
+}
+impl<C: LogCommand> HttpTransport<C> {
+    /// The address peers POST to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Most bytes of one encoded message, 256 MiB by default. Messages past it aren't sent, and a
+    /// peer POSTing one is answered `413 Payload Too Large`, so every server of the cluster should
+    /// set the same.
+    pub fn max_message_size(self, bytes: usize) -> Self {
+        self.inbound.max_message_size.store(bytes, Ordering::SeqCst);
+        self
+    }
+
+    /// Hands `message` to the thread POSTing to `to`
+    fn send(&self, to: ServerId, message: RpcMessage<C>) -> Result<(), RaftTransportError> {
+        let Some(bodies) = self.outgoing.get(&to) else {
+            warn!("Dropping message for {:?}, it isn't a known peer", to);
+            return Ok(());
+        };
+        let body = match self.codec.encode(&message) {
+            Ok(body) if body.len() <= self.inbound.max_message_size.load(Ordering::SeqCst) => body,
+            Ok(body) => {
+                warn!(
+                    "Dropping message {:?} for {:?}, its {} bytes are too many",
+                    message.request_id(),
+                    to,
+                    body.len()
+                );
+                self.record_dropped();
+                return Ok(());
+            }
+            Err(error) => {
+                warn!(
+                    "Dropping message {:?} for {:?}, it couldn't be encoded: {}",
+                    message.request_id(),
+                    to,
+                    error
+                );
+                self.record_dropped();
+                return Ok(());
+            }
+        };
+        bodies
+            .send(body)
+            .map_err(|_| RaftTransportError::TransportShutdown)
+    }
+
+    fn record_dropped(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_rpc_dropped();
+        }
+    }
+}
+impl<C: LogCommand> std::fmt::Debug for HttpTransport<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpTransport")
+            .field("codec", &self.codec)
+            .field("local_addr", &self.local_addr)
+            .field("peers", &self.outgoing.keys())
+            .finish()
+    }
+}
+impl<C: LogCommand> RaftTransportConnector<C> for HttpTransport<C> {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        max_wait: Duration,
+    ) -> Result<Option<RpcMessage<C>>, RaftTransportError> {
+        match self.incoming.recv_timeout(max_wait) {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(RaftTransportError::TransportShutdown),
+        }
+    }
+
+    /// POSTs `reply` to the server whose request it answers
+    fn enqueue_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        self.send(reply.to(), RpcMessage::Reply(reply))
+    }
+
+    fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError> {
+        self.send(request.to(), RpcMessage::Request(request))
+    }
+
+    /// Counts the messages dropped for not fitting in [`HttpTransport::max_message_size`] into
+    /// `metrics`
+    fn attach_metrics(&mut self, metrics: RaftMetrics) {
+        self.metrics = Some(metrics);
+    }
+
+    fn message_size_limit(&self) -> Option<usize> {
+        Some(self.inbound.max_message_size.load(Ordering::SeqCst))
+    }
+}
+impl<C: LogCommand> Drop for HttpTransport<C> {
+    fn drop(&mut self) {
+        self.inbound.closed.store(true, Ordering::SeqCst);
+        self.inbound.server.unblock();
+    }
+}
+
+/// Hands every message POSTed to the server to the node, until the transport is dropped
+fn serve<C: LogCommand>(
+    inbound: &Inbound,
+    incoming: mpsc::Sender<RpcMessage<C>>,
+    codec: &dyn WireCodec<C>,
+) {
+    loop {
+        let mut request = match inbound.server.recv() {
+            Ok(request) => request,
+            Err(_) if inbound.closed.load(Ordering::SeqCst) => return,
+            Err(error) => {
+                warn!("Could not take a request: {}", error);
+                continue;
+            }
+        };
+        if request.url() != MESSAGE_PATH {
+            let _ = request.respond(Response::empty(404));
+            continue;
+        }
+        if *request.method() != Method::Post {
+            let _ = request.respond(Response::empty(405));
+            continue;
+        }
+        let max_message_size = inbound.max_message_size.load(Ordering::SeqCst);
+        if request.body_length().unwrap_or(0) > max_message_size {
+            let _ = request.respond(Response::empty(413));
+            continue;
+        }
+        let mut body = Vec::new();
+        if let Err(error) = request
+            .as_reader()
+            .take(max_message_size as u64)
+            .read_to_end(&mut body)
+        {
+            debug!("Could not read a message's body: {}", error);
+            continue;
+        }
+        match codec.decode(&body) {
+            Ok(message) => {
+                let _ = request.respond(Response::empty(204));
+                if incoming.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(error) => {
+                let _ =
+                    request.respond(Response::from_string(error.to_string()).with_status_code(400));
+            }
+        }
+    }
+}
+
+/// POSTs every body handed over to the peer at `addr`, one at a time so they arrive in order.
+/// A body the peer couldn't be reached with is retried as the peer's [`Backoff`] allows, then
+/// dropped, as is one the peer turns down. Stops once the transport is dropped.
+fn post_to_peer(addr: SocketAddr, content_type: &str, bodies: mpsc::Receiver<Vec<u8>>) {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build();
+    let url = format!("http://{}{}", addr, MESSAGE_PATH);
+    let mut backoff = Backoff::default();
+    for body in bodies {
+        if backoff.waiting() {
+            continue;
+        }
+        let attempts = if backoff.failing() {
+            1
+        } else {
+            MAX_SEND_ATTEMPTS
+        };
+        for attempt in 1..=attempts {
+            match agent
+                .post(&url)
+                .set("Content-Type", content_type)
+                .send_bytes(&body)
+            {
+                Ok(_) => {
+                    backoff.succeeded();
+                    break;
+                }
+                Err(ureq::Error::Status(status, _)) => {
+                    // The peer is up, it just won't take this message
+                    backoff.succeeded();
+                    warn!("{} turned a message down with status {}", addr, status);
+                    break;
+                }
+                Err(ureq::Error::Transport(error)) => {
+                    let wait = backoff.failed();
+                    let transient = matches!(
+                        error.kind(),
+                        ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+                    );
+                    if attempt == attempts || !transient {
+                        debug!("Dropped a message for {}: {}", addr, error);
+                        break;
+                    }
+                    debug!(
+                        "Could not reach {}, retrying in {:?}: {}",
+                        addr, wait, error
+                    );
+                    thread::sleep(wait);
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,18 @@
-/// This is an example of a Raft implementation in rust
+#[cfg(feature = "tokio")]
+mod async_runtime;
+#[cfg(feature = "tokio")]
+mod async_storage;
+mod audit_log;
+mod backoff;
+#[cfg(feature = "bench_internals")]
+#[doc(hidden)]
+pub mod bench_internals;
+mod boxed_storage;
+mod client_sessions;
+mod codec;
+/// The runtime around `raft_core`'s state machine: the Raft thread and its handle, file backed
+/// storage, event collectors, metrics and diagnostics. Re-exports the core so applications only
+/// need this crate.
 #[deny(
     bad_style,
     dead_code,
@@ -8,7 +22,6 @@
     overflowing_literals,
     path_statements,
     patterns_in_fns_without_body,
-    private_in_public,
     unconditional_recursion,
     unused,
     unused_allocation,
@@ -25,23 +38,109 @@
     unused_results
 )]
 mod common;
+mod crash_reporting;
 mod default_storage;
+mod diagnostics;
+mod events;
+#[cfg(feature = "http_transport")]
+mod http_transport;
+mod metrics;
+mod multi_raft;
+mod node_builder;
+#[cfg(feature = "prometheus")]
+mod prometheus_metrics;
 mod raft_thread;
-pub mod rpc_messages;
-mod state_machine;
-pub mod system_clock;
+mod rate_limit;
+mod rpc_router;
+#[cfg(feature = "sled")]
+mod sled_storage;
+mod slow_operations;
+mod storage_export;
+mod tcp_transport;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+#[cfg(feature = "tls")]
+mod tls;
+mod wire_codec;
 
-pub use common::LogCommand;
-pub use common::LogEntry;
-pub use common::LogIndex;
-pub use common::PersistentStorage;
-pub use common::RaftConfig;
-pub use common::ServerId;
-pub use common::TermIndex;
+#[cfg(feature = "tokio")]
+pub use async_runtime::AsyncRaftTransport;
+#[cfg(feature = "tokio")]
+pub use async_storage::AsyncPersistentStorage;
+#[cfg(feature = "tokio")]
+pub use async_storage::SpawnBlockingStorage;
+pub use codec::BincodeCodec;
+pub use codec::CodecError;
+pub use codec::CommandCodec;
+#[cfg(feature = "encryption")]
+pub use codec::EncryptingCodec;
+#[cfg(feature = "encryption")]
+pub use codec::EncryptionKey;
+#[cfg(feature = "json_codec")]
+pub use codec::JsonCodec;
 pub use common::*;
-pub use raft_thread::start_raft_in_new_thread;
-pub use raft_thread::NoOpRaftEventCollector;
-pub use raft_thread::RaftNodeState;
-pub use raft_thread::RaftStateEvent;
-pub use raft_thread::RaftStateEventCollector;
-pub use rpc_messages::*;
+pub use default_storage::DefaultPersistentStorage;
+pub use default_storage::IntegrityProblem;
+pub use default_storage::IntegrityReport;
+pub use default_storage::SnapshotMetadata;
+pub use diagnostics::NodeDiagnostics;
+pub use diagnostics::RaftDiagnostics;
+pub use diagnostics::RaftStatus;
+pub use events::ChannelOverflowPolicy;
+pub use events::ChannelRaftEventCollector;
+pub use events::EventQueue;
+pub use events::NoOpRaftEventCollector;
+pub use events::QueueOverflowPolicy;
+pub use events::QueueRaftEventCollector;
+pub use events::RaftStateEventCollector;
+#[cfg(feature = "http_transport")]
+pub use http_transport::HttpTransport;
+pub use metrics::metric_names;
+pub use metrics::ElectionStats;
+pub use metrics::HistogramSnapshot;
+pub use metrics::LatencyHistogram;
+pub use metrics::MetricsSink;
+pub use metrics::NoOpMetricsSink;
+pub use metrics::NodeGauges;
+pub use metrics::PeerConnection;
+pub use metrics::RaftMetrics;
+pub use metrics::RpcStats;
+pub use metrics::StorageStats;
+pub use metrics::TracingMetricsSink;
+pub use multi_raft::GroupId;
+pub use multi_raft::GroupRouter;
+pub use multi_raft::MultiRaftTransport;
+pub use multi_raft::RaftGroupManager;
+pub use node_builder::RaftNodeBuilder;
+#[cfg(feature = "prometheus")]
+pub use prometheus_metrics::PrometheusCollector;
+#[cfg(feature = "prometheus")]
+pub use prometheus_metrics::PrometheusMetricsSink;
+pub use raft_core::*;
+pub use raft_thread::Pending;
+pub use raft_thread::PendingBackup;
+pub use raft_thread::PendingIndex;
+pub use raft_thread::PendingMembershipChange;
+pub use raft_thread::PendingProposal;
+pub use raft_thread::PendingRead;
+pub use raft_thread::PendingStepDown;
+pub use raft_thread::PendingTimingUpdate;
+pub use raft_thread::RaftNodeHandle;
+pub use rate_limit::RateLimit;
+pub use rpc_router::RpcRouter;
+pub use rpc_router::TimedOutRequest;
+/// The rustls the transport's [`TlsConfig`] is built with
+#[cfg(feature = "tls")]
+pub use rustls;
+#[cfg(feature = "sled")]
+pub use sled_storage::SledStorage;
+pub use storage_export::StorageExport;
+pub use tcp_transport::PeerHealth;
+pub use tcp_transport::PeerLiveness;
+pub use tcp_transport::TcpTransport;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+pub use wire_codec::BincodeWireCodec;
+#[cfg(feature = "json_codec")]
+pub use wire_codec::JsonWireCodec;
+pub use wire_codec::WireCodec;
@@ -0,0 +1,583 @@
+use raft_core::system_clock;
+use raft_core::system_clock::Instant;
+use raft_core::{LogIndex, RaftEvent, RaftNodeState, ServerId, TermIndex};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::trace;
+
+/// Upper bounds of the latency histogram buckets, anything slower lands in the overflow bucket
+pub(crate) const LATENCY_BUCKET_BOUNDS_MS: [u64; 12] =
+    [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Fixed bucket histogram of durations that can be observed from one thread and read from any other
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    bucket_bounds: Vec<Duration>,
+    /// One counter per bound plus a final overflow bucket
+    bucket_counts: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+impl LatencyHistogram {
+    fn new() -> Self {
+        let bucket_bounds: Vec<Duration> = LATENCY_BUCKET_BOUNDS_MS
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect();
+        let bucket_counts = (0..=bucket_bounds.len())
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        Self {
+            bucket_bounds,
+            bucket_counts,
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Record one measurement
+    pub fn observe(&self, latency: Duration) {
+        let bucket = self
+            .bucket_bounds
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(self.bucket_bounds.len());
+        self.bucket_counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(
+            latency.as_micros().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Copy of the current counts, the buckets are not cumulative
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .bucket_bounds
+                .iter()
+                .map(|bound| Some(*bound))
+                .chain(std::iter::once(None))
+                .zip(
+                    self.bucket_counts
+                        .iter()
+                        .map(|count| count.load(Ordering::Relaxed)),
+                )
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Point in time copy of a [`LatencyHistogram`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// `(upper bound, count)` for every bucket, the last bucket has no upper bound
+    pub buckets: Vec<(Option<Duration>, u64)>,
+    /// Total number of measurements
+    pub count: u64,
+    /// Sum of all measurements
+    pub sum: Duration,
+}
+
+/// Point in time copy of a node's election counters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElectionStats {
+    /// Elections this node started as a candidate, including retries after a split vote
+    pub elections_started: u64,
+    /// Elections that timed out without a winner so the candidate had to start another one
+    pub split_votes: u64,
+    /// Pre-vote requests this node turned down
+    pub pre_vote_rejections: u64,
+    /// Votes this node granted to candidates
+    pub votes_granted: u64,
+    /// Time from starting to campaign until becoming leader or stepping down to follower
+    pub election_duration: HistogramSnapshot,
+}
+
+/// Point in time copy of the RPCs a node's transport carried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpcStats {
+    /// Requests and replies the node handed to its transport
+    pub sent: u64,
+    /// Requests and replies the transport handed to the node
+    pub received: u64,
+    /// Requests and replies the transport couldn't take, or reported losing
+    pub dropped: u64,
+}
+
+/// Point in time copy of what a node's storage wrote and read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Bytes of appended entries storage made durable, as bincode encodes them
+    pub bytes_written: u64,
+    /// Bytes of entries read back from storage, mostly to replicate them, as bincode encodes them
+    pub bytes_read: u64,
+    /// Bytes the log took up on disk after the last sync, 0 if the storage doesn't say
+    pub log_size_on_disk: u64,
+}
+
+/// Where a node stood when it last published its state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeGauges {
+    pub current_term: TermIndex,
+    pub commit_index: LogIndex,
+    /// `None` until the node published its state for the first time
+    pub role: Option<RaftNodeState>,
+}
+
+/// Where a node's transport stands with one peer, for transports that keep connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerConnection {
+    /// Whether the transport is connected to the peer, while it isn't it reconnects in the background
+    pub connected: bool,
+    /// Messages for the peer held back until the transport is connected to it again
+    pub buffered_messages: u64,
+}
+
+/// Names of the metrics a Raft node records, as passed to a [`MetricsSink`]
+pub mod metric_names {
+    pub const COMMIT_LATENCY: &str = "raft_commit_latency";
+    pub const APPLY_LATENCY: &str = "raft_apply_latency";
+    pub const ELECTIONS_STARTED: &str = "raft_elections_started";
+    pub const SPLIT_VOTES: &str = "raft_split_votes";
+    pub const PRE_VOTE_REJECTIONS: &str = "raft_pre_vote_rejections";
+    pub const ELECTION_DURATION: &str = "raft_election_duration";
+    pub const VOTES_GRANTED: &str = "raft_votes_granted";
+    pub const RPCS_SENT: &str = "raft_rpcs_sent";
+    pub const RPCS_RECEIVED: &str = "raft_rpcs_received";
+    pub const RPCS_DROPPED: &str = "raft_rpcs_dropped";
+    pub const APPEND_LATENCY: &str = "raft_append_latency";
+    pub const SYNC_LATENCY: &str = "raft_storage_sync_latency";
+    pub const BYTES_WRITTEN: &str = "raft_storage_bytes_written";
+    pub const BYTES_READ: &str = "raft_storage_bytes_read";
+    pub const LOG_SIZE_ON_DISK: &str = "raft_log_size_on_disk_bytes";
+    pub const CURRENT_TERM: &str = "raft_current_term";
+    pub const COMMIT_INDEX: &str = "raft_commit_index";
+    /// 0 for a follower, 1 for a pre-candidate, 2 for a candidate and 3 for a leader
+    pub const ROLE: &str = "raft_role";
+    /// Per peer, 1 while the transport is connected to the peer and 0 otherwise
+    pub const PEER_CONNECTED: &str = "raft_peer_connected";
+    /// Per peer
+    pub const PEER_BUFFERED_MESSAGES: &str = "raft_peer_buffered_messages";
+}
+
+/// Receives every metric a Raft node records, in addition to the in-memory values kept by [`RaftMetrics`].
+/// Implement this to forward metrics to a monitoring system. Called from the Raft thread so it should not block.
+pub trait MetricsSink: Send + Sync + Debug {
+    /// A counter went up by `value`
+    fn increment_counter(&self, name: &'static str, value: u64);
+    /// A duration was measured
+    fn observe_duration(&self, name: &'static str, duration: Duration);
+    /// A gauge was set to `value`, ignored unless implemented
+    fn set_gauge(&self, _name: &'static str, _value: u64) {}
+    /// A gauge kept per peer was set to `value` for `peer`, ignored unless implemented
+    fn set_peer_gauge(&self, _name: &'static str, _peer: ServerId, _value: u64) {}
+}
+
+/// Sink that logs every metric at trace level, handy while debugging without a monitoring system
+#[derive(Debug, Default)]
+pub struct TracingMetricsSink;
+impl MetricsSink for TracingMetricsSink {
+    fn increment_counter(&self, name: &'static str, value: u64) {
+        trace!(metric = name, value, "counter incremented");
+    }
+
+    fn observe_duration(&self, name: &'static str, duration: Duration) {
+        trace!(
+            metric = name,
+            duration_us = duration.as_micros() as u64,
+            "duration observed"
+        );
+    }
+
+    fn set_gauge(&self, name: &'static str, value: u64) {
+        trace!(metric = name, value, "gauge set");
+    }
+
+    fn set_peer_gauge(&self, name: &'static str, peer: ServerId, value: u64) {
+        trace!(metric = name, peer = peer.0, value, "gauge set");
+    }
+}
+
+/// Sink that drops every metric, for a node read only through [`RaftMetrics`]
+#[derive(Debug, Default)]
+pub struct NoOpMetricsSink;
+impl MetricsSink for NoOpMetricsSink {
+    fn increment_counter(&self, _name: &'static str, _value: u64) {}
+
+    fn observe_duration(&self, _name: &'static str, _duration: Duration) {}
+}
+
+/// What [`metric_names::ROLE`] is set to for `role`
+pub(crate) fn role_code(role: RaftNodeState) -> u64 {
+    match role {
+        RaftNodeState::Follower => 0,
+        RaftNodeState::PreCandidate => 1,
+        RaftNodeState::Candidate => 2,
+        RaftNodeState::Leader => 3,
+    }
+}
+
+/// `None` for the code stored before the node published its state
+fn role_from_code(code: u64) -> Option<RaftNodeState> {
+    match code {
+        0 => Some(RaftNodeState::Follower),
+        1 => Some(RaftNodeState::PreCandidate),
+        2 => Some(RaftNodeState::Candidate),
+        3 => Some(RaftNodeState::Leader),
+        _ => None,
+    }
+}
+
+/// The role gauge holds this until the node first publishes its state
+const UNKNOWN_ROLE: u64 = u64::MAX;
+
+#[derive(Debug)]
+struct RaftMetricsInner {
+    sinks: Vec<Arc<dyn MetricsSink>>,
+    commit_latency: LatencyHistogram,
+    apply_latency: LatencyHistogram,
+    elections_started: AtomicU64,
+    split_votes: AtomicU64,
+    pre_vote_rejections: AtomicU64,
+    election_duration: LatencyHistogram,
+    votes_granted: AtomicU64,
+    rpcs_sent: AtomicU64,
+    rpcs_received: AtomicU64,
+    rpcs_dropped: AtomicU64,
+    append_latency: LatencyHistogram,
+    sync_latency: LatencyHistogram,
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+    log_size_on_disk: AtomicU64,
+    current_term: AtomicU64,
+    commit_index: AtomicU64,
+    role: AtomicU64,
+    peer_connections: Mutex<HashMap<ServerId, PeerConnection>>,
+}
+
+/// Metrics recorded by a Raft node, clones share the same underlying counters
+/// so the application can keep one and read it while the Raft thread records into another.
+#[derive(Debug, Clone)]
+pub struct RaftMetrics {
+    inner: Arc<RaftMetricsInner>,
+}
+impl RaftMetrics {
+    pub fn new() -> Self {
+        Self::with_sinks(vec![])
+    }
+
+    /// Metrics that are also forwarded to `sinks` as they are recorded
+    pub fn with_sinks(sinks: Vec<Arc<dyn MetricsSink>>) -> Self {
+        Self {
+            inner: Arc::new(RaftMetricsInner {
+                sinks,
+                commit_latency: LatencyHistogram::new(),
+                apply_latency: LatencyHistogram::new(),
+                elections_started: AtomicU64::new(0),
+                split_votes: AtomicU64::new(0),
+                pre_vote_rejections: AtomicU64::new(0),
+                election_duration: LatencyHistogram::new(),
+                votes_granted: AtomicU64::new(0),
+                rpcs_sent: AtomicU64::new(0),
+                rpcs_received: AtomicU64::new(0),
+                rpcs_dropped: AtomicU64::new(0),
+                append_latency: LatencyHistogram::new(),
+                sync_latency: LatencyHistogram::new(),
+                bytes_written: AtomicU64::new(0),
+                bytes_read: AtomicU64::new(0),
+                log_size_on_disk: AtomicU64::new(0),
+                current_term: AtomicU64::new(0),
+                commit_index: AtomicU64::new(0),
+                role: AtomicU64::new(UNKNOWN_ROLE),
+                peer_connections: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Time from the leader appending a proposed entry to its log until it's committed
+    pub fn commit_latency(&self) -> &LatencyHistogram {
+        &self.inner.commit_latency
+    }
+
+    /// Time from the leader appending a proposed entry to its log until it's applied to the state machine
+    pub fn apply_latency(&self) -> &LatencyHistogram {
+        &self.inner.apply_latency
+    }
+
+    /// Time from starting to campaign until becoming leader or stepping down to follower
+    pub fn election_duration(&self) -> &LatencyHistogram {
+        &self.inner.election_duration
+    }
+
+    /// Time storage took to make entries appended to the log durable, once per sync that wrote any
+    pub fn append_latency(&self) -> &LatencyHistogram {
+        &self.inner.append_latency
+    }
+
+    /// Time every storage sync took, whether it wrote entries, the term and vote or a snapshot
+    pub fn sync_latency(&self) -> &LatencyHistogram {
+        &self.inner.sync_latency
+    }
+
+    pub fn storage_stats(&self) -> StorageStats {
+        StorageStats {
+            bytes_written: self.inner.bytes_written.load(Ordering::Relaxed),
+            bytes_read: self.inner.bytes_read.load(Ordering::Relaxed),
+            log_size_on_disk: self.inner.log_size_on_disk.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn election_stats(&self) -> ElectionStats {
+        ElectionStats {
+            elections_started: self.inner.elections_started.load(Ordering::Relaxed),
+            split_votes: self.inner.split_votes.load(Ordering::Relaxed),
+            pre_vote_rejections: self.inner.pre_vote_rejections.load(Ordering::Relaxed),
+            votes_granted: self.inner.votes_granted.load(Ordering::Relaxed),
+            election_duration: self.inner.election_duration.snapshot(),
+        }
+    }
+
+    pub fn rpc_stats(&self) -> RpcStats {
+        RpcStats {
+            sent: self.inner.rpcs_sent.load(Ordering::Relaxed),
+            received: self.inner.rpcs_received.load(Ordering::Relaxed),
+            dropped: self.inner.rpcs_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn gauges(&self) -> NodeGauges {
+        NodeGauges {
+            current_term: TermIndex(self.inner.current_term.load(Ordering::Relaxed)),
+            commit_index: LogIndex(self.inner.commit_index.load(Ordering::Relaxed)),
+            role: role_from_code(self.inner.role.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Where the transport stands with every peer, empty unless the transport keeps connections
+    pub fn peer_connections(&self) -> HashMap<ServerId, PeerConnection> {
+        self.inner
+            .peer_connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Records where the transport stands with `peer`, for transports that keep connections
+    pub fn record_peer_connection(&self, peer: ServerId, connection: PeerConnection) {
+        let _ = self
+            .inner
+            .peer_connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(peer, connection);
+        for sink in &self.inner.sinks {
+            sink.set_peer_gauge(
+                metric_names::PEER_CONNECTED,
+                peer,
+                connection.connected as u64,
+            );
+            sink.set_peer_gauge(
+                metric_names::PEER_BUFFERED_MESSAGES,
+                peer,
+                connection.buffered_messages,
+            );
+        }
+    }
+
+    /// Counts an RPC the transport lost after taking it from the node, for transports that know.
+    /// The node already counts the ones its transport turned away.
+    pub fn record_rpc_dropped(&self) {
+        self.increment_counter(metric_names::RPCS_DROPPED, &self.inner.rpcs_dropped);
+    }
+
+    pub(crate) fn record_rpc_sent(&self) {
+        self.increment_counter(metric_names::RPCS_SENT, &self.inner.rpcs_sent);
+    }
+
+    pub(crate) fn record_rpc_received(&self) {
+        self.increment_counter(metric_names::RPCS_RECEIVED, &self.inner.rpcs_received);
+    }
+
+    pub(crate) fn record_append_latency(&self, duration: Duration) {
+        self.observe_duration(
+            metric_names::APPEND_LATENCY,
+            &self.inner.append_latency,
+            duration,
+        );
+    }
+
+    pub(crate) fn record_sync_latency(&self, duration: Duration) {
+        self.observe_duration(
+            metric_names::SYNC_LATENCY,
+            &self.inner.sync_latency,
+            duration,
+        );
+    }
+
+    pub(crate) fn record_bytes_written(&self, bytes: u64) {
+        self.add_to_counter(
+            metric_names::BYTES_WRITTEN,
+            &self.inner.bytes_written,
+            bytes,
+        );
+    }
+
+    pub(crate) fn record_bytes_read(&self, bytes: u64) {
+        self.add_to_counter(metric_names::BYTES_READ, &self.inner.bytes_read, bytes);
+    }
+
+    pub(crate) fn set_log_size_on_disk(&self, bytes: u64) {
+        self.set_gauge(
+            metric_names::LOG_SIZE_ON_DISK,
+            &self.inner.log_size_on_disk,
+            bytes,
+        );
+    }
+
+    fn increment_counter(&self, name: &'static str, counter: &AtomicU64) {
+        self.add_to_counter(name, counter, 1);
+    }
+
+    fn add_to_counter(&self, name: &'static str, counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+        for sink in &self.inner.sinks {
+            sink.increment_counter(name, value);
+        }
+    }
+
+    fn set_gauge(&self, name: &'static str, gauge: &AtomicU64, value: u64) {
+        gauge.store(value, Ordering::Relaxed);
+        for sink in &self.inner.sinks {
+            sink.set_gauge(name, value);
+        }
+    }
+
+    fn observe_duration(
+        &self,
+        name: &'static str,
+        histogram: &LatencyHistogram,
+        duration: Duration,
+    ) {
+        histogram.observe(duration);
+        for sink in &self.inner.sinks {
+            sink.observe_duration(name, duration);
+        }
+    }
+}
+impl Default for RaftMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Follows the events published by the Raft thread and records them into [`RaftMetrics`].
+/// Only entries this node proposed as leader are timed, for replicated entries the proposal was received elsewhere.
+#[derive(Debug, Default)]
+pub(crate) struct EventMetricsRecorder {
+    proposed_at: BTreeMap<u64, Instant>,
+    committed: BTreeMap<u64, Instant>,
+    campaigning_since: Option<Instant>,
+}
+impl EventMetricsRecorder {
+    pub(crate) fn observe_event(
+        &mut self,
+        event: &RaftEvent,
+        is_leader: bool,
+        metrics: &RaftMetrics,
+    ) {
+        match event {
+            RaftEvent::EntryAppended { index, .. } if is_leader => {
+                self.proposed_at.insert(index.0, system_clock::now());
+            }
+            RaftEvent::EntryCommitted { index, .. } => {
+                let still_pending = self.proposed_at.split_off(&(index.0 + 1));
+                for (committed_index, proposed_at) in
+                    std::mem::replace(&mut self.proposed_at, still_pending)
+                {
+                    metrics.observe_duration(
+                        metric_names::COMMIT_LATENCY,
+                        metrics.commit_latency(),
+                        proposed_at.elapsed(),
+                    );
+                    self.committed.insert(committed_index, proposed_at);
+                }
+            }
+            RaftEvent::EntryApplied { index, .. } => {
+                let still_pending = self.committed.split_off(&(index.0 + 1));
+                for (_, proposed_at) in std::mem::replace(&mut self.committed, still_pending) {
+                    metrics.observe_duration(
+                        metric_names::APPLY_LATENCY,
+                        metrics.apply_latency(),
+                        proposed_at.elapsed(),
+                    );
+                }
+            }
+            RaftEvent::ElectionStarted { .. } => {
+                metrics.increment_counter(
+                    metric_names::ELECTIONS_STARTED,
+                    &metrics.inner.elections_started,
+                );
+                if self.campaigning_since.is_some() {
+                    // The previous election ran out of time without anybody winning it
+                    metrics
+                        .increment_counter(metric_names::SPLIT_VOTES, &metrics.inner.split_votes);
+                } else {
+                    self.campaigning_since = Some(system_clock::now());
+                }
+            }
+            RaftEvent::VoteGranted { .. } => {
+                metrics
+                    .increment_counter(metric_names::VOTES_GRANTED, &metrics.inner.votes_granted);
+            }
+            RaftEvent::PreVoteRejected { .. } => {
+                metrics.increment_counter(
+                    metric_names::PRE_VOTE_REJECTIONS,
+                    &metrics.inner.pre_vote_rejections,
+                );
+            }
+            RaftEvent::BecameLeader { .. } => self.finish_election(metrics),
+            RaftEvent::StateChanged(state) => {
+                metrics.set_gauge(
+                    metric_names::CURRENT_TERM,
+                    &metrics.inner.current_term,
+                    state.current_term.0,
+                );
+                metrics.set_gauge(
+                    metric_names::COMMIT_INDEX,
+                    &metrics.inner.commit_index,
+                    state.commit_index.0,
+                );
+                metrics.set_gauge(
+                    metric_names::ROLE,
+                    &metrics.inner.role,
+                    role_code(state.current_state),
+                );
+                if state.current_state == RaftNodeState::Follower {
+                    // Somebody else won, or a higher term showed up mid election
+                    self.finish_election(metrics);
+                }
+                if state.current_state != RaftNodeState::Leader {
+                    // Losing leadership means uncommitted entries may be overwritten, they'd never be timed
+                    self.proposed_at.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish_election(&mut self, metrics: &RaftMetrics) {
+        if let Some(campaigning_since) = self.campaigning_since.take() {
+            metrics.observe_duration(
+                metric_names::ELECTION_DURATION,
+                metrics.election_duration(),
+                campaigning_since.elapsed(),
+            );
+        }
+    }
+}
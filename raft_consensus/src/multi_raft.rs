@@ -0,0 +1,581 @@
+use crate::codec::BincodeCodec;
+use crate::common::{ApplicationThatNeedsConsensus, NodeBuildError, RaftTransportConnector};
+use crate::crash_reporting;
+use crate::diagnostics::RaftDiagnostics;
+use crate::events::{RaftStateEventCollector, RecentEvents};
+use crate::metrics::RaftMetrics;
+use crate::raft_thread::{
+    hosted_runtime, node_channels, EventPublisher, HostedExit, NodeLoop, NodeRuntime, NodeStatus,
+    NodeStorage, RaftNodeHandle, RECENT_EVENTS_CAPACITY,
+};
+use raft_core::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_core::system_clock::{self, Instant, SystemClock};
+use raft_core::{LogCommand, RaftConfig, RaftTransportError, ServerId};
+use rand_chacha::ChaCha8Rng;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{error, info_span, trace};
+
+/// How long a worker without groups waits for one to be added before checking again
+const IDLE_WORKER_WAIT: Duration = Duration::from_millis(100);
+
+/// Tells apart the Raft groups a [`RaftGroupManager`] hosts. Every server uses the same id for
+/// its node of the same group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupId(pub u64);
+
+/// Carries the messages of every group a [`RaftGroupManager`] hosts over one set of connections,
+/// tagged with the group they belong to. Messages coming in for a group are handed back to the
+/// manager through its [`GroupRouter`].
+pub trait MultiRaftTransport<C: LogCommand>: Send + Sync {
+    /// Sends `request` of group `group_id` to the server it's addressed to.
+    fn send_request(
+        &self,
+        group_id: GroupId,
+        request: Request<C>,
+    ) -> Result<(), RaftTransportError>;
+
+    /// Sends `reply` of group `group_id` back to the server whose request it answers.
+    fn send_reply(&self, group_id: GroupId, reply: ReplyTo) -> Result<(), RaftTransportError>;
+}
+
+/// What a worker gets handed, messages for its groups and groups to run
+enum WorkerInput<C: LogCommand> {
+    Incoming(GroupId, Box<RpcMessage<C>>),
+    AddGroup(GroupId, Box<dyn HostedGroup<C>>),
+}
+
+/// Hands the messages a [`MultiRaftTransport`] receives to the worker running their group
+#[derive(Debug, Clone)]
+pub struct GroupRouter<C: LogCommand> {
+    workers: Vec<mpsc::Sender<WorkerInput<C>>>,
+}
+impl<C: LogCommand> GroupRouter<C> {
+    /// Hands `message`, which came in for `group_id`, to the worker running that group. Messages
+    /// for a group this process doesn't host are dropped by the worker.
+    pub fn deliver(
+        &self,
+        group_id: GroupId,
+        message: RpcMessage<C>,
+    ) -> Result<(), RaftTransportError> {
+        self.send(group_id, WorkerInput::Incoming(group_id, Box::new(message)))
+    }
+
+    fn send(&self, group_id: GroupId, input: WorkerInput<C>) -> Result<(), RaftTransportError> {
+        let worker = (group_id.0 % self.workers.len() as u64) as usize;
+        self.workers[worker]
+            .send(input)
+            .map_err(|_| RaftTransportError::TransportShutdown)
+    }
+}
+
+/// Runs many independent Raft groups in one process. Instead of a thread and a set of connections
+/// per group, a fixed pool of workers runs the groups' nodes and one [`MultiRaftTransport`] carries
+/// all their messages. Each group stays on the worker its id picks, which hands it its messages
+/// and wakes it when its timer runs out, the same way a node on its own thread runs.
+#[derive(Debug)]
+pub struct RaftGroupManager<C: LogCommand, T: MultiRaftTransport<C>> {
+    server_id: ServerId,
+    transport: Arc<T>,
+    router: GroupRouter<C>,
+    workers: Vec<thread::Thread>,
+}
+impl<C: LogCommand + 'static, T: MultiRaftTransport<C> + 'static> RaftGroupManager<C, T> {
+    /// Starts `workers` worker threads, at least one, for the groups `server_id` is a member of.
+    /// Fails with [`NodeBuildError::ThreadSpawnFailed`] if the OS won't start one of them, those
+    /// already started exit again.
+    pub fn new(server_id: ServerId, workers: usize, transport: T) -> Result<Self, NodeBuildError> {
+        crash_reporting::install_panic_hook();
+        let (senders, threads) = (0..workers.max(1))
+            .map(|worker| {
+                let (inputs_tx, inputs_rx) = mpsc::channel();
+                let thread_handle = thread::Builder::new()
+                    .name(format!(
+                        "raft-server-{server_id}-worker-{worker}",
+                        server_id = server_id.0
+                    ))
+                    .spawn(move || run_worker(inputs_rx))
+                    .map_err(|error| {
+                        error!(
+                            "{:?}: Could not spawn raft worker thread: {}",
+                            server_id, error
+                        );
+                        NodeBuildError::ThreadSpawnFailed
+                    })?;
+                Ok((inputs_tx, thread_handle.thread().clone()))
+            })
+            .collect::<Result<(Vec<_>, Vec<_>), NodeBuildError>>()?;
+        Ok(RaftGroupManager {
+            server_id,
+            transport: Arc::new(transport),
+            router: GroupRouter { workers: senders },
+            workers: threads,
+        })
+    }
+
+    /// Where the transport hands the messages it receives
+    pub fn router(&self) -> GroupRouter<C> {
+        self.router.clone()
+    }
+
+    /// Starts this server's node of group `group_id`, which runs on one of the manager's workers
+    /// from now on. Takes what a [`crate::RaftNodeBuilder`] does, less the transport, each
+    /// group still keeps its own storage directory. Commands are encoded with [`BincodeCodec`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_group<A>(
+        &self,
+        group_id: GroupId,
+        other_servers: HashSet<ServerId>,
+        storage_path: String,
+        application: A,
+        config: RaftConfig,
+        rng: ChaCha8Rng,
+        event_collector: impl RaftStateEventCollector + 'static,
+        metrics: RaftMetrics,
+    ) -> RaftNodeHandle<A>
+    where
+        A: ApplicationThatNeedsConsensus<Command = C> + 'static,
+    {
+        let max_entry_bytes = config.max_entry_bytes();
+        let (inbox, senders) = node_channels();
+        let diagnostics = RaftDiagnostics::default();
+        let recent_events = RecentEvents::new(RECENT_EVENTS_CAPACITY);
+        let worker = (group_id.0 % self.workers.len() as u64) as usize;
+        let (runtime, exit) = hosted_runtime(self.workers[worker].clone());
+
+        let event_publisher = EventPublisher::new(
+            event_collector,
+            metrics.clone(),
+            recent_events.clone(),
+            Some(&storage_path),
+        );
+        let connector = GroupConnector {
+            group_id,
+            inbox: VecDeque::new(),
+            transport: self.transport.clone(),
+        };
+        let opened = {
+            let _group_span = info_span!(
+                "raft_group",
+                group_id = group_id.0,
+                server_id = self.server_id.0
+            )
+            .entered();
+            NodeLoop::open(
+                self.server_id,
+                other_servers,
+                NodeStorage::Directory {
+                    path: storage_path,
+                    codec: Arc::new(BincodeCodec),
+                },
+                application,
+                config,
+                rng,
+                Box::new(SystemClock),
+                connector,
+                event_publisher,
+                inbox,
+                diagnostics.clone(),
+            )
+        };
+        match opened {
+            Ok(node_loop) => {
+                let group = GroupRunner {
+                    group_id,
+                    server_id: self.server_id,
+                    node_loop,
+                    exit,
+                };
+                if self
+                    .router
+                    .send(group_id, WorkerInput::AddGroup(group_id, Box::new(group)))
+                    .is_err()
+                {
+                    // Dropping the group marks it finished, its handle sees it never ran
+                    trace!("Worker for group {:?} is gone", group_id);
+                }
+            }
+            Err(error) => exit.send(Ok(Err(error))),
+        }
+
+        RaftNodeHandle::new(
+            NodeRuntime::Hosted(runtime),
+            senders,
+            diagnostics,
+            recent_events,
+            metrics,
+            max_entry_bytes,
+        )
+    }
+}
+
+/// The transport of one hosted group: the worker pushes the group's messages into `inbox` before
+/// running it, so waiting for the next one never blocks the other groups on the worker
+struct GroupConnector<C: LogCommand, T: MultiRaftTransport<C>> {
+    group_id: GroupId,
+    inbox: VecDeque<RpcMessage<C>>,
+    transport: Arc<T>,
+}
+impl<C: LogCommand, T: MultiRaftTransport<C>> RaftTransportConnector<C> for GroupConnector<C, T> {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        _max_wait: Duration,
+    ) -> Result<Option<RpcMessage<C>>, RaftTransportError> {
+        Ok(self.inbox.pop_front())
+    }
+
+    fn enqueue_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        self.transport.send_reply(self.group_id, reply)
+    }
+
+    fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError> {
+        self.transport.send_request(self.group_id, request)
+    }
+}
+
+/// A group as its worker sees it, whatever application it runs
+trait HostedGroup<C: LogCommand>: Send {
+    /// Queues `message` for the group's next pass
+    fn deliver(&mut self, message: RpcMessage<C>);
+
+    /// When the group has to run next, now if messages are waiting for it
+    fn wake_at(&self) -> Instant;
+
+    /// Runs the group for as long as messages are waiting or its timer ran out. False once the
+    /// group was shut down, halted or crashed, it doesn't run again.
+    fn run_while_due(&mut self) -> bool;
+}
+
+struct GroupRunner<C, A, T, E>
+where
+    C: LogCommand,
+    A: ApplicationThatNeedsConsensus<Command = C>,
+    T: MultiRaftTransport<C>,
+    E: RaftStateEventCollector,
+{
+    group_id: GroupId,
+    server_id: ServerId,
+    node_loop: NodeLoop<C, A, GroupConnector<C, T>, E>,
+    exit: HostedExit,
+}
+impl<C, A, T, E> HostedGroup<C> for GroupRunner<C, A, T, E>
+where
+    C: LogCommand,
+    A: ApplicationThatNeedsConsensus<Command = C>,
+    T: MultiRaftTransport<C>,
+    E: RaftStateEventCollector,
+{
+    fn deliver(&mut self, message: RpcMessage<C>) {
+        self.node_loop.transport_connector.inbox.push_back(message);
+    }
+
+    fn wake_at(&self) -> Instant {
+        // A paused group leaves its messages queued until it's resumed
+        if self.node_loop.transport_connector.inbox.is_empty() || self.node_loop.is_paused() {
+            self.node_loop.wake_at()
+        } else {
+            system_clock::now()
+        }
+    }
+
+    fn run_while_due(&mut self) -> bool {
+        crash_reporting::mark_current_thread(self.server_id);
+        let _group_span = info_span!(
+            "raft_group",
+            group_id = self.group_id.0,
+            server_id = self.server_id.0
+        )
+        .entered();
+        while self.wake_at() <= system_clock::now() {
+            match panic::catch_unwind(AssertUnwindSafe(|| self.node_loop.run_once())) {
+                Ok(Ok(NodeStatus::Running | NodeStatus::Paused)) => {}
+                Ok(Ok(NodeStatus::ShutDown)) => {
+                    self.exit.send(Ok(Ok(())));
+                    return false;
+                }
+                Ok(Err(error)) => {
+                    self.node_loop.publish_halted(error);
+                    self.exit.send(Ok(Err(error)));
+                    return false;
+                }
+                Err(panic) => {
+                    self.node_loop.publish_crashed(&*panic);
+                    self.exit.send(Err(panic));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// A group on its worker, with the deadline its entry in the worker's timer heap is for
+struct ScheduledGroup<C: LogCommand> {
+    group: Box<dyn HostedGroup<C>>,
+    timer: Instant,
+    /// Whether the group is queued to run already
+    ready: bool,
+}
+
+/// The groups one worker runs. Only the groups messages came in for, and those whose timer ran
+/// out, are run, so a worker with many idle groups doesn't check every one of them each time it
+/// wakes.
+struct WorkerGroups<C: LogCommand> {
+    groups: HashMap<GroupId, ScheduledGroup<C>>,
+    // Earliest first, an entry whose deadline isn't its group's `timer` anymore is stale
+    timers: BinaryHeap<Reverse<(Instant, GroupId)>>,
+    ready: VecDeque<GroupId>,
+}
+impl<C: LogCommand> WorkerGroups<C> {
+    fn new() -> Self {
+        WorkerGroups {
+            groups: HashMap::new(),
+            timers: BinaryHeap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// How long until the worker has a group to run
+    fn max_wait(&self) -> Duration {
+        if !self.ready.is_empty() {
+            return Duration::ZERO;
+        }
+        self.timers
+            .peek()
+            .map(|Reverse((wake_at, _))| wake_at.saturating_duration_since(system_clock::now()))
+            .unwrap_or(IDLE_WORKER_WAIT)
+    }
+
+    fn add(&mut self, group_id: GroupId, group: Box<dyn HostedGroup<C>>) {
+        let timer = group.wake_at();
+        self.timers.push(Reverse((timer, group_id)));
+        let _ = self.groups.insert(
+            group_id,
+            ScheduledGroup {
+                group,
+                timer,
+                ready: false,
+            },
+        );
+    }
+
+    fn deliver(&mut self, group_id: GroupId, message: RpcMessage<C>) {
+        match self.groups.get_mut(&group_id) {
+            Some(scheduled) => {
+                scheduled.group.deliver(message);
+                Self::mark_ready(&mut self.ready, group_id, scheduled);
+            }
+            None => trace!("Dropping message for unknown group {:?}", group_id),
+        }
+    }
+
+    fn mark_ready(
+        ready: &mut VecDeque<GroupId>,
+        group_id: GroupId,
+        scheduled: &mut ScheduledGroup<C>,
+    ) {
+        if !scheduled.ready {
+            scheduled.ready = true;
+            ready.push_back(group_id);
+        }
+    }
+
+    /// Runs the groups that are due, dropping those that stopped
+    fn run_due(&mut self) {
+        let now = system_clock::now();
+        while let Some(Reverse((wake_at, group_id))) = self.timers.peek().copied() {
+            if wake_at > now {
+                break;
+            }
+            let _ = self.timers.pop();
+            if let Some(scheduled) = self.groups.get_mut(&group_id) {
+                if scheduled.timer == wake_at {
+                    Self::mark_ready(&mut self.ready, group_id, scheduled);
+                }
+            }
+        }
+        while let Some(group_id) = self.ready.pop_front() {
+            let Some(scheduled) = self.groups.get_mut(&group_id) else {
+                continue;
+            };
+            scheduled.ready = false;
+            if scheduled.group.run_while_due() {
+                scheduled.timer = scheduled.group.wake_at();
+                self.timers.push(Reverse((scheduled.timer, group_id)));
+            } else {
+                let _ = self.groups.remove(&group_id);
+            }
+        }
+    }
+}
+
+/// Runs the groups handed to one worker until the manager and its routers are gone
+fn run_worker<C: LogCommand>(inputs: mpsc::Receiver<WorkerInput<C>>) {
+    let mut groups = WorkerGroups::new();
+    loop {
+        let first_input = match inputs.recv_timeout(groups.max_wait()) {
+            Ok(input) => Some(input),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        for input in first_input.into_iter().chain(inputs.try_iter()) {
+            match input {
+                WorkerInput::Incoming(group_id, message) => groups.deliver(group_id, *message),
+                WorkerInput::AddGroup(group_id, group) => groups.add(group_id, group),
+            }
+        }
+        groups.run_due();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use raft_core::rpc_messages::{ReplyTo, RpcMessage, Vote};
+    use raft_core::system_clock::{self, Instant};
+    use raft_core::{ServerId, TermIndex};
+    use uuid::Uuid;
+
+    use super::{GroupId, HostedGroup, WorkerGroups};
+
+    /// Far enough out that a group waking then never comes due during a test
+    const IDLE: Duration = Duration::from_secs(3600);
+
+    /// Group counting its passes, due again at `rearm_at` after each one
+    struct FakeGroup {
+        runs: Arc<AtomicUsize>,
+        wake_at: Instant,
+        rearm_at: Instant,
+        keep_running: bool,
+    }
+    impl HostedGroup<u64> for FakeGroup {
+        fn deliver(&mut self, _message: RpcMessage<u64>) {
+            self.wake_at = system_clock::now();
+        }
+
+        fn wake_at(&self) -> Instant {
+            self.wake_at
+        }
+
+        fn run_while_due(&mut self) -> bool {
+            let _ = self.runs.fetch_add(1, Ordering::Relaxed);
+            self.wake_at = self.rearm_at;
+            self.keep_running
+        }
+    }
+
+    /// Adds a group first due at `wake_at` and at `rearm_at` after every pass, returning how
+    /// many passes it made
+    fn add_group(
+        groups: &mut WorkerGroups<u64>,
+        group_id: GroupId,
+        wake_at: Instant,
+        rearm_at: Instant,
+    ) -> Arc<AtomicUsize> {
+        let runs = Arc::new(AtomicUsize::new(0));
+        groups.add(
+            group_id,
+            Box::new(FakeGroup {
+                runs: runs.clone(),
+                wake_at,
+                rearm_at,
+                keep_running: true,
+            }),
+        );
+        runs
+    }
+
+    fn message() -> RpcMessage<u64> {
+        RpcMessage::Reply(ReplyTo::RequestVote(Vote {
+            request_id: Uuid::new_v4(),
+            from: ServerId(2),
+            to: ServerId(1),
+            term: TermIndex(1),
+            vote_granted: true,
+        }))
+    }
+
+    #[test]
+    fn should_only_run_groups_that_are_due_or_got_messages() {
+        let mut groups = WorkerGroups::new();
+        let now = system_clock::now();
+        let due = add_group(&mut groups, GroupId(1), now, now + IDLE);
+        let idle = add_group(&mut groups, GroupId(2), now + IDLE, now + IDLE);
+        let messaged = add_group(&mut groups, GroupId(3), now + IDLE, now + IDLE);
+
+        groups.deliver(GroupId(3), message());
+        assert_eq!(groups.max_wait(), Duration::ZERO);
+        groups.run_due();
+
+        assert_eq!(due.load(Ordering::Relaxed), 1);
+        assert_eq!(idle.load(Ordering::Relaxed), 0);
+        assert_eq!(messaged.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn should_rearm_a_group_at_its_next_deadline_after_it_ran() {
+        let mut groups = WorkerGroups::new();
+        let now = system_clock::now();
+        let runs = add_group(&mut groups, GroupId(1), now, now + IDLE);
+
+        groups.run_due();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert_eq!(groups.groups[&GroupId(1)].timer, now + IDLE);
+        assert!(groups.max_wait() > IDLE / 2);
+
+        // Nothing due until the new deadline, running again leaves the group be
+        groups.run_due();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert_eq!(groups.timers.len(), 1);
+    }
+
+    #[test]
+    fn should_skip_heap_entries_for_deadlines_a_group_moved_off() {
+        let mut groups = WorkerGroups::new();
+        let now = system_clock::now();
+        let deadline = Duration::from_millis(20);
+        let runs = add_group(&mut groups, GroupId(1), now + deadline, now + IDLE);
+
+        // A message runs the group before its first deadline, which moves to the idle one
+        groups.deliver(GroupId(1), message());
+        groups.run_due();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert_eq!(groups.timers.len(), 2);
+
+        std::thread::sleep(deadline * 2);
+        groups.run_due();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert_eq!(groups.timers.len(), 1);
+    }
+
+    #[test]
+    fn should_drop_groups_that_stopped() {
+        let mut groups = WorkerGroups::new();
+        let now = system_clock::now();
+        groups.add(
+            GroupId(1),
+            Box::new(FakeGroup {
+                runs: Arc::new(AtomicUsize::new(0)),
+                wake_at: now,
+                rearm_at: now,
+                keep_running: false,
+            }),
+        );
+
+        groups.run_due();
+        assert!(groups.groups.is_empty());
+        // The group's messages from then on go nowhere
+        groups.deliver(GroupId(1), message());
+        assert!(groups.ready.is_empty());
+    }
+}
@@ -0,0 +1,310 @@
+#[cfg(feature = "tokio")]
+use crate::async_runtime::{spawn_raft_task, AsyncRaftTransport};
+#[cfg(feature = "tokio")]
+use crate::async_storage::{AsyncPersistentStorage, BlockOnStorage};
+use crate::boxed_storage::BoxedStorage;
+use crate::codec::{BincodeCodec, CommandCodec};
+use crate::common::{ApplicationThatNeedsConsensus, NodeBuildError, RaftTransportConnector};
+use crate::events::{NoOpRaftEventCollector, RaftStateEventCollector};
+use crate::metrics::RaftMetrics;
+use crate::raft_thread::{start_raft_in_new_thread, NodeStorage, RaftNodeHandle};
+use raft_core::system_clock::{Clock, SystemClock};
+use raft_core::{PersistentStorage, RaftConfig, ServerId};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Collects what a node needs and starts it in its own thread. Only the server id, application,
+/// storage, or a storage directory, and transport have to be given. By default the node has no peers, runs with
+/// [`RaftConfig::builder`]'s defaults, seeds its rng from the OS, reads the time off the
+/// [`SystemClock`], publishes events nowhere and records metrics no one reads.
+///
+/// Until [`RaftNodeBuilder::transport`] is called the builder can't start a node. With the `tokio`
+/// feature, a builder given an [`crate::AsyncRaftTransport`] starts it as a task instead.
+pub struct RaftNodeBuilder<A, T = (), E = NoOpRaftEventCollector>
+where
+    A: ApplicationThatNeedsConsensus,
+{
+    server_id: ServerId,
+    application: A,
+    other_servers: HashSet<ServerId>,
+    storage_path: Option<String>,
+    storage: Option<BoxedStorage<A::Command>>,
+    transport: T,
+    config: Option<RaftConfig>,
+    rng: Option<ChaCha8Rng>,
+    clock: Option<Box<dyn Clock>>,
+    thread_stack_size: Option<usize>,
+    command_codec: Option<Arc<dyn CommandCodec<A::Command>>>,
+    event_collector: E,
+    metrics: RaftMetrics,
+}
+impl<A: ApplicationThatNeedsConsensus> RaftNodeBuilder<A> {
+    /// Start building the node `server_id`, which applies committed commands to `application`
+    pub fn new(server_id: ServerId, application: A) -> Self {
+        RaftNodeBuilder {
+            server_id,
+            application,
+            other_servers: HashSet::new(),
+            storage_path: None,
+            storage: None,
+            transport: (),
+            config: None,
+            rng: None,
+            clock: None,
+            thread_stack_size: None,
+            command_codec: None,
+            event_collector: NoOpRaftEventCollector,
+            metrics: RaftMetrics::new(),
+        }
+    }
+}
+impl<A: ApplicationThatNeedsConsensus, T, E> RaftNodeBuilder<A, T, E> {
+    /// The other members of the cluster the node starts with
+    pub fn peers(mut self, other_servers: HashSet<ServerId>) -> Self {
+        self.other_servers = other_servers;
+        self
+    }
+
+    /// Directory the node keeps its log, snapshots and audit log in. It has to exist already.
+    /// With [`RaftNodeBuilder::storage`] given, only the audit log is kept here.
+    pub fn storage_path(mut self, storage_path: impl Into<String>) -> Self {
+        self.storage_path = Some(storage_path.into());
+        self
+    }
+
+    /// Storage the node runs on instead of a [`crate::DefaultPersistentStorage`] in
+    /// [`RaftNodeBuilder::storage_path`], already opened. Without a storage path as well the node
+    /// keeps no audit log.
+    pub fn storage(mut self, storage: impl PersistentStorage<A::Command> + 'static) -> Self {
+        self.storage = Some(BoxedStorage::new(storage));
+        self
+    }
+
+    /// Like [`RaftNodeBuilder::storage`], for storage that awaits the disk, e.g. a
+    /// [`crate::SpawnBlockingStorage`]. Meant for [`RaftNodeBuilder::start_async`], a node started
+    /// on a thread of its own waits for it on a runtime of its own.
+    #[cfg(feature = "tokio")]
+    pub fn async_storage(
+        mut self,
+        storage: impl AsyncPersistentStorage<A::Command> + 'static,
+    ) -> Self
+    where
+        A::Command: 'static,
+    {
+        self.storage = Some(BoxedStorage::new(BlockOnStorage::new(storage)));
+        self
+    }
+
+    /// How the node talks to its peers
+    pub fn transport<T2>(self, transport: T2) -> RaftNodeBuilder<A, T2, E> {
+        RaftNodeBuilder {
+            server_id: self.server_id,
+            application: self.application,
+            other_servers: self.other_servers,
+            storage_path: self.storage_path,
+            storage: self.storage,
+            transport,
+            config: self.config,
+            rng: self.rng,
+            clock: self.clock,
+            thread_stack_size: self.thread_stack_size,
+            command_codec: self.command_codec,
+            event_collector: self.event_collector,
+            metrics: self.metrics,
+        }
+    }
+
+    /// The node's config, checked again before the node starts
+    pub fn config(mut self, config: RaftConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Seed for the rng the node draws election timeouts from, the same seed draws the same ones
+    pub fn rng_seed(self, seed: u64) -> Self {
+        self.rng(ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    /// The rng the node draws election timeouts from
+    pub fn rng(mut self, rng: ChaCha8Rng) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// The clock the node reads the time off to run its election and heartbeat timers
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Stack size in bytes of the node's thread, handy to keep many nodes in one process small.
+    /// The platform default without it, a node started as a task runs on the runtime's threads.
+    pub fn thread_stack_size(mut self, bytes: usize) -> Self {
+        self.thread_stack_size = Some(bytes);
+        self
+    }
+
+    /// How the node encodes commands in its log file. Every node of the cluster has to use the
+    /// same codec, and a node has to keep the one its log file was written with. Storage given
+    /// with [`RaftNodeBuilder::storage`] encodes commands its own way.
+    pub fn command_codec(mut self, codec: impl CommandCodec<A::Command> + 'static) -> Self {
+        self.command_codec = Some(Arc::new(codec));
+        self
+    }
+
+    /// Where the node publishes its events
+    pub fn event_collector<E2>(self, event_collector: E2) -> RaftNodeBuilder<A, T, E2> {
+        RaftNodeBuilder {
+            server_id: self.server_id,
+            application: self.application,
+            other_servers: self.other_servers,
+            storage_path: self.storage_path,
+            storage: self.storage,
+            transport: self.transport,
+            config: self.config,
+            rng: self.rng,
+            clock: self.clock,
+            thread_stack_size: self.thread_stack_size,
+            command_codec: self.command_codec,
+            event_collector,
+            metrics: self.metrics,
+        }
+    }
+
+    /// Where the node records its metrics, share them to read them while it runs
+    pub fn metrics(mut self, metrics: RaftMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Checks what was given, filling in the defaults
+    fn validate(self) -> Result<ValidNode<A, T, E>, NodeBuildError> {
+        let storage_path = self.storage_path.filter(|path| !path.is_empty());
+        let storage = match (self.storage, &storage_path) {
+            (Some(storage), _) => NodeStorage::Provided(storage),
+            (None, Some(path)) => NodeStorage::Directory {
+                path: path.clone(),
+                codec: self.command_codec.unwrap_or_else(|| Arc::new(BincodeCodec)),
+            },
+            (None, None) => return Err(NodeBuildError::MissingStoragePath),
+        };
+        if self.other_servers.contains(&self.server_id) {
+            return Err(NodeBuildError::ServerIsOwnPeer(self.server_id));
+        }
+        let config = match self.config {
+            Some(config) => {
+                config.validate().map_err(NodeBuildError::InvalidConfig)?;
+                config
+            }
+            None => RaftConfig::builder()
+                .build()
+                .map_err(NodeBuildError::InvalidConfig)?,
+        };
+        Ok(ValidNode {
+            server_id: self.server_id,
+            application: self.application,
+            other_servers: self.other_servers,
+            storage,
+            audit_log_dir: storage_path,
+            transport: self.transport,
+            config,
+            rng: self.rng.unwrap_or_else(ChaCha8Rng::from_entropy),
+            clock: self.clock.unwrap_or_else(|| Box::new(SystemClock)),
+            thread_stack_size: self.thread_stack_size,
+            event_collector: self.event_collector,
+            metrics: self.metrics,
+        })
+    }
+}
+impl<A, T, E> RaftNodeBuilder<A, T, E>
+where
+    A: ApplicationThatNeedsConsensus + 'static,
+    T: RaftTransportConnector<A::Command> + 'static,
+    E: RaftStateEventCollector + 'static,
+{
+    /// Checks what was given and starts the node in a new thread. Storage is opened on that
+    /// thread, a node that can't open it halts right away, see [`RaftNodeHandle::join`]. Fails
+    /// with [`NodeBuildError::ThreadSpawnFailed`] if the OS won't start the thread.
+    pub fn start(self) -> Result<RaftNodeHandle<A>, NodeBuildError> {
+        let node = self.validate()?;
+        start_raft_in_new_thread(
+            node.server_id,
+            node.other_servers,
+            node.storage,
+            node.audit_log_dir,
+            node.application,
+            node.config,
+            node.rng,
+            node.clock,
+            node.thread_stack_size,
+            node.transport,
+            node.event_collector,
+            node.metrics,
+        )
+    }
+}
+#[cfg(feature = "tokio")]
+impl<A, T, E> RaftNodeBuilder<A, T, E>
+where
+    A: ApplicationThatNeedsConsensus + 'static,
+    T: AsyncRaftTransport<A::Command> + 'static,
+    E: RaftStateEventCollector + 'static,
+{
+    /// Like [`RaftNodeBuilder::start`], but runs the node as a task on the tokio runtime it's
+    /// called from instead of a thread of its own. Storage is opened on one of the runtime's
+    /// blocking threads, a node that can't open it halts. The handle's [`RaftNodeHandle::join`] blocks, call it
+    /// from outside the runtime.
+    pub fn start_async(self) -> Result<RaftNodeHandle<A>, NodeBuildError> {
+        let runtime =
+            tokio::runtime::Handle::try_current().map_err(|_| NodeBuildError::NoAsyncRuntime)?;
+        let node = self.validate()?;
+        Ok(spawn_raft_task(
+            &runtime,
+            node.server_id,
+            node.other_servers,
+            node.storage,
+            node.audit_log_dir,
+            node.application,
+            node.config,
+            node.rng,
+            node.clock,
+            node.transport,
+            node.event_collector,
+            node.metrics,
+        ))
+    }
+}
+
+/// What a builder collected once it checked out
+struct ValidNode<A: ApplicationThatNeedsConsensus, T, E> {
+    server_id: ServerId,
+    application: A,
+    other_servers: HashSet<ServerId>,
+    storage: NodeStorage<A::Command>,
+    audit_log_dir: Option<String>,
+    transport: T,
+    config: RaftConfig,
+    rng: ChaCha8Rng,
+    clock: Box<dyn Clock>,
+    thread_stack_size: Option<usize>,
+    event_collector: E,
+    metrics: RaftMetrics,
+}
+impl<A: ApplicationThatNeedsConsensus, T, E> Debug for RaftNodeBuilder<A, T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RaftNodeBuilder")
+            .field("server_id", &self.server_id)
+            .field("other_servers", &self.other_servers)
+            .field("storage_path", &self.storage_path)
+            .field("storage_provided", &self.storage.is_some())
+            .field("config", &self.config)
+            .field("clock", &self.clock)
+            .field("thread_stack_size", &self.thread_stack_size)
+            .field("command_codec", &self.command_codec)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,349 @@
+use crate::metrics::{
+    metric_names, role_code, HistogramSnapshot, MetricsSink, PeerConnection, RaftMetrics,
+    LATENCY_BUCKET_BOUNDS_MS,
+};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{self, MetricFamily, MetricType};
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntGauge, IntGaugeVec, Opts, Registry};
+use raft_core::ServerId;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Counters, as `(name, help)`, exported with a `_total` suffix
+const COUNTERS: [(&str, &str); 9] = [
+    (
+        metric_names::ELECTIONS_STARTED,
+        "Elections the node started as a candidate",
+    ),
+    (
+        metric_names::SPLIT_VOTES,
+        "Elections that timed out without a winner",
+    ),
+    (
+        metric_names::PRE_VOTE_REJECTIONS,
+        "Pre-vote requests the node turned down",
+    ),
+    (
+        metric_names::VOTES_GRANTED,
+        "Votes the node granted to candidates",
+    ),
+    (
+        metric_names::RPCS_SENT,
+        "Requests and replies the node handed to its transport",
+    ),
+    (
+        metric_names::RPCS_RECEIVED,
+        "Requests and replies the transport handed to the node",
+    ),
+    (
+        metric_names::RPCS_DROPPED,
+        "Requests and replies the transport turned away or lost",
+    ),
+    (
+        metric_names::BYTES_WRITTEN,
+        "Bytes of appended entries storage made durable",
+    ),
+    (
+        metric_names::BYTES_READ,
+        "Bytes of entries read back from storage",
+    ),
+];
+
+/// Gauges, as `(name, help)`
+const GAUGES: [(&str, &str); 4] = [
+    (metric_names::CURRENT_TERM, "The node's current term"),
+    (metric_names::COMMIT_INDEX, "The node's commit index"),
+    (
+        metric_names::ROLE,
+        "0 for a follower, 1 for a pre-candidate, 2 for a candidate, 3 for a leader",
+    ),
+    (
+        metric_names::LOG_SIZE_ON_DISK,
+        "Bytes the log took up on disk after the last sync",
+    ),
+];
+
+/// A gauge kept per peer, as `(name, help, value)`
+type PeerGauge = (&'static str, &'static str, fn(&PeerConnection) -> u64);
+
+/// Gauges kept per peer, labelled with the peer's id
+const PEER_GAUGES: [PeerGauge; 2] = [
+    (
+        metric_names::PEER_CONNECTED,
+        "1 while the transport is connected to the peer, 0 while it reconnects",
+        |connection| connection.connected as u64,
+    ),
+    (
+        metric_names::PEER_BUFFERED_MESSAGES,
+        "Messages for the peer held back until the transport reconnects",
+        |connection| connection.buffered_messages,
+    ),
+];
+
+/// Histograms, as `(name, help)`, exported in seconds with a `_seconds` suffix
+const HISTOGRAMS: [(&str, &str); 5] = [
+    (
+        metric_names::COMMIT_LATENCY,
+        "Time from the leader appending a proposed entry until it's committed",
+    ),
+    (
+        metric_names::APPLY_LATENCY,
+        "Time from the leader appending a proposed entry until it's applied",
+    ),
+    (
+        metric_names::ELECTION_DURATION,
+        "Time from starting to campaign until becoming leader or follower",
+    ),
+    (
+        metric_names::APPEND_LATENCY,
+        "Time storage took to make appended entries durable",
+    ),
+    (metric_names::SYNC_LATENCY, "Time every storage sync took"),
+];
+
+/// Exports a node's [`RaftMetrics`] to a Prometheus registry, reading them as they are when
+/// scraped. Register one per node, with a registry or const labels telling the nodes apart if
+/// several share a process.
+#[derive(Debug)]
+pub struct PrometheusCollector {
+    metrics: RaftMetrics,
+    descs: Vec<Desc>,
+    peer_descs: Vec<Desc>,
+}
+impl PrometheusCollector {
+    /// Collects `metrics`, as a clone shares them with the node recording into them
+    pub fn new(metrics: RaftMetrics) -> Self {
+        let counters = COUNTERS
+            .iter()
+            .map(|(name, help)| (format!("{name}_total"), help));
+        let gauges = GAUGES.iter().map(|(name, help)| (name.to_string(), help));
+        let histograms = HISTOGRAMS
+            .iter()
+            .map(|(name, help)| (format!("{name}_seconds"), help));
+        let descs = counters
+            .chain(gauges)
+            .chain(histograms)
+            .map(|(name, help)| {
+                Desc::new(name, help.to_string(), vec![], HashMap::new())
+                    .expect("metric names are valid")
+            })
+            .collect();
+        let peer_descs = PEER_GAUGES
+            .iter()
+            .map(|(name, help, _)| {
+                Desc::new(
+                    name.to_string(),
+                    help.to_string(),
+                    vec!["peer".to_string()],
+                    HashMap::new(),
+                )
+                .expect("metric names are valid")
+            })
+            .collect();
+        PrometheusCollector {
+            metrics,
+            descs,
+            peer_descs,
+        }
+    }
+
+    /// A family per gauge in [`PEER_GAUGES`], with a metric for every peer the transport reported
+    /// on, empty if it doesn't keep connections
+    fn peer_families(&self) -> Vec<MetricFamily> {
+        let mut connections: Vec<_> = self.metrics.peer_connections().into_iter().collect();
+        connections.sort_by_key(|(peer, _)| peer.0);
+        PEER_GAUGES
+            .iter()
+            .zip(&self.peer_descs)
+            .map(|((_, _, value), desc)| {
+                let mut family = MetricFamily::default();
+                family.set_name(desc.fq_name.clone());
+                family.set_help(desc.help.clone());
+                family.set_field_type(MetricType::GAUGE);
+                for (peer, connection) in &connections {
+                    let mut label = proto::LabelPair::default();
+                    label.set_name("peer".to_string());
+                    label.set_value(peer.0.to_string());
+                    let mut gauge = proto::Gauge::default();
+                    gauge.set_value(value(connection) as f64);
+                    let mut metric = proto::Metric::default();
+                    metric.set_label(vec![label].into());
+                    metric.set_gauge(gauge);
+                    family.mut_metric().push(metric);
+                }
+                family
+            })
+            .collect()
+    }
+}
+impl Collector for PrometheusCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().chain(&self.peer_descs).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let elections = self.metrics.election_stats();
+        let rpcs = self.metrics.rpc_stats();
+        let gauges = self.metrics.gauges();
+        let storage = self.metrics.storage_stats();
+        let counter_values = [
+            elections.elections_started,
+            elections.split_votes,
+            elections.pre_vote_rejections,
+            elections.votes_granted,
+            rpcs.sent,
+            rpcs.received,
+            rpcs.dropped,
+            storage.bytes_written,
+            storage.bytes_read,
+        ];
+        // A node that hasn't published its state yet is starting up as a follower
+        let role = gauges.role.map(role_code).unwrap_or(0);
+        let gauge_values = [
+            gauges.current_term.0,
+            gauges.commit_index.0,
+            role,
+            storage.log_size_on_disk,
+        ];
+        let histogram_values = [
+            self.metrics.commit_latency().snapshot(),
+            self.metrics.apply_latency().snapshot(),
+            elections.election_duration,
+            self.metrics.append_latency().snapshot(),
+            self.metrics.sync_latency().snapshot(),
+        ];
+
+        let counters = counter_values.into_iter().map(|value| {
+            let mut counter = proto::Counter::default();
+            counter.set_value(value as f64);
+            let mut metric = proto::Metric::default();
+            metric.set_counter(counter);
+            (MetricType::COUNTER, metric)
+        });
+        let gauges = gauge_values.into_iter().map(|value| {
+            let mut gauge = proto::Gauge::default();
+            gauge.set_value(value as f64);
+            let mut metric = proto::Metric::default();
+            metric.set_gauge(gauge);
+            (MetricType::GAUGE, metric)
+        });
+        let histograms = histogram_values.iter().map(|snapshot| {
+            let mut metric = proto::Metric::default();
+            metric.set_histogram(histogram(snapshot));
+            (MetricType::HISTOGRAM, metric)
+        });
+        counters
+            .chain(gauges)
+            .chain(histograms)
+            .zip(&self.descs)
+            .map(|((metric_type, metric), desc)| {
+                let mut family = MetricFamily::default();
+                family.set_name(desc.fq_name.clone());
+                family.set_help(desc.help.clone());
+                family.set_field_type(metric_type);
+                family.mut_metric().push(metric);
+                family
+            })
+            .chain(self.peer_families())
+            .collect()
+    }
+}
+
+/// Updates Prometheus counters, gauges and histograms as a node records its metrics, named like
+/// [`PrometheusCollector`]'s. Suits a registry the application already pushes or exposes other
+/// metrics through, where the collector suits reading the node's [`RaftMetrics`] as they are when
+/// scraped, including what was recorded before registering. Register one of the two, not both.
+#[derive(Debug, Clone)]
+pub struct PrometheusMetricsSink {
+    counters: HashMap<&'static str, IntCounter>,
+    gauges: HashMap<&'static str, IntGauge>,
+    peer_gauges: HashMap<&'static str, IntGaugeVec>,
+    histograms: HashMap<&'static str, Histogram>,
+}
+impl PrometheusMetricsSink {
+    /// A sink whose metrics are registered with `registry`, fails if it already has metrics of
+    /// the same names
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let mut counters = HashMap::new();
+        for (name, help) in COUNTERS {
+            let counter = IntCounter::new(format!("{name}_total"), help)?;
+            registry.register(Box::new(counter.clone()))?;
+            let _ = counters.insert(name, counter);
+        }
+        let mut gauges = HashMap::new();
+        for (name, help) in GAUGES {
+            let gauge = IntGauge::new(name, help)?;
+            registry.register(Box::new(gauge.clone()))?;
+            let _ = gauges.insert(name, gauge);
+        }
+        let mut peer_gauges = HashMap::new();
+        for (name, help, _) in PEER_GAUGES {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), &["peer"])?;
+            registry.register(Box::new(gauge.clone()))?;
+            let _ = peer_gauges.insert(name, gauge);
+        }
+        let buckets: Vec<f64> = LATENCY_BUCKET_BOUNDS_MS
+            .iter()
+            .map(|bound| Duration::from_millis(*bound).as_secs_f64())
+            .collect();
+        let mut histograms = HashMap::new();
+        for (name, help) in HISTOGRAMS {
+            let histogram = Histogram::with_opts(
+                HistogramOpts::new(format!("{name}_seconds"), help).buckets(buckets.clone()),
+            )?;
+            registry.register(Box::new(histogram.clone()))?;
+            let _ = histograms.insert(name, histogram);
+        }
+        Ok(PrometheusMetricsSink {
+            counters,
+            gauges,
+            peer_gauges,
+            histograms,
+        })
+    }
+}
+impl MetricsSink for PrometheusMetricsSink {
+    fn increment_counter(&self, name: &'static str, value: u64) {
+        if let Some(counter) = self.counters.get(name) {
+            counter.inc_by(value);
+        }
+    }
+
+    fn observe_duration(&self, name: &'static str, duration: Duration) {
+        if let Some(histogram) = self.histograms.get(name) {
+            histogram.observe(duration.as_secs_f64());
+        }
+    }
+
+    fn set_gauge(&self, name: &'static str, value: u64) {
+        if let Some(gauge) = self.gauges.get(name) {
+            gauge.set(i64::try_from(value).unwrap_or(i64::MAX));
+        }
+    }
+
+    fn set_peer_gauge(&self, name: &'static str, peer: ServerId, value: u64) {
+        if let Some(gauge) = self.peer_gauges.get(name) {
+            gauge
+                .with_label_values(&[&peer.0.to_string()])
+                .set(i64::try_from(value).unwrap_or(i64::MAX));
+        }
+    }
+}
+
+/// `snapshot` with cumulative buckets in seconds, Prometheus adds the `+Inf` one from the count
+fn histogram(snapshot: &HistogramSnapshot) -> proto::Histogram {
+    let mut histogram = proto::Histogram::default();
+    histogram.set_sample_count(snapshot.count);
+    histogram.set_sample_sum(snapshot.sum.as_secs_f64());
+    let mut cumulative_count = 0;
+    for (upper_bound, count) in &snapshot.buckets {
+        cumulative_count += count;
+        if let Some(upper_bound) = upper_bound {
+            let mut bucket = proto::Bucket::default();
+            bucket.set_upper_bound(upper_bound.as_secs_f64());
+            bucket.set_cumulative_count(cumulative_count);
+            histogram.mut_bucket().push(bucket);
+        }
+    }
+    histogram
+}
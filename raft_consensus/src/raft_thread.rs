@@ -1,177 +1,2163 @@
+use crate::audit_log::AuditLog;
+use crate::boxed_storage::BoxedStorage;
+use crate::client_sessions::ClientSessions;
+use crate::codec::CommandCodec;
 pub use crate::common::*;
+use crate::crash_reporting;
+use crate::default_storage::encoded_len;
 pub use crate::default_storage::DefaultPersistentStorage;
-use crate::rpc_messages::RpcMessage;
-use crate::state_machine::*;
-use crate::system_clock;
+use crate::diagnostics::{NodeDiagnostics, RaftDiagnostics, RaftStatus};
+use crate::events::*;
+use crate::metrics::{EventMetricsRecorder, RaftMetrics};
+use crate::slow_operations::{warn_if_slow, SyncTimingStorage};
+use raft_core::rpc_messages::{
+    ForwardProposals, ForwardProposalsAck, ForwardedProposalsOutcome, ReplyTo, Request, RpcMessage,
+};
+use raft_core::system_clock::{self, Clock, Instant};
+use raft_core::*;
 use rand_chacha::ChaCha8Rng;
 
-use std::collections::HashSet;
-use std::path::Path;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
-use std::{thread, vec};
 
 use crate::common::RaftTransportConnector;
 
-use tracing::{info, trace};
+use tracing::{debug_span, error, info, info_span, trace, warn, Span};
+use uuid::Uuid;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum RaftNodeState {
-    Follower,
-    Candidate,
-    Leader,
+/// Number of published events a node keeps around for [`RaftNodeHandle::recent_events`]
+pub(crate) const RECENT_EVENTS_CAPACITY: usize = 256;
+
+/// How often a paused node checks whether it was resumed or shut down
+pub(crate) const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where a node gets its storage from
+pub(crate) enum NodeStorage<LC: LogCommand> {
+    /// A [`DefaultPersistentStorage`] in this directory, encoding commands with `codec`
+    Directory {
+        path: String,
+        codec: Arc<dyn CommandCodec<LC>>,
+    },
+    /// Storage the application opened itself
+    Provided(BoxedStorage<LC>),
+}
+impl<LC: LogCommand + 'static> NodeStorage<LC> {
+    /// Opens the storage, on the thread the node runs on
+    fn open(self) -> Result<BoxedStorage<LC>, PersistentStorageError> {
+        match self {
+            NodeStorage::Directory { path, codec } => {
+                DefaultPersistentStorage::with_codec(Path::new(&path), codec).map(BoxedStorage::new)
+            }
+            NodeStorage::Provided(storage) => Ok(storage),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct RaftStateEvent {
-    pub server_id: ServerId,
-    pub current_state: RaftNodeState,
-    pub current_term: TermIndex,
-    pub voted_for: Option<ServerId>,
-    pub leader_for_term: Option<ServerId>,
+/// Starts a node in its own thread with everything a [`crate::RaftNodeBuilder`] collected
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn start_raft_in_new_thread<
+    LC: LogCommand + 'static,
+    A: ApplicationThatNeedsConsensus<Command = LC> + 'static,
+>(
+    server_id: ServerId,
+    other_servers: HashSet<ServerId>,
+    storage: NodeStorage<LC>,
+    audit_log_dir: Option<String>,
+    application: A,
+    mut config: RaftConfig,
+    rng: ChaCha8Rng,
+    clock: Box<dyn Clock>,
+    thread_stack_size: Option<usize>,
+    mut transport_connector: impl RaftTransportConnector<LC> + 'static,
+    event_collector: impl RaftStateEventCollector + 'static,
+    metrics: RaftMetrics,
+) -> Result<RaftNodeHandle<A>, NodeBuildError> {
+    transport_connector.attach_metrics(metrics.clone());
+    if let Some(transport_max) = transport_connector.message_size_limit() {
+        let max_message_size = config
+            .max_message_size
+            .map_or(transport_max, |max_message_size| {
+                max_message_size.0.min(transport_max)
+            });
+        config.max_message_size = Some(MaxMessageSize(max_message_size));
+    }
+    let max_entry_bytes = config.max_entry_bytes();
+    let (inbox, senders) = node_channels();
+    let diagnostics = RaftDiagnostics::default();
+    let thread_diagnostics = diagnostics.clone();
+    let recent_events = RecentEvents::new(RECENT_EVENTS_CAPACITY);
+    let thread_recent_events = recent_events.clone();
+    let thread_metrics = metrics.clone();
+    crash_reporting::install_panic_hook();
+    let mut thread_builder =
+        thread::Builder::new().name(format!("raft-server-{server_id}", server_id = server_id.0));
+    if let Some(stack_size) = thread_stack_size {
+        thread_builder = thread_builder.stack_size(stack_size);
+    }
+    let thread_handle = thread_builder
+        .spawn(move || {
+            crash_reporting::mark_current_thread(server_id);
+            let _node_span = info_span!("raft_node", server_id = server_id.0).entered();
+            let event_publisher = EventPublisher::new(
+                event_collector,
+                thread_metrics,
+                thread_recent_events,
+                audit_log_dir.as_deref(),
+            );
+            let mut node_loop = NodeLoop::open(
+                server_id,
+                other_servers,
+                storage,
+                application,
+                config,
+                rng,
+                clock,
+                transport_connector,
+                event_publisher,
+                inbox,
+                thread_diagnostics,
+            )?;
+
+            // Only returns once the node is shut down, or halts with the error it could not recover from
+            let run = panic::catch_unwind(AssertUnwindSafe(|| -> Option<RaftError> {
+                loop {
+                    match node_loop.run_once() {
+                        Ok(NodeStatus::Running) => {}
+                        Ok(NodeStatus::Paused) => thread::sleep(PAUSED_POLL_INTERVAL),
+                        Ok(NodeStatus::ShutDown) => return None,
+                        Err(error) => return Some(error),
+                    }
+                }
+            }));
+            match run {
+                Ok(None) => Ok(()),
+                Ok(Some(error)) => {
+                    node_loop.publish_halted(error);
+                    Err(error)
+                }
+                Err(panic) => {
+                    node_loop.publish_crashed(&*panic);
+                    panic::resume_unwind(panic);
+                }
+            }
+        })
+        .map_err(|error| {
+            error!("{:?}: Could not spawn raft thread: {}", server_id, error);
+            NodeBuildError::ThreadSpawnFailed
+        })?;
+
+    Ok(RaftNodeHandle::new(
+        NodeRuntime::Thread(thread_handle),
+        senders,
+        diagnostics,
+        recent_events,
+        metrics,
+        max_entry_bytes,
+    ))
+}
+
+/// The receiving ends of the channels a [`RaftNodeHandle`] hands work to its node through
+pub(crate) struct NodeInbox<A: ApplicationThatNeedsConsensus> {
+    /// Each message a batch of proposals to append at consecutive indexes
+    proposals: mpsc::Receiver<Vec<Proposal<A>>>,
+    membership_changes: mpsc::Receiver<MembershipChangeRequest>,
+    lease_reads: mpsc::Receiver<LeaseRead<A>>,
+    reads_after: mpsc::Receiver<ReadAfter<A>>,
+    step_downs: mpsc::Receiver<StepDownRequest>,
+    index_waits: mpsc::Receiver<IndexWait>,
+    pauses: mpsc::Receiver<PauseRequest>,
+    timing_updates: mpsc::Receiver<TimingUpdateRequest>,
+    backups: mpsc::Receiver<BackupRequest>,
+    applied_subscriptions: mpsc::Receiver<mpsc::Sender<AppliedEntry<A::Command>>>,
+    shutdown: mpsc::Receiver<()>,
+}
+
+/// The sending ends of the channels a [`RaftNodeHandle`] hands work to its node through
+#[derive(Debug)]
+pub(crate) struct NodeSenders<A: ApplicationThatNeedsConsensus> {
+    proposals: mpsc::Sender<Vec<Proposal<A>>>,
+    membership_changes: mpsc::Sender<MembershipChangeRequest>,
+    lease_reads: mpsc::Sender<LeaseRead<A>>,
+    reads_after: mpsc::Sender<ReadAfter<A>>,
+    step_downs: mpsc::Sender<StepDownRequest>,
+    index_waits: mpsc::Sender<IndexWait>,
+    pauses: mpsc::Sender<PauseRequest>,
+    timing_updates: mpsc::Sender<TimingUpdateRequest>,
+    backups: mpsc::Sender<BackupRequest>,
+    applied_subscriptions: mpsc::Sender<mpsc::Sender<AppliedEntry<A::Command>>>,
+    shutdown: mpsc::Sender<()>,
 }
 
-pub trait RaftStateEventCollector: Send {
-    fn push_event(&mut self, event: RaftStateEvent);
+pub(crate) fn node_channels<A: ApplicationThatNeedsConsensus>() -> (NodeInbox<A>, NodeSenders<A>) {
+    let (proposals, proposals_rx) = mpsc::channel();
+    let (membership_changes, membership_changes_rx) = mpsc::channel();
+    let (lease_reads, lease_reads_rx) = mpsc::channel();
+    let (reads_after, reads_after_rx) = mpsc::channel();
+    let (step_downs, step_downs_rx) = mpsc::channel();
+    let (index_waits, index_waits_rx) = mpsc::channel();
+    let (pauses, pauses_rx) = mpsc::channel();
+    let (timing_updates, timing_updates_rx) = mpsc::channel();
+    let (backups, backups_rx) = mpsc::channel();
+    let (applied_subscriptions, applied_subscriptions_rx) = mpsc::channel();
+    let (shutdown, shutdown_rx) = mpsc::channel();
+    (
+        NodeInbox {
+            proposals: proposals_rx,
+            membership_changes: membership_changes_rx,
+            lease_reads: lease_reads_rx,
+            reads_after: reads_after_rx,
+            step_downs: step_downs_rx,
+            index_waits: index_waits_rx,
+            pauses: pauses_rx,
+            timing_updates: timing_updates_rx,
+            backups: backups_rx,
+            applied_subscriptions: applied_subscriptions_rx,
+            shutdown: shutdown_rx,
+        },
+        NodeSenders {
+            proposals,
+            membership_changes,
+            lease_reads,
+            reads_after,
+            step_downs,
+            index_waits,
+            pauses,
+            timing_updates,
+            backups,
+            applied_subscriptions,
+            shutdown,
+        },
+    )
 }
 
-pub struct NoOpRaftEventCollector;
-impl RaftStateEventCollector for NoOpRaftEventCollector {
-    fn push_event(&mut self, _event: RaftStateEvent) {}
+/// Whether a node carries on after a pass
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NodeStatus {
+    Running,
+    /// Its handle paused it, the node took nothing in and checks again once its timer runs out
+    Paused,
+    /// Its handle shut it down, the node won't take another pass
+    ShutDown,
 }
 
-pub fn start_raft_in_new_thread<LC: LogCommand>(
+/// Everything a Raft node keeps between the passes that hand it events, whether it runs on its own
+/// thread or shares a worker of a [`crate::RaftGroupManager`] with other groups
+pub(crate) struct NodeLoop<LC, A, T, E>
+where
+    LC: LogCommand,
+    A: ApplicationThatNeedsConsensus<Command = LC>,
+    T: RaftTransportConnector<LC>,
+    E: RaftStateEventCollector,
+{
     server_id: ServerId,
     other_servers: HashSet<ServerId>,
-    storage_path: String,
+    application: A,
     config: RaftConfig,
-    mut rng: ChaCha8Rng,
-    mut transport_connector: impl RaftTransportConnector<LC> + 'static,
-    mut event_collector: impl RaftStateEventCollector + 'static,
-) -> thread::JoinHandle<()> {
-    thread::Builder::new()
-        .name(format!("raft-server-{server_id}", server_id = server_id.0))
-        .spawn(move || {
-            let start_time = system_clock::now();
+    rng: ChaCha8Rng,
+    /// What the node's timers run by, the loop's own timing and metrics go by the system clock
+    clock: Box<dyn Clock>,
+    storage: SyncTimingStorage<BoxedStorage<LC>>,
+    pub(crate) transport_connector: T,
+    event_publisher: EventPublisher<E>,
+    inbox: NodeInbox<A>,
+    diagnostics: RaftDiagnostics,
+    start_time: Instant,
+    /// `None` until the first pass starts the node, and while a pass hands it events
+    state: Option<Node>,
+    client_sessions: ClientSessions<A>,
+    max_wait_time: Duration,
+    /// When the node last started waiting, `max_wait_time` counts from here
+    waiting_since: Instant,
+    last_published_state: Option<RaftStateEvent>,
+    // Reused across passes so the hot loop doesn't allocate a fresh buffer per event
+    actions: Vec<Action<LC>>,
+    proposals_in_flight: ProposalsInFlight<A>,
+    forwards_in_flight: ForwardsInFlight<A>,
+    membership_changes_in_flight: HashMap<Uuid, mpsc::Sender<MembershipChangeOutcome>>,
+    lease_reads_in_flight: LeaseReadsInFlight<A>,
+    step_downs_in_flight: HashMap<Uuid, mpsc::Sender<StepDownOutcome>>,
+    /// Proposals a leader holds on to for [`RaftConfig::group_commit`], and when it took the first
+    held_proposals: Vec<Vec<Proposal<A>>>,
+    held_since: Option<Instant>,
+    // Dropping a sender tells its waiter the node stopped, they're only sent to once applied
+    index_waits: BTreeMap<LogIndex, Vec<mpsc::Sender<Result<(), IndexWaitError>>>>,
+    // Dropped once their receiver is
+    applied_subscribers: Vec<mpsc::Sender<AppliedEntry<LC>>>,
+    // Ahead of the application's own index once entries it never sees were applied
+    last_applied: LogIndex,
+    /// Set while the node is paused, it takes no events until resumed
+    paused_since: Option<Instant>,
+    /// How long the node was paused since its last pass, handed to it with the next one
+    paused_for: Duration,
+    term_span: TermSpan,
+}
+impl<LC, A, T, E> NodeLoop<LC, A, T, E>
+where
+    LC: LogCommand,
+    A: ApplicationThatNeedsConsensus<Command = LC>,
+    T: RaftTransportConnector<LC>,
+    E: RaftStateEventCollector,
+{
+    /// Opens the node's storage, publishing why the node halts if it can't
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn open(
+        server_id: ServerId,
+        other_servers: HashSet<ServerId>,
+        storage: NodeStorage<LC>,
+        application: A,
+        config: RaftConfig,
+        rng: ChaCha8Rng,
+        clock: Box<dyn Clock>,
+        transport_connector: T,
+        mut event_publisher: EventPublisher<E>,
+        inbox: NodeInbox<A>,
+        diagnostics: RaftDiagnostics,
+    ) -> Result<Self, RaftError>
+    where
+        LC: 'static,
+    {
+        let storage = match storage.open() {
+            Ok(storage) => SyncTimingStorage::new(
+                storage,
+                config.slow_operation_thresholds.storage_sync,
+                event_publisher.metrics.clone(),
+            ),
+            Err(error) => {
+                let error = RaftError::from(error);
+                error!(
+                    "{:?}: Could not open persistent storage: {}",
+                    server_id, error
+                );
+                event_publisher.publish(RaftEvent::NodeHalted { server_id, error }, false);
+                return Err(error);
+            }
+        };
+        let now = clock.now();
+        let last_applied = application.last_applied_index();
+        let term_span = TermSpan::new(storage.current_term(), RaftNodeState::Follower);
+        Ok(NodeLoop {
+            server_id,
+            other_servers,
+            application,
+            config,
+            rng,
+            clock,
+            storage,
+            transport_connector,
+            event_publisher,
+            inbox,
+            diagnostics,
+            start_time: system_clock::now(),
+            state: None,
+            client_sessions: ClientSessions::new(),
+            max_wait_time: Duration::from_millis(0),
+            waiting_since: now,
+            last_published_state: None,
+            actions: Vec::new(),
+            proposals_in_flight: ProposalsInFlight::new(),
+            forwards_in_flight: ForwardsInFlight::new(),
+            membership_changes_in_flight: HashMap::new(),
+            lease_reads_in_flight: LeaseReadsInFlight::new(),
+            step_downs_in_flight: HashMap::new(),
+            held_proposals: Vec::new(),
+            held_since: None,
+            index_waits: BTreeMap::new(),
+            applied_subscribers: Vec::new(),
+            last_applied,
+            paused_since: None,
+            paused_for: Duration::ZERO,
+            term_span,
+        })
+    }
 
-            let mut storage = DefaultPersistentStorage::new(Path::new(&storage_path));
+    /// When the node's timer runs out or the proposals it holds are due, by its clock, it has to
+    /// be handed a tick by then
+    pub(crate) fn wake_at(&self) -> Instant {
+        self.waiting_since + self.wait_time()
+    }
 
-            let (mut state, first_election_timeout) =
-                Node::new(server_id, other_servers, &config, &mut rng);
-            info!(
-                "{:?}: Starting raft node with state: {:?}, term: {:?}",
-                server_id,
-                match state {
-                    Node::Follower(_) => RaftNodeState::Follower,
-                    Node::Candidate(_) => RaftNodeState::Candidate,
-                    Node::Leader(_) => RaftNodeState::Leader,
-                },
-                storage.current_term(),
-            );
+    /// How long the node may wait for the next message, counting from `waiting_since`
+    fn wait_time(&self) -> Duration {
+        match (self.held_since, self.config.group_commit) {
+            (Some(held_since), Some(group_commit)) => self.max_wait_time.min(
+                (held_since + group_commit.window).saturating_duration_since(self.waiting_since),
+            ),
+            _ => self.max_wait_time,
+        }
+    }
 
-            let mut max_wait_time = first_election_timeout.0;
-            loop {
-                trace!(
-                    "Waiting {:?}ms for next message at time {:?}...",
-                    max_wait_time.as_millis(),
-                    start_time.elapsed().as_millis(),
-                );
+    /// The time by the node's clock
+    #[cfg(feature = "tokio")]
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Catches the application and client sessions up with storage and starts the node as a follower
+    fn start(&mut self) -> Node {
+        let mut last_applied = self.application.last_applied_index();
+        // The entries the application is missing may only be left in the snapshot
+        if let Some(snapshot) = PersistentStorage::<LC>::snapshot(&self.storage) {
+            if self.config.is_witness(self.server_id) {
+                // A witness's snapshot has no data, its application never has anything to restore
+                last_applied = last_applied.max(snapshot.last_included_index);
+            } else if last_applied < snapshot.last_included_index {
+                self.application.restore_snapshot(snapshot);
+                last_applied = self.application.last_applied_index();
+            }
+            self.client_sessions.restore(&snapshot.sessions);
+        }
+        self.client_sessions.replay(&self.storage, last_applied);
+        let (state, first_election_timeout) = Node::new(
+            self.server_id,
+            self.other_servers.clone(),
+            last_applied,
+            &self.storage,
+            &self.config,
+            &mut self.rng,
+            &*self.clock,
+        );
+        info!(
+            "{:?}: Starting raft node with state: {:?}, term: {:?}",
+            self.server_id,
+            node_role(&state),
+            self.storage.current_term(),
+        );
+        self.last_applied = last_applied;
+        self.max_wait_time = first_election_timeout.0;
+        self.waiting_since = self.clock.now();
+        state
+    }
 
-                let time_before_waiting = system_clock::now();
-                let maybe_next_message =
-                    transport_connector.wait_for_next_incoming_message(max_wait_time);
+    /// Waits for the next message, for as long as the node's timer allows, and hands the node
+    /// everything that came in meanwhile. Recovers from what it can, returns the error the node
+    /// halts with otherwise.
+    pub(crate) fn run_once(&mut self) -> Result<NodeStatus, RaftError> {
+        if self.follow_pause_requests() {
+            // Messages wait in the transport and the node's timers stand still until it's resumed
+            self.waiting_since = self.clock.now();
+            self.max_wait_time = PAUSED_POLL_INTERVAL;
+            if self.inbox.shutdown.try_recv().is_ok() {
+                return self.stop();
+            }
+            return Ok(NodeStatus::Paused);
+        }
+        let state = match self.state.take() {
+            Some(state) => state,
+            None => self.start(),
+        };
+        self.term_span
+            .follow(self.storage.current_term(), node_role(&state));
+        let term_span = self.term_span.span.clone();
+        let _term_span_guard = term_span.enter();
 
-                trace!(
-                    "Got next message: {:?} after waiting for {:?}, time is now {:?}",
-                    maybe_next_message,
-                    time_before_waiting.elapsed().as_millis(),
-                    start_time.elapsed().as_millis(),
+        let new_state = match self.handle_pending_events(state) {
+            Ok(new_state) => new_state,
+            Err(error) => {
+                // Whatever the failed event left behind is stale now
+                self.actions.clear();
+                for (_, outcome_tx) in self.membership_changes_in_flight.drain() {
+                    let _ = outcome_tx.send(Err(MembershipChangeError::LeadershipLost));
+                }
+                self.lease_reads_in_flight
+                    .reject_unanswered(ReadError::NotLeader(None));
+                // The node carries on as a follower, which is what a step down ends in
+                for (_, outcome_tx) in self.step_downs_in_flight.drain() {
+                    let _ = outcome_tx.send(Ok(()));
+                }
+                recover_from_error(
+                    error,
+                    &mut self.storage,
+                    self.config.storage_sync_retry,
+                    self.config.storage_full_policy,
+                )?;
+                let commit_index = self
+                    .last_published_state
+                    .map(|state| state.commit_index)
+                    .unwrap_or(LogIndex(0));
+                let (follower, election_timeout) = Node::stepped_down(
+                    self.server_id,
+                    self.other_servers.clone(),
+                    commit_index,
+                    self.application.last_applied_index(),
+                    &self.storage,
+                    &self.config,
+                    &mut self.rng,
+                    &*self.clock,
                 );
+                self.max_wait_time = election_timeout.0;
+                follower
+            }
+        };
 
-                let (mut new_state, mut tick_actions) = match state.next(
-                    Event::Tick(system_clock::now()),
-                    &mut storage,
-                    &config,
-                    &mut rng,
-                ) {
-                    Ok((new_state, actions)) => (new_state, actions),
-                    Err(_) => {
-                        info!("Persistent storage error, shutting down raft thread...");
-                        return;
-                    }
-                };
+        let current_state = state_snapshot(self.server_id, &new_state, &self.storage);
+        if self.last_published_state != Some(current_state) {
+            self.event_publisher.publish(
+                RaftEvent::StateChanged(current_state),
+                matches!(new_state, Node::Leader(_)),
+            );
+            self.last_published_state = Some(current_state);
+        }
+        self.diagnostics.publish(NodeDiagnostics {
+            state: current_state,
+            peers: new_state.other_servers(),
+            learners: new_state.learners(),
+            in_configuration: new_state.in_configuration(),
+            replication_progress: new_state.replication_progress(),
+            config: self.config.clone(),
+            pending_proposals: match new_state {
+                Node::Leader(_) => {
+                    (current_state.last_log_index.0 - current_state.commit_index.0) as usize
+                }
+                _ => 0,
+            },
+            elections: self.event_publisher.metrics.election_stats(),
+            updated_at: system_clock::now(),
+            started_at: self.start_time,
+        });
 
-                if let Err(_) = maybe_next_message {
-                    info!("Transport shutdown, shutting down raft thread...");
-                    return;
-                }
-
-                let mut actions_after_processing_message =
-                    if let Ok(Some(incoming_message)) = maybe_next_message {
-                        let actions;
-                        (new_state, actions) = match new_state.next(
-                            Event::IncomingRpc(incoming_message),
-                            &mut storage,
-                            &config,
-                            &mut rng,
-                        ) {
-                            Ok((new_state, actions)) => (new_state, actions),
-                            Err(_) => {
-                                info!("Persistent storage error, shutting down raft thread...");
-                                return;
-                            }
-                        };
-                        actions
-                    } else {
-                        vec![]
-                    };
-
-                max_wait_time = max_wait_time
-                    .checked_sub(time_before_waiting.elapsed())
-                    .unwrap_or(Duration::from_millis(0));
-
-                for action in tick_actions
-                    .drain(..)
-                    .chain(actions_after_processing_message.drain(..))
-                {
-                    match action {
-                        Action::OutgoingRpc(RpcMessage::Request(r)) => {
-                            if let Err(_) = transport_connector.enqueue_outgoing_request(r) {
-                                info!("Transport shutdown, shutting down raft thread...");
-                                return;
+        self.state = Some(new_state);
+        self.waiting_since = self.clock.now();
+
+        // Finishing the pass first leaves nothing the node already took in half done
+        if self.inbox.shutdown.try_recv().is_ok() {
+            return self.stop();
+        }
+        Ok(NodeStatus::Running)
+    }
+
+    /// Whether the node's handle paused it, as of its last pass
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused_since.is_some()
+    }
+
+    /// Takes the pause and resume requests made since the last pass, true while the node stays
+    /// paused. Pausing a paused node or resuming a running one changes nothing.
+    fn follow_pause_requests(&mut self) -> bool {
+        for request in self.inbox.pauses.try_iter() {
+            match (request, self.paused_since) {
+                (PauseRequest::Pause, None) => {
+                    info!("{:?}: Pausing raft node", self.server_id);
+                    self.paused_since = Some(self.clock.now());
+                }
+                (PauseRequest::Resume, Some(paused_since)) => {
+                    info!("{:?}: Resuming raft node", self.server_id);
+                    self.paused_since = None;
+                    self.paused_for += self.clock.now().saturating_duration_since(paused_since);
+                }
+                _ => {}
+            }
+        }
+        self.is_paused()
+    }
+
+    fn stop(&mut self) -> Result<NodeStatus, RaftError> {
+        self.storage.sync()?;
+        info!("{:?}: Shutting down raft node", self.server_id);
+        self.event_publisher.publish(
+            RaftEvent::NodeStopped {
+                server_id: self.server_id,
+            },
+            false,
+        );
+        Ok(NodeStatus::ShutDown)
+    }
+
+    fn handle_pending_events(&mut self, state: Node) -> Result<Node, RaftError> {
+        let wait_time = self.wait_time();
+        trace!(
+            "Waiting {:?}ms for next message at time {:?}...",
+            wait_time.as_millis(),
+            self.start_time.elapsed().as_millis(),
+        );
+
+        let time_before_waiting = system_clock::now();
+        let maybe_next_message = self
+            .transport_connector
+            .wait_for_next_incoming_message(wait_time);
+
+        trace!(
+            "Got next message: {:?} after waiting for {:?}, time is now {:?}",
+            maybe_next_message,
+            time_before_waiting.elapsed().as_millis(),
+            self.start_time.elapsed().as_millis(),
+        );
+
+        let mut new_state = state;
+        if !self.paused_for.is_zero() {
+            let paused_for = std::mem::take(&mut self.paused_for);
+            new_state = self.handle(new_state, Event::Resumed { paused_for })?;
+        }
+        let now = self.clock.now();
+        new_state = self.handle(new_state, Event::Tick(now))?;
+
+        if let Some(incoming_message) = maybe_next_message? {
+            self.event_publisher.metrics.record_rpc_received();
+            new_state = match incoming_message {
+                // Forwarded proposals are ours to answer, the node only sees them once appended
+                RpcMessage::Request(Request::ForwardProposals(request)) => {
+                    self.take_forwarded_proposals(new_state, request)?
+                }
+                RpcMessage::Reply(ReplyTo::ForwardProposals(ack)) => {
+                    self.follow_forwarded_proposals_ack(&new_state, ack)?;
+                    new_state
+                }
+                message => self.handle(new_state, Event::IncomingRpc(message))?,
+            };
+        }
+        // Commands applied while forwarded proposals wait for the leader's ack may be theirs
+        self.proposals_in_flight
+            .keep_unclaimed(self.forwards_in_flight.awaits_leader());
+
+        // Everything proposed since the last pass goes into the log as one batch
+        let proposals: Vec<Vec<Proposal<A>>> = self.inbox.proposals.try_iter().collect();
+        let proposals = self.hold_for_group_commit(&new_state, proposals);
+        let proposals = self.forward_proposals(&new_state, proposals)?;
+        if !proposals.is_empty() {
+            let commands = self.proposals_in_flight.track(
+                proposals,
+                &new_state,
+                &self.storage,
+                self.last_applied,
+                self.config.max_unapplied_entries,
+                self.rejects_proposals_for_full_storage(),
+            );
+            new_state = self.handle(new_state, Event::ClientProposals(commands))?;
+        }
+
+        let membership_changes: Vec<MembershipChangeRequest> =
+            self.inbox.membership_changes.try_iter().collect();
+        for MembershipChangeRequest { change, outcome_tx } in membership_changes {
+            let id = Uuid::new_v4();
+            let _ = self.membership_changes_in_flight.insert(id, outcome_tx);
+            new_state = self.handle(new_state, Event::MembershipChangeRequested { id, change })?;
+        }
+
+        let step_downs: Vec<StepDownRequest> = self.inbox.step_downs.try_iter().collect();
+        for StepDownRequest {
+            transfer_to,
+            outcome_tx,
+        } in step_downs
+        {
+            let id = Uuid::new_v4();
+            let _ = self.step_downs_in_flight.insert(id, outcome_tx);
+            new_state = self.handle(new_state, Event::StepDownRequested { id, transfer_to })?;
+        }
+
+        let reads: Vec<LeaseRead<A>> = self.inbox.lease_reads.try_iter().collect();
+        for read in reads {
+            let id = self.lease_reads_in_flight.track(read);
+            new_state = self.handle(new_state, Event::LeaseReadRequested { id })?;
+        }
+
+        // Served off the application whatever the node's role, once it caught up
+        for ReadAfter { index, read } in self.inbox.reads_after.try_iter() {
+            self.lease_reads_in_flight.wait_for_apply(index, read);
+        }
+
+        // The node reads its config with every event, the update takes effect as timers are reset
+        for TimingUpdateRequest { update, outcome_tx } in self.inbox.timing_updates.try_iter() {
+            let outcome = match self.config.with_timing(update) {
+                Ok(config) => {
+                    info!("{:?}: Updating timing to {:?}", self.server_id, update);
+                    self.config = config;
+                    Ok(())
+                }
+                Err(error) => Err(TimingUpdateError::InvalidConfig(error)),
+            };
+            // The caller may have stopped waiting
+            let _ = outcome_tx.send(outcome);
+        }
+
+        for IndexWait { index, applied_tx } in self.inbox.index_waits.try_iter() {
+            self.index_waits.entry(index).or_default().push(applied_tx);
+        }
+
+        self.applied_subscribers
+            .extend(self.inbox.applied_subscriptions.try_iter());
+
+        self.max_wait_time = self
+            .max_wait_time
+            .checked_sub(
+                self.clock
+                    .now()
+                    .saturating_duration_since(self.waiting_since),
+            )
+            .unwrap_or(Duration::from_millis(0));
+
+        // Syncing the log, applying entries and taking snapshots hand events back to the node,
+        // whose actions take another pass
+        loop {
+            let mut synced_through = None;
+            let mut applied_through = None;
+            let mut snapshot_taken = None;
+            let mut actions = std::mem::take(&mut self.actions);
+            for action in actions.drain(..) {
+                match action {
+                    Action::OutgoingRpc(RpcMessage::Request(r)) => {
+                        self.send_request(r)?;
+                    }
+                    Action::OutgoingRpc(RpcMessage::Reply(message)) => {
+                        self.send_reply(message)?;
+                    }
+                    Action::SetNextTimeout(timer_duration) => {
+                        trace!("Resetting wait timeout to duration {:?}", timer_duration);
+                        self.max_wait_time = timer_duration;
+                    }
+                    Action::PublishEvent(event) => self
+                        .event_publisher
+                        .publish(event, matches!(new_state, Node::Leader(_))),
+                    Action::ApplyLogEntries(entries) => {
+                        let started_at = system_clock::now();
+                        for LogEntry {
+                            index,
+                            term,
+                            command,
+                        } in entries
+                        {
+                            match command {
+                                LogEntryCommand::Application(command) => {
+                                    let published = self.published_copy(&command);
+                                    let result = self.application.apply(index, command);
+                                    self.proposals_in_flight.resolve(
+                                        index,
+                                        term,
+                                        Ok(Applied { index, result }),
+                                    );
+                                    self.publish_applied(index, term, published);
+                                }
+                                LogEntryCommand::SessionCommand(session, command) => {
+                                    let published = self.published_copy(&command);
+                                    let application = &mut self.application;
+                                    let outcome = self
+                                        .client_sessions
+                                        .apply(session, || application.apply(index, command))
+                                        .map(|result| Applied { index, result });
+                                    self.proposals_in_flight.resolve(index, term, outcome);
+                                    self.publish_applied(index, term, published);
+                                }
+                                // Configuration and no-op entries are the node's own business
+                                LogEntryCommand::MembershipChange(_) | LogEntryCommand::NoOp => {
+                                    self.proposals_in_flight.supersede(index, term);
+                                }
                             }
+                            applied_through = Some(index);
+                            self.last_applied = index;
                         }
-                        Action::OutgoingRpc(RpcMessage::Reply(message)) => {
-                            if let Err(_) = transport_connector.enqueue_reply(message) {
-                                info!("Transport shutdown, shutting down raft thread...");
-                                return;
-                            }
+                        warn_if_slow(
+                            "apply",
+                            started_at.elapsed(),
+                            self.config.slow_operation_thresholds.apply,
+                        );
+                    }
+                    Action::SyncLog(index) => {
+                        self.storage.sync()?;
+                        synced_through = Some(index);
+                    }
+                    Action::TakeSnapshot {
+                        last_included_index,
+                        last_included_term,
+                        members,
+                        learners,
+                    } => {
+                        snapshot_taken = Some(Snapshot {
+                            last_included_index,
+                            last_included_term,
+                            members,
+                            learners,
+                            sessions: self.client_sessions.sequences(),
+                            data: self.application.snapshot(),
+                        });
+                    }
+                    Action::RestoreSnapshot(snapshot) => {
+                        self.application.restore_snapshot(&snapshot);
+                        self.client_sessions.restore(&snapshot.sessions);
+                        self.last_applied = snapshot.last_included_index;
+                    }
+                    Action::MembershipChangeFinished { id, outcome } => {
+                        if let Some(outcome_tx) = self.membership_changes_in_flight.remove(&id) {
+                            // The operator may have stopped waiting
+                            let _ = outcome_tx.send(outcome);
                         }
-                        Action::SetNextTimeout(timer_duration) => {
-                            trace!("Resetting wait timeout to duration {:?}", timer_duration);
-                            max_wait_time = timer_duration;
+                    }
+                    Action::StepDownFinished { id, outcome } => {
+                        if let Some(outcome_tx) = self.step_downs_in_flight.remove(&id) {
+                            let _ = outcome_tx.send(outcome);
                         }
-                        Action::ApplyLogEntries(_) => todo!(),
+                    }
+                    Action::LeaseReadFinished { id, outcome } => {
+                        self.lease_reads_in_flight.finish(
+                            id,
+                            outcome,
+                            &self.application,
+                            self.last_applied,
+                        );
                     }
                 }
+            }
+            // Hand the emptied buffer back so its allocation is kept
+            self.actions = actions;
+            self.lease_reads_in_flight
+                .serve_applied(&self.application, self.last_applied);
+            self.resolve_index_waits();
+            if synced_through.is_none() && applied_through.is_none() && snapshot_taken.is_none() {
+                break;
+            }
+            if let Some(index) = synced_through {
+                new_state = self.handle(new_state, Event::LogSynced(index))?;
+            }
+            if let Some(index) = applied_through {
+                new_state = self.handle(new_state, Event::LogEntryAppliedByApplication(index))?;
+            }
+            if let Some(snapshot) = snapshot_taken {
+                new_state = self.handle(new_state, Event::SnapshotTaken(snapshot))?;
+            }
+        }
 
-                event_collector.push_event(RaftStateEvent {
-                    server_id,
-                    current_state: match new_state {
-                        Node::Follower(_) => RaftNodeState::Follower,
-                        Node::Candidate(_) => RaftNodeState::Candidate,
-                        Node::Leader(_) => RaftNodeState::Leader,
-                    },
-                    current_term: storage.current_term(),
-                    voted_for: storage.vote_for_current_term(),
-                    leader_for_term: match &new_state {
-                        Node::Leader(_) => Some(server_id),
-                        Node::Follower(follower) => follower.inner.leader_id,
-                        _ => None,
-                    },
+        // Once the pass synced everything, so the copy holds what the node acknowledged
+        for BackupRequest { path, outcome_tx } in self.inbox.backups.try_iter() {
+            info!("{:?}: Backing up storage to {:?}", self.server_id, path);
+            let outcome = self.storage.backup_to(&path).map_err(BackupError::Storage);
+            // The caller may have stopped waiting
+            let _ = outcome_tx.send(outcome);
+        }
+        Ok(new_state)
+    }
+
+    /// Holds `batches` back on a leader with [`RaftConfig::group_commit`] on, until the window
+    /// the first of them opened closes or enough commands wait. Returns what the node takes now,
+    /// everything it held once it's no longer leading.
+    fn hold_for_group_commit(
+        &mut self,
+        node: &Node,
+        batches: Vec<Vec<Proposal<A>>>,
+    ) -> Vec<Vec<Proposal<A>>> {
+        let Some(group_commit) = self.config.group_commit else {
+            return batches;
+        };
+        self.held_proposals.extend(batches);
+        if self.held_proposals.is_empty() {
+            return Vec::new();
+        }
+        let now = self.clock.now();
+        let held_since = *self.held_since.get_or_insert(now);
+        let held_entries: usize = self.held_proposals.iter().map(Vec::len).sum();
+        if !matches!(node, Node::Leader(_))
+            || held_entries >= group_commit.max_entries
+            || now >= held_since + group_commit.window
+        {
+            self.held_since = None;
+            return std::mem::take(&mut self.held_proposals);
+        }
+        Vec::new()
+    }
+
+    /// Hands `batches` on to the leader the node knows of, when forwarding is on and it isn't
+    /// leading itself. Returns the batches it kept, for the node to take or turn down.
+    fn forward_proposals(
+        &mut self,
+        node: &Node,
+        batches: Vec<Vec<Proposal<A>>>,
+    ) -> Result<Vec<Vec<Proposal<A>>>, RaftError> {
+        let leader = match node.leader_id() {
+            Some(leader)
+                if self.config.max_forwarding_hops.0 > 0
+                    && !node.accepts_proposals()
+                    && leader != self.server_id =>
+            {
+                leader
+            }
+            _ => return Ok(batches),
+        };
+        for batch in batches {
+            let (proposals, outcome_txs) = batch
+                .into_iter()
+                .map(|proposal| {
+                    (
+                        ClientProposal {
+                            command: proposal.command,
+                            session: proposal.session,
+                        },
+                        proposal.outcome_tx,
+                    )
+                })
+                .unzip();
+            let request_id = Uuid::new_v4();
+            self.forwards_in_flight
+                .track(request_id, Forwarded::Proposed(outcome_txs));
+            self.send_request(Request::ForwardProposals(ForwardProposals {
+                request_id,
+                from: self.server_id,
+                to: leader,
+                term: self.storage.current_term(),
+                proposals,
+                hops: 1,
+            }))?;
+        }
+        Ok(Vec::new())
+    }
+
+    /// Hands `request` to the transport, counting it as sent or dropped
+    fn send_request(&mut self, request: Request<LC>) -> Result<(), RaftTransportError> {
+        let sent = self.transport_connector.enqueue_outgoing_request(request);
+        self.event_publisher.record_rpc(&sent);
+        sent
+    }
+
+    /// Hands `reply` to the transport, counting it as sent or dropped
+    fn send_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        let sent = self.transport_connector.enqueue_reply(reply);
+        self.event_publisher.record_rpc(&sent);
+        sent
+    }
+
+    /// Appends proposals another server forwarded when leading, passes them on to the leader
+    /// while there are hops left otherwise, and lets the sender know which it was
+    fn take_forwarded_proposals(
+        &mut self,
+        node: Node,
+        request: ForwardProposals<LC>,
+    ) -> Result<Node, RaftError> {
+        let term = self.storage.current_term();
+        let last_index = self.storage.last_entry_index().unwrap_or(LogIndex(0));
+        let unapplied =
+            last_index.0.saturating_sub(self.last_applied.0) + request.proposals.len() as u64;
+        let (node, outcome) = if !node.accepts_proposals() {
+            match node.leader_id() {
+                Some(leader)
+                    if request.hops < self.config.max_forwarding_hops.0
+                        && leader != self.server_id
+                        && leader != request.from =>
+                {
+                    let request_id = Uuid::new_v4();
+                    self.forwards_in_flight.track(
+                        request_id,
+                        Forwarded::Relayed {
+                            to: request.from,
+                            request_id: request.request_id,
+                        },
+                    );
+                    self.send_request(Request::ForwardProposals(ForwardProposals {
+                        request_id,
+                        from: self.server_id,
+                        to: leader,
+                        term,
+                        proposals: request.proposals,
+                        hops: request.hops + 1,
+                    }))?;
+                    return Ok(node);
+                }
+                _ => (node, ForwardedProposalsOutcome::NotLeader),
+            }
+        } else if unapplied > self.config.max_unapplied_entries.0
+            // The ack has no room for why, the follower only learns it should retry later
+            || self.rejects_proposals_for_full_storage()
+        {
+            (node, ForwardedProposalsOutcome::Busy)
+        } else {
+            let node = self.handle(node, Event::ClientProposals(request.proposals))?;
+            let first_index = last_index.next();
+            (
+                node,
+                ForwardedProposalsOutcome::Appended { first_index, term },
+            )
+        };
+        self.send_reply(ReplyTo::ForwardProposals(ForwardProposalsAck {
+            request_id: request.request_id,
+            from: self.server_id,
+            to: request.from,
+            term,
+            outcome,
+        }))?;
+        Ok(node)
+    }
+
+    /// Follows the proposals the leader appended to the log like the node's own, or hands the
+    /// ack back to the server the proposals were relayed for
+    fn follow_forwarded_proposals_ack(
+        &mut self,
+        node: &Node,
+        ack: ForwardProposalsAck,
+    ) -> Result<(), RaftError> {
+        match self.forwards_in_flight.finish(ack.request_id) {
+            // Not one of ours, or already answered
+            None => {}
+            Some(Forwarded::Proposed(outcome_txs)) => match ack.outcome {
+                ForwardedProposalsOutcome::Appended { first_index, term } => {
+                    self.proposals_in_flight.track_appended_by_leader(
+                        first_index,
+                        term,
+                        outcome_txs,
+                        self.last_applied,
+                    );
+                }
+                ForwardedProposalsOutcome::NotLeader => {
+                    for outcome_tx in outcome_txs {
+                        let _ = outcome_tx.send(Err(ProposalError::NotLeader(node.leader_id())));
+                    }
+                }
+                ForwardedProposalsOutcome::Busy => {
+                    for outcome_tx in outcome_txs {
+                        let _ = outcome_tx.send(Err(ProposalError::Busy));
+                    }
+                }
+            },
+            Some(Forwarded::Relayed { to, request_id }) => {
+                self.send_reply(ReplyTo::ForwardProposals(ForwardProposalsAck {
+                    request_id,
+                    from: self.server_id,
+                    to,
+                    term: self.storage.current_term(),
+                    outcome: ack.outcome,
+                }))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether storage ran full and the node was told to turn proposals away until it has room
+    fn rejects_proposals_for_full_storage(&self) -> bool {
+        self.config.storage_full_policy == StorageFullPolicy::RejectProposals
+            && self.storage.is_full()
+    }
+
+    /// A copy of `command` for the subscribers to applied entries, if there are any
+    fn published_copy(&self, command: &LC) -> Option<LC> {
+        (!self.applied_subscribers.is_empty()).then(|| command.clone())
+    }
+
+    /// Hands the command applied at `index` to every subscriber still listening
+    fn publish_applied(&mut self, index: LogIndex, term: TermIndex, command: Option<LC>) {
+        if let Some(command) = command {
+            self.applied_subscribers
+                .retain(|applied_tx| applied_tx.send((index, term, command.clone())).is_ok());
+        }
+    }
+
+    /// Lets everyone waiting for an index the node applied by now know it did
+    fn resolve_index_waits(&mut self) {
+        let still_waiting = self.index_waits.split_off(&self.last_applied.next());
+        for applied_tx in std::mem::replace(&mut self.index_waits, still_waiting)
+            .into_values()
+            .flatten()
+        {
+            // The waiter may have stopped waiting
+            let _ = applied_tx.send(Ok(()));
+        }
+    }
+
+    fn handle(&mut self, state: Node, event: Event<LC>) -> Result<Node, RaftError> {
+        handle_event_timed(
+            state,
+            event,
+            &mut self.storage,
+            &self.config,
+            &mut self.rng,
+            &*self.clock,
+            &mut self.actions,
+        )
+    }
+
+    /// Tells observers the node halted with `error`
+    pub(crate) fn publish_halted(&mut self, error: RaftError) {
+        self.event_publisher.publish(
+            RaftEvent::NodeHalted {
+                server_id: self.server_id,
+                error,
+            },
+            false,
+        );
+    }
+
+    /// Tells observers the node crashed with `panic`
+    pub(crate) fn publish_crashed(&mut self, panic: &(dyn Any + Send)) {
+        self.event_publisher.publish(
+            RaftEvent::NodeCrashed {
+                server_id: self.server_id,
+                message: crash_reporting::panic_message(panic),
+                location: crash_reporting::take_panic_location(),
+            },
+            false,
+        );
+    }
+}
+
+/// Every event the Raft thread publishes goes through here on its way to the event collector
+pub(crate) struct EventPublisher<E: RaftStateEventCollector> {
+    event_collector: E,
+    metrics: RaftMetrics,
+    metrics_recorder: EventMetricsRecorder,
+    recent_events: RecentEvents,
+    audit_log: Option<AuditLog>,
+}
+impl<E: RaftStateEventCollector> EventPublisher<E> {
+    /// Publishes to `event_collector` and `recent_events`, auditing into `audit_log_dir` if there is one
+    pub(crate) fn new(
+        event_collector: E,
+        metrics: RaftMetrics,
+        recent_events: RecentEvents,
+        audit_log_dir: Option<&str>,
+    ) -> Self {
+        EventPublisher {
+            event_collector,
+            metrics,
+            metrics_recorder: EventMetricsRecorder::default(),
+            recent_events,
+            audit_log: audit_log_dir
+                .and_then(|dir| AuditLog::open(&Path::new(dir).join("audit.log"))),
+        }
+    }
+
+    fn record_rpc(&self, sent: &Result<(), RaftTransportError>) {
+        match sent {
+            Ok(()) => self.metrics.record_rpc_sent(),
+            Err(_) => self.metrics.record_rpc_dropped(),
+        }
+    }
+
+    fn publish(&mut self, event: RaftEvent, is_leader: bool) {
+        self.metrics_recorder
+            .observe_event(&event, is_leader, &self.metrics);
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            audit_log.observe_event(&event);
+        }
+        self.recent_events.push(event.clone());
+        self.event_collector.push_event(event);
+    }
+}
+
+/// A command on its way from [`RaftNodeHandle::propose`] to the Raft thread
+#[derive(Debug)]
+struct Proposal<A: ApplicationThatNeedsConsensus> {
+    command: A::Command,
+    session: Option<ClientSession>,
+    outcome_tx: mpsc::Sender<ProposalOutcome<A>>,
+}
+
+/// Commands a leader appended for [`RaftNodeHandle::propose`] callers, by the index they were
+/// appended at, until the entry at that index is applied
+struct ProposalsInFlight<A: ApplicationThatNeedsConsensus> {
+    by_index: HashMap<LogIndex, (TermIndex, mpsc::Sender<ProposalOutcome<A>>)>,
+    /// Outcomes of entries applied with nobody waiting on them, kept while commands forwarded to
+    /// the leader wait for its ack, which may still claim them
+    unclaimed: Option<BTreeMap<LogIndex, (TermIndex, ProposalOutcome<A>)>>,
+}
+impl<A: ApplicationThatNeedsConsensus> ProposalsInFlight<A> {
+    fn new() -> Self {
+        ProposalsInFlight {
+            by_index: HashMap::new(),
+            unclaimed: None,
+        }
+    }
+
+    /// Starts keeping the outcomes nobody waits on, or stops and drops the ones kept
+    fn keep_unclaimed(&mut self, keep: bool) {
+        match (keep, &self.unclaimed) {
+            (true, None) => self.unclaimed = Some(BTreeMap::new()),
+            (false, Some(_)) => self.unclaimed = None,
+            _ => {}
+        }
+    }
+
+    /// Remembers where a leader is about to append `batches`, turns them down on any other node
+    /// and on a leader handing leadership over. A batch that would take the leader's log more than
+    /// `max_unapplied` entries past `last_applied`, or any batch while `storage_full`, is turned
+    /// down as a whole and left out of what it's handed, so every batch it appends lands at
+    /// consecutive indexes.
+    fn track(
+        &mut self,
+        batches: Vec<Vec<Proposal<A>>>,
+        node: &Node,
+        storage: &impl PersistentStorage<A::Command>,
+        last_applied: LogIndex,
+        max_unapplied: MaxUnappliedEntries,
+        storage_full: bool,
+    ) -> Vec<ClientProposal<A::Command>> {
+        let accepts = node.accepts_proposals();
+        // A leader handing leadership over doesn't know who takes it yet
+        let leader_hint = match node {
+            Node::Leader(_) => None,
+            node => node.leader_id(),
+        };
+        let term = storage.current_term();
+        let mut index = storage.last_entry_index().unwrap_or(LogIndex(0));
+        let mut commands = Vec::new();
+        for batch in batches {
+            if accepts && storage_full {
+                for proposal in batch {
+                    let _ = proposal.outcome_tx.send(Err(ProposalError::StorageFull));
+                }
+                continue;
+            }
+            let unapplied = index.0.saturating_sub(last_applied.0) + batch.len() as u64;
+            if accepts && unapplied > max_unapplied.0 {
+                for proposal in batch {
+                    let _ = proposal.outcome_tx.send(Err(ProposalError::Busy));
+                }
+                continue;
+            }
+            for proposal in batch {
+                if accepts {
+                    index = index.next();
+                    // An earlier proposal at this index was cut from the log before it committed
+                    if let Some((_, superseded)) =
+                        self.by_index.insert(index, (term, proposal.outcome_tx))
+                    {
+                        let _ = superseded.send(Err(ProposalError::Superseded));
+                    }
+                } else {
+                    let _ = proposal
+                        .outcome_tx
+                        .send(Err(ProposalError::NotLeader(leader_hint)));
+                }
+                commands.push(ClientProposal {
+                    command: proposal.command,
+                    session: proposal.session,
                 });
+            }
+        }
+        commands
+    }
+
+    /// Remembers where the leader appended the commands another server forwarded for
+    /// `outcome_txs`, in order from `first_index`. Those the node applied already get the outcome
+    /// it kept for their index, [`ProposalError::ResponseDiscarded`] if it restored them from a
+    /// snapshot instead.
+    fn track_appended_by_leader(
+        &mut self,
+        first_index: LogIndex,
+        term: TermIndex,
+        outcome_txs: Vec<mpsc::Sender<ProposalOutcome<A>>>,
+        last_applied: LogIndex,
+    ) {
+        let indexes = std::iter::successors(Some(first_index), |index| Some(index.next()));
+        for (index, outcome_tx) in indexes.zip(outcome_txs) {
+            if index <= last_applied {
+                let outcome = match self
+                    .unclaimed
+                    .as_mut()
+                    .and_then(|unclaimed| unclaimed.remove(&index))
+                {
+                    Some((applied_in_term, outcome)) if applied_in_term == term => outcome,
+                    Some(_) => Err(ProposalError::Superseded),
+                    None => Err(ProposalError::ResponseDiscarded),
+                };
+                let _ = outcome_tx.send(outcome);
+            } else if let Some((_, superseded)) = self.by_index.insert(index, (term, outcome_tx)) {
+                let _ = superseded.send(Err(ProposalError::Superseded));
+            }
+        }
+    }
+
+    /// Turns down whoever proposed the command at `index`, another leader put a configuration entry there
+    fn supersede(&mut self, index: LogIndex, term: TermIndex) {
+        self.resolve(index, term, Err(ProposalError::Superseded));
+    }
+
+    /// Hands the outcome of applying the entry at `index` to whoever proposed it, or keeps it for
+    /// an ack that may still claim it. Entries are identified by index and term, another term
+    /// means another leader's entry took its place.
+    fn resolve(&mut self, index: LogIndex, term: TermIndex, outcome: ProposalOutcome<A>) {
+        match self.by_index.remove(&index) {
+            Some((proposed_in_term, outcome_tx)) => {
+                let outcome = if proposed_in_term == term {
+                    outcome
+                } else {
+                    Err(ProposalError::Superseded)
+                };
+                // The proposer may have stopped waiting
+                let _ = outcome_tx.send(outcome);
+            }
+            None => {
+                if let Some(unclaimed) = self.unclaimed.as_mut() {
+                    let _ = unclaimed.insert(index, (term, outcome));
+                }
+            }
+        }
+    }
+}
+
+/// Who proposals forwarded towards the leader are waiting on an ack for
+enum Forwarded<A: ApplicationThatNeedsConsensus> {
+    /// Proposed on this node, in the order they were appended
+    Proposed(Vec<mpsc::Sender<ProposalOutcome<A>>>),
+    /// Relayed for another server, the ack goes back to it as the reply to its own request
+    Relayed { to: ServerId, request_id: Uuid },
+}
+
+/// Proposals forwarded towards the leader, by the id of the request carrying them, until they're
+/// acked. Every server a forward reaches acks it, with where the leader appended the proposals or
+/// why it didn't, so they wait for the ack however long it takes: the leader may have appended
+/// them, giving up would have proposers retry a command that gets applied. Proposers that won't
+/// wait that long use [`Pending::wait_timeout`].
+struct ForwardsInFlight<A: ApplicationThatNeedsConsensus> {
+    by_request: HashMap<Uuid, Forwarded<A>>,
+}
+impl<A: ApplicationThatNeedsConsensus> ForwardsInFlight<A> {
+    fn new() -> Self {
+        ForwardsInFlight {
+            by_request: HashMap::new(),
+        }
+    }
+
+    fn track(&mut self, request_id: Uuid, forwarded: Forwarded<A>) {
+        let _ = self.by_request.insert(request_id, forwarded);
+    }
+
+    fn finish(&mut self, request_id: Uuid) -> Option<Forwarded<A>> {
+        self.by_request.remove(&request_id)
+    }
+
+    /// Whether proposals made on this node wait for the leader to say where it appended them
+    fn awaits_leader(&self) -> bool {
+        self.by_request
+            .values()
+            .any(|forwarded| matches!(forwarded, Forwarded::Proposed(_)))
+    }
+}
+
+/// A request handed to a [`RaftNodeHandle`], resolved once the Raft thread carried it out or gave
+/// up on it. The outcome is handed out once, [`NodeStopped`] if the thread exited without one.
+#[derive(Debug)]
+pub struct Pending<T, E: From<NodeStopped>> {
+    outcome_rx: mpsc::Receiver<Result<T, E>>,
+}
+impl<T, E: From<NodeStopped>> Pending<T, E> {
+    fn new(outcome_rx: mpsc::Receiver<Result<T, E>>) -> Self {
+        Pending { outcome_rx }
+    }
+
+    /// Blocks until the request was carried out or given up on
+    pub fn wait(self) -> Result<T, E> {
+        self.outcome_rx
+            .recv()
+            .unwrap_or_else(|_| Err(E::from(NodeStopped)))
+    }
+
+    /// Like [`Pending::wait`], `None` if there is no outcome after `timeout`
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Result<T, E>> {
+        match self.outcome_rx.recv_timeout(timeout) {
+            Ok(outcome) => Some(outcome),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(Err(E::from(NodeStopped))),
+        }
+    }
+
+    /// The outcome if there is one yet, without blocking
+    pub fn try_outcome(&self) -> Option<Result<T, E>> {
+        match self.outcome_rx.try_recv() {
+            Ok(outcome) => Some(outcome),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(E::from(NodeStopped))),
+        }
+    }
+}
 
-                state = new_state;
+/// A command handed to [`RaftNodeHandle::propose`], resolved once the node applied it
+pub type PendingProposal<A> = Pending<Applied<ApplyResult<A>>, ProposalError>;
+/// A membership change resolved once its configuration entry committed
+pub type PendingMembershipChange = Pending<LogIndex, MembershipChangeError>;
+/// A step down resolved once the leader gave up leadership
+pub type PendingStepDown = Pending<(), StepDownError>;
+/// A timing update resolved once the node took it
+pub type PendingTimingUpdate = Pending<(), TimingUpdateError>;
+/// A backup resolved with the index of the last entry copied once the node made it
+pub type PendingBackup = Pending<LogIndex, BackupError>;
+/// A read handed to [`RaftNodeHandle::read`], resolved with what it returned once it was served
+pub type PendingRead<R> = Pending<R, ReadError>;
+/// A log index handed to [`RaftNodeHandle::wait_for_index`], resolved once the node applied it
+pub type PendingIndex = Pending<(), IndexWaitError>;
+
+/// A change handed to [`RaftNodeHandle::add_server`] or [`RaftNodeHandle::remove_server`] on its way to the Raft thread
+#[derive(Debug)]
+struct MembershipChangeRequest {
+    change: MembershipChange,
+    outcome_tx: mpsc::Sender<MembershipChangeOutcome>,
+}
+
+/// A step down handed to [`RaftNodeHandle::step_down`] on its way to the Raft thread
+#[derive(Debug)]
+struct StepDownRequest {
+    transfer_to: Option<ServerId>,
+    outcome_tx: mpsc::Sender<StepDownOutcome>,
+}
+
+/// A timing update handed to [`RaftNodeHandle::update_timing`] on its way to the Raft thread
+#[derive(Debug)]
+struct TimingUpdateRequest {
+    update: TimingUpdate,
+    outcome_tx: mpsc::Sender<Result<(), TimingUpdateError>>,
+}
+
+/// A backup handed to [`RaftNodeHandle::backup_to`] on its way to the Raft thread
+#[derive(Debug)]
+struct BackupRequest {
+    path: PathBuf,
+    outcome_tx: mpsc::Sender<Result<LogIndex, BackupError>>,
+}
+
+/// Runs a read against the application, or tells the reader why it couldn't
+type ServeRead<A> = Box<dyn FnOnce(Result<&A, ReadError>) + Send>;
+
+/// A read handed to [`RaftNodeHandle::read`] on its way to the Raft thread, called with the
+/// application once the read can be served or with the reason it can't
+struct LeaseRead<A: ApplicationThatNeedsConsensus> {
+    serve: ServeRead<A>,
+}
+impl<A: ApplicationThatNeedsConsensus> Debug for LeaseRead<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LeaseRead").finish_non_exhaustive()
+    }
+}
+
+/// A read handed to [`RaftNodeHandle::read_after`], served once the node applied `index`
+#[derive(Debug)]
+struct ReadAfter<A: ApplicationThatNeedsConsensus> {
+    index: LogIndex,
+    read: LeaseRead<A>,
+}
+
+/// Reads waiting for the node to check the lease, then for the application to catch up with
+/// the index the read has to see, the commit index or the one handed to
+/// [`RaftNodeHandle::read_after`]
+struct LeaseReadsInFlight<A: ApplicationThatNeedsConsensus> {
+    unanswered: HashMap<Uuid, LeaseRead<A>>,
+    waiting_for_apply: Vec<(LogIndex, LeaseRead<A>)>,
+}
+impl<A: ApplicationThatNeedsConsensus> LeaseReadsInFlight<A> {
+    fn new() -> Self {
+        LeaseReadsInFlight {
+            unanswered: HashMap::new(),
+            waiting_for_apply: Vec::new(),
+        }
+    }
+
+    /// Holds on to `read` until the node answers the id handed to it
+    fn track(&mut self, read: LeaseRead<A>) -> Uuid {
+        let id = Uuid::new_v4();
+        let _ = self.unanswered.insert(id, read);
+        id
+    }
+
+    /// Serves the read right away if the node applied the entries it has to see, turns it down if the
+    /// node did
+    fn finish(
+        &mut self,
+        id: Uuid,
+        outcome: LeaseReadOutcome,
+        application: &A,
+        last_applied: LogIndex,
+    ) {
+        let Some(read) = self.unanswered.remove(&id) else {
+            return;
+        };
+        match outcome {
+            Ok(read_index) if last_applied >= read_index => (read.serve)(Ok(application)),
+            Ok(read_index) => self.waiting_for_apply.push((read_index, read)),
+            Err(error) => (read.serve)(Err(error)),
+        }
+    }
+
+    /// Holds `read` back until the node applied `index`, served along with the lease reads
+    fn wait_for_apply(&mut self, index: LogIndex, read: LeaseRead<A>) {
+        self.waiting_for_apply.push((index, read));
+    }
+
+    /// Serves the reads whose commit index the node has applied by now. That includes configuration
+    /// and no-op entries, which the application's own last applied index doesn't count.
+    fn serve_applied(&mut self, application: &A, last_applied: LogIndex) {
+        let (ready, waiting) = std::mem::take(&mut self.waiting_for_apply)
+            .into_iter()
+            .partition(|(read_index, _)| *read_index <= last_applied);
+        self.waiting_for_apply = waiting;
+        for (_, read) in ready {
+            (read.serve)(Ok(application));
+        }
+    }
+
+    /// Turns down the reads the node won't answer anymore, a read whose lease was checked still
+    /// sees everything it has to once it's served
+    fn reject_unanswered(&mut self, error: ReadError) {
+        for (_, read) in self.unanswered.drain() {
+            (read.serve)(Err(error));
+        }
+    }
+}
+
+/// A wait handed to [`RaftNodeHandle::wait_for_index`] on its way to the Raft thread
+#[derive(Debug)]
+struct IndexWait {
+    index: LogIndex,
+    applied_tx: mpsc::Sender<Result<(), IndexWaitError>>,
+}
+
+/// Handed to the Raft thread by [`RaftNodeHandle::pause`] and [`RaftNodeHandle::resume`]
+#[derive(Debug, Clone, Copy)]
+enum PauseRequest {
+    Pause,
+    Resume,
+}
+
+/// Where a node runs, on its own thread or on a thread it shares with others
+#[derive(Debug)]
+pub(crate) enum NodeRuntime {
+    Thread(thread::JoinHandle<Result<(), RaftError>>),
+    Hosted(HostedRuntime),
+}
+impl NodeRuntime {
+    /// Like [`RaftNodeHandle::join`], `None` if the node is still running after `timeout`
+    fn join_timeout(self, timeout: Duration) -> Option<thread::Result<Result<(), RaftError>>> {
+        match self {
+            NodeRuntime::Thread(thread_handle) => {
+                let deadline = std::time::Instant::now() + timeout;
+                while !thread_handle.is_finished() {
+                    if std::time::Instant::now() >= deadline {
+                        return None;
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Some(thread_handle.join())
             }
+            NodeRuntime::Hosted(hosted) => hosted.join_timeout(timeout),
+        }
+    }
+}
+
+/// How a node that shares its thread ended, `Err` if it panicked
+type HostedOutcome = thread::Result<Result<(), RaftError>>;
+
+/// A node sharing its thread, a worker of a [`crate::RaftGroupManager`] or one of an async
+/// runtime, as its [`RaftNodeHandle`] sees it
+#[derive(Debug)]
+pub(crate) struct HostedRuntime {
+    thread: thread::Thread,
+    finished: Arc<AtomicBool>,
+    outcome_rx: Mutex<mpsc::Receiver<HostedOutcome>>,
+}
+impl HostedRuntime {
+    fn thread(&self) -> &thread::Thread {
+        &self.thread
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    fn join_timeout(self, timeout: Duration) -> Option<HostedOutcome> {
+        let outcome_rx = self
+            .outcome_rx
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match outcome_rx.recv_timeout(timeout) {
+            Ok(outcome) => Some(outcome),
+            Err(RecvTimeoutError::Timeout) => None,
+            // Dropped without an outcome, the node went away with whatever ran it
+            Err(RecvTimeoutError::Disconnected) => Some(Ok(Ok(()))),
+        }
+    }
+
+    fn join(self) -> HostedOutcome {
+        let outcome_rx = self
+            .outcome_rx
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        outcome_rx.recv().unwrap_or(Ok(Ok(())))
+    }
+}
+
+/// What a node sharing its thread reports how it ended through. Dropping it marks the node
+/// finished, whether or not it reported anything.
+pub(crate) struct HostedExit {
+    finished: Arc<AtomicBool>,
+    outcome_tx: mpsc::Sender<HostedOutcome>,
+}
+impl HostedExit {
+    pub(crate) fn send(&self, outcome: HostedOutcome) {
+        // The handle may be gone
+        let _ = self.outcome_tx.send(outcome);
+    }
+}
+impl Drop for HostedExit {
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::Release);
+    }
+}
+
+/// The runtime for the handle of a node that runs on `thread` with others, and where the node
+/// reports how it ended
+pub(crate) fn hosted_runtime(thread: thread::Thread) -> (HostedRuntime, HostedExit) {
+    let (outcome_tx, outcome_rx) = mpsc::channel();
+    let finished = Arc::new(AtomicBool::new(false));
+    (
+        HostedRuntime {
+            thread,
+            finished: finished.clone(),
+            outcome_rx: Mutex::new(outcome_rx),
+        },
+        HostedExit {
+            finished,
+            outcome_tx,
+        },
+    )
+}
+
+/// Handle to a Raft node running in its own thread, or hosted by a [`crate::RaftGroupManager`]
+#[derive(Debug)]
+pub struct RaftNodeHandle<A: ApplicationThatNeedsConsensus> {
+    runtime: NodeRuntime,
+    senders: NodeSenders<A>,
+    diagnostics: RaftDiagnostics,
+    recent_events: RecentEvents,
+    metrics: RaftMetrics,
+    /// Most bytes a proposed command's entry may take, see [`RaftConfig::max_entry_bytes`]
+    max_entry_bytes: usize,
+}
+impl<A: ApplicationThatNeedsConsensus> RaftNodeHandle<A> {
+    pub(crate) fn new(
+        runtime: NodeRuntime,
+        senders: NodeSenders<A>,
+        diagnostics: RaftDiagnostics,
+        recent_events: RecentEvents,
+        metrics: RaftMetrics,
+        max_entry_bytes: usize,
+    ) -> Self {
+        RaftNodeHandle {
+            runtime,
+            senders,
+            diagnostics,
+            recent_events,
+            metrics,
+            max_entry_bytes,
+        }
+    }
+
+    /// Hand `command` to the node to replicate. Only a leader appends it to its log, followers
+    /// and candidates turn it down with [`ProposalError::NotLeader`], naming the leader they know
+    /// of, a leader whose application fell [`RaftConfig::max_unapplied_entries`] behind with
+    /// [`ProposalError::Busy`]. A command whose entry can't fit in one message is turned down here
+    /// with [`ProposalError::TooLarge`]. With [`RaftConfig::max_forwarding_hops`] set, a follower that knows
+    /// the leader forwards it there instead, resolving once the follower applies it. The node
+    /// picks it up the next time it wakes, when leading that's at the latest one heartbeat interval
+    /// later, together with anything else proposed meanwhile. The returned proposal resolves once
+    /// the command is applied.
+    pub fn propose(&self, command: A::Command) -> Result<PendingProposal<A>, ProposalError> {
+        self.send_proposal(command, None)
+    }
+
+    /// Like [`RaftNodeHandle::propose`] for each of `commands`, which the leader appends at
+    /// consecutive indexes in one go and replicates together, in as few AppendEntries as the
+    /// follower's batch size allows. The leader takes all of them or turns all of them down, the
+    /// returned proposals resolve as each command is applied.
+    pub fn propose_many(
+        &self,
+        commands: Vec<A::Command>,
+    ) -> Result<Vec<PendingProposal<A>>, ProposalError> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        for command in &commands {
+            self.check_entry_fits(command, None)?;
+        }
+        let (batch, pending) = commands
+            .into_iter()
+            .map(|command| {
+                let (outcome_tx, outcome_rx) = mpsc::channel();
+                (
+                    Proposal {
+                        command,
+                        session: None,
+                        outcome_tx,
+                    },
+                    Pending::new(outcome_rx),
+                )
+            })
+            .unzip();
+        self.senders
+            .proposals
+            .send(batch)
+            .map_err(|_| ProposalError::NodeStopped)?;
+        Ok(pending)
+    }
+
+    /// Like [`RaftNodeHandle::propose`], but applies `command` once however often the client retries
+    /// it with the same `session`, here or on whichever server leads next. A retry of the client's
+    /// latest command resolves with the response the command got the first time.
+    pub fn propose_in_session(
+        &self,
+        session: ClientSession,
+        command: A::Command,
+    ) -> Result<PendingProposal<A>, ProposalError> {
+        self.send_proposal(command, Some(session))
+    }
+
+    fn send_proposal(
+        &self,
+        command: A::Command,
+        session: Option<ClientSession>,
+    ) -> Result<PendingProposal<A>, ProposalError> {
+        self.check_entry_fits(&command, session)?;
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        self.senders
+            .proposals
+            .send(vec![Proposal {
+                command,
+                session,
+                outcome_tx,
+            }])
+            .map_err(|_| ProposalError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// Turns `command` down unless its entry fits in one message, at whatever index and term
+    fn check_entry_fits(
+        &self,
+        command: &A::Command,
+        session: Option<ClientSession>,
+    ) -> Result<(), ProposalError> {
+        if self.max_entry_bytes == usize::MAX {
+            return Ok(());
+        }
+        let command = match session {
+            Some(session) => LogEntryCommand::SessionCommand(session, command.clone()),
+            None => LogEntryCommand::Application(command.clone()),
+        };
+        // The largest index and term encode the longest
+        let entry = LogEntry {
+            index: LogIndex(u64::MAX),
+            term: TermIndex(u64::MAX),
+            command,
+        };
+        if encoded_len(std::slice::from_ref(&entry)) > self.max_entry_bytes as u64 {
+            return Err(ProposalError::TooLarge);
+        }
+        Ok(())
+    }
+
+    /// Add `server_id` to the cluster. Only a leader takes the change on: it first replicates its log
+    /// to the new server for up to [`RaftConfig::catch_up_rounds`] rounds, each of which has to end
+    /// within an election timeout, and only then appends the configuration entry that makes it a member.
+    /// One change is carried out at a time, the returned change resolves once its entry committed.
+    pub fn add_server(
+        &self,
+        server_id: ServerId,
+    ) -> Result<PendingMembershipChange, MembershipChangeError> {
+        self.change_membership(MembershipChange::AddServer(server_id))
+    }
+
+    /// Add `server_id` to the cluster as a learner: the leader replicates its log to it, but it doesn't
+    /// vote or count toward a majority, so adding it never risks the cluster's availability. Promote
+    /// it once it caught up with [`RaftNodeHandle::promote_learner`].
+    pub fn add_learner(
+        &self,
+        server_id: ServerId,
+    ) -> Result<PendingMembershipChange, MembershipChangeError> {
+        self.change_membership(MembershipChange::AddLearner(server_id))
+    }
+
+    /// Make the learner `server_id` a voting member. Like [`RaftNodeHandle::add_server`] the leader
+    /// only appends the configuration entry once the learner caught up within
+    /// [`RaftConfig::catch_up_rounds`] rounds, one that didn't stays a learner.
+    pub fn promote_learner(
+        &self,
+        server_id: ServerId,
+    ) -> Result<PendingMembershipChange, MembershipChangeError> {
+        self.change_membership(MembershipChange::PromoteLearner(server_id))
+    }
+
+    /// Remove `server_id` from the cluster, which may be the leader itself, or a learner. It stops
+    /// counting toward a majority as soon as the leader appends the configuration entry, a leader
+    /// removing itself steps down once that entry committed.
+    pub fn remove_server(
+        &self,
+        server_id: ServerId,
+    ) -> Result<PendingMembershipChange, MembershipChangeError> {
+        self.change_membership(MembershipChange::RemoveServer(server_id))
+    }
+
+    fn change_membership(
+        &self,
+        change: MembershipChange,
+    ) -> Result<PendingMembershipChange, MembershipChangeError> {
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        self.senders
+            .membership_changes
+            .send(MembershipChangeRequest { change, outcome_tx })
+            .map_err(|_| MembershipChangeError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// Make the leader give up leadership, say before taking it offline. With `transfer_to` set the
+    /// leader first stops taking proposals and replicates its log to that member, then tells it to
+    /// start an election right away and steps down, so the cluster barely goes without a leader.
+    /// A member that doesn't catch up within an election timeout fails the step down with
+    /// [`StepDownError::TransferTimedOut`] and the leader takes proposals again. Without
+    /// `transfer_to` the leader steps down right away and the cluster elects whoever times out first.
+    pub fn step_down(
+        &self,
+        transfer_to: Option<ServerId>,
+    ) -> Result<PendingStepDown, StepDownError> {
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        self.senders
+            .step_downs
+            .send(StepDownRequest {
+                transfer_to,
+                outcome_tx,
+            })
+            .map_err(|_| StepDownError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// Run `read` against the application without going through the log. Only a leader holding its
+    /// [`RaftConfig::read_lease`] serves it, once the application applied every entry committed when
+    /// the node picked the read up. Everyone else turns it down, see [`ReadError`].
+    pub fn read<R, F>(&self, read: F) -> Result<PendingRead<R>, ReadError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&A) -> R + Send + 'static,
+    {
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        let serve = Box::new(move |application: Result<&A, ReadError>| {
+            // The reader may have stopped waiting
+            let _ = outcome_tx.send(application.map(read));
+        });
+        self.senders
+            .lease_reads
+            .send(LeaseRead { serve })
+            .map_err(|_| ReadError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// Run `read` against the application once the node applied `index`, whatever its role. Handed
+    /// the [`Applied::index`] of a client's own proposal it sees that command, on a follower as
+    /// well as on the leader, without going through the log or a lease. The node picks the read up
+    /// the next time it wakes.
+    pub fn read_after<R, F>(&self, index: LogIndex, read: F) -> Result<PendingRead<R>, ReadError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&A) -> R + Send + 'static,
+    {
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        let serve = Box::new(move |application: Result<&A, ReadError>| {
+            // The reader may have stopped waiting
+            let _ = outcome_tx.send(application.map(read));
+        });
+        self.senders
+            .reads_after
+            .send(ReadAfter {
+                index,
+                read: LeaseRead { serve },
+            })
+            .map_err(|_| ReadError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// A copy of the application's state as of the last entry it applied, whatever the node's
+    /// role, for backups and reads that take their time. Taken between two applies the next time
+    /// the node wakes, applies only wait for [`ApplicationThatNeedsConsensus::freeze`], whoever
+    /// reads the view serializes it with [`StateView::data`].
+    pub fn state_view(&self) -> Result<PendingRead<StateView>, ReadError> {
+        self.read_after(LogIndex(0), |application| StateView {
+            applied_index: application.last_applied_index(),
+            state: application.freeze(),
         })
-        .expect("Failed to spawn raft thread")
+    }
+
+    /// Stop the node: it finishes the pass it's in, syncs its log, publishes
+    /// [`RaftEvent::NodeStopped`] and exits. A node waiting for a message only notices when it
+    /// wakes, within a heartbeat interval or election timeout, this waits up to `timeout` for it.
+    /// Whatever is still in flight resolves with `NodeStopped`.
+    pub fn shutdown(self, timeout: Duration) -> Result<(), ShutdownError> {
+        // A node that already stopped doesn't take the request, how it ended says why
+        let _ = self.senders.shutdown.send(());
+        match self.runtime.join_timeout(timeout) {
+            None => Err(ShutdownError::TimedOut),
+            Some(Ok(Ok(()))) => Ok(()),
+            Some(Ok(Err(error))) => Err(ShutdownError::Halted(error)),
+            Some(Err(_)) => Err(ShutdownError::Crashed),
+        }
+    }
+
+    /// Wait for the node to apply every entry up to and including `index`, whatever its role. With
+    /// the index a write was applied at, or a commit index learned from the leader, reads from the
+    /// local application see that write. The node picks the wait up
+    /// the next time it wakes, an index it already applied resolves then.
+    pub fn wait_for_index(&self, index: LogIndex) -> Result<PendingIndex, IndexWaitError> {
+        let (applied_tx, applied_rx) = mpsc::channel();
+        self.senders
+            .index_waits
+            .send(IndexWait { index, applied_tx })
+            .map_err(|_| IndexWaitError::NodeStopped)?;
+        Ok(Pending::new(applied_rx))
+    }
+
+    /// Every client command the node applies from now on, in log order, whatever its role. The
+    /// node picks the subscription up the next time it wakes, entries it applied before then, or
+    /// skipped by restoring a snapshot, aren't sent. Entries queue up until they're received, the
+    /// channel disconnects once the node stops.
+    pub fn subscribe_applied(&self) -> mpsc::Receiver<AppliedEntry<A::Command>> {
+        let (applied_tx, applied_rx) = mpsc::channel();
+        // A stopped node dropped the sender with its inbox, the receiver is disconnected then
+        let _ = self.senders.applied_subscriptions.send(applied_tx);
+        applied_rx
+    }
+
+    /// Where the node stands as of its last pass: role, term, leader, log progress, peers,
+    /// election counts and uptime. `None` until the node took its first pass. Read from what the node publishes, so it
+    /// answers even while the node is busy, stuck or stopped.
+    pub fn status(&self) -> Option<RaftStatus> {
+        self.diagnostics
+            .latest()
+            .map(|diagnostics| diagnostics.status())
+    }
+
+    /// Stop the node from taking in messages, ticks and requests until [`RaftNodeHandle::resume`],
+    /// to see how the cluster copes without it or for maintenance. Messages wait in its transport
+    /// meanwhile, requests wait in the handle's queues. The node pauses when it next wakes, within
+    /// a heartbeat interval or election timeout. [`RaftNodeHandle::shutdown`] still stops it.
+    pub fn pause(&self) -> Result<(), PauseError> {
+        self.senders
+            .pauses
+            .send(PauseRequest::Pause)
+            .map_err(|_| PauseError::NodeStopped)
+    }
+
+    /// Let a paused node carry on. The time it was paused doesn't count toward its election timer,
+    /// so it hears from the leader before it would start an election.
+    pub fn resume(&self) -> Result<(), PauseError> {
+        self.senders
+            .pauses
+            .send(PauseRequest::Resume)
+            .map_err(|_| PauseError::NodeStopped)
+    }
+
+    /// Change the node's election timeout, heartbeat interval or batch limits without restarting
+    /// it. The node checks its config with the update like [`RaftConfig::validate`] when it next
+    /// wakes, within a heartbeat interval or election timeout, and picks the new settings up the
+    /// next time it resets the timer or sizes the batch they're for. Every node keeps its own
+    /// config, update each of them to tune the cluster.
+    pub fn update_timing(
+        &self,
+        update: TimingUpdate,
+    ) -> Result<PendingTimingUpdate, TimingUpdateError> {
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        self.senders
+            .timing_updates
+            .send(TimingUpdateRequest { update, outcome_tx })
+            .map_err(|_| TimingUpdateError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// Copy the node's term and vote, latest snapshot and log to a new storage directory at
+    /// `path` while it keeps running, see [`PersistentStorage::backup_to`]. The node makes the copy
+    /// at the end of the pass it next takes, once everything it acknowledged is synced, and waits
+    /// for it meanwhile. Restoring means starting a node on the copy, or on a copy of it.
+    pub fn backup_to(&self, path: impl Into<PathBuf>) -> Result<PendingBackup, BackupError> {
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        self.senders
+            .backups
+            .send(BackupRequest {
+                path: path.into(),
+                outcome_tx,
+            })
+            .map_err(|_| BackupError::NodeStopped)?;
+        Ok(Pending::new(outcome_rx))
+    }
+
+    /// The thread the node runs on, transports unpark it when a message arrives. A group hosted by
+    /// a [`crate::RaftGroupManager`] shares it with the other groups on the same worker.
+    pub fn thread(&self) -> &thread::Thread {
+        match &self.runtime {
+            NodeRuntime::Thread(thread_handle) => thread_handle.thread(),
+            NodeRuntime::Hosted(hosted) => hosted.thread(),
+        }
+    }
+
+    /// True once the Raft thread has exited, because of an error it could not recover from or a panic.
+    /// A hosted group is finished once it stopped running on its worker.
+    pub fn is_finished(&self) -> bool {
+        match &self.runtime {
+            NodeRuntime::Thread(thread_handle) => thread_handle.is_finished(),
+            NodeRuntime::Hosted(hosted) => hosted.is_finished(),
+        }
+    }
+
+    /// Wait for the Raft thread to exit, returning the error that made it halt. For a hosted group,
+    /// wait for it to stop running on its worker.
+    pub fn join(self) -> thread::Result<Result<(), RaftError>> {
+        match self.runtime {
+            NodeRuntime::Thread(thread_handle) => thread_handle.join(),
+            NodeRuntime::Hosted(hosted) => hosted.join(),
+        }
+    }
+
+    /// Shared view of the node's latest diagnostic state, can be handed to an admin endpoint
+    pub fn diagnostics(&self) -> RaftDiagnostics {
+        self.diagnostics.clone()
+    }
+
+    /// The last events the node published, oldest first, up to 256 of them
+    pub fn recent_events(&self) -> Vec<RaftEvent> {
+        self.recent_events.snapshot()
+    }
+
+    /// The metrics the node records into, shared with the one it was built with
+    pub fn metrics(&self) -> RaftMetrics {
+        self.metrics.clone()
+    }
+
+    /// A collector of the node's metrics to register with a Prometheus registry
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus_collector(&self) -> crate::PrometheusCollector {
+        crate::PrometheusCollector::new(self.metrics.clone())
+    }
+}
+
+/// Span covering the time a node spends in one role during one term.
+/// Spans for the events handled meanwhile nest under it, which nests under the node's span,
+/// so everything that happened in a term can be told apart in the trace.
+struct TermSpan {
+    term: TermIndex,
+    role: RaftNodeState,
+    span: Span,
+}
+impl TermSpan {
+    fn new(term: TermIndex, role: RaftNodeState) -> Self {
+        TermSpan {
+            term,
+            role,
+            span: info_span!("term", term = term.0, role = ?role),
+        }
+    }
+
+    /// Start a new span if the node moved to another term or role since the current one was created
+    fn follow(&mut self, term: TermIndex, role: RaftNodeState) {
+        if term != self.term || role != self.role {
+            *self = TermSpan::new(term, role);
+        }
+    }
+}
+
+fn node_role(node: &Node) -> RaftNodeState {
+    match node {
+        Node::Follower(_) => RaftNodeState::Follower,
+        Node::PreCandidate(_) => RaftNodeState::PreCandidate,
+        Node::Candidate(_) => RaftNodeState::Candidate,
+        Node::Leader(_) => RaftNodeState::Leader,
+    }
+}
+
+/// Runs one event through the state machine inside a span naming it, warning if it was slow to handle
+fn handle_event_timed<LC: LogCommand, PS: PersistentStorage<LC>>(
+    node: Node,
+    event: Event<LC>,
+    storage: &mut PS,
+    config: &RaftConfig,
+    rng: &mut ChaCha8Rng,
+    clock: &dyn Clock,
+    actions: &mut Vec<Action<LC>>,
+) -> Result<Node, RaftError> {
+    let event_name = match &event {
+        Event::Tick(_) => "tick",
+        Event::LogEntryAppliedByApplication(_) => "log_entry_applied",
+        Event::ClientProposals(_) => "client_proposals",
+        Event::LogSynced(_) => "log_synced",
+        Event::SnapshotTaken(_) => "snapshot_taken",
+        Event::MembershipChangeRequested { .. } => "membership_change_requested",
+        Event::LeaseReadRequested { .. } => "lease_read_requested",
+        Event::StepDownRequested { .. } => "step_down_requested",
+        Event::Resumed { .. } => "resumed",
+        Event::IncomingRpc(RpcMessage::Request(Request::AppendEntries(_))) => "append_entries",
+        Event::IncomingRpc(RpcMessage::Request(Request::RequestVote(_))) => "request_vote",
+        Event::IncomingRpc(RpcMessage::Request(Request::PreVote(_))) => "pre_vote",
+        Event::IncomingRpc(RpcMessage::Request(Request::InstallSnapshot(_))) => "install_snapshot",
+        Event::IncomingRpc(RpcMessage::Request(Request::TimeoutNow(_))) => "timeout_now",
+        Event::IncomingRpc(RpcMessage::Reply(ReplyTo::AppendEntries(_))) => "append_entries_ack",
+        Event::IncomingRpc(RpcMessage::Reply(ReplyTo::RequestVote(_))) => "vote",
+        Event::IncomingRpc(RpcMessage::Reply(ReplyTo::PreVote(_))) => "pre_vote_reply",
+        Event::IncomingRpc(RpcMessage::Reply(ReplyTo::InstallSnapshot(_))) => {
+            "install_snapshot_ack"
+        }
+        Event::IncomingRpc(RpcMessage::Reply(ReplyTo::TimeoutNow(_))) => "timeout_now_ack",
+        Event::IncomingRpc(RpcMessage::Request(Request::ForwardProposals(_))) => {
+            "forward_proposals"
+        }
+        Event::IncomingRpc(RpcMessage::Reply(ReplyTo::ForwardProposals(_))) => {
+            "forward_proposals_ack"
+        }
+    };
+    let _span = debug_span!("handle_event", event = event_name).entered();
+
+    let started_at = system_clock::now();
+    let result = node.next(event, storage, config, rng, clock, actions);
+    warn_if_slow(
+        "message handling",
+        started_at.elapsed(),
+        config.slow_operation_thresholds.message_handling,
+    );
+    result
+}
+
+/// Gets the node's storage back into a usable state after `error`, if there is a way to.
+/// On `Ok` the node carries on as a follower, on `Err` the Raft thread halts.
+fn recover_from_error<LC: LogCommand, PS: PersistentStorage<LC>>(
+    error: RaftError,
+    storage: &mut PS,
+    retry: StorageSyncRetry,
+    storage_full_policy: StorageFullPolicy,
+) -> Result<(), RaftError> {
+    let recovery = match error {
+        RaftError::Storage(PersistentStorageError::StorageFull) => storage_full_policy.recovery(),
+        error => error.recovery(),
+    };
+    match recovery {
+        RaftErrorRecovery::Halt => {
+            error!("Halting raft thread: {}", error);
+            Err(error)
+        }
+        RaftErrorRecovery::StepDown => {
+            warn!("Stepping down to follower: {}", error);
+            Ok(())
+        }
+        RaftErrorRecovery::Retry => {
+            let mut backoff = retry.initial_backoff;
+            for attempt in 1..=retry.attempts {
+                warn!(
+                    "Retrying storage sync ({}/{}) in {:?}: {}",
+                    attempt, retry.attempts, backoff, error
+                );
+                thread::sleep(backoff);
+                if storage.sync().is_ok() {
+                    return Ok(());
+                }
+                backoff *= 2;
+            }
+            error!("Halting raft thread, storage sync kept failing: {}", error);
+            Err(error)
+        }
+    }
+}
+
+fn state_snapshot<LC: LogCommand>(
+    server_id: ServerId,
+    node: &Node,
+    storage: &impl PersistentStorage<LC>,
+) -> RaftStateEvent {
+    RaftStateEvent {
+        server_id,
+        current_state: node_role(node),
+        current_term: storage.current_term(),
+        voted_for: storage.vote_for_current_term(),
+        leader_for_term: node.leader_id(),
+        commit_index: node.commit_index(),
+        last_applied: node.last_applied(),
+        last_log_index: storage.last_entry_index().unwrap_or(LogIndex(0)),
+        last_log_term: storage.last_entry_term().unwrap_or(TermIndex(0)),
+    }
 }
@@ -0,0 +1,105 @@
+use std::num::{NonZeroU32, NonZeroU64};
+use std::time::{Duration, Instant};
+
+/// Most a transport sends to one peer, so catching up a lagging follower can't take all the
+/// bandwidth from the heartbeats to the rest of the cluster. Unlimited by default. Up to a
+/// second's worth can go out in a burst, and a message bigger than a second's worth of bytes goes
+/// out once the peer had no traffic for a second, holding back the ones after it for longer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    messages_per_second: Option<NonZeroU32>,
+    bytes_per_second: Option<NonZeroU64>,
+}
+impl RateLimit {
+    /// Most messages sent to the peer a second
+    pub fn messages_per_second(mut self, messages: NonZeroU32) -> Self {
+        self.messages_per_second = Some(messages);
+        self
+    }
+
+    /// Most bytes of messages sent to the peer a second
+    pub fn bytes_per_second(mut self, bytes: NonZeroU64) -> Self {
+        self.bytes_per_second = Some(bytes);
+        self
+    }
+}
+
+/// Tokens refilled at `rate` a second, up to a second's worth
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+}
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        Bucket { rate, tokens: rate }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+    }
+
+    /// How long until there are tokens for `cost`, or the bucket is full for a cost past its size
+    fn wait(&self, cost: f64) -> Duration {
+        let missing = cost.min(self.rate) - self.tokens;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.rate)
+        }
+    }
+}
+
+/// Token buckets holding frames to one peer to its [`RateLimit`]. Goes by real time, like
+/// [`Backoff`](crate::backoff::Backoff).
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    limit: RateLimit,
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+    refilled_at: Option<Instant>,
+}
+impl Throttle {
+    /// Holds frames to `limit` from now on, starting with a full burst if it changed
+    pub(crate) fn limit(&mut self, limit: RateLimit) {
+        if limit == self.limit {
+            return;
+        }
+        self.limit = limit;
+        self.messages = limit
+            .messages_per_second
+            .map(|rate| Bucket::new(f64::from(rate.get())));
+        self.bytes = limit
+            .bytes_per_second
+            .map(|rate| Bucket::new(rate.get() as f64));
+    }
+
+    /// How long until a frame of `bytes` may go out, zero if it may now
+    pub(crate) fn wait(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = self
+            .refilled_at
+            .map_or(Duration::ZERO, |refilled_at| now - refilled_at);
+        self.refilled_at = Some(now);
+        let mut wait = Duration::ZERO;
+        if let Some(messages) = &mut self.messages {
+            messages.refill(elapsed);
+            wait = wait.max(messages.wait(1.0));
+        }
+        if let Some(bytes_bucket) = &mut self.bytes {
+            bytes_bucket.refill(elapsed);
+            wait = wait.max(bytes_bucket.wait(bytes as f64));
+        }
+        wait
+    }
+
+    /// Takes a frame of `bytes` that went out off the buckets
+    pub(crate) fn sent(&mut self, bytes: usize) {
+        if let Some(messages) = &mut self.messages {
+            messages.tokens -= 1.0;
+        }
+        if let Some(bytes_bucket) = &mut self.bytes {
+            bytes_bucket.tokens -= bytes as f64;
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use raft_core::rpc_messages::{ReplyTo, Request};
+use raft_core::system_clock::{Clock, Instant, SystemClock};
+use raft_core::{LogCommand, ServerId};
+
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A request still waiting on its reply
+#[derive(Debug)]
+struct Outstanding<W> {
+    from: ServerId,
+    to: ServerId,
+    deadline: Instant,
+    waiting: W,
+}
+
+/// A request no reply came for before its timeout ran out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedOutRequest<W> {
+    pub request_id: Uuid,
+    /// The server the request was sent to
+    pub to: ServerId,
+    /// What was waiting on the reply
+    pub waiting: W,
+}
+
+/// Matches replies back to the requests they answer by their request id, so transports don't each
+/// have to. Every request tracked holds whatever waits on its reply, like the channel it's handed
+/// to, until the reply comes or the request times out. Works the same whichever end of the request
+/// the transport is on: a reply answers a request when it goes back from the server the request was
+/// sent to, to the one that sent it.
+#[derive(Debug)]
+pub struct RpcRouter<W> {
+    outstanding: HashMap<Uuid, Outstanding<W>>,
+    timeout: Duration,
+    clock: Box<dyn Clock>,
+}
+impl<W> RpcRouter<W> {
+    /// Times requests out `timeout` after they're tracked
+    pub fn new(timeout: Duration) -> Self {
+        RpcRouter {
+            outstanding: HashMap::new(),
+            timeout,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// The clock timeouts are counted on, the system's by default
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Keeps `waiting` until the reply to `request` comes, or the request times out. Tracking a
+    /// request id again replaces what was waiting on it, which is handed back.
+    pub fn track<C: LogCommand>(&mut self, request: &Request<C>, waiting: W) -> Option<W> {
+        self.track_with_timeout(request, waiting, self.timeout)
+    }
+
+    /// Like [`RpcRouter::track`], but times the request out after `timeout` rather than the
+    /// router's
+    pub fn track_with_timeout<C: LogCommand>(
+        &mut self,
+        request: &Request<C>,
+        waiting: W,
+        timeout: Duration,
+    ) -> Option<W> {
+        let outstanding = Outstanding {
+            from: request.from(),
+            to: request.to(),
+            deadline: self.clock.now() + timeout,
+            waiting,
+        };
+        self.outstanding
+            .insert(request.request_id(), outstanding)
+            .map(|replaced| replaced.waiting)
+    }
+
+    /// What's waiting on the request `reply` answers, no longer tracked. `None` if no request is
+    /// waiting on it: it was never tracked, timed out, was already answered, or `reply` doesn't go
+    /// between the servers the request did.
+    pub fn route(&mut self, reply: &ReplyTo) -> Option<W> {
+        let answers = self
+            .outstanding
+            .get(&reply.request_id())
+            .is_some_and(|request| request.to == reply.from() && request.from == reply.to());
+        if !answers {
+            return None;
+        }
+        self.outstanding
+            .remove(&reply.request_id())
+            .map(|request| request.waiting)
+    }
+
+    /// Stops tracking every request whose timeout ran out, returning them earliest deadline first
+    pub fn expire(&mut self) -> Vec<TimedOutRequest<W>> {
+        let now = self.clock.now();
+        let expired: Vec<Uuid> = self
+            .outstanding
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(request_id, _)| *request_id)
+            .collect();
+        let mut timed_out: Vec<(Instant, TimedOutRequest<W>)> = expired
+            .into_iter()
+            .filter_map(|request_id| {
+                self.outstanding.remove(&request_id).map(|request| {
+                    let timed_out = TimedOutRequest {
+                        request_id,
+                        to: request.to,
+                        waiting: request.waiting,
+                    };
+                    (request.deadline, timed_out)
+                })
+            })
+            .collect();
+        timed_out.sort_by_key(|(deadline, _)| *deadline);
+        timed_out
+            .into_iter()
+            .map(|(_, timed_out)| timed_out)
+            .collect()
+    }
+
+    /// How long until the next request times out, `None` with none outstanding. A transport can
+    /// wait that long before calling [`RpcRouter::expire`].
+    pub fn time_to_next_timeout(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.outstanding
+            .values()
+            .map(|request| request.deadline.saturating_duration_since(now))
+            .min()
+    }
+
+    /// Requests still waiting on their reply
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+}
@@ -0,0 +1,376 @@
+use raft_core::{
+    LogCommand, LogEntry, LogIndex, PersistentStorage, PersistentStorageError, ServerId, Snapshot,
+    TermIndex,
+};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bincode::Options;
+
+use crate::codec::{BincodeCodec, CommandCodec};
+use crate::default_storage::{
+    decode_log_record, decode_snapshot, encode_log_record, encode_snapshot, get_election_bincode,
+    get_snapshot_bincode, io_error, truncate_to_bytes, Election,
+};
+
+/// Key of the term and vote in the database's default tree
+const ELECTION_KEY: &[u8] = b"election";
+/// Key of the snapshot in the database's default tree
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+/// Key of the [`CommandCodec::format`] the log's commands are in, in the database's default tree
+const FORMAT_KEY: &[u8] = b"format";
+/// Name of the tree holding the log, keyed by big endian index so it's kept in log order
+const LOG_TREE: &[u8] = b"log";
+/// Times opening the database is tried again while another handle to it is still closing
+const OPEN_ATTEMPTS: u32 = 50;
+const OPEN_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Storage keeping the log, term, vote and snapshot in a sled database. Entries are records like
+/// [`crate::DefaultPersistentStorage`] writes to its log file, only their terms are kept in
+/// memory. Changes go to sled right away and [`PersistentStorage::sync`] flushes them, after a crash
+/// sled recovers them in the order they were made, so no torn record is ever read back.
+#[derive(Debug)]
+pub struct SledStorage<C: LogCommand> {
+    db: sled::Db,
+    log: sled::Tree,
+    election: Election,
+    snapshot: Option<Snapshot>,
+    /// Term of each entry in the log tree, `terms[0]` is the one right after the snapshot
+    terms: Vec<TermIndex>,
+    /// A change sled turned down, which every sync reports from then on since the log in sled no
+    /// longer matches `terms`
+    failed_write: Option<PersistentStorageError>,
+    /// What the commands in the log are encoded with
+    codec: Arc<dyn CommandCodec<C>>,
+}
+impl<C: LogCommand> SledStorage<C> {
+    /// Opens the database in `path`, creating it if it doesn't exist yet. Commands are encoded with
+    /// [`BincodeCodec`].
+    pub fn new(path: &Path) -> Result<Self, PersistentStorageError> {
+        Self::with_codec(path, Arc::new(BincodeCodec))
+    }
+
+    /// Like [`SledStorage::new`], but encodes commands with `codec`. A database has to be opened
+    /// with a codec of the format it was written in, or this fails with
+    /// [`PersistentStorageError::FormatMismatch`].
+    pub fn with_codec(
+        path: &Path,
+        codec: Arc<dyn CommandCodec<C>>,
+    ) -> Result<Self, PersistentStorageError> {
+        let db = open_db(path)?;
+        let log = db.open_tree(LOG_TREE).map_err(sled_error)?;
+        match db.get(FORMAT_KEY).map_err(sled_error)? {
+            Some(format) if *format == [codec.format()] => {}
+            None if log.is_empty() => {
+                let _ = db
+                    .insert(FORMAT_KEY, &[codec.format()])
+                    .map_err(sled_error)?;
+            }
+            _ => return Err(PersistentStorageError::FormatMismatch),
+        }
+        let election = match db.get(ELECTION_KEY).map_err(sled_error)? {
+            Some(record) => get_election_bincode()
+                .deserialize(&record)
+                .map_err(|_| PersistentStorageError::SerdeError)?,
+            None => Election {
+                current_term: TermIndex(0),
+                voted_for: None,
+            },
+        };
+        let snapshot: Option<Snapshot> = match db.get(SNAPSHOT_KEY).map_err(sled_error)? {
+            Some(record) => Some(decode_snapshot(
+                &*codec,
+                get_snapshot_bincode()
+                    .deserialize(&record)
+                    .map_err(|_| PersistentStorageError::SerdeError)?,
+            )?),
+            None => None,
+        };
+
+        let mut storage = SledStorage {
+            db,
+            log,
+            election,
+            snapshot: None,
+            terms: Vec::new(),
+            failed_write: None,
+            codec,
+        };
+        let mut entries = Vec::new();
+        for record in storage.log.iter() {
+            let (_, record) = record.map_err(sled_error)?;
+            let entry = decode_log_record(&*storage.codec, &record)?;
+            entries.push((entry.index, entry.term));
+        }
+        if let Some(snapshot) = snapshot {
+            // Entries the snapshot covers are left over if the node crashed between writing the
+            // snapshot and removing them
+            let covered = entries
+                .iter()
+                .take_while(|(index, _)| *index <= snapshot.last_included_index)
+                .count();
+            if covered > 0 {
+                let dropped = match entries[covered - 1] {
+                    (index, term)
+                        if index == snapshot.last_included_index
+                            && term == snapshot.last_included_term =>
+                    {
+                        covered
+                    }
+                    _ => entries.len(),
+                };
+                let dropped_through = entries.get(dropped).map(|(index, _)| LogIndex(index.0 - 1));
+                storage.remove_entries(entries[0].0, dropped_through)?;
+                let _ = entries.drain(..dropped);
+            }
+            storage.snapshot = Some(snapshot);
+        }
+        storage.terms = entries.into_iter().map(|(_, term)| term).collect();
+        Ok(storage)
+    }
+
+    /// Index of the last entry the snapshot covers, `terms[0]` is the entry right after it
+    fn compacted_through(&self) -> u64 {
+        self.snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.last_included_index.0)
+            .unwrap_or(0)
+    }
+
+    /// Position in `terms` of the entry at `index`, `None` for `LogIndex(0)` and compacted entries
+    fn log_position(&self, index: LogIndex) -> Option<usize> {
+        index
+            .0
+            .checked_sub(self.compacted_through() + 1)
+            .map(|position| position as usize)
+    }
+
+    /// Removes the entries from `from` through `through`, or through the end of the log without it
+    fn remove_entries(
+        &mut self,
+        from: LogIndex,
+        through: Option<LogIndex>,
+    ) -> Result<(), PersistentStorageError> {
+        let from = log_key(from);
+        let keys = match through {
+            Some(through) => self.log.range(from..=log_key(through)),
+            None => self.log.range(from..),
+        };
+        let mut batch = sled::Batch::default();
+        for key in keys.keys() {
+            batch.remove(key.map_err(sled_error)?);
+        }
+        self.log.apply_batch(batch).map_err(sled_error)
+    }
+
+    /// Keeps the first error a change ran into for the next sync to report
+    fn record_failure(&mut self, result: Result<(), PersistentStorageError>) {
+        if let Err(error) = result {
+            self.failed_write.get_or_insert(error);
+        }
+    }
+}
+
+/// Opens the database in `path`. A database closed a moment ago stays locked until sled's flusher
+/// thread lets go of it, which is waited out for a little while.
+fn open_db(path: &Path) -> Result<sled::Db, PersistentStorageError> {
+    let mut attempts = 0;
+    loop {
+        match sled::open(path) {
+            Ok(db) => return Ok(db),
+            Err(sled::Error::Io(_)) if attempts < OPEN_ATTEMPTS => {
+                attempts += 1;
+                thread::sleep(OPEN_RETRY_INTERVAL);
+            }
+            Err(error) => return Err(sled_error(error)),
+        }
+    }
+}
+
+/// Key of the entry at `index` in the log tree
+fn log_key(index: LogIndex) -> [u8; 8] {
+    index.0.to_be_bytes()
+}
+
+/// Whatever sled failed at, it couldn't read or write its files, for want of room if the disk is
+/// full
+fn sled_error(error: sled::Error) -> PersistentStorageError {
+    match error {
+        sled::Error::Io(error) => io_error(error),
+        _ => PersistentStorageError::IoError,
+    }
+}
+
+impl<C: LogCommand> PersistentStorage<C> for SledStorage<C> {
+    fn current_term(&self) -> TermIndex {
+        self.election.current_term
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.election.vote_for_current_term()
+    }
+
+    fn update_term(&mut self, term: TermIndex) -> &mut Self {
+        self.election.current_term = term;
+        self
+    }
+
+    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self {
+        self.election.voted_for = Some((self.current_term(), voted_for));
+        self
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        match self.terms.len() {
+            0 => self.snapshot.as_ref().map(|s| s.last_included_index),
+            len => Some(LogIndex(self.compacted_through() + len as u64)),
+        }
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        self.terms
+            .last()
+            .copied()
+            .or(self.snapshot.as_ref().map(|s| s.last_included_term))
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        match &self.snapshot {
+            _ if index.0 == 0 => Some(TermIndex(0)),
+            Some(snapshot) if snapshot.last_included_index == index => {
+                Some(snapshot.last_included_term)
+            }
+            _ => self
+                .log_position(index)
+                .and_then(|position| self.terms.get(position))
+                .copied(),
+        }
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>> {
+        if from.0 != 0 && from.0 <= self.compacted_through() {
+            return Vec::new();
+        }
+        // Reading stops at the first entry sled or the codec can't give back, the node asks again
+        self.log
+            .range(log_key(from)..)
+            .values()
+            .take(max_entries)
+            .map_while(|record| {
+                let record = record.ok()?;
+                decode_log_record(&*self.codec, &record).ok()
+            })
+            .collect()
+    }
+
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>> {
+        let mut entries = self.entries(from, max_entries);
+        truncate_to_bytes(&mut entries, max_bytes);
+        entries
+    }
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self {
+        if snapshot.last_included_index.0 <= self.compacted_through() {
+            return self;
+        }
+        let record = match encode_snapshot(&*self.codec, &snapshot).and_then(|stored| {
+            get_snapshot_bincode()
+                .serialize(&stored)
+                .map_err(|_| PersistentStorageError::SerdeError)
+        }) {
+            Ok(record) => record,
+            Err(_) => {
+                self.record_failure(Err(PersistentStorageError::SerdeError));
+                return self;
+            }
+        };
+        // The snapshot goes first, the entries it covers can only go once it's there
+        let inserted = self.db.insert(SNAPSHOT_KEY, record).map_err(sled_error);
+        self.record_failure(inserted.map(|_| ()));
+
+        let first_index = LogIndex(self.compacted_through() + 1);
+        let last_included_position = self.log_position(snapshot.last_included_index);
+        let removed = match last_included_position.and_then(|position| self.terms.get(position)) {
+            Some(term) if *term == snapshot.last_included_term => {
+                let _ = self.terms.drain(..=last_included_position.unwrap_or(0));
+                self.remove_entries(first_index, Some(snapshot.last_included_index))
+            }
+            _ => {
+                self.terms.clear();
+                self.remove_entries(first_index, None)
+            }
+        };
+        self.record_failure(removed);
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    fn append(&mut self, entries: Vec<LogEntry<C>>) -> &mut Self {
+        let mut batch = sled::Batch::default();
+        let mut written = Ok(());
+        for entry in entries {
+            // Compacted entries were committed, whoever sends them again has the same ones
+            let Some(position) = self.log_position(entry.index) else {
+                continue;
+            };
+            match self.terms.get(position) {
+                Some(existing) if *existing == entry.term => continue,
+                Some(_) => {
+                    for position in position..self.terms.len() {
+                        let index = LogIndex(self.compacted_through() + 1 + position as u64);
+                        batch.remove(&log_key(index));
+                    }
+                    self.terms.truncate(position);
+                }
+                None => {}
+            }
+            assert_eq!(
+                position,
+                self.terms.len(),
+                "BUG: appending {:?} would leave a gap in the log",
+                entry.index
+            );
+            match encode_log_record(&*self.codec, &entry) {
+                Ok(record) => batch.insert(&log_key(entry.index), record),
+                Err(error) => written = Err(error),
+            }
+            self.terms.push(entry.term);
+        }
+        // Truncating and appending happen together or not at all
+        let applied = written.and_then(|_| self.log.apply_batch(batch).map_err(sled_error));
+        self.record_failure(applied);
+        self
+    }
+
+    fn sync(&mut self) -> Result<(), PersistentStorageError> {
+        if let Some(error) = self.failed_write {
+            return Err(error);
+        }
+        let record = get_election_bincode()
+            .serialize(&self.election)
+            .map_err(|_| PersistentStorageError::SerdeError)?;
+        let _ = self.db.insert(ELECTION_KEY, record).map_err(sled_error)?;
+        let _ = self.db.flush().map_err(sled_error)?;
+        Ok(())
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        // Sled keeps the log with everything else, it doesn't tell the trees apart
+        self.db.size_on_disk().ok()
+    }
+
+    fn is_full(&self) -> bool {
+        self.failed_write == Some(PersistentStorageError::StorageFull)
+    }
+}
@@ -0,0 +1,162 @@
+use crate::default_storage::encoded_len;
+use crate::metrics::RaftMetrics;
+use raft_core::system_clock;
+use raft_core::*;
+use std::time::Duration;
+use tracing::warn;
+
+/// Logs a warning when `elapsed` went over `threshold`.
+/// Called from inside the span of the operation so the warning carries its context.
+pub(crate) fn warn_if_slow(operation: &str, elapsed: Duration, threshold: Duration) {
+    if elapsed > threshold {
+        warn!(
+            operation,
+            elapsed_ms = elapsed.as_millis() as u64,
+            threshold_ms = threshold.as_millis() as u64,
+            "Slow {operation} took {elapsed:?}, over the {threshold:?} threshold"
+        );
+    }
+}
+
+/// Wraps the node's storage to time every sync, which is where the fsync cost shows up.
+/// Syncs that wrote appended entries are recorded as the append latency. Also counts the bytes of
+/// entries written and read, and reports the log's size on disk after every sync.
+#[derive(Debug)]
+pub(crate) struct SyncTimingStorage<PS> {
+    inner: PS,
+    threshold: Duration,
+    metrics: RaftMetrics,
+    appended_since_sync: bool,
+    /// Encoded size of the entries appended since the last sync
+    unsynced_bytes: u64,
+}
+impl<PS> SyncTimingStorage<PS> {
+    pub(crate) fn new(inner: PS, threshold: Duration, metrics: RaftMetrics) -> Self {
+        Self {
+            inner,
+            threshold,
+            metrics,
+            appended_since_sync: false,
+            unsynced_bytes: 0,
+        }
+    }
+
+    /// Runs `sync` on the wrapped storage, timing it and recording what it wrote
+    fn timed_sync<C: LogCommand>(
+        &mut self,
+        sync: impl FnOnce(&mut PS) -> Result<(), PersistentStorageError>,
+    ) -> Result<(), PersistentStorageError>
+    where
+        PS: PersistentStorage<C>,
+    {
+        let _span = tracing::debug_span!("storage_sync").entered();
+        let started_at = system_clock::now();
+        let result = sync(&mut self.inner);
+        let elapsed = started_at.elapsed();
+        warn_if_slow("storage sync", elapsed, self.threshold);
+        self.metrics.record_sync_latency(elapsed);
+        if result.is_ok() {
+            if std::mem::take(&mut self.appended_since_sync) {
+                self.metrics.record_append_latency(elapsed);
+            }
+            let written = std::mem::take(&mut self.unsynced_bytes);
+            if written > 0 {
+                self.metrics.record_bytes_written(written);
+            }
+            if let Some(log_size) = self.inner.log_size_on_disk() {
+                self.metrics.set_log_size_on_disk(log_size);
+            }
+        }
+        result
+    }
+}
+impl<C: LogCommand, PS: PersistentStorage<C>> PersistentStorage<C> for SyncTimingStorage<PS> {
+    fn current_term(&self) -> TermIndex {
+        self.inner.current_term()
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.inner.vote_for_current_term()
+    }
+
+    fn update_term(&mut self, term: TermIndex) -> &mut Self {
+        self.inner.update_term(term);
+        self
+    }
+
+    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self {
+        self.inner.record_vote(voted_for);
+        self
+    }
+
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> Result<(), PersistentStorageError> {
+        self.timed_sync(|inner| inner.persist_term_and_vote(term, voted_for))
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        self.inner.last_entry_index()
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        self.inner.last_entry_term()
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        self.inner.entry_term(index)
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>> {
+        let entries = self.inner.entries(from, max_entries);
+        self.metrics.record_bytes_read(encoded_len(&entries));
+        entries
+    }
+
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>> {
+        let entries = self.inner.entries_within(from, max_entries, max_bytes);
+        self.metrics.record_bytes_read(encoded_len(&entries));
+        entries
+    }
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        self.inner.snapshot()
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self {
+        self.inner.install_snapshot(snapshot);
+        self
+    }
+
+    fn append(&mut self, entries: Vec<LogEntry<C>>) -> &mut Self {
+        self.appended_since_sync |= !entries.is_empty();
+        self.unsynced_bytes += encoded_len(&entries);
+        self.inner.append(entries);
+        self
+    }
+
+    fn sync(&mut self) -> Result<(), PersistentStorageError> {
+        self.timed_sync(|inner| inner.sync())
+    }
+
+    fn log_size_on_disk(&self) -> Option<u64> {
+        self.inner.log_size_on_disk()
+    }
+
+    fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+
+    fn backup_to(&mut self, path: &std::path::Path) -> Result<LogIndex, PersistentStorageError> {
+        // What's unsynced is recorded like any other sync, copying it out isn't one
+        self.sync()?;
+        self.inner.backup_to(path)
+    }
+}
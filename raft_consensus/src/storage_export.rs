@@ -0,0 +1,63 @@
+use raft_core::{
+    LogCommand, LogEntry, LogIndex, PersistentStorage, PersistentStorageError, ServerId, Snapshot,
+    TermIndex,
+};
+use serde::{Deserialize, Serialize};
+
+/// Entries read from the storage exported at a time
+const EXPORT_BATCH_ENTRIES: usize = 1024;
+
+/// Everything a node keeps in storage: its term and vote, its snapshot and the log after it.
+/// Serializes with any serde format, so a node's storage can be exported from one backend, e.g. a
+/// [`crate::DefaultPersistentStorage`], and imported into another, e.g. a [`crate::SledStorage`],
+/// without the node having to catch up from the cluster again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct StorageExport<C: LogCommand> {
+    /// The node's term
+    pub current_term: TermIndex,
+    /// The vote cast in `current_term`
+    pub voted_for: Option<ServerId>,
+    /// The latest snapshot, if the log was ever compacted
+    pub snapshot: Option<Snapshot>,
+    /// The entries after the snapshot, in log order
+    pub entries: Vec<LogEntry<C>>,
+}
+impl<C: LogCommand> StorageExport<C> {
+    /// What `storage` holds, as of its last sync for storage that only reads back what it synced
+    pub fn read_from(storage: &impl PersistentStorage<C>) -> Self {
+        let mut entries: Vec<LogEntry<C>> = Vec::new();
+        let last_index = storage.last_entry_index().unwrap_or(LogIndex(0));
+        let mut next = storage.snapshot_index().next();
+        while next <= last_index {
+            let batch = storage.entries(next, EXPORT_BATCH_ENTRIES);
+            let Some(last) = batch.last() else {
+                break;
+            };
+            next = last.index.next();
+            entries.extend(batch);
+        }
+        StorageExport {
+            current_term: storage.current_term(),
+            voted_for: storage.vote_for_current_term(),
+            snapshot: storage.snapshot().cloned(),
+            entries,
+        }
+    }
+
+    /// Writes the export into `storage` and syncs it. Meant for storage that's new, the entries
+    /// are appended like the leader's would be, replacing whatever conflicts with them.
+    pub fn write_to(
+        self,
+        storage: &mut impl PersistentStorage<C>,
+    ) -> Result<(), PersistentStorageError> {
+        if let Some(snapshot) = self.snapshot {
+            let _ = storage.install_snapshot(snapshot);
+        }
+        let storage = storage.append(self.entries).update_term(self.current_term);
+        match self.voted_for {
+            Some(voted_for) => storage.record_vote(voted_for).sync(),
+            None => storage.sync(),
+        }
+    }
+}
@@ -0,0 +1,878 @@
+use crate::backoff::{self, Backoff};
+use crate::common::RaftTransportConnector;
+use crate::metrics::{PeerConnection, RaftMetrics};
+use crate::rate_limit::{RateLimit, Throttle};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::wire_codec::{BincodeWireCodec, WireCodec};
+use raft_core::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_core::{LogCommand, RaftTransportError, ServerId};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+/// Most bytes of message a frame may carry unless set otherwise, a longer length means the
+/// connection is out of step
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+/// How long connecting to a peer may take before the attempt counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often a connection to a peer is checked for having been closed, and a lost one reopened
+const PROBE_INTERVAL: Duration = Duration::from_millis(50);
+/// How long checking a connection waits on a read to find it closed
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1);
+/// Most bytes of frames held for a peer while there's no connection to it, the oldest frames are
+/// dropped past it
+const MAX_BUFFERED_BYTES: usize = 8 * 1024 * 1024;
+/// Version of the frames this build writes, bumped whenever their layout or encoding changes
+const PROTOCOL_VERSION: u8 = 2;
+/// Oldest version this build still reads and writes, peers that only speak older ones are refused
+const MIN_PROTOCOL_VERSION: u8 = 1;
+/// Starts every connection, so a peer that isn't a Raft server, or predates versions, is turned
+/// away before its bytes are read as frames
+const HANDSHAKE_MAGIC: &[u8; 4] = b"RAFT";
+/// What a server answers a handshake offering no version it speaks with
+const NO_COMMON_VERSION: u8 = 0;
+/// First version with keepalives: an empty frame the peer answers with a [`PONG`]
+const KEEPALIVE_VERSION: u8 = 2;
+/// The one byte a server writes back over a connection it accepted, for every keepalive read off it
+const PONG: u8 = b'P';
+/// How often a keepalive goes out over the connection to a peer that answers them
+const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a peer may leave keepalives unanswered before its connection is taken as dead, and
+/// opened again
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Both ends of a connection, plain or TLS
+pub(crate) trait Connection: Read + Write + Send {}
+impl<T: Read + Write + Send> Connection for T {}
+
+/// An inbound connection, ready to read frames off
+pub(crate) struct Accepted {
+    pub(crate) stream: Box<dyn Connection>,
+    /// The servers messages over the connection may come from, `None` if the peer wasn't
+    /// authenticated
+    pub(crate) senders: Option<HashSet<ServerId>>,
+}
+
+/// How connections carry frames
+#[derive(Debug, Clone)]
+enum Connections {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(Arc<TlsConfig>),
+}
+impl Connections {
+    /// Where the frames a peer sends over `stream` are read from
+    fn accept(&self, stream: TcpStream) -> io::Result<Accepted> {
+        match self {
+            Connections::Plain => Ok(Accepted {
+                stream: Box::new(stream),
+                senders: None,
+            }),
+            #[cfg(feature = "tls")]
+            Connections::Tls(tls) => tls.accept(stream),
+        }
+    }
+
+    /// Where the frames for `peer` are written to over `stream`
+    fn connect(&self, _peer: ServerId, stream: TcpStream) -> io::Result<Box<dyn Connection>> {
+        match self {
+            Connections::Plain => Ok(Box::new(stream)),
+            #[cfg(feature = "tls")]
+            Connections::Tls(tls) => tls.connect(_peer, stream),
+        }
+    }
+}
+
+/// Whether a peer is reachable, as far as the transport's keepalives go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerLiveness {
+    /// Whether the connection to the peer is open, and the peer answered a keepalive over it
+    /// lately. A peer too old to answer keepalives is alive while its connection is open.
+    pub alive: bool,
+    /// When the peer last answered a keepalive, or a handshake. `None` if it never did.
+    pub last_heard: Option<Instant>,
+}
+
+/// Where every peer of a [`TcpTransport`] stands, kept up to date by its keepalives. Stays usable
+/// once the transport was handed to the node, so the application and operators can check which
+/// peers are reachable whatever Raft is doing.
+#[derive(Debug, Clone)]
+pub struct PeerHealth {
+    states: Arc<PeerStates>,
+}
+impl PeerHealth {
+    /// How `peer` is doing, `None` if it isn't one of the transport's peers
+    pub fn liveness(&self, peer: ServerId) -> Option<PeerLiveness> {
+        self.states.liveness().get(&peer).copied()
+    }
+
+    /// How every peer is doing
+    pub fn peers(&self) -> HashMap<ServerId, PeerLiveness> {
+        self.states.liveness().clone()
+    }
+
+    /// The peers currently alive
+    pub fn alive(&self) -> HashSet<ServerId> {
+        self.states
+            .liveness()
+            .iter()
+            .filter(|(_, liveness)| liveness.alive)
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+}
+
+/// Where the connection to every peer stands, kept by the threads writing to them
+#[derive(Debug, Default)]
+struct PeerStates {
+    connections: Mutex<HashMap<ServerId, PeerConnection>>,
+    liveness: Mutex<HashMap<ServerId, PeerLiveness>>,
+    metrics: OnceLock<RaftMetrics>,
+}
+impl PeerStates {
+    fn liveness(&self) -> std::sync::MutexGuard<'_, HashMap<ServerId, PeerLiveness>> {
+        self.liveness
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn record_liveness(&self, peer: ServerId, liveness: PeerLiveness) {
+        let _ = self.liveness().insert(peer, liveness);
+    }
+
+    fn record(&self, peer: ServerId, connection: PeerConnection) {
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = connections.insert(peer, connection);
+        if let Some(metrics) = self.metrics.get() {
+            metrics.record_peer_connection(peer, connection);
+        }
+    }
+
+    fn record_dropped(&self, messages: usize) {
+        if let Some(metrics) = self.metrics.get() {
+            for _ in 0..messages {
+                metrics.record_rpc_dropped();
+            }
+        }
+    }
+
+    /// Records into `metrics` from now on, starting with where every connection stands
+    fn attach(&self, metrics: RaftMetrics) {
+        let connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (peer, connection) in connections.iter() {
+            metrics.record_peer_connection(*peer, *connection);
+        }
+        let _ = self.metrics.set(metrics);
+    }
+}
+
+/// Rate limits set on the transport, read by the threads writing to each peer
+#[derive(Debug, Default)]
+struct RateLimits {
+    every_peer: RateLimit,
+    peers: HashMap<ServerId, RateLimit>,
+}
+impl RateLimits {
+    fn for_peer(&self, peer: ServerId) -> RateLimit {
+        self.peers.get(&peer).copied().unwrap_or(self.every_peer)
+    }
+}
+
+/// The connections peers opened to this server, closed when the transport is dropped
+#[derive(Debug, Default)]
+struct Inbound {
+    closed: AtomicBool,
+    /// The connections still read from, by the order they were accepted in
+    streams: Mutex<HashMap<u64, TcpStream>>,
+}
+impl Inbound {
+    fn streams(&self) -> std::sync::MutexGuard<'_, HashMap<u64, TcpStream>> {
+        self.streams
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Network transport over TCP. Every server accepts connections on its own listener and keeps one
+/// open to each peer, requests and replies alike go out over the connection to the server they're
+/// addressed to. Messages are frames behind their protocol version and length, encoded by the
+/// transport's [`WireCodec`], which every server of the cluster has to share.
+///
+/// A connection the peer closed, or that broke, is opened again in the background, with jittered
+/// exponential backoff while the peer can't be reached. Meanwhile messages for the peer are held,
+/// up to 8 MiB of them, past that the oldest are dropped and Raft sends them again. Where every
+/// connection stands is recorded into the node's [`RaftMetrics`]. What goes out to a peer can be
+/// held to a [`RateLimit`], the frames past it waiting in the same buffer. Messages are no bigger
+/// than [`TcpTransport::max_message_size`], which the node splits what the leader sends to fit.
+///
+/// Every connection carries keepalives, independent of Raft's heartbeats, which a peer answers
+/// over the same connection. A peer that stops answering them has its connection closed and
+/// opened again, even if the connection looks open, and [`TcpTransport::peer_health`] tells which
+/// peers are alive.
+///
+/// A connection starts with the connecting server offering the protocol versions it speaks, and
+/// the peer picking the newest one both speak, so servers of different builds can run side by side
+/// during a rolling upgrade. A peer that speaks none of them is refused rather than sent frames it
+/// would misread. Connections are plain TCP unless the transport is started with TLS, through the
+/// `tls` feature.
+#[derive(Debug)]
+pub struct TcpTransport<C: LogCommand> {
+    codec: Arc<dyn WireCodec<C>>,
+    /// Messages read off every inbound connection
+    incoming: mpsc::Receiver<RpcMessage<C>>,
+    /// Frames for the thread writing to each peer's connection
+    outgoing: HashMap<ServerId, mpsc::Sender<Vec<u8>>>,
+    local_addr: SocketAddr,
+    inbound: Arc<Inbound>,
+    peer_states: Arc<PeerStates>,
+    rate_limits: Arc<Mutex<RateLimits>>,
+    max_message_size: Arc<AtomicUsize>,
+}
+impl<C: LogCommand + 'static> TcpTransport<C> {
+    /// Accepts connections from peers on `listener`, and reaches every peer at its address in
+    /// `peers`. Messages are encoded with [`BincodeWireCodec`].
+    pub fn new(listener: TcpListener, peers: HashMap<ServerId, SocketAddr>) -> io::Result<Self> {
+        Self::with_codec(listener, peers, Arc::new(BincodeWireCodec::new()))
+    }
+
+    /// Like [`TcpTransport::new`], but encodes messages with `codec`
+    pub fn with_codec(
+        listener: TcpListener,
+        peers: HashMap<ServerId, SocketAddr>,
+        codec: Arc<dyn WireCodec<C>>,
+    ) -> io::Result<Self> {
+        Self::start(listener, peers, codec, Connections::Plain)
+    }
+
+    /// Like [`TcpTransport::with_codec`], but connections go over TLS set up from `tls`
+    #[cfg(feature = "tls")]
+    pub fn with_tls(
+        listener: TcpListener,
+        peers: HashMap<ServerId, SocketAddr>,
+        codec: Arc<dyn WireCodec<C>>,
+        mut tls: TlsConfig,
+    ) -> io::Result<Self> {
+        tls.name_peers_by_address(&peers);
+        Self::start(listener, peers, codec, Connections::Tls(Arc::new(tls)))
+    }
+
+    fn start(
+        listener: TcpListener,
+        peers: HashMap<ServerId, SocketAddr>,
+        codec: Arc<dyn WireCodec<C>>,
+        connections: Connections,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (incoming_tx, incoming) = mpsc::channel();
+        let inbound = Arc::new(Inbound::default());
+        let max_message_size = Arc::new(AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE));
+        {
+            let inbound = inbound.clone();
+            let codec = codec.clone();
+            let connections = connections.clone();
+            let max_message_size = max_message_size.clone();
+            let _ = thread::Builder::new()
+                .name(format!("tcp-accept-{}", local_addr))
+                .spawn(move || {
+                    accept_connections(
+                        listener,
+                        inbound,
+                        incoming_tx,
+                        codec,
+                        connections,
+                        max_message_size,
+                    )
+                })?;
+        }
+        let mut outgoing = HashMap::new();
+        let peer_states = Arc::new(PeerStates::default());
+        let rate_limits = Arc::new(Mutex::new(RateLimits::default()));
+        for (server_id, addr) in peers {
+            peer_states.record_liveness(
+                server_id,
+                PeerLiveness {
+                    alive: false,
+                    last_heard: None,
+                },
+            );
+            let (frames_tx, frames) = mpsc::channel();
+            let connections = connections.clone();
+            let peer_states = peer_states.clone();
+            let rate_limits = rate_limits.clone();
+            let _ = thread::Builder::new()
+                .name(format!("tcp-write-{}", addr))
+                .spawn(move || {
+                    write_to_peer(
+                        server_id,
+                        addr,
+                        frames,
+                        &connections,
+                        &peer_states,
+                        &rate_limits,
+                    )
+                })?;
+            let _ = outgoing.insert(server_id, frames_tx);
+        }
+        Ok(TcpTransport {
+            codec,
+            incoming,
+            outgoing,
+            local_addr,
+            inbound,
+            peer_states,
+            rate_limits,
+            max_message_size,
+        })
+    }
+}
+impl<C: LogCommand> TcpTransport<C> {
+    /// The address peers connect to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Most bytes of one encoded message, 256 MiB by default. Messages past it aren't sent, and a
+    /// peer's connection is closed once it sends one, so every server of the cluster should set
+    /// the same.
+    pub fn max_message_size(self, bytes: usize) -> Self {
+        self.max_message_size.store(bytes, Ordering::SeqCst);
+        self
+    }
+
+    /// Holds what's sent to every peer to `limit`, each peer on its own
+    pub fn rate_limit(self, limit: RateLimit) -> Self {
+        self.rate_limits().every_peer = limit;
+        self
+    }
+
+    /// Holds what's sent to `peer` to `limit`, rather than the one set for every peer
+    pub fn peer_rate_limit(self, peer: ServerId, limit: RateLimit) -> Self {
+        let _ = self.rate_limits().peers.insert(peer, limit);
+        self
+    }
+
+    fn rate_limits(&self) -> std::sync::MutexGuard<'_, RateLimits> {
+        self.rate_limits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Which peers are alive, as the transport's keepalives find out
+    pub fn peer_health(&self) -> PeerHealth {
+        PeerHealth {
+            states: self.peer_states.clone(),
+        }
+    }
+
+    /// Hands `message` to the thread writing to the connection to `to`
+    fn send(&self, to: ServerId, message: RpcMessage<C>) -> Result<(), RaftTransportError> {
+        let Some(frames) = self.outgoing.get(&to) else {
+            warn!("Dropping message for {:?}, it isn't a known peer", to);
+            return Ok(());
+        };
+        let body = match self.codec.encode(&message) {
+            Ok(body) if body.len() <= self.max_message_size.load(Ordering::SeqCst) => body,
+            Ok(body) => {
+                warn!(
+                    "Dropping message {:?} for {:?}, its {} bytes are too many for a frame",
+                    message.request_id(),
+                    to,
+                    body.len()
+                );
+                self.peer_states.record_dropped(1);
+                return Ok(());
+            }
+            Err(error) => {
+                warn!(
+                    "Dropping message {:?} for {:?}, it couldn't be encoded: {}",
+                    message.request_id(),
+                    to,
+                    error
+                );
+                self.peer_states.record_dropped(1);
+                return Ok(());
+            }
+        };
+        // The writing thread fills in the version agreed on with the peer
+        let mut frame = Vec::with_capacity(5 + body.len());
+        frame.push(PROTOCOL_VERSION);
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&body);
+        frames
+            .send(frame)
+            .map_err(|_| RaftTransportError::TransportShutdown)
+    }
+}
+impl<C: LogCommand> RaftTransportConnector<C> for TcpTransport<C> {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        max_wait: Duration,
+    ) -> Result<Option<RpcMessage<C>>, RaftTransportError> {
+        match self.incoming.recv_timeout(max_wait) {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(RaftTransportError::TransportShutdown),
+        }
+    }
+
+    /// Sends `reply` to the server whose request it answers, over this server's connection to it
+    fn enqueue_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        self.send(reply.to(), RpcMessage::Reply(reply))
+    }
+
+    fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError> {
+        self.send(request.to(), RpcMessage::Request(request))
+    }
+
+    /// Records where the connection to every peer stands into `metrics`, and the messages dropped
+    /// from a full buffer or for not fitting in a frame
+    fn attach_metrics(&mut self, metrics: RaftMetrics) {
+        self.peer_states.attach(metrics);
+    }
+
+    fn message_size_limit(&self) -> Option<usize> {
+        Some(self.max_message_size.load(Ordering::SeqCst))
+    }
+}
+impl<C: LogCommand> Drop for TcpTransport<C> {
+    fn drop(&mut self) {
+        self.inbound.closed.store(true, Ordering::SeqCst);
+        for (_, stream) in self.inbound.streams().drain() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        // Wakes the accepting thread so it sees the transport is gone
+        let _ = TcpStream::connect_timeout(&self.local_addr, CONNECT_TIMEOUT);
+    }
+}
+
+/// Reads every connection accepted on `listener` on a thread of its own, until the transport is
+/// dropped
+fn accept_connections<C: LogCommand + 'static>(
+    listener: TcpListener,
+    inbound: Arc<Inbound>,
+    incoming: mpsc::Sender<RpcMessage<C>>,
+    codec: Arc<dyn WireCodec<C>>,
+    connections: Connections,
+    max_message_size: Arc<AtomicUsize>,
+) {
+    for (connection_id, stream) in (0..).zip(listener.incoming()) {
+        if inbound.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Could not accept a connection: {}", error);
+                continue;
+            }
+        };
+        match stream.try_clone() {
+            Ok(clone) => {
+                let _ = inbound.streams().insert(connection_id, clone);
+            }
+            Err(error) => {
+                warn!("Dropping a connection that couldn't be tracked: {}", error);
+                continue;
+            }
+        }
+        let incoming = incoming.clone();
+        let codec = codec.clone();
+        let connections = connections.clone();
+        let max_message_size = max_message_size.clone();
+        let reading_inbound = inbound.clone();
+        if let Err(error) = thread::Builder::new()
+            .name("tcp-read".to_string())
+            .spawn(move || {
+                let peer_addr = stream.peer_addr().ok();
+                let accepted = connections.accept(stream).and_then(|mut accepted| {
+                    let version = choose_version(&mut *accepted.stream)?;
+                    Ok((accepted, version))
+                });
+                match accepted {
+                    Ok((accepted, version)) => read_from_peer(
+                        accepted,
+                        version,
+                        peer_addr,
+                        incoming,
+                        &*codec,
+                        &max_message_size,
+                    ),
+                    Err(error) => warn!("Refused connection from {:?}: {}", peer_addr, error),
+                }
+                // Closes the connection for good, the transport has no more use for it
+                let _ = reading_inbound.streams().remove(&connection_id);
+            })
+        {
+            warn!(
+                "Dropping a connection that couldn't be read from: {}",
+                error
+            );
+            let _ = inbound.streams().remove(&connection_id);
+        }
+    }
+}
+
+/// Answers the handshake a peer starts `stream` with, returning the version it picked for the
+/// peer's frames
+fn choose_version(stream: &mut dyn Connection) -> io::Result<u8> {
+    let mut handshake = [0; 6];
+    stream.read_exact(&mut handshake)?;
+    if handshake[..4] != HANDSHAKE_MAGIC[..] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "peer didn't start with a handshake",
+        ));
+    }
+    let (oldest, newest) = (handshake[4], handshake[5]);
+    let version = newest.min(PROTOCOL_VERSION);
+    if version < oldest.max(MIN_PROTOCOL_VERSION) {
+        stream.write_all(&[NO_COMMON_VERSION])?;
+        stream.flush()?;
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "peer speaks protocol versions {} to {}, this server {} to {}",
+                oldest, newest, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        ));
+    }
+    stream.write_all(&[version])?;
+    stream.flush()?;
+    Ok(version)
+}
+
+/// Starts `stream` with the handshake, returning the version the peer picked for this server's
+/// frames
+fn offer_versions(stream: &mut dyn Connection) -> io::Result<u8> {
+    let mut handshake = [0; 6];
+    handshake[..4].copy_from_slice(HANDSHAKE_MAGIC);
+    handshake[4] = MIN_PROTOCOL_VERSION;
+    handshake[5] = PROTOCOL_VERSION;
+    stream.write_all(&handshake)?;
+    stream.flush()?;
+    let mut version = [0; 1];
+    stream.read_exact(&mut version)?;
+    match version[0] {
+        NO_COMMON_VERSION => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "peer speaks none of this server's protocol versions",
+        )),
+        version if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) => Ok(version),
+        version => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("peer picked protocol version {} it wasn't offered", version),
+        )),
+    }
+}
+
+/// Hands every message read off `stream` to the node, and answers every keepalive, until the
+/// connection closes, a frame isn't in the agreed `version`, is too big or doesn't decode, or a message comes
+/// from a server the peer wasn't authenticated as
+fn read_from_peer<C: LogCommand>(
+    Accepted {
+        mut stream,
+        senders,
+    }: Accepted,
+    version: u8,
+    peer_addr: Option<SocketAddr>,
+    incoming: mpsc::Sender<RpcMessage<C>>,
+    codec: &dyn WireCodec<C>,
+    max_message_size: &AtomicUsize,
+) {
+    loop {
+        let mut header = [0; 5];
+        if stream.read_exact(&mut header).is_err() {
+            debug!("Connection from {:?} closed", peer_addr);
+            return;
+        }
+        if header[0] != version {
+            warn!(
+                "Closing connection from {:?}, a frame is in protocol version {} rather than {}",
+                peer_addr, header[0], version
+            );
+            return;
+        }
+        let body_len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if body_len > max_message_size.load(Ordering::SeqCst) {
+            warn!(
+                "Closing connection from {:?}, a frame claims {} bytes",
+                peer_addr, body_len
+            );
+            return;
+        }
+        if body_len == 0 && version >= KEEPALIVE_VERSION {
+            if stream
+                .write_all(&[PONG])
+                .and_then(|_| stream.flush())
+                .is_err()
+            {
+                debug!("Connection from {:?} closed", peer_addr);
+                return;
+            }
+            continue;
+        }
+        let mut body = vec![0; body_len];
+        if stream.read_exact(&mut body).is_err() {
+            debug!("Connection from {:?} closed mid frame", peer_addr);
+            return;
+        }
+        let message = match codec.decode(&body) {
+            Ok(message) => message,
+            Err(error) => {
+                warn!(
+                    "Closing connection from {:?}, a frame didn't decode: {}",
+                    peer_addr, error
+                );
+                return;
+            }
+        };
+        if let Some(senders) = &senders {
+            if !senders.contains(&message.from()) {
+                warn!(
+                    "Closing connection from {:?}, it sent a message from {:?} it isn't authenticated as",
+                    peer_addr,
+                    message.from()
+                );
+                return;
+            }
+        }
+        if incoming.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+/// Frames waiting for the connection to a peer, in the order they were sent
+#[derive(Debug, Default)]
+struct FrameBuffer {
+    frames: VecDeque<Vec<u8>>,
+    bytes: usize,
+}
+impl FrameBuffer {
+    /// Adds `frame`, returning how many of the oldest frames were dropped to stay within
+    /// [`MAX_BUFFERED_BYTES`]. The newest frame is kept whatever its size.
+    fn push(&mut self, frame: Vec<u8>) -> usize {
+        self.bytes += frame.len();
+        self.frames.push_back(frame);
+        let mut dropped = 0;
+        while self.bytes > MAX_BUFFERED_BYTES && self.frames.len() > 1 {
+            if let Some(oldest) = self.frames.pop_front() {
+                self.bytes -= oldest.len();
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// Drops every frame, returning how many there were
+    fn clear(&mut self) -> usize {
+        let dropped = self.frames.len();
+        self.frames.clear();
+        self.bytes = 0;
+        dropped
+    }
+}
+
+/// This server's connection to a peer
+struct Outbound {
+    stream: Box<dyn Connection>,
+    /// The protocol version the peer picked
+    version: u8,
+    checked_at: Instant,
+    pinged_at: Instant,
+    /// When the peer last answered a keepalive, or the handshake
+    heard_at: Instant,
+}
+impl Outbound {
+    /// Connects to `peer` at `addr` and goes through the handshake
+    fn open(peer: ServerId, addr: SocketAddr, connections: &Connections) -> io::Result<Self> {
+        let socket = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        let _ = socket.set_nodelay(true);
+        // A peer that never answers the handshake is given up on
+        socket.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        // A peer that stops reading is given up on like one that stops answering keepalives,
+        // rather than holding up the writes to it for good
+        socket.set_write_timeout(Some(KEEPALIVE_TIMEOUT))?;
+        let probe = socket.try_clone()?;
+        let mut stream = connections.connect(peer, socket)?;
+        let version = offer_versions(&mut *stream)?;
+        // From here on reads only pick up pongs, or find the peer closed the connection
+        probe.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        let now = Instant::now();
+        Ok(Outbound {
+            stream,
+            version,
+            checked_at: now,
+            pinged_at: now,
+            heard_at: now,
+        })
+    }
+
+    fn keepalives(&self) -> bool {
+        self.version >= KEEPALIVE_VERSION
+    }
+
+    /// Whether the peer closed the connection, or stopped answering keepalives. Peers only write
+    /// pongs to connections they accepted, so reading times out unless it picks some up, or hits
+    /// the end of the connection or an error.
+    fn closed(&mut self) -> bool {
+        self.checked_at = Instant::now();
+        let mut pongs = [0; 64];
+        let closed = match self.stream.read(&mut pongs) {
+            Ok(0) => true,
+            Ok(read) if pongs[..read].iter().all(|byte| *byte == PONG) => {
+                self.heard_at = self.checked_at;
+                false
+            }
+            Ok(_) => true,
+            Err(error) => !matches!(
+                error.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+            ),
+        };
+        closed || (self.keepalives() && self.heard_at.elapsed() > KEEPALIVE_TIMEOUT)
+    }
+
+    /// Writes a keepalive, an empty frame, if the last one went out long enough ago
+    fn ping_if_due(&mut self) -> io::Result<()> {
+        if !self.keepalives() || self.pinged_at.elapsed() < KEEPALIVE_INTERVAL {
+            return Ok(());
+        }
+        self.pinged_at = Instant::now();
+        self.stream.write_all(&[self.version, 0, 0, 0, 0])?;
+        self.stream.flush()
+    }
+
+    /// Writes out the frames in `buffer` that `throttle` lets through, taking them off as they're
+    /// written. Returns how long until the next frame held back may go out, `None` if every frame
+    /// was written.
+    fn write(
+        &mut self,
+        buffer: &mut FrameBuffer,
+        throttle: &mut Throttle,
+    ) -> io::Result<Option<Duration>> {
+        while let Some(frame) = buffer.frames.front_mut() {
+            let wait = throttle.wait(frame.len());
+            if !wait.is_zero() {
+                self.stream.flush()?;
+                return Ok(Some(wait));
+            }
+            frame[0] = self.version;
+            self.stream.write_all(frame)?;
+            throttle.sent(frame.len());
+            if let Some(written) = buffer.frames.pop_front() {
+                buffer.bytes -= written.len();
+            }
+        }
+        self.stream.flush()?;
+        Ok(None)
+    }
+}
+
+/// Keeps a connection open to `peer` at `addr` and writes every frame handed over to it, one at a
+/// time so they arrive in order and no faster than the peer's rate limit, with keepalives in
+/// between. A connection found closed, that
+/// breaks, whose writes time out, or whose keepalives go unanswered, is opened again as the peer's [`Backoff`] allows,
+/// the frames buffered meanwhile. Stops once the transport is dropped.
+fn write_to_peer(
+    peer: ServerId,
+    addr: SocketAddr,
+    frames: mpsc::Receiver<Vec<u8>>,
+    connections: &Connections,
+    peer_states: &PeerStates,
+    rate_limits: &Mutex<RateLimits>,
+) {
+    let mut outbound: Option<Outbound> = None;
+    let mut buffer = FrameBuffer::default();
+    let mut backoff = Backoff::default();
+    let mut recorded = None;
+    let mut recorded_liveness = None;
+    let mut heard_at = None;
+    let mut throttle = Throttle::default();
+    let mut wait = PROBE_INTERVAL;
+    loop {
+        match frames.recv_timeout(wait) {
+            Ok(frame) => peer_states.record_dropped(buffer.push(frame)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if outbound.as_mut().is_some_and(|outbound| {
+            outbound.checked_at.elapsed() >= PROBE_INTERVAL && outbound.closed()
+        }) {
+            debug!("Connection to {} was closed, or stopped answering", addr);
+            outbound = None;
+        }
+        if outbound.is_none() && !backoff.waiting() {
+            match Outbound::open(peer, addr, connections) {
+                Ok(opened) => {
+                    backoff.succeeded();
+                    outbound = Some(opened);
+                }
+                Err(error) => {
+                    let wait = backoff.failed();
+                    debug!(
+                        "Could not connect to {}, retrying in {:?}: {}",
+                        addr, wait, error
+                    );
+                    if !backoff::is_transient(&error) {
+                        // The peer won't take them any time soon
+                        peer_states.record_dropped(buffer.clear());
+                    }
+                }
+            }
+        }
+        throttle.limit(
+            rate_limits
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .for_peer(peer),
+        );
+        wait = PROBE_INTERVAL;
+        if let Some(connected) = outbound.as_mut() {
+            match connected
+                .ping_if_due()
+                .and_then(|_| connected.write(&mut buffer, &mut throttle))
+            {
+                Ok(held) => wait = held.map_or(PROBE_INTERVAL, |held| held.min(PROBE_INTERVAL)),
+                Err(error) => {
+                    debug!("Connection to {} broke: {}", addr, error);
+                    outbound = None;
+                }
+            }
+        }
+        let connection = PeerConnection {
+            connected: outbound.is_some(),
+            buffered_messages: buffer.frames.len() as u64,
+        };
+        if recorded != Some(connection) {
+            peer_states.record(peer, connection);
+            recorded = Some(connection);
+        }
+        if let Some(connected) = &outbound {
+            heard_at = Some(connected.heard_at);
+        }
+        let liveness = PeerLiveness {
+            alive: outbound.is_some(),
+            last_heard: heard_at,
+        };
+        if recorded_liveness != Some(liveness) {
+            peer_states.record_liveness(peer, liveness);
+            recorded_liveness = Some(liveness);
+        }
+    }
+}
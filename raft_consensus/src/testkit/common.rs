@@ -0,0 +1,112 @@
+use crate::system_clock::{Clock, Instant, ManualClock};
+use crate::{rpc_messages::RpcMessage, LogCommand, ServerId};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, ops::Add, time::Duration};
+
+/// A command for simulations that don't need one of their own
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SimLogCommand(pub u64);
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord, Hash)]
+pub struct SimTime(pub Duration);
+impl SimTime {
+    pub fn checked_sub(&self, other: &Self) -> Option<Duration> {
+        self.0.checked_sub(other.0)
+    }
+
+    pub fn from_millis(millis: u64) -> Self {
+        SimTime(Duration::from_millis(millis))
+    }
+
+    pub fn as_millis(&self) -> u128 {
+        self.0.as_millis()
+    }
+}
+
+/// The simulation's clock, which only moves when the simulator moves it. Every server reads the
+/// same time off it, each simulation has its own. Clones read and move the same time.
+#[derive(Debug, Clone)]
+pub struct SimClock {
+    clock: ManualClock,
+    started_at: Instant,
+}
+impl SimClock {
+    /// A clock at the start of a simulation
+    pub fn new() -> Self {
+        let clock = ManualClock::new();
+        let started_at = clock.now();
+        SimClock { clock, started_at }
+    }
+
+    /// How far the simulation got
+    pub fn now(&self) -> SimTime {
+        SimTime(self.clock.now() - self.started_at)
+    }
+
+    /// Moves the simulation forward by `by`
+    pub fn advance(&self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// The clock servers are handed, reading the time this one moves to
+    pub fn manual_clock(&self) -> ManualClock {
+        self.clock.clone()
+    }
+}
+impl Default for SimClock {
+    fn default() -> Self {
+        SimClock::new()
+    }
+}
+
+impl Add<SimTime> for SimTime {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        SimTime(self.0 + rhs.0)
+    }
+}
+impl Add<Duration> for SimTime {
+    type Output = Self;
+    fn add(self, rhs: Duration) -> Self::Output {
+        SimTime(self.0 + rhs)
+    }
+}
+impl From<Duration> for SimTime {
+    fn from(d: Duration) -> Self {
+        SimTime(d)
+    }
+}
+impl From<SimTime> for Duration {
+    fn from(t: SimTime) -> Self {
+        t.0
+    }
+}
+
+/// Something the simulator does at a [`SimulatorEvent`]'s time
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum SimulatorAction<C: LogCommand> {
+    SendOverNetwork(Box<RpcMessage<C>>),
+    PartitionNetwork(Vec<HashSet<ServerId>>),
+    HealNetworkPartition,
+    InjectIOFailureEveryNOps(u64),
+    RestoreIOFunctioning,
+}
+/// An action the simulator carries out once its clock reaches `time`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SimulatorEvent<C: LogCommand> {
+    pub time: SimTime,
+    pub action: SimulatorAction<C>,
+}
+impl<C: LogCommand> PartialOrd for SimulatorEvent<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<C: LogCommand> Ord for SimulatorEvent<C> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.time.0.cmp(&other.time.0)
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct WakeUpAtOrBefore(pub SimTime);
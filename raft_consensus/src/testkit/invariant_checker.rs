@@ -1,4 +1,7 @@
-use raft_consensus::{RaftNodeState, RaftStateEvent, RaftStateEventCollector, ServerId, TermIndex};
+use crate::{
+    ChannelOverflowPolicy, ChannelRaftEventCollector, LogCommand, RaftEvent, RaftNodeState,
+    RaftStateEvent, ServerId, TermIndex,
+};
 use tracing::info;
 
 use std::{
@@ -11,77 +14,86 @@ use super::{
     sim_log::{SimLog, SimLogEntry},
 };
 
-/// Our implementation of raft allows for collection of events that represent the current state
-/// of the raft node. This is used to check invariants of the raft algorithm.
-/// This is a simple implementation that collects all events and then checks them at each step of the simulation.
-/// It uses a channel to send events from the server processes to the aggregator which is used by the
-/// simulation to check invariants. The channel is needed since the simulated raft node runs in a separate thread.
-#[derive(Clone)]
-pub(crate) struct ServerProcessRaftStateEventCollector {
-    event_tx: mpsc::Sender<RaftStateEvent>,
-}
-impl RaftStateEventCollector for ServerProcessRaftStateEventCollector {
-    fn push_event(&mut self, event: RaftStateEvent) {
-        self.event_tx.send(event).unwrap_or_default();
-    }
-}
+/// Max number of events buffered between two invariant checks.
+/// Server processes block when it's reached, the checker must see every event to verify the invariants.
+const EVENT_CHANNEL_CAPACITY: usize = 10_000;
 
 /// This is used by the simulation to check invariants of the raft implementation.
 /// This collects all events from all servers, for each incoming event it updates the current state of
-/// the server the event is from. It then uses the states of the servers to check that invariants are not violated.
-pub(crate) struct InvariantChecker {
+/// the server the event is from. Server processes send their events over a channel since the simulated
+/// raft node runs in a separate thread.
+/// It then uses the states of the servers to check that invariants are not violated.
+/// Leadership and vote events are also tracked over the whole simulation, since a snapshot of current states
+/// can miss a violation that happened between two checks.
+pub struct InvariantChecker {
     server_states: HashMap<ServerId, RaftStateEvent>,
-    event_tx: mpsc::Sender<RaftStateEvent>,
-    event_rx: mpsc::Receiver<RaftStateEvent>,
+    leaders_elected: HashMap<TermIndex, ServerId>,
+    votes_granted: HashMap<(ServerId, TermIndex), ServerId>,
+    event_collector: ChannelRaftEventCollector,
+    event_rx: mpsc::Receiver<RaftEvent>,
 }
 impl InvariantChecker {
-    pub(crate) fn new() -> Self {
-        let (event_tx, event_rx) = mpsc::channel();
+    pub fn new() -> Self {
+        let (event_collector, event_rx) =
+            ChannelRaftEventCollector::new(EVENT_CHANNEL_CAPACITY, ChannelOverflowPolicy::Block);
         Self {
             server_states: HashMap::new(),
-            event_tx,
+            leaders_elected: HashMap::new(),
+            votes_granted: HashMap::new(),
+            event_collector,
             event_rx,
         }
     }
 
     /// Get a new RaftStateEventCollector that can be used to collect events from a server process.
-    pub(crate) fn event_collector_for_server(&self) -> ServerProcessRaftStateEventCollector {
-        ServerProcessRaftStateEventCollector {
-            event_tx: self.event_tx.clone(),
-        }
+    pub fn event_collector_for_server(&self) -> ChannelRaftEventCollector {
+        self.event_collector.clone()
     }
 
     /// Get the current state of all servers. Returns a cloned copy of the state.
-    pub(crate) fn get_current_state(&self) -> HashMap<ServerId, RaftStateEvent> {
+    pub fn get_current_state(&self) -> HashMap<ServerId, RaftStateEvent> {
         self.server_states
             .iter()
-            .map(|(id, state)| (id.clone(), state.clone()))
+            .map(|(id, state)| (*id, *state))
             .collect()
     }
 
-    pub(crate) fn get_current_leader(&self) -> Option<ServerId> {
+    pub fn get_current_leader(&self) -> Option<ServerId> {
         for (id, state) in self.server_states.iter() {
             if let RaftNodeState::Leader = state.current_state {
-                return Some(id.clone());
+                return Some(*id);
             }
         }
         None
     }
 
     /// Check that Raft invariants are not violated.
-    pub(crate) fn check_invariants(&mut self, time: SimTime, log: &mut SimLog) {
+    pub fn check_invariants<C: LogCommand>(&mut self, time: SimTime, log: &mut SimLog<C>) {
         let old_server_states = self.server_states.clone();
         while let Ok(event) = self.event_rx.try_recv() {
-            self.check_state_change_invariants(event);
-            self.server_states.insert(event.server_id, event);
+            match event {
+                RaftEvent::StateChanged(state) => {
+                    self.check_state_change_invariants(state);
+                    self.server_states.insert(state.server_id, state);
+                }
+                RaftEvent::BecameLeader { server_id, term } => {
+                    self.check_leader_elected_invariants(server_id, term);
+                }
+                RaftEvent::VoteGranted {
+                    server_id,
+                    candidate_id,
+                    term,
+                } => {
+                    self.check_vote_granted_invariants(server_id, candidate_id, term);
+                }
+                _ => {}
+            }
         }
         let current_state = self.get_current_state();
 
-        let new_state_has_changes = current_state.iter().any(|(server_id, old_state)| {
-            old_server_states
-                .get(server_id)
-                .map_or(true, |new_state| old_state != new_state)
-        });
+        let new_state_has_changes = current_state
+            .iter()
+            .any(|(server_id, old_state)| old_server_states.get(server_id) != Some(old_state));
         let servers_removed = old_server_states
             .keys()
             .any(|id| !current_state.contains_key(id));
@@ -112,10 +124,39 @@ impl InvariantChecker {
         }
     }
 
+    /// Property 2 (Election Safety) checked against every election won during the simulation,
+    /// not just the leaders that are still around when invariants are checked.
+    fn check_leader_elected_invariants(&mut self, server_id: ServerId, term: TermIndex) {
+        let leader = *self.leaders_elected.entry(term).or_insert(server_id);
+        assert_eq!(
+            leader, server_id,
+            "CLUSTER INVARIANT VIOLATED: {server_id:?} won the election for term {term:?} but {leader:?} already won it!",
+        );
+    }
+
+    /// A server may only vote for one candidate per term (§5.2).
+    /// Granting the vote again to the same candidate is allowed, e.g. when a vote request is retried.
+    fn check_vote_granted_invariants(
+        &mut self,
+        server_id: ServerId,
+        candidate_id: ServerId,
+        term: TermIndex,
+    ) {
+        let vote = *self
+            .votes_granted
+            .entry((server_id, term))
+            .or_insert(candidate_id);
+        assert_eq!(
+            vote, candidate_id,
+            "CLUSTER INVARIANT VIOLATED: {server_id:?} voted for {candidate_id:?} in term {term:?} but already voted for {vote:?}!",
+        );
+    }
+
     /// There should only be one leader chosen for a term, this means that:
     /// - Only one node that believes it is the leader for a term
     /// - All nodes should agree on who the leader is for that term
-    /// See: <https://homes.cs.washington.edu/~mernst/pub(crate)s/raft-proof-cpp2016.pdf>
+    ///
+    /// See: <https://homes.cs.washington.edu/~mernst/pubs/raft-proof-cpp2016.pdf>
     /// Property 2 (Election Safety). There is at most one leader per term.
     fn assert_at_most_one_leader_in_term(&mut self) {
         // TermIndex -> Set nodes that believe they are the leader for that term
@@ -126,21 +167,18 @@ impl InvariantChecker {
         let mut nodes_that_other_nodes_see_as_leaders =
             HashMap::<TermIndex, HashSet<ServerId>>::new();
 
-        for (_, server_state) in &self.server_states {
-            match server_state.current_state {
-                RaftNodeState::Leader => {
-                    nodes_that_think_they_are_leaders
-                        .entry(server_state.current_term)
-                        .and_modify(|servers| {
-                            servers.insert(server_state.server_id);
-                        })
-                        .or_insert_with(|| {
-                            let mut servers = HashSet::new();
-                            servers.insert(server_state.server_id);
-                            servers
-                        });
-                }
-                _ => (),
+        for server_state in self.server_states.values() {
+            if server_state.current_state == RaftNodeState::Leader {
+                nodes_that_think_they_are_leaders
+                    .entry(server_state.current_term)
+                    .and_modify(|servers| {
+                        servers.insert(server_state.server_id);
+                    })
+                    .or_insert_with(|| {
+                        let mut servers = HashSet::new();
+                        servers.insert(server_state.server_id);
+                        servers
+                    });
             }
 
             nodes_that_other_nodes_see_as_leaders
@@ -172,15 +210,15 @@ impl InvariantChecker {
             });
         nodes_that_other_nodes_see_as_leaders.iter().for_each(|(term, leaders)| {
                 if leaders.len() > 1 {
-                   for (_, server_state) in &self.server_states {
-                        server_state.leader_for_term.map(|leader| {
+                   for server_state in self.server_states.values() {
+                        if let Some(leader) = server_state.leader_for_term {
                             info!(
                                 "Node {node:?} believes leader for term {term:?} is {leader:?}",
                                 node = server_state.server_id,
                                 term = server_state.current_term,
                                 leader = leader
                             );
-                        });
+                        }
                     }
                 }
 
@@ -193,3 +231,8 @@ impl InvariantChecker {
             });
     }
 }
+impl Default for InvariantChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
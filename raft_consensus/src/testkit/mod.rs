@@ -0,0 +1,556 @@
+//! Deterministic cluster simulations. Every server runs its real Raft thread, talking over a simulated
+//! network with latency and packet loss, while the simulator moves their clock forward and checks
+//! the Raft invariants against the events servers publish. Generic over the log command so
+//! applications can simulate clusters replicating their own commands.
+//!
+//! Each simulation has a clock of its own, but the storage fault injection is process wide, run
+//! simulations injecting I/O failures one at a time.
+pub mod common;
+pub mod invariant_checker;
+pub mod sim_application;
+pub mod sim_log;
+pub mod sim_network;
+pub mod sim_process;
+pub mod sim_transport;
+
+use crate::{
+    AppliedEntry, ChannelRaftEventCollector, ClientSession, LogCommand, LogIndex, MembershipChange,
+    NodeDiagnostics, PendingIndex, PendingMembershipChange, PendingProposal, PendingRead,
+    PendingStepDown, RaftConfig, RaftEvent, RaftMetrics, ServerId, StateView,
+};
+use fault_injection::{set_trigger_function, FAULT_INJECT_COUNTER};
+use tracing::{info, trace};
+
+use invariant_checker::InvariantChecker;
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use rand_chacha::ChaCha8Rng;
+
+use crate::testkit::common::SimulatorAction;
+use crate::testkit::sim_log::SimLogEntry;
+
+use self::common::SimClock;
+use self::common::SimTime;
+use self::common::SimulatorEvent;
+use self::common::WakeUpAtOrBefore;
+use self::sim_application::SimApplication;
+use self::sim_log::SimLog;
+use self::sim_network::SimNetwork;
+use self::sim_process::SimRaftProcess;
+
+fn io_fault_injection_trigger_fn(crate_name: &str, file_name: &str, line_number: u32) {
+    println!(
+        "fault injected at {} {} {}",
+        crate_name, file_name, line_number
+    );
+    FAULT_INJECT_COUNTER.store(
+        FAIL_EVERY_N_IO_OPS.load(std::sync::atomic::Ordering::Acquire),
+        std::sync::atomic::Ordering::Release,
+    );
+}
+
+static FAIL_EVERY_N_IO_OPS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// A simulation of a cluster of Raft servers.
+/// This is used to test the Raft algorithm in a controlled environment.
+/// The simulation is deterministic and can be run multiple times with the same inputs as long as you use a random number generator with the same seed.
+/// The simulation is also fast, as it does not use real time.
+pub struct ClusterSim<C: LogCommand + 'static> {
+    rng: ChaCha8Rng,
+    servers: HashMap<ServerId, SimRaftProcess<C, ChannelRaftEventCollector>>,
+    network: SimNetwork<C>,
+    transport_wake_up_rx: mpsc::Receiver<WakeUpAtOrBefore>,
+    events_to_process: BinaryHeap<Reverse<SimulatorEvent<C>>>,
+    invariant_checker: InvariantChecker,
+    pub results: SimResults,
+    pub log: SimLog<C>,
+    transport_wakeup_requests: BTreeSet<SimTime>,
+    config: RaftConfig,
+    storage_temp_dir: String,
+    clock: SimClock,
+}
+
+pub struct SimResults {
+    pub was_leader_elected: bool,
+    pub all_elected_leaders: HashSet<ServerId>,
+}
+
+impl<C: LogCommand + 'static> ClusterSim<C> {
+    /// Starts servers `0..num_servers` as one cluster. The network may have room for more, started
+    /// later with [`ClusterSim::start_joining_server`].
+    pub fn new(
+        num_servers: u64,
+        mut network: SimNetwork<C>,
+        config: RaftConfig,
+        rng: ChaCha8Rng,
+        storage_temp_dir: String,
+        log_file_path: Option<PathBuf>,
+    ) -> Self {
+        assert!(
+            num_servers <= network.server_ids.len() as u64,
+            "Network should have room for every server in the cluster"
+        );
+        set_trigger_function(io_fault_injection_trigger_fn);
+
+        let timer_rx = network.take_timer_rx();
+        let clock = network.clock();
+
+        let log = SimLog::new(log_file_path);
+
+        let invariant_checker = InvariantChecker::new();
+
+        let mut servers = HashMap::new();
+        let mut server_ids = HashSet::new();
+        for s in 0..num_servers {
+            let sid = ServerId(s);
+            // Server ID should have connection in network
+            assert!(
+                network.server_ids.contains(&sid),
+                "Server {server:?} should have a connection in the network",
+                server = s
+            );
+            server_ids.insert(sid);
+        }
+        for s in 0..num_servers {
+            let sid = ServerId(s);
+            let other_servers = server_ids
+                .iter()
+                .copied()
+                .filter(|server_id| *server_id != sid)
+                .collect();
+            let process = SimRaftProcess::new(
+                sid,
+                other_servers,
+                config.clone(),
+                storage_temp_dir.clone(),
+                rng.clone(),
+                &mut network,
+                invariant_checker.event_collector_for_server(),
+            );
+            servers.insert(sid, process);
+        }
+
+        let messages = BinaryHeap::new();
+
+        ClusterSim {
+            servers,
+            network,
+            events_to_process: messages,
+            rng,
+            transport_wake_up_rx: timer_rx,
+            results: SimResults {
+                was_leader_elected: false,
+                all_elected_leaders: HashSet::new(),
+            },
+            log,
+            invariant_checker,
+            transport_wakeup_requests: BTreeSet::new(),
+            config,
+            storage_temp_dir,
+            clock,
+        }
+    }
+
+    /// Starts a server that isn't a member of the cluster, with
+    /// [`RaftConfig::join_existing_cluster`] set. It stays quiet until a leader adds it with
+    /// [`ClusterSim::add_server`] or [`ClusterSim::add_learner`].
+    pub fn start_joining_server(&mut self, server_id: ServerId) {
+        assert!(
+            self.network.server_ids.contains(&server_id),
+            "Server {server_id:?} should have a connection in the network"
+        );
+        assert!(
+            !self.servers.contains_key(&server_id),
+            "Server {server_id:?} is already running"
+        );
+        let config = RaftConfig {
+            join_existing_cluster: true,
+            ..self.config.clone()
+        };
+        let members = self.servers.keys().copied().collect();
+        let process = SimRaftProcess::new(
+            server_id,
+            members,
+            config,
+            self.storage_temp_dir.clone(),
+            self.rng.clone(),
+            &mut self.network,
+            self.invariant_checker.event_collector_for_server(),
+        );
+        let _ = self.servers.insert(server_id, process);
+    }
+
+    /// Asks the given server to add `server_id` to the cluster, picked up the next time it wakes up.
+    /// Resolves as the simulation runs, check on it with [`PendingMembershipChange::try_outcome`].
+    pub fn add_server(&self, via: ServerId, server_id: ServerId) -> PendingMembershipChange {
+        self.servers
+            .get(&via)
+            .expect("SIM: no such server")
+            .add_server(server_id)
+    }
+
+    /// Asks the given server to remove `server_id` from the cluster, like [`ClusterSim::add_server`]
+    pub fn remove_server(&self, via: ServerId, server_id: ServerId) -> PendingMembershipChange {
+        self.servers
+            .get(&via)
+            .expect("SIM: no such server")
+            .remove_server(server_id)
+    }
+
+    /// Asks the given server to add `server_id` as a learner, like [`ClusterSim::add_server`]
+    pub fn add_learner(&self, via: ServerId, server_id: ServerId) -> PendingMembershipChange {
+        self.servers
+            .get(&via)
+            .expect("SIM: no such server")
+            .add_learner(server_id)
+    }
+
+    /// Asks the given server to promote the learner `server_id`, like [`ClusterSim::add_server`]
+    pub fn promote_learner(&self, via: ServerId, server_id: ServerId) -> PendingMembershipChange {
+        self.servers
+            .get(&via)
+            .expect("SIM: no such server")
+            .promote_learner(server_id)
+    }
+
+    /// Asks the given server to carry out `change`, whichever kind it is
+    pub fn change_membership(
+        &self,
+        via: ServerId,
+        change: MembershipChange,
+    ) -> PendingMembershipChange {
+        match change {
+            MembershipChange::AddServer(server_id) => self.add_server(via, server_id),
+            MembershipChange::RemoveServer(server_id) => self.remove_server(via, server_id),
+            MembershipChange::AddLearner(server_id) => self.add_learner(via, server_id),
+            MembershipChange::PromoteLearner(server_id) => self.promote_learner(via, server_id),
+        }
+    }
+
+    /// Asks the given server to step down, handing leadership to `transfer_to` if set. Resolves as
+    /// the simulation runs, check on it with [`PendingStepDown::try_outcome`].
+    pub fn step_down(&self, via: ServerId, transfer_to: Option<ServerId>) -> PendingStepDown {
+        self.servers
+            .get(&via)
+            .expect("SIM: no such server")
+            .step_down(transfer_to)
+    }
+
+    /// How far the simulation got
+    pub fn now(&self) -> SimTime {
+        self.clock.now()
+    }
+
+    pub fn reset_results(&mut self) {
+        self.results.was_leader_elected = false;
+        self.results.all_elected_leaders = HashSet::new();
+        self.log.reset();
+    }
+
+    /// The last events the given server published, as kept by its node handle
+    pub fn recent_events(&self, server_id: ServerId) -> Vec<RaftEvent> {
+        self.servers
+            .get(&server_id)
+            .map(|server_process| server_process.recent_events())
+            .unwrap_or_default()
+    }
+
+    /// What the given server last published about itself through its node handle
+    pub fn diagnostics(&self, server_id: ServerId) -> Option<NodeDiagnostics> {
+        self.servers
+            .get(&server_id)
+            .and_then(|server_process| server_process.diagnostics())
+    }
+
+    /// The metrics the given server records, shared with its node so they keep moving as the
+    /// simulation runs
+    pub fn metrics(&self, server_id: ServerId) -> Option<RaftMetrics> {
+        self.servers
+            .get(&server_id)
+            .map(|server_process| server_process.metrics())
+    }
+
+    /// Hands a command to the given server, it's picked up the next time the server wakes up.
+    /// The proposal resolves as the simulation runs, check on it with [`PendingProposal::try_outcome`].
+    pub fn propose(&self, server_id: ServerId, command: C) -> PendingProposal<SimApplication<C>> {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .propose(command)
+    }
+
+    /// Like [`ClusterSim::propose`], appending `commands` at consecutive indexes
+    pub fn propose_many(
+        &self,
+        server_id: ServerId,
+        commands: Vec<C>,
+    ) -> Vec<PendingProposal<SimApplication<C>>> {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .propose_many(commands)
+    }
+
+    /// Like [`ClusterSim::propose`], within the client's `session`
+    pub fn propose_in_session(
+        &self,
+        server_id: ServerId,
+        session: ClientSession,
+        command: C,
+    ) -> PendingProposal<SimApplication<C>> {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .propose_in_session(session, command)
+    }
+
+    /// Asks the given server to serve `read` off its lease, like [`ClusterSim::propose`] it resolves as
+    /// the simulation runs
+    pub fn read<R, F>(&self, server_id: ServerId, read: F) -> PendingRead<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&SimApplication<C>) -> R + Send + 'static,
+    {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .read(read)
+    }
+
+    /// Every command the given server applies from the next time it wakes up, as the simulation runs
+    pub fn subscribe_applied(&self, server_id: ServerId) -> mpsc::Receiver<AppliedEntry<C>> {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .subscribe_applied()
+    }
+
+    /// Runs `read` on the given server once it applied `index`, like [`ClusterSim::propose`] it
+    /// resolves as the simulation runs
+    pub fn read_after<R, F>(&self, server_id: ServerId, index: LogIndex, read: F) -> PendingRead<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&SimApplication<C>) -> R + Send + 'static,
+    {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .read_after(index, read)
+    }
+
+    /// A copy of the given server's application state, like [`ClusterSim::propose`] it resolves as
+    /// the simulation runs
+    pub fn state_view(&self, server_id: ServerId) -> PendingRead<StateView> {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .state_view()
+    }
+
+    /// Waits for the given server to apply `index`, like [`ClusterSim::propose`] it resolves as the
+    /// simulation runs
+    pub fn wait_for_index(&self, server_id: ServerId, index: LogIndex) -> PendingIndex {
+        self.servers
+            .get(&server_id)
+            .expect("SIM: no such server")
+            .wait_for_index(index)
+    }
+
+    /// The commands the given server applied so far, with their index, in the order it applied them
+    pub fn applied_commands(&self, server_id: ServerId) -> Vec<(LogIndex, C)> {
+        self.servers
+            .get(&server_id)
+            .map(|server_process| server_process.applied_commands())
+            .unwrap_or_default()
+    }
+
+    /// The leader as seen by the invariant checker, if there is one right now
+    pub fn current_leader(&self) -> Option<ServerId> {
+        self.invariant_checker.get_current_leader()
+    }
+
+    /// Provides a way for tests to inject messages into the simulation.
+    pub fn enqueue_event(&mut self, msg: SimulatorEvent<C>) {
+        assert!(
+            msg.time >= self.clock.now(),
+            "Cannot enqueue an event in the past {msg:?} (sim time = {sim_time:?}!",
+            msg = msg,
+            sim_time = self.clock.now()
+        );
+
+        self.log
+            .push(SimLogEntry::event_queued(self.clock.now(), &msg));
+        self.events_to_process.push(Reverse(msg));
+    }
+
+    /// Runs a single step of the simulation, this doess...
+    /// 1. Interrupt network transports for each node so they can check the current simulation time and time out waiting if necessary
+    /// 2. Fetch all messages that have been sent over the nextwork since the last iteration
+    /// 3. Get the next simulator message to be processed and run the appropriate action (i.e. deliver a message to a server, partion the network, etc.)
+    /// 4. Checks that no Raft invariants have been violated in the cluster
+    fn run_step(&mut self) {
+        for (_, server_process) in self.servers.iter_mut() {
+            server_process.restart_if_needed(&mut self.network);
+        }
+        self.wait_for_servers_to_catch_up();
+
+        let outbound_messages = self
+            .network
+            .get_all_queued_outbound_messages(&mut self.rng, &mut self.log);
+        for (message, delivery_time) in outbound_messages {
+            self.enqueue_event(SimulatorEvent {
+                time: delivery_time,
+                action: SimulatorAction::SendOverNetwork(Box::new(message)),
+            });
+        }
+
+        let maybe_next = self.events_to_process.peek();
+        // Find the first transport wakeup request that is before the next event to process (if there is one)
+        let transport_wake_up_requests: HashSet<WakeUpAtOrBefore> =
+            self.transport_wake_up_rx.try_iter().collect();
+        for wake_up_by in transport_wake_up_requests {
+            self.transport_wakeup_requests
+                .insert(if wake_up_by.0 >= self.clock.now() {
+                    wake_up_by.0
+                } else {
+                    self.clock.now()
+                });
+        }
+
+        let maybe_wakeup_time = self
+            .transport_wakeup_requests
+            .iter()
+            .find(|wake_up| maybe_next.is_none() || wake_up <= &&maybe_next.unwrap().0.time)
+            .cloned();
+
+        if let Some(wakeup_time) = maybe_wakeup_time {
+            let advance_by = wakeup_time.checked_sub(&self.clock.now()).unwrap_or_else(|| panic!("Time should not go backwards, wake up time {wakeup_time:?} is in the past (sim time = {sim_time:?}!",
+                    wakeup_time = wakeup_time,
+                    sim_time=self.clock.now()));
+            self.clock.advance(advance_by);
+            for (_, server_process) in self.servers.iter_mut() {
+                server_process.wake_up_transport_connector();
+            }
+            self.transport_wakeup_requests.remove(&wakeup_time);
+        } else if !self.events_to_process.is_empty() {
+            let next = self.events_to_process.pop().unwrap().0;
+            self.log
+                .push(SimLogEntry::event_processed(self.clock.now(), &next));
+
+            let advance_duration = next.time.checked_sub(&self.clock.now());
+            if let Some(advance_duration) = advance_duration {
+                self.clock.advance(advance_duration);
+            }
+
+            trace!(
+                "Performing action: {:?} at time {:?}ms",
+                next.action,
+                next.time.0.as_millis()
+            );
+
+            match next.action {
+                SimulatorAction::SendOverNetwork(network_message) => {
+                    trace!(
+                            "DELIVER NETWORK MESSAGE: msg_time = {time:?}ms, sim_time={sim_time:?}ms -- ({from:?} -> {to:?}): {rpc_message:?}",
+                            time = next.time.as_millis(),
+                            sim_time = self.clock.now().as_millis(),
+                            rpc_message = network_message,
+                            from = network_message.from(),
+                            to = network_message.to(),
+                        );
+
+                    self.network
+                        .deliver_message(network_message.to(), *network_message);
+                }
+                SimulatorAction::PartitionNetwork(partitions) => {
+                    trace!(
+                            "PARTITION NETWORK: msg_time = {time:?}ms, sim_time={sim_time:?}ms -- Partitioning network: {partition:?}",
+                            time = next.time.as_millis(),
+                            sim_time = self.clock.now().as_millis(),
+                            partition = partitions,
+                        );
+                    self.network.partition_network(partitions);
+                }
+                SimulatorAction::HealNetworkPartition => self.network.heal_network_partition(),
+                SimulatorAction::InjectIOFailureEveryNOps(n) => {
+                    FAIL_EVERY_N_IO_OPS.store(n, std::sync::atomic::Ordering::Release);
+                    FAULT_INJECT_COUNTER.store(1, std::sync::atomic::Ordering::Release);
+                }
+                SimulatorAction::RestoreIOFunctioning => {
+                    FAULT_INJECT_COUNTER.store(u64::MAX, std::sync::atomic::Ordering::Release);
+                }
+            }
+
+            self.invariant_checker
+                .check_invariants(self.clock.now(), &mut self.log);
+
+            if let Some(leader) = self.invariant_checker.get_current_leader() {
+                self.results.was_leader_elected = true;
+                self.results.all_elected_leaders.insert(leader);
+            }
+        }
+    }
+
+    /// Wakes every server and waits until they have all handled what was delivered to them and parked again.
+    /// Without this the clock can run ahead of servers that are still busy, firing their timers early.
+    fn wait_for_servers_to_catch_up(&mut self) {
+        for server_process in self.servers.values() {
+            server_process.wake_up_transport_connector();
+        }
+        while !self
+            .servers
+            .values()
+            .all(|server_process| server_process.has_caught_up())
+        {
+            std::thread::yield_now();
+        }
+    }
+
+    /// Runs the simulation until the given time has been reached.
+    pub fn run_until_time(&mut self, time: Duration) {
+        info!(
+            "Running simulation: current time = {current_time:?}, run until = {run_until:?}",
+            current_time = self.clock.now().0,
+            run_until = time
+        );
+        let mut last_time_log = Duration::from_millis(0);
+        while self.clock.now().0 <= time {
+            if self.clock.now().0 - last_time_log >= Duration::from_millis(1000) {
+                info!(
+                    "Current simulator time {time:?}ms",
+                    time = self.clock.now().as_millis()
+                );
+                last_time_log = self.clock.now().0;
+            }
+            trace!(
+                "Simulation time = {time:?}ms",
+                time = self.clock.now().as_millis()
+            );
+            let time_before_step = self.clock.now().0;
+            self.run_step();
+            let time_after_step = self.clock.now().0;
+
+            assert!(
+                time_after_step >= time_before_step,
+                "Simulator time went backwards! This is a bug in the simulator!"
+            )
+        }
+        info!(
+            "Finished simulation! time = {current_time:?}ms",
+            current_time = self.clock.now().as_millis()
+        );
+
+        if self.log.flush().is_err() {
+            panic!("Failed to flush simulation log to disk, it may be incomplete!");
+        }
+    }
+}
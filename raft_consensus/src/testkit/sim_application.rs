@@ -0,0 +1,98 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use bincode::Options;
+
+use crate::{ApplicationThatNeedsConsensus, FrozenState, LogCommand, LogIndex, Snapshot};
+
+/// Every command applied with its index, in the order they were applied
+type AppliedCommands<C> = Arc<Vec<(LogIndex, C)>>;
+
+/// The application every simulated server runs, it remembers the commands applied to it and
+/// hands back their index. Clones share what was applied, so it survives the Raft thread restarting.
+/// Its snapshots hold every command applied so far, so servers restored from one can still be compared.
+/// The commands are copied on write, frozen states share them until the next apply.
+#[derive(Debug, Clone)]
+pub struct SimApplication<C: LogCommand> {
+    applied: Arc<Mutex<AppliedCommands<C>>>,
+}
+impl<C: LogCommand> SimApplication<C> {
+    pub fn new() -> Self {
+        SimApplication {
+            applied: Arc::new(Mutex::new(Arc::new(Vec::new()))),
+        }
+    }
+
+    /// Every command applied so far with its index, in the order they were applied
+    pub fn applied_commands(&self) -> Vec<(LogIndex, C)> {
+        self.applied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .to_vec()
+    }
+}
+impl<C: LogCommand> Default for SimApplication<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<C: LogCommand + 'static> ApplicationThatNeedsConsensus for SimApplication<C> {
+    type Command = C;
+    type Output = LogIndex;
+    type Error = Infallible;
+
+    fn apply(&mut self, log_index: LogIndex, command: C) -> Result<LogIndex, Infallible> {
+        let mut applied = self
+            .applied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::make_mut(&mut applied).push((log_index, command));
+        Ok(log_index)
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.applied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .last()
+            .map(|(index, _)| *index)
+            .unwrap_or(LogIndex(0))
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        serialize_applied(&self.applied_commands())
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        let applied = bincode::DefaultOptions::new()
+            .deserialize(&snapshot.data)
+            .expect("SIM: snapshot should hold applied commands");
+        *self
+            .applied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(applied);
+    }
+
+    fn freeze(&self) -> Arc<dyn FrozenState> {
+        let applied = self
+            .applied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::new(FrozenApplied(Arc::clone(&applied)))
+    }
+}
+
+/// The commands applied when the state was frozen, shared with the application until it applies
+/// the next one
+struct FrozenApplied<C>(AppliedCommands<C>);
+impl<C: LogCommand> FrozenState for FrozenApplied<C> {
+    fn serialize(&self) -> Vec<u8> {
+        serialize_applied(&self.0)
+    }
+}
+
+fn serialize_applied<C: LogCommand>(applied: &[(LogIndex, C)]) -> Vec<u8> {
+    bincode::DefaultOptions::new()
+        .serialize(applied)
+        .expect("SIM: applied commands should serialize")
+}
@@ -15,14 +15,13 @@ use std::{
     time::Duration,
 };
 
-use mock_instant::MockClock;
-use raft_consensus::{rpc_messages::RpcMessage, LogCommand, ServerId};
+use crate::{rpc_messages::RpcMessage, LogCommand, ServerId};
 use rand_chacha::ChaCha8Rng;
 use rand_distr::{Bernoulli, Distribution, LogNormal};
 use tracing::{debug, trace};
 
 use super::{
-    common::{SimLogCommand, SimTime, WakeUpAtOrBefore},
+    common::{SimClock, SimTime, WakeUpAtOrBefore},
     sim_log::{LoggedSimEvent, SimLog, SimLogEntry},
     sim_transport::SimNetworkRaftTransportConnector,
 };
@@ -30,13 +29,13 @@ use super::{
 use rand_distr::num_traits::ToPrimitive;
 
 #[derive(Debug, Clone)]
-pub(crate) struct PacketLossProbability(pub(crate) f64);
+pub struct PacketLossProbability(pub f64);
 #[derive(Debug, Clone)]
-pub(crate) struct LatencyMean(pub(crate) f64);
+pub struct LatencyMean(pub f64);
 #[derive(Debug, Clone)]
-pub(crate) struct LatencyStdDev(pub(crate) f64);
+pub struct LatencyStdDev(pub f64);
 
-pub(crate) struct NetworkConnectionQuality {
+pub struct NetworkConnectionQuality {
     /// Probability that a message is dropped
     packet_loss: Bernoulli,
     /// Latency is calculated with a log-normal distribution
@@ -48,36 +47,34 @@ struct NetworkNode<C: LogCommand> {
 }
 
 /// Models a network with packet loss and latency, uses Bernoulli distribution for packet loss and log-normal distribution for latency
-pub(crate) struct SimNetwork {
-    pub(crate) server_ids: HashSet<ServerId>,
+pub struct SimNetwork<C: LogCommand> {
+    pub server_ids: HashSet<ServerId>,
     /// Servers in network, map of IDs to network nodes (which contain the transport and incoming message channel)
-    servers: HashMap<ServerId, NetworkNode<SimLogCommand>>,
+    servers: HashMap<ServerId, NetworkNode<C>>,
     /// Map of server IDs to probability of packet loss, mean latency, std dev for latency ((server_id, server_id) -> (probability of message being dropped, mean latency, standard deviation)
     connections: HashMap<(ServerId, ServerId), NetworkConnectionQuality>,
     /// Sender side of channel that sends outgoing messages from server process to network to be delivered to other servers
-    outbound_message_tx: mpsc::Sender<RpcMessage<SimLogCommand>>,
+    outbound_message_tx: mpsc::Sender<RpcMessage<C>>,
     /// Receiver side of channel that receives outgoing messages from the server processes
-    outbound_message_rx: mpsc::Receiver<RpcMessage<SimLogCommand>>,
+    outbound_message_rx: mpsc::Receiver<RpcMessage<C>>,
     /// Used by server transport connector to register a wake up request
     timer_tx: mpsc::Sender<WakeUpAtOrBefore>,
     /// Used to retrieve wake up requests
     maybe_timer_rx: Option<mpsc::Receiver<WakeUpAtOrBefore>>,
+    /// The simulation's clock, handed to the transports and servers on the network
+    clock: SimClock,
 }
 
-impl SimNetwork {
+impl<C: LogCommand> SimNetwork<C> {
     /// Creates a new network with the given connections
     /// `network_connections` - Map of server IDs to probability of packet loss, mean latency, std dev for latency ((server_id, server_id) -> (probability of message being dropped, mean latency, standard deviation)
-    pub(crate) fn new(
+    pub fn new(
         network_connections: HashMap<
             (ServerId, ServerId),
             (PacketLossProbability, LatencyMean, LatencyStdDev),
         >,
     ) -> Self {
-        let server_connections = network_connections
-            .keys()
-            .into_iter()
-            .cloned()
-            .collect::<HashSet<_>>();
+        let server_connections = network_connections.keys().cloned().collect::<HashSet<_>>();
         let network: HashMap<(ServerId, ServerId), NetworkConnectionQuality> = network_connections
             .into_iter()
             .map(|((from, to), (drop_probability, mean_latency, std_dev))| {
@@ -125,11 +122,12 @@ impl SimNetwork {
             outbound_message_rx,
             timer_tx,
             maybe_timer_rx: Some(timer_rx),
+            clock: SimClock::new(),
         }
     }
 
     /// Creates a network with the same packet loss and latency for all connections
-    pub(crate) fn with_defaults(
+    pub fn with_defaults(
         num_servers: u64,
         packet_loss: PacketLossProbability,
         mean_latency: LatencyMean,
@@ -156,10 +154,10 @@ impl SimNetwork {
     /// Called by the simulator when it is creating server processes
     /// After the network has been initialized it uses this method
     /// to take ownership of the transport object and give it to the server process
-    pub(crate) fn join_network_and_take_transport_connector(
+    pub fn join_network_and_take_transport_connector(
         &mut self,
         server_id: ServerId,
-    ) -> SimNetworkRaftTransportConnector {
+    ) -> SimNetworkRaftTransportConnector<C> {
         let (inbound_message_tx, inbound_message_rx) = mpsc::channel();
         self.servers.insert(
             server_id,
@@ -171,10 +169,16 @@ impl SimNetwork {
             self.outbound_message_tx.clone(),
             inbound_message_rx,
             self.timer_tx.clone(),
+            self.clock.clone(),
         )
     }
 
-    pub(crate) fn take_timer_rx(&mut self) -> mpsc::Receiver<WakeUpAtOrBefore> {
+    /// The clock the network delivers messages by, shared with everything on the network
+    pub fn clock(&self) -> SimClock {
+        self.clock.clone()
+    }
+
+    pub fn take_timer_rx(&mut self) -> mpsc::Receiver<WakeUpAtOrBefore> {
         self.maybe_timer_rx
             .take()
             .expect("SIM: Timer already taken!")
@@ -182,7 +186,7 @@ impl SimNetwork {
 
     /// Used by tests to partition the network into multiple partitions, where each partition is a disjoin set of server IDs
     /// Servers in each partition are connected to each other, but servers in different partitions are not connected
-    pub(crate) fn partition_network(&mut self, partitions: Vec<HashSet<ServerId>>) {
+    pub fn partition_network(&mut self, partitions: Vec<HashSet<ServerId>>) {
         // Validate that sets are disjoint, i.e. no server is in multiple partitions
         let mut all_servers = HashSet::new();
         for partition in &partitions {
@@ -205,8 +209,7 @@ impl SimNetwork {
             );
         }
         // Set packet loss to 1.0 for all connections between servers in different partitions
-        let keys: Vec<(ServerId, ServerId)> =
-            self.connections.keys().into_iter().cloned().collect();
+        let keys: Vec<(ServerId, ServerId)> = self.connections.keys().cloned().collect();
         for (from, to) in keys {
             let from_partition = partitions
                 .iter()
@@ -219,14 +222,14 @@ impl SimNetwork {
         }
     }
 
-    pub(crate) fn heal_network_partition(&mut self) {
+    pub fn heal_network_partition(&mut self) {
         for connection in self.connections.values_mut() {
             connection.packet_loss = Bernoulli::new(0.01).unwrap();
         }
     }
 
     /// Can be used by tests to change the probability of messages being dropped between two servers
-    pub(crate) fn update_connection_packet_loss(
+    pub fn update_connection_packet_loss(
         &mut self,
         from: ServerId,
         to: ServerId,
@@ -238,16 +241,18 @@ impl SimNetwork {
             from = from,
             to = to,
         );
-        let connection = self.connections.get_mut(&(from, to)).expect(&format!(
-            "SIM: Should have a connection between server {from:?} and server {to:?}",
-            from = from,
-            to = to
-        ));
+        let connection = self.connections.get_mut(&(from, to)).unwrap_or_else(|| {
+            panic!(
+                "SIM: Should have a connection between server {from:?} and server {to:?}",
+                from = from,
+                to = to
+            )
+        });
         connection.packet_loss = Bernoulli::new(packet_loss.0).unwrap();
     }
 
     /// Can be used by tests to change the latency profile of messages sent from one server to another
-    pub(crate) fn update_connection_latency(
+    pub fn update_connection_latency(
         &mut self,
         from: ServerId,
         to: ServerId,
@@ -266,11 +271,13 @@ impl SimNetwork {
             from = from,
             to = to,
         );
-        let connection = self.connections.get_mut(&(from, to)).expect(&format!(
-            "SIM: Should have a connection between server {from:?} and server {to:?}",
-            from = from,
-            to = to
-        ));
+        let connection = self.connections.get_mut(&(from, to)).unwrap_or_else(|| {
+            panic!(
+                "SIM: Should have a connection between server {from:?} and server {to:?}",
+                from = from,
+                to = to
+            )
+        });
         connection.latency = LogNormal::new(mean_latency.0.ln(), latency_std_dev.0).unwrap();
     }
 
@@ -279,19 +286,21 @@ impl SimNetwork {
     /// This is called by the simulator
     fn determine_when_and_if_message_should_be_delivered(
         &self,
-        message: RpcMessage<SimLogCommand>,
+        message: RpcMessage<C>,
         rng: &mut ChaCha8Rng,
-    ) -> Option<(RpcMessage<SimLogCommand>, SimTime)> {
+    ) -> Option<(RpcMessage<C>, SimTime)> {
         let to = message.to();
         let from = message.from();
 
-        let time = MockClock::time();
+        let time = self.clock.now().0;
 
-        let connection = self.connections.get(&(from, to)).expect(&format!(
-            "Should have a connection between server {from:?} and server {to:?}",
-            from = from,
-            to = to
-        ));
+        let connection = self.connections.get(&(from, to)).unwrap_or_else(|| {
+            panic!(
+                "Should have a connection between server {from:?} and server {to:?}",
+                from = from,
+                to = to
+            )
+        });
         let drop_message = connection.packet_loss.sample(rng);
         let message_latency = connection
             .latency
@@ -323,12 +332,12 @@ impl SimNetwork {
 
     /// This is called by the simulator to get all messages that have been sent from server processes
     /// to the network that have not been queued in the simulator yet
-    pub(crate) fn get_all_queued_outbound_messages(
+    pub fn get_all_queued_outbound_messages(
         &mut self,
         rng: &mut ChaCha8Rng,
-        log: &mut SimLog,
-    ) -> Vec<(RpcMessage<SimLogCommand>, SimTime)> {
-        let mut messages: Vec<(RpcMessage<SimLogCommand>, SimTime)> = Vec::new();
+        log: &mut SimLog<C>,
+    ) -> Vec<(RpcMessage<C>, SimTime)> {
+        let mut messages: Vec<(RpcMessage<C>, SimTime)> = Vec::new();
 
         while let Ok(message) = self.outbound_message_rx.try_recv() {
             let message_cloned = message.clone();
@@ -337,10 +346,10 @@ impl SimNetwork {
             {
                 messages.push(message_to_be_delivered);
             } else {
-                let time = MockClock::time();
+                let time = self.clock.now();
                 log.push(SimLogEntry::EventProcessed(
-                    SimTime(time),
-                    LoggedSimEvent::DroppedNetworkMessage(SimTime(time), message_cloned),
+                    time,
+                    LoggedSimEvent::DroppedNetworkMessage(time, message_cloned),
                 ));
             }
         }
@@ -349,23 +358,26 @@ impl SimNetwork {
     }
 
     /// Called by the simulator to actually deliver the message to the server process once it is time to deliver it
-    pub(crate) fn deliver_message(&mut self, target: ServerId, message: RpcMessage<SimLogCommand>) {
-        let network_node = self.servers.get_mut(&target).expect(&format!(
-            "Should have a server with ID {to:?} in the simulation",
-            to = target
-        ));
+    pub fn deliver_message(&mut self, target: ServerId, message: RpcMessage<C>) {
+        let network_node = self.servers.get_mut(&target).unwrap_or_else(|| {
+            panic!(
+                "Should have a server with ID {to:?} in the simulation",
+                to = target
+            )
+        });
 
-        if let Err(_) = network_node.incoming_message_tx.send(message) {
+        if network_node.incoming_message_tx.send(message).is_err() {
             debug!("SIM: Could not send network message to server (raft thread shutdown?)");
         }
     }
 }
 
+#[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use raft_consensus::rpc_messages::RpcMessage;
-    use raft_consensus::{
+    use crate::rpc_messages::RpcMessage;
+    use crate::{
         rpc_messages::Request, rpc_messages::RequestVote, LogIndex, RaftTransportConnector,
         RaftTransportError, ServerId, TermIndex,
     };
@@ -375,7 +387,8 @@ mod tests {
     use tracing::info;
     use uuid::Uuid;
 
-    use crate::simulator::sim_log::SimLog;
+    use crate::testkit::common::SimLogCommand;
+    use crate::testkit::sim_log::SimLog;
 
     use super::{LatencyMean, LatencyStdDev, PacketLossProbability, SimNetwork};
 
@@ -393,7 +406,7 @@ mod tests {
         }
     }
 
-    fn new_sim_log(log_file_name: Option<&str>) -> SimLog {
+    fn new_sim_log(log_file_name: Option<&str>) -> SimLog<SimLogCommand> {
         let log_file_path = log_file_name.map(|filename| {
             let pwd = std::env::current_dir().unwrap();
             pwd.join("..").join(filename)
@@ -405,7 +418,7 @@ mod tests {
     fn it_should_return_a_vec_with_all_queued_outbound_messages_from_servers() {
         let mut rng = new_rng(None);
 
-        let mut network = SimNetwork::with_defaults(
+        let mut network = SimNetwork::<SimLogCommand>::with_defaults(
             2,
             PacketLossProbability(0.0),
             LatencyMean(0.0),
@@ -422,10 +435,14 @@ mod tests {
             term: TermIndex(1),
             last_log_index: LogIndex(0),
             last_log_term: TermIndex(0),
+            leadership_transfer: false,
         });
         let expected_message = outgoing_message.clone();
 
-        if let Err(_) = originating_server_transport.enqueue_outgoing_request(outgoing_message) {
+        if originating_server_transport
+            .enqueue_outgoing_request(outgoing_message)
+            .is_err()
+        {
             panic!("Could not enqueue outgoing request! (transport shutdown)");
         }
 
@@ -433,7 +450,7 @@ mod tests {
         let messages = network.get_all_queued_outbound_messages(&mut rng, &mut sim_log);
         assert_eq!(messages.len(), 1);
 
-        let (message, _) = messages.get(0).unwrap();
+        let (message, _) = messages.first().unwrap();
         match message {
             RpcMessage::Request(request) => {
                 assert_eq!(request, &expected_message);
@@ -446,7 +463,7 @@ mod tests {
     fn it_should_drop_messages_if_servers_are_in_different_network_partitions() {
         let mut rng = new_rng(None);
 
-        let mut network = SimNetwork::with_defaults(
+        let mut network = SimNetwork::<SimLogCommand>::with_defaults(
             2,
             PacketLossProbability(1.0),
             LatencyMean(0.0),
@@ -463,9 +480,13 @@ mod tests {
             term: TermIndex(1),
             last_log_index: LogIndex(0),
             last_log_term: TermIndex(0),
+            leadership_transfer: false,
         });
 
-        if let Err(_) = originating_server_transport.enqueue_outgoing_request(outgoing_message) {
+        if originating_server_transport
+            .enqueue_outgoing_request(outgoing_message)
+            .is_err()
+        {
             panic!("Could not enqueue outgoing request! (transport shutdown)");
         }
 
@@ -476,7 +497,7 @@ mod tests {
 
     #[test]
     fn it_should_deliver_message_to_transport_for_server() {
-        let mut network = SimNetwork::with_defaults(
+        let mut network = SimNetwork::<SimLogCommand>::with_defaults(
             2,
             PacketLossProbability(0.0),
             LatencyMean(0.0),
@@ -493,6 +514,7 @@ mod tests {
             term: TermIndex(1),
             last_log_index: LogIndex(0),
             last_log_term: TermIndex(0),
+            leadership_transfer: false,
         });
         let expected_message = incoming_message.clone();
 
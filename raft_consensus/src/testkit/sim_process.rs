@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use crate::{
+    AppliedEntry, ClientSession, LogCommand, LogIndex, NodeDiagnostics, PendingIndex,
+    PendingMembershipChange, PendingProposal, PendingRead, PendingStepDown, RaftConfig, RaftEvent,
+    RaftMetrics, RaftNodeBuilder, RaftNodeHandle, RaftStateEventCollector, ServerId, StateView,
+};
+use raft_core::system_clock::ManualClock;
+use rand_chacha::ChaCha8Rng;
+
+use super::sim_application::SimApplication;
+use super::sim_network::SimNetwork;
+use super::sim_transport::TransportIdleState;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+/// A process in the simulation that represents a single server.
+/// This runs the Raft algorithm for this simulated server in it's own thread.
+/// It uses the provided transport to send and to receive messages from other servers.
+pub struct SimRaftProcess<C: LogCommand + 'static, E: RaftStateEventCollector + Clone> {
+    server_id: ServerId,
+    config: RaftConfig,
+    rng: ChaCha8Rng,
+    clock: ManualClock,
+    other_servers: HashSet<ServerId>,
+    storage_path: String,
+    application: SimApplication<C>,
+    event_collector: E,
+    metrics: RaftMetrics,
+    thread_handle: RaftNodeHandle<SimApplication<C>>,
+    transport_idle_state: Arc<TransportIdleState>,
+}
+impl<C: LogCommand + 'static, E: RaftStateEventCollector + Clone + 'static> SimRaftProcess<C, E> {
+    /// Starts a server whose cluster is `other_servers` and itself, or only `other_servers` if
+    /// `config` has it join an existing cluster
+    pub fn new(
+        server_id: ServerId,
+        other_servers: HashSet<ServerId>,
+        config: RaftConfig,
+        storage_path: String,
+        mut rng: ChaCha8Rng,
+        network_to_join: &mut SimNetwork<C>,
+        event_collector: E,
+    ) -> Self {
+        rng.set_stream(server_id.0);
+
+        // Each server gets its own directory so their logs don't overwrite each other
+        let storage_path = format!("{storage_path}/server-{id}", id = server_id.0);
+        std::fs::create_dir_all(&storage_path).expect("SIM: could not create storage directory");
+
+        let application = SimApplication::new();
+        let metrics = RaftMetrics::new();
+        let clock = network_to_join.clock().manual_clock();
+        let transport = network_to_join.join_network_and_take_transport_connector(server_id);
+        let transport_idle_state = transport.idle_state();
+        let raft_thread_handle = RaftNodeBuilder::new(server_id, application.clone())
+            .peers(other_servers.clone())
+            .storage_path(storage_path.clone())
+            .transport(transport)
+            .config(config.clone())
+            .rng(rng.clone())
+            .clock(clock.clone())
+            .event_collector(event_collector.clone())
+            .metrics(metrics.clone())
+            .start()
+            .expect("SIM: could not start server");
+        SimRaftProcess {
+            server_id,
+            rng,
+            clock,
+            config,
+            other_servers,
+            storage_path,
+            application,
+            event_collector,
+            metrics,
+            thread_handle: raft_thread_handle,
+            transport_idle_state,
+        }
+    }
+
+    pub fn restart_if_needed(&mut self, network_to_join: &mut SimNetwork<C>) {
+        if self.thread_handle.is_finished() {
+            println!("Restarting server {}...", self.server_id.0);
+            let transport =
+                network_to_join.join_network_and_take_transport_connector(self.server_id);
+            self.transport_idle_state = transport.idle_state();
+            self.thread_handle = RaftNodeBuilder::new(self.server_id, self.application.clone())
+                .peers(self.other_servers.clone())
+                .storage_path(self.storage_path.clone())
+                .transport(transport)
+                .config(self.config.clone())
+                .rng(self.rng.clone())
+                .clock(self.clock.clone())
+                .event_collector(self.event_collector.clone())
+                .metrics(self.metrics.clone())
+                .start()
+                .expect("SIM: could not restart server");
+        }
+    }
+
+    pub fn recent_events(&self) -> Vec<RaftEvent> {
+        self.thread_handle.recent_events()
+    }
+
+    pub fn diagnostics(&self) -> Option<NodeDiagnostics> {
+        self.thread_handle.diagnostics().latest()
+    }
+
+    pub fn metrics(&self) -> RaftMetrics {
+        self.thread_handle.metrics()
+    }
+
+    pub fn propose(&self, command: C) -> PendingProposal<SimApplication<C>> {
+        self.thread_handle
+            .propose(command)
+            .expect("SIM: server should be running to accept proposals")
+    }
+
+    pub fn propose_many(&self, commands: Vec<C>) -> Vec<PendingProposal<SimApplication<C>>> {
+        self.thread_handle
+            .propose_many(commands)
+            .expect("SIM: server should be running to accept proposals")
+    }
+
+    pub fn propose_in_session(
+        &self,
+        session: ClientSession,
+        command: C,
+    ) -> PendingProposal<SimApplication<C>> {
+        self.thread_handle
+            .propose_in_session(session, command)
+            .expect("SIM: server should be running to accept proposals")
+    }
+
+    pub fn add_server(&self, server_id: ServerId) -> PendingMembershipChange {
+        self.thread_handle
+            .add_server(server_id)
+            .expect("SIM: server should be running to accept membership changes")
+    }
+
+    pub fn add_learner(&self, server_id: ServerId) -> PendingMembershipChange {
+        self.thread_handle
+            .add_learner(server_id)
+            .expect("SIM: server should be running to accept membership changes")
+    }
+
+    pub fn promote_learner(&self, server_id: ServerId) -> PendingMembershipChange {
+        self.thread_handle
+            .promote_learner(server_id)
+            .expect("SIM: server should be running to accept membership changes")
+    }
+
+    pub fn remove_server(&self, server_id: ServerId) -> PendingMembershipChange {
+        self.thread_handle
+            .remove_server(server_id)
+            .expect("SIM: server should be running to accept membership changes")
+    }
+
+    pub fn step_down(&self, transfer_to: Option<ServerId>) -> PendingStepDown {
+        self.thread_handle
+            .step_down(transfer_to)
+            .expect("SIM: server should be running to accept step downs")
+    }
+
+    pub fn read<R, F>(&self, read: F) -> PendingRead<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&SimApplication<C>) -> R + Send + 'static,
+    {
+        self.thread_handle
+            .read(read)
+            .expect("SIM: server should be running to accept reads")
+    }
+
+    pub fn read_after<R, F>(&self, index: LogIndex, read: F) -> PendingRead<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&SimApplication<C>) -> R + Send + 'static,
+    {
+        self.thread_handle
+            .read_after(index, read)
+            .expect("SIM: server should be running to accept reads")
+    }
+
+    pub fn state_view(&self) -> PendingRead<StateView> {
+        self.thread_handle
+            .state_view()
+            .expect("SIM: server should be running to accept reads")
+    }
+
+    pub fn wait_for_index(&self, index: LogIndex) -> PendingIndex {
+        self.thread_handle
+            .wait_for_index(index)
+            .expect("SIM: server should be running to accept index waits")
+    }
+
+    pub fn subscribe_applied(&self) -> mpsc::Receiver<AppliedEntry<C>> {
+        self.thread_handle.subscribe_applied()
+    }
+
+    pub fn applied_commands(&self) -> Vec<(LogIndex, C)> {
+        self.application.applied_commands()
+    }
+
+    pub fn wake_up_transport_connector(&self) {
+        self.transport_idle_state.record_wake_up();
+        self.thread_handle.thread().unpark();
+    }
+
+    /// True when the Raft thread has handled everything it was woken up for, or has exited
+    pub fn has_caught_up(&self) -> bool {
+        self.transport_idle_state.is_idle() || self.thread_handle.is_finished()
+    }
+}
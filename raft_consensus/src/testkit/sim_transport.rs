@@ -1,48 +1,89 @@
 use std::{
+    sync::atomic::{AtomicU64, Ordering},
     sync::mpsc::{self, SendError, TryRecvError},
+    sync::Arc,
     thread,
     time::Duration,
 };
 
-use raft_consensus::{
+use crate::{
     rpc_messages::{ReplyTo, Request, RpcMessage},
-    system_clock, RaftTransportConnector, RaftTransportError,
+    RaftTransportConnector, RaftTransportError,
 };
-use tracing::{debug, trace};
+use tracing::trace;
 
-use crate::simulator::common::SimTime;
+use crate::testkit::common::SimClock;
 
-use super::common::{SimLogCommand, WakeUpAtOrBefore};
+use crate::LogCommand;
+
+use super::common::WakeUpAtOrBefore;
+
+/// Lets the simulator tell whether the Raft thread behind a transport has caught up with the clock,
+/// i.e. it looked for messages after the simulator last woke it and found nothing to do.
+#[derive(Debug)]
+pub struct TransportIdleState {
+    wake_ups: AtomicU64,
+    parked_after_wake_up: AtomicU64,
+}
+impl TransportIdleState {
+    const BUSY: u64 = u64::MAX;
+
+    fn new() -> Self {
+        Self {
+            wake_ups: AtomicU64::new(0),
+            parked_after_wake_up: AtomicU64::new(Self::BUSY),
+        }
+    }
+
+    /// Called by the simulator right before it unparks the Raft thread
+    pub fn record_wake_up(&self) {
+        let _ = self.wake_ups.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// True once the Raft thread parked with nothing to do since the last wake up
+    pub fn is_idle(&self) -> bool {
+        self.parked_after_wake_up.load(Ordering::Acquire) == self.wake_ups.load(Ordering::Acquire)
+    }
+}
 
 /// Transport used by raft nodes in the simulator. Allows the simulated network to send/receive messages from the raft nodes.
 /// Parks the Raft node's thread when it is waiting for the next message, and unparks it when the simulator clock is updated
 /// so that it can check if the wait timeout has been reached.
-pub(crate) struct SimNetworkRaftTransportConnector {
-    outbound_message_tx: mpsc::Sender<RpcMessage<SimLogCommand>>,
-    inbound_message_rx: mpsc::Receiver<RpcMessage<SimLogCommand>>,
+pub struct SimNetworkRaftTransportConnector<C: LogCommand> {
+    outbound_message_tx: mpsc::Sender<RpcMessage<C>>,
+    inbound_message_rx: mpsc::Receiver<RpcMessage<C>>,
     wake_up_tx: mpsc::Sender<WakeUpAtOrBefore>,
     thread_handle: Option<thread::Thread>,
+    idle_state: Arc<TransportIdleState>,
+    clock: SimClock,
 }
-impl SimNetworkRaftTransportConnector {
-    pub(crate) fn new(
-        outbound_message_tx: mpsc::Sender<RpcMessage<SimLogCommand>>,
-        inbound_message_rx: mpsc::Receiver<RpcMessage<SimLogCommand>>,
+impl<C: LogCommand> SimNetworkRaftTransportConnector<C> {
+    pub fn new(
+        outbound_message_tx: mpsc::Sender<RpcMessage<C>>,
+        inbound_message_rx: mpsc::Receiver<RpcMessage<C>>,
         timer_tx: mpsc::Sender<WakeUpAtOrBefore>,
+        clock: SimClock,
     ) -> Self {
         Self {
             outbound_message_tx,
             inbound_message_rx,
             wake_up_tx: timer_tx,
             thread_handle: None,
+            idle_state: Arc::new(TransportIdleState::new()),
+            clock,
         }
     }
+
+    pub fn idle_state(&self) -> Arc<TransportIdleState> {
+        self.idle_state.clone()
+    }
 }
 
-impl RaftTransportConnector<SimLogCommand> for SimNetworkRaftTransportConnector {
+impl<C: LogCommand> RaftTransportConnector<C> for SimNetworkRaftTransportConnector<C> {
     fn wait_for_next_incoming_message(
         &mut self,
         max_wait: Duration,
-    ) -> Result<Option<RpcMessage<SimLogCommand>>, RaftTransportError> {
+    ) -> Result<Option<RpcMessage<C>>, RaftTransportError> {
         let current_thread = thread::current();
         let current_thread_id = current_thread.id();
         let saved_handle = self.thread_handle.get_or_insert(current_thread);
@@ -53,11 +94,11 @@ impl RaftTransportConnector<SimLogCommand> for SimNetworkRaftTransportConnector
             "Simulated network transport can only be used from a single thread"
         );
 
-        let started_waiting_at = system_clock::now();
+        let started_waiting_at = self.clock.now();
 
         match self
             .wake_up_tx
-            .send(WakeUpAtOrBefore(SimTime::now() + max_wait))
+            .send(WakeUpAtOrBefore(started_waiting_at + max_wait))
         {
             Ok(_) => {}
             Err(SendError(_)) => {
@@ -67,14 +108,37 @@ impl RaftTransportConnector<SimLogCommand> for SimNetworkRaftTransportConnector
 
         loop {
             trace!("Simulated network transport checking for incoming messages...");
+            // Read before checking for messages, so messages delivered before the wake up are seen
+            let wake_up = self.idle_state.wake_ups.load(Ordering::Acquire);
             match self.inbound_message_rx.try_recv() {
-                Ok(message) => return Ok(Some(message)),
+                Ok(message) => {
+                    self.idle_state
+                        .parked_after_wake_up
+                        .store(TransportIdleState::BUSY, Ordering::Release);
+                    return Ok(Some(message));
+                }
                 Err(TryRecvError::Empty) => {
-                    let time_waited = system_clock::now() - started_waiting_at;
+                    let time_waited = self
+                        .clock
+                        .now()
+                        .checked_sub(&started_waiting_at)
+                        .unwrap_or_default();
                     if time_waited >= max_wait {
+                        if max_wait.is_zero() {
+                            // Its timers are already due, the thread spins until the clock moves on
+                            self.idle_state
+                                .parked_after_wake_up
+                                .store(wake_up, Ordering::Release);
+                        }
                         return Ok(None);
                     }
+                    self.idle_state
+                        .parked_after_wake_up
+                        .store(wake_up, Ordering::Release);
                     thread::park();
+                    self.idle_state
+                        .parked_after_wake_up
+                        .store(TransportIdleState::BUSY, Ordering::Release);
                 }
                 Err(TryRecvError::Disconnected) => {
                     return Err(RaftTransportError::TransportShutdown);
@@ -83,10 +147,7 @@ impl RaftTransportConnector<SimLogCommand> for SimNetworkRaftTransportConnector
         }
     }
 
-    fn enqueue_outgoing_request(
-        &mut self,
-        request: Request<SimLogCommand>,
-    ) -> Result<(), RaftTransportError> {
+    fn enqueue_outgoing_request(&mut self, request: Request<C>) -> Result<(), RaftTransportError> {
         match self.outbound_message_tx.send(RpcMessage::Request(request)) {
             Ok(_) => Ok(()),
             Err(SendError(_)) => Err(RaftTransportError::TransportShutdown),
@@ -101,15 +162,16 @@ impl RaftTransportConnector<SimLogCommand> for SimNetworkRaftTransportConnector
     }
 }
 
+#[cfg(test)]
 mod tests {
     use std::thread;
     use test_log::test;
 
-    use mock_instant::MockClock;
     use std::time::Duration;
     use tracing::debug;
 
-    use raft_consensus::{
+    use crate::testkit::common::{SimClock, SimLogCommand};
+    use crate::{
         rpc_messages::{ReplyTo, RpcMessage, Vote},
         RaftTransportConnector, ServerId, TermIndex,
     };
@@ -117,7 +179,7 @@ mod tests {
     #[test]
     fn sim_transport_should_be_send() {
         fn assert_send<T: Send>() {}
-        assert_send::<super::SimNetworkRaftTransportConnector>();
+        assert_send::<super::SimNetworkRaftTransportConnector<SimLogCommand>>();
     }
 
     #[test]
@@ -126,8 +188,12 @@ mod tests {
         let (inbound_tx, inbound_rx) = std::sync::mpsc::channel();
         let (timer_tx, _timer_rx) = std::sync::mpsc::channel();
 
-        let mut transport =
-            super::SimNetworkRaftTransportConnector::new(outbound_tx, inbound_rx, timer_tx);
+        let mut transport = super::SimNetworkRaftTransportConnector::<SimLogCommand>::new(
+            outbound_tx,
+            inbound_rx,
+            timer_tx,
+            SimClock::new(),
+        );
 
         let thread_handle = std::thread::spawn(move || {
             match transport.wait_for_next_incoming_message(Duration::from_millis(127)) {
@@ -160,9 +226,14 @@ mod tests {
         let (outbound_tx, _) = std::sync::mpsc::channel();
         let (_inbound_tx, inbound_rx) = std::sync::mpsc::channel();
         let (timer_tx, _timer_rx) = std::sync::mpsc::channel();
+        let clock = SimClock::new();
 
-        let mut transport =
-            super::SimNetworkRaftTransportConnector::new(outbound_tx, inbound_rx, timer_tx);
+        let mut transport = super::SimNetworkRaftTransportConnector::<SimLogCommand>::new(
+            outbound_tx,
+            inbound_rx,
+            timer_tx,
+            clock.clone(),
+        );
 
         let thread_handle = std::thread::spawn(move || {
             let message = transport.wait_for_next_incoming_message(Duration::from_millis(127));
@@ -188,9 +259,9 @@ mod tests {
         assert!(!thread_handle.is_finished());
 
         // Now if we advance the clock, it should timeout when it is unparked
-        MockClock::advance(Duration::from_millis(128));
+        clock.advance(Duration::from_millis(128));
         thread_handle.thread().unpark();
 
-        assert_eq!(true, thread_handle.join().unwrap());
+        assert!(thread_handle.join().unwrap());
     }
 }
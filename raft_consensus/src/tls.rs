@@ -0,0 +1,179 @@
+use crate::tcp_transport::{Accepted, Connection};
+use raft_core::ServerId;
+use rustls::client::verify_server_name;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::{ParsedCertificate, WebPkiClientVerifier};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use rustls::{StreamOwned, SupportedProtocolVersion};
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+/// First byte of every TLS connection, the content type of the ClientHello's record
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Certificates and settings for [`TcpTransport`](crate::TcpTransport) connections to go over
+/// TLS. Peers are checked against their IP address unless given a name with
+/// [`TlsConfig::server_name`].
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+    server_names: HashMap<ServerId, ServerName<'static>>,
+    required: bool,
+    authenticate_peers: bool,
+}
+impl TlsConfig {
+    /// Accepts peers' connections with `server` and connects to peers with `client`
+    pub fn new(server: Arc<ServerConfig>, client: Arc<ClientConfig>) -> Self {
+        TlsConfig {
+            server,
+            client,
+            server_names: HashMap::new(),
+            required: true,
+            authenticate_peers: false,
+        }
+    }
+
+    /// Mutual TLS for a cluster whose servers all have a certificate signed by `ca`. The server
+    /// shows `cert_chain` both to peers connecting to it and to peers it connects to, and only
+    /// talks to peers whose certificate `ca` signed.
+    pub fn mutual(
+        ca: CertificateDer<'static>,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, rustls::Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let versions: &[&SupportedProtocolVersion] = rustls::DEFAULT_VERSIONS;
+        let mut roots = RootCertStore::empty();
+        roots.add(ca)?;
+        let roots = Arc::new(roots);
+        let client_verifier =
+            WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+                .build()
+                .map_err(|error| rustls::Error::General(error.to_string()))?;
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(versions)?
+            .with_client_cert_verifier(client_verifier)
+            .with_single_cert(cert_chain.clone(), key.clone_key())?;
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(cert_chain, key)?;
+        Ok(Self::new(Arc::new(server), Arc::new(client)))
+    }
+
+    /// Name to ask `peer` for through SNI and to check its certificate against, rather than its
+    /// IP address
+    pub fn server_name(mut self, peer: ServerId, name: ServerName<'static>) -> Self {
+        let _ = self.server_names.insert(peer, name);
+        self
+    }
+
+    /// Whether peers have to connect over TLS, on by default. Turned off, peers that don't use TLS
+    /// yet can still connect in plain TCP. This server connects to its peers over TLS either way.
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Whether a peer connecting to this server has to show a client certificate for one of the
+    /// peers' names, off by default. Messages over its connection then have to come from a peer
+    /// the certificate is for, so a process that isn't one of the peers can't send votes or
+    /// entries. Peers go by their name given with [`TlsConfig::server_name`], or else their IP
+    /// address. Needs a server config that asks for client certificates, like
+    /// [`TlsConfig::mutual`]'s, and implies TLS is required.
+    pub fn authenticate_peers(mut self, authenticate: bool) -> Self {
+        self.authenticate_peers = authenticate;
+        self
+    }
+
+    /// Names every peer in `peers` without one by its IP address
+    pub(crate) fn name_peers_by_address(&mut self, peers: &HashMap<ServerId, SocketAddr>) {
+        for (peer, addr) in peers {
+            let _ = self
+                .server_names
+                .entry(*peer)
+                .or_insert_with(|| ServerName::IpAddress(addr.ip().into()));
+        }
+    }
+
+    /// `stream` from a peer, read through TLS unless the peer didn't start a handshake and TLS
+    /// isn't required. With peers authenticated, also the servers the peer's certificate is for.
+    pub(crate) fn accept(&self, mut stream: TcpStream) -> io::Result<Accepted> {
+        let mut first_byte = [0; 1];
+        let starts_handshake =
+            stream.peek(&mut first_byte)? == 1 && first_byte[0] == TLS_HANDSHAKE_RECORD;
+        if !starts_handshake {
+            if self.required || self.authenticate_peers {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "peer connected without TLS",
+                ));
+            }
+            return Ok(Accepted {
+                stream: Box::new(stream),
+                senders: None,
+            });
+        }
+        let mut connection =
+            ServerConnection::new(self.server.clone()).map_err(io::Error::other)?;
+        if !self.authenticate_peers {
+            return Ok(Accepted {
+                stream: Box::new(StreamOwned::new(connection, stream)),
+                senders: None,
+            });
+        }
+        while connection.is_handshaking() {
+            let _ = connection.complete_io(&mut stream)?;
+        }
+        let senders = self.peers_certified(connection.peer_certificates())?;
+        Ok(Accepted {
+            stream: Box::new(StreamOwned::new(connection, stream)),
+            senders: Some(senders),
+        })
+    }
+
+    /// The peers whose name the end entity of `certificates` is valid for, an error for none
+    fn peers_certified(
+        &self,
+        certificates: Option<&[CertificateDer<'static>]>,
+    ) -> io::Result<HashSet<ServerId>> {
+        let refused = |reason: &str| io::Error::new(io::ErrorKind::PermissionDenied, reason);
+        let end_entity = certificates
+            .and_then(|certificates| certificates.first())
+            .ok_or_else(|| refused("peer showed no client certificate"))?;
+        let certificate = ParsedCertificate::try_from(end_entity).map_err(io::Error::other)?;
+        let senders: HashSet<ServerId> = self
+            .server_names
+            .iter()
+            .filter(|(_, name)| verify_server_name(&certificate, name).is_ok())
+            .map(|(peer, _)| *peer)
+            .collect();
+        if senders.is_empty() {
+            return Err(refused("peer's certificate is for none of the peers"));
+        }
+        Ok(senders)
+    }
+
+    /// `stream` to `peer` with the handshake done, so a peer that turns this server down is found
+    /// out before any frame is written
+    pub(crate) fn connect(
+        &self,
+        peer: ServerId,
+        mut stream: TcpStream,
+    ) -> io::Result<Box<dyn Connection>> {
+        let name = match self.server_names.get(&peer) {
+            Some(name) => name.clone(),
+            None => ServerName::IpAddress(stream.peer_addr()?.ip().into()),
+        };
+        let mut connection =
+            ClientConnection::new(self.client.clone(), name).map_err(io::Error::other)?;
+        while connection.is_handshaking() {
+            let _ = connection.complete_io(&mut stream)?;
+        }
+        Ok(Box::new(StreamOwned::new(connection, stream)))
+    }
+}
@@ -0,0 +1,218 @@
+use crate::codec::{BincodeCodec, CodecError, CommandCodec};
+use crate::default_storage::{decode_log_record, encode_log_record};
+use raft_core::rpc_messages::{
+    AppendEntries, ForwardProposals, InstallSnapshot, ReplyTo, Request, RequestVote, RpcMessage,
+    TimeoutNow,
+};
+use raft_core::{ClientProposal, ClientSession, LogCommand, LogIndex, ServerId, TermIndex};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Turns the messages a transport carries into bytes and back, so the format is picked apart
+/// from how bytes get to peers. Every server of a cluster has to use the same one.
+pub trait WireCodec<C: LogCommand>: std::fmt::Debug + Send + Sync {
+    /// The bytes `message` goes over the network as
+    fn encode(&self, message: &RpcMessage<C>) -> Result<Vec<u8>, CodecError>;
+
+    /// The message `bytes` were encoded from
+    fn decode(&self, bytes: &[u8]) -> Result<RpcMessage<C>, CodecError>;
+
+    /// Media type of encoded messages, for transports that label what they send, like HTTP
+    fn content_type(&self) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// A message as bincode encodes it, with its commands encoded by the command codec
+#[derive(Serialize, Deserialize)]
+enum WireMessage {
+    AppendEntries {
+        request_id: Uuid,
+        from: ServerId,
+        to: ServerId,
+        term: TermIndex,
+        prev_log_term: TermIndex,
+        prev_log_index: LogIndex,
+        /// Encoded like the records of the log file
+        entries: Vec<Vec<u8>>,
+        leader_commit: LogIndex,
+    },
+    RequestVote(RequestVote),
+    PreVote(RequestVote),
+    InstallSnapshot(InstallSnapshot),
+    TimeoutNow(TimeoutNow),
+    ForwardProposals {
+        request_id: Uuid,
+        from: ServerId,
+        to: ServerId,
+        term: TermIndex,
+        proposals: Vec<(Vec<u8>, Option<ClientSession>)>,
+        hops: u32,
+    },
+    Reply(ReplyTo),
+}
+
+/// Compact binary messages with bincode's default options, their commands encoded by a
+/// [`CommandCodec`], [`BincodeCodec`] unless told otherwise
+#[derive(Debug, Clone)]
+pub struct BincodeWireCodec<C: LogCommand> {
+    commands: Arc<dyn CommandCodec<C>>,
+}
+impl<C: LogCommand> BincodeWireCodec<C> {
+    pub fn new() -> Self {
+        Self::with_command_codec(Arc::new(BincodeCodec))
+    }
+
+    /// Encodes the commands in messages with `commands`
+    pub fn with_command_codec(commands: Arc<dyn CommandCodec<C>>) -> Self {
+        BincodeWireCodec { commands }
+    }
+}
+impl<C: LogCommand> Default for BincodeWireCodec<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<C: LogCommand> WireCodec<C> for BincodeWireCodec<C> {
+    fn encode(&self, message: &RpcMessage<C>) -> Result<Vec<u8>, CodecError> {
+        let commands = &*self.commands;
+        let message = match message {
+            RpcMessage::Request(Request::AppendEntries(append_entries)) => {
+                WireMessage::AppendEntries {
+                    request_id: append_entries.request_id,
+                    from: append_entries.from,
+                    to: append_entries.to,
+                    term: append_entries.term,
+                    prev_log_term: append_entries.prev_log_term,
+                    prev_log_index: append_entries.prev_log_index,
+                    entries: append_entries
+                        .entries
+                        .iter()
+                        .map(|entry| {
+                            encode_log_record(commands, entry)
+                                .map_err(|error| CodecError(format!("{:?}", error)))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    leader_commit: append_entries.leader_commit,
+                }
+            }
+            RpcMessage::Request(Request::RequestVote(request_vote)) => {
+                WireMessage::RequestVote(request_vote.clone())
+            }
+            RpcMessage::Request(Request::PreVote(pre_vote)) => {
+                WireMessage::PreVote(pre_vote.clone())
+            }
+            RpcMessage::Request(Request::InstallSnapshot(install_snapshot)) => {
+                WireMessage::InstallSnapshot(install_snapshot.clone())
+            }
+            RpcMessage::Request(Request::TimeoutNow(timeout_now)) => {
+                WireMessage::TimeoutNow(timeout_now.clone())
+            }
+            RpcMessage::Request(Request::ForwardProposals(forward_proposals)) => {
+                WireMessage::ForwardProposals {
+                    request_id: forward_proposals.request_id,
+                    from: forward_proposals.from,
+                    to: forward_proposals.to,
+                    term: forward_proposals.term,
+                    proposals: forward_proposals
+                        .proposals
+                        .iter()
+                        .map(|proposal| Ok((commands.encode(&proposal.command)?, proposal.session)))
+                        .collect::<Result<_, CodecError>>()?,
+                    hops: forward_proposals.hops,
+                }
+            }
+            RpcMessage::Reply(reply) => WireMessage::Reply(reply.clone()),
+        };
+        bincode::DefaultOptions::new()
+            .serialize(&message)
+            .map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<RpcMessage<C>, CodecError> {
+        let commands = &*self.commands;
+        let message: WireMessage = bincode::DefaultOptions::new()
+            .deserialize(bytes)
+            .map_err(|error| CodecError(error.to_string()))?;
+        Ok(match message {
+            WireMessage::AppendEntries {
+                request_id,
+                from,
+                to,
+                term,
+                prev_log_term,
+                prev_log_index,
+                entries,
+                leader_commit,
+            } => RpcMessage::append_entries(AppendEntries {
+                request_id,
+                from,
+                to,
+                term,
+                prev_log_term,
+                prev_log_index,
+                entries: entries
+                    .iter()
+                    .map(|record| {
+                        decode_log_record(commands, record)
+                            .map_err(|error| CodecError(format!("{:?}", error)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into(),
+                leader_commit,
+            }),
+            WireMessage::RequestVote(request_vote) => RpcMessage::request_vote(request_vote),
+            WireMessage::PreVote(pre_vote) => RpcMessage::pre_vote(pre_vote),
+            WireMessage::InstallSnapshot(install_snapshot) => {
+                RpcMessage::install_snapshot(install_snapshot)
+            }
+            WireMessage::TimeoutNow(timeout_now) => RpcMessage::timeout_now(timeout_now),
+            WireMessage::ForwardProposals {
+                request_id,
+                from,
+                to,
+                term,
+                proposals,
+                hops,
+            } => RpcMessage::forward_proposals(ForwardProposals {
+                request_id,
+                from,
+                to,
+                term,
+                proposals: proposals
+                    .iter()
+                    .map(|(command, session)| {
+                        Ok(ClientProposal {
+                            command: commands.decode(command)?,
+                            session: *session,
+                        })
+                    })
+                    .collect::<Result<_, CodecError>>()?,
+                hops,
+            }),
+            WireMessage::Reply(reply) => RpcMessage::Reply(reply),
+        })
+    }
+}
+
+/// Messages as JSON, commands included, for transports whose traffic should be readable
+#[cfg(feature = "json_codec")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonWireCodec;
+#[cfg(feature = "json_codec")]
+impl<C: LogCommand> WireCodec<C> for JsonWireCodec {
+    fn encode(&self, message: &RpcMessage<C>) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(message).map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<RpcMessage<C>, CodecError> {
+        serde_json::from_slice(bytes).map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+}
@@ -0,0 +1,246 @@
+use raft_consensus::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_consensus::{
+    ApplicationThatNeedsConsensus, AsyncRaftTransport, DefaultPersistentStorage, LogIndex,
+    NodeBuildError, PersistentStorage, RaftNodeBuilder, RaftTransportError, ServerId, Snapshot,
+    SpawnBlockingStorage,
+};
+/// Tests nodes running as tasks on a tokio runtime
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+
+/// Transport handing messages to the other nodes' channels, or nowhere without any
+struct ChannelTransport {
+    incoming: mpsc::UnboundedReceiver<RpcMessage<u64>>,
+    peers: HashMap<ServerId, mpsc::UnboundedSender<RpcMessage<u64>>>,
+}
+impl ChannelTransport {
+    /// Transport of a cluster of one, nothing ever comes in
+    fn silent() -> Self {
+        let (_, incoming) = mpsc::unbounded_channel();
+        ChannelTransport {
+            incoming,
+            peers: HashMap::new(),
+        }
+    }
+
+    fn send(&self, message: RpcMessage<u64>) -> Result<(), RaftTransportError> {
+        if let Some(peer) = self.peers.get(&message.to()) {
+            // A stopped peer is like one that's unreachable
+            let _ = peer.send(message);
+        }
+        Ok(())
+    }
+}
+impl AsyncRaftTransport<u64> for ChannelTransport {
+    async fn next_incoming_message(&mut self) -> Result<RpcMessage<u64>, RaftTransportError> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or(RaftTransportError::TransportShutdown)
+    }
+
+    fn enqueue_reply(&mut self, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        self.send(RpcMessage::Reply(reply))
+    }
+
+    fn enqueue_outgoing_request(
+        &mut self,
+        request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        self.send(RpcMessage::Request(request))
+    }
+}
+
+/// Transports connecting `servers` to each other
+fn connect(servers: &[ServerId]) -> Vec<ChannelTransport> {
+    let (senders, receivers): (HashMap<_, _>, Vec<_>) = servers
+        .iter()
+        .map(|server_id| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            ((*server_id, sender), receiver)
+        })
+        .unzip();
+    receivers
+        .into_iter()
+        .map(|incoming| ChannelTransport {
+            incoming,
+            peers: senders.clone(),
+        })
+        .collect()
+}
+
+/// Application adding up the commands it applied
+struct SumApplication {
+    sum: u64,
+    last_applied: LogIndex,
+}
+impl SumApplication {
+    fn new() -> Self {
+        SumApplication {
+            sum: 0,
+            last_applied: LogIndex(0),
+        }
+    }
+}
+impl ApplicationThatNeedsConsensus for SumApplication {
+    type Command = u64;
+    type Output = u64;
+    type Error = ();
+
+    fn apply(&mut self, log_index: LogIndex, command: u64) -> Result<u64, ()> {
+        self.sum += command;
+        self.last_applied = log_index;
+        Ok(self.sum)
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, _snapshot: &Snapshot) {}
+}
+
+#[test]
+fn should_commit_and_shut_down_nodes_running_as_tasks() {
+    let storage_dir = TempDir::new().unwrap();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_time()
+        .build()
+        .unwrap();
+    let _entered = runtime.enter();
+
+    let servers = [ServerId(1), ServerId(2), ServerId(3)];
+    let nodes: Vec<_> = servers
+        .iter()
+        .zip(connect(&servers))
+        .map(|(server_id, transport)| {
+            let storage_path = storage_dir.path().join(format!("server-{}", server_id.0));
+            std::fs::create_dir(&storage_path).unwrap();
+            RaftNodeBuilder::new(*server_id, SumApplication::new())
+                .peers(
+                    servers
+                        .iter()
+                        .copied()
+                        .filter(|id| id != server_id)
+                        .collect::<HashSet<_>>(),
+                )
+                .storage_path(storage_path.to_str().unwrap())
+                .transport(transport)
+                .rng_seed(server_id.0)
+                .start_async()
+                .unwrap()
+        })
+        .collect();
+
+    // Whoever wins the election takes the command, the others turn it down
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let applied = loop {
+        assert!(Instant::now() < deadline, "no node ever committed");
+        let applied = nodes.iter().find_map(|node| {
+            match node
+                .propose(5)
+                .unwrap()
+                .wait_timeout(Duration::from_millis(500))
+            {
+                Some(Ok(applied)) => Some(applied),
+                _ => None,
+            }
+        });
+        if let Some(applied) = applied {
+            break applied;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(applied.result, Ok(5));
+
+    for node in nodes {
+        assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    }
+}
+
+#[test]
+fn should_refuse_to_start_a_task_outside_a_runtime() {
+    let storage_dir = TempDir::new().unwrap();
+
+    let started = RaftNodeBuilder::new(ServerId(1), SumApplication::new())
+        .storage_path(storage_dir.path().to_str().unwrap())
+        .transport(ChannelTransport::silent())
+        .start_async();
+
+    assert_eq!(started.err(), Some(NodeBuildError::NoAsyncRuntime));
+}
+
+#[test]
+fn should_commit_on_async_storage() {
+    let storage_dir = TempDir::new().unwrap();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_time()
+        .build()
+        .unwrap();
+    let _entered = runtime.enter();
+
+    let servers = [ServerId(1), ServerId(2), ServerId(3)];
+    let storage_path =
+        |server_id: &ServerId| storage_dir.path().join(format!("server-{}", server_id.0));
+    let nodes: Vec<_> = servers
+        .iter()
+        .zip(connect(&servers))
+        .map(|(server_id, transport)| {
+            std::fs::create_dir(storage_path(server_id)).unwrap();
+            let storage = DefaultPersistentStorage::<u64>::new(&storage_path(server_id)).unwrap();
+            RaftNodeBuilder::new(*server_id, SumApplication::new())
+                .peers(
+                    servers
+                        .iter()
+                        .copied()
+                        .filter(|id| id != server_id)
+                        .collect::<HashSet<_>>(),
+                )
+                .async_storage(SpawnBlockingStorage::new(storage))
+                .transport(transport)
+                .rng_seed(server_id.0)
+                .start_async()
+                .unwrap()
+        })
+        .collect();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let applied = loop {
+        assert!(Instant::now() < deadline, "no node ever committed");
+        let applied = nodes.iter().find_map(|node| {
+            match node
+                .propose(5)
+                .unwrap()
+                .wait_timeout(Duration::from_millis(500))
+            {
+                Some(Ok(applied)) => Some(applied),
+                _ => None,
+            }
+        });
+        if let Some(applied) = applied {
+            break applied;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(applied.result, Ok(5));
+    for node in nodes {
+        assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    }
+
+    // The term the leader won its election in and the entry made it to its disk
+    let leader_storage = servers
+        .iter()
+        .map(|server_id| DefaultPersistentStorage::<u64>::new(&storage_path(server_id)).unwrap())
+        .find(|storage| storage.last_entry_index() >= Some(applied.index))
+        .expect("no node kept the entry");
+    assert!(leader_storage.current_term().0 > 0);
+}
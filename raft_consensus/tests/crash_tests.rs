@@ -0,0 +1,651 @@
+use raft_consensus::rpc_messages::{ReplyTo, Request, RpcMessage, Vote};
+use raft_consensus::{
+    ApplicationThatNeedsConsensus, ChannelOverflowPolicy, ChannelRaftEventCollector,
+    DefaultPersistentStorage, ElectionTimeoutRange, HeartbeatInterval, LogIndex, NodeBuildError,
+    PersistentStorage, PersistentStorageError, ProposalError, RaftConfig, RaftConfigError,
+    RaftError, RaftErrorRecovery, RaftEvent, RaftNodeBuilder, RaftNodeState,
+    RaftTransportConnector, RaftTransportError, ReplicationWindow, ServerId, ShutdownError,
+    Snapshot, StorageFullPolicy, TermIndex, TimingUpdate, TimingUpdateError,
+};
+/// Tests what a node reports when its Raft thread panics, halts or is shut down, and what it picks
+/// back up from storage when restarted
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Transport whose network layer blows up as soon as the node waits for a message
+struct PanickingTransport;
+impl RaftTransportConnector<u64> for PanickingTransport {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        _max_wait: Duration,
+    ) -> Result<Option<RpcMessage<u64>>, RaftTransportError> {
+        panic!("network stack exploded");
+    }
+
+    fn enqueue_reply(&mut self, _reply: ReplyTo) -> Result<(), RaftTransportError> {
+        Ok(())
+    }
+
+    fn enqueue_outgoing_request(
+        &mut self,
+        _request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        Ok(())
+    }
+}
+
+/// Transport that was shut down before the node got to use it
+struct ShutDownTransport;
+impl RaftTransportConnector<u64> for ShutDownTransport {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        _max_wait: Duration,
+    ) -> Result<Option<RpcMessage<u64>>, RaftTransportError> {
+        Err(RaftTransportError::TransportShutdown)
+    }
+
+    fn enqueue_reply(&mut self, _reply: ReplyTo) -> Result<(), RaftTransportError> {
+        Err(RaftTransportError::TransportShutdown)
+    }
+
+    fn enqueue_outgoing_request(
+        &mut self,
+        _request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        Err(RaftTransportError::TransportShutdown)
+    }
+}
+
+/// Transport of a network nobody else is on, the node only ever hears from its timer
+struct SilentTransport;
+impl RaftTransportConnector<u64> for SilentTransport {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        max_wait: Duration,
+    ) -> Result<Option<RpcMessage<u64>>, RaftTransportError> {
+        std::thread::sleep(max_wait.min(Duration::from_millis(10)));
+        Ok(None)
+    }
+
+    fn enqueue_reply(&mut self, _reply: ReplyTo) -> Result<(), RaftTransportError> {
+        Ok(())
+    }
+
+    fn enqueue_outgoing_request(
+        &mut self,
+        _request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        Ok(())
+    }
+}
+
+/// Transport of peers that vote for whoever asks and never answer anything else
+#[derive(Default)]
+struct VotingTransport {
+    votes: Vec<Vote>,
+}
+impl RaftTransportConnector<u64> for VotingTransport {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        max_wait: Duration,
+    ) -> Result<Option<RpcMessage<u64>>, RaftTransportError> {
+        match self.votes.pop() {
+            Some(vote) => Ok(Some(RpcMessage::Reply(ReplyTo::RequestVote(vote)))),
+            None => {
+                std::thread::sleep(max_wait.min(Duration::from_millis(10)));
+                Ok(None)
+            }
+        }
+    }
+
+    fn enqueue_reply(&mut self, _reply: ReplyTo) -> Result<(), RaftTransportError> {
+        Ok(())
+    }
+
+    fn enqueue_outgoing_request(
+        &mut self,
+        request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        if let Request::RequestVote(request) = request {
+            self.votes.push(Vote {
+                request_id: request.request_id,
+                from: request.to,
+                to: request.from,
+                term: request.term,
+                vote_granted: true,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Application for nodes that stop before they get to commit anything
+struct IdleApplication;
+impl ApplicationThatNeedsConsensus for IdleApplication {
+    type Command = u64;
+    type Output = ();
+    type Error = ();
+
+    fn apply(&mut self, _log_index: LogIndex, _command: u64) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        LogIndex(0)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, _snapshot: &Snapshot) {}
+}
+
+fn halted_with(events: &std::sync::mpsc::Receiver<RaftEvent>) -> Option<RaftError> {
+    events.try_iter().find_map(|event| match event {
+        RaftEvent::NodeHalted { error, .. } => Some(error),
+        _ => None,
+    })
+}
+
+#[test]
+fn should_pick_recovery_per_error() {
+    assert_eq!(
+        RaftError::Storage(PersistentStorageError::IoError).recovery(),
+        RaftErrorRecovery::Retry
+    );
+    assert_eq!(
+        RaftError::Storage(PersistentStorageError::SerdeError).recovery(),
+        RaftErrorRecovery::Halt
+    );
+    assert_eq!(
+        RaftError::Transport(RaftTransportError::TransportShutdown).recovery(),
+        RaftErrorRecovery::Halt
+    );
+    assert_eq!(
+        RaftError::InvariantViolated("two leaders in one term").recovery(),
+        RaftErrorRecovery::StepDown
+    );
+    assert_eq!(
+        RaftError::Storage(PersistentStorageError::StorageFull).recovery(),
+        RaftErrorRecovery::Halt
+    );
+    assert_eq!(
+        StorageFullPolicy::RejectProposals.recovery(),
+        RaftErrorRecovery::StepDown
+    );
+}
+
+#[test]
+fn should_refuse_to_start_a_node_missing_what_it_needs() {
+    let storage_dir = TempDir::new().unwrap();
+    let storage_path = storage_dir.path().to_str().unwrap().to_string();
+
+    let without_storage = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .transport(ShutDownTransport)
+        .start();
+    assert_eq!(
+        without_storage.err(),
+        Some(NodeBuildError::MissingStoragePath)
+    );
+
+    let own_peer = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(1), ServerId(2)]))
+        .storage_path(storage_path.clone())
+        .transport(ShutDownTransport)
+        .start();
+    assert_eq!(
+        own_peer.err(),
+        Some(NodeBuildError::ServerIsOwnPeer(ServerId(1)))
+    );
+
+    let mut config = RaftConfig::builder().build().unwrap();
+    config.replication_window = ReplicationWindow(0);
+    let invalid_config = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .storage_path(storage_path)
+        .transport(ShutDownTransport)
+        .config(config)
+        .start();
+    assert_eq!(
+        invalid_config.err(),
+        Some(NodeBuildError::InvalidConfig(
+            RaftConfigError::ZeroReplicationWindow
+        ))
+    );
+}
+
+#[test]
+fn should_halt_with_event_when_transport_shuts_down() {
+    let storage_dir = TempDir::new().unwrap();
+    let (event_collector, events) =
+        ChannelRaftEventCollector::new(16, ChannelOverflowPolicy::Block);
+
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(ShutDownTransport)
+        .rng_seed(0)
+        .event_collector(event_collector)
+        .start()
+        .unwrap();
+
+    let shutdown = RaftError::Transport(RaftTransportError::TransportShutdown);
+    assert_eq!(node.join().unwrap(), Err(shutdown));
+    assert_eq!(halted_with(&events), Some(shutdown));
+}
+
+#[test]
+fn should_halt_with_event_when_storage_cannot_be_opened() {
+    let storage_dir = TempDir::new().unwrap();
+    // A file where the storage directory should be
+    let storage_path = storage_dir.path().join("not-a-directory");
+    std::fs::write(&storage_path, b"").unwrap();
+    let (event_collector, events) =
+        ChannelRaftEventCollector::new(16, ChannelOverflowPolicy::Block);
+
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_path.to_str().unwrap().to_string())
+        .transport(PanickingTransport)
+        .rng_seed(0)
+        .event_collector(event_collector)
+        .start()
+        .unwrap();
+
+    let storage_error = RaftError::Storage(PersistentStorageError::IoError);
+    assert_eq!(node.join().unwrap(), Err(storage_error));
+    assert_eq!(halted_with(&events), Some(storage_error));
+}
+
+#[test]
+fn should_publish_crash_event_when_raft_thread_panics() {
+    let storage_dir = TempDir::new().unwrap();
+    let (event_collector, events) =
+        ChannelRaftEventCollector::new(16, ChannelOverflowPolicy::Block);
+
+    let node = RaftNodeBuilder::new(ServerId(7), IdleApplication)
+        .peers(HashSet::from([ServerId(8), ServerId(9)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(PanickingTransport)
+        .thread_stack_size(512 * 1024)
+        .rng_seed(0)
+        .event_collector(event_collector)
+        .start()
+        .unwrap();
+
+    assert_eq!(node.thread().name(), Some("raft-server-7"));
+    assert!(node.join().is_err());
+    let crash = events
+        .try_iter()
+        .find(|event| matches!(event, RaftEvent::NodeCrashed { .. }));
+    match crash {
+        Some(RaftEvent::NodeCrashed {
+            server_id,
+            message,
+            location,
+        }) => {
+            assert_eq!(server_id, ServerId(7));
+            assert_eq!(message, "network stack exploded");
+            assert!(location.unwrap().contains("crash_tests.rs"));
+        }
+        other => panic!("expected a crash event, got {:?}", other),
+    }
+}
+
+#[test]
+fn should_stop_with_event_when_shut_down() {
+    let storage_dir = TempDir::new().unwrap();
+    let (event_collector, events) =
+        ChannelRaftEventCollector::new(1024, ChannelOverflowPolicy::Block);
+
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(SilentTransport)
+        .rng_seed(0)
+        .event_collector(event_collector)
+        .start()
+        .unwrap();
+
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    assert!(events.try_iter().any(|event| event
+        == RaftEvent::NodeStopped {
+            server_id: ServerId(1)
+        }));
+}
+
+#[test]
+fn should_report_why_a_halted_node_could_not_be_shut_down() {
+    let storage_dir = TempDir::new().unwrap();
+
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(ShutDownTransport)
+        .rng_seed(0)
+        .start()
+        .unwrap();
+
+    assert_eq!(
+        node.shutdown(Duration::from_secs(5)),
+        Err(ShutdownError::Halted(RaftError::Transport(
+            RaftTransportError::TransportShutdown
+        )))
+    );
+}
+
+#[test]
+fn should_resume_as_follower_of_persisted_term_after_restart() {
+    let storage_dir = TempDir::new().unwrap();
+    let start_node = || {
+        RaftNodeBuilder::new(ServerId(1), IdleApplication)
+            .peers(HashSet::from([ServerId(2), ServerId(3)]))
+            .storage_path(storage_dir.path().to_str().unwrap().to_string())
+            .transport(SilentTransport)
+            .config(RaftConfig::builder().pre_vote(false).build().unwrap())
+            .rng_seed(0)
+            .start()
+            .unwrap()
+    };
+
+    // Nobody answers, so the node keeps starting elections, voting for itself in each new term
+    let node = start_node();
+    let diagnostics = node.diagnostics();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while diagnostics
+        .latest()
+        .is_none_or(|latest| latest.state.current_term < TermIndex(2))
+    {
+        assert!(Instant::now() < deadline, "node never started an election");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    let stopped = diagnostics.latest().unwrap().state;
+
+    let node = start_node();
+    let diagnostics = node.diagnostics();
+    let restarted = loop {
+        if let Some(latest) = diagnostics.latest() {
+            break latest.state;
+        }
+        assert!(Instant::now() < deadline, "node never started");
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(restarted.current_state, RaftNodeState::Follower);
+    assert_eq!(restarted.current_term, stopped.current_term);
+    assert_eq!(restarted.voted_for, Some(ServerId(1)));
+    assert_eq!(restarted.last_log_index, stopped.last_log_index);
+    assert_eq!(restarted.last_log_term, stopped.last_log_term);
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn should_back_up_storage_while_the_node_runs() {
+    let storage_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(SilentTransport)
+        .config(RaftConfig::builder().pre_vote(false).build().unwrap())
+        .rng_seed(0)
+        .start()
+        .unwrap();
+
+    // Nobody answers, so the node keeps voting for itself in new terms
+    let diagnostics = node.diagnostics();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while diagnostics
+        .latest()
+        .is_none_or(|latest| latest.state.current_term < TermIndex(2))
+    {
+        assert!(Instant::now() < deadline, "node never started an election");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let backed_up = node
+        .backup_to(backup_dir.path())
+        .unwrap()
+        .wait_timeout(Duration::from_secs(10))
+        .expect("node never made the backup");
+    assert_eq!(backed_up, Ok(LogIndex(0)));
+    assert!(node.status().is_some());
+
+    let backup = DefaultPersistentStorage::<u64>::new(backup_dir.path()).unwrap();
+    assert!(backup.current_term() >= TermIndex(2));
+    assert_eq!(backup.vote_for_current_term(), Some(ServerId(1)));
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn should_turn_proposals_away_once_storage_is_full() {
+    let storage_dir = TempDir::new().unwrap();
+    // The leader's first entry takes the log file past its quota
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path())
+        .unwrap()
+        .byte_quota(16);
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage(storage)
+        .transport(VotingTransport::default())
+        .config(
+            RaftConfig::builder()
+                .pre_vote(false)
+                .check_quorum(false)
+                .storage_full_policy(StorageFullPolicy::RejectProposals)
+                .build()
+                .unwrap(),
+        )
+        .rng_seed(0)
+        .start()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        assert!(
+            Instant::now() < deadline,
+            "node never turned a proposal away"
+        );
+        // Proposals fail another way until the node leads
+        let outcome = match node.propose(7) {
+            Ok(pending) => pending.wait_timeout(Duration::from_secs(1)),
+            Err(error) => Some(Err(error)),
+        };
+        if outcome == Some(Err(ProposalError::StorageFull)) {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(node.status().is_some(), "node halted rather than carry on");
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn should_halt_with_event_when_storage_runs_full() {
+    let storage_dir = TempDir::new().unwrap();
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path())
+        .unwrap()
+        .byte_quota(16);
+    let (event_collector, events) =
+        ChannelRaftEventCollector::new(1024, ChannelOverflowPolicy::DropNewest);
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage(storage)
+        .transport(VotingTransport::default())
+        .config(
+            RaftConfig::builder()
+                .pre_vote(false)
+                .check_quorum(false)
+                .build()
+                .unwrap(),
+        )
+        .rng_seed(0)
+        .event_collector(event_collector)
+        .start()
+        .unwrap();
+
+    // The first command the leader appends has no room
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !node.is_finished() {
+        assert!(Instant::now() < deadline, "node never halted");
+        let _ = node.propose(7);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let full = RaftError::Storage(PersistentStorageError::StorageFull);
+    assert_eq!(node.join().unwrap(), Err(full));
+    assert_eq!(halted_with(&events), Some(full));
+}
+
+#[test]
+fn should_run_on_storage_given_to_the_builder() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(5))
+        .record_vote(ServerId(2))
+        .sync()
+        .unwrap();
+
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage(storage)
+        .transport(SilentTransport)
+        .rng_seed(0)
+        .start()
+        .unwrap();
+    let diagnostics = node.diagnostics();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let started = loop {
+        if let Some(latest) = diagnostics.latest() {
+            break latest.state;
+        }
+        assert!(Instant::now() < deadline, "node never started");
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    assert!(started.current_term >= TermIndex(5));
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    // Without a storage path there's nowhere to keep an audit log
+    assert!(!storage_dir.path().join("audit.log").exists());
+}
+
+#[test]
+fn should_hold_off_elections_while_paused() {
+    let storage_dir = TempDir::new().unwrap();
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(SilentTransport)
+        .config(RaftConfig::builder().pre_vote(false).build().unwrap())
+        .rng_seed(0)
+        .start()
+        .unwrap();
+    let diagnostics = node.diagnostics();
+    let current_term = || {
+        diagnostics
+            .latest()
+            .map_or(TermIndex(0), |latest| latest.state.current_term)
+    };
+
+    node.pause().unwrap();
+    // The node takes the pause when it next wakes, within its first election timeout
+    std::thread::sleep(Duration::from_millis(500));
+    let paused_in = current_term();
+    std::thread::sleep(Duration::from_secs(1));
+    assert_eq!(current_term(), paused_in);
+
+    node.resume().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while current_term() == paused_in {
+        assert!(Instant::now() < deadline, "node never carried on");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    node.pause().unwrap();
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn should_report_status_once_running() {
+    let storage_dir = TempDir::new().unwrap();
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(SilentTransport)
+        .rng_seed(0)
+        .start()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = node.status() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "node never started");
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(status.server_id, ServerId(1));
+    assert_eq!(status.leader_id, None);
+    assert_eq!(status.commit_index, LogIndex(0));
+    assert_eq!(status.last_log_index, LogIndex(0));
+    assert_eq!(status.peers, HashSet::from([ServerId(2), ServerId(3)]));
+
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn should_take_timing_updates_that_keep_the_config_valid() {
+    let storage_dir = TempDir::new().unwrap();
+    let node = RaftNodeBuilder::new(ServerId(1), IdleApplication)
+        .peers(HashSet::from([ServerId(2), ServerId(3)]))
+        .storage_path(storage_dir.path().to_str().unwrap().to_string())
+        .transport(SilentTransport)
+        .rng_seed(0)
+        .start()
+        .unwrap();
+
+    // Heartbeats as slow as the default election timeout would have followers start elections
+    let slow_heartbeats = node
+        .update_timing(TimingUpdate {
+            leader_heartbeat_interval: Some(HeartbeatInterval(Duration::from_millis(200))),
+            ..TimingUpdate::default()
+        })
+        .unwrap()
+        .wait();
+    assert!(matches!(
+        slow_heartbeats,
+        Err(TimingUpdateError::InvalidConfig(
+            RaftConfigError::HeartbeatNotShorterThanElectionTimeout { .. }
+        ))
+    ));
+
+    let longer_timeout = ElectionTimeoutRange::from_millis(1000, 2000);
+    let outcome = node
+        .update_timing(TimingUpdate {
+            election_timeout: Some(longer_timeout),
+            leader_heartbeat_interval: Some(HeartbeatInterval(Duration::from_millis(200))),
+            ..TimingUpdate::default()
+        })
+        .unwrap()
+        .wait();
+    assert_eq!(outcome, Ok(()));
+
+    let diagnostics = node.diagnostics();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while diagnostics
+        .latest()
+        .is_none_or(|latest| latest.config.election_timeout != longer_timeout)
+    {
+        assert!(
+            Instant::now() < deadline,
+            "node never published the new config"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let config = diagnostics.latest().unwrap().config;
+    assert_eq!(
+        config.leader_heartbeat_interval,
+        HeartbeatInterval(Duration::from_millis(200))
+    );
+
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+}
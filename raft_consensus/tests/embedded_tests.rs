@@ -0,0 +1,97 @@
+use raft_consensus::rpc_messages::{Request, RpcMessage, Vote};
+use raft_consensus::system_clock::{self, Clock, ManualClock, SystemClock};
+use raft_consensus::{
+    Action, DefaultPersistentStorage, EmbeddedNode, LogIndex, Node, RaftConfig, ServerId,
+};
+/// Tests driving the core node directly, without a thread or transport
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+
+#[test]
+fn should_win_an_election_driven_by_the_host() {
+    let storage_dir = TempDir::new().unwrap();
+    let storage = DefaultPersistentStorage::<u64>::new(Path::new(storage_dir.path())).unwrap();
+    let (mut node, first_election_timeout) = EmbeddedNode::new(
+        ServerId(1),
+        HashSet::from([ServerId(2), ServerId(3)]),
+        LogIndex(0),
+        storage,
+        RaftConfig::builder().pre_vote(false).build().unwrap(),
+        ChaCha8Rng::seed_from_u64(0),
+        SystemClock,
+    );
+
+    // Nothing heard from a leader by the end of the timeout, the node asks for votes
+    let actions = node
+        .tick(system_clock::now() + first_election_timeout.0)
+        .unwrap();
+    let vote_requests: Vec<_> = actions
+        .into_iter()
+        .filter_map(|action| match action {
+            Action::OutgoingRpc(RpcMessage::Request(Request::RequestVote(request))) => {
+                Some(request)
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        vote_requests
+            .iter()
+            .map(|request| request.to)
+            .collect::<HashSet<_>>(),
+        HashSet::from([ServerId(2), ServerId(3)])
+    );
+
+    let request = &vote_requests[0];
+    let actions = node
+        .step(RpcMessage::vote(Vote {
+            request_id: request.request_id,
+            from: request.to,
+            to: ServerId(1),
+            term: request.term,
+            vote_granted: true,
+        }))
+        .unwrap();
+
+    assert!(matches!(node.node(), Node::Leader(_)));
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        Action::OutgoingRpc(RpcMessage::Request(Request::AppendEntries(_)))
+    )));
+}
+
+#[test]
+fn should_only_time_out_once_its_clock_is_moved_past_the_timeout() {
+    let storage_dir = TempDir::new().unwrap();
+    let storage = DefaultPersistentStorage::<u64>::new(Path::new(storage_dir.path())).unwrap();
+    let clock = ManualClock::new();
+    let (mut node, first_election_timeout) = EmbeddedNode::new(
+        ServerId(1),
+        HashSet::from([ServerId(2), ServerId(3)]),
+        LogIndex(0),
+        storage,
+        RaftConfig::builder().pre_vote(false).build().unwrap(),
+        ChaCha8Rng::seed_from_u64(0),
+        clock.clone(),
+    );
+    let asks_for_votes = |actions: Vec<Action<u64>>| {
+        actions.into_iter().any(|action| {
+            matches!(
+                action,
+                Action::OutgoingRpc(RpcMessage::Request(Request::RequestVote(_)))
+            )
+        })
+    };
+
+    // The node's time stands still until the clock is moved
+    assert!(!asks_for_votes(node.tick(clock.now()).unwrap()));
+    assert!(matches!(node.node(), Node::Follower(_)));
+
+    clock.advance(first_election_timeout.0 + Duration::from_millis(1));
+    assert!(asks_for_votes(node.tick(clock.now()).unwrap()));
+    assert!(matches!(node.node(), Node::Candidate(_)));
+}
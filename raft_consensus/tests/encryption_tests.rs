@@ -0,0 +1,85 @@
+/// Tests storage keeping commands and snapshot data encrypted
+use raft_consensus::{
+    BincodeCodec, DefaultPersistentStorage, EncryptingCodec, EncryptionKey, LogEntry,
+    LogEntryCommand, LogIndex, PersistentStorage, PersistentStorageError, ServerId, Snapshot,
+    TermIndex,
+};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+const SECRET: &str = "the launch codes";
+
+fn entry(index: u64, term: u64) -> LogEntry<String> {
+    LogEntry {
+        index: LogIndex(index),
+        term: TermIndex(term),
+        command: LogEntryCommand::Application(format!("{} #{}", SECRET, index)),
+    }
+}
+
+fn snapshot(last_included_index: u64, last_included_term: u64) -> Snapshot {
+    Snapshot {
+        last_included_index: LogIndex(last_included_index),
+        last_included_term: TermIndex(last_included_term),
+        members: [ServerId(1), ServerId(2), ServerId(3)].into(),
+        learners: [].into(),
+        sessions: [].into(),
+        data: SECRET.as_bytes().to_vec(),
+    }
+}
+
+fn open(
+    path: &Path,
+    key: [u8; 32],
+) -> Result<DefaultPersistentStorage<String>, PersistentStorageError> {
+    DefaultPersistentStorage::with_codec(
+        path,
+        Arc::new(EncryptingCodec::new(BincodeCodec, EncryptionKey::new(key))),
+    )
+}
+
+/// Whether any file in `dir` holds `needle` as is
+fn any_file_contains(dir: &Path, needle: &[u8]) -> bool {
+    std::fs::read_dir(dir).unwrap().any(|file| {
+        let bytes = std::fs::read(file.unwrap().path()).unwrap();
+        bytes.windows(needle.len()).any(|window| window == needle)
+    })
+}
+
+#[test]
+fn should_read_back_commands_and_snapshot_data_it_kept_encrypted() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = open(storage_dir.path(), [7; 32]).unwrap();
+    storage
+        .install_snapshot(snapshot(2, 1))
+        .append(vec![entry(3, 1), entry(4, 2)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    assert!(!any_file_contains(storage_dir.path(), SECRET.as_bytes()));
+    let storage = open(storage_dir.path(), [7; 32]).unwrap();
+    assert_eq!(storage.snapshot(), Some(&snapshot(2, 1)));
+    assert_eq!(
+        storage.entries(LogIndex(3), 10),
+        vec![entry(3, 1), entry(4, 2)]
+    );
+}
+
+#[test]
+fn should_refuse_to_open_encrypted_storage_without_its_key() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = open(storage_dir.path(), [7; 32]).unwrap();
+    storage.append(vec![entry(1, 1)]).sync().unwrap();
+    drop(storage);
+
+    assert_eq!(
+        open(storage_dir.path(), [8; 32]).err(),
+        Some(PersistentStorageError::SerdeError)
+    );
+    assert_eq!(
+        DefaultPersistentStorage::<String>::new(storage_dir.path()).err(),
+        Some(PersistentStorageError::FormatMismatch)
+    );
+}
@@ -0,0 +1,93 @@
+/// Tests the event collectors a node can publish into
+use raft_consensus::{
+    ChannelOverflowPolicy, ChannelRaftEventCollector, LogIndex, QueueOverflowPolicy,
+    QueueRaftEventCollector, RaftEvent, RaftNodeState, RaftStateEvent, RaftStateEventCollector,
+    ServerId, TermIndex,
+};
+
+fn applied(index: u64) -> RaftEvent {
+    RaftEvent::EntryApplied {
+        server_id: ServerId(0),
+        index: LogIndex(index),
+    }
+}
+
+fn state_changed(term: u64) -> RaftEvent {
+    RaftEvent::StateChanged(RaftStateEvent {
+        server_id: ServerId(0),
+        current_state: RaftNodeState::Follower,
+        current_term: TermIndex(term),
+        voted_for: None,
+        leader_for_term: None,
+        commit_index: LogIndex(0),
+        last_applied: LogIndex(0),
+        last_log_index: LogIndex(0),
+        last_log_term: TermIndex(0),
+    })
+}
+
+#[test]
+fn should_count_the_events_dropped_once_the_channel_is_full() {
+    let (mut collector, events) =
+        ChannelRaftEventCollector::new(2, ChannelOverflowPolicy::DropNewest);
+    let clone = collector.clone();
+    for index in 1..=5 {
+        collector.push_event(applied(index));
+    }
+
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![applied(1), applied(2)]
+    );
+    assert_eq!(collector.dropped_events(), 3);
+    assert_eq!(clone.dropped_events(), 3);
+
+    // Room again once the receiver caught up
+    collector.push_event(applied(6));
+    assert_eq!(events.try_recv(), Ok(applied(6)));
+    assert_eq!(collector.dropped_events(), 3);
+
+    // Nobody is listening anymore, that's not the channel being full
+    drop(events);
+    collector.push_event(applied(7));
+    assert_eq!(collector.dropped_events(), 3);
+}
+
+#[test]
+fn should_drop_the_newest_events_once_the_queue_is_full() {
+    let (mut collector, queue) = QueueRaftEventCollector::new(2, QueueOverflowPolicy::DropNewest);
+    for index in 1..=3 {
+        collector.push_event(applied(index));
+    }
+
+    assert_eq!(queue.drain(), vec![applied(1), applied(2)]);
+    assert_eq!(queue.dropped_events(), 1);
+}
+
+#[test]
+fn should_drop_the_oldest_events_once_the_queue_is_full() {
+    let (mut collector, queue) = QueueRaftEventCollector::new(2, QueueOverflowPolicy::DropOldest);
+    for index in 1..=3 {
+        collector.push_event(applied(index));
+    }
+
+    assert_eq!(queue.drain(), vec![applied(2), applied(3)]);
+    assert_eq!(queue.dropped_events(), 1);
+}
+
+#[test]
+fn should_hold_back_only_the_latest_state_while_the_queue_is_full() {
+    let (mut collector, queue) =
+        QueueRaftEventCollector::new(2, QueueOverflowPolicy::CoalesceStateChanges);
+    collector.push_event(applied(1));
+    collector.push_event(applied(2));
+    collector.push_event(state_changed(1));
+    collector.push_event(state_changed(2));
+    collector.push_event(applied(3));
+    assert_eq!(queue.drain(), vec![applied(1), applied(2)]);
+
+    collector.push_event(applied(4));
+    assert_eq!(queue.drain(), vec![state_changed(2), applied(4)]);
+    assert_eq!(queue.coalesced_events(), 1);
+    assert_eq!(queue.dropped_events(), 1);
+}
@@ -0,0 +1,213 @@
+/// Tests the HTTP transport carrying messages between servers as JSON
+use raft_consensus::rpc_messages::{
+    AppendEntries, AppendEntriesAck, ReplyTo, Request, RpcMessage, TimeoutNow,
+};
+use raft_consensus::{
+    BincodeWireCodec, HttpTransport, LogEntry, LogEntryCommand, LogIndex, RaftMetrics,
+    RaftTransportConnector, ServerId, TermIndex,
+};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+fn transport(peers: HashMap<ServerId, SocketAddr>) -> HttpTransport<String> {
+    HttpTransport::new(TcpListener::bind("127.0.0.1:0").unwrap(), peers).unwrap()
+}
+
+fn next_message(transport: &mut HttpTransport<String>) -> Option<RpcMessage<String>> {
+    transport
+        .wait_for_next_incoming_message(Duration::from_secs(5))
+        .unwrap()
+}
+
+/// POSTs `body` to `path` on `addr` the way curl would, returning the response's status line
+fn post(addr: SocketAddr, path: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn should_carry_requests_to_peers_and_route_replies_back() {
+    let first_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let second_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let first_addr = first_listener.local_addr().unwrap();
+    let second_addr = second_listener.local_addr().unwrap();
+    let mut first =
+        HttpTransport::new(first_listener, HashMap::from([(ServerId(2), second_addr)])).unwrap();
+    let mut second =
+        HttpTransport::new(second_listener, HashMap::from([(ServerId(1), first_addr)])).unwrap();
+
+    let append_entries = Request::AppendEntries(AppendEntries {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(3),
+        prev_log_term: TermIndex(2),
+        prev_log_index: LogIndex(4),
+        entries: vec![LogEntry {
+            index: LogIndex(5),
+            term: TermIndex(3),
+            command: LogEntryCommand::Application("set x".to_string()),
+        }]
+        .into(),
+        leader_commit: LogIndex(4),
+    });
+    first
+        .enqueue_outgoing_request(append_entries.clone())
+        .unwrap();
+    assert_eq!(
+        next_message(&mut second),
+        Some(RpcMessage::Request(append_entries.clone()))
+    );
+
+    let ack = ReplyTo::AppendEntries(AppendEntriesAck {
+        request_id: append_entries.request_id(),
+        from: ServerId(2),
+        to: ServerId(1),
+        term: TermIndex(3),
+        success: true,
+        conflict: None,
+    });
+    second.enqueue_reply(ack.clone()).unwrap();
+    assert_eq!(next_message(&mut first), Some(RpcMessage::Reply(ack)));
+}
+
+#[test]
+fn should_take_messages_posted_by_hand() {
+    let mut node = transport(HashMap::new());
+    let request_id = Uuid::new_v4();
+    let body = format!(
+        r#"{{"Request":{{"TimeoutNow":{{"request_id":"{}","from":1,"to":2,"term":4}}}}}}"#,
+        request_id
+    );
+
+    assert!(post(node.local_addr(), "/raft", &body).contains("204"));
+    assert_eq!(
+        next_message(&mut node),
+        Some(RpcMessage::Request(Request::TimeoutNow(TimeoutNow {
+            request_id,
+            from: ServerId(1),
+            to: ServerId(2),
+            term: TermIndex(4),
+        })))
+    );
+    assert!(post(node.local_addr(), "/raft", "{\"Request\":").contains("400"));
+    assert!(post(node.local_addr(), "/elsewhere", &body).contains("404"));
+}
+
+#[test]
+fn should_turn_away_messages_past_its_max_message_size() {
+    let mut node = transport(HashMap::new()).max_message_size(64);
+    let body = format!(
+        r#"{{"Request":{{"TimeoutNow":{{"request_id":"{}","from":1,"to":2,"term":4}}}}}}"#,
+        Uuid::new_v4()
+    );
+
+    assert!(body.len() > 64);
+    assert!(post(node.local_addr(), "/raft", &body).contains("413"));
+    assert_eq!(
+        node.wait_for_next_incoming_message(Duration::from_millis(200))
+            .unwrap(),
+        None
+    );
+    assert_eq!(node.message_size_limit(), Some(64));
+}
+
+#[test]
+fn should_count_messages_past_its_max_message_size_as_dropped() {
+    let mut peer = transport(HashMap::new());
+    let metrics = RaftMetrics::new();
+    let mut node =
+        transport(HashMap::from([(ServerId(2), peer.local_addr())])).max_message_size(64);
+    node.attach_metrics(metrics.clone());
+
+    node.enqueue_outgoing_request(Request::TimeoutNow(TimeoutNow {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(4),
+    }))
+    .unwrap();
+
+    assert_eq!(metrics.rpc_stats().dropped, 1);
+    assert_eq!(
+        peer.wait_for_next_incoming_message(Duration::from_millis(200))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn should_carry_messages_in_the_format_of_the_codec_it_is_given() {
+    let (first_listener, second_listener) = (
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+    );
+    let second_addr = second_listener.local_addr().unwrap();
+    let mut first = HttpTransport::with_codec(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr)]),
+        Arc::new(BincodeWireCodec::new()),
+    )
+    .unwrap();
+    let mut second = HttpTransport::<String>::with_codec(
+        second_listener,
+        HashMap::new(),
+        Arc::new(BincodeWireCodec::new()),
+    )
+    .unwrap();
+
+    let request = Request::TimeoutNow(TimeoutNow {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(4),
+    });
+    first.enqueue_outgoing_request(request.clone()).unwrap();
+    assert_eq!(
+        next_message(&mut second),
+        Some(RpcMessage::Request(request))
+    );
+    // Bincode isn't what a hand written JSON body decodes with
+    let body = r#"{"Request":{"TimeoutNow":{"request_id":"00000000-0000-0000-0000-000000000000","from":1,"to":2,"term":4}}}"#;
+    assert!(post(second_addr, "/raft", body).contains("400"));
+}
+
+#[test]
+fn should_retry_messages_for_a_peer_that_comes_up_shortly() {
+    let second_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let second_addr = second_listener.local_addr().unwrap();
+    // Nothing listens on the second server's port until its transport starts
+    drop(second_listener);
+    let mut first = transport(HashMap::from([(ServerId(2), second_addr)]));
+    let request = Request::TimeoutNow(TimeoutNow {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(4),
+    });
+    first.enqueue_outgoing_request(request.clone()).unwrap();
+    // Gives the transport time to fail to connect, and back off
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut second =
+        HttpTransport::new(TcpListener::bind(second_addr).unwrap(), HashMap::new()).unwrap();
+    assert_eq!(
+        next_message(&mut second),
+        Some(RpcMessage::Request(request))
+    );
+}
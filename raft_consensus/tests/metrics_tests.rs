@@ -0,0 +1,366 @@
+use raft_consensus::rpc_messages::{AppendEntriesAck, ReplyTo, Request, RpcMessage, Vote};
+use raft_consensus::{
+    metric_names, ApplicationThatNeedsConsensus, DefaultPersistentStorage, LogEntry, LogIndex,
+    MetricsSink, NoOpMetricsSink, PersistentStorage, PersistentStorageError, RaftConfig,
+    RaftMetrics, RaftNodeBuilder, RaftTransportConnector, RaftTransportError, ServerId,
+    SlowOperationThresholds, Snapshot, TermIndex,
+};
+/// Tests what a node records in its metrics and hands to its metrics sinks, and the slow
+/// operations it warns about
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Transport of peers that vote for whoever asks and take whatever entries the leader sends
+#[derive(Default)]
+struct AgreeableTransport {
+    replies: Vec<ReplyTo>,
+}
+impl RaftTransportConnector<u64> for AgreeableTransport {
+    fn wait_for_next_incoming_message(
+        &mut self,
+        max_wait: Duration,
+    ) -> Result<Option<RpcMessage<u64>>, RaftTransportError> {
+        match self.replies.pop() {
+            Some(reply) => Ok(Some(RpcMessage::Reply(reply))),
+            None => {
+                std::thread::sleep(max_wait.min(Duration::from_millis(10)));
+                Ok(None)
+            }
+        }
+    }
+
+    fn enqueue_reply(&mut self, _reply: ReplyTo) -> Result<(), RaftTransportError> {
+        Ok(())
+    }
+
+    fn enqueue_outgoing_request(
+        &mut self,
+        request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        match request {
+            Request::RequestVote(request) => self.replies.push(ReplyTo::RequestVote(Vote {
+                request_id: request.request_id,
+                from: request.to,
+                to: request.from,
+                term: request.term,
+                vote_granted: true,
+            })),
+            Request::AppendEntries(request) => {
+                self.replies.push(ReplyTo::AppendEntries(AppendEntriesAck {
+                    request_id: request.request_id,
+                    from: request.to,
+                    to: request.from,
+                    term: request.term,
+                    success: true,
+                    conflict: None,
+                }))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Application that only keeps track of how far it got
+struct TrackingApplication {
+    last_applied_index: LogIndex,
+}
+impl ApplicationThatNeedsConsensus for TrackingApplication {
+    type Command = u64;
+    type Output = ();
+    type Error = ();
+
+    fn apply(&mut self, log_index: LogIndex, _command: u64) -> Result<(), ()> {
+        self.last_applied_index = log_index;
+        Ok(())
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied_index
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.last_applied_index = snapshot.last_included_index;
+    }
+}
+
+/// Sink keeping the name of every metric handed to it, with gauges' latest values
+#[derive(Debug, Default)]
+struct RecordingSink {
+    counters: Mutex<Vec<&'static str>>,
+    durations: Mutex<Vec<&'static str>>,
+    gauges: Mutex<Vec<(&'static str, u64)>>,
+}
+impl MetricsSink for RecordingSink {
+    fn increment_counter(&self, name: &'static str, _value: u64) {
+        self.counters.lock().unwrap().push(name);
+    }
+
+    fn observe_duration(&self, name: &'static str, _duration: Duration) {
+        self.durations.lock().unwrap().push(name);
+    }
+
+    fn set_gauge(&self, name: &'static str, value: u64) {
+        self.gauges.lock().unwrap().push((name, value));
+    }
+}
+
+#[test]
+fn should_hand_counters_durations_and_gauges_to_every_sink() {
+    let storage_dir = TempDir::new().unwrap();
+    let sink = Arc::new(RecordingSink::default());
+    let metrics = RaftMetrics::with_sinks(vec![sink.clone(), Arc::new(NoOpMetricsSink)]);
+    let node = RaftNodeBuilder::new(
+        ServerId(1),
+        TrackingApplication {
+            last_applied_index: LogIndex(0),
+        },
+    )
+    .peers(HashSet::from([ServerId(2), ServerId(3)]))
+    .storage_path(storage_dir.path().to_str().unwrap())
+    .transport(AgreeableTransport::default())
+    .config(RaftConfig::builder().pre_vote(false).build().unwrap())
+    .metrics(metrics.clone())
+    .start()
+    .unwrap();
+
+    // The peers elect the node and take the command, so it commits
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !node
+        .propose(7)
+        .is_ok_and(|pending| matches!(pending.wait_timeout(Duration::from_secs(1)), Some(Ok(_))))
+    {
+        assert!(
+            Instant::now() < deadline,
+            "node never committed the command"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+
+    let counters = sink.counters.lock().unwrap();
+    assert!(counters.contains(&metric_names::ELECTIONS_STARTED));
+    assert!(counters.contains(&metric_names::BYTES_WRITTEN));
+    let durations = sink.durations.lock().unwrap();
+    for name in [
+        metric_names::ELECTION_DURATION,
+        metric_names::COMMIT_LATENCY,
+        metric_names::APPLY_LATENCY,
+        metric_names::SYNC_LATENCY,
+    ] {
+        assert!(
+            durations.contains(&name),
+            "{name} missing from {durations:?}"
+        );
+    }
+    let gauges = sink.gauges.lock().unwrap();
+    assert!(gauges.contains(&(metric_names::ROLE, 3)));
+    assert!(gauges.contains(&(metric_names::CURRENT_TERM, metrics.gauges().current_term.0)));
+    assert!(gauges.contains(&(metric_names::COMMIT_INDEX, metrics.gauges().commit_index.0)));
+}
+
+/// Application whose every apply takes a while
+struct SlowApplication {
+    last_applied_index: LogIndex,
+}
+impl ApplicationThatNeedsConsensus for SlowApplication {
+    type Command = u64;
+    type Output = ();
+    type Error = ();
+
+    fn apply(&mut self, log_index: LogIndex, _command: u64) -> Result<(), ()> {
+        std::thread::sleep(Duration::from_millis(2));
+        self.last_applied_index = log_index;
+        Ok(())
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied_index
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.last_applied_index = snapshot.last_included_index;
+    }
+}
+
+/// Storage whose every sync takes a while
+struct SlowStorage(DefaultPersistentStorage<u64>);
+impl PersistentStorage<u64> for SlowStorage {
+    fn current_term(&self) -> TermIndex {
+        self.0.current_term()
+    }
+
+    fn vote_for_current_term(&self) -> Option<ServerId> {
+        self.0.vote_for_current_term()
+    }
+
+    fn update_term(&mut self, term: TermIndex) -> &mut Self {
+        self.0.update_term(term);
+        self
+    }
+
+    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self {
+        self.0.record_vote(voted_for);
+        self
+    }
+
+    fn last_entry_index(&self) -> Option<LogIndex> {
+        self.0.last_entry_index()
+    }
+
+    fn last_entry_term(&self) -> Option<TermIndex> {
+        self.0.last_entry_term()
+    }
+
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex> {
+        self.0.entry_term(index)
+    }
+
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<u64>> {
+        self.0.entries(from, max_entries)
+    }
+
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<u64>> {
+        self.0.entries_within(from, max_entries, max_bytes)
+    }
+
+    fn snapshot(&self) -> Option<&Snapshot> {
+        self.0.snapshot()
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self {
+        self.0.install_snapshot(snapshot);
+        self
+    }
+
+    fn append(&mut self, entries: Vec<LogEntry<u64>>) -> &mut Self {
+        self.0.append(entries);
+        self
+    }
+
+    fn sync(&mut self) -> Result<(), PersistentStorageError> {
+        std::thread::sleep(Duration::from_millis(2));
+        self.0.sync()
+    }
+}
+
+/// A warning's fields, and the name of the innermost span it was logged in
+#[derive(Debug, Clone, Default)]
+struct Warning {
+    fields: HashMap<String, String>,
+    span: Option<String>,
+}
+impl Visit for Warning {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let _ = self
+            .fields
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let _ = self
+            .fields
+            .insert(field.name().to_string(), value.to_string());
+    }
+}
+
+/// Keeps every warning logged on any thread
+#[derive(Clone, Default)]
+struct CapturedWarnings(Arc<Mutex<Vec<Warning>>>);
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CapturedWarnings {
+    fn on_event(&self, event: &Event<'_>, context: Context<'_, S>) {
+        if *event.metadata().level() != Level::WARN {
+            return;
+        }
+        let mut warning = Warning {
+            span: context
+                .event_span(event)
+                .map(|span| span.name().to_string()),
+            ..Warning::default()
+        };
+        event.record(&mut warning);
+        self.0.lock().unwrap().push(warning);
+    }
+}
+
+#[test]
+fn should_warn_about_every_operation_over_a_zero_threshold() {
+    let warnings = CapturedWarnings::default();
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(warnings.clone()))
+        .unwrap();
+    let storage_dir = TempDir::new().unwrap();
+    let config = RaftConfig::builder()
+        .pre_vote(false)
+        .slow_operation_thresholds(SlowOperationThresholds {
+            message_handling: Duration::ZERO,
+            storage_sync: Duration::ZERO,
+            apply: Duration::ZERO,
+        })
+        .build()
+        .unwrap();
+    let node = RaftNodeBuilder::new(
+        ServerId(1),
+        SlowApplication {
+            last_applied_index: LogIndex(0),
+        },
+    )
+    .peers(HashSet::from([ServerId(2), ServerId(3)]))
+    .storage(SlowStorage(
+        DefaultPersistentStorage::new(Path::new(storage_dir.path())).unwrap(),
+    ))
+    .transport(AgreeableTransport::default())
+    .config(config)
+    .start()
+    .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !node
+        .propose(7)
+        .is_ok_and(|pending| matches!(pending.wait_timeout(Duration::from_secs(1)), Some(Ok(_))))
+    {
+        assert!(
+            Instant::now() < deadline,
+            "node never committed the command"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+
+    // Each is logged in the span of what it's part of
+    let warnings = warnings.0.lock().unwrap();
+    for (operation, span) in [
+        ("message handling", "handle_event"),
+        ("storage sync", "storage_sync"),
+        ("apply", "term"),
+    ] {
+        let warning = warnings
+            .iter()
+            .find(|warning| warning.fields.get("operation").map(String::as_str) == Some(operation))
+            .unwrap_or_else(|| panic!("no warning about {operation} in {warnings:?}"));
+        assert_eq!(warning.fields["threshold_ms"], "0");
+        assert!(warning.fields["elapsed_ms"].parse::<u64>().is_ok());
+        assert!(warning.fields["message"].contains(&format!("Slow {operation} took")));
+        assert_eq!(warning.span.as_deref(), Some(span), "{operation}");
+    }
+}
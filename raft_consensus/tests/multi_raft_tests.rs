@@ -0,0 +1,173 @@
+use raft_consensus::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_consensus::{
+    ApplicationThatNeedsConsensus, GroupId, GroupRouter, LogIndex, MultiRaftTransport,
+    NoOpRaftEventCollector, RaftConfig, RaftGroupManager, RaftMetrics, RaftNodeHandle,
+    RaftTransportError, ServerId, Snapshot,
+};
+/// Tests many Raft groups sharing the workers and transport of one manager per server
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+type Routers = Arc<Mutex<HashMap<ServerId, GroupRouter<u64>>>>;
+type Applied = Arc<Mutex<Vec<u64>>>;
+
+/// Hands every group's messages straight to the router of the server they're addressed to
+struct InMemoryTransport {
+    routers: Routers,
+}
+impl InMemoryTransport {
+    fn deliver(
+        &self,
+        group_id: GroupId,
+        message: RpcMessage<u64>,
+    ) -> Result<(), RaftTransportError> {
+        let router = self.routers.lock().unwrap().get(&message.to()).cloned();
+        match router {
+            Some(router) => router.deliver(group_id, message),
+            // Not started yet, the sender retries like it would over a real network
+            None => Ok(()),
+        }
+    }
+}
+impl MultiRaftTransport<u64> for InMemoryTransport {
+    fn send_request(
+        &self,
+        group_id: GroupId,
+        request: Request<u64>,
+    ) -> Result<(), RaftTransportError> {
+        self.deliver(group_id, RpcMessage::Request(request))
+    }
+
+    fn send_reply(&self, group_id: GroupId, reply: ReplyTo) -> Result<(), RaftTransportError> {
+        self.deliver(group_id, RpcMessage::Reply(reply))
+    }
+}
+
+/// Application recording the commands it applied
+struct RecordingApplication {
+    applied: Applied,
+    last_applied: LogIndex,
+}
+impl ApplicationThatNeedsConsensus for RecordingApplication {
+    type Command = u64;
+    type Output = ();
+    type Error = ();
+
+    fn apply(&mut self, log_index: LogIndex, command: u64) -> Result<(), ()> {
+        self.applied.lock().unwrap().push(command);
+        self.last_applied = log_index;
+        Ok(())
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, _snapshot: &Snapshot) {}
+}
+
+#[test]
+fn should_run_independent_groups_on_shared_workers() {
+    let servers = [ServerId(1), ServerId(2), ServerId(3)];
+    let groups = [GroupId(1), GroupId(2), GroupId(3)];
+    let routers: Routers = Arc::new(Mutex::new(HashMap::new()));
+    let storage_dir = TempDir::new().unwrap();
+
+    let managers: Vec<RaftGroupManager<u64, InMemoryTransport>> = servers
+        .iter()
+        .map(|server_id| {
+            let manager = RaftGroupManager::new(
+                *server_id,
+                2,
+                InMemoryTransport {
+                    routers: routers.clone(),
+                },
+            )
+            .unwrap();
+            let _ = routers.lock().unwrap().insert(*server_id, manager.router());
+            manager
+        })
+        .collect();
+
+    let mut nodes: HashMap<GroupId, Vec<(RaftNodeHandle<RecordingApplication>, Applied)>> =
+        HashMap::new();
+    for (manager, server_id) in managers.iter().zip(servers) {
+        for group_id in groups {
+            let applied = Arc::new(Mutex::new(Vec::new()));
+            let storage_path = storage_dir
+                .path()
+                .join(format!("server-{}-group-{}", server_id.0, group_id.0));
+            std::fs::create_dir(&storage_path).unwrap();
+            let node = manager.add_group(
+                group_id,
+                servers
+                    .iter()
+                    .copied()
+                    .filter(|id| *id != server_id)
+                    .collect::<HashSet<_>>(),
+                storage_path.to_str().unwrap().to_string(),
+                RecordingApplication {
+                    applied: applied.clone(),
+                    last_applied: LogIndex(0),
+                },
+                RaftConfig::builder().build().unwrap(),
+                ChaCha8Rng::seed_from_u64(server_id.0 * 10 + group_id.0),
+                NoOpRaftEventCollector,
+                RaftMetrics::new(),
+            );
+            nodes.entry(group_id).or_default().push((node, applied));
+        }
+    }
+
+    // Each group elects its own leader, which commits a command only that group sees
+    let deadline = Instant::now() + Duration::from_secs(30);
+    for group_id in groups {
+        let command = group_id.0 * 100;
+        loop {
+            assert!(
+                Instant::now() < deadline,
+                "{:?} never committed a command",
+                group_id
+            );
+            let committed = nodes[&group_id].iter().any(|(node, _)| {
+                node.propose(command)
+                    .ok()
+                    .and_then(|proposal| proposal.wait_timeout(Duration::from_millis(500)))
+                    .is_some_and(|outcome| outcome.is_ok())
+            });
+            if committed {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    for group_id in groups {
+        for (node, applied) in &nodes[&group_id] {
+            let expected = group_id.0 * 100;
+            while !applied.lock().unwrap().contains(&expected) {
+                assert!(
+                    Instant::now() < deadline,
+                    "{:?} never applied its command",
+                    group_id
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(applied
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|command| *command == expected));
+            assert!(!node.is_finished());
+        }
+    }
+}
@@ -0,0 +1,89 @@
+/// Tests exporting a node's metrics to Prometheus
+use prometheus::{Encoder, Registry, TextEncoder};
+use raft_consensus::{
+    metric_names, MetricsSink, PeerConnection, PrometheusCollector, PrometheusMetricsSink,
+    RaftMetrics, ServerId,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn should_export_every_metric_to_a_registry() {
+    let metrics = RaftMetrics::new();
+    metrics.record_rpc_dropped();
+    metrics.record_peer_connection(
+        ServerId(2),
+        PeerConnection {
+            connected: false,
+            buffered_messages: 3,
+        },
+    );
+    let registry = Registry::new();
+    registry
+        .register(Box::new(PrometheusCollector::new(metrics)))
+        .unwrap();
+
+    let families = registry.gather();
+    let names: Vec<&str> = families.iter().map(|family| family.get_name()).collect();
+    for name in [
+        "raft_elections_started_total",
+        "raft_votes_granted_total",
+        "raft_rpcs_sent_total",
+        "raft_rpcs_received_total",
+        "raft_rpcs_dropped_total",
+        "raft_current_term",
+        "raft_commit_index",
+        "raft_role",
+        "raft_append_latency_seconds",
+        "raft_storage_bytes_written_total",
+        "raft_storage_bytes_read_total",
+        "raft_log_size_on_disk_bytes",
+        "raft_storage_sync_latency_seconds",
+        "raft_peer_connected",
+        "raft_peer_buffered_messages",
+    ] {
+        assert!(names.contains(&name), "{name} missing from {names:?}");
+    }
+
+    let mut text = Vec::new();
+    TextEncoder::new().encode(&families, &mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("raft_rpcs_dropped_total 1"));
+    assert!(text.contains("raft_append_latency_seconds_bucket{le=\"+Inf\"} 0"));
+    assert!(text.contains("raft_peer_connected{peer=\"2\"} 0"));
+    assert!(text.contains("raft_peer_buffered_messages{peer=\"2\"} 3"));
+}
+
+#[test]
+fn should_update_a_registry_as_a_sink_is_handed_metrics() {
+    let registry = Registry::new();
+    let sink = Arc::new(PrometheusMetricsSink::register(&registry).unwrap());
+    let metrics = RaftMetrics::with_sinks(vec![sink.clone()]);
+    metrics.record_rpc_dropped();
+    metrics.record_rpc_dropped();
+    metrics.record_peer_connection(
+        ServerId(2),
+        PeerConnection {
+            connected: true,
+            buffered_messages: 0,
+        },
+    );
+    sink.set_gauge(metric_names::CURRENT_TERM, 4);
+    sink.observe_duration(metric_names::COMMIT_LATENCY, Duration::from_millis(3));
+
+    let mut text = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut text)
+        .unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("raft_rpcs_dropped_total 2"));
+    assert!(text.contains("raft_peer_connected{peer=\"2\"} 1"));
+    assert!(text.contains("raft_current_term 4"));
+    assert!(text.contains("raft_commit_latency_seconds_bucket{le=\"0.002\"} 0"));
+    assert!(text.contains("raft_commit_latency_seconds_bucket{le=\"0.005\"} 1"));
+
+    // The sink's metrics are already in the registry, so the collector's names are taken
+    assert!(registry
+        .register(Box::new(PrometheusCollector::new(metrics)))
+        .is_err());
+}
@@ -1,70 +1,2350 @@
 /// Tests consensus with simulator
-use crate::simulator::{
-    common::{SimTime, SimulatorAction, SimulatorEvent},
+use bincode::Options;
+use quickcheck::{Arbitrary, QuickCheck};
+use raft_consensus::testkit::{
+    common::{SimLogCommand, SimTime, SimulatorAction, SimulatorEvent},
     sim_network::{LatencyMean, LatencyStdDev, PacketLossProbability, SimNetwork},
     ClusterSim,
 };
-use lazy_static::lazy_static;
-use quickcheck::{Arbitrary, QuickCheck, Testable};
-use raft_consensus::{RaftConfig, ServerId};
+use raft_consensus::{
+    AppendEntriesBatchLimits, ClientId, ClientSession, ElectionTimeoutRange, GroupCommit,
+    HeartbeatInterval, LogIndex, MaxForwardingHops, MaxUnappliedEntries, MembershipChange,
+    MembershipChangeError, PendingMembershipChange, ProposalError, RaftConfig, RaftEvent,
+    RaftNodeState, ReadError, ReadLease, ReplicationWindow, ServerId, SnapshotChunkSize,
+    SnapshotThreshold, StepDownError, StorageSyncRetry,
+};
 use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use rand_distr::num_traits::ToPrimitive;
-use simulator::sim_log::SimLog;
+use serde::{Deserialize, Serialize};
 use std::{
-    char::MAX,
-    collections::{BTreeSet, HashMap, HashSet},
-    path::{Path, PathBuf},
-    sync::atomic::{AtomicI64, AtomicU64},
-    time::Duration,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tempfile::TempDir;
-use tracing::{debug, error, info};
-mod simulator;
-use fault_injection::{set_trigger_function, FAULT_INJECT_COUNTER};
 use test_log::test;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+// Use quickcheck to implement some stateful tests
+// Generate a series of ops
+// - PartitionNetwork - Creates a network partition separating a set of nodes from the rest of the cluster
+// - HealPartition - Makes the network whole again, reversing the effect of PartitionNetwork
+// - RunForDuration - Runs the simulation for a given duration without any other actions
+// - FailNode - Put one node in a network partition to simulate a failure of a node
+// - RecoverNode - Heal the network partition to simulate a recovery of a node
+
+fn new_rng(maybe_seed: Option<u64>) -> ChaCha8Rng {
+    match maybe_seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => {
+            let mut rng = ChaCha8Rng::from_entropy();
+            let seed = rng.next_u64();
+            println!("====================================");
+            println!("RNG SEED FOR TESTS: {seed}", seed = seed);
+            println!("====================================");
+            ChaCha8Rng::seed_from_u64(seed)
+        }
+    }
+}
+
+fn sim_log_path(log_file_name: Option<&str>) -> Option<PathBuf> {
+    log_file_name.map(|filename| {
+        let pwd = std::env::current_dir().unwrap();
+        pwd.join("..").join(filename)
+    })
+}
+
+const SIMULATION_DURATION: Duration = Duration::from_secs(300);
+
+#[test]
+fn should_elect_leader_without_network_partition() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.01),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    sim.run_until_time(SIMULATION_DURATION);
+
+    assert!(sim.results.was_leader_elected);
+}
+
+#[test]
+fn should_keep_recent_events_queryable_through_the_node_handle() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.01),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    sim.run_until_time(Duration::from_secs(10));
+    assert!(sim.results.was_leader_elected);
+
+    let election_events: Vec<RaftEvent> = NODES
+        .iter()
+        .flat_map(|server_id| sim.recent_events(*server_id))
+        .filter(|event| matches!(event, RaftEvent::ElectionStarted { .. }))
+        .collect();
+    assert!(!election_events.is_empty());
+}
+
+#[test]
+fn should_grow_append_entries_batches_for_followers_that_ack_quickly() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+    let initial_batch_limit = config.append_entries_batch.initial_entries;
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    sim.run_until_time(Duration::from_secs(10));
+    assert!(sim.results.was_leader_elected);
+
+    let leader_diagnostics = NODES
+        .iter()
+        .filter_map(|server_id| sim.diagnostics(*server_id))
+        .find(|diagnostics| diagnostics.state.current_state == RaftNodeState::Leader)
+        .expect("a leader should be publishing diagnostics");
+    assert_eq!(leader_diagnostics.replication_progress.len(), 4);
+    for progress in leader_diagnostics.replication_progress.values() {
+        assert!(progress.append_entries_batch_limit > initial_batch_limit);
+        // Every follower is answering, the leader knows when it last heard from each
+        assert!(progress.last_reply.is_some());
+    }
+}
+
+#[test]
+fn should_replicate_and_commit_proposals_on_every_server() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.01),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Elections in the simulator can take a while to settle, keep proposing to whoever leads
+    // until something commits
+    let mut next_command = 1;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let leader_state = sim
+            .current_leader()
+            .and_then(|leader| sim.diagnostics(leader))
+            .map(|diagnostics| diagnostics.state);
+        if let Some(leader_state) = leader_state {
+            let all_caught_up = NODES.iter().all(|server_id| {
+                sim.diagnostics(*server_id)
+                    .map(|diagnostics| {
+                        diagnostics.state.commit_index >= LogIndex(3)
+                            && diagnostics.state.last_log_index == leader_state.last_log_index
+                    })
+                    .unwrap_or(false)
+            });
+            if all_caught_up {
+                return;
+            }
+            if leader_state.commit_index < LogIndex(3) {
+                sim.propose(leader_state.server_id, SimLogCommand(next_command));
+                next_command += 1;
+            }
+        }
+    }
+    panic!("proposals were not replicated and committed on every server");
+}
+
+#[test]
+fn should_commit_a_no_op_entry_of_the_new_leaders_term_without_proposals() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.01),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Nothing is ever proposed, whatever commits was appended by a leader on its own
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let leader_state = sim
+            .current_leader()
+            .and_then(|leader| sim.diagnostics(leader))
+            .map(|diagnostics| diagnostics.state);
+        if let Some(leader_state) = leader_state {
+            let all_committed = NODES.iter().all(|server_id| {
+                sim.diagnostics(*server_id)
+                    .map(|diagnostics| {
+                        diagnostics.state.commit_index == leader_state.last_log_index
+                            && diagnostics.state.last_log_term == leader_state.current_term
+                    })
+                    .unwrap_or(false)
+            });
+            if leader_state.last_log_index > LogIndex(0) && all_committed {
+                for server_id in NODES {
+                    assert!(sim.applied_commands(server_id).is_empty());
+                }
+                return;
+            }
+        }
+    }
+    panic!("no leader committed an entry of its own term on every server");
+}
+
+#[test]
+fn should_commit_a_burst_of_proposals_on_every_server() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Proposals handed over between two wake ups of the leader go into its log as one batch
+    const BURST: u64 = 20;
+    let mut proposed = false;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let leader_state = sim
+            .current_leader()
+            .and_then(|leader| sim.diagnostics(leader))
+            .map(|diagnostics| diagnostics.state);
+        let Some(leader_state) = leader_state else {
+            continue;
+        };
+        if !proposed {
+            for command in 0..BURST {
+                sim.propose(leader_state.server_id, SimLogCommand(command));
+            }
+            proposed = true;
+            continue;
+        }
+        let all_committed = NODES.iter().all(|server_id| {
+            sim.diagnostics(*server_id)
+                .map(|diagnostics| diagnostics.state.commit_index >= LogIndex(BURST))
+                .unwrap_or(false)
+        });
+        if all_committed {
+            return;
+        }
+    }
+    panic!("the burst of proposals was not committed on every server");
+}
+
+#[test]
+fn should_append_commands_proposed_together_at_consecutive_indexes() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Single proposals handed over meanwhile can't land between the batch's commands
+    const COMMANDS: u64 = 10;
+    let mut batch = Vec::new();
+    let mut outcomes = Vec::new();
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if batch.is_empty() {
+            let _single = sim.propose(leader, SimLogCommand(COMMANDS));
+            batch = sim.propose_many(leader, (0..COMMANDS).map(SimLogCommand).collect());
+            let _single = sim.propose(leader, SimLogCommand(COMMANDS + 1));
+            outcomes = vec![None; batch.len()];
+            continue;
+        }
+        for (proposal, outcome) in batch.iter().zip(outcomes.iter_mut()) {
+            if outcome.is_none() {
+                *outcome = proposal.try_outcome();
+            }
+        }
+        if outcomes.iter().all(Option::is_some) {
+            break;
+        }
+    }
+
+    let applied_at: Vec<LogIndex> = outcomes
+        .into_iter()
+        .map(|outcome| {
+            outcome
+                .expect("every proposal should be resolved")
+                .expect("the proposal should have been committed")
+                .result
+                .expect("the simulated application never fails")
+        })
+        .collect();
+    for (command, index) in applied_at.iter().enumerate() {
+        assert_eq!(index.0, applied_at[0].0 + command as u64);
+    }
+}
+
+#[test]
+fn should_stream_applied_commands_to_subscribers_in_log_order() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Subscribed before anything was proposed, so the subscriber sees every command applied
+    let subscribed = ServerId(0);
+    let applied = sim.subscribe_applied(subscribed);
+    const COMMANDS: u64 = 5;
+    let mut pending = Vec::new();
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if pending.is_empty() {
+            pending = sim.propose_many(leader, (0..COMMANDS).map(SimLogCommand).collect());
+            continue;
+        }
+        if sim.applied_commands(subscribed).len() as u64 >= COMMANDS {
+            break;
+        }
+    }
+
+    let streamed: Vec<_> = applied.try_iter().collect();
+    assert_eq!(
+        streamed
+            .iter()
+            .map(|(index, _, command)| (*index, *command))
+            .collect::<Vec<_>>(),
+        sim.applied_commands(subscribed)
+    );
+    assert_eq!(streamed.len() as u64, COMMANDS);
+    assert!(streamed.iter().all(|(_, term, _)| term.0 > 0));
+}
+
+#[test]
+fn should_forward_proposals_made_on_a_follower_to_the_leader() {
+    let rng = new_rng(Some(2995625000989813265));
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .max_forwarding_hops(MaxForwardingHops(1))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    const COMMANDS: u64 = 3;
+    let mut batch = Vec::new();
+    let mut outcomes = Vec::new();
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if batch.is_empty() {
+            // Only propose once the follower heard from the leader, it can't forward before
+            let follower = ServerId((leader.0 + 1) % 5);
+            let knows_leader = sim
+                .diagnostics(follower)
+                .is_some_and(|diagnostics| diagnostics.state.leader_for_term == Some(leader));
+            if !knows_leader {
+                continue;
+            }
+            batch = sim.propose_many(follower, (0..COMMANDS).map(SimLogCommand).collect());
+            outcomes = vec![None; batch.len()];
+            continue;
+        }
+        for (proposal, outcome) in batch.iter().zip(outcomes.iter_mut()) {
+            if outcome.is_none() {
+                *outcome = proposal.try_outcome();
+            }
+        }
+        if outcomes.iter().all(Option::is_some) {
+            break;
+        }
+    }
+
+    let applied_at: Vec<LogIndex> = outcomes
+        .into_iter()
+        .map(|outcome| {
+            outcome
+                .expect("every proposal should be resolved")
+                .expect("the follower should have forwarded the proposal to the leader")
+                .result
+                .expect("the simulated application never fails")
+        })
+        .collect();
+    for (command, index) in applied_at.iter().enumerate() {
+        assert_eq!(index.0, applied_at[0].0 + command as u64);
+    }
+}
+
+#[test]
+fn should_turn_proposals_away_while_the_application_is_too_far_behind() {
+    let rng = new_rng(None);
+    const MAX_UNAPPLIED: u64 = 5;
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .max_unapplied_entries(MaxUnappliedEntries(MAX_UNAPPLIED))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // A burst handed over in one batch gets ahead of the application before any of it commits
+    const BURST: u64 = 20;
+    let mut pending = Vec::new();
+    let mut outcomes = Vec::new();
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if pending.is_empty() {
+            for command in 0..BURST {
+                pending.push(sim.propose(leader, SimLogCommand(command)));
+            }
+            outcomes = vec![None; pending.len()];
+            continue;
+        }
+        for (proposal, outcome) in pending.iter().zip(outcomes.iter_mut()) {
+            if outcome.is_none() {
+                *outcome = proposal.try_outcome();
+            }
+        }
+        if outcomes.iter().all(Option::is_some) {
+            break;
+        }
+    }
+
+    let outcomes: Vec<_> = outcomes
+        .into_iter()
+        .map(|outcome| outcome.expect("every proposal should be resolved"))
+        .collect();
+    let applied = outcomes.iter().filter(|outcome| outcome.is_ok()).count() as u64;
+    let busy = outcomes
+        .iter()
+        .filter(|outcome| **outcome == Err(ProposalError::Busy))
+        .count() as u64;
+    assert!((1..=MAX_UNAPPLIED).contains(&applied));
+    assert_eq!(applied + busy, BURST);
+}
+
+#[test]
+fn should_apply_a_retried_session_command_once() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.01),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let client_id = ClientId(Uuid::new_v4());
+    let first = ClientSession {
+        client_id,
+        sequence: 1,
+    };
+    let second = ClientSession {
+        client_id,
+        sequence: 2,
+    };
+
+    // The client retries before hearing back, so both copies of the command end up in the log
+    let mut responses = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let original = sim.propose_in_session(leader, first, SimLogCommand(7));
+        let retry = sim.propose_in_session(leader, first, SimLogCommand(7));
+        sim.run_until_time(Duration::from_millis(500 * step + 250));
+        if let (Some(Ok(original)), Some(Ok(retry))) = (original.try_outcome(), retry.try_outcome())
+        {
+            responses = Some((original, retry));
+            break;
+        }
+    }
+    let (original, retry) = responses.expect("the session command should have been applied");
+    assert_eq!(original.result, retry.result);
+    assert!(retry.index > original.index);
+
+    // Once the client moved on, a late retry of its first command is applied no more
+    let mut stale_retry_discarded = false;
+    for step in 61..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let next = sim.propose_in_session(leader, second, SimLogCommand(8));
+        sim.run_until_time(Duration::from_millis(500 * step + 250));
+        if !matches!(next.try_outcome(), Some(Ok(_))) {
+            continue;
+        }
+        let retry = sim.propose_in_session(leader, first, SimLogCommand(7));
+        sim.run_until_time(Duration::from_millis(500 * step + 400));
+        if matches!(
+            retry.try_outcome(),
+            Some(Err(ProposalError::ResponseDiscarded))
+        ) {
+            stale_retry_discarded = true;
+            break;
+        }
+    }
+    assert!(stale_retry_discarded);
+
+    for step in 121..=180 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let leader_state = sim
+            .current_leader()
+            .and_then(|leader| sim.diagnostics(leader))
+            .map(|diagnostics| diagnostics.state);
+        let Some(leader_state) = leader_state else {
+            continue;
+        };
+        let all_applied = NODES.iter().all(|server_id| {
+            sim.diagnostics(*server_id)
+                .map(|diagnostics| {
+                    diagnostics.state.last_applied == leader_state.last_log_index
+                        && diagnostics.state.commit_index == leader_state.last_log_index
+                })
+                .unwrap_or(false)
+        });
+        if all_applied {
+            for server_id in NODES {
+                let applied: Vec<(LogIndex, SimLogCommand)> = sim
+                    .applied_commands(server_id)
+                    .into_iter()
+                    .filter(|(_, command)| *command == SimLogCommand(7))
+                    .collect();
+                assert_eq!(applied, vec![(original.index, SimLogCommand(7))]);
+            }
+            return;
+        }
+    }
+    panic!("the session commands were not applied on every server");
+}
+
+#[test]
+fn should_apply_committed_commands_in_log_order_on_every_server() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    const COMMANDS: u64 = 10;
+    let mut pending = Vec::new();
+    let mut turned_down = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let leader_state = sim
+            .current_leader()
+            .and_then(|leader| sim.diagnostics(leader))
+            .map(|diagnostics| diagnostics.state);
+        let Some(leader_state) = leader_state else {
+            continue;
+        };
+        if pending.is_empty() {
+            for command in 0..COMMANDS {
+                pending.push(sim.propose(leader_state.server_id, SimLogCommand(command)));
+            }
+            let follower = NODES
+                .iter()
+                .find(|server_id| **server_id != leader_state.server_id)
+                .unwrap();
+            turned_down = Some(sim.propose(*follower, SimLogCommand(COMMANDS)));
+            continue;
+        }
+        let all_applied = NODES
+            .iter()
+            .all(|server_id| sim.applied_commands(*server_id).len() as u64 >= COMMANDS);
+        if all_applied {
+            break;
+        }
+    }
+
+    let applied_by_leader = sim.applied_commands(NODES[0]);
+    assert_eq!(applied_by_leader.len() as u64, COMMANDS);
+    for server_id in NODES.iter() {
+        assert_eq!(sim.applied_commands(*server_id), applied_by_leader);
+    }
+    for (command, proposal) in pending.iter().enumerate() {
+        let applied = proposal
+            .try_outcome()
+            .expect("the proposal should be resolved once applied")
+            .expect("the proposal should have been committed");
+        let applied_at = applied
+            .result
+            .expect("the simulated application never fails");
+        assert_eq!(applied.index, applied_at);
+        assert_eq!(
+            applied_by_leader[command],
+            (applied_at, SimLogCommand(command as u64))
+        );
+    }
+    assert!(matches!(
+        turned_down.unwrap().try_outcome(),
+        Some(Err(ProposalError::NotLeader(_)))
+    ));
+}
+
+#[test]
+fn should_point_proposals_turned_down_on_a_follower_at_the_leader() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Only propose once the follower heard from the leader, it can't point anywhere before
+    let mut turned_down = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let follower = ServerId((leader.0 + 1) % 5);
+        let knows_leader = sim
+            .diagnostics(follower)
+            .is_some_and(|diagnostics| diagnostics.state.leader_for_term == Some(leader));
+        if !knows_leader {
+            continue;
+        }
+        let proposal = sim.propose(follower, SimLogCommand(1));
+        sim.run_until_time(Duration::from_millis(500 * step + 250));
+        if sim.current_leader() == Some(leader) {
+            turned_down = Some((leader, proposal.try_outcome()));
+            break;
+        }
+    }
+
+    let (leader, outcome) = turned_down.expect("a follower should have known the leader");
+    assert_eq!(outcome, Some(Err(ProposalError::NotLeader(Some(leader)))));
+}
+
+#[test]
+fn should_catch_up_a_far_away_follower_with_several_batches_in_flight() {
+    let rng = new_rng(None);
+    // Small batches, so catching up takes many of them, sent over a slow, jittery network where
+    // they overtake one another
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(400, 800))
+        .append_entries_batch(AppendEntriesBatchLimits {
+            min_entries: 1,
+            initial_entries: 2,
+            max_entries: 2,
+            ..AppendEntriesBatchLimits::default()
+        })
+        .replication_window(ReplicationWindow(4))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(30.0),
+        LatencyStdDev(1.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    const COMMANDS: u64 = 40;
+    let mut lagging = None;
+    let mut healed = false;
+    let mut caught_up = false;
+    for step in 1..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some(lagging) = lagging else {
+            // Cut a follower off before anything is proposed so it misses the whole log
+            let follower = *NODES
+                .iter()
+                .find(|server_id| **server_id != leader)
+                .unwrap();
+            let rest = NODES
+                .iter()
+                .copied()
+                .filter(|server_id| *server_id != follower)
+                .collect();
+            sim.enqueue_event(SimulatorEvent {
+                time: sim.now(),
+                action: SimulatorAction::PartitionNetwork(vec![HashSet::from([follower]), rest]),
+            });
+            for command in 0..COMMANDS {
+                sim.propose(leader, SimLogCommand(command));
+            }
+            lagging = Some(follower);
+            continue;
+        };
+        if !healed {
+            let majority_applied = NODES
+                .iter()
+                .filter(|server_id| **server_id != lagging)
+                .all(|server_id| sim.applied_commands(*server_id).len() as u64 >= COMMANDS);
+            if majority_applied {
+                sim.enqueue_event(SimulatorEvent {
+                    time: sim.now(),
+                    action: SimulatorAction::HealNetworkPartition,
+                });
+                healed = true;
+            }
+            continue;
+        }
+        if sim.applied_commands(lagging).len() as u64 >= COMMANDS {
+            caught_up = true;
+            break;
+        }
+    }
+
+    assert!(caught_up, "the lagging follower did not catch up");
+    let applied_by_leader = sim.applied_commands(sim.current_leader().unwrap());
+    assert_eq!(applied_by_leader.len() as u64, COMMANDS);
+    for server_id in NODES.iter() {
+        assert_eq!(sim.applied_commands(*server_id), applied_by_leader);
+    }
+}
+
+#[test]
+fn should_replace_the_uncommitted_entries_of_a_deposed_leader() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // The cut off leader appends these, they never commit
+    const UNCOMMITTED: std::ops::Range<u64> = 100..130;
+    const COMMANDS: u64 = 5;
+    let mut deposed = None;
+    let mut proposed = false;
+    let mut healed = false;
+    let mut converged = false;
+    for step in 1..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some(deposed) = deposed else {
+            let rest = NODES
+                .iter()
+                .copied()
+                .filter(|server_id| *server_id != leader)
+                .collect();
+            sim.enqueue_event(SimulatorEvent {
+                time: sim.now(),
+                action: SimulatorAction::PartitionNetwork(vec![HashSet::from([leader]), rest]),
+            });
+            // Cut off before it sends anything, well before it notices and steps down
+            let partitioned_at = sim.now().as_millis() as u64;
+            sim.run_until_time(Duration::from_millis(partitioned_at + 10));
+            for command in UNCOMMITTED {
+                sim.propose(leader, SimLogCommand(command));
+            }
+            deposed = Some(leader);
+            continue;
+        };
+        if leader == deposed {
+            continue;
+        }
+        if !proposed {
+            for command in 0..COMMANDS {
+                sim.propose(leader, SimLogCommand(command));
+            }
+            proposed = true;
+            continue;
+        }
+        if !healed {
+            if sim.applied_commands(leader).len() as u64 >= COMMANDS {
+                let deposed_log = sim.diagnostics(deposed).unwrap().state.last_log_index;
+                assert!(
+                    deposed_log.0 >= UNCOMMITTED.end - UNCOMMITTED.start,
+                    "the deposed leader should have appended the entries it was given"
+                );
+                sim.enqueue_event(SimulatorEvent {
+                    time: sim.now(),
+                    action: SimulatorAction::HealNetworkPartition,
+                });
+                healed = true;
+            }
+            continue;
+        }
+        if sim.applied_commands(deposed).len() as u64 >= COMMANDS {
+            converged = true;
+            break;
+        }
+    }
+
+    assert!(
+        converged,
+        "the deposed leader did not take the new leader's log"
+    );
+    let applied_by_leader = sim.applied_commands(sim.current_leader().unwrap());
+    assert!(applied_by_leader
+        .iter()
+        .all(|(_, command)| !UNCOMMITTED.contains(&command.0)));
+    for server_id in NODES.iter() {
+        assert_eq!(sim.applied_commands(*server_id), applied_by_leader);
+    }
+}
+
+#[test]
+fn should_catch_up_a_follower_behind_the_leaders_snapshot() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .snapshot_threshold(SnapshotThreshold(5))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    const COMMANDS: u64 = 20;
+    let mut lagging = None;
+    let mut healed = false;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some(lagging) = lagging else {
+            // Cut a follower off before anything is proposed so it misses the whole log
+            let follower = *NODES
+                .iter()
+                .find(|server_id| **server_id != leader)
+                .unwrap();
+            let rest = NODES
+                .iter()
+                .copied()
+                .filter(|server_id| *server_id != follower)
+                .collect();
+            sim.enqueue_event(SimulatorEvent {
+                time: sim.now(),
+                action: SimulatorAction::PartitionNetwork(vec![HashSet::from([follower]), rest]),
+            });
+            for command in 0..COMMANDS {
+                sim.propose(leader, SimLogCommand(command));
+            }
+            lagging = Some(follower);
+            continue;
+        };
+        if !healed {
+            let majority_applied = NODES
+                .iter()
+                .filter(|server_id| **server_id != lagging)
+                .all(|server_id| sim.applied_commands(*server_id).len() as u64 >= COMMANDS);
+            if majority_applied {
+                sim.enqueue_event(SimulatorEvent {
+                    time: sim.now(),
+                    action: SimulatorAction::HealNetworkPartition,
+                });
+                healed = true;
+            }
+            continue;
+        }
+        if sim.applied_commands(lagging).len() as u64 >= COMMANDS {
+            break;
+        }
+    }
+
+    let lagging = lagging.expect("a leader should have been elected");
+    assert!(sim
+        .recent_events(lagging)
+        .iter()
+        .any(|event| matches!(event, RaftEvent::SnapshotInstalled { .. })));
+    let applied_by_leader = sim.applied_commands(sim.current_leader().unwrap());
+    assert_eq!(applied_by_leader.len() as u64, COMMANDS);
+    for server_id in NODES.iter() {
+        assert_eq!(sim.applied_commands(*server_id), applied_by_leader);
+    }
+}
+
+#[test]
+fn should_send_a_snapshot_in_chunks_over_a_lossy_network() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .snapshot_threshold(SnapshotThreshold(5))
+        .snapshot_chunk_size(SnapshotChunkSize(8))
+        .build()
+        .unwrap();
+
+    // Lost chunks are sent again from where the follower's last ack left off
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.1),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    const COMMANDS: u64 = 20;
+    let mut lagging = None;
+    let mut healed = false;
+    for step in 1..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some(lagging) = lagging else {
+            let follower = *NODES
+                .iter()
+                .find(|server_id| **server_id != leader)
+                .unwrap();
+            let rest = NODES
+                .iter()
+                .copied()
+                .filter(|server_id| *server_id != follower)
+                .collect();
+            sim.enqueue_event(SimulatorEvent {
+                time: sim.now(),
+                action: SimulatorAction::PartitionNetwork(vec![HashSet::from([follower]), rest]),
+            });
+            for command in 0..COMMANDS {
+                sim.propose(leader, SimLogCommand(command));
+            }
+            lagging = Some(follower);
+            continue;
+        };
+        if !healed {
+            let majority_applied = NODES
+                .iter()
+                .filter(|server_id| **server_id != lagging)
+                .all(|server_id| sim.applied_commands(*server_id).len() as u64 >= COMMANDS);
+            if majority_applied {
+                sim.enqueue_event(SimulatorEvent {
+                    time: sim.now(),
+                    action: SimulatorAction::HealNetworkPartition,
+                });
+                healed = true;
+            }
+            continue;
+        }
+        if NODES
+            .iter()
+            .all(|server_id| sim.applied_commands(*server_id).len() as u64 >= COMMANDS)
+        {
+            break;
+        }
+    }
+
+    let lagging = lagging.expect("a leader should have been elected");
+    assert!(sim
+        .recent_events(lagging)
+        .iter()
+        .any(|event| matches!(event, RaftEvent::SnapshotInstalled { .. })));
+    let applied_by_leader = sim.applied_commands(sim.current_leader().unwrap());
+    assert_eq!(applied_by_leader.len() as u64, COMMANDS);
+    for server_id in NODES.iter() {
+        assert_eq!(sim.applied_commands(*server_id), applied_by_leader);
+    }
+}
+
+#[test]
+fn should_not_raise_the_term_of_a_follower_cut_off_from_the_cluster() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut isolated = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        if let Some(leader) = sim.current_leader() {
+            isolated = NODES.iter().copied().find(|server_id| *server_id != leader);
+            break;
+        }
+    }
+    let isolated = isolated.expect("a leader should have been elected");
+    let rest = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != isolated)
+        .collect();
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::PartitionNetwork(vec![HashSet::from([isolated]), rest]),
+    });
+    let partitioned_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(partitioned_at + 100));
+    let term_when_cut_off = sim.diagnostics(isolated).unwrap().state.current_term;
+
+    // Dozens of election timeouts go by, none of them gets a pre-vote through
+    sim.run_until_time(Duration::from_millis(partitioned_at + 10_000));
+    let diagnostics = sim.diagnostics(isolated).unwrap();
+    assert_eq!(diagnostics.state.current_term, term_when_cut_off);
+    assert_eq!(diagnostics.state.current_state, RaftNodeState::PreCandidate);
+}
+
+#[test]
+fn should_count_pre_votes_turned_down_for_a_log_behind() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        leader = sim.current_leader();
+        if leader.is_some() {
+            break;
+        }
+    }
+    let leader = leader.expect("a leader should have been elected");
+    let mut followers = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != leader);
+    let (behind, up_to_date) = (followers.next().unwrap(), followers.next().unwrap());
+
+    // The rest of the cluster commits an entry without the server that falls behind
+    let rest = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != behind)
+        .collect();
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::PartitionNetwork(vec![HashSet::from([behind]), rest]),
+    });
+    let partitioned_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(partitioned_at + 10));
+    let pending = sim.propose(leader, SimLogCommand(7));
+    sim.run_until_time(Duration::from_millis(partitioned_at + 1_000));
+    assert!(matches!(pending.try_outcome(), Some(Ok(_))));
+
+    // Cut off from the leader along with it, a server with the entry turns its pre-votes down
+    let now = sim.now().as_millis() as u64;
+    sim.enqueue_event(SimulatorEvent {
+        time: SimTime::from_millis(now + 1),
+        action: SimulatorAction::HealNetworkPartition,
+    });
+    let rest = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != behind && *server_id != up_to_date)
+        .collect();
+    sim.enqueue_event(SimulatorEvent {
+        time: SimTime::from_millis(now + 2),
+        action: SimulatorAction::PartitionNetwork(vec![HashSet::from([behind, up_to_date]), rest]),
+    });
+    // Checked as soon as there is one, before later events push it out of the recent ones
+    let mut rejections = 0;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(now + 500 * step));
+        rejections = sim
+            .metrics(up_to_date)
+            .unwrap()
+            .election_stats()
+            .pre_vote_rejections;
+        if rejections > 0 {
+            break;
+        }
+    }
+    assert!(rejections > 0);
+    assert!(sim.recent_events(up_to_date).iter().any(|event| matches!(
+        event,
+        RaftEvent::PreVoteRejected { candidate_id, .. } if *candidate_id == behind
+    )));
+    let status = sim.diagnostics(up_to_date).unwrap().status();
+    assert!(status.elections.pre_vote_rejections > 0);
+    assert!(
+        status.elections.pre_vote_rejections
+            <= sim
+                .metrics(up_to_date)
+                .unwrap()
+                .election_stats()
+                .pre_vote_rejections
+    );
+}
+
+#[test]
+fn should_step_down_a_leader_cut_off_from_the_majority() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut isolated_leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        isolated_leader = sim.current_leader();
+        if isolated_leader.is_some() {
+            break;
+        }
+    }
+    let isolated_leader = isolated_leader.expect("a leader should have been elected");
+    let rest = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != isolated_leader)
+        .collect();
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::PartitionNetwork(vec![HashSet::from([isolated_leader]), rest]),
+    });
+
+    // A couple of election timeouts without replies is enough for it to notice
+    let partitioned_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(partitioned_at + 2_000));
+    let state = sim.diagnostics(isolated_leader).unwrap().state;
+    assert_ne!(state.current_state, RaftNodeState::Leader);
+}
+
+#[test]
+fn should_keep_the_leader_when_a_removed_server_asks_for_votes() {
+    let rng = new_rng(None);
+    // Without pre-votes the removed server keeps raising its term and asking for votes
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(50)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .pre_vote(false)
+        .build()
+        .unwrap();
+
+    // Heartbeats late enough to time a follower out would rightly unseat the leader, keep them on time
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(0.5),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        leader = sim.current_leader();
+        if leader.is_some() {
+            break;
+        }
+    }
+    let leader = leader.expect("a leader should have been elected");
+    let removed = *NODES
+        .iter()
+        .find(|server_id| **server_id != leader)
+        .unwrap();
+    let rest: HashSet<ServerId> = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != removed)
+        .collect();
+
+    // Cut off, the removed server never learns it was removed
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::PartitionNetwork(vec![HashSet::from([removed]), rest.clone()]),
+    });
+    let partitioned_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(partitioned_at + 10));
+    let removal = sim.change_membership(leader, MembershipChange::RemoveServer(removed));
+    sim.run_until_time(Duration::from_millis(partitioned_at + 2_000));
+    assert!(matches!(removal.try_outcome(), Some(Ok(_))));
+    let leader_term = sim.diagnostics(leader).unwrap().state.current_term;
+    assert!(sim.diagnostics(removed).unwrap().state.current_term > leader_term);
+
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::HealNetworkPartition,
+    });
+    let healed_at = sim.now().as_millis() as u64;
+    for step in 1..=10 {
+        sim.run_until_time(Duration::from_millis(healed_at + 500 * step));
+        for server_id in &rest {
+            let state = sim.diagnostics(*server_id).unwrap().state;
+            assert_eq!(state.current_term, leader_term);
+            assert_eq!(state.leader_for_term, Some(leader));
+        }
+    }
+}
+
+#[test]
+fn should_hand_leadership_over_to_the_server_a_leader_steps_down_for() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(50)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    // Heartbeats late enough to time a follower out would start another election, keep them on time
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(0.5),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        leader = sim.current_leader();
+        if leader.is_some() {
+            break;
+        }
+    }
+    let leader = leader.expect("a leader should have been elected");
+    for command in 1..=3 {
+        let _ = sim.propose(leader, SimLogCommand(command));
+    }
+    let proposed_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(proposed_at + 500));
+    let leader_term = sim.diagnostics(leader).unwrap().state.current_term;
+
+    let mut followers = NODES
+        .iter()
+        .copied()
+        .filter(|server_id| *server_id != leader);
+    let successor = followers.next().unwrap();
+    let bystander = followers.next().unwrap();
+    let step_down = sim.step_down(leader, Some(successor));
+    let not_leading = sim.step_down(bystander, None);
+    let stepped_down_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(stepped_down_at + 500));
+
+    assert!(matches!(step_down.try_outcome(), Some(Ok(()))));
+    assert!(matches!(
+        not_leading.try_outcome(),
+        Some(Err(StepDownError::NotLeader))
+    ));
+    // The successor won the one election the hand over started, stickiness didn't get in the way
+    let successor_state = sim.diagnostics(successor).unwrap().state;
+    assert_eq!(successor_state.current_state, RaftNodeState::Leader);
+    assert_eq!(successor_state.current_term.0, leader_term.0 + 1);
+    let old_leader_state = sim.diagnostics(leader).unwrap().state;
+    assert_eq!(old_leader_state.current_state, RaftNodeState::Follower);
+    assert_eq!(old_leader_state.leader_for_term, Some(successor));
+
+    let _ = sim.propose(successor, SimLogCommand(4));
+    sim.run_until_time(Duration::from_millis(stepped_down_at + 1_000));
+    assert_eq!(
+        sim.applied_commands(leader)
+            .iter()
+            .map(|(_, command)| *command)
+            .collect::<Vec<_>>(),
+        (1..=4).map(SimLogCommand).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn should_resolve_index_waits_once_the_index_is_applied() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(50)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(0.5),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        leader = sim.current_leader();
+        if leader.is_some() {
+            break;
+        }
+    }
+    let leader = leader.expect("a leader should have been elected");
+    let follower = *NODES
+        .iter()
+        .find(|server_id| **server_id != leader)
+        .unwrap();
+    let last_log_index = sim.diagnostics(leader).unwrap().state.last_log_index;
+    let applied = sim.wait_for_index(follower, last_log_index);
+    let next = sim.wait_for_index(follower, last_log_index.next());
+    let waited_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(waited_at + 500));
+    assert!(matches!(applied.try_outcome(), Some(Ok(()))));
+    assert!(next.try_outcome().is_none());
+
+    let _ = sim.propose(leader, SimLogCommand(1));
+    let proposed_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(proposed_at + 500));
+    assert!(matches!(next.try_outcome(), Some(Ok(()))));
+    assert_eq!(
+        sim.applied_commands(follower),
+        vec![(last_log_index.next(), SimLogCommand(1))]
+    );
+}
+
+#[test]
+fn should_serve_lease_reads_on_the_leader_only() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(50)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .read_lease(ReadLease {
+            duration: Duration::from_millis(150),
+            clock_skew_bound: Duration::from_millis(10),
+        })
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Leadership may move while the sim runs, keep asking whoever leads until a read goes through
+    let mut proposed = false;
+    let mut served = None;
+    let mut turned_down = None;
+    let mut served_by = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if !proposed {
+            let _ = sim.propose(leader, SimLogCommand(7));
+            proposed = true;
+            continue;
+        }
+        if sim.applied_commands(leader).is_empty() {
+            continue;
+        }
+        let read = sim.read(leader, |application| application.applied_commands());
+        let follower = *NODES
+            .iter()
+            .find(|server_id| **server_id != leader)
+            .unwrap();
+        let follower_read = sim.read(follower, |application| application.applied_commands());
+        sim.run_until_time(Duration::from_millis(500 * step + 250));
+        if let Some(Ok(applied)) = read.try_outcome() {
+            served = Some(applied);
+            served_by = Some(leader);
+            turned_down = follower_read.try_outcome();
+            break;
+        }
+    }
+
+    let served = served.expect("the leader should have served a read off its lease");
+    assert_eq!(
+        served
+            .iter()
+            .map(|(_, command)| *command)
+            .collect::<Vec<_>>(),
+        vec![SimLogCommand(7)]
+    );
+    // The follower heard from the leader since the command it applied committed
+    assert_eq!(turned_down, Some(Err(ReadError::NotLeader(served_by))));
+}
+
+#[test]
+fn should_read_a_proposal_on_a_follower_once_it_applied_the_proposals_index() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut proposal = None;
+    let mut applied_at = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some((proposed_to, pending)) = &proposal else {
+            proposal = Some((leader, sim.propose(leader, SimLogCommand(7))));
+            continue;
+        };
+        if let Some(Ok(applied)) = pending.try_outcome() {
+            applied_at = Some((*proposed_to, applied.index));
+            break;
+        }
+    }
+    let (leader, applied_at) = applied_at.expect("the proposal should have been applied");
+
+    // The read waits for the follower to catch up with the leader, rather than missing the write
+    let follower = ServerId((leader.0 + 1) % 5);
+    let read = sim.read_after(follower, applied_at, |application| {
+        application.applied_commands()
+    });
+    let mut served = None;
+    for step in 61..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        if let Some(outcome) = read.try_outcome() {
+            served = Some(outcome);
+            break;
+        }
+    }
+    let served = served
+        .expect("the read should have been served")
+        .expect("any server should serve reads after an index");
+    assert!(served.contains(&(applied_at, SimLogCommand(7))));
+}
+
+#[test]
+fn should_hand_out_a_view_of_the_state_as_of_the_last_applied_index() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut proposal = None;
+    let mut applied_at = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some((proposed_to, pending)) = &proposal else {
+            proposal = Some((leader, sim.propose(leader, SimLogCommand(7))));
+            continue;
+        };
+        if let Some(Ok(applied)) = pending.try_outcome() {
+            applied_at = Some((*proposed_to, applied.index));
+            break;
+        }
+    }
+    let (leader, applied_at) = applied_at.expect("the proposal should have been applied");
+
+    let view = sim.state_view(leader);
+    sim.run_until_time(Duration::from_millis(500 * 61));
+    let view = view
+        .try_outcome()
+        .expect("the view should have been taken")
+        .expect("any server should hand out a view");
+    assert!(view.applied_index >= applied_at);
+
+    // Serialized after the leader applied more, the view still holds the state it was taken at
+    let _ = sim.propose(leader, SimLogCommand(8));
+    sim.run_until_time(Duration::from_millis(500 * 70));
+    let state: Vec<(LogIndex, SimLogCommand)> = bincode::DefaultOptions::new()
+        .deserialize(&view.data())
+        .unwrap();
+    assert!(state.contains(&(applied_at, SimLogCommand(7))));
+    assert!(state.iter().all(|(index, _)| *index <= view.applied_index));
+    assert_eq!(
+        state.last().map(|(index, _)| *index),
+        Some(view.applied_index)
+    );
+}
+
+#[test]
+fn should_record_the_leaders_rpcs_role_term_commit_index_and_storage_use_in_its_metrics() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut proposal = None;
+    let mut applied_at = None;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let Some((proposed_to, pending)) = &proposal else {
+            proposal = Some((leader, sim.propose(leader, SimLogCommand(7))));
+            continue;
+        };
+        if let Some(Ok(applied)) = pending.try_outcome() {
+            applied_at = Some((*proposed_to, applied.index));
+            break;
+        }
+    }
+    let (leader, applied_at) = applied_at.expect("the proposal should have been applied");
+    let metrics = sim.metrics(leader).unwrap();
+
+    let gauges = metrics.gauges();
+    assert_eq!(gauges.role, Some(RaftNodeState::Leader));
+    assert!(gauges.current_term.0 > 0);
+    assert!(gauges.commit_index >= applied_at);
+    let rpcs = metrics.rpc_stats();
+    assert!(rpcs.sent > 0);
+    assert!(rpcs.received > 0);
+    assert!(metrics.append_latency().snapshot().count > 0);
+    assert!(metrics.sync_latency().snapshot().count >= metrics.append_latency().snapshot().count);
+    let storage = metrics.storage_stats();
+    assert!(storage.bytes_written > 0);
+    // Replicating the entries to the followers read them back
+    assert!(storage.bytes_read > 0);
+    assert!(storage.log_size_on_disk > 0);
+    assert!(metrics.election_stats().elections_started > 0);
+    let votes_granted: u64 = (0..5)
+        .map(|id| {
+            sim.metrics(ServerId(id))
+                .unwrap()
+                .election_stats()
+                .votes_granted
+        })
+        .sum();
+    assert!(votes_granted > 0);
+}
+
+#[test]
+fn should_serve_a_lease_read_before_anything_was_proposed() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(50)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .read_lease(ReadLease {
+            duration: Duration::from_millis(150),
+            clock_skew_bound: Duration::from_millis(10),
+        })
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
 
-// Use quickcheck to implement some stateful tests
-// Generate a series of ops
-// - PartitionNetwork - Creates a network partition separating a set of nodes from the rest of the cluster
-// - HealPartition - Makes the network whole again, reversing the effect of PartitionNetwork
-// - RunForDuration - Runs the simulation for a given duration without any other actions
-// - FailNode - Put one node in a network partition to simulate a failure of a node
-// - RecoverNode - Heal the network partition to simulate a recovery of a node
+    // The leader's commit index is its no-op entry, which the application never sees
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let read = sim.read(leader, |application| application.applied_commands());
+        sim.run_until_time(Duration::from_millis(500 * step + 250));
+        if let Some(Ok(applied)) = read.try_outcome() {
+            assert!(applied.is_empty());
+            return;
+        }
+    }
+    panic!("the leader should have served a read off its lease");
+}
 
-fn new_rng(maybe_seed: Option<u64>) -> ChaCha8Rng {
-    match maybe_seed {
-        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
-        None => {
-            let mut rng = ChaCha8Rng::from_entropy();
-            let seed = rng.next_u64();
-            println!("====================================");
-            println!("RNG SEED FOR TESTS: {seed}", seed = seed);
-            println!("====================================");
-            ChaCha8Rng::seed_from_u64(seed)
+#[test]
+fn should_replicate_to_a_learner_without_counting_it_until_promoted() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    // Room in the network for a fourth server that joins as a learner
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        4,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        3,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+    let learner = ServerId(3);
+    sim.start_joining_server(learner);
+
+    let mut pending: Option<(MembershipChange, PendingMembershipChange)> = None;
+    let mut added = false;
+    let mut seen_as_learner = false;
+    let mut promoted = false;
+    let mut next_command = 0;
+    for step in 1..=160 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        if let Some(outcome) = pending
+            .as_ref()
+            .and_then(|(_, pending)| pending.try_outcome())
+        {
+            let (change, _) = pending.take().unwrap();
+            // The change may have committed after its leader lost leadership, asking again changes nothing
+            if matches!(outcome, Ok(_) | Err(MembershipChangeError::NothingToChange)) {
+                match change {
+                    MembershipChange::AddLearner(_) => added = true,
+                    MembershipChange::PromoteLearner(_) => promoted = true,
+                    _ => unreachable!(),
+                }
+            }
+        }
+        if promoted {
+            break;
+        }
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if pending.is_some() {
+            continue;
+        }
+        // Configuration entries wait for the leader to commit an entry of its own term
+        sim.propose(leader, SimLogCommand(next_command));
+        next_command += 1;
+        if !added {
+            let change = MembershipChange::AddLearner(learner);
+            pending = Some((change, sim.change_membership(leader, change)));
+            continue;
+        }
+        // Promote it only once it was seen getting the log without being a voter
+        let diagnostics = sim.diagnostics(leader).unwrap();
+        if diagnostics.learners.contains(&learner) && !sim.applied_commands(learner).is_empty() {
+            assert!(!diagnostics.peers.contains(&learner));
+            seen_as_learner = true;
+            let change = MembershipChange::PromoteLearner(learner);
+            pending = Some((change, sim.change_membership(leader, change)));
         }
     }
+    assert!(seen_as_learner, "the learner did not get the log");
+    assert!(promoted, "the learner was not promoted");
+
+    let mut voter = false;
+    for step in 161..=200 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let diagnostics = sim.diagnostics(leader).unwrap();
+        if leader == learner
+            || (diagnostics.peers.contains(&learner) && diagnostics.learners.is_empty())
+        {
+            voter = true;
+            break;
+        }
+    }
+    assert!(voter, "the promoted learner is not a voting member");
 }
 
-fn sim_log_path(log_file_name: Option<&str>) -> Option<PathBuf> {
-    log_file_name.map(|filename| {
-        let pwd = std::env::current_dir().unwrap();
-        pwd.join("..").join(filename)
-    })
+#[test]
+fn should_commit_with_a_witness_while_the_other_server_is_down() {
+    let rng = new_rng(None);
+    let witness = ServerId(2);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .witness(witness)
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        3,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        3,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        leader = sim.current_leader();
+        if leader.is_some() {
+            break;
+        }
+    }
+    let leader = leader.expect("a leader should have been elected");
+    assert_ne!(leader, witness);
+    let down = *NODES[..2]
+        .iter()
+        .find(|server_id| **server_id != leader)
+        .unwrap();
+
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::PartitionNetwork(vec![
+            HashSet::from([down]),
+            HashSet::from([leader, witness]),
+        ]),
+    });
+    let partitioned_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(partitioned_at + 10));
+    let commands: Vec<SimLogCommand> = (1..=5).map(SimLogCommand).collect();
+    for command in &commands {
+        sim.propose(leader, *command);
+    }
+    sim.run_until_time(Duration::from_millis(partitioned_at + 2_000));
+    assert_eq!(sim.current_leader(), Some(leader));
+    let applied_by_leader: Vec<SimLogCommand> = sim
+        .applied_commands(leader)
+        .into_iter()
+        .map(|(_, command)| command)
+        .collect();
+    assert_eq!(applied_by_leader, commands);
+    let witness_state = sim.diagnostics(witness).unwrap().state;
+    assert_eq!(
+        witness_state.last_log_index,
+        sim.diagnostics(leader).unwrap().state.last_log_index
+    );
+    assert!(sim.applied_commands(witness).is_empty());
+
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::HealNetworkPartition,
+    });
+    let healed_at = sim.now().as_millis() as u64;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(healed_at + 500 * step));
+        assert_ne!(sim.current_leader(), Some(witness));
+        if sim.applied_commands(down) == sim.applied_commands(leader) {
+            assert!(sim.applied_commands(witness).is_empty());
+            return;
+        }
+    }
+    panic!("the server that was down did not catch up");
 }
 
-const SIMULATION_DURATION: Duration = Duration::from_secs(300);
+#[test]
+fn should_catch_a_witness_up_with_a_snapshot_holding_no_application_state() {
+    let rng = new_rng(None);
+    let witness = ServerId(2);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .snapshot_threshold(SnapshotThreshold(5))
+        .witness(witness)
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        3,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        3,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut leader = None;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        leader = sim.current_leader();
+        if leader.is_some() {
+            break;
+        }
+    }
+    let leader = leader.expect("a leader should have been elected");
+    let other = *NODES[..3]
+        .iter()
+        .find(|server_id| **server_id != leader && **server_id != witness)
+        .unwrap();
+
+    // The witness misses the whole log, which the leader compacts meanwhile
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::PartitionNetwork(vec![
+            HashSet::from([witness]),
+            HashSet::from([leader, other]),
+        ]),
+    });
+    let partitioned_at = sim.now().as_millis() as u64;
+    sim.run_until_time(Duration::from_millis(partitioned_at + 10));
+    const COMMANDS: u64 = 20;
+    for command in 0..COMMANDS {
+        sim.propose(leader, SimLogCommand(command));
+    }
+    sim.run_until_time(Duration::from_millis(partitioned_at + 2_000));
+    assert_eq!(sim.applied_commands(leader).len() as u64, COMMANDS);
+
+    sim.enqueue_event(SimulatorEvent {
+        time: sim.now(),
+        action: SimulatorAction::HealNetworkPartition,
+    });
+    let healed_at = sim.now().as_millis() as u64;
+    for step in 1..=20 {
+        sim.run_until_time(Duration::from_millis(healed_at + 500 * step));
+        let caught_up = sim.diagnostics(witness).unwrap().state.last_log_index
+            == sim.diagnostics(leader).unwrap().state.last_log_index;
+        if caught_up {
+            assert!(sim
+                .recent_events(witness)
+                .iter()
+                .any(|event| matches!(event, RaftEvent::SnapshotInstalled { .. })));
+            assert!(sim.applied_commands(witness).is_empty());
+            return;
+        }
+    }
+    panic!("the witness did not catch up");
+}
 
 #[test]
-fn should_elect_leader_without_network_partition() {
+fn should_add_a_caught_up_server_and_remove_another_one_at_a_time() {
     let rng = new_rng(None);
-    let config = RaftConfig {
-        leader_heartbeat_interval: Duration::from_millis(100),
-        min_election_timeout_ms: 150,
-        max_election_timeout_ms: 300,
-    };
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    // Room in the network for a fourth server that joins later
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        4,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        3,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+    let new_server = ServerId(3);
+    sim.start_joining_server(new_server);
+
+    let mut members: HashSet<ServerId> = NODES[..3].iter().copied().collect();
+    let mut changes = vec![MembershipChange::AddServer(new_server)];
+    let mut pending: Option<(MembershipChange, PendingMembershipChange)> = None;
+    let mut next_command = 0;
+    for step in 1..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        if let Some(outcome) = pending
+            .as_ref()
+            .and_then(|(_, pending)| pending.try_outcome())
+        {
+            let (change, _) = pending.take().unwrap();
+            // The change may have committed after its leader lost leadership, asking again changes nothing
+            if matches!(outcome, Ok(_) | Err(MembershipChangeError::NothingToChange)) {
+                change.apply_to(&mut members);
+                if let MembershipChange::AddServer(_) = change {
+                    changes.push(MembershipChange::RemoveServer(
+                        *NODES[..3]
+                            .iter()
+                            .find(|server_id| Some(**server_id) != sim.current_leader())
+                            .unwrap(),
+                    ));
+                }
+            } else {
+                changes.insert(0, change);
+            }
+        }
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if pending.is_none() {
+            let Some(change) = changes.pop() else {
+                break;
+            };
+            // Configuration entries wait for the leader to commit an entry of its own term
+            sim.propose(leader, SimLogCommand(next_command));
+            next_command += 1;
+            pending = Some((change, sim.change_membership(leader, change)));
+        }
+    }
+    assert!(
+        changes.is_empty() && pending.is_none(),
+        "membership changes did not go through"
+    );
+    assert_eq!(members.len(), 3);
+    assert!(members.contains(&new_server));
+
+    // The cluster keeps committing with the new server counting toward the majority
+    let mut last_command = None;
+    for step in 121..=200 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        let applied_by_leader = sim.applied_commands(leader);
+        let all_applied = applied_by_leader.last().map(|(_, command)| *command) == last_command
+            && members
+                .iter()
+                .all(|server_id| sim.applied_commands(*server_id) == applied_by_leader);
+        if last_command.is_some() && all_applied {
+            assert_eq!(
+                sim.diagnostics(leader).unwrap().peers,
+                members
+                    .iter()
+                    .copied()
+                    .filter(|server_id| *server_id != leader)
+                    .collect::<HashSet<_>>()
+            );
+            return;
+        }
+        if last_command.is_none() || sim.applied_commands(leader).len() as u64 >= next_command {
+            sim.propose(leader, SimLogCommand(next_command));
+            last_command = Some(SimLogCommand(next_command));
+            next_command += 1;
+        }
+    }
+    panic!("the new members did not all apply the last command");
+}
+
+/// A command type of the application's own, the testkit replicates anything that is a `LogCommand`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum KeyValueCommand {
+    Set { key: String, value: String },
+    Delete { key: String },
+}
 
-    let network = SimNetwork::with_defaults(
+#[test]
+fn should_replicate_commands_of_an_application_defined_type() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<KeyValueCommand>::with_defaults(
         5,
         PacketLossProbability(0.01),
         LatencyMean(5.0),
@@ -81,21 +2361,50 @@ fn should_elect_leader_without_network_partition() {
         sim_log_path(None),
     );
 
-    sim.run_until_time(SIMULATION_DURATION);
-
-    assert_eq!(sim.results.was_leader_elected, true);
+    let mut next_key = 1;
+    for step in 1..=60 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        let leader_state = sim
+            .current_leader()
+            .and_then(|leader| sim.diagnostics(leader))
+            .map(|diagnostics| diagnostics.state);
+        let Some(leader_state) = leader_state else {
+            continue;
+        };
+        let all_committed = NODES.iter().all(|server_id| {
+            sim.diagnostics(*server_id)
+                .map(|diagnostics| diagnostics.state.commit_index >= LogIndex(2))
+                .unwrap_or(false)
+        });
+        if all_committed {
+            return;
+        }
+        if leader_state.commit_index < LogIndex(2) {
+            let key = format!("key-{next_key}");
+            sim.propose(
+                leader_state.server_id,
+                KeyValueCommand::Set {
+                    key: key.clone(),
+                    value: "value".into(),
+                },
+            );
+            sim.propose(leader_state.server_id, KeyValueCommand::Delete { key });
+            next_key += 1;
+        }
+    }
+    panic!("commands of the application's type were not committed on every server");
 }
 
 #[test]
 fn should_elect_leader_during_network_partition_if_we_have_quorum() {
     let rng = new_rng(None);
-    let config = RaftConfig {
-        leader_heartbeat_interval: Duration::from_millis(100),
-        min_election_timeout_ms: 150,
-        max_election_timeout_ms: 300,
-    };
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
 
-    let network = SimNetwork::with_defaults(
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
         5,
         PacketLossProbability(0.01),
         LatencyMean(5.0),
@@ -113,10 +2422,10 @@ fn should_elect_leader_during_network_partition_if_we_have_quorum() {
         sim_log_path(None),
     );
 
-    info!("Current sim time is {time:?}", time = SimTime::now());
+    info!("Current sim time is {time:?}", time = sim.now());
 
     sim.enqueue_event(SimulatorEvent {
-        time: SimTime::now(),
+        time: sim.now(),
         action: SimulatorAction::PartitionNetwork(vec![
             {
                 let mut partition = HashSet::new();
@@ -135,7 +2444,7 @@ fn should_elect_leader_during_network_partition_if_we_have_quorum() {
     });
 
     sim.run_until_time(SIMULATION_DURATION);
-    assert_eq!(sim.results.was_leader_elected, true);
+    assert!(sim.results.was_leader_elected);
 
     // 2 & 4 in a partition without quorum, they should not be able to be elected leader
     assert!(!sim.results.all_elected_leaders.contains(&ServerId(2)));
@@ -145,13 +2454,13 @@ fn should_elect_leader_during_network_partition_if_we_have_quorum() {
 #[test]
 fn should_not_be_able_to_elect_leader_without_quorum() {
     let rng = new_rng(None);
-    let config = RaftConfig {
-        leader_heartbeat_interval: Duration::from_millis(100),
-        min_election_timeout_ms: 150,
-        max_election_timeout_ms: 300,
-    };
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
 
-    let network = SimNetwork::with_defaults(
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
         5,
         PacketLossProbability(0.01),
         LatencyMean(5.0),
@@ -159,7 +2468,6 @@ fn should_not_be_able_to_elect_leader_without_quorum() {
     );
     let temp_dir = TempDir::new().unwrap();
     let temp_dir_path = temp_dir.path().to_str().unwrap();
-    let pwd = std::env::current_dir().unwrap();
 
     let mut sim = ClusterSim::new(
         5,
@@ -194,17 +2502,18 @@ fn should_not_be_able_to_elect_leader_without_quorum() {
     });
 
     sim.run_until_time(SIMULATION_DURATION);
-    assert_eq!(sim.results.was_leader_elected, false);
+    assert!(!sim.results.was_leader_elected);
     drop(sim);
 }
 
 #[derive(Debug, Clone)]
 struct SimInstructionSequence {
-    generated_state_changes: Vec<SimulatorEvent>,
+    generated_state_changes: Vec<SimulatorEvent<SimLogCommand>>,
 }
 
 impl SimInstructionSequence {}
 
+#[allow(dead_code)]
 const NUM_NODES_IN_CLUSTER: usize = 5;
 const NODES: [ServerId; 5] = [
     ServerId(0),
@@ -215,6 +2524,7 @@ const NODES: [ServerId; 5] = [
 ];
 
 const CLOCK_ADVANCE_CHOICES: [u64; 9] = [100, 500, 100, 1000, 500, 1000, 100, 5000, 10000];
+#[allow(dead_code)]
 const MAX_TIME_BETWEEN_INSTRUCTIONS: usize = 60_000;
 const INSTRUCTION_PARTITION_NETWORK: &str = "PartitionNetwork";
 const INSTRUCTION_HEAL_NETWORK_PARTITION: &str = "HealNetworkPartition";
@@ -227,10 +2537,10 @@ const FAIL_EVERY_N_IO_OPS_CHOICES: [u64; 6] = [5, 5, 5, 100, 100, u64::MAX];
 impl Arbitrary for SimInstructionSequence {
     fn arbitrary(g: &mut quickcheck::Gen) -> Self {
         let mut reduced_io_functioning = false;
-        let failed_nodes = HashSet::<ServerId>::new();
+        let _failed_nodes = HashSet::<ServerId>::new();
         let mut network_partition: Option<Vec<HashSet<ServerId>>> = None;
 
-        let mut sequence_of_events = Vec::<SimulatorEvent>::new();
+        let mut sequence_of_events = Vec::<SimulatorEvent<SimLogCommand>>::new();
 
         let mut clock: u64 = 0;
 
@@ -259,7 +2569,7 @@ impl Arbitrary for SimInstructionSequence {
             // }
 
             let next_event_time = g.choose(CLOCK_ADVANCE_CHOICES.as_slice()).unwrap();
-            clock = clock + *next_event_time;
+            clock += *next_event_time;
             let next_event_type = g.choose(&options).unwrap();
 
             match *next_event_type {
@@ -271,14 +2581,14 @@ impl Arbitrary for SimInstructionSequence {
                     let mut nodes_available: HashSet<_> = NODES.iter().cloned().collect();
                     let mut nodes_selected = HashSet::<ServerId>::new();
                     let mut current_partition = 0;
-                    while nodes_available.len() > 0 {
+                    while !nodes_available.is_empty() {
                         let node = *g
                             .choose(&nodes_available.iter().cloned().collect::<Vec<_>>())
                             .unwrap();
 
                         partitions
                             .entry(current_partition)
-                            .or_insert(HashSet::new())
+                            .or_default()
                             .insert(node);
 
                         nodes_available.remove(&node);
@@ -291,9 +2601,7 @@ impl Arbitrary for SimInstructionSequence {
                     let partitions: Vec<_> = partitions.iter().map(|e| e.1).cloned().collect();
                     sequence_of_events.push(SimulatorEvent {
                         time: SimTime::from_millis(clock),
-                        action: SimulatorAction::PartitionNetwork(
-                            partitions.iter().cloned().collect(),
-                        ),
+                        action: SimulatorAction::PartitionNetwork(partitions.to_vec()),
                     });
                     network_partition = Some(partitions);
                 }
@@ -341,13 +2649,18 @@ fn run_simulation_with_sequence_of_events(
     maybe_log_file_path: Option<&str>,
 ) {
     let rng = new_rng(maybe_rng_seed);
-    let config = RaftConfig {
-        leader_heartbeat_interval: Duration::from_millis(100),
-        min_election_timeout_ms: 150,
-        max_election_timeout_ms: 300,
-    };
+    // Retry storage syncs without waiting, a real sleep would hold up the mock clock
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .storage_sync_retry(StorageSyncRetry {
+            attempts: 3,
+            initial_backoff: Duration::ZERO,
+        })
+        .build()
+        .unwrap();
 
-    let network = SimNetwork::with_defaults(
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
         5,
         PacketLossProbability(0.01),
         LatencyMean(5.0),
@@ -380,7 +2693,7 @@ fn run_simulation_with_sequence_of_events(
     });
 
     sim.run_until_time((run_until_time + Duration::from_secs(60)).into());
-    assert_eq!(sim.results.was_leader_elected, true);
+    assert!(sim.results.was_leader_elected);
     drop(sim);
 }
 
@@ -405,3 +2718,270 @@ fn test_with_example_sequence_of_events() {
         run_simulation_with_sequence_of_events(events, maybe_rng_seed, maybe_log_file_path);
     }
 }
+
+#[test]
+fn should_append_proposals_made_within_the_group_commit_window_with_one_sync() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .group_commit(GroupCommit {
+            window: Duration::from_millis(50),
+            max_entries: 64,
+        })
+        .build()
+        .unwrap();
+
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    let mut now = Duration::ZERO;
+    let leader = loop {
+        now += Duration::from_millis(500);
+        sim.run_until_time(now);
+        if let Some(leader) = sim.current_leader() {
+            break leader;
+        }
+        assert!(now < Duration::from_secs(30), "no leader was elected");
+    };
+    // Let the leader sync its no-op entry before counting its syncs
+    now += Duration::from_millis(500);
+    sim.run_until_time(now);
+    let metrics = sim.metrics(leader).unwrap();
+    let syncs_before = metrics.append_latency().snapshot().count;
+
+    // Three proposals 10ms apart, all within the window the first one opens
+    let mut proposals = Vec::new();
+    for command in 0..3 {
+        proposals.push(sim.propose(leader, SimLogCommand(command)));
+        now += Duration::from_millis(10);
+        sim.run_until_time(now);
+    }
+    let mut outcomes = vec![None; proposals.len()];
+    while outcomes.iter().any(Option::is_none) {
+        now += Duration::from_millis(50);
+        sim.run_until_time(now);
+        for (proposal, outcome) in proposals.iter().zip(outcomes.iter_mut()) {
+            if outcome.is_none() {
+                *outcome = proposal.try_outcome();
+            }
+        }
+        assert!(
+            now < Duration::from_secs(60),
+            "the proposals were never applied"
+        );
+    }
+    let applied_at: Vec<LogIndex> = outcomes
+        .into_iter()
+        .map(|outcome| {
+            outcome
+                .expect("every proposal should be resolved")
+                .expect("the leader should have kept its leadership")
+                .index
+        })
+        .collect();
+
+    assert_eq!(applied_at[1].0, applied_at[0].0 + 1);
+    assert_eq!(applied_at[2].0, applied_at[0].0 + 2);
+    assert_eq!(metrics.append_latency().snapshot().count, syncs_before + 1);
+}
+
+#[test]
+fn should_audit_terms_votes_leaders_and_membership_changes() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        4,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let mut sim = ClusterSim::new(
+        3,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+    let new_server = ServerId(3);
+    sim.start_joining_server(new_server);
+
+    let mut pending: Option<PendingMembershipChange> = None;
+    let mut added_by = None;
+    for step in 1..=120 {
+        sim.run_until_time(Duration::from_millis(500 * step));
+        if let Some(outcome) = pending.as_ref().and_then(|pending| pending.try_outcome()) {
+            pending = None;
+            // The change may have committed after its leader lost leadership, asking again changes nothing
+            if matches!(outcome, Ok(_) | Err(MembershipChangeError::NothingToChange)) {
+                added_by = sim.current_leader();
+                break;
+            }
+        }
+        let Some(leader) = sim.current_leader() else {
+            continue;
+        };
+        if pending.is_none() {
+            // Configuration entries wait for the leader to commit an entry of its own term
+            sim.propose(leader, SimLogCommand(0));
+            pending = Some(sim.add_server(leader, new_server));
+        }
+    }
+    let leader = added_by.expect("the server was never added");
+    let finished_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    // Every record starts with when it was written, in milliseconds since the epoch
+    let read_audit_log = |server_id: ServerId| -> Vec<String> {
+        let path = format!("{temp_dir_path}/server-{id}/audit.log", id = server_id.0);
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (timestamp_ms, record) = line.split_once(' ').unwrap();
+                let timestamp_ms: u128 = timestamp_ms.parse().unwrap();
+                assert!(
+                    (started_at.as_millis()..=finished_at.as_millis()).contains(&timestamp_ms),
+                    "{line} written outside the test"
+                );
+                record.to_string()
+            })
+            .collect()
+    };
+
+    let leader_records = read_audit_log(leader);
+    let term = leader_records
+        .iter()
+        .rev()
+        .find_map(|record| record.strip_prefix("became_leader term="))
+        .expect("the leader never audited becoming leader")
+        .to_string();
+    assert!(leader_records.contains(&format!(
+        "election_started term={term} voted_for={}",
+        leader.0
+    )));
+    assert!(leader_records
+        .iter()
+        .any(|record| record.starts_with(&format!("term_changed term={term} "))));
+    assert!(
+        leader_records.contains(&"membership_changed members=[0, 1, 2, 3] learners=[]".to_string())
+    );
+
+    // The leader won its term with a vote from another server
+    let vote = format!("vote_granted term={term} voted_for={}", leader.0);
+    assert!(NODES[..3]
+        .iter()
+        .filter(|server_id| **server_id != leader)
+        .any(|server_id| read_audit_log(*server_id).contains(&vote)));
+}
+
+#[test]
+fn should_time_commit_and_apply_of_each_entry_only_on_the_leader() {
+    let rng = new_rng(None);
+    let config = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build()
+        .unwrap();
+    let network = SimNetwork::<SimLogCommand>::with_defaults(
+        5,
+        PacketLossProbability(0.0),
+        LatencyMean(5.0),
+        LatencyStdDev(2.0),
+    );
+    let temp_dir = TempDir::new().unwrap();
+    let temp_dir_path = temp_dir.path().to_str().unwrap();
+    let mut sim = ClusterSim::new(
+        5,
+        network,
+        config,
+        rng,
+        temp_dir_path.into(),
+        sim_log_path(None),
+    );
+
+    // Shared with the nodes, they keep moving as the simulation runs
+    let metrics: Vec<_> = NODES
+        .iter()
+        .map(|server_id| sim.metrics(*server_id).unwrap())
+        .collect();
+    let samples = |server: usize| {
+        (
+            metrics[server].commit_latency().snapshot().count,
+            metrics[server].apply_latency().snapshot().count,
+        )
+    };
+
+    // Leadership changing hands while the entries commit changes who times them, try again then
+    let mut step = 0;
+    for _ in 0..5 {
+        // A leader that applied everything it appended, its no-op entry included
+        let settled = loop {
+            step += 1;
+            assert!(step <= 200, "no leader settled");
+            sim.run_until_time(Duration::from_millis(500 * step));
+            let settled = sim.current_leader().and_then(|leader| {
+                sim.diagnostics(leader)
+                    .filter(|diagnostics| {
+                        diagnostics.state.last_applied == diagnostics.state.last_log_index
+                    })
+                    .map(|diagnostics| (leader, diagnostics.state.current_term))
+            });
+            if let Some(settled) = settled {
+                break settled;
+            }
+        };
+        let (leader, term) = settled;
+        let before: Vec<_> = (0..NODES.len()).map(samples).collect();
+
+        let proposals: Vec<_> = (0..5)
+            .map(|command| sim.propose(leader, SimLogCommand(command)))
+            .collect();
+        for _ in 0..10 {
+            step += 1;
+            sim.run_until_time(Duration::from_millis(500 * step));
+        }
+        let still_leading = sim.current_leader() == Some(leader)
+            && sim
+                .diagnostics(leader)
+                .is_some_and(|diagnostics| diagnostics.state.current_term == term);
+        if !still_leading
+            || !proposals
+                .iter()
+                .all(|proposal| matches!(proposal.try_outcome(), Some(Ok(_))))
+        {
+            continue;
+        }
+
+        for (server, (commits_before, applies_before)) in before.into_iter().enumerate() {
+            let server_id = NODES[server];
+            let (commits, applies) = samples(server);
+            let expected = if server_id == leader { 5 } else { 0 };
+            assert_eq!(commits - commits_before, expected, "{server_id:?} commits");
+            assert_eq!(applies - applies_before, expected, "{server_id:?} applies");
+        }
+        return;
+    }
+    panic!("leadership never held while the entries committed");
+}
@@ -0,0 +1,78 @@
+/// Tests matching replies back to requests with the RPC router
+use raft_consensus::rpc_messages::{ReplyTo, Request, TimeoutNow, TimeoutNowAck};
+use raft_consensus::system_clock::ManualClock;
+use raft_consensus::{RpcRouter, ServerId, TermIndex, TimedOutRequest};
+use std::time::Duration;
+use uuid::Uuid;
+
+fn request(to: u64) -> Request<String> {
+    Request::TimeoutNow(TimeoutNow {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(to),
+        term: TermIndex(2),
+    })
+}
+
+/// The reply to `request`, coming from `from`
+fn reply(request: &Request<String>, from: u64) -> ReplyTo {
+    ReplyTo::TimeoutNow(TimeoutNowAck {
+        request_id: request.request_id(),
+        from: ServerId(from),
+        to: request.from(),
+        term: TermIndex(2),
+    })
+}
+
+#[test]
+fn should_hand_a_reply_to_what_waits_on_its_request_once() {
+    let mut router = RpcRouter::new(Duration::from_secs(1));
+    let (first, second) = (request(2), request(3));
+    assert_eq!(router.track(&first, "first"), None);
+    assert_eq!(router.track(&second, "second"), None);
+
+    assert_eq!(router.route(&reply(&second, 3)), Some("second"));
+    assert_eq!(router.route(&reply(&second, 3)), None);
+    assert_eq!(router.outstanding(), 1);
+    // Another server can't answer the request
+    assert_eq!(router.route(&reply(&first, 3)), None);
+    assert_eq!(router.route(&reply(&first, 2)), Some("first"));
+    assert_eq!(router.route(&reply(&request(2), 2)), None);
+}
+
+#[test]
+fn should_time_requests_out_and_drop_their_late_replies() {
+    let clock = ManualClock::new();
+    let mut router = RpcRouter::new(Duration::from_millis(100)).clock(clock.clone());
+    let (slow, slower, answered) = (request(2), request(3), request(2));
+    let _ = router.track_with_timeout(&slower, "slower", Duration::from_millis(300));
+    let _ = router.track(&slow, "slow");
+    let _ = router.track(&answered, "answered");
+    assert_eq!(
+        router.time_to_next_timeout(),
+        Some(Duration::from_millis(100))
+    );
+
+    clock.advance(Duration::from_millis(50));
+    assert_eq!(router.expire(), vec![]);
+    assert_eq!(router.route(&reply(&answered, 2)), Some("answered"));
+
+    clock.advance(Duration::from_millis(300));
+    assert_eq!(
+        router.expire(),
+        vec![
+            TimedOutRequest {
+                request_id: slow.request_id(),
+                to: ServerId(2),
+                waiting: "slow",
+            },
+            TimedOutRequest {
+                request_id: slower.request_id(),
+                to: ServerId(3),
+                waiting: "slower",
+            },
+        ]
+    );
+    assert_eq!(router.route(&reply(&slow, 2)), None);
+    assert_eq!(router.time_to_next_timeout(), None);
+}
@@ -0,0 +1,172 @@
+/// Tests the storage kept in a sled database
+use raft_consensus::{
+    ClientId, DefaultPersistentStorage, LogEntry, LogEntryCommand, LogIndex, PersistentStorage,
+    ServerId, SledStorage, Snapshot, StorageExport, TermIndex,
+};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+fn entry(index: u64, term: u64) -> LogEntry<u64> {
+    LogEntry {
+        index: LogIndex(index),
+        term: TermIndex(term),
+        command: LogEntryCommand::Application(index * 10),
+    }
+}
+
+fn snapshot(last_included_index: u64, last_included_term: u64) -> Snapshot {
+    Snapshot {
+        last_included_index: LogIndex(last_included_index),
+        last_included_term: TermIndex(last_included_term),
+        members: [ServerId(1), ServerId(2), ServerId(3)].into(),
+        learners: [ServerId(4)].into(),
+        sessions: [(ClientId(Uuid::from_u128(1)), 5)].into(),
+        data: vec![1, 2, 3],
+    }
+}
+
+#[test]
+fn should_read_the_election_and_entries_back_after_reopening() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(2))
+        .record_vote(ServerId(3))
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 2)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    let storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.current_term(), TermIndex(2));
+    assert_eq!(storage.vote_for_current_term(), Some(ServerId(3)));
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(3)));
+    assert_eq!(storage.last_entry_term(), Some(TermIndex(2)));
+    assert_eq!(storage.entry_term(LogIndex(0)), Some(TermIndex(0)));
+    assert_eq!(storage.entry_term(LogIndex(4)), None);
+    assert_eq!(
+        storage.entries(LogIndex(2), 10),
+        vec![entry(2, 1), entry(3, 2)]
+    );
+    assert_eq!(
+        storage.entries_within(LogIndex(1), 10, 1),
+        vec![entry(1, 1)]
+    );
+}
+
+#[test]
+fn should_replace_conflicting_entries_synced_before_reopening() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // Entry 1 is already there and kept, entry 2 conflicts so 2 and 3 go
+    let mut storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 2)])
+        .sync()
+        .unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    drop(storage);
+
+    let storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(
+        storage.entries(LogIndex(1), 10),
+        vec![entry(1, 1), entry(2, 2)]
+    );
+    assert!(!storage.has_entry(LogIndex(3), TermIndex(1)));
+}
+
+#[test]
+fn should_keep_the_entries_after_a_snapshot_it_matches() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append((1..=10).map(|index| entry(index, 1)).collect())
+        .sync()
+        .unwrap();
+    storage.install_snapshot(snapshot(6, 1)).sync().unwrap();
+    storage.append(vec![entry(11, 2)]).sync().unwrap();
+    drop(storage);
+
+    let storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.snapshot(), Some(&snapshot(6, 1)));
+    assert_eq!(storage.entry_term(LogIndex(5)), None);
+    assert_eq!(storage.entry_term(LogIndex(6)), Some(TermIndex(1)));
+    assert_eq!(storage.entries(LogIndex(3), 10), vec![]);
+    assert_eq!(
+        storage.entries(LogIndex(9), 10),
+        vec![entry(9, 1), entry(10, 1), entry(11, 2)]
+    );
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(11)));
+}
+
+#[test]
+fn should_drop_the_whole_log_for_a_snapshot_it_does_not_match() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+
+    // The leader's snapshot ends at an entry this log has in another term
+    storage.install_snapshot(snapshot(2, 2)).sync().unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    assert_eq!(storage.last_entry_term(), Some(TermIndex(2)));
+    drop(storage);
+
+    let mut storage = SledStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.entries(LogIndex(3), 10), vec![]);
+    storage.append(vec![entry(3, 2)]).sync().unwrap();
+    assert_eq!(storage.entries(LogIndex(3), 10), vec![entry(3, 2)]);
+}
+
+#[test]
+fn should_take_over_what_the_file_storage_exported() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(3))
+        .record_vote(ServerId(2))
+        .append((1..=10).map(|index| entry(index, 1 + index / 8)).collect())
+        .sync()
+        .unwrap();
+    storage.install_snapshot(snapshot(6, 1)).sync().unwrap();
+    let export = StorageExport::read_from(&storage);
+    drop(storage);
+
+    let sled_dir = TempDir::new().unwrap();
+    let mut storage = SledStorage::<u64>::new(sled_dir.path()).unwrap();
+    export.clone().write_to(&mut storage).unwrap();
+    drop(storage);
+
+    let storage = SledStorage::<u64>::new(sled_dir.path()).unwrap();
+    assert_eq!(storage.current_term(), TermIndex(3));
+    assert_eq!(storage.vote_for_current_term(), Some(ServerId(2)));
+    assert_eq!(storage.snapshot(), Some(&snapshot(6, 1)));
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(10)));
+    assert_eq!(StorageExport::read_from(&storage), export);
+}
+
+#[cfg(feature = "json_codec")]
+#[test]
+fn should_refuse_a_database_written_in_another_format() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = SledStorage::<u64>::with_codec(
+        storage_dir.path(),
+        std::sync::Arc::new(raft_consensus::JsonCodec),
+    )
+    .unwrap();
+    storage.append(vec![entry(1, 1)]).sync().unwrap();
+    drop(storage);
+
+    assert_eq!(
+        SledStorage::<u64>::new(storage_dir.path()).err(),
+        Some(raft_consensus::PersistentStorageError::FormatMismatch)
+    );
+}
@@ -0,0 +1,671 @@
+/// Tests the log kept by the default storage
+use raft_consensus::{
+    ClientId, ClientSession, CodecError, CommandCodec, DefaultPersistentStorage, IntegrityProblem,
+    LogEntry, LogEntryCommand, LogIndex, MembershipChange, PersistentStorage,
+    PersistentStorageError, ServerId, Snapshot, StorageExport, TermIndex,
+};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+fn entry(index: u64, term: u64) -> LogEntry<u64> {
+    LogEntry {
+        index: LogIndex(index),
+        term: TermIndex(term),
+        command: LogEntryCommand::Application(index * 10),
+    }
+}
+
+fn snapshot(last_included_index: u64, last_included_term: u64) -> Snapshot {
+    Snapshot {
+        last_included_index: LogIndex(last_included_index),
+        last_included_term: TermIndex(last_included_term),
+        members: [ServerId(1), ServerId(2), ServerId(3)].into(),
+        learners: [ServerId(4)].into(),
+        sessions: [(ClientId(Uuid::from_u128(1)), 5)].into(),
+        data: vec![1, 2, 3],
+    }
+}
+
+/// Writes commands out as their decimal digits
+#[derive(Debug)]
+struct DecimalCodec;
+impl CommandCodec<u64> for DecimalCodec {
+    fn encode(&self, command: &u64) -> Result<Vec<u8>, CodecError> {
+        Ok(command.to_string().into_bytes())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<u64, CodecError> {
+        std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| CodecError(format!("not a decimal number: {bytes:?}")))
+    }
+
+    fn format(&self) -> u8 {
+        10
+    }
+}
+
+#[test]
+fn should_read_synced_entries_back_after_reopening() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 2)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(3)));
+    assert_eq!(storage.last_entry_term(), Some(TermIndex(2)));
+    assert_eq!(storage.entry_term(LogIndex(0)), Some(TermIndex(0)));
+    assert_eq!(storage.entry_term(LogIndex(4)), None);
+    assert_eq!(
+        storage.entries(LogIndex(2), 10),
+        vec![entry(2, 1), entry(3, 2)]
+    );
+    assert_eq!(storage.entries(LogIndex(1), 1), vec![entry(1, 1)]);
+}
+
+#[test]
+fn should_stop_a_batch_of_entries_at_the_byte_limit() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage.append((1..=10).map(|index| entry(index, 1)).collect());
+
+    // Each of these entries encodes to 4 bytes
+    assert_eq!(storage.entries_within(LogIndex(1), 10, 13).len(), 3);
+    assert_eq!(storage.entries_within(LogIndex(1), 2, 13).len(), 2);
+    assert_eq!(
+        storage.entries_within(LogIndex(1), 10, usize::MAX).len(),
+        10
+    );
+    // An entry bigger than the limit still goes out on its own
+    assert_eq!(
+        storage.entries_within(LogIndex(4), 10, 1),
+        vec![entry(4, 1)]
+    );
+}
+
+#[test]
+fn should_replace_conflicting_entries_on_disk() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+
+    // Entry 1 is already there and kept, entry 2 conflicts so 2 and 3 go
+    storage
+        .append(vec![entry(1, 1), entry(2, 2)])
+        .sync()
+        .unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(
+        storage.entries(LogIndex(1), 10),
+        vec![entry(1, 1), entry(2, 2)]
+    );
+    assert!(storage.has_entry(LogIndex(2), TermIndex(2)));
+    assert!(!storage.has_entry(LogIndex(3), TermIndex(1)));
+}
+
+#[test]
+fn should_not_persist_entries_before_sync() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage.append(vec![entry(1, 1)]).sync().unwrap();
+    let _ = storage.append(vec![entry(2, 1)]);
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(1)));
+}
+
+#[test]
+fn should_replace_conflicting_entries_synced_before_reopening() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // Entry 2 starts in the file right after the one record replayed on startup
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage.append(vec![entry(2, 2)]).sync().unwrap();
+    storage.append(vec![entry(3, 2)]).sync().unwrap();
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(
+        storage.entries(LogIndex(1), 10),
+        vec![entry(1, 1), entry(2, 2), entry(3, 2)]
+    );
+}
+
+#[test]
+fn should_replace_conflicting_entries_far_into_the_log_after_reopening() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append((1..=200).map(|index| entry(index, 1)).collect())
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // Entry 150 is found from the offset kept for one a few records before it
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage.append(vec![entry(150, 2)]).sync().unwrap();
+    storage.append(vec![entry(151, 2)]).sync().unwrap();
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(151)));
+    assert_eq!(
+        storage.entries(LogIndex(148), 10),
+        vec![entry(148, 1), entry(149, 1), entry(150, 2), entry(151, 2)]
+    );
+}
+
+#[test]
+fn should_cut_a_partially_written_record_off_the_log() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // A crash in the middle of writing the next record leaves its header and part of its entry
+    let log_path = storage_dir.path().join("log");
+    let mut contents = std::fs::read(&log_path).unwrap();
+    let synced_len = contents.len();
+    contents.extend_from_slice(&[8, 0, 0, 0, 3]);
+    std::fs::write(&log_path, contents).unwrap();
+
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    assert_eq!(
+        std::fs::metadata(&log_path).unwrap().len(),
+        synced_len as u64
+    );
+
+    storage.append(vec![entry(3, 1)]).sync().unwrap();
+    drop(storage);
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(3)));
+}
+
+#[test]
+fn should_cut_a_record_whose_length_runs_past_the_end_of_the_file_off() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // A torn header whose length is garbage, far longer than what's left of the file
+    let log_path = storage_dir.path().join("log");
+    let mut contents = std::fs::read(&log_path).unwrap();
+    let synced_len = contents.len();
+    contents.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 3]);
+    std::fs::write(&log_path, contents).unwrap();
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    assert_eq!(
+        std::fs::metadata(&log_path).unwrap().len(),
+        synced_len as u64
+    );
+}
+
+#[test]
+fn should_cut_zeros_a_crash_left_at_the_end_of_the_log_off() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // The file grew before the crash but the next record's bytes never reached the disk
+    let log_path = storage_dir.path().join("log");
+    let mut contents = std::fs::read(&log_path).unwrap();
+    let synced_len = contents.len();
+    contents.extend_from_slice(&[0; 64]);
+    std::fs::write(&log_path, contents).unwrap();
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    assert_eq!(
+        std::fs::metadata(&log_path).unwrap().len(),
+        synced_len as u64
+    );
+}
+
+#[test]
+fn should_stop_replaying_the_log_at_a_record_that_fails_its_checksum() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+
+    // Bit rot in the last record, which is complete so it can't be a write cut short by a crash
+    let log_path = storage_dir.path().join("log");
+    let mut contents = std::fs::read(&log_path).unwrap();
+    *contents.last_mut().unwrap() ^= 0x10;
+    std::fs::write(&log_path, contents).unwrap();
+
+    assert_eq!(
+        DefaultPersistentStorage::<u64>::new(storage_dir.path()).err(),
+        Some(PersistentStorageError::Corrupted)
+    );
+}
+
+#[test]
+fn should_persist_the_term_and_vote_together() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .persist_term_and_vote(TermIndex(4), Some(ServerId(2)))
+        .unwrap();
+    // The vote cast in term 4 stays, a later term starts without one
+    storage.persist_term_and_vote(TermIndex(4), None).unwrap();
+    drop(storage);
+
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.current_term(), TermIndex(4));
+    assert_eq!(storage.vote_for_current_term(), Some(ServerId(2)));
+    storage.persist_term_and_vote(TermIndex(5), None).unwrap();
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.current_term(), TermIndex(5));
+    assert_eq!(storage.vote_for_current_term(), None);
+    assert!(!storage_dir.path().join("election.tmp").exists());
+}
+
+#[test]
+fn should_verify_storage_that_is_sound() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(2))
+        .append((1..=10).map(|index| entry(index, 1 + index / 6)).collect())
+        .sync()
+        .unwrap();
+    storage.install_snapshot(snapshot(4, 1)).sync().unwrap();
+    drop(storage);
+
+    let report = DefaultPersistentStorage::<u64>::verify(storage_dir.path()).unwrap();
+    assert!(report.is_sound(), "{report:?}");
+    assert_eq!(report.records, 6);
+    assert_eq!(report.last_index, Some(LogIndex(10)));
+}
+
+#[test]
+fn should_verify_a_log_damaged_past_what_opening_repairs() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(1))
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+    drop(storage);
+    let log_path = storage_dir.path().join("log");
+    let synced = std::fs::read(&log_path).unwrap();
+
+    // What a crash mid-append leaves is reported, though opening the storage cuts it off
+    let mut contents = synced.clone();
+    contents.extend_from_slice(&[8, 0, 0, 0, 3]);
+    std::fs::write(&log_path, contents).unwrap();
+    let report = DefaultPersistentStorage::<u64>::verify(storage_dir.path()).unwrap();
+    assert_eq!(report.records, 3);
+    assert_eq!(
+        report.problems,
+        vec![IntegrityProblem::IncompleteRecord {
+            offset: synced.len() as u64
+        }]
+    );
+
+    // Bit rot in the second record stops the walk there
+    let mut contents = synced.clone();
+    let second_record = 8 + 8 + u32::from_le_bytes(synced[8..12].try_into().unwrap()) as usize;
+    contents[second_record + 8] ^= 0x01;
+    std::fs::write(&log_path, contents).unwrap();
+    let report = DefaultPersistentStorage::<u64>::verify(storage_dir.path()).unwrap();
+    assert_eq!(report.records, 1);
+    assert_eq!(report.last_index, Some(LogIndex(1)));
+    assert_eq!(
+        report.problems,
+        vec![IntegrityProblem::ChecksumMismatch {
+            offset: second_record as u64
+        }]
+    );
+    assert_eq!(
+        DefaultPersistentStorage::<u64>::new(storage_dir.path()).err(),
+        Some(PersistentStorageError::Corrupted)
+    );
+}
+
+#[test]
+fn should_back_up_the_term_vote_snapshot_and_log() {
+    let storage_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(3))
+        .record_vote(ServerId(2))
+        .append((1..=10).map(|index| entry(index, 1)).collect())
+        .sync()
+        .unwrap();
+    storage.install_snapshot(snapshot(6, 1)).sync().unwrap();
+    // Not synced yet, the backup syncs it first
+    storage.append(vec![entry(11, 3)]);
+
+    let backup_path = backup_dir.path().join("backup");
+    assert_eq!(storage.backup_to(&backup_path), Ok(LogIndex(11)));
+    storage.append(vec![entry(12, 3)]).sync().unwrap();
+    drop(storage);
+
+    let backup = DefaultPersistentStorage::<u64>::new(&backup_path).unwrap();
+    assert_eq!(backup.current_term(), TermIndex(3));
+    assert_eq!(backup.vote_for_current_term(), Some(ServerId(2)));
+    assert_eq!(backup.snapshot(), Some(&snapshot(6, 1)));
+    assert_eq!(
+        backup.entries(LogIndex(7), 10),
+        vec![
+            entry(7, 1),
+            entry(8, 1),
+            entry(9, 1),
+            entry(10, 1),
+            entry(11, 3)
+        ]
+    );
+}
+
+#[test]
+fn should_import_an_export_into_storage_with_another_codec() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(2))
+        .append((1..=5).map(|index| entry(index, 2)).collect())
+        .sync()
+        .unwrap();
+    let exported = bincode::serialize(&StorageExport::read_from(&storage)).unwrap();
+    drop(storage);
+
+    let imported_dir = TempDir::new().unwrap();
+    let mut storage =
+        DefaultPersistentStorage::<u64>::with_codec(imported_dir.path(), Arc::new(DecimalCodec))
+            .unwrap();
+    bincode::deserialize::<StorageExport<u64>>(&exported)
+        .unwrap()
+        .write_to(&mut storage)
+        .unwrap();
+    drop(storage);
+
+    let storage =
+        DefaultPersistentStorage::<u64>::with_codec(imported_dir.path(), Arc::new(DecimalCodec))
+            .unwrap();
+    assert_eq!(storage.current_term(), TermIndex(2));
+    assert_eq!(storage.vote_for_current_term(), None);
+    assert_eq!(
+        storage.entries(LogIndex(1), 10),
+        (1..=5).map(|index| entry(index, 2)).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn should_refuse_an_election_file_that_fails_its_checksum() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .update_term(TermIndex(3))
+        .record_vote(ServerId(2))
+        .sync()
+        .unwrap();
+    drop(storage);
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.current_term(), TermIndex(3));
+    assert_eq!(storage.vote_for_current_term(), Some(ServerId(2)));
+    drop(storage);
+
+    // The term is the first thing after the record's length and checksum
+    let election_path = storage_dir.path().join("election");
+    let mut contents = std::fs::read(&election_path).unwrap();
+    contents[8] ^= 0x01;
+    std::fs::write(&election_path, contents).unwrap();
+
+    assert_eq!(
+        DefaultPersistentStorage::<u64>::new(storage_dir.path()).err(),
+        Some(PersistentStorageError::Corrupted)
+    );
+}
+
+#[test]
+fn should_compact_the_log_file_once_a_snapshot_covers_a_prefix() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append((1..=10).map(|index| entry(index, 1)).collect())
+        .sync()
+        .unwrap();
+    let log_path = storage_dir.path().join("log");
+    let uncompacted_len = std::fs::metadata(&log_path).unwrap().len();
+
+    storage.install_snapshot(snapshot(6, 1)).sync().unwrap();
+    assert!(std::fs::metadata(&log_path).unwrap().len() < uncompacted_len);
+    storage.append(vec![entry(11, 2)]).sync().unwrap();
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.snapshot(), Some(&snapshot(6, 1)));
+    assert_eq!(storage.snapshot_index(), LogIndex(6));
+    assert_eq!(storage.entry_term(LogIndex(5)), None);
+    assert_eq!(storage.entry_term(LogIndex(6)), Some(TermIndex(1)));
+    assert_eq!(storage.entries(LogIndex(3), 10), vec![]);
+    assert_eq!(
+        storage.entries(LogIndex(7), 10),
+        vec![
+            entry(7, 1),
+            entry(8, 1),
+            entry(9, 1),
+            entry(10, 1),
+            entry(11, 2)
+        ]
+    );
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(11)));
+}
+
+#[test]
+fn should_drop_the_whole_log_for_a_snapshot_it_does_not_match() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append(vec![entry(1, 1), entry(2, 1), entry(3, 1)])
+        .sync()
+        .unwrap();
+
+    // The leader's snapshot ends at an entry this log has in another term
+    storage.install_snapshot(snapshot(2, 2)).sync().unwrap();
+    assert_eq!(storage.last_entry_index(), Some(LogIndex(2)));
+    assert_eq!(storage.last_entry_term(), Some(TermIndex(2)));
+    drop(storage);
+
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.entries(LogIndex(3), 10), vec![]);
+    assert!(!storage.has_entry(LogIndex(3), TermIndex(1)));
+    storage.append(vec![entry(3, 2)]).sync().unwrap();
+    assert_eq!(storage.entries(LogIndex(3), 10), vec![entry(3, 2)]);
+}
+
+#[test]
+fn should_keep_as_many_snapshots_as_asked_for() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path())
+        .unwrap()
+        .retain_snapshots(NonZeroUsize::new(2).unwrap());
+    storage
+        .append((1..=9).map(|index| entry(index, 1)).collect())
+        .sync()
+        .unwrap();
+    for last_included_index in [3, 6, 9] {
+        storage
+            .install_snapshot(snapshot(last_included_index, 1))
+            .sync()
+            .unwrap();
+    }
+
+    let kept: Vec<_> = storage
+        .snapshots()
+        .unwrap()
+        .into_iter()
+        .map(|metadata| metadata.last_included_index)
+        .collect();
+    assert_eq!(kept, vec![LogIndex(9), LogIndex(6)]);
+    assert_eq!(
+        storage.read_snapshot(LogIndex(6)).unwrap(),
+        Some(snapshot(6, 1))
+    );
+    assert_eq!(storage.read_snapshot(LogIndex(3)).unwrap(), None);
+    drop(storage);
+
+    // Without asking for more, only the latest is kept from the next snapshot on
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.snapshots().unwrap().len(), 2);
+    storage
+        .append(vec![entry(10, 1)])
+        .install_snapshot(snapshot(10, 1))
+        .sync()
+        .unwrap();
+    let kept = storage.snapshots().unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].last_included_index, LogIndex(10));
+    assert_eq!(kept[0].members, snapshot(10, 1).members);
+}
+
+#[test]
+fn should_compact_entries_left_over_from_a_crash_before_the_log_file_was_rewritten() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    storage
+        .append((1..=5).map(|index| entry(index, 1)).collect())
+        .sync()
+        .unwrap();
+    let log_path = storage_dir.path().join("log");
+    let uncompacted_log = std::fs::read(&log_path).unwrap();
+    storage.install_snapshot(snapshot(3, 1)).sync().unwrap();
+    drop(storage);
+
+    // The snapshot made it to disk, the compacted log file didn't
+    std::fs::write(&log_path, uncompacted_log).unwrap();
+
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.snapshot_index(), LogIndex(3));
+    assert_eq!(
+        storage.entries(LogIndex(4), 10),
+        vec![entry(4, 1), entry(5, 1)]
+    );
+    storage.append(vec![entry(6, 1)]).sync().unwrap();
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(
+        storage.entries(LogIndex(4), 10),
+        vec![entry(4, 1), entry(5, 1), entry(6, 1)]
+    );
+}
+
+#[test]
+fn should_encode_commands_in_the_log_file_with_the_storage_codec() {
+    let storage_dir = TempDir::new().unwrap();
+    let session = ClientSession {
+        client_id: ClientId(Uuid::from_u128(7)),
+        sequence: 3,
+    };
+    let entries = vec![
+        entry(1, 1),
+        LogEntry {
+            index: LogIndex(2),
+            term: TermIndex(1),
+            command: LogEntryCommand::SessionCommand(session, 12345),
+        },
+        LogEntry {
+            index: LogIndex(3),
+            term: TermIndex(2),
+            command: LogEntryCommand::MembershipChange(MembershipChange::AddServer(ServerId(4))),
+        },
+        LogEntry {
+            index: LogIndex(4),
+            term: TermIndex(2),
+            command: LogEntryCommand::NoOp,
+        },
+    ];
+    let mut storage =
+        DefaultPersistentStorage::<u64>::with_codec(storage_dir.path(), Arc::new(DecimalCodec))
+            .unwrap();
+    storage.append(entries.clone()).sync().unwrap();
+    drop(storage);
+
+    let log_file = std::fs::read(storage_dir.path().join("log")).unwrap();
+    assert!(log_file.windows(5).any(|bytes| bytes == b"12345"));
+
+    let storage =
+        DefaultPersistentStorage::<u64>::with_codec(storage_dir.path(), Arc::new(DecimalCodec))
+            .unwrap();
+    assert_eq!(storage.entries(LogIndex(1), 10), entries);
+}
+
+#[test]
+fn should_count_storage_as_full_once_the_log_file_takes_up_its_quota() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage = DefaultPersistentStorage::<u64>::new(storage_dir.path())
+        .unwrap()
+        .byte_quota(16);
+    assert!(!storage.is_full());
+    storage.append(vec![entry(1, 1)]).sync().unwrap();
+    assert!(storage.is_full());
+    assert_eq!(
+        storage.append(vec![entry(2, 1)]).sync(),
+        Err(PersistentStorageError::StorageFull)
+    );
+    drop(storage);
+
+    let storage = DefaultPersistentStorage::<u64>::new(storage_dir.path()).unwrap();
+    assert_eq!(storage.entries(LogIndex(1), 10), vec![entry(1, 1)]);
+}
+
+#[test]
+fn should_refuse_a_log_file_written_in_another_format() {
+    let storage_dir = TempDir::new().unwrap();
+    let mut storage =
+        DefaultPersistentStorage::<u64>::with_codec(storage_dir.path(), Arc::new(DecimalCodec))
+            .unwrap();
+    storage.append(vec![entry(1, 1)]).sync().unwrap();
+    drop(storage);
+
+    // Bincode would read the decimal digits as some other command rather than fail
+    assert_eq!(
+        DefaultPersistentStorage::<u64>::new(storage_dir.path()).err(),
+        Some(PersistentStorageError::FormatMismatch)
+    );
+}
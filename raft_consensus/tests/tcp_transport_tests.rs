@@ -0,0 +1,787 @@
+/// Tests the TCP transport carrying messages between servers
+use raft_consensus::rpc_messages::{
+    AppendEntries, AppendEntriesAck, ForwardProposals, ReplyTo, Request, RpcMessage,
+};
+use raft_consensus::{
+    ApplicationThatNeedsConsensus, BincodeWireCodec, ClientId, ClientProposal, ClientSession,
+    DefaultPersistentStorage, LogEntry, LogEntryCommand, LogIndex, PeerConnection, PeerLiveness,
+    ProposalError, RaftConfig, RaftMetrics, RaftNodeBuilder, RaftTransportConnector, RateLimit,
+    ServerId, Snapshot, TcpTransport, TermIndex, WireCodec,
+};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::num::{NonZeroU32, NonZeroU64};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+/// Listeners for `count` servers, numbered from 1, on ports of their own
+fn listeners(count: u64) -> Vec<(ServerId, TcpListener, SocketAddr)> {
+    (1..=count)
+        .map(|id| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            (ServerId(id), listener, addr)
+        })
+        .collect()
+}
+
+/// Transports for a cluster of `count` servers, each knowing where the others listen
+fn cluster(count: u64) -> Vec<(ServerId, TcpTransport<String>)> {
+    let listeners = listeners(count);
+    let addrs: HashMap<ServerId, SocketAddr> = listeners
+        .iter()
+        .map(|(server_id, _, addr)| (*server_id, *addr))
+        .collect();
+    listeners
+        .into_iter()
+        .map(|(server_id, listener, _)| {
+            let mut peers = addrs.clone();
+            let _ = peers.remove(&server_id);
+            (server_id, TcpTransport::new(listener, peers).unwrap())
+        })
+        .collect()
+}
+
+fn next_message(transport: &mut TcpTransport<String>) -> Option<RpcMessage<String>> {
+    transport
+        .wait_for_next_incoming_message(Duration::from_secs(5))
+        .unwrap()
+}
+
+#[test]
+fn should_carry_requests_to_peers_and_route_replies_back() {
+    let mut transports = cluster(2);
+    let (_, mut second) = transports.pop().unwrap();
+    let (_, mut first) = transports.pop().unwrap();
+    let append_entries = Request::AppendEntries(AppendEntries {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(3),
+        prev_log_term: TermIndex(2),
+        prev_log_index: LogIndex(4),
+        entries: vec![
+            LogEntry {
+                index: LogIndex(5),
+                term: TermIndex(3),
+                command: LogEntryCommand::Application("set x".to_string()),
+            },
+            LogEntry {
+                index: LogIndex(6),
+                term: TermIndex(3),
+                command: LogEntryCommand::NoOp,
+            },
+        ]
+        .into(),
+        leader_commit: LogIndex(4),
+    });
+    first
+        .enqueue_outgoing_request(append_entries.clone())
+        .unwrap();
+    assert_eq!(
+        next_message(&mut second),
+        Some(RpcMessage::Request(append_entries.clone()))
+    );
+
+    let ack = ReplyTo::AppendEntries(AppendEntriesAck {
+        request_id: append_entries.request_id(),
+        from: ServerId(2),
+        to: ServerId(1),
+        term: TermIndex(3),
+        success: true,
+        conflict: None,
+    });
+    second.enqueue_reply(ack.clone()).unwrap();
+    assert_eq!(next_message(&mut first), Some(RpcMessage::Reply(ack)));
+
+    let forward_proposals = Request::ForwardProposals(ForwardProposals {
+        request_id: Uuid::new_v4(),
+        from: ServerId(2),
+        to: ServerId(1),
+        term: TermIndex(3),
+        proposals: vec![ClientProposal {
+            command: "set y".to_string(),
+            session: Some(ClientSession {
+                client_id: ClientId(Uuid::new_v4()),
+                sequence: 1,
+            }),
+        }],
+        hops: 1,
+    });
+    second
+        .enqueue_outgoing_request(forward_proposals.clone())
+        .unwrap();
+    assert_eq!(
+        next_message(&mut first),
+        Some(RpcMessage::Request(forward_proposals))
+    );
+}
+
+#[test]
+fn should_retry_messages_for_a_peer_that_comes_up_shortly() {
+    let mut listeners = listeners(2);
+    let (_, second_listener, second_addr) = listeners.pop().unwrap();
+    let (_, first_listener, _) = listeners.pop().unwrap();
+    // Nothing listens on the second server's port until its transport starts
+    drop(second_listener);
+    let mut first =
+        TcpTransport::<String>::new(first_listener, HashMap::from([(ServerId(2), second_addr)]))
+            .unwrap();
+    let ack = ack(1);
+    first.enqueue_reply(ack.clone()).unwrap();
+    // Gives the transport time to fail to connect, and back off
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut second =
+        TcpTransport::<String>::new(TcpListener::bind(second_addr).unwrap(), HashMap::new())
+            .unwrap();
+    assert_eq!(next_message(&mut second), Some(RpcMessage::Reply(ack)));
+}
+
+/// Binds `addr` again once the transport that listened on it let go of it
+fn rebind(addr: SocketAddr) -> TcpListener {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match TcpListener::bind(addr) {
+            Ok(listener) => return listener,
+            Err(error) => assert!(
+                Instant::now() < deadline,
+                "{} stayed taken: {}",
+                addr,
+                error
+            ),
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Waits for `metrics` to show `connection` to the second server
+fn wait_for_connection(metrics: &RaftMetrics, connection: PeerConnection) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while metrics.peer_connections().get(&ServerId(2)) != Some(&connection) {
+        assert!(
+            Instant::now() < deadline,
+            "connection stayed {:?} rather than {:?}",
+            metrics.peer_connections().get(&ServerId(2)),
+            connection
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn ack(term: u64) -> ReplyTo {
+    ReplyTo::AppendEntries(AppendEntriesAck {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(term),
+        success: true,
+        conflict: None,
+    })
+}
+
+#[test]
+fn should_reconnect_in_the_background_and_send_what_was_held_meanwhile() {
+    let mut listeners = listeners(2);
+    let (_, second_listener, second_addr) = listeners.pop().unwrap();
+    let (_, first_listener, _) = listeners.pop().unwrap();
+    let metrics = RaftMetrics::new();
+    let mut first =
+        TcpTransport::<String>::new(first_listener, HashMap::from([(ServerId(2), second_addr)]))
+            .unwrap();
+    first.attach_metrics(metrics.clone());
+    let second = TcpTransport::<String>::new(second_listener, HashMap::new()).unwrap();
+    // Without a message to send yet
+    wait_for_connection(
+        &metrics,
+        PeerConnection {
+            connected: true,
+            buffered_messages: 0,
+        },
+    );
+
+    drop(second);
+    wait_for_connection(
+        &metrics,
+        PeerConnection {
+            connected: false,
+            buffered_messages: 0,
+        },
+    );
+    let acks: Vec<ReplyTo> = (1..=3).map(ack).collect();
+    for ack in &acks {
+        first.enqueue_reply(ack.clone()).unwrap();
+    }
+    wait_for_connection(
+        &metrics,
+        PeerConnection {
+            connected: false,
+            buffered_messages: 3,
+        },
+    );
+
+    let mut second = TcpTransport::<String>::new(rebind(second_addr), HashMap::new()).unwrap();
+    for ack in acks {
+        assert_eq!(next_message(&mut second), Some(RpcMessage::Reply(ack)));
+    }
+    wait_for_connection(
+        &metrics,
+        PeerConnection {
+            connected: true,
+            buffered_messages: 0,
+        },
+    );
+    assert_eq!(metrics.rpc_stats().dropped, 0);
+}
+
+#[test]
+fn should_drop_the_oldest_messages_held_for_a_peer_past_8_mib() {
+    let mut listeners = listeners(2);
+    let (_, second_listener, second_addr) = listeners.pop().unwrap();
+    let (_, first_listener, _) = listeners.pop().unwrap();
+    drop(second_listener);
+    let metrics = RaftMetrics::new();
+    let mut first =
+        TcpTransport::<String>::new(first_listener, HashMap::from([(ServerId(2), second_addr)]))
+            .unwrap();
+    first.attach_metrics(metrics.clone());
+
+    // A megabyte each, four more than fit
+    let append_entries = |term| {
+        Request::AppendEntries(AppendEntries {
+            request_id: Uuid::new_v4(),
+            from: ServerId(1),
+            to: ServerId(2),
+            term: TermIndex(term),
+            prev_log_term: TermIndex(0),
+            prev_log_index: LogIndex(0),
+            entries: vec![LogEntry {
+                index: LogIndex(1),
+                term: TermIndex(term),
+                command: LogEntryCommand::Application("x".repeat(1_000_000)),
+            }]
+            .into(),
+            leader_commit: LogIndex(0),
+        })
+    };
+    for term in 1..=12 {
+        first
+            .enqueue_outgoing_request(append_entries(term))
+            .unwrap();
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while metrics.rpc_stats().dropped < 4 {
+        assert!(Instant::now() < deadline, "messages were never dropped");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    wait_for_connection(
+        &metrics,
+        PeerConnection {
+            connected: false,
+            buffered_messages: 8,
+        },
+    );
+    assert_eq!(metrics.rpc_stats().dropped, 4);
+
+    let mut second = TcpTransport::<String>::new(rebind(second_addr), HashMap::new()).unwrap();
+    for term in 5..=12 {
+        let delivered = next_message(&mut second).expect("every held message is sent");
+        assert!(matches!(
+            delivered,
+            RpcMessage::Request(Request::AppendEntries(AppendEntries { term: delivered_term, .. }))
+                if delivered_term == TermIndex(term)
+        ));
+    }
+}
+
+#[test]
+fn should_count_messages_too_big_for_a_frame_as_dropped() {
+    let mut listeners = listeners(2);
+    let (_, second_listener, second_addr) = listeners.pop().unwrap();
+    let (_, first_listener, _) = listeners.pop().unwrap();
+    let metrics = RaftMetrics::new();
+    let mut first =
+        TcpTransport::<String>::new(first_listener, HashMap::from([(ServerId(2), second_addr)]))
+            .unwrap()
+            .max_message_size(1024);
+    first.attach_metrics(metrics.clone());
+    let mut second = TcpTransport::<String>::new(second_listener, HashMap::new()).unwrap();
+
+    first
+        .enqueue_outgoing_request(Request::AppendEntries(AppendEntries {
+            request_id: Uuid::new_v4(),
+            from: ServerId(1),
+            to: ServerId(2),
+            term: TermIndex(1),
+            prev_log_term: TermIndex(0),
+            prev_log_index: LogIndex(0),
+            entries: vec![LogEntry {
+                index: LogIndex(1),
+                term: TermIndex(1),
+                command: LogEntryCommand::Application("x".repeat(2048)),
+            }]
+            .into(),
+            leader_commit: LogIndex(0),
+        }))
+        .unwrap();
+
+    assert_eq!(metrics.rpc_stats().dropped, 1);
+    assert_eq!(
+        second
+            .wait_for_next_incoming_message(Duration::from_millis(200))
+            .unwrap(),
+        None
+    );
+}
+
+/// Reads whatever `stream` has left, true if the server closed it
+fn closed(stream: &mut TcpStream) -> bool {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut rest = Vec::new();
+    // A reset connection is closed too
+    stream
+        .read_to_end(&mut rest)
+        .map_or(true, |_| rest.is_empty())
+}
+
+/// A transport sending to two peers, and the peers' own transports
+fn sender_and_two_peers() -> (
+    TcpTransport<String>,
+    TcpTransport<String>,
+    TcpTransport<String>,
+) {
+    let mut listeners = listeners(3);
+    let (_, third_listener, third_addr) = listeners.pop().unwrap();
+    let (_, second_listener, second_addr) = listeners.pop().unwrap();
+    let (_, first_listener, _) = listeners.pop().unwrap();
+    let first = TcpTransport::new(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr), (ServerId(3), third_addr)]),
+    )
+    .unwrap();
+    let second = TcpTransport::new(second_listener, HashMap::new()).unwrap();
+    let third = TcpTransport::new(third_listener, HashMap::new()).unwrap();
+    (first, second, third)
+}
+
+/// Waits for `transport` to receive `count` messages
+fn receive(transport: &mut TcpTransport<String>, count: usize) {
+    for _ in 0..count {
+        assert!(next_message(transport).is_some(), "every message is sent");
+    }
+}
+
+#[test]
+fn should_hold_messages_to_a_peer_to_its_rate_limit() {
+    let (first, mut second, mut third) = sender_and_two_peers();
+    let mut first = first
+        .rate_limit(RateLimit::default().messages_per_second(NonZeroU32::new(10).unwrap()))
+        .peer_rate_limit(ServerId(3), RateLimit::default());
+    let started = Instant::now();
+    for term in 1..=30 {
+        first.enqueue_reply(ack(term)).unwrap();
+        let mut to_third = ack(term);
+        if let ReplyTo::AppendEntries(ack) = &mut to_third {
+            ack.to = ServerId(3);
+        }
+        first.enqueue_reply(to_third).unwrap();
+    }
+
+    receive(&mut third, 30);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    // A burst of 10, then 10 a second
+    receive(&mut second, 30);
+    assert!(started.elapsed() >= Duration::from_millis(1500));
+}
+
+#[test]
+fn should_spread_large_messages_out_to_a_peer_over_its_bytes_per_second() {
+    let (first, mut second, _third) = sender_and_two_peers();
+    let mut first = first.peer_rate_limit(
+        ServerId(2),
+        RateLimit::default().bytes_per_second(NonZeroU64::new(400_000).unwrap()),
+    );
+    let started = Instant::now();
+    for term in 1..=10 {
+        let request = Request::ForwardProposals(ForwardProposals {
+            request_id: Uuid::new_v4(),
+            from: ServerId(1),
+            to: ServerId(2),
+            term: TermIndex(term),
+            proposals: vec![ClientProposal {
+                command: "x".repeat(100_000),
+                session: None,
+            }],
+            hops: 1,
+        });
+        first.enqueue_outgoing_request(request).unwrap();
+    }
+
+    // Four in a burst, then four a second
+    receive(&mut second, 10);
+    assert!(started.elapsed() >= Duration::from_millis(1200));
+}
+
+#[test]
+fn should_offer_its_protocol_versions_and_frame_messages_in_the_one_picked() {
+    let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut transport = TcpTransport::<String>::new(
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+        HashMap::from([(ServerId(2), peer.local_addr().unwrap())]),
+    )
+    .unwrap();
+    let ack = ReplyTo::AppendEntries(AppendEntriesAck {
+        request_id: Uuid::new_v4(),
+        from: ServerId(1),
+        to: ServerId(2),
+        term: TermIndex(1),
+        success: true,
+        conflict: None,
+    });
+    transport.enqueue_reply(ack.clone()).unwrap();
+
+    let (mut stream, _) = peer.accept().unwrap();
+    let mut handshake = [0; 6];
+    stream.read_exact(&mut handshake).unwrap();
+    // Magic, then the oldest and newest versions the transport speaks
+    assert_eq!(&handshake, b"RAFT\x01\x02");
+    // A peer of an older build, it only speaks version 1
+    stream.write_all(&[1]).unwrap();
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).unwrap();
+    assert_eq!(header[0], 1);
+    let mut body = vec![0; u32::from_le_bytes(header[1..].try_into().unwrap()) as usize];
+    stream.read_exact(&mut body).unwrap();
+    assert_eq!(
+        BincodeWireCodec::<String>::new().decode(&body),
+        Ok(RpcMessage::Reply(ack))
+    );
+    // Version 1 has no keepalives
+    stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    assert!(stream.read(&mut header).is_err());
+}
+
+#[test]
+fn should_refuse_peers_speaking_none_of_its_protocol_versions() {
+    let mut transport =
+        TcpTransport::<String>::new(TcpListener::bind("127.0.0.1:0").unwrap(), HashMap::new())
+            .unwrap();
+
+    // A newer server that dropped support for versions 1 and 2
+    let mut newer = TcpStream::connect(transport.local_addr()).unwrap();
+    newer.write_all(b"RAFT\x03\x04").unwrap();
+    let mut version = [0xff; 1];
+    newer.read_exact(&mut version).unwrap();
+    assert_eq!(version, [0]);
+    assert!(closed(&mut newer));
+
+    // An older server, framing messages without a version or handshake
+    let body = BincodeWireCodec::new()
+        .encode(&RpcMessage::<String>::Reply(ReplyTo::AppendEntries(
+            AppendEntriesAck {
+                request_id: Uuid::new_v4(),
+                from: ServerId(2),
+                to: ServerId(1),
+                term: TermIndex(1),
+                success: true,
+                conflict: None,
+            },
+        )))
+        .unwrap();
+    let mut older = TcpStream::connect(transport.local_addr()).unwrap();
+    older.write_all(&(body.len() as u32).to_le_bytes()).unwrap();
+    older.write_all(&body).unwrap();
+    assert!(closed(&mut older));
+    assert_eq!(
+        transport
+            .wait_for_next_incoming_message(Duration::from_millis(200))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn should_hear_from_peers_through_keepalives_without_any_messages() {
+    let mut cluster = cluster(2);
+    let (_, second) = cluster.pop().unwrap();
+    let (_, first) = cluster.pop().unwrap();
+    let health = first.peer_health();
+    assert_eq!(health.liveness(ServerId(3)), None);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let first_heard = loop {
+        match health.liveness(ServerId(2)).unwrap() {
+            PeerLiveness {
+                alive: true,
+                last_heard: Some(heard),
+            } => break heard,
+            _ => assert!(Instant::now() < deadline, "peer was never alive"),
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // Heard from again once it answers a keepalive
+    while health.liveness(ServerId(2)).unwrap().last_heard == Some(first_heard) {
+        assert!(Instant::now() < deadline, "peer never answered a keepalive");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(health.alive(), HashSet::from([ServerId(2)]));
+    assert!(second.peer_health().alive().contains(&ServerId(1)));
+}
+
+#[test]
+fn should_close_the_connection_to_a_peer_that_stops_answering_keepalives() {
+    let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+    let transport = TcpTransport::<String>::new(
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+        HashMap::from([(ServerId(2), peer.local_addr().unwrap())]),
+    )
+    .unwrap();
+    let health = transport.peer_health();
+
+    let (mut stream, _) = peer.accept().unwrap();
+    let mut handshake = [0; 6];
+    stream.read_exact(&mut handshake).unwrap();
+    stream.write_all(&[2]).unwrap();
+    let answered_at = Instant::now();
+    // The peer hangs without closing the connection, nor can it be reached again
+    drop(peer);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut keepalive = [0xff; 5];
+    stream.read_exact(&mut keepalive).unwrap();
+    assert_eq!(keepalive, [2, 0, 0, 0, 0]);
+    assert!(health.alive().contains(&ServerId(2)));
+
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest).unwrap();
+    assert!(answered_at.elapsed() >= Duration::from_secs(2));
+    assert!(rest.iter().all(|byte| *byte == 0 || *byte == 2));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while health.liveness(ServerId(2)).unwrap().alive {
+        assert!(Instant::now() < deadline, "peer was never taken as dead");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(health.liveness(ServerId(2)).unwrap().last_heard.is_some());
+}
+
+#[test]
+fn should_close_the_connection_to_a_peer_that_stops_reading() {
+    let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut transport = TcpTransport::<String>::new(
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+        HashMap::from([(ServerId(2), peer.local_addr().unwrap())]),
+    )
+    .unwrap();
+    let health = transport.peer_health();
+
+    let (mut stream, _) = peer.accept().unwrap();
+    let mut handshake = [0; 6];
+    stream.read_exact(&mut handshake).unwrap();
+    stream.write_all(&[2]).unwrap();
+    // The peer hangs without reading anything more, nor can it be reached again
+    drop(peer);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !health.alive().contains(&ServerId(2)) {
+        assert!(Instant::now() < deadline, "peer was never alive");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Far more than the connection's buffers take, writing them blocks once they're full
+    for term in 1..=24 {
+        transport
+            .enqueue_outgoing_request(Request::AppendEntries(AppendEntries {
+                request_id: Uuid::new_v4(),
+                from: ServerId(1),
+                to: ServerId(2),
+                term: TermIndex(term),
+                prev_log_term: TermIndex(0),
+                prev_log_index: LogIndex(0),
+                entries: vec![LogEntry {
+                    index: LogIndex(1),
+                    term: TermIndex(term),
+                    command: LogEntryCommand::Application("x".repeat(1_000_000)),
+                }]
+                .into(),
+                leader_commit: LogIndex(0),
+            }))
+            .unwrap();
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while health.liveness(ServerId(2)).unwrap().alive {
+        assert!(Instant::now() < deadline, "peer was never taken as dead");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    drop(stream);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn should_let_go_of_connections_peers_closed() {
+    let transport =
+        TcpTransport::<String>::new(TcpListener::bind("127.0.0.1:0").unwrap(), HashMap::new())
+            .unwrap();
+    let open_files = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+    let before = open_files();
+
+    for _ in 0..200 {
+        let mut stream = TcpStream::connect(transport.local_addr()).unwrap();
+        // Refused for not starting with a handshake
+        stream.write_all(b"hello!").unwrap();
+    }
+
+    // Other tests open files of their own meanwhile, but nowhere near one per connection
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while open_files() > before + 50 {
+        assert!(
+            Instant::now() < deadline,
+            "connections were never let go of"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Application that only keeps track of how far it got
+struct TrackingApplication {
+    last_applied_index: LogIndex,
+}
+impl ApplicationThatNeedsConsensus for TrackingApplication {
+    type Command = String;
+    type Output = ();
+    type Error = ();
+
+    fn apply(&mut self, log_index: LogIndex, _command: String) -> Result<(), ()> {
+        self.last_applied_index = log_index;
+        Ok(())
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied_index
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.last_applied_index = snapshot.last_included_index;
+    }
+}
+
+#[test]
+fn should_commit_proposals_in_a_cluster_talking_over_tcp() {
+    let storage_dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+    let nodes: Vec<_> = cluster(3)
+        .into_iter()
+        .zip(&storage_dirs)
+        .map(|((server_id, transport), storage_dir)| {
+            let peers: HashSet<ServerId> = (1..=3)
+                .map(ServerId)
+                .filter(|peer| *peer != server_id)
+                .collect();
+            RaftNodeBuilder::new(
+                server_id,
+                TrackingApplication {
+                    last_applied_index: LogIndex(0),
+                },
+            )
+            .peers(peers)
+            .storage(DefaultPersistentStorage::new(storage_dir.path()).unwrap())
+            .transport(transport)
+            .config(RaftConfig::builder().build().unwrap())
+            .start()
+            .unwrap()
+        })
+        .collect();
+
+    // Proposals fail until there's a leader, followers forward theirs to it over TCP
+    let deadline = Instant::now() + Duration::from_secs(20);
+    'proposing: loop {
+        assert!(Instant::now() < deadline, "no proposal was ever committed");
+        for node in &nodes {
+            let committed = node
+                .propose("set x".to_string())
+                .ok()
+                .and_then(|pending| pending.wait_timeout(Duration::from_secs(1)));
+            if matches!(committed, Some(Ok(_))) {
+                break 'proposing;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    for node in nodes {
+        assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    }
+}
+
+#[test]
+fn should_split_what_the_leader_sends_to_fit_the_transports_max_message_size() {
+    let storage_dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+    let nodes: Vec<_> = cluster(3)
+        .into_iter()
+        .zip(&storage_dirs)
+        .map(|((server_id, transport), storage_dir)| {
+            let peers: HashSet<ServerId> = (1..=3)
+                .map(ServerId)
+                .filter(|peer| *peer != server_id)
+                .collect();
+            RaftNodeBuilder::new(
+                server_id,
+                TrackingApplication {
+                    last_applied_index: LogIndex(0),
+                },
+            )
+            .peers(peers)
+            .storage(DefaultPersistentStorage::new(storage_dir.path()).unwrap())
+            .transport(transport.max_message_size(8 * 1024))
+            .config(RaftConfig::builder().build().unwrap())
+            .start()
+            .unwrap()
+        })
+        .collect();
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let leader = 'electing: loop {
+        assert!(Instant::now() < deadline, "no leader was ever elected");
+        for node in &nodes {
+            let committed = node
+                .propose("set x".to_string())
+                .ok()
+                .and_then(|pending| pending.wait_timeout(Duration::from_secs(1)));
+            if matches!(committed, Some(Ok(_))) {
+                break 'electing node;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    // Forty kilobytes at once, they'd make an AppendEntries five times too big to send
+    let pending: Vec<_> = (0..40)
+        .map(|_| leader.propose("x".repeat(1000)).unwrap())
+        .collect();
+    // Well before batches that went unsent could time out and shrink
+    let deadline = Instant::now() + Duration::from_secs(2);
+    for pending in pending {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        assert!(matches!(pending.wait_timeout(wait), Some(Ok(_))));
+    }
+
+    // Half the transport's limit is left for the rest of the message and the codec
+    assert!(matches!(
+        leader.propose("x".repeat(4 * 1024)),
+        Err(ProposalError::TooLarge)
+    ));
+    assert!(matches!(
+        leader.propose_many(vec!["x".to_string(), "x".repeat(4 * 1024)]),
+        Err(ProposalError::TooLarge)
+    ));
+    for node in nodes {
+        assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    }
+}
@@ -0,0 +1,303 @@
+/// Tests the TCP transport with its connections over TLS
+use raft_consensus::rpc_messages::{ReplyTo, Request, RpcMessage, TimeoutNow, TimeoutNowAck};
+use raft_consensus::rustls::pki_types::{PrivateKeyDer, ServerName};
+use raft_consensus::{
+    BincodeWireCodec, RaftMetrics, RaftTransportConnector, ServerId, TcpTransport, TermIndex,
+    TlsConfig,
+};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// A certificate authority, able to sign certificates for the cluster's servers
+struct Authority {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+}
+impl Authority {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        Authority {
+            cert: params.self_signed(&key).unwrap(),
+            key,
+        }
+    }
+
+    /// Mutual TLS for a server with a certificate for `names` signed by this authority
+    fn tls_config(&self, names: &[&str]) -> TlsConfig {
+        let names = names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(names)
+            .unwrap()
+            .signed_by(&key, &self.cert, &self.key)
+            .unwrap();
+        TlsConfig::mutual(
+            self.cert.der().clone(),
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+        )
+        .unwrap()
+    }
+}
+
+fn listener() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+fn start(
+    listener: TcpListener,
+    peers: HashMap<ServerId, SocketAddr>,
+    tls: TlsConfig,
+) -> TcpTransport<String> {
+    TcpTransport::with_tls(listener, peers, Arc::new(BincodeWireCodec::new()), tls).unwrap()
+}
+
+fn timeout_now(from: u64, to: u64) -> Request<String> {
+    Request::TimeoutNow(TimeoutNow {
+        request_id: Uuid::new_v4(),
+        from: ServerId(from),
+        to: ServerId(to),
+        term: TermIndex(2),
+    })
+}
+
+fn next_message(
+    transport: &mut TcpTransport<String>,
+    max_wait: Duration,
+) -> Option<RpcMessage<String>> {
+    transport.wait_for_next_incoming_message(max_wait).unwrap()
+}
+
+#[test]
+fn should_carry_requests_and_replies_over_mutual_tls() {
+    let authority = Authority::new();
+    let (first_listener, first_addr) = listener();
+    let (second_listener, second_addr) = listener();
+    let mut first = start(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr)]),
+        authority.tls_config(&["127.0.0.1"]),
+    );
+    // Certificates for the servers' addresses, which is what peers are checked against by default
+    let mut second = start(
+        second_listener,
+        HashMap::from([(ServerId(1), first_addr)]),
+        authority.tls_config(&["127.0.0.1"]),
+    );
+
+    let request = timeout_now(1, 2);
+    first.enqueue_outgoing_request(request.clone()).unwrap();
+    assert_eq!(
+        next_message(&mut second, Duration::from_secs(5)),
+        Some(RpcMessage::Request(request.clone()))
+    );
+    let reply = ReplyTo::TimeoutNow(TimeoutNowAck {
+        request_id: request.request_id(),
+        from: ServerId(2),
+        to: ServerId(1),
+        term: TermIndex(2),
+    });
+    second.enqueue_reply(reply.clone()).unwrap();
+    assert_eq!(
+        next_message(&mut first, Duration::from_secs(5)),
+        Some(RpcMessage::Reply(reply))
+    );
+}
+
+#[test]
+fn should_ask_peers_for_their_certificate_by_the_name_they_are_given() {
+    let authority = Authority::new();
+    let (first_listener, _) = listener();
+    let (second_listener, second_addr) = listener();
+    let mut first = start(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr)]),
+        authority.tls_config(&["raft-1.internal"]).server_name(
+            ServerId(2),
+            ServerName::try_from("raft-2.internal").unwrap(),
+        ),
+    );
+    let mut second = start(
+        second_listener,
+        HashMap::new(),
+        authority.tls_config(&["raft-2.internal"]),
+    );
+
+    let request = timeout_now(1, 2);
+    first.enqueue_outgoing_request(request.clone()).unwrap();
+    assert_eq!(
+        next_message(&mut second, Duration::from_secs(5)),
+        Some(RpcMessage::Request(request))
+    );
+}
+
+#[test]
+fn should_not_talk_to_peers_with_certificates_from_another_authority() {
+    let (first_listener, _) = listener();
+    let (second_listener, second_addr) = listener();
+    let mut first = start(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr)]),
+        Authority::new().tls_config(&["127.0.0.1"]),
+    );
+    let mut second = start(
+        second_listener,
+        HashMap::new(),
+        Authority::new().tls_config(&["127.0.0.1"]),
+    );
+
+    first.enqueue_outgoing_request(timeout_now(1, 2)).unwrap();
+    assert_eq!(next_message(&mut second, Duration::from_millis(500)), None);
+}
+
+#[test]
+fn should_refuse_plain_tcp_from_peers_unless_tls_is_optional() {
+    let authority = Authority::new();
+    for required in [true, false] {
+        let (first_listener, _) = listener();
+        let (second_listener, second_addr) = listener();
+        let mut first = TcpTransport::<String>::new(
+            first_listener,
+            HashMap::from([(ServerId(2), second_addr)]),
+        )
+        .unwrap();
+        let mut second = start(
+            second_listener,
+            HashMap::new(),
+            authority.tls_config(&["127.0.0.1"]).required(required),
+        );
+
+        let request = timeout_now(1, 2);
+        first.enqueue_outgoing_request(request.clone()).unwrap();
+        let delivered = next_message(&mut second, Duration::from_millis(500));
+        if required {
+            assert_eq!(delivered, None);
+        } else {
+            assert_eq!(delivered, Some(RpcMessage::Request(request)));
+        }
+    }
+}
+
+#[test]
+fn should_find_out_a_peer_closed_the_connection_and_reconnect_once_it_is_back() {
+    let authority = Authority::new();
+    let (first_listener, _) = listener();
+    let (second_listener, second_addr) = listener();
+    let metrics = RaftMetrics::new();
+    let mut first = start(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr)]),
+        authority.tls_config(&["127.0.0.1"]),
+    );
+    first.attach_metrics(metrics.clone());
+    let second = start(
+        second_listener,
+        HashMap::new(),
+        authority.tls_config(&["127.0.0.1"]),
+    );
+    let wait_for = |connected| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while metrics
+            .peer_connections()
+            .get(&ServerId(2))
+            .map(|connection| connection.connected)
+            != Some(connected)
+        {
+            assert!(
+                Instant::now() < deadline,
+                "connected never became {}",
+                connected
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+    wait_for(true);
+
+    drop(second);
+    wait_for(false);
+    // Once the dropped transport let go of the port
+    let second_listener = (0..500)
+        .find_map(|_| {
+            let bound = TcpListener::bind(second_addr).ok();
+            if bound.is_none() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            bound
+        })
+        .unwrap();
+    let mut second = start(
+        second_listener,
+        HashMap::new(),
+        authority.tls_config(&["127.0.0.1"]),
+    );
+    let request = timeout_now(1, 2);
+    first.enqueue_outgoing_request(request.clone()).unwrap();
+    assert_eq!(
+        next_message(&mut second, Duration::from_secs(5)),
+        Some(RpcMessage::Request(request))
+    );
+}
+
+fn name(name: &'static str) -> ServerName<'static> {
+    ServerName::try_from(name).unwrap()
+}
+
+/// A second server authenticating its peers, the first and third, by name, with a transport for
+/// the first connecting to it with a certificate for `first_names`
+fn authenticating_pair(first_names: &[&str]) -> (TcpTransport<String>, TcpTransport<String>) {
+    let authority = Authority::new();
+    let (first_listener, first_addr) = listener();
+    let (second_listener, second_addr) = listener();
+    let (_, third_addr) = listener();
+    let first = start(
+        first_listener,
+        HashMap::from([(ServerId(2), second_addr)]),
+        authority
+            .tls_config(first_names)
+            .server_name(ServerId(2), name("raft-2.internal")),
+    );
+    let second = start(
+        second_listener,
+        HashMap::from([(ServerId(1), first_addr), (ServerId(3), third_addr)]),
+        authority
+            .tls_config(&["raft-2.internal"])
+            .server_name(ServerId(1), name("raft-1.internal"))
+            .server_name(ServerId(3), name("raft-3.internal"))
+            .authenticate_peers(true),
+    );
+    (first, second)
+}
+
+#[test]
+fn should_take_messages_from_the_peer_a_connection_is_authenticated_as() {
+    let (mut first, mut second) = authenticating_pair(&["raft-1.internal"]);
+
+    let request = timeout_now(1, 2);
+    first.enqueue_outgoing_request(request.clone()).unwrap();
+    assert_eq!(
+        next_message(&mut second, Duration::from_secs(5)),
+        Some(RpcMessage::Request(request))
+    );
+    // The first server's certificate doesn't make it the third
+    first.enqueue_outgoing_request(timeout_now(3, 2)).unwrap();
+    assert_eq!(next_message(&mut second, Duration::from_millis(500)), None);
+}
+
+#[test]
+fn should_refuse_connections_with_certificates_for_none_of_the_peers() {
+    // Signed by the cluster's authority, but not for any of its servers
+    let (mut rogue, mut second) = authenticating_pair(&["rogue.internal"]);
+
+    rogue.enqueue_outgoing_request(timeout_now(1, 2)).unwrap();
+    assert_eq!(next_message(&mut second, Duration::from_millis(500)), None);
+}
@@ -0,0 +1,1160 @@
+use crate::election_jitter::{ElectionJitter, UniformJitter};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// A unique identifier for a server in the cluster.
+pub struct ServerId(pub u64);
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// Identifies a client across its retries, picked by the client itself.
+pub struct ClientId(pub Uuid);
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// Where a command falls in its client's session. The client numbers its commands 1, 2, 3... and
+/// retries a command with the number it first had, so the command is applied once however often
+/// it ends up in the log (§6.3 of the Raft dissertation).
+pub struct ClientSession {
+    /// The client the command comes from.
+    pub client_id: ClientId,
+    /// The command's number among the client's commands, starting at 1.
+    pub sequence: u64,
+}
+
+/// A trait that defines the interface for a log command.
+/// Commands are `Sync` so replicated entries can be shared between the messages sent to each follower,
+/// and serializable so storage can write them to the log.
+pub trait LogCommand:
+    Debug + Clone + Send + Sync + Eq + PartialEq + Serialize + DeserializeOwned
+{
+}
+impl<T> LogCommand for T where
+    T: Debug + Clone + Send + Sync + Eq + PartialEq + Serialize + DeserializeOwned
+{
+}
+
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// The index of a log entry. Entries are numbered from 1, `LogIndex(0)` is the empty log.
+pub struct LogIndex(pub u64);
+impl LogIndex {
+    /// The index right after this one.
+    pub fn next(self) -> Self {
+        LogIndex(self.0 + 1)
+    }
+}
+
+#[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// The term of a log entry.
+pub struct TermIndex(pub u64);
+impl TermIndex {
+    /// Increments the term index by 1.
+    pub fn increment(&mut self) -> Self {
+        TermIndex(self.0 + 1)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+/// A log entry in the Raft log.
+pub struct LogEntry<T: LogCommand> {
+    /// The index of the log entry.
+    pub index: LogIndex,
+    /// The term of the log entry.
+    pub term: TermIndex,
+    /// What the entry carries, a command for the application or a change to the cluster's members.
+    pub command: LogEntryCommand<T>,
+}
+impl<T: LogCommand> LogEntry<T> {
+    /// The entry as a witness keeps it, with a no-op in place of a client's command
+    pub fn for_witness(self) -> Self {
+        let command = match self.command {
+            LogEntryCommand::Application(_) | LogEntryCommand::SessionCommand(..) => {
+                LogEntryCommand::NoOp
+            }
+            command => command,
+        };
+        LogEntry { command, ..self }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+/// What a log entry carries.
+pub enum LogEntryCommand<T: LogCommand> {
+    /// A command a client proposed, applied to the application's state once committed.
+    Application(T),
+    /// A command a client proposed within its session, applied unless the session shows an earlier
+    /// entry already carried it.
+    SessionCommand(ClientSession, T),
+    /// Adds, promotes or removes one server, the application never sees it.
+    MembershipChange(MembershipChange),
+    /// Appended by a new leader so an entry of its own term commits right away, which commits
+    /// whatever earlier entries it inherited too (§5.4.2). Also what witnesses get in place of
+    /// client commands. The application never sees it.
+    NoOp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+/// A command a client asked the leader to replicate, in its session if it has one.
+pub struct ClientProposal<T: LogCommand> {
+    /// The command to apply.
+    pub command: T,
+    /// Where the command falls in its client's session, `None` if applying it twice is fine.
+    pub session: Option<ClientSession>,
+}
+impl<T: LogCommand> ClientProposal<T> {
+    /// What the leader appends to its log for the proposal
+    pub fn into_log_entry_command(self) -> LogEntryCommand<T> {
+        match self.session {
+            Some(session) => LogEntryCommand::SessionCommand(session, self.command),
+            None => LogEntryCommand::Application(self.command),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// Adds or removes a single voting server, or a learner. Changing one server at a time keeps any
+/// majority of the old configuration overlapping any majority of the new one, so a configuration
+/// takes effect as soon as its entry is in a server's log, committed or not (§4.1 of the Raft
+/// dissertation). Learners get the log replicated but don't vote or count toward commits, they
+/// never change a majority (§4.2.1).
+pub enum MembershipChange {
+    /// The server becomes a voting member.
+    AddServer(ServerId),
+    /// The server stops being a member, or a learner.
+    RemoveServer(ServerId),
+    /// The server becomes a learner.
+    AddLearner(ServerId),
+    /// The learner becomes a voting member.
+    PromoteLearner(ServerId),
+}
+impl MembershipChange {
+    /// The server being added or removed.
+    pub fn server_id(&self) -> ServerId {
+        match self {
+            MembershipChange::AddServer(server_id)
+            | MembershipChange::RemoveServer(server_id)
+            | MembershipChange::AddLearner(server_id)
+            | MembershipChange::PromoteLearner(server_id) => *server_id,
+        }
+    }
+
+    /// Applies the change to the voting `members`.
+    pub fn apply_to(&self, members: &mut HashSet<ServerId>) {
+        let _ = match self {
+            MembershipChange::AddServer(server_id)
+            | MembershipChange::PromoteLearner(server_id) => members.insert(*server_id),
+            MembershipChange::RemoveServer(server_id) => members.remove(server_id),
+            MembershipChange::AddLearner(_) => false,
+        };
+    }
+
+    /// Applies the change to the `learners`.
+    pub fn apply_to_learners(&self, learners: &mut HashSet<ServerId>) {
+        let _ = match self {
+            MembershipChange::AddLearner(server_id) => learners.insert(*server_id),
+            MembershipChange::RemoveServer(server_id)
+            | MembershipChange::PromoteLearner(server_id) => learners.remove(server_id),
+            MembershipChange::AddServer(_) => false,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why the leader didn't carry out a [`MembershipChange`].
+pub enum MembershipChangeError {
+    /// The node isn't leading, only the leader changes the cluster's members.
+    NotLeader,
+    /// Another change hasn't committed yet, changes go one at a time.
+    ChangeInProgress,
+    /// The server being added is already a member or learner, the one being promoted isn't a
+    /// learner, or the one being removed is neither.
+    NothingToChange,
+    /// Removing the server would leave the cluster without members.
+    NoMembersLeft,
+    /// The new server, or the learner being promoted, didn't catch up with the leader's log in
+    /// [`RaftConfig::catch_up_rounds`] rounds.
+    CatchUpFailed,
+    /// The leader stepped down before the change committed, the next leader may or may not keep it.
+    LeadershipLost,
+    /// The Raft thread has exited and won't pick up new changes.
+    NodeStopped,
+}
+
+/// Index of the committed configuration entry, or why the change didn't go through.
+pub type MembershipChangeOutcome = Result<LogIndex, MembershipChangeError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a node didn't serve a read from its own state.
+pub enum ReadError {
+    /// The node isn't leading, only the leader serves reads. Carries the leader the node knows of,
+    /// if any, to retry the read with.
+    NotLeader(Option<ServerId>),
+    /// The leader can't be sure it still is one: a majority hasn't acked it within the lease,
+    /// or it hasn't committed an entry of its own term yet so it may not know everything committed.
+    LeaseExpired,
+    /// [`RaftConfig::read_lease`] isn't set.
+    LeaseReadsDisabled,
+    /// The Raft thread has exited and won't pick up new reads.
+    NodeStopped,
+}
+
+/// Index the application has to have applied for a read to see every entry committed when it
+/// came in, or why the read can't be served.
+pub type LeaseReadOutcome = Result<LogIndex, ReadError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why the leader didn't step down the way it was asked to.
+pub enum StepDownError {
+    /// The node isn't leading, there's no leadership to give up.
+    NotLeader,
+    /// The server to hand leadership to is this one, a witness, or not a voting member.
+    InvalidTransferee(ServerId),
+    /// The server didn't catch up with the leader's log within an election timeout, the leader
+    /// kept leading.
+    TransferTimedOut(ServerId),
+    /// The leader is already handing leadership over to another server.
+    TransferInProgress,
+    /// The Raft thread has exited and won't pick up new requests.
+    NodeStopped,
+}
+
+/// Whether the leader stepped down, having handed leadership over if it was asked to.
+pub type StepDownOutcome = Result<(), StepDownError>;
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The application's state with every entry up to `last_included_index` applied, standing in
+/// for those entries once they are compacted out of the log (§7).
+pub struct Snapshot {
+    /// Index of the last entry the snapshot covers.
+    pub last_included_index: LogIndex,
+    /// Term of the last entry the snapshot covers.
+    pub last_included_term: TermIndex,
+    /// The cluster's voting members as of `last_included_index`, configuration entries are compacted too.
+    pub members: HashSet<ServerId>,
+    /// The learners as of `last_included_index`.
+    pub learners: HashSet<ServerId>,
+    /// The last sequence number applied for each client session as of `last_included_index`.
+    pub sessions: HashMap<ClientId, u64>,
+    /// The application's state, serialized by the application.
+    pub data: Vec<u8>,
+}
+impl Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("last_included_index", &self.last_included_index)
+            .field("last_included_term", &self.last_included_term)
+            .field("members", &self.members)
+            .field("learners", &self.learners)
+            .field("sessions", &self.sessions.len())
+            .field("data_len", &self.data.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Time between two heartbeats a leader sends to a follower.
+pub struct HeartbeatInterval(pub Duration);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Followers and candidates pick their election timeout at random from `min..max`.
+pub struct ElectionTimeoutRange {
+    /// Shortest possible election timeout.
+    pub min: Duration,
+    /// Longest possible election timeout, exclusive.
+    pub max: Duration,
+}
+impl ElectionTimeoutRange {
+    /// A range from `min_ms` up to, but not including, `max_ms` milliseconds.
+    pub fn from_millis(min_ms: u64, max_ms: u64) -> Self {
+        ElectionTimeoutRange {
+            min: Duration::from_millis(min_ms),
+            max: Duration::from_millis(max_ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// A node snapshots the application and compacts its log once this many entries were applied since the last snapshot.
+pub struct SnapshotThreshold(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Most bytes of snapshot data the leader puts in one InstallSnapshot. A follower missing a chunk
+/// gets it again without the chunks it already has.
+pub struct SnapshotChunkSize(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Most bytes of one encoded message the transport carries. The leader keeps the entries of an
+/// AppendEntries, and the data of an InstallSnapshot, to half of it as the log's bincode encodes
+/// them, see [`RaftConfig::max_entry_bytes`]. The other half leaves room for the rest of the
+/// message and for a wire codec encoding up to twice as large as the log. Bincode wire codecs stay
+/// well within that, but JSON writes every byte of a `Vec<u8>` as a number, growing byte payloads
+/// 3-4 times: clusters encoding messages as JSON should set a third or less of what the transport
+/// carries.
+pub struct MaxMessageSize(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Rounds of replication the leader gives a server it adds to catch up with its log. A round ends
+/// once the server has the entries the leader had when it started, or after an election timeout.
+/// The server is added once a round ends in time, the change fails if none does.
+pub struct CatchUpRounds(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// AppendEntries carrying entries the leader may have on their way to one follower at once. The
+/// leader sends the next batch without waiting for the previous one to be acked, so a follower far
+/// away still gets this many batches per round trip.
+pub struct ReplicationWindow(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Entries the leader's log may hold past what the application applied, committed or not. Once
+/// the application falls this far behind the leader turns new proposals away until it catches up,
+/// rather than piling them up in its log.
+pub struct MaxUnappliedEntries(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Times a proposal made on a follower may be passed on towards the leader. A follower hands it to
+/// the leader it knows of, which passes it on again if it stepped down in the meantime. Off at 0,
+/// followers turn proposals away and clients find the leader themselves.
+pub struct MaxForwardingHops(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How long a leader serves reads from its own state after a majority acked it. A lease counts
+/// from when the leader sent the AppendEntries a majority acked, not from when the acks arrived.
+pub struct ReadLease {
+    /// How long a lease lasts. No longer than the shortest election timeout, followers that just
+    /// heard from the leader don't start an election before that.
+    pub duration: Duration,
+    /// Most the clocks may drift apart over a lease, taken off its end.
+    pub clock_skew_bound: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How long a leader holds on to proposals so it appends, syncs and replicates more of them in one
+/// go. The first proposal it holds opens the window, the leader takes everything it holds once the
+/// window closes or `max_entries` commands wait, whichever comes first.
+pub struct GroupCommit {
+    /// Longest a proposal waits for others to join it. Shorter than the heartbeat interval.
+    pub window: Duration,
+    /// Commands held that close the window early.
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// How long a transport waits for the reply to an RPC before giving up on it.
+pub struct RpcTimeout(pub Duration);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Settings replacing the cluster wide ones for a single peer, e.g. a follower in another region.
+pub struct PeerOverrides {
+    /// Heartbeat interval the leader uses for this peer.
+    pub heartbeat_interval: Option<HeartbeatInterval>,
+    /// Timeout for RPCs sent to this peer.
+    pub rpc_timeout: Option<RpcTimeout>,
+}
+
+#[derive(Debug, Clone)]
+/// The configuration for a Raft node.
+pub struct RaftConfig {
+    /// The amount of time that a leader will wait before sending a heartbeat to its followers.
+    pub leader_heartbeat_interval: HeartbeatInterval,
+    /// The range a follower picks the time it waits before becoming a candidate from.
+    pub election_timeout: ElectionTimeoutRange,
+    /// How election timeouts are drawn from `election_timeout`.
+    pub election_jitter: Arc<dyn ElectionJitter>,
+    /// How long transports wait for the reply to an RPC.
+    pub rpc_timeout: RpcTimeout,
+    /// Per peer replacements for the settings above.
+    pub peer_overrides: HashMap<ServerId, PeerOverrides>,
+    /// Operations taking longer than these are logged as warnings.
+    pub slow_operation_thresholds: SlowOperationThresholds,
+    /// How many entries the leader may put in one AppendEntries, adapted per follower within these bounds.
+    pub append_entries_batch: AppendEntriesBatchLimits,
+    /// How many AppendEntries carrying entries may be unacked per follower.
+    pub replication_window: ReplicationWindow,
+    /// How far the application may fall behind the leader's log before it turns proposals away.
+    pub max_unapplied_entries: MaxUnappliedEntries,
+    /// How many times a proposal made on a follower may be passed on towards the leader.
+    pub max_forwarding_hops: MaxForwardingHops,
+    /// Lets the leader wait for more proposals before it appends them with a single sync. Off when
+    /// `None`, the leader takes whatever arrived since its last pass.
+    pub group_commit: Option<GroupCommit>,
+    /// How the Raft thread retries failed storage syncs before halting.
+    pub storage_sync_retry: StorageSyncRetry,
+    /// What the node does once its storage runs out of room.
+    pub storage_full_policy: StorageFullPolicy,
+    /// How many applied entries the log keeps before they are compacted into a snapshot.
+    pub snapshot_threshold: SnapshotThreshold,
+    /// How much of a snapshot the leader sends a follower per InstallSnapshot.
+    pub snapshot_chunk_size: SnapshotChunkSize,
+    /// Rounds a server being added gets to catch up with the leader's log before it counts towards quorum.
+    pub catch_up_rounds: CatchUpRounds,
+    /// The node is started to be added to a running cluster rather than as one of its first members.
+    /// It doesn't count itself as a member, so it never starts an election, until its log has a
+    /// configuration that includes it.
+    pub join_existing_cluster: bool,
+    /// A follower whose election timeout expires first asks whether a majority would vote for it,
+    /// and only starts an election once one would. Keeps a node cut off from the rest of the
+    /// cluster from bumping the term and unseating the leader when it comes back.
+    pub pre_vote: bool,
+    /// The leader steps down once a majority of the cluster hasn't replied to it within an election
+    /// timeout, so a leader cut off from the rest stops taking proposals that can never commit.
+    pub check_quorum: bool,
+    /// The leader, and followers that heard from it within the minimum election timeout, turn down
+    /// votes and pre-votes without taking up the candidate's term. Keeps a server removed from the
+    /// cluster, which never hears from the leader again, from unseating it with ever higher terms.
+    pub leader_stickiness: bool,
+    /// Lets the leader serve reads from its own state while it holds a lease, without a round of
+    /// heartbeats per read. Off when `None`, reads are only as safe as the clocks are within
+    /// [`ReadLease::clock_skew_bound`] of each other.
+    pub read_lease: Option<ReadLease>,
+    /// Members that vote and count toward commits like any other, but never start an election.
+    /// The leader only sends them the index and term of client commands, so they keep no more of
+    /// the log than its configuration and apply nothing. Two servers and a witness tolerate either
+    /// server failing, but a leader that fails while only the witness has its latest entries keeps
+    /// the cluster from electing another until it's back. The same on every node.
+    pub witnesses: HashSet<ServerId>,
+    /// Most bytes of one message the transport carries, the leader splits batches of entries and
+    /// snapshots to fit on top of `append_entries_batch` and `snapshot_chunk_size`, keeping half of
+    /// it as headroom for the codec as [`MaxMessageSize`] explains. Proposals whose entry wouldn't
+    /// fit in one message are turned down. Nodes take the smaller of this and their transport's own
+    /// limit.
+    pub max_message_size: Option<MaxMessageSize>,
+}
+
+impl RaftConfig {
+    /// Start building a config from the defaults: 100ms heartbeats, a 150-300ms election timeout
+    /// drawn uniformly, a 1s RPC timeout, a snapshot every 10000 applied entries sent to followers
+    /// in 1MiB chunks, proposals turned away once 10000 entries wait to be applied and on followers,
+    /// 10 rounds for new servers to catch up, a pre-vote before every election and leaders stepping
+    /// down once they lose contact with a majority.
+    pub fn builder() -> RaftConfigBuilder {
+        RaftConfigBuilder {
+            config: RaftConfig {
+                leader_heartbeat_interval: HeartbeatInterval(Duration::from_millis(100)),
+                election_timeout: ElectionTimeoutRange::from_millis(150, 300),
+                election_jitter: Arc::new(UniformJitter),
+                rpc_timeout: RpcTimeout(Duration::from_secs(1)),
+                peer_overrides: HashMap::new(),
+                slow_operation_thresholds: SlowOperationThresholds::default(),
+                append_entries_batch: AppendEntriesBatchLimits::default(),
+                replication_window: ReplicationWindow(4),
+                max_unapplied_entries: MaxUnappliedEntries(10_000),
+                max_forwarding_hops: MaxForwardingHops(0),
+                group_commit: None,
+                storage_sync_retry: StorageSyncRetry::default(),
+                storage_full_policy: StorageFullPolicy::Halt,
+                snapshot_threshold: SnapshotThreshold(10_000),
+                snapshot_chunk_size: SnapshotChunkSize(1024 * 1024),
+                catch_up_rounds: CatchUpRounds(10),
+                join_existing_cluster: false,
+                pre_vote: true,
+                check_quorum: true,
+                leader_stickiness: true,
+                read_lease: None,
+                witnesses: HashSet::new(),
+                max_message_size: None,
+            },
+        }
+    }
+
+    /// Heartbeat interval the leader uses for `peer`, taking overrides into account.
+    pub fn heartbeat_interval_for(&self, peer: ServerId) -> HeartbeatInterval {
+        self.peer_overrides
+            .get(&peer)
+            .and_then(|overrides| overrides.heartbeat_interval)
+            .unwrap_or(self.leader_heartbeat_interval)
+    }
+
+    /// Whether `server_id` is one of the cluster's [`RaftConfig::witnesses`].
+    pub fn is_witness(&self, server_id: ServerId) -> bool {
+        self.witnesses.contains(&server_id)
+    }
+
+    /// Timeout for RPCs sent to `peer`, taking overrides into account.
+    pub fn rpc_timeout_for(&self, peer: ServerId) -> RpcTimeout {
+        self.peer_overrides
+            .get(&peer)
+            .and_then(|overrides| overrides.rpc_timeout)
+            .unwrap_or(self.rpc_timeout)
+    }
+
+    /// Most bytes of encoded entries the leader puts in one AppendEntries, taking
+    /// [`RaftConfig::max_message_size`] into account.
+    pub fn append_entries_max_bytes(&self) -> usize {
+        self.append_entries_batch.max_bytes.min(self.message_room())
+    }
+
+    /// Most bytes of snapshot data the leader puts in one InstallSnapshot, taking
+    /// [`RaftConfig::max_message_size`] into account.
+    pub fn snapshot_chunk_bytes(&self) -> usize {
+        self.snapshot_chunk_size.0.min(self.message_room())
+    }
+
+    /// Most bytes one entry may take as the log encodes it with bincode, half of
+    /// [`RaftConfig::max_message_size`], no limit without one.
+    pub fn max_entry_bytes(&self) -> usize {
+        self.message_room()
+    }
+
+    fn message_room(&self) -> usize {
+        self.max_message_size
+            .map_or(usize::MAX, |max_message_size| {
+                (max_message_size.0 / 2).max(1)
+            })
+    }
+
+    /// This config with the timing settings `update` sets replaced, checked like
+    /// [`RaftConfig::validate`].
+    pub fn with_timing(&self, update: TimingUpdate) -> Result<RaftConfig, RaftConfigError> {
+        let mut config = self.clone();
+        if let Some(election_timeout) = update.election_timeout {
+            config.election_timeout = election_timeout;
+        }
+        if let Some(leader_heartbeat_interval) = update.leader_heartbeat_interval {
+            config.leader_heartbeat_interval = leader_heartbeat_interval;
+        }
+        if let Some(append_entries_batch) = update.append_entries_batch {
+            config.append_entries_batch = append_entries_batch;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the invariants the Raft thread relies on, see [`RaftConfigError`].
+    pub fn validate(&self) -> Result<(), RaftConfigError> {
+        let batch = self.append_entries_batch;
+        if batch.min_entries == 0
+            || batch.min_entries > batch.initial_entries
+            || batch.initial_entries > batch.max_entries
+            || batch.max_bytes == 0
+        {
+            return Err(RaftConfigError::InvalidAppendEntriesBatchLimits(batch));
+        }
+        if self.replication_window.0 == 0 {
+            return Err(RaftConfigError::ZeroReplicationWindow);
+        }
+        if self.max_unapplied_entries.0 == 0 {
+            return Err(RaftConfigError::ZeroMaxUnappliedEntries);
+        }
+        if self.snapshot_threshold.0 == 0 {
+            return Err(RaftConfigError::ZeroSnapshotThreshold);
+        }
+        if self.snapshot_chunk_size.0 == 0 {
+            return Err(RaftConfigError::ZeroSnapshotChunkSize);
+        }
+        if self.catch_up_rounds.0 == 0 {
+            return Err(RaftConfigError::ZeroCatchUpRounds);
+        }
+        if self.max_message_size == Some(MaxMessageSize(0)) {
+            return Err(RaftConfigError::ZeroMaxMessageSize);
+        }
+        if self.election_timeout.min >= self.election_timeout.max {
+            return Err(RaftConfigError::EmptyElectionTimeoutRange(
+                self.election_timeout,
+            ));
+        }
+        let heartbeat_intervals = std::iter::once(self.leader_heartbeat_interval).chain(
+            self.peer_overrides
+                .values()
+                .filter_map(|overrides| overrides.heartbeat_interval),
+        );
+        for heartbeat_interval in heartbeat_intervals {
+            if heartbeat_interval.0.is_zero() {
+                return Err(RaftConfigError::ZeroHeartbeatInterval);
+            }
+            if heartbeat_interval.0 >= self.election_timeout.min {
+                return Err(RaftConfigError::HeartbeatNotShorterThanElectionTimeout {
+                    heartbeat_interval,
+                    election_timeout: self.election_timeout,
+                });
+            }
+        }
+        let rpc_timeouts = std::iter::once(self.rpc_timeout).chain(
+            self.peer_overrides
+                .values()
+                .filter_map(|overrides| overrides.rpc_timeout),
+        );
+        for rpc_timeout in rpc_timeouts {
+            if rpc_timeout.0.is_zero() {
+                return Err(RaftConfigError::ZeroRpcTimeout);
+            }
+        }
+        if let Some(group_commit) = self.group_commit {
+            if group_commit.window.is_zero()
+                || group_commit.window >= self.leader_heartbeat_interval.0
+                || group_commit.max_entries == 0
+            {
+                return Err(RaftConfigError::InvalidGroupCommit {
+                    group_commit,
+                    heartbeat_interval: self.leader_heartbeat_interval,
+                });
+            }
+        }
+        if let Some(lease) = self.read_lease {
+            if lease.clock_skew_bound >= lease.duration
+                || lease.duration > self.election_timeout.min
+            {
+                return Err(RaftConfigError::InvalidReadLease {
+                    lease,
+                    election_timeout: self.election_timeout,
+                });
+            }
+            if !self.leader_stickiness {
+                return Err(RaftConfigError::ReadLeaseWithoutLeaderStickiness(lease));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Timing settings to change on a running node, those left `None` stay as they are. A node picks
+/// them up the next time it resets the timer or sizes the batch they're for.
+pub struct TimingUpdate {
+    /// Replaces [`RaftConfig::election_timeout`].
+    pub election_timeout: Option<ElectionTimeoutRange>,
+    /// Replaces [`RaftConfig::leader_heartbeat_interval`].
+    pub leader_heartbeat_interval: Option<HeartbeatInterval>,
+    /// Replaces [`RaftConfig::append_entries_batch`].
+    pub append_entries_batch: Option<AppendEntriesBatchLimits>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A [`RaftConfig`] that would keep the cluster from working.
+pub enum RaftConfigError {
+    /// Leaders would flood followers with heartbeats.
+    ZeroHeartbeatInterval,
+    /// The election timeout is drawn from `min..max`, so `min` has to be less than `max`.
+    EmptyElectionTimeoutRange(ElectionTimeoutRange),
+    /// Followers would time out and start elections while the leader is healthy.
+    HeartbeatNotShorterThanElectionTimeout {
+        /// The offending heartbeat interval, the cluster wide one or a peer override.
+        heartbeat_interval: HeartbeatInterval,
+        /// The configured election timeout range.
+        election_timeout: ElectionTimeoutRange,
+    },
+    /// Every RPC would time out before its reply could come back, the cluster wide one or a peer
+    /// override.
+    ZeroRpcTimeout,
+    /// Batch limits need `0 < min_entries <= initial_entries <= max_entries` and `max_bytes > 0`.
+    InvalidAppendEntriesBatchLimits(AppendEntriesBatchLimits),
+    /// The leader could never send a follower entries.
+    ZeroReplicationWindow,
+    /// The leader would never take a proposal.
+    ZeroMaxUnappliedEntries,
+    /// Nodes would snapshot after every applied entry.
+    ZeroSnapshotThreshold,
+    /// The leader could never send a snapshot's data.
+    ZeroSnapshotChunkSize,
+    /// No server could ever be added.
+    ZeroCatchUpRounds,
+    /// No message could ever be sent.
+    ZeroMaxMessageSize,
+    /// A lease has to outlast its clock skew bound and end before a follower could start an election.
+    InvalidReadLease {
+        /// The configured read lease.
+        lease: ReadLease,
+        /// The configured election timeout range.
+        election_timeout: ElectionTimeoutRange,
+    },
+    /// Without leader stickiness followers vote for a candidate while the leader's lease runs, a
+    /// deposed leader could serve stale reads.
+    ReadLeaseWithoutLeaderStickiness(ReadLease),
+    /// A group commit window has to be open for a while, hold at least one command and close
+    /// before the leader's next heartbeat is due.
+    InvalidGroupCommit {
+        /// The configured group commit.
+        group_commit: GroupCommit,
+        /// The configured heartbeat interval.
+        heartbeat_interval: HeartbeatInterval,
+    },
+}
+impl std::fmt::Display for RaftConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RaftConfigError::ZeroHeartbeatInterval => {
+                write!(f, "leader heartbeat interval must be greater than zero")
+            }
+            RaftConfigError::EmptyElectionTimeoutRange(election_timeout) => write!(
+                f,
+                "min election timeout ({:?}) must be less than max election timeout ({:?})",
+                election_timeout.min, election_timeout.max
+            ),
+            RaftConfigError::HeartbeatNotShorterThanElectionTimeout {
+                heartbeat_interval,
+                election_timeout,
+            } => write!(
+                f,
+                "leader heartbeat interval ({:?}) must be shorter than the min election timeout ({:?})",
+                heartbeat_interval.0, election_timeout.min
+            ),
+            RaftConfigError::ZeroRpcTimeout => write!(f, "rpc timeout must be greater than zero"),
+            RaftConfigError::InvalidAppendEntriesBatchLimits(batch) => write!(
+                f,
+                "append entries batch limits must satisfy 0 < min ({}) <= initial ({}) <= max ({}) and max bytes ({}) > 0",
+                batch.min_entries, batch.initial_entries, batch.max_entries, batch.max_bytes
+            ),
+            RaftConfigError::ZeroReplicationWindow => {
+                write!(f, "replication window must be greater than zero")
+            }
+            RaftConfigError::ZeroMaxUnappliedEntries => {
+                write!(f, "max unapplied entries must be greater than zero")
+            }
+            RaftConfigError::ZeroSnapshotThreshold => {
+                write!(f, "snapshot threshold must be greater than zero")
+            }
+            RaftConfigError::ZeroSnapshotChunkSize => {
+                write!(f, "snapshot chunk size must be greater than zero")
+            }
+            RaftConfigError::ZeroCatchUpRounds => {
+                write!(f, "catch up rounds must be greater than zero")
+            }
+            RaftConfigError::ZeroMaxMessageSize => {
+                write!(f, "max message size must be greater than zero")
+            }
+            RaftConfigError::InvalidReadLease {
+                lease,
+                election_timeout,
+            } => write!(
+                f,
+                "read lease clock skew bound ({:?}) must be shorter than its duration ({:?}), which must not exceed the min election timeout ({:?})",
+                lease.clock_skew_bound, lease.duration, election_timeout.min
+            ),
+            RaftConfigError::ReadLeaseWithoutLeaderStickiness(lease) => write!(
+                f,
+                "read lease ({:?}) needs leader stickiness, followers would vote while it runs",
+                lease.duration
+            ),
+            RaftConfigError::InvalidGroupCommit {
+                group_commit,
+                heartbeat_interval,
+            } => write!(
+                f,
+                "group commit window ({:?}) must be greater than zero and shorter than the leader heartbeat interval ({:?}), and max entries ({}) greater than zero",
+                group_commit.window, heartbeat_interval.0, group_commit.max_entries
+            ),
+        }
+    }
+}
+impl std::error::Error for RaftConfigError {}
+
+#[derive(Debug, Clone)]
+/// Builds a [`RaftConfig`], checking its invariants in [`RaftConfigBuilder::build`].
+pub struct RaftConfigBuilder {
+    config: RaftConfig,
+}
+impl RaftConfigBuilder {
+    /// How often the leader sends heartbeats to its followers.
+    pub fn leader_heartbeat_interval(mut self, interval: HeartbeatInterval) -> Self {
+        self.config.leader_heartbeat_interval = interval;
+        self
+    }
+
+    /// The range election timeouts are randomly picked from.
+    pub fn election_timeout(mut self, election_timeout: ElectionTimeoutRange) -> Self {
+        self.config.election_timeout = election_timeout;
+        self
+    }
+
+    /// Strategy picking election timeouts from the range, see [`ElectionJitter`].
+    pub fn election_jitter(mut self, election_jitter: impl ElectionJitter + 'static) -> Self {
+        self.config.election_jitter = Arc::new(election_jitter);
+        self
+    }
+
+    /// How long transports wait for the reply to an RPC.
+    pub fn rpc_timeout(mut self, rpc_timeout: RpcTimeout) -> Self {
+        self.config.rpc_timeout = rpc_timeout;
+        self
+    }
+
+    /// Replace settings for a single peer, replacing any overrides set for it before.
+    pub fn peer_overrides(mut self, peer: ServerId, overrides: PeerOverrides) -> Self {
+        let _ = self.config.peer_overrides.insert(peer, overrides);
+        self
+    }
+
+    /// When to warn about slow operations.
+    pub fn slow_operation_thresholds(mut self, thresholds: SlowOperationThresholds) -> Self {
+        self.config.slow_operation_thresholds = thresholds;
+        self
+    }
+
+    /// Bounds for the number of entries and bytes per AppendEntries.
+    pub fn append_entries_batch(mut self, limits: AppendEntriesBatchLimits) -> Self {
+        self.config.append_entries_batch = limits;
+        self
+    }
+
+    /// How many AppendEntries carrying entries may be on their way to a follower at once.
+    pub fn replication_window(mut self, window: ReplicationWindow) -> Self {
+        self.config.replication_window = window;
+        self
+    }
+
+    /// How far the application may fall behind the leader's log before it turns proposals away.
+    pub fn max_unapplied_entries(mut self, max_unapplied_entries: MaxUnappliedEntries) -> Self {
+        self.config.max_unapplied_entries = max_unapplied_entries;
+        self
+    }
+
+    /// How many times a proposal made on a follower may be passed on towards the leader, 0 to
+    /// turn proposals away on followers.
+    pub fn max_forwarding_hops(mut self, hops: MaxForwardingHops) -> Self {
+        self.config.max_forwarding_hops = hops;
+        self
+    }
+
+    /// How often, and how far apart, failed storage syncs are retried.
+    pub fn storage_sync_retry(mut self, retry: StorageSyncRetry) -> Self {
+        self.config.storage_sync_retry = retry;
+        self
+    }
+
+    /// What the node does once its storage runs out of room, halting by default.
+    pub fn storage_full_policy(mut self, policy: StorageFullPolicy) -> Self {
+        self.config.storage_full_policy = policy;
+        self
+    }
+
+    /// How many applied entries pile up in the log before they are compacted into a snapshot.
+    pub fn snapshot_threshold(mut self, threshold: SnapshotThreshold) -> Self {
+        self.config.snapshot_threshold = threshold;
+        self
+    }
+
+    /// Most bytes of snapshot data sent to a follower in one InstallSnapshot.
+    pub fn snapshot_chunk_size(mut self, chunk_size: SnapshotChunkSize) -> Self {
+        self.config.snapshot_chunk_size = chunk_size;
+        self
+    }
+
+    /// Most bytes of one message the transport carries, see [`RaftConfig::max_message_size`].
+    pub fn max_message_size(mut self, max_message_size: MaxMessageSize) -> Self {
+        self.config.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// How many rounds a server being added gets to catch up with the leader's log.
+    pub fn catch_up_rounds(mut self, rounds: CatchUpRounds) -> Self {
+        self.config.catch_up_rounds = rounds;
+        self
+    }
+
+    /// Start the node to be added to a running cluster with [`MembershipChange::AddServer`].
+    pub fn join_existing_cluster(mut self, join: bool) -> Self {
+        self.config.join_existing_cluster = join;
+        self
+    }
+
+    /// Whether followers run a pre-vote before starting an election, see [`RaftConfig::pre_vote`].
+    pub fn pre_vote(mut self, enabled: bool) -> Self {
+        self.config.pre_vote = enabled;
+        self
+    }
+
+    /// Whether leaders step down without a majority's replies, see [`RaftConfig::check_quorum`].
+    pub fn check_quorum(mut self, enabled: bool) -> Self {
+        self.config.check_quorum = enabled;
+        self
+    }
+
+    /// Whether nodes that hear from a leader turn candidates down, see [`RaftConfig::leader_stickiness`].
+    pub fn leader_stickiness(mut self, enabled: bool) -> Self {
+        self.config.leader_stickiness = enabled;
+        self
+    }
+
+    /// Make `server_id` a witness, see [`RaftConfig::witnesses`].
+    pub fn witness(mut self, server_id: ServerId) -> Self {
+        let _ = self.config.witnesses.insert(server_id);
+        self
+    }
+
+    /// Let the leader wait for more proposals before appending them, see [`RaftConfig::group_commit`].
+    pub fn group_commit(mut self, group_commit: GroupCommit) -> Self {
+        self.config.group_commit = Some(group_commit);
+        self
+    }
+
+    /// Let the leader serve reads while it holds a lease, see [`RaftConfig::read_lease`].
+    pub fn read_lease(mut self, lease: ReadLease) -> Self {
+        self.config.read_lease = Some(lease);
+        self
+    }
+
+    /// The config, if it passes [`RaftConfig::validate`].
+    pub fn build(self) -> Result<RaftConfig, RaftConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// How long an operation may take before the Raft thread logs a warning about it.
+pub struct SlowOperationThresholds {
+    /// Handling a single incoming message or timer tick, including any storage writes it causes.
+    pub message_handling: Duration,
+    /// Writing/fsyncing pending changes to persistent storage.
+    pub storage_sync: Duration,
+    /// Applying committed entries to the application's state machine.
+    pub apply: Duration,
+}
+impl Default for SlowOperationThresholds {
+    fn default() -> Self {
+        SlowOperationThresholds {
+            message_handling: Duration::from_millis(50),
+            storage_sync: Duration::from_millis(100),
+            apply: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Bounds for the number of entries in one AppendEntries. Each follower starts at `initial_entries`,
+/// grows towards `max_entries` while it acks quickly and shrinks towards `min_entries` on timeouts and rejections.
+/// Whatever the count, a batch stops before the entry that takes it past `max_bytes`.
+pub struct AppendEntriesBatchLimits {
+    /// Fewest entries a batch is shrunk to.
+    pub min_entries: usize,
+    /// Batch size for a follower the leader knows nothing about yet.
+    pub initial_entries: usize,
+    /// Most entries a batch is grown to.
+    pub max_entries: usize,
+    /// Most bytes of encoded entries in one batch, a single larger entry still goes out on its own.
+    pub max_bytes: usize,
+}
+impl Default for AppendEntriesBatchLimits {
+    fn default() -> Self {
+        AppendEntriesBatchLimits {
+            min_entries: 1,
+            initial_entries: 64,
+            max_entries: 4096,
+            // Well below the 4MiB gRPC transports accept by default
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Retries of a failed storage sync, see [`RaftErrorRecovery::Retry`]. The wait doubles after every attempt.
+pub struct StorageSyncRetry {
+    /// Syncs tried before the node halts, none halts right away.
+    pub attempts: u32,
+    /// Wait before the first attempt.
+    pub initial_backoff: Duration,
+}
+impl Default for StorageSyncRetry {
+    fn default() -> Self {
+        StorageSyncRetry {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// What a node does once its storage is full, see [`PersistentStorageError::StorageFull`].
+pub enum StorageFullPolicy {
+    /// Carry on as a follower, and while leading turn proposals away until storage has room again.
+    RejectProposals,
+    /// Carry on as a follower, leaving leadership to a node that has room.
+    StepDown,
+    /// Stop the Raft thread after publishing [`crate::RaftEvent::NodeHalted`].
+    #[default]
+    Halt,
+}
+impl StorageFullPolicy {
+    /// How the Raft thread recovers from storage running full under this policy.
+    pub fn recovery(self) -> RaftErrorRecovery {
+        match self {
+            StorageFullPolicy::RejectProposals | StorageFullPolicy::StepDown => {
+                RaftErrorRecovery::StepDown
+            }
+            StorageFullPolicy::Halt => RaftErrorRecovery::Halt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Defines errors that can occur when interacting with the persistent storage layer.
+pub enum PersistentStorageError {
+    /// An error occurred while reading from/writing to disk.
+    IoError,
+    /// An error occurred while serializing/deserializing data.
+    SerdeError,
+    /// A record read back from disk doesn't match its checksum, it was torn or has rotted.
+    Corrupted,
+    /// The files on disk were written in another format than the one storage was opened with.
+    FormatMismatch,
+    /// The storage can't do what was asked of it, e.g. [`PersistentStorage::backup_to`].
+    Unsupported,
+    /// The disk ran out of space, or the storage went over its byte quota.
+    StorageFull,
+}
+
+/// A trait that defines the interface for a persistent storage layer for Raft.
+pub trait PersistentStorage<C: LogCommand>: Send {
+    /// Returns the current term of the Raft node.
+    fn current_term(&self) -> TermIndex;
+    /// Returns the server that the Raft node voted for in the current term.
+    fn vote_for_current_term(&self) -> Option<ServerId>;
+
+    /// Updates the current term of the Raft node.
+    fn update_term(&mut self, term: TermIndex) -> &mut Self;
+    /// Updates the current term of the Raft node.
+    fn record_vote(&mut self, voted_for: ServerId) -> &mut Self;
+    /// Makes `term`, and the vote cast in it if any, durable together, before the node asks for
+    /// votes or answers a vote request. A vote already cast in `term` is kept. Storage that can
+    /// write the two as one record overrides this, by default both are set and synced.
+    fn persist_term_and_vote(
+        &mut self,
+        term: TermIndex,
+        voted_for: Option<ServerId>,
+    ) -> Result<(), PersistentStorageError> {
+        let storage = self.update_term(term);
+        match voted_for {
+            Some(voted_for) => storage.record_vote(voted_for).sync(),
+            None => storage.sync(),
+        }
+    }
+
+    /// Returns the log index of the last entry in the log, or of the snapshot if it covers the whole log.
+    fn last_entry_index(&self) -> Option<LogIndex>;
+    /// Returns the term of the last entry in the log, or of the snapshot if it covers the whole log.
+    fn last_entry_term(&self) -> Option<TermIndex>;
+    /// Returns the term of the entry at `index`, `TermIndex(0)` for `LogIndex(0)`, the snapshot's
+    /// term for its last included index, or `None` if the log doesn't reach `index` or it was compacted.
+    fn entry_term(&self, index: LogIndex) -> Option<TermIndex>;
+    /// Returns true if the log contains an entry with the given index and term.
+    fn has_entry(&self, index: LogIndex, term: TermIndex) -> bool {
+        self.entry_term(index) == Some(term)
+    }
+    /// Returns up to `max_entries` entries, starting with the one at `from`.
+    /// Entries covered by the snapshot are gone, asking for them returns none.
+    fn entries(&self, from: LogIndex, max_entries: usize) -> Vec<LogEntry<C>>;
+    /// Like [`PersistentStorage::entries`], but stops before the entry that takes the entries past
+    /// `max_bytes` once encoded. The first entry is returned whatever its size, so it isn't stuck.
+    fn entries_within(
+        &self,
+        from: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Vec<LogEntry<C>>;
+
+    /// The latest snapshot, if the log was ever compacted.
+    fn snapshot(&self) -> Option<&Snapshot>;
+    /// Returns the index of the last entry compacted into the snapshot, `LogIndex(0)` without one.
+    fn snapshot_index(&self) -> LogIndex {
+        self.snapshot()
+            .map(|snapshot| snapshot.last_included_index)
+            .unwrap_or(LogIndex(0))
+    }
+    /// Replaces the entries `snapshot` covers with it. Entries after it are kept if the log has the
+    /// snapshot's last included entry, otherwise they can't be trusted and the whole log is dropped (§7).
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> &mut Self;
+
+    /// Appends the given entries to the log, first deleting any conflicting entries (same index
+    /// but different term). Entries the log already holds are skipped.
+    fn append(&mut self, entries: Vec<LogEntry<C>>) -> &mut Self;
+
+    /// Writes/fsyncs any pending changes to disk.
+    fn sync(&mut self) -> Result<(), PersistentStorageError>;
+
+    /// Bytes the log takes up on disk as of the last sync, `None` if the storage doesn't know.
+    fn log_size_on_disk(&self) -> Option<u64> {
+        None
+    }
+    /// Whether storage has no room for more entries, e.g. the last sync failed with
+    /// [`PersistentStorageError::StorageFull`]. A leader told to by
+    /// [`RaftConfig::storage_full_policy`] turns proposals away for as long as it's full.
+    fn is_full(&self) -> bool {
+        false
+    }
+    /// Syncs, then copies the term and vote, the latest snapshot and the log to a storage directory
+    /// at `path` that opens like this one, returning the index of the last entry copied. Fails with
+    /// [`PersistentStorageError::Unsupported`] unless the storage overrides it.
+    fn backup_to(&mut self, _path: &Path) -> Result<LogIndex, PersistentStorageError> {
+        Err(PersistentStorageError::Unsupported)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Enum of errors that can originate from the Raft transport code
+pub enum RaftTransportError {
+    /// The transport was shutdown.
+    TransportShutdown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors that keep a Raft node from handling an event. The Raft thread recovers from them
+/// as [`RaftError::recovery`] says instead of dying.
+pub enum RaftError {
+    /// Reading from or writing to persistent storage failed.
+    Storage(PersistentStorageError),
+    /// The transport can't send or receive messages anymore.
+    Transport(RaftTransportError),
+    /// The node's state contradicts itself or a peer broke the protocol, e.g. two leaders in one term.
+    InvariantViolated(&'static str),
+}
+impl RaftError {
+    /// What the Raft thread does about this error.
+    pub fn recovery(&self) -> RaftErrorRecovery {
+        match self {
+            RaftError::Storage(PersistentStorageError::IoError) => RaftErrorRecovery::Retry,
+            // Encoding the same entries again gives the same error
+            RaftError::Storage(PersistentStorageError::SerdeError) => RaftErrorRecovery::Halt,
+            // Reading the same record again gives the same bytes
+            RaftError::Storage(PersistentStorageError::Corrupted) => RaftErrorRecovery::Halt,
+            RaftError::Storage(PersistentStorageError::FormatMismatch) => RaftErrorRecovery::Halt,
+            // Asking again gets the same answer
+            RaftError::Storage(PersistentStorageError::Unsupported) => RaftErrorRecovery::Halt,
+            // Unless RaftConfig::storage_full_policy says otherwise
+            RaftError::Storage(PersistentStorageError::StorageFull) => RaftErrorRecovery::Halt,
+            RaftError::Transport(RaftTransportError::TransportShutdown) => RaftErrorRecovery::Halt,
+            RaftError::InvariantViolated(_) => RaftErrorRecovery::StepDown,
+        }
+    }
+}
+impl std::fmt::Display for RaftError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RaftError::Storage(error) => write!(f, "persistent storage error: {:?}", error),
+            RaftError::Transport(error) => write!(f, "transport error: {:?}", error),
+            RaftError::InvariantViolated(invariant) => {
+                write!(f, "invariant violated: {}", invariant)
+            }
+        }
+    }
+}
+impl std::error::Error for RaftError {}
+impl From<PersistentStorageError> for RaftError {
+    fn from(error: PersistentStorageError) -> Self {
+        RaftError::Storage(error)
+    }
+}
+impl From<RaftTransportError> for RaftError {
+    fn from(error: RaftTransportError) -> Self {
+        RaftError::Transport(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How the Raft thread recovers from a [`RaftError`].
+pub enum RaftErrorRecovery {
+    /// Sync storage again a few times, backing off in between, and carry on as a follower once it works.
+    /// Halts if it keeps failing.
+    Retry,
+    /// Drop the node's volatile state and carry on as a follower.
+    StepDown,
+    /// Stop the Raft thread after publishing [`crate::RaftEvent::NodeHalted`].
+    Halt,
+}
@@ -0,0 +1,115 @@
+use crate::common::{ElectionTimeoutRange, ServerId};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a node knows when it picks its next election timeout.
+pub struct ElectionTimeoutContext {
+    /// The node picking the timeout.
+    pub server_id: ServerId,
+    /// Elections this node started since it last heard from a leader, the one being started included.
+    /// Zero while following a leader.
+    pub elections_without_leader: u32,
+}
+
+/// Picks randomized election timeouts for followers and candidates. Strategies draw from the node's
+/// RNG, so a seeded simulation stays reproducible whatever strategy it runs with.
+pub trait ElectionJitter: Debug + Send + Sync {
+    /// The next election timeout, usually somewhere in `range`.
+    fn next_timeout(
+        &self,
+        range: ElectionTimeoutRange,
+        context: ElectionTimeoutContext,
+        rng: &mut ChaCha8Rng,
+    ) -> Duration;
+
+    /// Longest timeout this strategy ever picks from `range`, the Raft thread may sleep that long.
+    fn longest_timeout(&self, range: ElectionTimeoutRange) -> Duration {
+        range.max
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Draws every timeout uniformly from the range, as in the Raft paper.
+pub struct UniformJitter;
+impl ElectionJitter for UniformJitter {
+    fn next_timeout(
+        &self,
+        range: ElectionTimeoutRange,
+        _context: ElectionTimeoutContext,
+        rng: &mut ChaCha8Rng,
+    ) -> Duration {
+        rng.gen_range(range.min..range.max)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Doubles the uniformly drawn timeout for every election after the first that ends without a leader,
+/// so candidates that keep splitting the vote spread further apart.
+pub struct ExponentialBackoffJitter {
+    /// Most times the timeout gets doubled.
+    pub max_doublings: u32,
+}
+impl Default for ExponentialBackoffJitter {
+    fn default() -> Self {
+        ExponentialBackoffJitter { max_doublings: 4 }
+    }
+}
+impl ElectionJitter for ExponentialBackoffJitter {
+    fn next_timeout(
+        &self,
+        range: ElectionTimeoutRange,
+        context: ElectionTimeoutContext,
+        rng: &mut ChaCha8Rng,
+    ) -> Duration {
+        let doublings = context
+            .elections_without_leader
+            .saturating_sub(1)
+            .min(self.max_doublings);
+        rng.gen_range(range.min..range.max) * 2u32.pow(doublings)
+    }
+
+    fn longest_timeout(&self, range: ElectionTimeoutRange) -> Duration {
+        range.max * 2u32.pow(self.max_doublings)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Skews timeouts towards the start of the range for servers with a weight above 1 and towards
+/// the end for weights below 1, so preferred servers usually time out, and get elected, first.
+/// Servers without a weight, or with one that isn't a positive number, draw uniformly.
+pub struct PriorityWeightedJitter {
+    weights: HashMap<ServerId, f64>,
+}
+impl PriorityWeightedJitter {
+    /// Every server draws uniformly until given a weight.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Weight `server_id`'s timeouts, replacing any weight set for it before.
+    pub fn weight(mut self, server_id: ServerId, weight: f64) -> Self {
+        let _ = self.weights.insert(server_id, weight);
+        self
+    }
+}
+impl ElectionJitter for PriorityWeightedJitter {
+    fn next_timeout(
+        &self,
+        range: ElectionTimeoutRange,
+        context: ElectionTimeoutContext,
+        rng: &mut ChaCha8Rng,
+    ) -> Duration {
+        let weight = self
+            .weights
+            .get(&context.server_id)
+            .copied()
+            .filter(|weight| weight.is_finite() && *weight > 0.0)
+            .unwrap_or(1.0);
+        let fraction = rng.gen::<f64>().powf(weight);
+        range.min + (range.max - range.min).mul_f64(fraction)
+    }
+}
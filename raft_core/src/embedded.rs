@@ -0,0 +1,132 @@
+use crate::common::*;
+use crate::rpc_messages::RpcMessage;
+use crate::state_machine::{Action, Event, FirstElectionTimeout, Node};
+use crate::system_clock::{Clock, Instant};
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+/// A [`Node`] kept together with the storage, config and rng it's handed along with every event,
+/// for hosts that drive it themselves rather than through a runtime's thread and transport.
+///
+/// The host calls [`EmbeddedNode::tick`] once the timeout of the latest [`Action::SetNextTimeout`]
+/// runs out, [`EmbeddedNode::step`] with every message that comes in and [`EmbeddedNode::handle`]
+/// with everything else, then carries out the returned actions in order: sending the RPCs, syncing
+/// [`EmbeddedNode::storage_mut`], applying entries, and reporting back with the events they ask for.
+#[derive(Debug)]
+pub struct EmbeddedNode<C: LogCommand, PS: PersistentStorage<C>> {
+    server_id: ServerId,
+    other_servers: HashSet<ServerId>,
+    /// Only `None` while an event is handed to it
+    node: Option<Node>,
+    storage: PS,
+    config: RaftConfig,
+    rng: ChaCha8Rng,
+    clock: Box<dyn Clock>,
+    _command: PhantomData<C>,
+}
+impl<C: LogCommand, PS: PersistentStorage<C>> EmbeddedNode<C, PS> {
+    /// Starts the node as a follower of the term in `storage`, see [`Node::new`]. The host hands it
+    /// its first tick once the returned timeout runs out, by `clock`, which the node reads the time
+    /// from with every event.
+    pub fn new(
+        server_id: ServerId,
+        other_servers: HashSet<ServerId>,
+        last_applied: LogIndex,
+        storage: PS,
+        config: RaftConfig,
+        mut rng: ChaCha8Rng,
+        clock: impl Clock + 'static,
+    ) -> (Self, FirstElectionTimeout) {
+        let (node, first_election_timeout) = Node::new(
+            server_id,
+            other_servers.clone(),
+            last_applied,
+            &storage,
+            &config,
+            &mut rng,
+            &clock,
+        );
+        (
+            EmbeddedNode {
+                server_id,
+                other_servers,
+                node: Some(node),
+                storage,
+                config,
+                rng,
+                clock: Box::new(clock),
+                _command: PhantomData,
+            },
+            first_election_timeout,
+        )
+    }
+
+    /// Hands the node the time, the timers that ran out by `now`, as read from its clock, fire
+    pub fn tick(&mut self, now: Instant) -> Result<Vec<Action<C>>, RaftError> {
+        self.handle(Event::Tick(now))
+    }
+
+    /// Hands the node a request or reply another server sent it
+    pub fn step(&mut self, message: RpcMessage<C>) -> Result<Vec<Action<C>>, RaftError> {
+        self.handle(Event::IncomingRpc(message))
+    }
+
+    /// Hands the node `event`, returning the actions to carry out in order. On an error the node
+    /// carries on as a follower and the event's actions are dropped, [`RaftError::recovery`] says
+    /// what the host should do about it.
+    pub fn handle(&mut self, event: Event<C>) -> Result<Vec<Action<C>>, RaftError> {
+        let node = self
+            .node
+            .take()
+            .expect("the node is put back after every event");
+        let commit_index = node.commit_index();
+        let last_applied = node.last_applied();
+        let mut actions = Vec::new();
+        match node.next(
+            event,
+            &mut self.storage,
+            &self.config,
+            &mut self.rng,
+            &*self.clock,
+            &mut actions,
+        ) {
+            Ok(node) => {
+                self.node = Some(node);
+                Ok(actions)
+            }
+            Err(error) => {
+                let (follower, _) = Node::stepped_down(
+                    self.server_id,
+                    self.other_servers.clone(),
+                    commit_index,
+                    last_applied,
+                    &self.storage,
+                    &self.config,
+                    &mut self.rng,
+                    &*self.clock,
+                );
+                self.node = Some(follower);
+                Err(error)
+            }
+        }
+    }
+
+    /// The node as of the last event
+    pub fn node(&self) -> &Node {
+        self.node
+            .as_ref()
+            .expect("the node is put back after every event")
+    }
+
+    /// The node's storage, to sync when an [`Action::SyncLog`] asks for it
+    pub fn storage_mut(&mut self) -> &mut PS {
+        &mut self.storage
+    }
+
+    /// The node's storage
+    pub fn storage(&self) -> &PS {
+        &self.storage
+    }
+}
@@ -0,0 +1,118 @@
+use crate::common::*;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaftNodeState {
+    Follower,
+    /// Asking for pre-votes, the term only goes up once a majority would vote for this node
+    PreCandidate,
+    Candidate,
+    Leader,
+}
+
+/// Snapshot of a node's state, published whenever any of it changes.
+/// `last_log_index` and `last_log_term` are 0 while the log is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaftStateEvent {
+    pub server_id: ServerId,
+    pub current_state: RaftNodeState,
+    pub current_term: TermIndex,
+    pub voted_for: Option<ServerId>,
+    pub leader_for_term: Option<ServerId>,
+    pub commit_index: LogIndex,
+    pub last_applied: LogIndex,
+    pub last_log_index: LogIndex,
+    pub last_log_term: TermIndex,
+}
+
+/// Events published by a Raft node to its event collector.
+/// `StateChanged` carries a snapshot of the node's state, the other variants describe
+/// the individual protocol steps that led there so observers don't have to infer them from snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RaftEvent {
+    /// Snapshot of the node's role, term, vote and log progress
+    StateChanged(RaftStateEvent),
+    /// The node timed out waiting for a leader and started an election for `term`
+    ElectionStarted {
+        server_id: ServerId,
+        term: TermIndex,
+    },
+    /// The node granted its vote for `term` to `candidate_id`
+    VoteGranted {
+        server_id: ServerId,
+        candidate_id: ServerId,
+        term: TermIndex,
+    },
+    /// The node turned down `candidate_id`'s pre-vote for the election it would start in `term`
+    PreVoteRejected {
+        server_id: ServerId,
+        candidate_id: ServerId,
+        term: TermIndex,
+    },
+    /// The node won the election for `term`
+    BecameLeader {
+        server_id: ServerId,
+        term: TermIndex,
+    },
+    /// An entry was written to the node's log
+    EntryAppended {
+        server_id: ServerId,
+        index: LogIndex,
+        term: TermIndex,
+    },
+    /// The node's commit index advanced to `index`
+    EntryCommitted {
+        server_id: ServerId,
+        index: LogIndex,
+    },
+    /// The entry at `index` was applied to the application's state machine
+    EntryApplied {
+        server_id: ServerId,
+        index: LogIndex,
+    },
+    /// The node replaced its log prefix with a snapshot
+    SnapshotInstalled {
+        server_id: ServerId,
+        last_included_index: LogIndex,
+        last_included_term: TermIndex,
+    },
+    /// The node started using a new cluster configuration
+    MembershipChanged {
+        server_id: ServerId,
+        members: HashSet<ServerId>,
+        learners: HashSet<ServerId>,
+    },
+    /// The Raft thread panicked and stopped, `location` is the `file:line` of the panic
+    NodeCrashed {
+        server_id: ServerId,
+        message: String,
+        location: Option<String>,
+    },
+    /// The Raft thread stopped because of `error`, which it could not recover from
+    NodeHalted {
+        server_id: ServerId,
+        error: RaftError,
+    },
+    /// The Raft thread was shut down through the node's handle and stopped
+    NodeStopped { server_id: ServerId },
+}
+impl RaftEvent {
+    /// The server that published this event
+    pub fn server_id(&self) -> ServerId {
+        match self {
+            RaftEvent::StateChanged(state) => state.server_id,
+            RaftEvent::ElectionStarted { server_id, .. }
+            | RaftEvent::VoteGranted { server_id, .. }
+            | RaftEvent::PreVoteRejected { server_id, .. }
+            | RaftEvent::BecameLeader { server_id, .. }
+            | RaftEvent::EntryAppended { server_id, .. }
+            | RaftEvent::EntryCommitted { server_id, .. }
+            | RaftEvent::EntryApplied { server_id, .. }
+            | RaftEvent::SnapshotInstalled { server_id, .. }
+            | RaftEvent::MembershipChanged { server_id, .. }
+            | RaftEvent::NodeCrashed { server_id, .. }
+            | RaftEvent::NodeHalted { server_id, .. }
+            | RaftEvent::NodeStopped { server_id } => *server_id,
+        }
+    }
+}
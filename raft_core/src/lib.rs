@@ -0,0 +1,53 @@
+/// The sans-io core of the Raft implementation: the state machine, its configuration and the
+/// messages servers exchange. It never touches threads, files or sockets, runtimes feed it events
+/// and carry out the actions it returns.
+#[deny(
+    bad_style,
+    dead_code,
+    improper_ctypes,
+    non_shorthand_field_patterns,
+    no_mangle_generic_items,
+    overflowing_literals,
+    path_statements,
+    patterns_in_fns_without_body,
+    unconditional_recursion,
+    unused,
+    unused_allocation,
+    unused_comparisons,
+    unused_parens,
+    while_true,
+    missing_debug_implementations,
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+mod common;
+mod election_jitter;
+mod embedded;
+mod events;
+mod membership;
+mod replication_batching;
+pub mod rpc_messages;
+mod state_machine;
+pub mod system_clock;
+
+pub use common::*;
+pub use election_jitter::ElectionJitter;
+pub use election_jitter::ElectionTimeoutContext;
+pub use election_jitter::ExponentialBackoffJitter;
+pub use election_jitter::PriorityWeightedJitter;
+pub use election_jitter::UniformJitter;
+pub use embedded::EmbeddedNode;
+pub use events::RaftEvent;
+pub use events::RaftNodeState;
+pub use events::RaftStateEvent;
+pub use rpc_messages::*;
+pub use state_machine::Action;
+pub use state_machine::Event;
+pub use state_machine::FirstElectionTimeout;
+pub use state_machine::Node;
+pub use state_machine::PeerProgress;
@@ -0,0 +1,149 @@
+use crate::common::*;
+use std::collections::HashSet;
+
+/// Most entries read from storage at once while looking for configuration entries
+const ENTRIES_PER_SCAN: usize = 1024;
+
+/// The cluster's voting members and learners as of the latest configuration in the log. A
+/// configuration takes effect as soon as its entry is appended, so it changes back if a leader cuts
+/// that entry off.
+#[derive(Debug, Clone)]
+pub(crate) struct Membership {
+    members: HashSet<ServerId>,
+    /// Servers the log is replicated to that don't vote
+    learners: HashSet<ServerId>,
+    /// Index of the entry the members come from, the snapshot's last included index once it was
+    /// compacted, `LogIndex(0)` for the members the node was started with
+    index: LogIndex,
+    /// The members the node was started with, in effect until its log or snapshot has a configuration
+    initial: HashSet<ServerId>,
+}
+impl Membership {
+    /// The latest configuration in `storage`, `initial` if it has none
+    pub(crate) fn load<C, PS>(initial: HashSet<ServerId>, storage: &PS) -> Self
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let mut membership = Membership {
+            members: initial.clone(),
+            learners: HashSet::new(),
+            index: LogIndex(0),
+            initial,
+        };
+        let _ = membership.reload(storage);
+        membership
+    }
+
+    pub(crate) fn members(&self) -> &HashSet<ServerId> {
+        &self.members
+    }
+
+    pub(crate) fn learners(&self) -> &HashSet<ServerId> {
+        &self.learners
+    }
+
+    pub(crate) fn is_learner(&self, server_id: ServerId) -> bool {
+        self.learners.contains(&server_id)
+    }
+
+    pub(crate) fn index(&self) -> LogIndex {
+        self.index
+    }
+
+    pub(crate) fn contains(&self, server_id: ServerId) -> bool {
+        self.members.contains(&server_id)
+    }
+
+    /// Every member except `server_id`
+    pub(crate) fn others(&self, server_id: ServerId) -> impl Iterator<Item = ServerId> + '_ {
+        self.members
+            .iter()
+            .copied()
+            .filter(move |member| *member != server_id)
+    }
+
+    /// Number of members that make a majority
+    pub(crate) fn quorum(&self) -> usize {
+        self.members.len() / 2 + 1
+    }
+
+    /// The leader appended `change` at `index`
+    pub(crate) fn apply(&mut self, index: LogIndex, change: MembershipChange) {
+        change.apply_to(&mut self.members);
+        change.apply_to_learners(&mut self.learners);
+        self.index = index;
+    }
+
+    /// Reads the configuration again after the log changed in a way that may have added or cut off
+    /// configuration entries. Returns whether the members or learners changed.
+    pub(crate) fn reload<C, PS>(&mut self, storage: &PS) -> bool
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let last_index = storage.last_entry_index().unwrap_or(LogIndex(0));
+        let (members, learners, index) = self.scan(storage, last_index);
+        let changed = members != self.members || learners != self.learners;
+        self.members = members;
+        self.learners = learners;
+        self.index = index;
+        changed
+    }
+
+    /// The members and learners as of the entry at `index`, only reads the log if the latest
+    /// configuration came after it
+    pub(crate) fn configuration_at<C, PS>(
+        &self,
+        storage: &PS,
+        index: LogIndex,
+    ) -> (HashSet<ServerId>, HashSet<ServerId>)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        if self.index <= index {
+            (self.members.clone(), self.learners.clone())
+        } else {
+            let (members, learners, _) = self.scan(storage, index);
+            (members, learners)
+        }
+    }
+
+    /// Replays the configuration entries after the snapshot up to and including `through`
+    fn scan<C, PS>(
+        &self,
+        storage: &PS,
+        through: LogIndex,
+    ) -> (HashSet<ServerId>, HashSet<ServerId>, LogIndex)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let (mut members, mut learners, mut index) = match storage.snapshot() {
+            Some(snapshot) => (
+                snapshot.members.clone(),
+                snapshot.learners.clone(),
+                snapshot.last_included_index,
+            ),
+            None => (self.initial.clone(), HashSet::new(), LogIndex(0)),
+        };
+        let mut next = index.next();
+        while next <= through {
+            let wanted = ((through.0 - next.0 + 1) as usize).min(ENTRIES_PER_SCAN);
+            let entries = storage.entries(next, wanted);
+            let Some(last_entry) = entries.last() else {
+                break;
+            };
+            next = last_entry.index.next();
+            for entry in entries {
+                if let LogEntryCommand::MembershipChange(change) = entry.command {
+                    change.apply_to(&mut members);
+                    change.apply_to_learners(&mut learners);
+                    index = entry.index;
+                }
+            }
+        }
+        (members, learners, index)
+    }
+}
@@ -0,0 +1,54 @@
+use crate::common::AppendEntriesBatchLimits;
+use std::time::Duration;
+
+/// Number of entries the leader may send a follower in one AppendEntries, adapted to how the
+/// follower keeps up: fast acks grow the batch, rejections and timeouts shrink it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AdaptiveBatchSize {
+    limits: AppendEntriesBatchLimits,
+    current: usize,
+}
+impl AdaptiveBatchSize {
+    pub(crate) fn new(limits: AppendEntriesBatchLimits) -> Self {
+        AdaptiveBatchSize {
+            limits,
+            current: limits.initial_entries,
+        }
+    }
+
+    /// Most entries to put in the next AppendEntries for this follower
+    pub(crate) fn limit(&self) -> usize {
+        self.current
+    }
+
+    /// Keeps the batch within `limits` from now on, they may have been changed on the running node
+    pub(crate) fn follow_limits(&mut self, limits: AppendEntriesBatchLimits) -> &mut Self {
+        if self.limits != limits {
+            self.limits = limits;
+            self.resize(self.current);
+        }
+        self
+    }
+
+    /// The follower acked after `round_trip`. Acks taking more than half the RPC timeout
+    /// leave the batch as is, the link is close to its limit already.
+    pub(crate) fn on_success(&mut self, round_trip: Duration, rpc_timeout: Duration) {
+        if round_trip <= rpc_timeout / 2 {
+            self.resize(self.current + (self.current / 4).max(1));
+        }
+    }
+
+    /// The follower turned the AppendEntries down
+    pub(crate) fn on_reject(&mut self) {
+        self.resize(self.current - self.current / 4);
+    }
+
+    /// No reply came back within the RPC timeout
+    pub(crate) fn on_timeout(&mut self) {
+        self.resize(self.current / 2);
+    }
+
+    fn resize(&mut self, entries: usize) {
+        self.current = entries.clamp(self.limits.min_entries, self.limits.max_entries);
+    }
+}
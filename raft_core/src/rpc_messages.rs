@@ -0,0 +1,430 @@
+use std::fmt::Debug;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::common::*;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub enum RpcMessage<C: LogCommand> {
+    Request(Request<C>),
+    Reply(ReplyTo),
+}
+
+impl<C: LogCommand> RpcMessage<C> {
+    pub fn from(&self) -> ServerId {
+        match self {
+            RpcMessage::Request(request) => request.from(),
+            RpcMessage::Reply(reply) => reply.from(),
+        }
+    }
+
+    pub fn to(&self) -> ServerId {
+        match self {
+            RpcMessage::Request(request) => request.to(),
+            RpcMessage::Reply(reply) => reply.to(),
+        }
+    }
+
+    pub fn request_id(&self) -> Uuid {
+        match self {
+            RpcMessage::Request(request) => match request {
+                Request::AppendEntries(ae) => ae.request_id,
+                Request::RequestVote(rv) | Request::PreVote(rv) => rv.request_id,
+                Request::InstallSnapshot(is) => is.request_id,
+                Request::TimeoutNow(tn) => tn.request_id,
+                Request::ForwardProposals(fp) => fp.request_id,
+            },
+            RpcMessage::Reply(reply) => match reply {
+                ReplyTo::AppendEntries(ae) => ae.request_id,
+                ReplyTo::RequestVote(rv) | ReplyTo::PreVote(rv) => rv.request_id,
+                ReplyTo::InstallSnapshot(is) => is.request_id,
+                ReplyTo::TimeoutNow(tn) => tn.request_id,
+                ReplyTo::ForwardProposals(fp) => fp.request_id,
+            },
+        }
+    }
+
+    pub fn append_entries(append_entries: AppendEntries<C>) -> Self {
+        RpcMessage::Request(Request::AppendEntries(append_entries))
+    }
+
+    pub fn request_vote(request_vote: RequestVote) -> Self {
+        RpcMessage::Request(Request::RequestVote(request_vote))
+    }
+
+    pub fn vote(vote: Vote) -> Self {
+        RpcMessage::Reply(ReplyTo::RequestVote(vote))
+    }
+
+    pub fn pre_vote(pre_vote: RequestVote) -> Self {
+        RpcMessage::Request(Request::PreVote(pre_vote))
+    }
+
+    pub fn pre_vote_reply(vote: Vote) -> Self {
+        RpcMessage::Reply(ReplyTo::PreVote(vote))
+    }
+
+    pub fn ack_append_entries(append_entries_ack: AppendEntriesAck) -> Self {
+        RpcMessage::Reply(ReplyTo::AppendEntries(append_entries_ack))
+    }
+
+    pub fn install_snapshot(install_snapshot: InstallSnapshot) -> Self {
+        RpcMessage::Request(Request::InstallSnapshot(install_snapshot))
+    }
+
+    pub fn ack_install_snapshot(install_snapshot_ack: InstallSnapshotAck) -> Self {
+        RpcMessage::Reply(ReplyTo::InstallSnapshot(install_snapshot_ack))
+    }
+
+    pub fn timeout_now(timeout_now: TimeoutNow) -> Self {
+        RpcMessage::Request(Request::TimeoutNow(timeout_now))
+    }
+
+    pub fn ack_timeout_now(timeout_now_ack: TimeoutNowAck) -> Self {
+        RpcMessage::Reply(ReplyTo::TimeoutNow(timeout_now_ack))
+    }
+
+    pub fn forward_proposals(forward_proposals: ForwardProposals<C>) -> Self {
+        RpcMessage::Request(Request::ForwardProposals(forward_proposals))
+    }
+
+    pub fn ack_forward_proposals(forward_proposals_ack: ForwardProposalsAck) -> Self {
+        RpcMessage::Reply(ReplyTo::ForwardProposals(forward_proposals_ack))
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct AppendEntries<C: LogCommand> {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    pub prev_log_term: TermIndex,
+    pub prev_log_index: LogIndex,
+    pub entries: SharedEntries<C>,
+    pub leader_commit: LogIndex,
+}
+
+/// Log entries carried by an [`AppendEntries`]. Clones and sub-slices point at the same buffer,
+/// so a leader replicating one batch to every follower copies a pointer instead of the payloads.
+#[derive(Clone)]
+pub struct SharedEntries<C: LogCommand> {
+    /// `None` for no entries, so heartbeats don't allocate
+    buffer: Option<Arc<[LogEntry<C>]>>,
+    range: Range<usize>,
+}
+impl<C: LogCommand> SharedEntries<C> {
+    pub fn empty() -> Self {
+        SharedEntries {
+            buffer: None,
+            range: 0..0,
+        }
+    }
+
+    /// The entries in `range` of this slice, sharing the buffer
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "BUG: entry range {:?} out of bounds for {} entries",
+            range,
+            self.len()
+        );
+        SharedEntries {
+            buffer: self.buffer.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+}
+impl<C: LogCommand> From<Vec<LogEntry<C>>> for SharedEntries<C> {
+    fn from(entries: Vec<LogEntry<C>>) -> Self {
+        if entries.is_empty() {
+            return Self::empty();
+        }
+        let range = 0..entries.len();
+        SharedEntries {
+            buffer: Some(entries.into()),
+            range,
+        }
+    }
+}
+impl<C: LogCommand> FromIterator<LogEntry<C>> for SharedEntries<C> {
+    fn from_iter<I: IntoIterator<Item = LogEntry<C>>>(entries: I) -> Self {
+        entries.into_iter().collect::<Vec<_>>().into()
+    }
+}
+impl<C: LogCommand> Deref for SharedEntries<C> {
+    type Target = [LogEntry<C>];
+
+    fn deref(&self) -> &Self::Target {
+        match &self.buffer {
+            Some(buffer) => &buffer[self.range.clone()],
+            None => &[],
+        }
+    }
+}
+impl<C: LogCommand> Default for SharedEntries<C> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+impl<C: LogCommand> PartialEq for SharedEntries<C> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+impl<C: LogCommand> Eq for SharedEntries<C> {}
+impl<C: LogCommand> Serialize for SharedEntries<C> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+impl<'de, C: LogCommand> Deserialize<'de> for SharedEntries<C> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<LogEntry<C>>::deserialize(deserializer).map(Self::from)
+    }
+}
+impl<C: LogCommand> Debug for SharedEntries<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RequestVote {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    pub last_log_index: LogIndex,
+    pub last_log_term: TermIndex,
+    /// Sent by the server the leader hands leadership to, receivers that still hear from that
+    /// leader vote anyway
+    pub leadership_transfer: bool,
+}
+
+/// Sent by the leader instead of an [`AppendEntries`] to a follower that needs entries the leader
+/// already compacted into its snapshot. The snapshot goes in chunks, one per message, that the
+/// follower puts back together before installing it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InstallSnapshot {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    /// The snapshot's metadata, with `data` holding only this chunk's bytes
+    pub snapshot: Snapshot,
+    /// Where this chunk's bytes start in the snapshot's data
+    pub offset: u64,
+    /// Set on the last chunk, the follower installs the snapshot once it has it
+    pub done: bool,
+}
+
+/// Sent by a leader handing leadership over to the follower it caught up with its log, which
+/// starts an election right away rather than waiting for its election timeout (§3.10)
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TimeoutNow {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+}
+
+/// Client proposals a server that isn't leading hands on to the leader it knows of, rather than
+/// turning them down. The runtime tracking the proposals answers it, it's never handed to the
+/// [`crate::Node`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ForwardProposals<C: LogCommand> {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    /// Appended at consecutive indexes, in this order, or not at all
+    pub proposals: Vec<ClientProposal<C>>,
+    /// Times the proposals were forwarded so far, this one included
+    pub hops: u32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub enum Request<C: LogCommand> {
+    AppendEntries(AppendEntries<C>),
+    RequestVote(RequestVote),
+    /// Asks whether the receiver would vote for the sender in `term` without starting that term.
+    /// The sender's current term is still `term - 1`, so receivers don't step down for it.
+    PreVote(RequestVote),
+    InstallSnapshot(InstallSnapshot),
+    TimeoutNow(TimeoutNow),
+    ForwardProposals(ForwardProposals<C>),
+}
+impl<C: LogCommand> Request<C> {
+    pub fn from(&self) -> ServerId {
+        match self {
+            Request::AppendEntries(ae) => ae.from,
+            Request::RequestVote(rv) | Request::PreVote(rv) => rv.from,
+            Request::InstallSnapshot(is) => is.from,
+            Request::TimeoutNow(tn) => tn.from,
+            Request::ForwardProposals(fp) => fp.from,
+        }
+    }
+    pub fn to(&self) -> ServerId {
+        match self {
+            Request::AppendEntries(ae) => ae.to,
+            Request::RequestVote(rv) | Request::PreVote(rv) => rv.to,
+            Request::InstallSnapshot(is) => is.to,
+            Request::TimeoutNow(tn) => tn.to,
+            Request::ForwardProposals(fp) => fp.to,
+        }
+    }
+    pub fn term(&self) -> TermIndex {
+        match self {
+            Request::AppendEntries(ae) => ae.term,
+            Request::RequestVote(rv) | Request::PreVote(rv) => rv.term,
+            Request::InstallSnapshot(is) => is.term,
+            Request::TimeoutNow(tn) => tn.term,
+            Request::ForwardProposals(fp) => fp.term,
+        }
+    }
+    pub fn request_id(&self) -> Uuid {
+        match self {
+            Request::AppendEntries(ae) => ae.request_id,
+            Request::RequestVote(rv) | Request::PreVote(rv) => rv.request_id,
+            Request::InstallSnapshot(is) => is.request_id,
+            Request::TimeoutNow(tn) => tn.request_id,
+            Request::ForwardProposals(fp) => fp.request_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AppendEntriesAck {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    pub success: bool,
+    /// Where the follower's log stops matching, set when it turned the entries down for that
+    /// rather than for a stale term
+    pub conflict: Option<LogConflict>,
+}
+
+/// Where a follower's log differs from the leader's at the AppendEntries' `prev_log_index`. The
+/// leader skips back past the whole conflicting term in one round trip instead of one entry per
+/// round trip (§5.3).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LogConflict {
+    /// Term of the follower's entry at `prev_log_index`, `None` if its log doesn't reach that far
+    pub term: Option<TermIndex>,
+    /// First index the follower has of `term`, or the index right after its last entry if its
+    /// log doesn't reach `prev_log_index`
+    pub first_index: LogIndex,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Vote {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    pub vote_granted: bool,
+}
+
+/// A follower's reply to an [`InstallSnapshot`] chunk. It has every entry the snapshot covers once
+/// it acks the last chunk with `next_offset` at the end of the data.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InstallSnapshotAck {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    /// Where the follower wants the next chunk to start: the end of what it put together so far, 0 if
+    /// it has nothing of this snapshot
+    pub next_offset: u64,
+}
+
+/// A follower's reply to a [`TimeoutNow`], sent before it starts the election
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TimeoutNowAck {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+}
+
+/// Where the leader appended the proposals of a [`ForwardProposals`], or why it didn't
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ForwardedProposalsOutcome {
+    /// The proposals are in the leader's log from `first_index` on, entries of `term`
+    Appended {
+        first_index: LogIndex,
+        term: TermIndex,
+    },
+    /// The receiver isn't leading and couldn't forward them any further
+    NotLeader,
+    /// The leader's application is too far behind, see [`RaftConfig::max_unapplied_entries`]
+    Busy,
+}
+
+/// The reply to a [`ForwardProposals`], from the leader or relayed back by the servers it passed
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ForwardProposalsAck {
+    pub request_id: Uuid,
+    pub from: ServerId,
+    pub to: ServerId,
+    pub term: TermIndex,
+    pub outcome: ForwardedProposalsOutcome,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ReplyTo {
+    AppendEntries(AppendEntriesAck),
+    RequestVote(Vote),
+    /// The receiver's answer to a [`Request::PreVote`], `term` is its own current term
+    PreVote(Vote),
+    InstallSnapshot(InstallSnapshotAck),
+    TimeoutNow(TimeoutNowAck),
+    ForwardProposals(ForwardProposalsAck),
+}
+impl ReplyTo {
+    pub fn from(&self) -> ServerId {
+        match self {
+            ReplyTo::AppendEntries(ae) => ae.from,
+            ReplyTo::RequestVote(rv) | ReplyTo::PreVote(rv) => rv.from,
+            ReplyTo::InstallSnapshot(is) => is.from,
+            ReplyTo::TimeoutNow(tn) => tn.from,
+            ReplyTo::ForwardProposals(fp) => fp.from,
+        }
+    }
+    pub fn to(&self) -> ServerId {
+        match self {
+            ReplyTo::AppendEntries(ae) => ae.to,
+            ReplyTo::RequestVote(rv) | ReplyTo::PreVote(rv) => rv.to,
+            ReplyTo::InstallSnapshot(is) => is.to,
+            ReplyTo::TimeoutNow(tn) => tn.to,
+            ReplyTo::ForwardProposals(fp) => fp.to,
+        }
+    }
+    pub fn term(&self) -> TermIndex {
+        match self {
+            ReplyTo::AppendEntries(ae) => ae.term,
+            ReplyTo::RequestVote(rv) | ReplyTo::PreVote(rv) => rv.term,
+            ReplyTo::InstallSnapshot(is) => is.term,
+            ReplyTo::TimeoutNow(tn) => tn.term,
+            ReplyTo::ForwardProposals(fp) => fp.term,
+        }
+    }
+    pub fn request_id(&self) -> Uuid {
+        match self {
+            ReplyTo::AppendEntries(ae) => ae.request_id,
+            ReplyTo::RequestVote(rv) | ReplyTo::PreVote(rv) => rv.request_id,
+            ReplyTo::InstallSnapshot(is) => is.request_id,
+            ReplyTo::TimeoutNow(tn) => tn.request_id,
+            ReplyTo::ForwardProposals(fp) => fp.request_id,
+        }
+    }
+}
@@ -0,0 +1,3260 @@
+use self::state_defs::*;
+
+/// Implementation of Raft consensus protocol
+/// See: <https://raft.github.io/raft.pdf> for details
+/// Implements leader election, log replication, log compaction and single server membership changes
+use super::common::*;
+use super::rpc_messages::*;
+use crate::election_jitter::ElectionTimeoutContext;
+use crate::events::RaftEvent;
+use crate::membership::Membership;
+use crate::replication_batching::AdaptiveBatchSize;
+use crate::system_clock::{Clock, Instant};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::time::Duration;
+use tracing::debug;
+use tracing::info;
+use tracing::trace;
+use uuid::Uuid;
+
+/// Most committed entries handed to the application in one [`Action::ApplyLogEntries`], so a node
+/// catching up on a long log doesn't load all of it at once
+const MAX_ENTRIES_PER_APPLY: usize = 1024;
+
+/// Inputs to [`Node::next`]: time passing, messages from peers and work handed over by the runtime.
+#[derive(Debug, Clone)]
+pub enum Event<C: LogCommand> {
+    /// The clock reached this instant, timers that expired by then fire
+    Tick(Instant),
+    /// The application applied every entry up to and including this index, in reply to [`Action::ApplyLogEntries`]
+    LogEntryAppliedByApplication(LogIndex),
+    /// A request or reply from another server
+    IncomingRpc(RpcMessage<C>),
+    /// Commands clients asked to replicate since the last event, only the leader appends them to the log.
+    /// They arrive together so the leader appends, syncs and replicates them in one round.
+    ClientProposals(Vec<ClientProposal<C>>),
+    /// The log is durable up to and including this index, in reply to [`Action::SyncLog`]
+    LogSynced(LogIndex),
+    /// The application's state serialized in reply to [`Action::TakeSnapshot`], the log up to it can be compacted
+    SnapshotTaken(Snapshot),
+    /// An operator asked to add or remove a server, answered with the [`Action::MembershipChangeFinished`]
+    /// carrying the same `id`. Only the leader takes it on.
+    MembershipChangeRequested { id: Uuid, change: MembershipChange },
+    /// A client wants to read the application's state without going through the log, answered with
+    /// the [`Action::LeaseReadFinished`] carrying the same `id`. Only a leader holding its lease serves it.
+    LeaseReadRequested { id: Uuid },
+    /// An operator asked the leader to give up leadership, handing it to `transfer_to` if set,
+    /// answered with the [`Action::StepDownFinished`] carrying the same `id`
+    StepDownRequested {
+        id: Uuid,
+        transfer_to: Option<ServerId>,
+    },
+    /// The runtime handed the node no events for `paused_for`, an operator paused it. That time
+    /// doesn't count toward the election timer, the leader may well have reached the node in its
+    /// transport meanwhile.
+    Resumed { paused_for: Duration },
+}
+
+/// Outputs of [`Node::next`], the runtime carries them out in order.
+#[derive(Debug, Clone)]
+pub enum Action<C: LogCommand> {
+    /// Hand the node an [`Event::Tick`] after waiting at most this long
+    SetNextTimeout(Duration),
+    /// Apply these committed entries to the application's state, in order, and report back with
+    /// [`Event::LogEntryAppliedByApplication`]. The node hands over more once it has.
+    ApplyLogEntries(Vec<LogEntry<C>>),
+    /// Send a request or reply to another server
+    OutgoingRpc(RpcMessage<C>),
+    /// Tell observers what the node just did
+    PublishEvent(RaftEvent),
+    /// Sync storage and report back with [`Event::LogSynced`]. The leader pushes this after the
+    /// AppendEntries carrying the same entries, so followers write them while the leader does.
+    SyncLog(LogIndex),
+    /// Serialize the application's state, which has every entry up to `last_included_index` applied,
+    /// and report back with [`Event::SnapshotTaken`] along with `members` and `learners`. Comes before
+    /// any entries handed over after it.
+    TakeSnapshot {
+        last_included_index: LogIndex,
+        last_included_term: TermIndex,
+        members: HashSet<ServerId>,
+        learners: HashSet<ServerId>,
+    },
+    /// Replace the application's state with a snapshot the leader sent, in place of the entries it covers
+    RestoreSnapshot(Snapshot),
+    /// The membership change requested with this `id` committed, or won't go through
+    MembershipChangeFinished {
+        id: Uuid,
+        outcome: MembershipChangeOutcome,
+    },
+    /// The read requested with this `id` can be served once the application applied the entry at
+    /// the index in `outcome`, or can't be served here
+    LeaseReadFinished { id: Uuid, outcome: LeaseReadOutcome },
+    /// The leader asked to step down with this `id` did, or won't
+    StepDownFinished { id: Uuid, outcome: StepDownOutcome },
+}
+
+/// How far the leader believes a follower's log has been replicated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerProgress {
+    /// Index of the next entry the leader will send to the follower
+    pub next_index: LogIndex,
+    /// Highest index known to be replicated on the follower
+    pub match_index: LogIndex,
+    /// Most entries the leader currently sends the follower in one AppendEntries
+    pub append_entries_batch_limit: usize,
+    /// When the follower last replied to this leader, `None` until it has
+    pub last_reply: Option<Instant>,
+    /// AppendEntries and snapshot chunks sent to the follower that it hasn't answered yet
+    pub in_flight: usize,
+}
+
+/// A Raft server in one of its roles. Handing it an [`Event`] consumes it and returns the
+/// node in its next role, along with the [`Action`]s the runtime has to carry out.
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// Boxed, the leader keeps per follower state the other roles don't
+    Leader(Box<NodeState<Leader>>),
+    Follower(NodeState<Follower>),
+    /// A follower whose election timeout expired, asking for pre-votes before it starts an election
+    PreCandidate(NodeState<PreCandidate>),
+    Candidate(NodeState<Candidate>),
+}
+impl Node {
+    /// A follower that just started, with the timeout after which it first starts an election.
+    /// `last_applied` is where the application's state already is, entries up to it aren't handed over again.
+    /// The cluster's members are the latest configuration in `storage`, `other_servers` and this
+    /// node until there is one.
+    pub fn new<C: LogCommand>(
+        server_id: ServerId,
+        other_servers: HashSet<ServerId>,
+        last_applied: LogIndex,
+        storage: &impl PersistentStorage<C>,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        clock: &dyn Clock,
+    ) -> (Self, FirstElectionTimeout) {
+        let (initial_state, first_timer) = NodeState::<Follower>::new(
+            server_id,
+            other_servers,
+            last_applied,
+            storage,
+            config,
+            rng,
+            clock.now(),
+        );
+
+        (initial_state.into(), first_timer)
+    }
+
+    /// A follower picking up after the node lost its state to a [`RaftError`]. Everything else
+    /// it needs is in storage or comes back from the leader, the commit index only moves forward.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stepped_down<C: LogCommand>(
+        server_id: ServerId,
+        other_servers: HashSet<ServerId>,
+        commit_index: LogIndex,
+        last_applied: LogIndex,
+        storage: &impl PersistentStorage<C>,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        clock: &dyn Clock,
+    ) -> (Self, FirstElectionTimeout) {
+        let (mut follower_state, first_timer) = NodeState::<Follower>::new(
+            server_id,
+            other_servers,
+            last_applied,
+            storage,
+            config,
+            rng,
+            clock.now(),
+        );
+        follower_state.commit_index = follower_state.commit_index.max(commit_index);
+
+        (follower_state.into(), first_timer)
+    }
+
+    fn server_id(&self) -> ServerId {
+        match self {
+            Node::Leader(state) => state.server_id,
+            Node::Follower(state) => state.server_id,
+            Node::Candidate(state) => state.server_id,
+            Node::PreCandidate(state) => state.server_id,
+        }
+    }
+
+    /// Highest log index known to be committed
+    pub fn commit_index(&self) -> LogIndex {
+        match self {
+            Node::Leader(state) => state.commit_index,
+            Node::Follower(state) => state.commit_index,
+            Node::Candidate(state) => state.commit_index,
+            Node::PreCandidate(state) => state.commit_index,
+        }
+    }
+
+    /// Highest log index the application applied
+    pub fn last_applied(&self) -> LogIndex {
+        match self {
+            Node::Leader(state) => state.last_applied,
+            Node::Follower(state) => state.last_applied,
+            Node::Candidate(state) => state.last_applied,
+            Node::PreCandidate(state) => state.last_applied,
+        }
+    }
+
+    /// The rest of the cluster as of the latest configuration in the log
+    pub fn other_servers(&self) -> HashSet<ServerId> {
+        match self {
+            Node::Leader(state) => state.membership.others(state.server_id).collect(),
+            Node::Follower(state) => state.membership.others(state.server_id).collect(),
+            Node::Candidate(state) => state.membership.others(state.server_id).collect(),
+            Node::PreCandidate(state) => state.membership.others(state.server_id).collect(),
+        }
+    }
+
+    /// Whether this server votes or learns in the latest configuration in the log
+    pub fn in_configuration(&self) -> bool {
+        let (server_id, membership) = match self {
+            Node::Leader(state) => (state.server_id, &state.membership),
+            Node::Follower(state) => (state.server_id, &state.membership),
+            Node::Candidate(state) => (state.server_id, &state.membership),
+            Node::PreCandidate(state) => (state.server_id, &state.membership),
+        };
+        membership.contains(server_id) || membership.is_learner(server_id)
+    }
+
+    /// The servers the log is replicated to that don't vote, as of the latest configuration in the log
+    pub fn learners(&self) -> HashSet<ServerId> {
+        match self {
+            Node::Leader(state) => state.membership.learners().clone(),
+            Node::Follower(state) => state.membership.learners().clone(),
+            Node::Candidate(state) => state.membership.learners().clone(),
+            Node::PreCandidate(state) => state.membership.learners().clone(),
+        }
+    }
+
+    /// The leader's view of how far each follower has replicated its log, empty for other roles
+    pub fn replication_progress(&self) -> HashMap<ServerId, PeerProgress> {
+        match self {
+            Node::Leader(state) => state
+                .inner
+                .batch_sizes
+                .iter()
+                .map(|(server_id, batch_size)| {
+                    (
+                        *server_id,
+                        PeerProgress {
+                            next_index: state
+                                .inner
+                                .next_index
+                                .get(server_id)
+                                .copied()
+                                .unwrap_or(LogIndex(0)),
+                            match_index: state
+                                .inner
+                                .match_index
+                                .get(server_id)
+                                .copied()
+                                .unwrap_or(LogIndex(0)),
+                            append_entries_batch_limit: batch_size.limit(),
+                            last_reply: state.inner.last_reply_received.get(server_id).copied(),
+                            in_flight: state
+                                .inner
+                                .in_flight_append_entries
+                                .values()
+                                .filter(|in_flight| in_flight.to == *server_id)
+                                .count(),
+                        },
+                    )
+                })
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// Whether the node appends proposals to its log, it leads and isn't handing leadership over
+    pub fn accepts_proposals(&self) -> bool {
+        matches!(self, Node::Leader(state) if state.inner.leadership_transfer.is_none())
+    }
+
+    /// The leader of the current term as far as this node knows, itself when it is leader
+    pub fn leader_id(&self) -> Option<ServerId> {
+        match self {
+            Node::Leader(state) => Some(state.server_id),
+            Node::Follower(state) => state.inner.leader_id,
+            Node::Candidate(_) => None,
+            Node::PreCandidate(_) => None,
+        }
+    }
+
+    fn take_snapshot_if_due<C: LogCommand>(
+        &mut self,
+        storage: &impl PersistentStorage<C>,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) {
+        match self {
+            Node::Leader(state) => state.take_snapshot_if_due(storage, config, actions),
+            Node::Follower(state) => state.take_snapshot_if_due(storage, config, actions),
+            Node::Candidate(state) => state.take_snapshot_if_due(storage, config, actions),
+            Node::PreCandidate(state) => state.take_snapshot_if_due(storage, config, actions),
+        }
+    }
+
+    fn hand_committed_entries_to_application<C: LogCommand>(
+        &mut self,
+        storage: &impl PersistentStorage<C>,
+        actions: &mut Vec<Action<C>>,
+    ) {
+        match self {
+            Node::Leader(state) => state.hand_committed_entries_to_application(storage, actions),
+            Node::Follower(state) => state.hand_committed_entries_to_application(storage, actions),
+            Node::Candidate(state) => state.hand_committed_entries_to_application(storage, actions),
+            Node::PreCandidate(state) => {
+                state.hand_committed_entries_to_application(storage, actions)
+            }
+        }
+    }
+
+    /// A leader that removed itself from the cluster hands over once the removal committed, as a
+    /// follower that isn't a member it never starts another election
+    fn step_down_if_removed<C: LogCommand>(
+        self,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Self {
+        match self {
+            Node::Leader(state)
+                if !state.membership.contains(state.server_id)
+                    && state.commit_index >= state.membership.index() =>
+            {
+                info!(
+                    "{:?}: Stepping down, the configuration removing us committed at {:?}",
+                    state.server_id,
+                    state.membership.index()
+                );
+                state.step_down(config, rng, actions).into()
+            }
+            node => node,
+        }
+    }
+
+    fn update_clock(&mut self, now: Instant) {
+        match self {
+            Node::Leader(state) => state.current_time = now,
+            Node::Follower(state) => state.current_time = now,
+            Node::Candidate(state) => state.current_time = now,
+            Node::PreCandidate(state) => state.current_time = now,
+        }
+    }
+
+    fn if_rpc_message_has_higher_term_become_follower<C: LogCommand>(
+        self,
+        storage: &mut impl PersistentStorage<C>,
+        event: &Event<C>,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Self, RaftError> {
+        let (should_become_follower, new_term) = match event {
+            // A pre-vote's term is the one its sender would start, its own term is still the one before
+            Event::IncomingRpc(RpcMessage::Request(Request::PreVote(_))) => {
+                (false, storage.current_term())
+            }
+            Event::IncomingRpc(RpcMessage::Request(r)) => {
+                (r.term() > storage.current_term(), r.term())
+            }
+            Event::IncomingRpc(RpcMessage::Reply(r)) => {
+                (r.term() > storage.current_term(), r.term())
+            }
+            _ => (false, storage.current_term()),
+        };
+
+        if should_become_follower {
+            info!(
+                "{:?}: Becoming follower because we've observed a higher term {:?} than ours {:?}",
+                self.server_id(),
+                new_term,
+                storage.current_term()
+            );
+            storage.persist_term_and_vote(new_term, None)?;
+            let mut follower_state: NodeState<Follower> = match self {
+                Node::Leader(mut state) => {
+                    state.finish_membership_change(
+                        Err(MembershipChangeError::LeadershipLost),
+                        actions,
+                    );
+                    state.finish_leadership_transfer(Ok(()), actions);
+                    state.transition_to()
+                }
+                Node::Follower(state) => state,
+                Node::Candidate(state) => state.transition_to(),
+                Node::PreCandidate(state) => state.transition_to(),
+            };
+
+            // Ensure we don't have a leader ID set, if we were already follower this would be set
+            // but since there is a newer term there might be a new leader
+            // if so new leader will send us a heartbeat eventually and we'll update this
+            follower_state.inner.leader_id = None;
+            follower_state.inner.last_heard_from_leader = None;
+            let election_timeout = follower_state.reset_election_timer(config, rng);
+            actions.push(Action::SetNextTimeout(election_timeout));
+            Ok(follower_state.into())
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Whether this node leads, or follows a leader it heard from within the minimum election timeout
+    fn hears_from_leader(&self, config: &RaftConfig) -> bool {
+        match self {
+            Node::Leader(_) => true,
+            Node::Follower(state) => state.inner.last_heard_from_leader.is_some_and(|heard_at| {
+                state.current_time < heard_at + config.election_timeout.min
+            }),
+            Node::Candidate(_) | Node::PreCandidate(_) => false,
+        }
+    }
+
+    fn turn_down_candidate<C: LogCommand>(
+        &self,
+        storage: &mut impl PersistentStorage<C>,
+        req: RequestVote,
+        pre_vote: bool,
+        actions: &mut Vec<Action<C>>,
+    ) {
+        match self {
+            Node::Leader(state) => state.turn_down_candidate(storage, req, pre_vote, actions),
+            Node::Follower(state) => state.turn_down_candidate(storage, req, pre_vote, actions),
+            Node::Candidate(state) => state.turn_down_candidate(storage, req, pre_vote, actions),
+            Node::PreCandidate(state) => state.turn_down_candidate(storage, req, pre_vote, actions),
+        }
+    }
+
+    /// Handles `event`, appending the resulting actions to `actions`. The caller owns the buffer
+    /// so the Raft thread can reuse one allocation for every loop iteration.
+    pub fn next<C: LogCommand, PS: PersistentStorage<C>>(
+        mut self,
+        event: Event<C>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        clock: &dyn Clock,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Self, RaftError> {
+        self.update_clock(clock.now());
+
+        // A node that hears from a leader ignores candidates, whatever their term, so a server the
+        // cluster moved on without can't unseat a leader that's doing fine (§4.2.3). The server
+        // the leader handed over to is the exception, the leader asked for that election.
+        let sticky = config.leader_stickiness && self.hears_from_leader(config);
+        let event = match event {
+            Event::IncomingRpc(RpcMessage::Request(Request::RequestVote(req)))
+                if sticky && !req.leadership_transfer =>
+            {
+                self.turn_down_candidate(storage, req, false, actions);
+                return Ok(self);
+            }
+            Event::IncomingRpc(RpcMessage::Request(Request::PreVote(req))) if sticky => {
+                self.turn_down_candidate(storage, req, true, actions);
+                return Ok(self);
+            }
+            event => event,
+        };
+
+        let mut node = match self
+            .if_rpc_message_has_higher_term_become_follower(storage, &event, config, rng, actions)?
+        {
+            Self::Leader(state) => state.handle_event(event, storage, config, rng, actions),
+            Self::Follower(state) => state.handle_event(event, storage, config, rng, actions),
+            Self::Candidate(state) => state.handle_event(event, storage, config, rng, actions),
+            Self::PreCandidate(state) => state.handle_event(event, storage, config, rng, actions),
+        }?;
+        if let Node::Leader(state) = &mut node {
+            state.advance_membership_change(storage, config, actions)?;
+        }
+        let mut node = node.step_down_if_removed(config, rng, actions);
+        // The snapshot has to be taken before the application gets any further
+        node.take_snapshot_if_due(storage, config, actions);
+        node.hand_committed_entries_to_application(storage, actions);
+        Ok(node)
+    }
+}
+impl From<NodeState<Leader>> for Node {
+    fn from(state: NodeState<Leader>) -> Self {
+        Node::Leader(Box::new(state))
+    }
+}
+impl From<NodeState<Follower>> for Node {
+    fn from(state: NodeState<Follower>) -> Self {
+        Node::Follower(state)
+    }
+}
+impl From<NodeState<PreCandidate>> for Node {
+    fn from(state: NodeState<PreCandidate>) -> Self {
+        Node::PreCandidate(state)
+    }
+}
+impl From<NodeState<Candidate>> for Node {
+    fn from(state: NodeState<Candidate>) -> Self {
+        Node::Candidate(state)
+    }
+}
+
+/// State every role keeps, `S` holds what only that role needs
+#[derive(Debug, Clone)]
+pub struct NodeState<S: State> {
+    server_id: ServerId,
+    start_time: Instant,
+    current_time: Instant,
+    membership: Membership,
+    commit_index: LogIndex,
+    last_applied: LogIndex,
+    /// Highest index handed to the application, ahead of `last_applied` until it reports back
+    handed_to_application: LogIndex,
+    /// Waiting for the [`Event::SnapshotTaken`] answering an [`Action::TakeSnapshot`]
+    snapshot_requested: bool,
+    pub(crate) inner: S,
+}
+
+trait Transitions {
+    fn handle_event<C, PS>(
+        self,
+        event: Event<C>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>;
+}
+
+trait CanTransitionTo<NewState: State> {
+    fn transition_to(self) -> NodeState<NewState>;
+}
+
+trait ElectionTimer {
+    fn reset_election_timer(&mut self, config: &RaftConfig, rng: &mut ChaCha8Rng) -> Duration;
+
+    /// Moves the running election timer back by `by`, returning how long is left of it
+    fn postpone_election_timer(&mut self, by: Duration) -> Duration;
+}
+macro_rules! has_election_timer {
+    ($state:ident) => {
+        impl ElectionTimer for NodeState<$state> {
+            fn reset_election_timer(
+                &mut self,
+                config: &RaftConfig,
+                rng: &mut ChaCha8Rng,
+            ) -> Duration {
+                let context = ElectionTimeoutContext {
+                    server_id: self.server_id,
+                    elections_without_leader: self.inner.elections_without_leader,
+                };
+                let election_timeout =
+                    config
+                        .election_jitter
+                        .next_timeout(config.election_timeout, context, rng);
+
+                self.inner.election_timeout = election_timeout;
+                self.inner.last_election_timer_started = self.current_time;
+
+                election_timeout
+            }
+
+            fn postpone_election_timer(&mut self, by: Duration) -> Duration {
+                self.inner.last_election_timer_started += by;
+                (self.inner.last_election_timer_started + self.inner.election_timeout)
+                    .saturating_duration_since(self.current_time)
+            }
+        }
+    };
+}
+
+mod state_defs {
+    use crate::common::LogIndex;
+    use crate::common::MembershipChange;
+    use crate::common::ServerId;
+    use crate::common::Snapshot;
+    use crate::replication_batching::AdaptiveBatchSize;
+    use crate::system_clock::Instant;
+
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+    use std::fmt::Debug;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[derive(Debug, Clone)]
+    struct Priv {}
+
+    pub trait State: Debug {}
+
+    /// Builds a state from the one the node leaves, `now` being the time of the transition
+    pub(crate) trait FromState<S: State> {
+        fn from_state(state: S, now: Instant) -> Self;
+    }
+
+    /// An AppendEntries waiting for a reply
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct InFlightAppendEntries {
+        pub(crate) to: ServerId,
+        pub(crate) sent_at: Instant,
+        pub(crate) prev_log_index: LogIndex,
+        /// Index of the last entry sent, the follower has everything up to here once it acks
+        pub(crate) last_index: LogIndex,
+        /// The chunk of an InstallSnapshot rather than an AppendEntries, the snapshot covers
+        /// everything up to `last_index`
+        pub(crate) snapshot: Option<SnapshotChunkSent>,
+    }
+    impl InFlightAppendEntries {
+        /// An AppendEntries with entries, which takes up room in the follower's replication window
+        pub(crate) fn carries_entries(&self) -> bool {
+            self.snapshot.is_none() && self.last_index > self.prev_log_index
+        }
+    }
+
+    /// The chunk of the snapshot's data an InstallSnapshot carried
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct SnapshotChunkSent {
+        /// Where the chunk ends in the data, the follower acks it with this as its next offset
+        pub(crate) end: u64,
+        /// The last chunk of the snapshot
+        pub(crate) done: bool,
+    }
+
+    /// How far sending a snapshot to a follower got, a lost chunk is sent again from here
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct SnapshotTransfer {
+        /// Last index the snapshot being sent covers, a newer snapshot is sent from its start
+        pub(crate) last_included_index: LogIndex,
+        /// Where the next chunk starts
+        pub(crate) offset: u64,
+    }
+
+    /// Where the leader is with the one membership change it carries out at a time
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) enum MembershipChangePhase {
+        /// Replicating to the server being added, the round ends once it has everything up to
+        /// `round_target` or an election timeout after `round_started`
+        CatchingUp {
+            round: u32,
+            round_target: LogIndex,
+            round_started: Instant,
+        },
+        /// Waiting to append the configuration entry
+        ReadyToAppend,
+        /// The configuration entry is in the log at this index, waiting for it to commit
+        Appended(LogIndex),
+    }
+
+    /// A step down waiting for `to` to catch up with the log before the leader hands leadership to it
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct LeadershipTransfer {
+        pub(crate) id: Uuid,
+        pub(crate) to: ServerId,
+        pub(crate) started: Instant,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct MembershipChangeInProgress {
+        pub(crate) id: Uuid,
+        pub(crate) change: MembershipChange,
+        pub(crate) phase: MembershipChangePhase,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Leader {
+        pub(crate) last_heartbeat_sent: HashMap<ServerId, Instant>,
+        pub(crate) next_index: HashMap<ServerId, LogIndex>,
+        pub(crate) match_index: HashMap<ServerId, LogIndex>,
+        /// How far the leader's own log is durable, counted towards commits like a follower's match index
+        pub(crate) synced_index: LogIndex,
+        pub(crate) batch_sizes: HashMap<ServerId, AdaptiveBatchSize>,
+        pub(crate) in_flight_append_entries: HashMap<Uuid, InFlightAppendEntries>,
+        /// Snapshots part way to followers that need them
+        pub(crate) snapshot_transfers: HashMap<ServerId, SnapshotTransfer>,
+        /// Boxed, it's rarely there and would make every leader bigger
+        pub(crate) membership_change: Option<Box<MembershipChangeInProgress>>,
+        /// The server leadership is being handed to, proposals are turned away until it's over
+        pub(crate) leadership_transfer: Option<LeadershipTransfer>,
+        /// When each follower last replied, a majority has to have within an election timeout
+        pub(crate) last_reply_received: HashMap<ServerId, Instant>,
+        /// When the leader sent the latest request each follower acked, read leases count from there
+        pub(crate) lease_acks: HashMap<ServerId, Instant>,
+        _priv: Priv,
+    }
+
+    impl State for Leader {}
+    impl FromState<Candidate> for Leader {
+        fn from_state(_: Candidate, _now: Instant) -> Self {
+            Leader {
+                last_heartbeat_sent: HashMap::new(),
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                synced_index: LogIndex(0),
+                batch_sizes: HashMap::new(),
+                in_flight_append_entries: HashMap::new(),
+                snapshot_transfers: HashMap::new(),
+                membership_change: None,
+                leadership_transfer: None,
+                last_reply_received: HashMap::new(),
+                lease_acks: HashMap::new(),
+                _priv: Priv {},
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Candidate {
+        pub(crate) last_election_timer_started: Instant,
+        pub(crate) election_timeout: Duration,
+        pub(crate) votes_received: HashSet<ServerId>,
+        /// Elections started since the last time this node heard from a leader, the running one included
+        pub(crate) elections_without_leader: u32,
+        _priv: Priv,
+    }
+    impl State for Candidate {}
+    impl FromState<Follower> for Candidate {
+        fn from_state(follower: Follower, now: Instant) -> Self {
+            Candidate {
+                last_election_timer_started: now,
+                election_timeout: Duration::from_millis(0),
+                votes_received: HashSet::new(),
+                elections_without_leader: follower.elections_without_leader,
+                _priv: Priv {},
+            }
+        }
+    }
+    impl FromState<PreCandidate> for Candidate {
+        fn from_state(pre_candidate: PreCandidate, now: Instant) -> Self {
+            Candidate {
+                last_election_timer_started: now,
+                election_timeout: Duration::from_millis(0),
+                votes_received: HashSet::new(),
+                elections_without_leader: pre_candidate.elections_without_leader,
+                _priv: Priv {},
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct PreCandidate {
+        pub(crate) last_election_timer_started: Instant,
+        pub(crate) election_timeout: Duration,
+        /// Members that would vote for this node in the next term, itself included. Late grants from an
+        /// earlier round still count, a pre-vote only decides whether an election is worth starting.
+        pub(crate) pre_votes_received: HashSet<ServerId>,
+        /// Elections started since the last time this node heard from a leader, pre-votes don't count
+        pub(crate) elections_without_leader: u32,
+        _priv: Priv,
+    }
+    impl State for PreCandidate {}
+    impl FromState<Follower> for PreCandidate {
+        fn from_state(follower: Follower, now: Instant) -> Self {
+            PreCandidate {
+                last_election_timer_started: now,
+                election_timeout: Duration::from_millis(0),
+                pre_votes_received: HashSet::new(),
+                elections_without_leader: follower.elections_without_leader,
+                _priv: Priv {},
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Follower {
+        pub(crate) last_election_timer_started: Instant,
+        pub(crate) election_timeout: Duration,
+        pub(crate) leader_id: Option<ServerId>,
+        /// When an AppendEntries or InstallSnapshot of the current term last came in from the leader
+        pub(crate) last_heard_from_leader: Option<Instant>,
+        /// The chunks of the leader's snapshot received so far, in a snapshot whose data is what they add up to
+        pub(crate) incoming_snapshot: Option<Snapshot>,
+        /// Elections this node started since it last heard from a leader, kept when a candidate steps down
+        pub(crate) elections_without_leader: u32,
+        _priv: Priv,
+    }
+    impl Follower {
+        pub(crate) fn new(now: Instant) -> Self {
+            Follower {
+                last_election_timer_started: now,
+                election_timeout: Duration::from_millis(0),
+                leader_id: None,
+                last_heard_from_leader: None,
+                incoming_snapshot: None,
+                elections_without_leader: 0,
+                _priv: Priv {},
+            }
+        }
+    }
+    impl State for Follower {}
+    impl FromState<Leader> for Follower {
+        fn from_state(_: Leader, now: Instant) -> Self {
+            Follower {
+                last_election_timer_started: now,
+                leader_id: None,
+                last_heard_from_leader: None,
+                incoming_snapshot: None,
+                election_timeout: Duration::from_millis(0),
+                elections_without_leader: 0,
+                _priv: Priv {},
+            }
+        }
+    }
+    impl FromState<Candidate> for Follower {
+        fn from_state(candidate: Candidate, now: Instant) -> Self {
+            Follower {
+                last_election_timer_started: now,
+                election_timeout: candidate.election_timeout,
+                leader_id: None,
+                last_heard_from_leader: None,
+                incoming_snapshot: None,
+                elections_without_leader: candidate.elections_without_leader,
+                _priv: Priv {},
+            }
+        }
+    }
+    impl FromState<PreCandidate> for Follower {
+        fn from_state(pre_candidate: PreCandidate, now: Instant) -> Self {
+            Follower {
+                last_election_timer_started: now,
+                election_timeout: pre_candidate.election_timeout,
+                leader_id: None,
+                last_heard_from_leader: None,
+                incoming_snapshot: None,
+                elections_without_leader: pre_candidate.elections_without_leader,
+                _priv: Priv {},
+            }
+        }
+    }
+}
+
+impl<St: State> NodeState<St> {
+    /// Number of servers, this one included if it's a member, that make a majority of the cluster
+    fn quorum(&self) -> usize {
+        self.membership.quorum()
+    }
+
+    /// Reads the configuration from the log again after it changed, telling observers if the members did
+    fn reload_membership<C, PS>(&mut self, storage: &PS, actions: &mut Vec<Action<C>>)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        if self.membership.reload(storage) {
+            actions.push(Action::PublishEvent(RaftEvent::MembershipChanged {
+                server_id: self.server_id,
+                members: self.membership.members().clone(),
+                learners: self.membership.learners().clone(),
+            }));
+        }
+    }
+
+    /// Hands the application committed entries it hasn't been given yet, at most
+    /// [`MAX_ENTRIES_PER_APPLY`] at a time. The rest follow once it reports back.
+    fn hand_committed_entries_to_application<C, PS>(
+        &mut self,
+        storage: &PS,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        if self.commit_index <= self.handed_to_application
+            || self.handed_to_application > self.last_applied
+        {
+            return;
+        }
+        let pending = (self.commit_index.0 - self.handed_to_application.0) as usize;
+        let entries = storage.entries(
+            self.handed_to_application.next(),
+            pending.min(MAX_ENTRIES_PER_APPLY),
+        );
+        if let Some(last_entry) = entries.last() {
+            self.handed_to_application = last_entry.index;
+            actions.push(Action::ApplyLogEntries(entries));
+        }
+    }
+
+    /// Asks for a snapshot once the application is [`RaftConfig::snapshot_threshold`] entries past
+    /// the last one, and only while it has nothing left to apply so the snapshot matches `last_applied`
+    fn take_snapshot_if_due<C, PS>(
+        &mut self,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        if self.snapshot_requested
+            || self.handed_to_application != self.last_applied
+            || self
+                .last_applied
+                .0
+                .saturating_sub(storage.snapshot_index().0)
+                < config.snapshot_threshold.0
+        {
+            return;
+        }
+        if let Some(last_included_term) = storage.entry_term(self.last_applied) {
+            self.snapshot_requested = true;
+            let (members, learners) = self.membership.configuration_at(storage, self.last_applied);
+            actions.push(Action::TakeSnapshot {
+                last_included_index: self.last_applied,
+                last_included_term,
+                members,
+                learners,
+            });
+        }
+    }
+
+    /// Compacts the log up to the snapshot the application just took
+    fn compact_log<C, PS>(
+        &mut self,
+        snapshot: Snapshot,
+        storage: &mut PS,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        self.snapshot_requested = false;
+        if snapshot.last_included_index <= storage.snapshot_index() {
+            return Ok(());
+        }
+        let (last_included_index, last_included_term) =
+            (snapshot.last_included_index, snapshot.last_included_term);
+        storage.install_snapshot(snapshot).sync()?;
+        actions.push(Action::PublishEvent(RaftEvent::SnapshotInstalled {
+            server_id: self.server_id,
+            last_included_index,
+            last_included_term,
+        }));
+        Ok(())
+    }
+
+    /// The application applied everything up to and including `index`
+    fn record_applied<C: LogCommand>(&mut self, index: LogIndex, actions: &mut Vec<Action<C>>) {
+        if index > self.last_applied {
+            self.last_applied = index;
+            self.handed_to_application = self.handed_to_application.max(index);
+            actions.push(Action::PublishEvent(RaftEvent::EntryApplied {
+                server_id: self.server_id,
+                index,
+            }));
+        }
+    }
+
+    fn ack_append_entries<C, PS>(
+        &self,
+        storage: &PS,
+        append_entries_req: AppendEntries<C>,
+        success: bool,
+        conflict: Option<LogConflict>,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        actions.push(Action::OutgoingRpc(RpcMessage::ack_append_entries(
+            AppendEntriesAck {
+                request_id: append_entries_req.request_id,
+                from: self.server_id,
+                to: append_entries_req.from,
+                term: storage.current_term(),
+                success,
+                conflict,
+            },
+        )));
+    }
+
+    fn ack_install_snapshot<C, PS>(
+        &self,
+        storage: &PS,
+        install_snapshot_req: InstallSnapshot,
+        next_offset: u64,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        actions.push(Action::OutgoingRpc(RpcMessage::ack_install_snapshot(
+            InstallSnapshotAck {
+                request_id: install_snapshot_req.request_id,
+                from: self.server_id,
+                to: install_snapshot_req.from,
+                term: storage.current_term(),
+                next_offset,
+            },
+        )));
+    }
+
+    fn ack_timeout_now<C, PS>(&self, storage: &PS, req: TimeoutNow, actions: &mut Vec<Action<C>>)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        actions.push(Action::OutgoingRpc(RpcMessage::ack_timeout_now(
+            TimeoutNowAck {
+                request_id: req.request_id,
+                from: self.server_id,
+                to: req.from,
+                term: storage.current_term(),
+            },
+        )));
+    }
+
+    fn vote_no<C, PS>(
+        &self,
+        storage: &mut PS,
+        vote_req: RequestVote,
+        reason: &str,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        debug!(
+            "{server_id:?}: Vote NO for candidate {candidate_id:?} because {reason:?} (my term: {my_term:?}, vote term: {candidate_term:?})",
+            server_id = self.server_id,
+            candidate_id = vote_req.from,
+            reason = reason,
+            my_term=storage.current_term(),
+            candidate_term=vote_req.term,
+        );
+        actions.push(Action::OutgoingRpc(RpcMessage::vote(Vote {
+            request_id: vote_req.request_id,
+            from: self.server_id,
+            to: vote_req.from,
+            term: storage.current_term(),
+            vote_granted: false,
+        })));
+    }
+
+    /// Turns down a vote or pre-vote request while this node hears from a leader, keeping its term
+    fn turn_down_candidate<C, PS>(
+        &self,
+        storage: &mut PS,
+        req: RequestVote,
+        pre_vote: bool,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        if pre_vote {
+            self.reply_to_pre_vote(storage, req, false, actions);
+        } else {
+            self.vote_no(
+                storage,
+                req,
+                "heard from the leader within the minimum election timeout",
+                actions,
+            );
+        }
+    }
+
+    /// Grants a pre-vote if this node would vote for the sender once it started `req.term`: that
+    /// term has to be ahead of ours and the sender's log at least as up to date (§5.4). Nothing
+    /// is recorded and the election timer keeps running, a pre-vote doesn't bind the real vote.
+    fn answer_pre_vote<C, PS>(&self, storage: &PS, req: RequestVote, actions: &mut Vec<Action<C>>)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let vote_granted = req.term > storage.current_term()
+            && candidate_log_is_up_to_date(storage, req.last_log_index, req.last_log_term);
+        self.reply_to_pre_vote(storage, req, vote_granted, actions);
+    }
+
+    fn reply_to_pre_vote<C, PS>(
+        &self,
+        storage: &PS,
+        req: RequestVote,
+        vote_granted: bool,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        debug!(
+            "{:?}: Pre-vote {} for {:?} in term {:?} (my term: {:?})",
+            self.server_id,
+            if vote_granted { "YES" } else { "NO" },
+            req.from,
+            req.term,
+            storage.current_term()
+        );
+        actions.push(Action::OutgoingRpc(RpcMessage::pre_vote_reply(Vote {
+            request_id: req.request_id,
+            from: self.server_id,
+            to: req.from,
+            term: storage.current_term(),
+            vote_granted,
+        })));
+        if !vote_granted {
+            actions.push(Action::PublishEvent(RaftEvent::PreVoteRejected {
+                server_id: self.server_id,
+                candidate_id: req.from,
+                term: req.term,
+            }));
+        }
+    }
+}
+
+/// Whether a snapshot chunk has the metadata of the chunks received before it, so its data goes
+/// with theirs
+fn same_snapshot(received: &Snapshot, chunk: &Snapshot) -> bool {
+    received.last_included_index == chunk.last_included_index
+        && received.last_included_term == chunk.last_included_term
+        && received.members == chunk.members
+        && received.learners == chunk.learners
+        && received.sessions == chunk.sessions
+}
+
+/// Where our log stops matching a leader whose entry at `prev_log_index` we don't have: the term we
+/// have there and the first index of that term, or the end of our log if it doesn't reach that far
+fn log_conflict_at<C, PS>(storage: &PS, prev_log_index: LogIndex) -> LogConflict
+where
+    C: LogCommand,
+    PS: PersistentStorage<C>,
+{
+    let Some(term) = storage.entry_term(prev_log_index) else {
+        return LogConflict {
+            term: None,
+            first_index: storage.last_entry_index().unwrap_or(LogIndex(0)).next(),
+        };
+    };
+    // Entries the snapshot covers were committed, they match the leader's
+    let mut first_index = prev_log_index;
+    while first_index.0 > storage.snapshot_index().0 + 1
+        && storage.entry_term(LogIndex(first_index.0 - 1)) == Some(term)
+    {
+        first_index = LogIndex(first_index.0 - 1);
+    }
+    LogConflict {
+        term: Some(term),
+        first_index,
+    }
+}
+
+/// Index of our last entry of `term` at or before `at_or_before`, if we have one. Terms only grow
+/// along the log, so the search stops at the first lower term.
+fn last_index_of_term<C, PS>(
+    storage: &PS,
+    term: TermIndex,
+    at_or_before: LogIndex,
+) -> Option<LogIndex>
+where
+    C: LogCommand,
+    PS: PersistentStorage<C>,
+{
+    let mut index = at_or_before.min(storage.last_entry_index().unwrap_or(LogIndex(0)));
+    while index > storage.snapshot_index() {
+        match storage.entry_term(index) {
+            Some(entry_term) if entry_term == term => return Some(index),
+            Some(entry_term) if entry_term < term => return None,
+            _ => index = LogIndex(index.0 - 1),
+        }
+    }
+    None
+}
+
+/// Whether a log ending with `last_log_index` and `last_log_term` is at least as up to date as ours (§5.4.1)
+fn candidate_log_is_up_to_date<C, PS>(
+    storage: &PS,
+    last_log_index: LogIndex,
+    last_log_term: TermIndex,
+) -> bool
+where
+    C: LogCommand,
+    PS: PersistentStorage<C>,
+{
+    let our_last_log_term = storage.last_entry_term().unwrap_or(TermIndex(0));
+    last_log_term > our_last_log_term
+        || (last_log_term == our_last_log_term
+            && last_log_index >= storage.last_entry_index().unwrap_or(LogIndex(0)))
+}
+
+impl NodeState<Leader> {
+    /// Every follower is assumed to be up to date until it says otherwise, entries the leader
+    /// already has count as durable since followers sync before acking and leaders before stepping down.
+    fn start_replication<C, PS>(&mut self, storage: &PS)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let last_index = storage.last_entry_index().unwrap_or(LogIndex(0));
+        for other_server in self.replication_targets() {
+            let _ = self
+                .inner
+                .next_index
+                .insert(other_server, last_index.next());
+            let _ = self.inner.match_index.insert(other_server, LogIndex(0));
+            // Their votes just came in, the first election timeout counts as heard from
+            let _ = self
+                .inner
+                .last_reply_received
+                .insert(other_server, self.current_time);
+        }
+        self.inner.synced_index = last_index;
+    }
+
+    /// The other members and the learners, plus the server the membership change in progress adds or
+    /// removes until it commits. The one being added has to catch up, the one being removed learns it was.
+    fn replication_targets(&self) -> Vec<ServerId> {
+        let mut targets: Vec<ServerId> = self.membership.others(self.server_id).collect();
+        targets.extend(self.membership.learners().iter().copied());
+        if let Some(in_progress) = &self.inner.membership_change {
+            let server_id = in_progress.change.server_id();
+            if server_id != self.server_id
+                && !self.membership.contains(server_id)
+                && !self.membership.is_learner(server_id)
+            {
+                targets.push(server_id);
+            }
+        }
+        targets
+    }
+
+    /// Stops replicating to a server that isn't a member or learner anymore, or won't become one
+    fn forget_replication_target(&mut self, server_id: ServerId) {
+        let _ = self.inner.next_index.remove(&server_id);
+        let _ = self.inner.match_index.remove(&server_id);
+        let _ = self.inner.batch_sizes.remove(&server_id);
+        let _ = self.inner.last_heartbeat_sent.remove(&server_id);
+        self.inner
+            .in_flight_append_entries
+            .retain(|_, request| request.to != server_id);
+    }
+
+    /// Takes on `change` unless another one is in progress or it changes nothing. A server being
+    /// added is replicated to right away, it only becomes a member once it caught up. A learner is
+    /// promoted once it caught up too, a new learner doesn't have to since it doesn't count toward commits.
+    fn start_membership_change<C, PS>(
+        &mut self,
+        id: Uuid,
+        change: MembershipChange,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let rejection = match change {
+            _ if self.inner.membership_change.is_some() => {
+                Some(MembershipChangeError::ChangeInProgress)
+            }
+            MembershipChange::AddServer(server_id) | MembershipChange::AddLearner(server_id)
+                if self.membership.contains(server_id) || self.membership.is_learner(server_id) =>
+            {
+                Some(MembershipChangeError::NothingToChange)
+            }
+            MembershipChange::PromoteLearner(server_id)
+                if !self.membership.is_learner(server_id) =>
+            {
+                Some(MembershipChangeError::NothingToChange)
+            }
+            MembershipChange::RemoveServer(server_id)
+                if !self.membership.contains(server_id)
+                    && !self.membership.is_learner(server_id) =>
+            {
+                Some(MembershipChangeError::NothingToChange)
+            }
+            MembershipChange::RemoveServer(server_id)
+                if self.membership.contains(server_id) && self.membership.members().len() == 1 =>
+            {
+                Some(MembershipChangeError::NoMembersLeft)
+            }
+            _ => None,
+        };
+        if let Some(error) = rejection {
+            actions.push(Action::MembershipChangeFinished {
+                id,
+                outcome: Err(error),
+            });
+            return Ok(());
+        }
+        info!(
+            "{:?}: Starting membership change {:?}",
+            self.server_id, change
+        );
+
+        if let MembershipChange::AddServer(server_id) | MembershipChange::AddLearner(server_id) =
+            change
+        {
+            // A new server most likely has an empty log, start from the beginning rather than
+            // backing up one entry per rejection
+            let _ = self.inner.next_index.insert(server_id, LogIndex(1));
+            let _ = self.inner.match_index.insert(server_id, LogIndex(0));
+        }
+        let phase = match change {
+            MembershipChange::AddServer(_) | MembershipChange::PromoteLearner(_) => {
+                MembershipChangePhase::CatchingUp {
+                    round: 1,
+                    round_target: storage.last_entry_index().unwrap_or(LogIndex(0)),
+                    round_started: self.current_time,
+                }
+            }
+            MembershipChange::RemoveServer(_) | MembershipChange::AddLearner(_) => {
+                MembershipChangePhase::ReadyToAppend
+            }
+        };
+        self.inner.membership_change =
+            Some(Box::new(MembershipChangeInProgress { id, change, phase }));
+        if let MembershipChange::AddServer(server_id) | MembershipChange::AddLearner(server_id) =
+            change
+        {
+            self.send_append_entries(server_id, storage, config, actions)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the membership change in progress along: ends catch up rounds, appends the
+    /// configuration entry once it may and reports the change once that entry committed
+    fn advance_membership_change<C, PS>(
+        &mut self,
+        storage: &mut PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let Some(in_progress) = self.inner.membership_change.as_deref().copied() else {
+            return Ok(());
+        };
+        let server_id = in_progress.change.server_id();
+
+        let mut phase = in_progress.phase;
+        if let MembershipChangePhase::CatchingUp {
+            round,
+            round_target,
+            round_started,
+        } = phase
+        {
+            let caught_up_to = self
+                .inner
+                .match_index
+                .get(&server_id)
+                .copied()
+                .unwrap_or(LogIndex(0));
+            let in_time = self.current_time.saturating_duration_since(round_started)
+                < config.election_timeout.min;
+            if caught_up_to >= round_target && in_time {
+                phase = MembershipChangePhase::ReadyToAppend;
+            } else if caught_up_to >= round_target || !in_time {
+                if round >= config.catch_up_rounds.0 {
+                    info!(
+                        "{:?}: Giving up on {:?}, {:?} did not catch up in {} rounds",
+                        self.server_id, in_progress.change, server_id, round
+                    );
+                    // A learner that didn't catch up stays a learner
+                    if let MembershipChange::AddServer(_) = in_progress.change {
+                        self.forget_replication_target(server_id);
+                    }
+                    self.finish_membership_change(
+                        Err(MembershipChangeError::CatchUpFailed),
+                        actions,
+                    );
+                    return Ok(());
+                }
+                phase = MembershipChangePhase::CatchingUp {
+                    round: round + 1,
+                    round_target: storage.last_entry_index().unwrap_or(LogIndex(0)),
+                    round_started: self.current_time,
+                };
+            }
+        }
+
+        // A configuration entry only goes in once an entry of this term committed. Until then an
+        // earlier leader's configuration entry may still commit without this leader knowing.
+        if matches!(phase, MembershipChangePhase::ReadyToAppend)
+            && storage.entry_term(self.commit_index) == Some(storage.current_term())
+        {
+            let index =
+                self.append_membership_change(in_progress.change, storage, config, actions)?;
+            phase = MembershipChangePhase::Appended(index);
+        }
+
+        match phase {
+            MembershipChangePhase::Appended(index) if self.commit_index >= index => {
+                info!(
+                    "{:?}: Membership change {:?} committed at {:?}",
+                    self.server_id, in_progress.change, index
+                );
+                if let MembershipChange::RemoveServer(removed) = in_progress.change {
+                    if removed != self.server_id {
+                        self.forget_replication_target(removed);
+                    }
+                }
+                self.finish_membership_change(Ok(index), actions);
+            }
+            phase => {
+                self.inner.membership_change = Some(Box::new(MembershipChangeInProgress {
+                    phase,
+                    ..in_progress
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the configuration entry for `change`, which takes effect right away, and sends it out
+    fn append_membership_change<C, PS>(
+        &mut self,
+        change: MembershipChange,
+        storage: &mut PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<LogIndex, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let term = storage.current_term();
+        let index = storage.last_entry_index().unwrap_or(LogIndex(0)).next();
+        let _ = storage.append(vec![LogEntry {
+            index,
+            term,
+            command: LogEntryCommand::MembershipChange(change),
+        }]);
+        self.membership.apply(index, change);
+        actions.push(Action::PublishEvent(RaftEvent::EntryAppended {
+            server_id: self.server_id,
+            index,
+            term,
+        }));
+        actions.push(Action::PublishEvent(RaftEvent::MembershipChanged {
+            server_id: self.server_id,
+            members: self.membership.members().clone(),
+            learners: self.membership.learners().clone(),
+        }));
+
+        for target in self.replication_targets() {
+            self.send_append_entries(target, storage, config, actions)?;
+        }
+        actions.push(Action::SyncLog(index));
+        Ok(index)
+    }
+
+    /// Appends a no-op entry for the leader's new term, the first heartbeats carry it out
+    fn append_no_op<C, PS>(&mut self, storage: &mut PS, actions: &mut Vec<Action<C>>) -> LogIndex
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let term = storage.current_term();
+        let index = storage.last_entry_index().unwrap_or(LogIndex(0)).next();
+        let _ = storage.append(vec![LogEntry {
+            index,
+            term,
+            command: LogEntryCommand::NoOp,
+        }]);
+        actions.push(Action::PublishEvent(RaftEvent::EntryAppended {
+            server_id: self.server_id,
+            index,
+            term,
+        }));
+        index
+    }
+
+    /// Reports how the membership change in progress ended, if there is one
+    fn finish_membership_change<C: LogCommand>(
+        &mut self,
+        outcome: MembershipChangeOutcome,
+        actions: &mut Vec<Action<C>>,
+    ) {
+        if let Some(in_progress) = self.inner.membership_change.take() {
+            actions.push(Action::MembershipChangeFinished {
+                id: in_progress.id,
+                outcome,
+            });
+        }
+    }
+
+    /// Gives up leadership, reporting the membership change and the step down in progress
+    fn step_down<C: LogCommand>(
+        mut self,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> NodeState<Follower> {
+        self.finish_membership_change(Err(MembershipChangeError::LeadershipLost), actions);
+        self.finish_leadership_transfer(Ok(()), actions);
+        let mut follower_state: NodeState<Follower> = self.transition_to();
+        let election_timeout = follower_state.reset_election_timer(config, rng);
+        actions.push(Action::SetNextTimeout(election_timeout));
+        follower_state
+    }
+
+    /// Steps down right away when there's no one to hand leadership to. Otherwise it waits for
+    /// `transfer_to` to catch up with the log, turning proposals away so the log stops growing
+    /// meanwhile, and tells it to start an election before stepping down (§3.10).
+    fn start_step_down<C, PS>(
+        mut self,
+        id: Uuid,
+        transfer_to: Option<ServerId>,
+        storage: &PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let Some(to) = transfer_to else {
+            info!("{:?}: Stepping down as an operator asked", self.server_id);
+            let follower_state = self.step_down(config, rng, actions);
+            actions.push(Action::StepDownFinished {
+                id,
+                outcome: Ok(()),
+            });
+            return Ok(follower_state.into());
+        };
+        let rejection = if self.inner.leadership_transfer.is_some() {
+            Some(StepDownError::TransferInProgress)
+        } else if to == self.server_id || !self.membership.contains(to) || config.is_witness(to) {
+            Some(StepDownError::InvalidTransferee(to))
+        } else {
+            None
+        };
+        if let Some(error) = rejection {
+            actions.push(Action::StepDownFinished {
+                id,
+                outcome: Err(error),
+            });
+            return Ok(self.into());
+        }
+        info!("{:?}: Handing leadership over to {:?}", self.server_id, to);
+        self.inner.leadership_transfer = Some(LeadershipTransfer {
+            id,
+            to,
+            started: self.current_time,
+        });
+        self.send_append_entries(to, storage, config, actions)?;
+        Ok(self.hand_over_leadership_if_caught_up(storage, config, rng, actions))
+    }
+
+    /// Once the server leadership is handed to has every entry in the log, tells it to start an
+    /// election without waiting for its timeout and steps down
+    fn hand_over_leadership_if_caught_up<C, PS>(
+        self,
+        storage: &PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Node
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let Some(transfer) = self.inner.leadership_transfer else {
+            return self.into();
+        };
+        let last_index = storage.last_entry_index().unwrap_or(LogIndex(0));
+        let caught_up = self
+            .inner
+            .match_index
+            .get(&transfer.to)
+            .is_some_and(|match_index| *match_index >= last_index);
+        if !caught_up {
+            return self.into();
+        }
+        info!(
+            "{:?}: {:?} caught up with the log through {:?}, telling it to start an election",
+            self.server_id, transfer.to, last_index
+        );
+        actions.push(Action::OutgoingRpc(RpcMessage::timeout_now(TimeoutNow {
+            request_id: Uuid::new_v4(),
+            from: self.server_id,
+            to: transfer.to,
+            term: storage.current_term(),
+        })));
+        self.step_down(config, rng, actions).into()
+    }
+
+    /// Gives up on handing leadership to a server that didn't catch up within an election timeout,
+    /// the leader takes proposals again
+    fn abandon_overdue_leadership_transfer<C: LogCommand>(
+        &mut self,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) {
+        let Some(transfer) = self.inner.leadership_transfer else {
+            return;
+        };
+        if self.current_time >= transfer.started + config.election_timeout.max {
+            info!(
+                "{:?}: {:?} didn't catch up within the election timeout {:?}, leading on",
+                self.server_id, transfer.to, config.election_timeout.max
+            );
+            self.finish_leadership_transfer(
+                Err(StepDownError::TransferTimedOut(transfer.to)),
+                actions,
+            );
+        }
+    }
+
+    /// Reports how the step down in progress ended, if there is one
+    fn finish_leadership_transfer<C: LogCommand>(
+        &mut self,
+        outcome: StepDownOutcome,
+        actions: &mut Vec<Action<C>>,
+    ) {
+        if let Some(transfer) = self.inner.leadership_transfer.take() {
+            actions.push(Action::StepDownFinished {
+                id: transfer.id,
+                outcome,
+            });
+        }
+    }
+
+    /// Appends the proposed commands to the log in one batch and sends them to the followers right
+    /// away, one AppendEntries each. The leader's single sync is queued behind the sends rather than
+    /// before them, so the entries commit as soon as any majority, with or without the leader, has them on disk.
+    fn append_proposals<C, PS>(
+        &mut self,
+        proposals: Vec<ClientProposal<C>>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let term = storage.current_term();
+        let mut index = storage.last_entry_index().unwrap_or(LogIndex(0));
+        let mut entries = Vec::with_capacity(proposals.len());
+        for proposal in proposals {
+            index = index.next();
+            entries.push(LogEntry {
+                index,
+                term,
+                command: proposal.into_log_entry_command(),
+            });
+            actions.push(Action::PublishEvent(RaftEvent::EntryAppended {
+                server_id: self.server_id,
+                index,
+                term,
+            }));
+        }
+        let _ = storage.append(entries);
+
+        for other_server in self.replication_targets() {
+            self.send_append_entries(other_server, storage, config, actions)?;
+        }
+        self.send_due_heartbeats(storage, config, actions)?;
+        actions.push(Action::SyncLog(index));
+        Ok(())
+    }
+
+    /// Number of AppendEntries with entries on their way to `to`, see [`RaftConfig::replication_window`]
+    fn batches_in_flight(&self, to: ServerId) -> usize {
+        self.inner
+            .in_flight_append_entries
+            .values()
+            .filter(|request| request.to == to && request.carries_entries())
+            .count()
+    }
+
+    /// Sends `to` the entries from its next index on, as many as its batch size and byte limit allow,
+    /// and moves its next index past them without waiting for the ack. Followers that are up to date,
+    /// or whose replication window is full, get an empty AppendEntries, which is the heartbeat.
+    fn send_append_entries<C, PS>(
+        &mut self,
+        to: ServerId,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let next_index = self
+            .inner
+            .next_index
+            .get(&to)
+            .copied()
+            .unwrap_or(LogIndex(1));
+        if next_index <= storage.snapshot_index() {
+            self.send_snapshot(to, storage, config, actions);
+            return Ok(());
+        }
+        let prev_log_index = LogIndex(next_index.0 - 1);
+        let prev_log_term =
+            storage
+                .entry_term(prev_log_index)
+                .ok_or(RaftError::InvariantViolated(
+                    "a follower's next index is past the end of the leader's log",
+                ))?;
+        let batch_limit = self
+            .inner
+            .batch_sizes
+            .entry(to)
+            .or_insert_with(|| AdaptiveBatchSize::new(config.append_entries_batch))
+            .follow_limits(config.append_entries_batch)
+            .limit();
+        let mut entries = if self.batches_in_flight(to) < config.replication_window.0 {
+            storage.entries_within(next_index, batch_limit, config.append_entries_max_bytes())
+        } else {
+            Vec::new()
+        };
+        if config.is_witness(to) {
+            entries = entries.into_iter().map(LogEntry::for_witness).collect();
+        }
+        let last_index = LogIndex(prev_log_index.0 + entries.len() as u64);
+        let _ = self.inner.next_index.insert(to, last_index.next());
+
+        let request_id = Uuid::new_v4();
+        let _ = self.inner.in_flight_append_entries.insert(
+            request_id,
+            InFlightAppendEntries {
+                to,
+                sent_at: self.current_time,
+                prev_log_index,
+                last_index,
+                snapshot: None,
+            },
+        );
+        let _ = self.inner.last_heartbeat_sent.insert(to, self.current_time);
+        actions.push(Action::OutgoingRpc(RpcMessage::append_entries(
+            AppendEntries {
+                request_id,
+                from: self.server_id,
+                to,
+                term: storage.current_term(),
+                prev_log_index,
+                prev_log_term,
+                entries: entries.into(),
+                leader_commit: self.commit_index,
+            },
+        )));
+        Ok(())
+    }
+
+    /// Sends `to` the next chunk of the snapshot in place of the entries it needs, which were compacted
+    /// out of the log. One chunk is on its way to `to` at a time, the next goes once it's acked and a
+    /// lost one is sent again once it times out, picking up where the follower's last ack left off.
+    fn send_snapshot<C, PS>(
+        &mut self,
+        to: ServerId,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let _ = self.inner.last_heartbeat_sent.insert(to, self.current_time);
+        let snapshot_in_flight = self
+            .inner
+            .in_flight_append_entries
+            .values()
+            .any(|request| request.to == to && request.snapshot.is_some());
+        let Some(snapshot) = storage.snapshot().filter(|_| !snapshot_in_flight) else {
+            return;
+        };
+        // Witnesses keep no application state, they only get what the snapshot says about the log
+        let data: &[u8] = if config.is_witness(to) {
+            &[]
+        } else {
+            &snapshot.data
+        };
+        let offset = self
+            .inner
+            .snapshot_transfers
+            .get(&to)
+            .filter(|transfer| transfer.last_included_index == snapshot.last_included_index)
+            .map_or(0, |transfer| transfer.offset as usize)
+            .min(data.len());
+        let end = data
+            .len()
+            .min(offset.saturating_add(config.snapshot_chunk_bytes()));
+        let done = end == data.len();
+        debug!(
+            "{:?}: Sending bytes {}..{} of {} of the snapshot up to {:?} to {:?}, the entries it needs were compacted",
+            self.server_id,
+            offset,
+            end,
+            data.len(),
+            snapshot.last_included_index,
+            to
+        );
+
+        let request_id = Uuid::new_v4();
+        let _ = self.inner.in_flight_append_entries.insert(
+            request_id,
+            InFlightAppendEntries {
+                to,
+                sent_at: self.current_time,
+                prev_log_index: snapshot.last_included_index,
+                last_index: snapshot.last_included_index,
+                snapshot: Some(SnapshotChunkSent {
+                    end: end as u64,
+                    done,
+                }),
+            },
+        );
+        actions.push(Action::OutgoingRpc(RpcMessage::install_snapshot(
+            InstallSnapshot {
+                request_id,
+                from: self.server_id,
+                to,
+                term: storage.current_term(),
+                snapshot: Snapshot {
+                    last_included_index: snapshot.last_included_index,
+                    last_included_term: snapshot.last_included_term,
+                    members: snapshot.members.clone(),
+                    learners: snapshot.learners.clone(),
+                    sessions: snapshot.sessions.clone(),
+                    data: data[offset..end].to_vec(),
+                },
+                offset: offset as u64,
+                done,
+            },
+        )));
+    }
+
+    /// Sends a heartbeat to every follower whose heartbeat interval has passed, each follower
+    /// may have its own interval, and sets the timer to wake up for the next one due.
+    fn send_due_heartbeats<C, PS>(
+        &mut self,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let mut next_heartbeat_due = config.leader_heartbeat_interval.0;
+
+        for other_server in self.replication_targets() {
+            let interval = config.heartbeat_interval_for(other_server).0;
+            let since_last_heartbeat = self
+                .inner
+                .last_heartbeat_sent
+                .get(&other_server)
+                .map(|sent_at| self.current_time.saturating_duration_since(*sent_at));
+
+            let due_in = match since_last_heartbeat {
+                Some(elapsed) if elapsed < interval => interval - elapsed,
+                _ => {
+                    trace!("Sending heartbeat to {:?}...", other_server);
+                    self.send_append_entries(other_server, storage, config, actions)?;
+                    interval
+                }
+            };
+            next_heartbeat_due = next_heartbeat_due.min(due_in);
+        }
+
+        actions.push(Action::SetNextTimeout(next_heartbeat_due));
+        Ok(())
+    }
+
+    /// Whether a majority, this leader included while it's a member, replied within the last election timeout
+    fn has_quorum_contact(&self, config: &RaftConfig) -> bool {
+        let mut in_contact = self
+            .membership
+            .others(self.server_id)
+            .filter(|server_id| {
+                self.inner
+                    .last_reply_received
+                    .get(server_id)
+                    .is_some_and(|replied_at| {
+                        self.current_time.saturating_duration_since(*replied_at)
+                            < config.election_timeout.max
+                    })
+            })
+            .count();
+        if self.membership.contains(self.server_id) {
+            in_contact += 1;
+        }
+        in_contact >= self.quorum()
+    }
+
+    /// `follower` acked a request this leader sent at `sent_at`, acks from earlier terms don't count
+    fn record_lease_ack<C, PS>(
+        &mut self,
+        follower: ServerId,
+        sent_at: Instant,
+        ack_term: TermIndex,
+        storage: &PS,
+    ) where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        if ack_term != storage.current_term() {
+            return;
+        }
+        let acked = self.inner.lease_acks.entry(follower).or_insert(sent_at);
+        *acked = (*acked).max(sent_at);
+    }
+
+    /// The commit index, if this leader holds a read lease: a majority, itself included while it's
+    /// a member, acked requests sent less than the lease duration ago. Reads also have to wait for
+    /// an entry of this term to commit, the entries of earlier terms aren't known to be committed before.
+    fn lease_read_index<C, PS>(&self, storage: &PS, config: &RaftConfig) -> LeaseReadOutcome
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let Some(lease) = config.read_lease else {
+            return Err(ReadError::LeaseReadsDisabled);
+        };
+        if storage.entry_term(self.commit_index) != Some(storage.current_term()) {
+            return Err(ReadError::LeaseExpired);
+        }
+        let mut acked_at: Vec<Instant> = self
+            .membership
+            .others(self.server_id)
+            .filter_map(|server_id| self.inner.lease_acks.get(&server_id).copied())
+            .collect();
+        if self.membership.contains(self.server_id) {
+            acked_at.push(self.current_time);
+        }
+        // Newest first, the lease runs from the oldest ack a majority needs
+        acked_at.sort_unstable_by(|a, b| b.cmp(a));
+        match acked_at.get(self.quorum() - 1) {
+            Some(lease_start)
+                if self.current_time < *lease_start + lease.duration - lease.clock_skew_bound =>
+            {
+                Ok(self.commit_index)
+            }
+            _ => Err(ReadError::LeaseExpired),
+        }
+    }
+
+    /// Shrinks the batch size of followers that didn't reply to an AppendEntries within the RPC timeout,
+    /// and moves their next index back to the first entry that wasn't acked so the next send retransmits it
+    fn expire_unanswered_append_entries(&mut self, config: &RaftConfig) {
+        let now = self.current_time;
+        let batch_sizes = &mut self.inner.batch_sizes;
+        let next_indexes = &mut self.inner.next_index;
+        self.inner.in_flight_append_entries.retain(|_, request| {
+            let answered_in_time = now.saturating_duration_since(request.sent_at)
+                < config.rpc_timeout_for(request.to).0;
+            if !answered_in_time && request.snapshot.is_none() {
+                if let Some(batch_size) = batch_sizes.get_mut(&request.to) {
+                    batch_size.on_timeout();
+                }
+                if request.carries_entries() {
+                    if let Some(next_index) = next_indexes.get_mut(&request.to) {
+                        *next_index = (*next_index).min(request.prev_log_index.next());
+                    }
+                }
+            }
+            answered_in_time
+        });
+    }
+
+    /// Moves the follower's progress forward on success, filling its replication window back up, and
+    /// its next index back on rejection. Replies to expired requests are ignored.
+    ///
+    /// With several AppendEntries on their way, a later one can overtake an earlier one and be turned
+    /// down for missing the entries the earlier one carries. That's no reason to back up further, it's
+    /// sent again after the earlier one. A rejection with nothing earlier in flight means the logs
+    /// differ: the requests sent after it are dropped, they'd all be turned down, and the leader backs
+    /// up past the follower's conflicting term and resends right away.
+    fn handle_append_entries_reply<C, PS>(
+        &mut self,
+        ack: &AppendEntriesAck,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let Some(request) = self.inner.in_flight_append_entries.remove(&ack.request_id) else {
+            return Ok(());
+        };
+        self.record_lease_ack(request.to, request.sent_at, ack.term, storage);
+        let overtook_earlier = !ack.success
+            && self.inner.in_flight_append_entries.values().any(|earlier| {
+                earlier.to == request.to
+                    && earlier.carries_entries()
+                    && earlier.prev_log_index < request.prev_log_index
+            });
+        if let Some(batch_size) = self.inner.batch_sizes.get_mut(&request.to) {
+            if ack.success {
+                batch_size.on_success(
+                    self.current_time.saturating_duration_since(request.sent_at),
+                    config.rpc_timeout_for(request.to).0,
+                );
+            } else if !overtook_earlier {
+                batch_size.on_reject();
+            }
+        }
+
+        if ack.success {
+            let match_index = self
+                .inner
+                .match_index
+                .entry(request.to)
+                .or_insert(LogIndex(0));
+            *match_index = (*match_index).max(request.last_index);
+            let next_index = self
+                .inner
+                .next_index
+                .entry(request.to)
+                .or_insert(LogIndex(1));
+            *next_index = (*next_index).max(request.last_index.next());
+            self.advance_commit_index(storage, actions);
+            let has_unsent_entries =
+                self.inner.next_index.get(&request.to).copied() <= storage.last_entry_index();
+            if has_unsent_entries
+                && self.batches_in_flight(request.to) < config.replication_window.0
+            {
+                self.send_append_entries(request.to, storage, config, actions)?;
+            }
+        } else if overtook_earlier {
+            // Sent again once the earlier one is acked, or times out
+            let next_index = self
+                .inner
+                .next_index
+                .entry(request.to)
+                .or_insert(LogIndex(1));
+            *next_index = (*next_index).min(request.prev_log_index.next());
+        } else {
+            // The follower's log doesn't have the entry before the ones we sent. Skip the term it
+            // has there: resend from after our last entry of that term, or from its first entry of
+            // it if we have none. Without a hint, back up one.
+            let backed_up = match ack.conflict {
+                Some(LogConflict {
+                    term: Some(term),
+                    first_index,
+                }) => last_index_of_term(storage, term, request.prev_log_index)
+                    .map(LogIndex::next)
+                    .unwrap_or(first_index),
+                Some(LogConflict {
+                    term: None,
+                    first_index,
+                }) => first_index,
+                None => request.prev_log_index,
+            };
+            self.inner.in_flight_append_entries.retain(|_, later| {
+                later.to != request.to || later.snapshot.is_some() || !later.carries_entries()
+            });
+            let next_index = self
+                .inner
+                .next_index
+                .entry(request.to)
+                .or_insert(LogIndex(1));
+            *next_index = (*next_index)
+                .min(backed_up)
+                .min(request.prev_log_index)
+                .max(LogIndex(1));
+            self.send_append_entries(request.to, storage, config, actions)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the follower the snapshot's next chunk, from where it asks for it. Once it acked the
+    /// last one it has everything the snapshot covers, replication carries on from there.
+    fn handle_install_snapshot_reply<C, PS>(
+        &mut self,
+        ack: &InstallSnapshotAck,
+        storage: &PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let Some(request) = self.inner.in_flight_append_entries.remove(&ack.request_id) else {
+            return Ok(());
+        };
+        self.record_lease_ack(request.to, request.sent_at, ack.term, storage);
+        let Some(chunk) = request.snapshot else {
+            return Ok(());
+        };
+        if !chunk.done || ack.next_offset != chunk.end {
+            let _ = self.inner.snapshot_transfers.insert(
+                request.to,
+                SnapshotTransfer {
+                    last_included_index: request.last_index,
+                    offset: ack.next_offset,
+                },
+            );
+            self.send_snapshot(request.to, storage, config, actions);
+            return Ok(());
+        }
+        let _ = self.inner.snapshot_transfers.remove(&request.to);
+        let match_index = self
+            .inner
+            .match_index
+            .entry(request.to)
+            .or_insert(LogIndex(0));
+        *match_index = (*match_index).max(request.last_index);
+        let next_index = self
+            .inner
+            .next_index
+            .entry(request.to)
+            .or_insert(LogIndex(1));
+        *next_index = (*next_index).max(request.last_index.next());
+        self.advance_commit_index(storage, actions);
+        self.send_append_entries(request.to, storage, config, actions)
+    }
+
+    /// Commits the highest entry of the current term that a majority of the members has durably
+    /// written, the leader's own synced log counting while it is a member (§5.3, §5.4)
+    fn advance_commit_index<C, PS>(&mut self, storage: &PS, actions: &mut Vec<Action<C>>)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let mut durable_indexes: Vec<LogIndex> = self
+            .membership
+            .members()
+            .iter()
+            .map(|member| {
+                if *member == self.server_id {
+                    self.inner.synced_index
+                } else {
+                    self.inner
+                        .match_index
+                        .get(member)
+                        .copied()
+                        .unwrap_or(LogIndex(0))
+                }
+            })
+            .collect();
+        durable_indexes.sort_unstable_by(|a, b| b.cmp(a));
+
+        let majority_index = durable_indexes[self.quorum() - 1];
+        if majority_index > self.commit_index
+            && storage.entry_term(majority_index) == Some(storage.current_term())
+        {
+            self.commit_index = majority_index;
+            actions.push(Action::PublishEvent(RaftEvent::EntryCommitted {
+                server_id: self.server_id,
+                index: majority_index,
+            }));
+        }
+    }
+}
+
+impl Transitions for NodeState<Leader> {
+    fn handle_event<C, PS>(
+        mut self,
+        event: Event<C>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        match event {
+            Event::Tick(_) if config.check_quorum && !self.has_quorum_contact(config) => {
+                info!(
+                    "{:?}: Stepping down, a majority hasn't replied within the election timeout {:?}",
+                    self.server_id, config.election_timeout.max
+                );
+                Ok(self.step_down(config, rng, actions).into())
+            }
+
+            Event::Tick(_) => {
+                self.abandon_overdue_leadership_transfer(config, actions);
+                self.expire_unanswered_append_entries(config);
+                self.send_due_heartbeats(storage, config, actions)?;
+                Ok(self.into())
+            }
+
+            Event::LogEntryAppliedByApplication(index) => {
+                self.record_applied(index, actions);
+                Ok(self.into())
+            }
+
+            Event::IncomingRpc(RpcMessage::Request(rpc_req)) => match rpc_req {
+                Request::RequestVote(req) => {
+                    self.vote_no(storage, req, "I am the leader", actions);
+                    Ok(self.into())
+                }
+
+                Request::PreVote(req) => {
+                    self.reply_to_pre_vote(storage, req, false, actions);
+                    Ok(self.into())
+                }
+
+                Request::AppendEntries(req) => {
+                    if req.term == storage.current_term() {
+                        Err(RaftError::InvariantViolated(
+                            "leader received append entries from another leader with the same term",
+                        ))
+                    } else if req.term < storage.current_term() {
+                        self.ack_append_entries(storage, req, false, None, actions);
+                        Ok(self.into())
+                    } else {
+                        Err(RaftError::InvariantViolated(
+                            "leader received append entries from a higher term without becoming follower first",
+                        ))
+                    }
+                }
+
+                Request::InstallSnapshot(req) => {
+                    if req.term == storage.current_term() {
+                        Err(RaftError::InvariantViolated(
+                            "leader received a snapshot from another leader with the same term",
+                        ))
+                    } else if req.term < storage.current_term() {
+                        self.ack_install_snapshot(storage, req, 0, actions);
+                        Ok(self.into())
+                    } else {
+                        Err(RaftError::InvariantViolated(
+                            "leader received a snapshot from a higher term without becoming follower first",
+                        ))
+                    }
+                }
+
+                Request::TimeoutNow(req) => {
+                    if req.term == storage.current_term() {
+                        Err(RaftError::InvariantViolated(
+                            "leader was handed leadership by another leader with the same term",
+                        ))
+                    } else if req.term < storage.current_term() {
+                        self.ack_timeout_now(storage, req, actions);
+                        Ok(self.into())
+                    } else {
+                        Err(RaftError::InvariantViolated(
+                            "leader was handed leadership in a higher term without becoming follower first",
+                        ))
+                    }
+                }
+
+                // Answered by the runtime tracking proposals, never handed to the node
+                Request::ForwardProposals(_) => Ok(self.into()),
+            },
+            Event::IncomingRpc(RpcMessage::Reply(reply)) => match reply {
+                ReplyTo::AppendEntries(ack) => {
+                    if ack.term == storage.current_term() {
+                        let _ = self
+                            .inner
+                            .last_reply_received
+                            .insert(ack.from, self.current_time);
+                    }
+                    self.handle_append_entries_reply(&ack, storage, config, actions)?;
+                    Ok(self.hand_over_leadership_if_caught_up(storage, config, rng, actions))
+                }
+
+                ReplyTo::InstallSnapshot(ack) => {
+                    if ack.term == storage.current_term() {
+                        let _ = self
+                            .inner
+                            .last_reply_received
+                            .insert(ack.from, self.current_time);
+                    }
+                    self.handle_install_snapshot_reply(&ack, storage, config, actions)?;
+                    Ok(self.hand_over_leadership_if_caught_up(storage, config, rng, actions))
+                }
+
+                ReplyTo::RequestVote(_) | ReplyTo::PreVote(_) | ReplyTo::TimeoutNow(_) => {
+                    Ok(self.into())
+                }
+
+                ReplyTo::ForwardProposals(_) => Ok(self.into()),
+            },
+
+            Event::ClientProposals(commands) if self.inner.leadership_transfer.is_some() => {
+                debug!(
+                    "{:?}: Dropping {} client proposals, leadership is being handed over",
+                    self.server_id,
+                    commands.len()
+                );
+                Ok(self.into())
+            }
+
+            Event::ClientProposals(commands) => {
+                if !commands.is_empty() {
+                    self.append_proposals(commands, storage, config, actions)?;
+                }
+                Ok(self.into())
+            }
+
+            Event::LogSynced(index) => {
+                self.inner.synced_index = self.inner.synced_index.max(index);
+                self.advance_commit_index(storage, actions);
+                Ok(self.into())
+            }
+
+            Event::SnapshotTaken(snapshot) => {
+                self.compact_log(snapshot, storage, actions)?;
+                Ok(self.into())
+            }
+
+            Event::MembershipChangeRequested { id, change } => {
+                self.start_membership_change(id, change, storage, config, actions)?;
+                Ok(self.into())
+            }
+
+            Event::LeaseReadRequested { id } => {
+                let outcome = self.lease_read_index(storage, config);
+                actions.push(Action::LeaseReadFinished { id, outcome });
+                Ok(self.into())
+            }
+
+            Event::StepDownRequested { id, transfer_to } => {
+                self.start_step_down(id, transfer_to, storage, config, rng, actions)
+            }
+
+            // Heartbeats that came due meanwhile go out with the next tick
+            Event::Resumed { .. } => Ok(self.into()),
+        }
+    }
+}
+
+has_election_timer!(Candidate);
+impl NodeState<Candidate> {
+    /// Votes for itself in the next term and asks the other members for their votes. The server a
+    /// leader handed over to says so, members still hearing from that leader vote for it anyway.
+    fn start_new_election<PS, C>(
+        &mut self,
+        config: &RaftConfig,
+        storage: &mut PS,
+        rng: &mut ChaCha8Rng,
+        leadership_transfer: bool,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        PS: PersistentStorage<C>,
+        C: LogCommand,
+    {
+        trace!(
+            "{server_id:?}: Starting new election!",
+            server_id = self.server_id
+        );
+        storage.persist_term_and_vote(storage.current_term().increment(), Some(self.server_id))?;
+
+        self.inner.elections_without_leader = self.inner.elections_without_leader.saturating_add(1);
+        let election_timeout = self.reset_election_timer(config, rng);
+        self.inner.votes_received = HashSet::new();
+        self.inner.votes_received.insert(self.server_id);
+
+        actions.push(Action::SetNextTimeout(election_timeout));
+        actions.push(Action::PublishEvent(RaftEvent::ElectionStarted {
+            server_id: self.server_id,
+            term: storage.current_term(),
+        }));
+
+        for other_server in self.membership.others(self.server_id) {
+            actions.push(Action::OutgoingRpc(RpcMessage::request_vote(RequestVote {
+                request_id: Uuid::new_v4(),
+                from: self.server_id,
+                to: other_server,
+                term: storage.current_term(),
+                last_log_index: storage.last_entry_index().unwrap_or(LogIndex(0)),
+                last_log_term: storage.last_entry_term().unwrap_or(TermIndex(0)),
+                leadership_transfer,
+            })));
+        }
+        Ok(())
+    }
+}
+
+impl Transitions for NodeState<Candidate> {
+    fn handle_event<C, PS>(
+        mut self,
+        event: Event<C>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        match event {
+            Event::Tick(now) => {
+                if now >= self.inner.last_election_timer_started + self.inner.election_timeout {
+                    trace!(
+                        "{server_id:?}: In candidate mode, did not receive enough votes before election timeout {timeout:?}ms, starting new election",
+                        server_id = self.server_id,
+                        timeout=self.inner.election_timeout.as_millis()
+                    );
+                    self.start_new_election(config, storage, rng, false, actions)?;
+                }
+
+                Ok(self.into())
+            }
+
+            Event::LogEntryAppliedByApplication(index) => {
+                self.record_applied(index, actions);
+                Ok(self.into())
+            }
+
+            Event::IncomingRpc(RpcMessage::Request(rpc_req)) => match rpc_req {
+                Request::RequestVote(req) => {
+                    let vote_no_reason = if req.term < storage.current_term() {
+                        "I am in a higher term than this vote request"
+                    } else {
+                        "I am a candidate for the same term"
+                    };
+                    self.vote_no(storage, req, vote_no_reason, actions);
+                    Ok(self.into())
+                }
+
+                Request::PreVote(req) => {
+                    self.answer_pre_vote(storage, req, actions);
+                    Ok(self.into())
+                }
+
+                Request::AppendEntries(req) => {
+                    if req.term < storage.current_term() {
+                        self.ack_append_entries(storage, req, false, None, actions);
+                        Ok(self.into())
+                    } else if req.term == storage.current_term() {
+                        // Someone else won the election, follow them and let the follower check the entries
+                        let follower_state: NodeState<Follower> = self.transition_to();
+                        follower_state.handle_event(
+                            Event::IncomingRpc(RpcMessage::append_entries(req)),
+                            storage,
+                            config,
+                            rng,
+                            actions,
+                        )
+                    } else {
+                        Err(RaftError::InvariantViolated(
+                            "candidate received append entries from a higher term without becoming follower first",
+                        ))
+                    }
+                }
+
+                Request::InstallSnapshot(req) => {
+                    if req.term < storage.current_term() {
+                        self.ack_install_snapshot(storage, req, 0, actions);
+                        Ok(self.into())
+                    } else if req.term == storage.current_term() {
+                        // Someone else won the election, follow them and let the follower install the snapshot
+                        let follower_state: NodeState<Follower> = self.transition_to();
+                        follower_state.handle_event(
+                            Event::IncomingRpc(RpcMessage::install_snapshot(req)),
+                            storage,
+                            config,
+                            rng,
+                            actions,
+                        )
+                    } else {
+                        Err(RaftError::InvariantViolated(
+                            "candidate received a snapshot from a higher term without becoming follower first",
+                        ))
+                    }
+                }
+
+                // Only a leader of our term or an earlier one could have sent it, either way it's
+                // not ours to act on while running an election
+                Request::TimeoutNow(req) => {
+                    self.ack_timeout_now(storage, req, actions);
+                    Ok(self.into())
+                }
+
+                // Answered by the runtime tracking proposals, never handed to the node
+                Request::ForwardProposals(_) => Ok(self.into()),
+            },
+
+            Event::IncomingRpc(RpcMessage::Reply(reply)) => match reply {
+                ReplyTo::RequestVote(vote) => {
+                    let qorum = self.quorum();
+
+                    // Only members' votes count, a server not yet added or already removed may still answer
+                    if vote.term == storage.current_term()
+                        && vote.vote_granted
+                        && self.membership.contains(vote.from)
+                    {
+                        self.inner.votes_received.insert(vote.from);
+
+                        if self.inner.votes_received.len() >= qorum {
+                            info!(
+                                "{server_id:?}: Received vote from {from:?} and won election with {votes:?} votes, becoming leader in term {term:?}",
+                                server_id=self.server_id,
+                                from=vote.from,
+                                votes = self.inner.votes_received,
+                                term=storage.current_term()
+                            );
+                            let mut new_state: NodeState<Leader> = self.transition_to();
+                            new_state.start_replication(storage);
+                            actions.push(Action::PublishEvent(RaftEvent::BecameLeader {
+                                server_id: new_state.server_id,
+                                term: storage.current_term(),
+                            }));
+                            let no_op_index = new_state.append_no_op(storage, actions);
+                            new_state.send_due_heartbeats(storage, config, actions)?;
+                            actions.push(Action::SyncLog(no_op_index));
+                            Ok(new_state.into())
+                        } else {
+                            self.inner.votes_received.insert(vote.from);
+                            info!(
+                                "{server_id:?}: Received vote from {from:?}, but still need {votes_needed:?} more votes to win election in term {term:?}",
+                                server_id=self.server_id,
+                                from=vote.from,
+                                votes_needed=qorum - self.inner.votes_received.len(),
+                                term=storage.current_term()
+                            );
+                            Ok(self.into())
+                        }
+                    } else {
+                        Ok(self.into())
+                    }
+                }
+
+                ReplyTo::AppendEntries(_)
+                | ReplyTo::InstallSnapshot(_)
+                | ReplyTo::PreVote(_)
+                | ReplyTo::TimeoutNow(_) => Ok(self.into()),
+
+                ReplyTo::ForwardProposals(_) => Ok(self.into()),
+            },
+
+            Event::ClientProposals(commands) => {
+                debug!(
+                    "{:?}: Dropping {} client proposals, there is no leader while an election is running",
+                    self.server_id,
+                    commands.len()
+                );
+                Ok(self.into())
+            }
+
+            // Only the leader syncs in the background, anything left over is from a term it lost
+            Event::LogSynced(_) => Ok(self.into()),
+
+            Event::SnapshotTaken(snapshot) => {
+                self.compact_log(snapshot, storage, actions)?;
+                Ok(self.into())
+            }
+
+            Event::MembershipChangeRequested { id, .. } => {
+                actions.push(Action::MembershipChangeFinished {
+                    id,
+                    outcome: Err(MembershipChangeError::NotLeader),
+                });
+                Ok(self.into())
+            }
+
+            Event::LeaseReadRequested { id } => {
+                actions.push(Action::LeaseReadFinished {
+                    id,
+                    outcome: Err(ReadError::NotLeader(None)),
+                });
+                Ok(self.into())
+            }
+
+            Event::StepDownRequested { id, .. } => {
+                actions.push(Action::StepDownFinished {
+                    id,
+                    outcome: Err(StepDownError::NotLeader),
+                });
+                Ok(self.into())
+            }
+
+            Event::Resumed { paused_for } => {
+                let remaining = self.postpone_election_timer(paused_for);
+                actions.push(Action::SetNextTimeout(remaining));
+                Ok(self.into())
+            }
+        }
+    }
+}
+
+has_election_timer!(PreCandidate);
+impl NodeState<PreCandidate> {
+    /// Asks every other member whether it would vote for us in the next term, leaving ours as it is
+    fn start_pre_vote<PS, C>(
+        &mut self,
+        config: &RaftConfig,
+        storage: &PS,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) where
+        PS: PersistentStorage<C>,
+        C: LogCommand,
+    {
+        trace!(
+            "{server_id:?}: Starting pre-vote!",
+            server_id = self.server_id
+        );
+        let election_timeout = self.reset_election_timer(config, rng);
+        self.inner.pre_votes_received = HashSet::new();
+        self.inner.pre_votes_received.insert(self.server_id);
+
+        actions.push(Action::SetNextTimeout(election_timeout));
+
+        let proposed_term = storage.current_term().increment();
+        for other_server in self.membership.others(self.server_id) {
+            actions.push(Action::OutgoingRpc(RpcMessage::pre_vote(RequestVote {
+                request_id: Uuid::new_v4(),
+                from: self.server_id,
+                to: other_server,
+                term: proposed_term,
+                last_log_index: storage.last_entry_index().unwrap_or(LogIndex(0)),
+                last_log_term: storage.last_entry_term().unwrap_or(TermIndex(0)),
+                leadership_transfer: false,
+            })));
+        }
+    }
+
+    /// Becomes a candidate and starts the election once a majority would vote for us
+    fn start_election_if_quorum<PS, C>(
+        self,
+        config: &RaftConfig,
+        storage: &mut PS,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        PS: PersistentStorage<C>,
+        C: LogCommand,
+    {
+        if self.inner.pre_votes_received.len() < self.quorum() {
+            return Ok(self.into());
+        }
+        info!(
+            "{server_id:?}: Won pre-vote with {votes:?}, becoming candidate...",
+            server_id = self.server_id,
+            votes = self.inner.pre_votes_received
+        );
+        let mut new_state: NodeState<Candidate> = self.transition_to();
+        new_state.start_new_election(config, storage, rng, false, actions)?;
+        Ok(new_state.into())
+    }
+}
+
+impl Transitions for NodeState<PreCandidate> {
+    fn handle_event<C, PS>(
+        mut self,
+        event: Event<C>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        match event {
+            Event::Tick(now) => {
+                if now >= self.inner.last_election_timer_started + self.inner.election_timeout {
+                    trace!(
+                        "{server_id:?}: In pre-candidate mode, did not receive enough pre-votes before election timeout {timeout:?}ms, asking again",
+                        server_id = self.server_id,
+                        timeout=self.inner.election_timeout.as_millis()
+                    );
+                    self.start_pre_vote(config, storage, rng, actions);
+                    self.start_election_if_quorum(config, storage, rng, actions)
+                } else {
+                    Ok(self.into())
+                }
+            }
+
+            Event::LogEntryAppliedByApplication(index) => {
+                self.record_applied(index, actions);
+                Ok(self.into())
+            }
+
+            Event::IncomingRpc(RpcMessage::Request(rpc_req)) => match rpc_req {
+                Request::PreVote(req) => {
+                    self.answer_pre_vote(storage, req, actions);
+                    Ok(self.into())
+                }
+
+                Request::RequestVote(req) if req.term < storage.current_term() => {
+                    self.vote_no(
+                        storage,
+                        req,
+                        "I am in a higher term than this vote request",
+                        actions,
+                    );
+                    Ok(self.into())
+                }
+
+                Request::AppendEntries(req) if req.term < storage.current_term() => {
+                    self.ack_append_entries(storage, req, false, None, actions);
+                    Ok(self.into())
+                }
+
+                Request::InstallSnapshot(req) if req.term < storage.current_term() => {
+                    self.ack_install_snapshot(storage, req, 0, actions);
+                    Ok(self.into())
+                }
+
+                Request::TimeoutNow(req) if req.term < storage.current_term() => {
+                    self.ack_timeout_now(storage, req, actions);
+                    Ok(self.into())
+                }
+
+                // Answered by the runtime tracking proposals, never handed to the node
+                Request::ForwardProposals(_) => Ok(self.into()),
+
+                // Our term never moved, so someone else's election or a leader of it is ours to
+                // take part in or follow as the follower we still are, with a fresh election timer
+                req => {
+                    let mut follower_state: NodeState<Follower> = self.transition_to();
+                    let election_timeout = follower_state.reset_election_timer(config, rng);
+                    actions.push(Action::SetNextTimeout(election_timeout));
+                    follower_state.handle_event(
+                        Event::IncomingRpc(RpcMessage::Request(req)),
+                        storage,
+                        config,
+                        rng,
+                        actions,
+                    )
+                }
+            },
+
+            Event::IncomingRpc(RpcMessage::Reply(reply)) => match reply {
+                // Only members' pre-votes count, like their votes
+                ReplyTo::PreVote(vote)
+                    if vote.vote_granted && self.membership.contains(vote.from) =>
+                {
+                    self.inner.pre_votes_received.insert(vote.from);
+                    self.start_election_if_quorum(config, storage, rng, actions)
+                }
+
+                _ => Ok(self.into()),
+            },
+
+            Event::ClientProposals(commands) => {
+                debug!(
+                    "{:?}: Dropping {} client proposals, there is no leader while asking for pre-votes",
+                    self.server_id,
+                    commands.len()
+                );
+                Ok(self.into())
+            }
+
+            // Only the leader syncs in the background, anything left over is from a term it lost
+            Event::LogSynced(_) => Ok(self.into()),
+
+            Event::SnapshotTaken(snapshot) => {
+                self.compact_log(snapshot, storage, actions)?;
+                Ok(self.into())
+            }
+
+            Event::MembershipChangeRequested { id, .. } => {
+                actions.push(Action::MembershipChangeFinished {
+                    id,
+                    outcome: Err(MembershipChangeError::NotLeader),
+                });
+                Ok(self.into())
+            }
+
+            Event::LeaseReadRequested { id } => {
+                actions.push(Action::LeaseReadFinished {
+                    id,
+                    outcome: Err(ReadError::NotLeader(None)),
+                });
+                Ok(self.into())
+            }
+
+            Event::StepDownRequested { id, .. } => {
+                actions.push(Action::StepDownFinished {
+                    id,
+                    outcome: Err(StepDownError::NotLeader),
+                });
+                Ok(self.into())
+            }
+
+            Event::Resumed { paused_for } => {
+                let remaining = self.postpone_election_timer(paused_for);
+                actions.push(Action::SetNextTimeout(remaining));
+                Ok(self.into())
+            }
+        }
+    }
+}
+
+/// How long a new node waits for a leader before starting its first election
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirstElectionTimeout(pub Duration);
+
+has_election_timer!(Follower);
+impl NodeState<Follower> {
+    pub(crate) fn new<C, PS>(
+        server_id: ServerId,
+        other_servers: HashSet<ServerId>,
+        last_applied: LogIndex,
+        storage: &PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        now: Instant,
+    ) -> (Self, FirstElectionTimeout)
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let follower_state = Follower::new(now);
+        let mut initial_members = other_servers;
+        if !config.join_existing_cluster {
+            let _ = initial_members.insert(server_id);
+        }
+
+        let mut node_state = Self {
+            start_time: now,
+            current_time: now,
+            server_id,
+            membership: Membership::load(initial_members, storage),
+            // Only committed entries get applied
+            commit_index: last_applied,
+            last_applied,
+            handed_to_application: last_applied,
+            snapshot_requested: false,
+            inner: follower_state,
+        };
+        let election_timeout = node_state.reset_election_timer(config, rng);
+        (node_state, FirstElectionTimeout(election_timeout))
+    }
+
+    fn vote_in_election<C, PS>(
+        &mut self,
+        storage: &mut PS,
+        vote_req: RequestVote,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        self.inner.leader_id = None;
+
+        // Reply false if term < currentTerm (§5.1)
+        // If votedFor is null or candidateId, and candidate’s log is at
+        // least as up-to-date as receiver’s log, grant vote (§5.2, §5.4)
+        let candidate_has_same_or_newer_term = vote_req.term >= storage.current_term();
+        let candidate_log_is_up_to_date =
+            candidate_log_is_up_to_date(storage, vote_req.last_log_index, vote_req.last_log_term);
+        let we_voted_this_term_already = storage.vote_for_current_term().is_some();
+        let we_voted_for_same_candidate_this_term_already = storage
+            .vote_for_current_term()
+            .map(|current_vote| current_vote == vote_req.from)
+            .unwrap_or(false);
+
+        let vote_granted = candidate_has_same_or_newer_term
+            && candidate_log_is_up_to_date
+            && (!we_voted_this_term_already || we_voted_for_same_candidate_this_term_already);
+
+        if vote_granted {
+            info!(
+                "{server_id:?}: Voting for candidate {candidate_id:?} in term {term:?}",
+                server_id = self.server_id,
+                candidate_id = vote_req.from,
+                term = vote_req.term
+            );
+            storage.persist_term_and_vote(storage.current_term(), Some(vote_req.from))?;
+        }
+
+        actions.push(Action::OutgoingRpc(RpcMessage::vote(Vote {
+            request_id: vote_req.request_id,
+            from: self.server_id,
+            to: vote_req.from,
+            term: storage.current_term(),
+            vote_granted,
+        })));
+        if vote_granted {
+            actions.push(Action::PublishEvent(RaftEvent::VoteGranted {
+                server_id: self.server_id,
+                candidate_id: vote_req.from,
+                term: storage.current_term(),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Appends the leader's entries if our log has the entry they follow (§5.3), syncing them
+    /// before the ack goes out, and follows the leader's commit index. Returns whether the log matched.
+    fn append_leader_entries<C, PS>(
+        &mut self,
+        req: &AppendEntries<C>,
+        storage: &mut PS,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Result<(), LogConflict>, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        // Compacted entries were committed, so they match the leader's
+        let snapshot_index = storage.snapshot_index();
+        if req.prev_log_index >= snapshot_index
+            && !storage.has_entry(req.prev_log_index, req.prev_log_term)
+        {
+            debug!(
+                "{:?}: Rejecting entries after {:?}, our log has term {:?} there instead of {:?}",
+                self.server_id,
+                req.prev_log_index,
+                storage.entry_term(req.prev_log_index),
+                req.prev_log_term
+            );
+            return Ok(Err(log_conflict_at(storage, req.prev_log_index)));
+        }
+
+        let new_entries: Vec<LogEntry<C>> = req
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.index > snapshot_index && !storage.has_entry(entry.index, entry.term)
+            })
+            .cloned()
+            .collect();
+        if let Some(first_new_entry) = new_entries.first() {
+            // Entries replacing a configuration entry cut it off, the one before it is back in effect
+            let membership_may_change = first_new_entry.index <= self.membership.index()
+                || new_entries
+                    .iter()
+                    .any(|entry| matches!(entry.command, LogEntryCommand::MembershipChange(_)));
+            for entry in &new_entries {
+                actions.push(Action::PublishEvent(RaftEvent::EntryAppended {
+                    server_id: self.server_id,
+                    index: entry.index,
+                    term: entry.term,
+                }));
+            }
+            storage.append(new_entries).sync()?;
+            if membership_may_change {
+                self.reload_membership(storage, actions);
+            }
+        }
+
+        let last_new_index = LogIndex(req.prev_log_index.0 + req.entries.len() as u64);
+        let commit_index = req.leader_commit.min(last_new_index);
+        if commit_index > self.commit_index {
+            self.commit_index = commit_index;
+            actions.push(Action::PublishEvent(RaftEvent::EntryCommitted {
+                server_id: self.server_id,
+                index: commit_index,
+            }));
+        }
+        Ok(Ok(()))
+    }
+
+    /// Adds a chunk of the leader's snapshot to the ones received before it, and installs the
+    /// snapshot with the last one. Returns where the next chunk should start. A chunk that doesn't
+    /// pick up where the received ones end is dropped and the leader asked to go on from there, or
+    /// from the start if it's of a snapshot other than the one being received.
+    fn receive_snapshot_chunk<C, PS>(
+        &mut self,
+        req: &InstallSnapshot,
+        storage: &mut PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<u64, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let chunk = &req.snapshot;
+        if req.offset == 0 {
+            self.inner.incoming_snapshot = Some(chunk.clone());
+        } else {
+            match &mut self.inner.incoming_snapshot {
+                Some(incoming) if same_snapshot(incoming, chunk) => {
+                    let received = incoming.data.len() as u64;
+                    if received != req.offset {
+                        debug!(
+                            "{:?}: Dropping snapshot chunk at {}, received {} bytes of it so far",
+                            self.server_id, req.offset, received
+                        );
+                        return Ok(received);
+                    }
+                    incoming.data.extend_from_slice(&chunk.data);
+                }
+                _ => {
+                    debug!(
+                        "{:?}: Dropping chunk of the snapshot up to {:?}, its start is missing",
+                        self.server_id, chunk.last_included_index
+                    );
+                    return Ok(0);
+                }
+            }
+        }
+
+        let received = self
+            .inner
+            .incoming_snapshot
+            .as_ref()
+            .map_or(0, |incoming| incoming.data.len() as u64);
+        if req.done {
+            if let Some(snapshot) = self.inner.incoming_snapshot.take() {
+                self.install_leader_snapshot(&snapshot, storage, config, actions)?;
+            }
+        }
+        Ok(received)
+    }
+
+    /// Replaces the log up to the leader's snapshot with it and has the application restore it,
+    /// unless the application already got every entry the snapshot covers. A witness keeps the
+    /// snapshot without its data and restores nothing.
+    fn install_leader_snapshot<C, PS>(
+        &mut self,
+        snapshot: &Snapshot,
+        storage: &mut PS,
+        config: &RaftConfig,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<(), RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        let last_included_index = snapshot.last_included_index;
+        if last_included_index <= self.handed_to_application {
+            return Ok(());
+        }
+        info!(
+            "{:?}: Installing snapshot up to {:?} from the leader",
+            self.server_id, last_included_index
+        );
+        let witness = config.is_witness(self.server_id);
+        let snapshot = if witness {
+            Snapshot {
+                data: Vec::new(),
+                ..snapshot.clone()
+            }
+        } else {
+            snapshot.clone()
+        };
+        storage.install_snapshot(snapshot.clone()).sync()?;
+        self.reload_membership(storage, actions);
+        if last_included_index > self.commit_index {
+            self.commit_index = last_included_index;
+            actions.push(Action::PublishEvent(RaftEvent::EntryCommitted {
+                server_id: self.server_id,
+                index: last_included_index,
+            }));
+        }
+        self.last_applied = last_included_index;
+        self.handed_to_application = last_included_index;
+        if !witness {
+            actions.push(Action::RestoreSnapshot(snapshot.clone()));
+        }
+        actions.push(Action::PublishEvent(RaftEvent::SnapshotInstalled {
+            server_id: self.server_id,
+            last_included_index,
+            last_included_term: snapshot.last_included_term,
+        }));
+        Ok(())
+    }
+}
+
+impl Transitions for NodeState<Follower> {
+    fn handle_event<C, PS>(
+        mut self,
+        event: Event<C>,
+        storage: &mut PS,
+        config: &RaftConfig,
+        rng: &mut ChaCha8Rng,
+        actions: &mut Vec<Action<C>>,
+    ) -> Result<Node, RaftError>
+    where
+        C: LogCommand,
+        PS: PersistentStorage<C>,
+    {
+        match event {
+            Event::Tick(now) => {
+                if now >= self.inner.last_election_timer_started + self.inner.election_timeout
+                    && (!self.membership.contains(self.server_id)
+                        || config.is_witness(self.server_id))
+                {
+                    // Not a member yet, or anymore, or a witness, only members with the whole log start elections
+                    let election_timeout = self.reset_election_timer(config, rng);
+                    actions.push(Action::SetNextTimeout(election_timeout));
+                    Ok(self.into())
+                } else if now
+                    >= self.inner.last_election_timer_started + self.inner.election_timeout
+                    && config.pre_vote
+                {
+                    info!(
+                        "{server_id:?}: In follower state, did not receive heartbeat before election timeout {timeout:?}ms, asking for pre-votes...",
+                        server_id=self.server_id,
+                        timeout=self.inner.election_timeout.as_millis(),
+                    );
+                    let mut new_state: NodeState<PreCandidate> = self.transition_to();
+                    new_state.start_pre_vote(config, storage, rng, actions);
+                    new_state.start_election_if_quorum(config, storage, rng, actions)
+                } else if now
+                    >= self.inner.last_election_timer_started + self.inner.election_timeout
+                {
+                    info!(
+                        "{server_id:?}: In follower state, did not receive heartbeat before election timeout {timeout:?}ms, becoming candidate...",
+                        server_id=self.server_id,
+                        timeout=self.inner.election_timeout.as_millis(),
+                    );
+                    let mut new_state: NodeState<Candidate> = self.transition_to();
+                    new_state.start_new_election(config, storage, rng, false, actions)?;
+                    Ok(new_state.into())
+                } else {
+                    Ok(self.into())
+                }
+            }
+
+            Event::LogEntryAppliedByApplication(index) => {
+                self.record_applied(index, actions);
+                Ok(self.into())
+            }
+
+            Event::IncomingRpc(RpcMessage::Request(rpc_req)) => match rpc_req {
+                Request::RequestVote(req) => {
+                    if req.term < storage.current_term() {
+                        self.vote_no(storage, req, "term is less than current term", actions);
+                    } else {
+                        self.inner.leader_id = None;
+                        self.vote_in_election(storage, req, actions)?;
+                    }
+                    Ok(self.into())
+                }
+
+                Request::PreVote(req) => {
+                    self.answer_pre_vote(storage, req, actions);
+                    Ok(self.into())
+                }
+
+                Request::AppendEntries(req) => {
+                    if req.term < storage.current_term() {
+                        self.ack_append_entries(storage, req, false, None, actions);
+                    } else {
+                        self.inner.leader_id = Some(req.from);
+                        self.inner.last_heard_from_leader = Some(self.current_time);
+                        self.inner.elections_without_leader = 0;
+                        let election_timeout = self.reset_election_timer(config, rng);
+                        let appended = self.append_leader_entries(&req, storage, actions)?;
+                        self.ack_append_entries(
+                            storage,
+                            req,
+                            appended.is_ok(),
+                            appended.err(),
+                            actions,
+                        );
+                        actions.push(Action::SetNextTimeout(election_timeout));
+                    }
+                    Ok(self.into())
+                }
+
+                Request::InstallSnapshot(req) => {
+                    if req.term >= storage.current_term() {
+                        self.inner.leader_id = Some(req.from);
+                        self.inner.last_heard_from_leader = Some(self.current_time);
+                        self.inner.elections_without_leader = 0;
+                        let election_timeout = self.reset_election_timer(config, rng);
+                        let next_offset =
+                            self.receive_snapshot_chunk(&req, storage, config, actions)?;
+                        actions.push(Action::SetNextTimeout(election_timeout));
+                        self.ack_install_snapshot(storage, req, next_offset, actions);
+                    } else {
+                        self.ack_install_snapshot(storage, req, 0, actions);
+                    }
+                    Ok(self.into())
+                }
+
+                Request::TimeoutNow(req) => {
+                    let take_over = req.term == storage.current_term()
+                        && self.membership.contains(self.server_id)
+                        && !config.is_witness(self.server_id);
+                    let from = req.from;
+                    self.ack_timeout_now(storage, req, actions);
+                    if take_over {
+                        info!(
+                            "{:?}: {:?} handed leadership over to us, becoming candidate...",
+                            self.server_id, from
+                        );
+                        let mut new_state: NodeState<Candidate> = self.transition_to();
+                        new_state.start_new_election(config, storage, rng, true, actions)?;
+                        Ok(new_state.into())
+                    } else {
+                        Ok(self.into())
+                    }
+                }
+
+                // Answered by the runtime tracking proposals, never handed to the node
+                Request::ForwardProposals(_) => Ok(self.into()),
+            },
+
+            // Followers don't send out RPCs so ignore replies, this can only happen for rpc responses delivered late
+            Event::IncomingRpc(RpcMessage::Reply(_)) => Ok(self.into()),
+
+            Event::ClientProposals(commands) => {
+                debug!(
+                    "{:?}: Dropping {} client proposals, only the leader {:?} accepts them",
+                    self.server_id,
+                    commands.len(),
+                    self.inner.leader_id
+                );
+                Ok(self.into())
+            }
+
+            // Only the leader syncs in the background, anything left over is from a term it lost
+            Event::LogSynced(_) => Ok(self.into()),
+
+            Event::SnapshotTaken(snapshot) => {
+                self.compact_log(snapshot, storage, actions)?;
+                Ok(self.into())
+            }
+
+            Event::MembershipChangeRequested { id, .. } => {
+                actions.push(Action::MembershipChangeFinished {
+                    id,
+                    outcome: Err(MembershipChangeError::NotLeader),
+                });
+                Ok(self.into())
+            }
+
+            Event::LeaseReadRequested { id } => {
+                actions.push(Action::LeaseReadFinished {
+                    id,
+                    outcome: Err(ReadError::NotLeader(self.inner.leader_id)),
+                });
+                Ok(self.into())
+            }
+
+            Event::StepDownRequested { id, .. } => {
+                actions.push(Action::StepDownFinished {
+                    id,
+                    outcome: Err(StepDownError::NotLeader),
+                });
+                Ok(self.into())
+            }
+
+            Event::Resumed { paused_for } => {
+                let remaining = self.postpone_election_timer(paused_for);
+                actions.push(Action::SetNextTimeout(remaining));
+                Ok(self.into())
+            }
+        }
+    }
+}
+
+impl<InState, OutState> CanTransitionTo<OutState> for NodeState<InState>
+where
+    InState: State,
+    OutState: State + FromState<InState>,
+{
+    fn transition_to(self) -> NodeState<OutState> {
+        NodeState {
+            inner: OutState::from_state(self.inner, self.current_time),
+            server_id: self.server_id,
+            start_time: self.start_time,
+            current_time: self.current_time,
+            membership: self.membership,
+            commit_index: self.commit_index,
+            last_applied: self.last_applied,
+            handed_to_application: self.handed_to_application,
+            snapshot_requested: self.snapshot_requested,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+pub use std::time::Instant;
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Return the current system monotonic clock time
+pub fn now() -> Instant {
+    Instant::now()
+}
+
+/// Where a node reads the time from. It has to be monotonic, the node's timers, leases and
+/// heartbeats all count from what it returns.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        now()
+    }
+}
+
+/// A clock that only moves when it's told to, for tests and simulators controlling time per node.
+/// Clones read and move the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+impl ManualClock {
+    /// A clock stopped at the system clock's current time
+    pub fn new() -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(now())),
+        }
+    }
+
+    /// Moves the clock forward by `by`
+    pub fn advance(&self, by: Duration) {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) += by;
+    }
+}
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,277 @@
+/// Tests validation of the Raft config
+use raft_core::{
+    AppendEntriesBatchLimits, CatchUpRounds, ElectionTimeoutRange, GroupCommit, HeartbeatInterval,
+    MaxMessageSize, MaxUnappliedEntries, PeerOverrides, RaftConfig, RaftConfigError, ReadLease,
+    RpcTimeout, ServerId, SnapshotChunkSize, SnapshotThreshold, TimingUpdate,
+};
+use std::time::Duration;
+
+#[test]
+fn should_build_config_with_defaults() {
+    let config = RaftConfig::builder().build().unwrap();
+
+    assert_eq!(
+        config.leader_heartbeat_interval,
+        HeartbeatInterval(Duration::from_millis(100))
+    );
+    assert_eq!(
+        config.election_timeout,
+        ElectionTimeoutRange::from_millis(150, 300)
+    );
+    assert_eq!(config.rpc_timeout, RpcTimeout(Duration::from_secs(1)));
+}
+
+#[test]
+fn should_reject_empty_election_timeout_range() {
+    let result = RaftConfig::builder()
+        .election_timeout(ElectionTimeoutRange::from_millis(300, 300))
+        .build();
+
+    assert_eq!(
+        result.unwrap_err(),
+        RaftConfigError::EmptyElectionTimeoutRange(ElectionTimeoutRange::from_millis(300, 300))
+    );
+}
+
+#[test]
+fn should_reject_heartbeat_interval_not_shorter_than_election_timeout() {
+    let result = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(150)))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300))
+        .build();
+
+    assert!(matches!(
+        result,
+        Err(RaftConfigError::HeartbeatNotShorterThanElectionTimeout { .. })
+    ));
+}
+
+#[test]
+fn should_reject_zero_heartbeat_interval() {
+    let result = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::ZERO))
+        .build();
+
+    assert_eq!(result.unwrap_err(), RaftConfigError::ZeroHeartbeatInterval);
+}
+
+#[test]
+fn should_reject_zero_rpc_timeout_for_any_peer() {
+    let result = RaftConfig::builder()
+        .peer_overrides(
+            ServerId(2),
+            PeerOverrides {
+                rpc_timeout: Some(RpcTimeout(Duration::ZERO)),
+                ..PeerOverrides::default()
+            },
+        )
+        .build();
+
+    assert_eq!(result.unwrap_err(), RaftConfigError::ZeroRpcTimeout);
+}
+
+#[test]
+fn should_reject_zero_snapshot_threshold() {
+    let result = RaftConfig::builder()
+        .snapshot_threshold(SnapshotThreshold(0))
+        .build();
+
+    assert_eq!(result.unwrap_err(), RaftConfigError::ZeroSnapshotThreshold);
+}
+
+#[test]
+fn should_reject_zero_snapshot_chunk_size() {
+    let result = RaftConfig::builder()
+        .snapshot_chunk_size(SnapshotChunkSize(0))
+        .build();
+
+    assert_eq!(result.unwrap_err(), RaftConfigError::ZeroSnapshotChunkSize);
+}
+
+#[test]
+fn should_reject_zero_max_unapplied_entries() {
+    let result = RaftConfig::builder()
+        .max_unapplied_entries(MaxUnappliedEntries(0))
+        .build();
+
+    assert_eq!(
+        result.unwrap_err(),
+        RaftConfigError::ZeroMaxUnappliedEntries
+    );
+}
+
+#[test]
+fn should_reject_zero_catch_up_rounds() {
+    let result = RaftConfig::builder()
+        .catch_up_rounds(CatchUpRounds(0))
+        .build();
+
+    assert_eq!(result.unwrap_err(), RaftConfigError::ZeroCatchUpRounds);
+}
+
+#[test]
+fn should_use_peer_overrides_over_cluster_wide_settings() {
+    let remote_peer = ServerId(3);
+    let config = RaftConfig::builder()
+        .peer_overrides(
+            remote_peer,
+            PeerOverrides {
+                heartbeat_interval: Some(HeartbeatInterval(Duration::from_millis(120))),
+                rpc_timeout: Some(RpcTimeout(Duration::from_secs(3))),
+            },
+        )
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        config.heartbeat_interval_for(remote_peer),
+        HeartbeatInterval(Duration::from_millis(120))
+    );
+    assert_eq!(
+        config.rpc_timeout_for(remote_peer),
+        RpcTimeout(Duration::from_secs(3))
+    );
+    assert_eq!(
+        config.heartbeat_interval_for(ServerId(2)),
+        config.leader_heartbeat_interval
+    );
+    assert_eq!(config.rpc_timeout_for(ServerId(2)), config.rpc_timeout);
+}
+
+#[test]
+fn should_reject_peer_heartbeat_override_not_shorter_than_election_timeout() {
+    let result = RaftConfig::builder()
+        .peer_overrides(
+            ServerId(3),
+            PeerOverrides {
+                heartbeat_interval: Some(HeartbeatInterval(Duration::from_millis(200))),
+                rpc_timeout: None,
+            },
+        )
+        .build();
+
+    assert!(matches!(
+        result,
+        Err(RaftConfigError::HeartbeatNotShorterThanElectionTimeout { .. })
+    ));
+}
+
+#[test]
+fn should_reject_initial_batch_size_outside_of_its_bounds() {
+    let limits = AppendEntriesBatchLimits {
+        min_entries: 16,
+        initial_entries: 8,
+        max_entries: 1024,
+        max_bytes: 1024 * 1024,
+    };
+
+    let result = RaftConfig::builder().append_entries_batch(limits).build();
+
+    assert_eq!(
+        result.unwrap_err(),
+        RaftConfigError::InvalidAppendEntriesBatchLimits(limits)
+    );
+}
+
+#[test]
+fn should_reject_a_zero_byte_batch_limit() {
+    let limits = AppendEntriesBatchLimits {
+        max_bytes: 0,
+        ..AppendEntriesBatchLimits::default()
+    };
+
+    let result = RaftConfig::builder().append_entries_batch(limits).build();
+
+    assert_eq!(
+        result.unwrap_err(),
+        RaftConfigError::InvalidAppendEntriesBatchLimits(limits)
+    );
+}
+
+#[test]
+fn should_keep_batches_and_snapshot_chunks_to_half_the_max_message_size() {
+    let config = RaftConfig::builder().build().unwrap();
+    assert_eq!(config.append_entries_max_bytes(), 1024 * 1024);
+    assert_eq!(config.snapshot_chunk_bytes(), 1024 * 1024);
+    assert_eq!(config.max_entry_bytes(), usize::MAX);
+
+    let config = RaftConfig::builder()
+        .max_message_size(MaxMessageSize(64 * 1024))
+        .snapshot_chunk_size(SnapshotChunkSize(16 * 1024))
+        .build()
+        .unwrap();
+    assert_eq!(config.append_entries_max_bytes(), 32 * 1024);
+    assert_eq!(config.snapshot_chunk_bytes(), 16 * 1024);
+    assert_eq!(config.max_entry_bytes(), 32 * 1024);
+
+    let result = RaftConfig::builder()
+        .max_message_size(MaxMessageSize(0))
+        .build();
+    assert_eq!(result.unwrap_err(), RaftConfigError::ZeroMaxMessageSize);
+}
+
+#[test]
+fn should_reject_a_group_commit_window_not_shorter_than_the_heartbeat_interval() {
+    let group_commit = GroupCommit {
+        window: Duration::from_millis(100),
+        max_entries: 64,
+    };
+    let result = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(100)))
+        .group_commit(group_commit)
+        .build();
+
+    assert_eq!(
+        result.unwrap_err(),
+        RaftConfigError::InvalidGroupCommit {
+            group_commit,
+            heartbeat_interval: HeartbeatInterval(Duration::from_millis(100)),
+        }
+    );
+}
+
+#[test]
+fn should_reject_a_read_lease_without_leader_stickiness() {
+    let lease = ReadLease {
+        duration: Duration::from_millis(120),
+        clock_skew_bound: Duration::from_millis(10),
+    };
+    let result = RaftConfig::builder()
+        .read_lease(lease)
+        .leader_stickiness(false)
+        .build();
+
+    assert_eq!(
+        result.unwrap_err(),
+        RaftConfigError::ReadLeaseWithoutLeaderStickiness(lease)
+    );
+}
+
+#[test]
+fn should_check_the_read_lease_again_on_a_timing_update() {
+    let lease = ReadLease {
+        duration: Duration::from_millis(120),
+        clock_skew_bound: Duration::from_millis(10),
+    };
+    let config = RaftConfig::builder().read_lease(lease).build().unwrap();
+
+    let shorter_election_timeout = TimingUpdate {
+        election_timeout: Some(ElectionTimeoutRange::from_millis(110, 220)),
+        ..TimingUpdate::default()
+    };
+    assert!(matches!(
+        config.with_timing(shorter_election_timeout),
+        Err(RaftConfigError::InvalidReadLease { .. })
+    ));
+
+    let without_stickiness = RaftConfig {
+        leader_stickiness: false,
+        ..config
+    };
+    assert_eq!(
+        without_stickiness
+            .with_timing(TimingUpdate::default())
+            .unwrap_err(),
+        RaftConfigError::ReadLeaseWithoutLeaderStickiness(lease)
+    );
+}
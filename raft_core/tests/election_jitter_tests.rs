@@ -0,0 +1,74 @@
+/// Tests the election timeout strategies
+use raft_core::{
+    ElectionJitter, ElectionTimeoutContext, ElectionTimeoutRange, ExponentialBackoffJitter,
+    PriorityWeightedJitter, ServerId, UniformJitter,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::time::Duration;
+
+const RANGE: ElectionTimeoutRange = ElectionTimeoutRange {
+    min: Duration::from_millis(150),
+    max: Duration::from_millis(300),
+};
+
+fn context(server_id: u64, elections_without_leader: u32) -> ElectionTimeoutContext {
+    ElectionTimeoutContext {
+        server_id: ServerId(server_id),
+        elections_without_leader,
+    }
+}
+
+fn mean_timeout(jitter: &dyn ElectionJitter, context: ElectionTimeoutContext) -> Duration {
+    let mut rng = ChaCha8Rng::seed_from_u64(7);
+    let total: Duration = (0..1000)
+        .map(|_| jitter.next_timeout(RANGE, context, &mut rng))
+        .sum();
+    total / 1000
+}
+
+#[test]
+fn should_draw_uniform_timeouts_from_the_range() {
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    for _ in 0..1000 {
+        let timeout = UniformJitter.next_timeout(RANGE, context(1, 1), &mut rng);
+        assert!(RANGE.min <= timeout && timeout < RANGE.max);
+    }
+}
+
+#[test]
+fn should_pick_the_same_timeouts_for_the_same_seed() {
+    let jitter = PriorityWeightedJitter::new().weight(ServerId(1), 2.0);
+    let mut first_rng = ChaCha8Rng::seed_from_u64(3);
+    let mut second_rng = ChaCha8Rng::seed_from_u64(3);
+    for _ in 0..100 {
+        assert_eq!(
+            jitter.next_timeout(RANGE, context(1, 0), &mut first_rng),
+            jitter.next_timeout(RANGE, context(1, 0), &mut second_rng)
+        );
+    }
+}
+
+#[test]
+fn should_double_timeouts_for_every_election_without_a_leader_up_to_the_cap() {
+    let jitter = ExponentialBackoffJitter { max_doublings: 2 };
+    let mut rng = ChaCha8Rng::seed_from_u64(5);
+    for (elections, factor) in [(0, 1), (1, 1), (2, 2), (3, 4), (10, 4)] {
+        let timeout = jitter.next_timeout(RANGE, context(1, elections), &mut rng);
+        assert!(RANGE.min * factor <= timeout && timeout < RANGE.max * factor);
+    }
+    assert_eq!(jitter.longest_timeout(RANGE), RANGE.max * 4);
+}
+
+#[test]
+fn should_give_heavier_weighted_servers_shorter_timeouts() {
+    let jitter = PriorityWeightedJitter::new()
+        .weight(ServerId(1), 4.0)
+        .weight(ServerId(2), 0.25)
+        .weight(ServerId(3), -1.0);
+
+    let preferred = mean_timeout(&jitter, context(1, 0));
+    let unweighted = mean_timeout(&jitter, context(3, 0));
+    let deferred = mean_timeout(&jitter, context(2, 0));
+    assert!(preferred < unweighted && unweighted < deferred);
+}
@@ -0,0 +1,42 @@
+/// Tests the log entries shared between AppendEntries messages
+use raft_core::rpc_messages::SharedEntries;
+use raft_core::{LogEntry, LogEntryCommand, LogIndex, TermIndex};
+
+fn entries(count: u64) -> Vec<LogEntry<u64>> {
+    (1..=count)
+        .map(|index| LogEntry {
+            index: LogIndex(index),
+            term: TermIndex(1),
+            command: LogEntryCommand::Application(index * 10),
+        })
+        .collect()
+}
+
+#[test]
+fn should_share_one_buffer_between_clones_and_slices() {
+    let batch: SharedEntries<u64> = entries(5).into();
+
+    let for_follower_behind = batch.clone();
+    let for_follower_caught_up = batch.slice(3..5);
+
+    assert_eq!(for_follower_behind.as_ptr(), batch.as_ptr());
+    assert_eq!(for_follower_caught_up.as_ptr(), batch[3..].as_ptr());
+    assert_eq!(&*for_follower_caught_up, &entries(5)[3..5]);
+}
+
+#[test]
+fn should_slice_relative_to_an_existing_slice() {
+    let batch: SharedEntries<u64> = entries(5).into();
+
+    let nested = batch.slice(1..4).slice(1..3);
+
+    assert_eq!(&*nested, &entries(5)[2..4]);
+}
+
+#[test]
+fn should_compare_entries_regardless_of_buffer() {
+    let batch: SharedEntries<u64> = entries(3).into();
+
+    assert_eq!(batch.slice(0..0), SharedEntries::empty());
+    assert_eq!(batch.slice(1..3), entries(3)[1..].to_vec().into());
+}
@@ -0,0 +1,73 @@
+/// Measures turning an AppendEntries into protobuf bytes and back, for heartbeats and for
+/// batches of entries, the work the gRPC transport does for every replication message.
+///
+/// Run with `cargo bench -p raft_grpc --bench append_entries_codec`
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prost::Message;
+use raft_consensus::rpc_messages::{AppendEntries, SharedEntries};
+use raft_consensus::{LogEntry, LogEntryCommand, LogIndex, ServerId, TermIndex};
+use raft_grpc::proto::AppendEntriesRequest;
+use uuid::Uuid;
+
+const BATCH_SIZES: [u64; 4] = [0, 1, 64, 1024];
+
+fn append_entries(entry_count: u64) -> AppendEntries<u64> {
+    AppendEntries {
+        request_id: Uuid::new_v4(),
+        from: ServerId(0),
+        to: ServerId(1),
+        term: TermIndex(3),
+        prev_log_term: TermIndex(3),
+        prev_log_index: LogIndex(1000),
+        entries: (1..=entry_count)
+            .map(|offset| LogEntry {
+                index: LogIndex(1000 + offset),
+                term: TermIndex(3),
+                command: LogEntryCommand::Application(offset),
+            })
+            .collect::<SharedEntries<u64>>(),
+        leader_commit: LogIndex(1000),
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append_entries_encode");
+    for entry_count in BATCH_SIZES {
+        let message = append_entries(entry_count);
+        group.throughput(Throughput::Elements(entry_count.max(1)));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(entry_count),
+            &message,
+            |b, message| {
+                b.iter(|| {
+                    let request: AppendEntriesRequest = message.clone().into();
+                    black_box(request.encode_to_vec())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append_entries_decode");
+    for entry_count in BATCH_SIZES {
+        let request: AppendEntriesRequest = append_entries(entry_count).into();
+        let bytes = request.encode_to_vec();
+        group.throughput(Throughput::Elements(entry_count.max(1)));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(entry_count),
+            &bytes,
+            |b, bytes| {
+                b.iter(|| {
+                    let request = AppendEntriesRequest::decode(bytes.as_slice()).unwrap();
+                    black_box(AppendEntries::<u64>::from(request))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,101 @@
+use clap::{Parser, Subcommand};
+use raft_grpc::proto::raft_admin_client::RaftAdminClient;
+use raft_grpc::proto::{ElapsedValue, InspectRequest, InspectResponse, ServerIdValue};
+use tonic::transport::Channel;
+
+/// Operator tool for inspecting the nodes of a Raft cluster
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Dump the diagnostic state of the node at the given address, ex: 127.0.0.1:5000
+    Inspect { node_address: String },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    match &cli.command {
+        Commands::Inspect { node_address } => {
+            let channel = Channel::from_shared(format!("http://{}", node_address))?
+                .connect()
+                .await?;
+            let mut client = RaftAdminClient::new(channel);
+            let diagnostics = client
+                .inspect(tonic::Request::new(InspectRequest {}))
+                .await?
+                .into_inner();
+            print_diagnostics(&diagnostics);
+        }
+    }
+    Ok(())
+}
+
+fn print_diagnostics(diagnostics: &InspectResponse) {
+    let format_server_id = |maybe_server_id: &Option<ServerIdValue>| match maybe_server_id {
+        Some(ServerIdValue { server_id }) => server_id.to_string(),
+        None => "-".to_string(),
+    };
+
+    println!("server:            {}", diagnostics.server_id);
+    println!("role:              {}", diagnostics.role);
+    println!("term:              {}", diagnostics.term);
+    println!("uptime:            {}s", diagnostics.uptime_ms / 1000);
+    println!(
+        "voted for:         {}",
+        format_server_id(&diagnostics.voted_for)
+    );
+    println!(
+        "leader:            {}",
+        format_server_id(&diagnostics.leader_id)
+    );
+    println!(
+        "log:               last index {} (term {}), commit index {}, last applied {}",
+        diagnostics.last_log_index,
+        diagnostics.last_log_term,
+        diagnostics.commit_index,
+        diagnostics.last_applied
+    );
+    println!("pending proposals: {}", diagnostics.pending_proposals);
+    println!("peers:             {:?}", diagnostics.peers);
+    println!(
+        "config:            heartbeat {}ms, election timeout {}-{}ms, rpc timeout {}ms",
+        diagnostics.leader_heartbeat_interval_ms,
+        diagnostics.min_election_timeout_ms,
+        diagnostics.max_election_timeout_ms,
+        diagnostics.rpc_timeout_ms
+    );
+    if let Some(elections) = &diagnostics.elections {
+        println!(
+            "elections:         {} started, {} split, {} pre-votes turned down, {} votes granted",
+            elections.elections_started,
+            elections.split_votes,
+            elections.pre_vote_rejections,
+            elections.votes_granted
+        );
+    }
+    if !diagnostics.replication_progress.is_empty() {
+        println!("replication progress:");
+        for progress in &diagnostics.replication_progress {
+            println!(
+                "  server {}: next index {}, match index {}, batch limit {}, in flight {}, last reply {}",
+                progress.server_id,
+                progress.next_index,
+                progress.match_index,
+                progress.append_entries_batch_limit,
+                progress.in_flight,
+                match &progress.since_last_reply {
+                    Some(ElapsedValue { ms }) => format!("{ms}ms ago"),
+                    None => "never".to_string(),
+                }
+            );
+        }
+    }
+}
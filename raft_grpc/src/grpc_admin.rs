@@ -0,0 +1,30 @@
+use crate::proto::raft_admin_server::RaftAdmin;
+use crate::proto::{InspectRequest, InspectResponse};
+use raft_consensus::RaftDiagnostics;
+use tonic::{Request, Response, Status};
+
+/// Admin gRPC endpoints for operators, answers from the diagnostics the Raft thread publishes
+/// so it keeps working when the Raft thread is stuck.
+#[derive(Debug)]
+pub struct RaftAdminServerImpl {
+    diagnostics: RaftDiagnostics,
+}
+
+impl RaftAdminServerImpl {
+    pub fn new(diagnostics: RaftDiagnostics) -> RaftAdminServerImpl {
+        RaftAdminServerImpl { diagnostics }
+    }
+}
+
+#[tonic::async_trait]
+impl RaftAdmin for RaftAdminServerImpl {
+    async fn inspect(
+        &self,
+        _: Request<InspectRequest>,
+    ) -> Result<Response<InspectResponse>, Status> {
+        match self.diagnostics.latest() {
+            Some(diagnostics) => Ok(Response::new(diagnostics.into())),
+            None => Err(Status::unavailable("Raft node has not started yet!")),
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use crate::grpc_transport::TransportMessage;
 use crate::proto::raft_consensus_server::RaftConsensus;
-use crate::proto::{AppendEntriesRequest, AppendEntriesResponse, VoteRequest, VoteResponse};
+use crate::proto::{
+    AppendEntriesRequest, AppendEntriesResponse, ForwardProposalsRequest, ForwardProposalsResponse,
+    InstallSnapshotRequest, InstallSnapshotResponse, TimeoutNowRequest, TimeoutNowResponse,
+    VoteRequest, VoteResponse,
+};
 use raft_consensus::rpc_messages;
 use std::thread;
 use tokio::sync::mpsc::error::SendError;
@@ -12,19 +16,19 @@ use tonic::{Request, Response, Status};
 #[derive(Debug)]
 pub struct RaftGrpcServerImpl {
     raft_input_tx: mpsc::UnboundedSender<TransportMessage>,
-    maybe_transport_thread_handle: Option<thread::JoinHandle<()>>,
+    maybe_raft_thread: Option<thread::Thread>,
 }
 
 impl RaftGrpcServerImpl {
     pub fn new(raft_input_tx: mpsc::UnboundedSender<TransportMessage>) -> RaftGrpcServerImpl {
         RaftGrpcServerImpl {
             raft_input_tx,
-            maybe_transport_thread_handle: None,
+            maybe_raft_thread: None,
         }
     }
 
-    pub fn register_raft_thread(&mut self, transport_thread_handle: thread::JoinHandle<()>) {
-        self.maybe_transport_thread_handle = Some(transport_thread_handle);
+    pub fn register_raft_thread(&mut self, raft_thread: thread::Thread) {
+        self.maybe_raft_thread = Some(raft_thread);
     }
 
     /// Send an incoming request to the Raft thread's message queue for processing
@@ -40,10 +44,9 @@ impl RaftGrpcServerImpl {
     ) -> Result<(), SendError<TransportMessage>> {
         self.raft_input_tx
             .send(TransportMessage::Request(reply_tx, incoming_request))?;
-        self.maybe_transport_thread_handle
+        self.maybe_raft_thread
             .as_ref()
             .expect("GRPC BUG ALERT: Transport thread not registered!")
-            .thread()
             .unpark();
         Ok(())
     }
@@ -58,10 +61,13 @@ impl RaftConsensus for RaftGrpcServerImpl {
         let vote_req = request.into_inner();
 
         let (reply_tx, reply_rx) = oneshot::channel();
-        if let Err(_) = self.send_incoming_request_to_transport(
-            reply_tx,
-            rpc_messages::Request::RequestVote(vote_req.into()),
-        ) {
+        if self
+            .send_incoming_request_to_transport(
+                reply_tx,
+                rpc_messages::Request::RequestVote(vote_req.into()),
+            )
+            .is_err()
+        {
             return Err(Status::internal("Raft state machine shutdown!"));
         }
 
@@ -74,6 +80,32 @@ impl RaftConsensus for RaftGrpcServerImpl {
         }
     }
 
+    async fn pre_vote(
+        &self,
+        request: Request<VoteRequest>,
+    ) -> Result<Response<VoteResponse>, Status> {
+        let pre_vote_req = request.into_inner();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self
+            .send_incoming_request_to_transport(
+                reply_tx,
+                rpc_messages::Request::PreVote(pre_vote_req.into()),
+            )
+            .is_err()
+        {
+            return Err(Status::internal("Raft state machine shutdown!"));
+        }
+
+        let pre_vote_response = reply_rx.await;
+
+        match pre_vote_response {
+            Ok(rpc_messages::ReplyTo::PreVote(vote)) => Ok(Response::new(vote.into())),
+            Err(_) => Err(Status::internal("Raft state machine shutdown!")),
+            _ => unreachable!("BUG ALERT: Unexpected response type, expected PreVote!"),
+        }
+    }
+
     async fn append_entries(
         &self,
         request: Request<AppendEntriesRequest>,
@@ -81,10 +113,13 @@ impl RaftConsensus for RaftGrpcServerImpl {
         let append_entries_req = request.into_inner();
 
         let (reply_tx, reply_rx) = oneshot::channel();
-        if let Err(_) = self.send_incoming_request_to_transport(
-            reply_tx,
-            rpc_messages::Request::AppendEntries(append_entries_req.into()),
-        ) {
+        if self
+            .send_incoming_request_to_transport(
+                reply_tx,
+                rpc_messages::Request::AppendEntries(append_entries_req.into()),
+            )
+            .is_err()
+        {
             return Err(Status::internal("Raft state machine shutdown!"));
         }
 
@@ -98,4 +133,88 @@ impl RaftConsensus for RaftGrpcServerImpl {
             _ => unreachable!("BUG ALERT: Unexpected response type, expected AppendEntries!"),
         }
     }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<InstallSnapshotRequest>,
+    ) -> Result<Response<InstallSnapshotResponse>, Status> {
+        let install_snapshot_req = request.into_inner();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self
+            .send_incoming_request_to_transport(
+                reply_tx,
+                rpc_messages::Request::InstallSnapshot(install_snapshot_req.into()),
+            )
+            .is_err()
+        {
+            return Err(Status::internal("Raft state machine shutdown!"));
+        }
+
+        let install_snapshot_response = reply_rx.await;
+
+        match install_snapshot_response {
+            Ok(rpc_messages::ReplyTo::InstallSnapshot(install_snapshot)) => {
+                Ok(Response::new(install_snapshot.into()))
+            }
+            Err(_) => Err(Status::internal("Raft state machine shutdown!")),
+            _ => unreachable!("BUG ALERT: Unexpected response type, expected InstallSnapshot!"),
+        }
+    }
+
+    async fn timeout_now(
+        &self,
+        request: Request<TimeoutNowRequest>,
+    ) -> Result<Response<TimeoutNowResponse>, Status> {
+        let timeout_now_req = request.into_inner();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self
+            .send_incoming_request_to_transport(
+                reply_tx,
+                rpc_messages::Request::TimeoutNow(timeout_now_req.into()),
+            )
+            .is_err()
+        {
+            return Err(Status::internal("Raft state machine shutdown!"));
+        }
+
+        let timeout_now_response = reply_rx.await;
+
+        match timeout_now_response {
+            Ok(rpc_messages::ReplyTo::TimeoutNow(timeout_now)) => {
+                Ok(Response::new(timeout_now.into()))
+            }
+            Err(_) => Err(Status::internal("Raft state machine shutdown!")),
+            _ => unreachable!("BUG ALERT: Unexpected response type, expected TimeoutNow!"),
+        }
+    }
+
+    async fn forward_proposals(
+        &self,
+        request: Request<ForwardProposalsRequest>,
+    ) -> Result<Response<ForwardProposalsResponse>, Status> {
+        let forward_proposals_req = request.into_inner();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self
+            .send_incoming_request_to_transport(
+                reply_tx,
+                rpc_messages::Request::ForwardProposals(forward_proposals_req.into()),
+            )
+            .is_err()
+        {
+            return Err(Status::internal("Raft state machine shutdown!"));
+        }
+
+        let forward_proposals_response = reply_rx.await;
+
+        match forward_proposals_response {
+            Ok(rpc_messages::ReplyTo::ForwardProposals(forward_proposals)) => {
+                Ok(Response::new(forward_proposals.into()))
+            }
+            Err(_) => Err(Status::internal("Raft state machine shutdown!")),
+            _ => unreachable!("BUG ALERT: Unexpected response type, expected ForwardProposals!"),
+        }
+    }
 }
@@ -4,7 +4,9 @@ use crate::proto::raft_consensus_client::RaftConsensusClient;
 pub use raft_consensus::rpc_messages;
 use raft_consensus::rpc_messages::RpcMessage;
 use raft_consensus::system_clock;
+use raft_consensus::RaftConfig;
 use raft_consensus::RaftTransportError;
+use raft_consensus::RpcRouter;
 use raft_consensus::ServerId;
 use tonic::transport::Channel;
 
@@ -12,8 +14,8 @@ use raft_consensus::RaftTransportConnector;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::thread;
+use std::time::Duration;
 use tracing::{info, trace};
-use uuid::Uuid;
 
 use tonic::{Request, Status};
 
@@ -34,18 +36,21 @@ pub struct RaftGrpcTransportConnector {
     raft_input_rx: mpsc::UnboundedReceiver<TransportMessage>,
     raft_output_tx: mpsc::UnboundedSender<rpc_messages::Request<u64>>,
     thread_handle: Option<thread::Thread>,
-    reply_channels: HashMap<Uuid, oneshot::Sender<rpc_messages::ReplyTo>>,
+    /// Where the node's reply to each request from a peer goes, dropped if the node doesn't reply
+    /// in time so the peer's call fails rather than hangs
+    reply_channels: RpcRouter<oneshot::Sender<rpc_messages::ReplyTo>>,
 }
 impl RaftGrpcTransportConnector {
     pub fn new(
         raft_input_rx: mpsc::UnboundedReceiver<TransportMessage>,
         raft_output_tx: mpsc::UnboundedSender<rpc_messages::Request<u64>>,
+        reply_timeout: Duration,
     ) -> RaftGrpcTransportConnector {
         RaftGrpcTransportConnector {
             raft_input_rx,
             raft_output_tx,
             thread_handle: None,
-            reply_channels: HashMap::new(),
+            reply_channels: RpcRouter::new(reply_timeout),
         }
     }
 }
@@ -71,9 +76,15 @@ impl RaftTransportConnector<u64> for RaftGrpcTransportConnector {
         let started_waiting_at = system_clock::now();
 
         loop {
+            for timed_out in self.reply_channels.expire() {
+                trace!(
+                    "Node didn't reply to request {} in time",
+                    timed_out.request_id
+                );
+            }
             match self.raft_input_rx.try_recv() {
                 Ok(TransportMessage::Request(reply_tx, message)) => {
-                    self.reply_channels.insert(message.request_id(), reply_tx);
+                    let _ = self.reply_channels.track(&message, reply_tx);
                     break Ok(Some(RpcMessage::Request(message)));
                 }
                 Ok(TransportMessage::Reply(reply)) => {
@@ -94,12 +105,14 @@ impl RaftTransportConnector<u64> for RaftGrpcTransportConnector {
     }
 
     fn enqueue_reply(&mut self, reply: rpc_messages::ReplyTo) -> Result<(), RaftTransportError> {
-        match self
-            .reply_channels
-            .remove(&reply.request_id())
-            .expect("GRPC BUG ALERT: No reply channel for this request!")
-            .send(reply)
-        {
+        let Some(reply_tx) = self.reply_channels.route(&reply) else {
+            trace!(
+                "Dropping reply to request {}, it timed out or was never received",
+                reply.request_id()
+            );
+            return Ok(());
+        };
+        match reply_tx.send(reply) {
             Ok(_) => Ok(()),
             Err(_) => Err(RaftTransportError::TransportShutdown),
         }
@@ -114,8 +127,15 @@ impl RaftTransportConnector<u64> for RaftGrpcTransportConnector {
             Err(_) => Err(RaftTransportError::TransportShutdown),
         }
     }
+
+    /// The 4MiB tonic decodes by default, the server is built without raising it
+    fn message_size_limit(&self) -> Option<usize> {
+        Some(4 * 1024 * 1024)
+    }
 }
 
+// tonic::Status is large but it only lives long enough to be logged
+#[allow(clippy::result_large_err)]
 async fn start_outgoing_message_sender(
     mut server_grpc_clients: HashMap<ServerId, RaftConsensusClient<Channel>>,
     raft_input_tx: mpsc::UnboundedSender<TransportMessage>,
@@ -155,6 +175,33 @@ async fn start_outgoing_message_sender(
                                 trace!("Failed to send request vote request: {:?}", e);
                             });
                     }
+                    rpc_messages::Request::PreVote(pre_vote_req) => {
+                        let pre_vote_req: proto::VoteRequest = pre_vote_req.into();
+                        let to = ServerId(pre_vote_req.to);
+
+                        let client = server_grpc_clients
+                            .get_mut(&to)
+                            .expect("GRPC BUG ALERT: No gRPC client for this server!");
+
+                        let _ = client
+                            .pre_vote(Request::new(pre_vote_req))
+                            .await
+                            .and_then(|response| {
+                                raft_input_tx
+                                    .send(TransportMessage::Reply(rpc_messages::ReplyTo::PreVote(
+                                        response.into_inner().into(),
+                                    )))
+                                    .map(|_| ())
+                                    .map_err(|e| match e {
+                                        mpsc::error::SendError(_) => Status::internal(
+                                            "Raft gRPC transport bridge disconnected!",
+                                        ),
+                                    })
+                            })
+                            .map_err(|e| {
+                                trace!("Failed to send pre-vote request: {:?}", e);
+                            });
+                    }
                     rpc_messages::Request::AppendEntries(append_entries_req) => {
                         let append_entries_req: proto::AppendEntriesRequest =
                             append_entries_req.into();
@@ -185,6 +232,103 @@ async fn start_outgoing_message_sender(
                                     trace!("Failed to send append entries request to {:?}: {:?}", to, e);
                                 });
                     }
+                    rpc_messages::Request::InstallSnapshot(install_snapshot_req) => {
+                        let install_snapshot_req: proto::InstallSnapshotRequest =
+                            install_snapshot_req.into();
+                        let to = ServerId(install_snapshot_req.to);
+
+                        let client = server_grpc_clients
+                            .get_mut(&to)
+                            .expect("GRPC BUG ALERT: No gRPC client for this server!");
+
+                        let _ = client
+                            .install_snapshot(Request::new(install_snapshot_req))
+                            .await
+                            .and_then(|response| {
+                                raft_input_tx
+                                    .send(TransportMessage::Reply(
+                                        rpc_messages::ReplyTo::InstallSnapshot(
+                                            response.into_inner().into(),
+                                        ),
+                                    ))
+                                    .map(|_| ())
+                                    .map_err(|e| match e {
+                                        mpsc::error::SendError(_) => Status::internal(
+                                            "Raft gRPC transport bridge disconnected!",
+                                        ),
+                                    })
+                            })
+                            .map_err(|e| {
+                                trace!(
+                                    "Failed to send install snapshot request to {:?}: {:?}",
+                                    to,
+                                    e
+                                );
+                            });
+                    }
+                    rpc_messages::Request::TimeoutNow(timeout_now_req) => {
+                        let timeout_now_req: proto::TimeoutNowRequest = timeout_now_req.into();
+                        let to = ServerId(timeout_now_req.to);
+
+                        let client = server_grpc_clients
+                            .get_mut(&to)
+                            .expect("GRPC BUG ALERT: No gRPC client for this server!");
+
+                        let _ = client
+                            .timeout_now(Request::new(timeout_now_req))
+                            .await
+                            .and_then(|response| {
+                                raft_input_tx
+                                    .send(TransportMessage::Reply(
+                                        rpc_messages::ReplyTo::TimeoutNow(
+                                            response.into_inner().into(),
+                                        ),
+                                    ))
+                                    .map(|_| ())
+                                    .map_err(|e| match e {
+                                        mpsc::error::SendError(_) => Status::internal(
+                                            "Raft gRPC transport bridge disconnected!",
+                                        ),
+                                    })
+                            })
+                            .map_err(|e| {
+                                trace!("Failed to send timeout now request to {:?}: {:?}", to, e);
+                            });
+                    }
+                    rpc_messages::Request::ForwardProposals(forward_proposals_req) => {
+                        let forward_proposals_req: proto::ForwardProposalsRequest =
+                            forward_proposals_req.into();
+                        let to = ServerId(forward_proposals_req.to);
+
+                        let client = server_grpc_clients
+                            .get_mut(&to)
+                            .expect("GRPC BUG ALERT: No gRPC client for this server!");
+
+                        let _ = client
+                            .forward_proposals(Request::new(forward_proposals_req))
+                            .await
+                            .and_then(|response| {
+                                raft_input_tx
+                                    .send(TransportMessage::Reply(
+                                        rpc_messages::ReplyTo::ForwardProposals(
+                                            response.into_inner().into(),
+                                        ),
+                                    ))
+                                    .map(|_| ())
+                                    .map_err(|e| match e {
+                                        mpsc::error::SendError(_) => Status::internal(
+                                            "Raft gRPC transport bridge disconnected!",
+                                        ),
+                                    })
+                            })
+                            .map_err(|e| {
+                                trace!(
+                                    "Failed to send forward proposals request to {:?}: {:?}",
+                                    to,
+                                    e
+                                );
+                            });
+                    }
                 }
             } else {
                 info!("Raft gRPC transport message sender exiting, raft state machine receiver disconnected/closed!");
@@ -203,6 +347,7 @@ impl RaftGrpcTransport {
     pub async fn start_grpc_transport(
         server_id: ServerId,
         server_addresses: HashMap<ServerId, SocketAddr>,
+        config: &RaftConfig,
     ) -> RaftGrpcTransport {
         let mut server_grpc_clients: HashMap<ServerId, RaftConsensusClient<Channel>> =
             HashMap::new();
@@ -210,6 +355,7 @@ impl RaftGrpcTransport {
             if other_server_id != server_id {
                 let channel = Channel::from_shared(format!("http://{}", server_address))
                     .expect("GRPC INIT: Failed to create channel")
+                    .timeout(config.rpc_timeout_for(other_server_id).0)
                     .connect_lazy();
                 server_grpc_clients
                     .insert(other_server_id, RaftConsensusClient::new(channel.clone()));
@@ -222,8 +368,11 @@ impl RaftGrpcTransport {
         let (raft_output_tx, raft_output_rx) =
             mpsc::unbounded_channel::<rpc_messages::Request<u64>>();
 
-        let transport_bridge =
-            RaftGrpcTransportConnector::new(raft_input_rx, raft_output_tx.clone());
+        let transport_bridge = RaftGrpcTransportConnector::new(
+            raft_input_rx,
+            raft_output_tx.clone(),
+            config.rpc_timeout.0,
+        );
         let grpc_server = RaftGrpcServerImpl::new(raft_input_tx.clone());
 
         // Outbound RPC messages from raft thread are sent here
@@ -0,0 +1,118 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, Registry, TextEncoder};
+use raft_consensus::{NodeDiagnostics, PrometheusCollector, RaftDiagnostics, RaftMetrics};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+/// Most committed entries a node may have left to apply and still take requests
+pub const MAX_READY_APPLY_LAG: u64 = 1_000;
+
+/// Serves plain HTTP endpoints for orchestrators, load balancers and scrapers:
+///
+/// - `GET /healthz` is 200 while the Raft thread is running and making progress
+/// - `GET /readyz` is 200 when the node is live, votes or learns in the latest configuration in
+///   its log, and has applied all but at most [`MAX_READY_APPLY_LAG`] of the entries it knows are
+///   committed, so it can serve requests
+/// - `GET /metrics` is the node's metrics in the Prometheus text format
+///
+/// The health checks return 503 otherwise.
+pub async fn serve_health_endpoints(
+    addr: SocketAddr,
+    diagnostics: RaftDiagnostics,
+    metrics: RaftMetrics,
+) -> Result<(), hyper::Error> {
+    let registry = Registry::new();
+    registry
+        .register(Box::new(PrometheusCollector::new(metrics)))
+        .expect("HEALTH: A fresh registry should take the node's metrics");
+    let registry = Arc::new(registry);
+    let make_service = make_service_fn(move |_| {
+        let diagnostics = diagnostics.clone();
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = health_response(&request, &diagnostics, &registry);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    info!("Serving health endpoints on {:?}", addr);
+    Server::bind(&addr).serve(make_service).await
+}
+
+fn health_response(
+    request: &Request<Body>,
+    diagnostics: &RaftDiagnostics,
+    registry: &Registry,
+) -> Response<Body> {
+    let latest = diagnostics.latest();
+    let is_live = latest
+        .as_ref()
+        .map(|diagnostics| !diagnostics.is_stale())
+        .unwrap_or(false);
+
+    let (status, body) = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") => {
+            if is_live {
+                (StatusCode::OK, "live".to_string())
+            } else {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "raft thread not running".to_string(),
+                )
+            }
+        }
+        (&Method::GET, "/readyz") => match latest {
+            _ if !is_live => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "raft thread not running".to_string(),
+            ),
+            Some(diagnostics) if !diagnostics.in_configuration => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "not in the cluster's configuration".to_string(),
+            ),
+            Some(diagnostics) if apply_lag(&diagnostics) > MAX_READY_APPLY_LAG => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "{} committed entries left to apply",
+                    apply_lag(&diagnostics)
+                ),
+            ),
+            _ => (StatusCode::OK, "ready".to_string()),
+        },
+        (&Method::GET, "/metrics") => {
+            let encoder = TextEncoder::new();
+            let mut text = Vec::new();
+            return match encoder.encode(&registry.gather(), &mut text) {
+                Ok(()) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+                    .body(Body::from(text))
+                    .expect("HEALTH: Metrics response should be valid"),
+                Err(error) => Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(error.to_string()))
+                    .expect("HEALTH: Error response should be valid"),
+            };
+        }
+        _ => (StatusCode::NOT_FOUND, "not found".to_string()),
+    };
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .expect("HEALTH: Static response should be valid")
+}
+
+/// Entries the node knows are committed but hasn't applied yet
+fn apply_lag(diagnostics: &NodeDiagnostics) -> u64 {
+    diagnostics
+        .state
+        .commit_index
+        .0
+        .saturating_sub(diagnostics.state.last_applied.0)
+}
@@ -1,3 +1,7 @@
+pub mod grpc_admin;
 pub(crate) mod grpc_server;
 pub mod grpc_transport;
+#[cfg(feature = "health_http")]
+pub mod health_http;
 pub mod proto;
+pub mod wire_codec;
@@ -1,10 +1,28 @@
 use raft_consensus::rpc_messages;
-use raft_consensus::{LogIndex, ServerId, TermIndex};
+use raft_consensus::{
+    BincodeCodec, ClientId, ClientProposal, ClientSession, CommandCodec, LogEntryCommand, LogIndex,
+    MembershipChange, NodeDiagnostics, RaftNodeState, ServerId, Snapshot, TermIndex,
+};
 use tonic;
 use uuid::Uuid;
 
 tonic::include_proto!("raft"); // The string specified here must match the proto package name
 
+/// How commands go over the wire, every node of the cluster has to use the same codec
+const COMMAND_CODEC: BincodeCodec = BincodeCodec;
+
+fn encode_command(command: &u64) -> Vec<u8> {
+    COMMAND_CODEC
+        .encode(command)
+        .expect("GRPC CONVERT: Could not encode application command!")
+}
+
+fn decode_command(serialized: &[u8]) -> u64 {
+    COMMAND_CODEC
+        .decode(serialized)
+        .expect("GRPC CONVERT: Invalid application command!")
+}
+
 // These convert the protobuf representation of the messages into the form needed for the Raft consensus module.
 // The module does not make any assumptions about the transport layer, so it uses it's own types to represent the messages received from the network.
 
@@ -18,6 +36,7 @@ impl From<VoteRequest> for rpc_messages::RequestVote {
             term: TermIndex(vote_request.term),
             last_log_index: LogIndex(vote_request.last_log_index),
             last_log_term: TermIndex(vote_request.last_log_term),
+            leadership_transfer: vote_request.leadership_transfer,
         }
     }
 }
@@ -52,12 +71,39 @@ impl From<AppendEntriesRequest> for rpc_messages::AppendEntries<u64> {
                         .map(|c| match c {
                             log_entry::Command::ApplicationCommand(ApplicationCommand {
                                 serialized,
-                            }) => u64::from_be_bytes(
-                                serialized
-                                    .try_into()
-                                    .expect("GRPC CONVERT: Invalid application command!"),
+                            }) => LogEntryCommand::Application(decode_command(&serialized)),
+                            log_entry::Command::ClusterMembershipChange(change) => {
+                                let server_id = ServerId(change.node_id);
+                                LogEntryCommand::MembershipChange(match change.change_type() {
+                                    cluster_membership_change::ChangeType::Add => {
+                                        MembershipChange::AddServer(server_id)
+                                    }
+                                    cluster_membership_change::ChangeType::Remove => {
+                                        MembershipChange::RemoveServer(server_id)
+                                    }
+                                    cluster_membership_change::ChangeType::AddLearner => {
+                                        MembershipChange::AddLearner(server_id)
+                                    }
+                                    cluster_membership_change::ChangeType::PromoteLearner => {
+                                        MembershipChange::PromoteLearner(server_id)
+                                    }
+                                })
+                            }
+                            log_entry::Command::NoOp(_) => LogEntryCommand::NoOp,
+                            log_entry::Command::SessionCommand(SessionCommand {
+                                client_id,
+                                sequence,
+                                serialized,
+                            }) => LogEntryCommand::SessionCommand(
+                                ClientSession {
+                                    client_id: ClientId(
+                                        Uuid::parse_str(&client_id)
+                                            .expect("GRPC CONVERT: Invalid client id!"),
+                                    ),
+                                    sequence,
+                                },
+                                decode_command(&serialized),
                             ),
-                            _ => panic!("Unexpected command!"),
                         })
                         .expect("GRPC CONVERT: No command"),
                 })
@@ -77,6 +123,141 @@ impl From<AppendEntriesResponse> for rpc_messages::AppendEntriesAck {
             to: ServerId(append_entries_response.to),
             term: TermIndex(append_entries_response.term),
             success: append_entries_response.added_entries_successfully,
+            conflict: append_entries_response
+                .conflict
+                .map(|conflict| rpc_messages::LogConflict {
+                    term: (conflict.term != 0).then_some(TermIndex(conflict.term)),
+                    first_index: LogIndex(conflict.first_index),
+                }),
+        }
+    }
+}
+impl From<InstallSnapshotRequest> for rpc_messages::InstallSnapshot {
+    fn from(install_snapshot_request: InstallSnapshotRequest) -> Self {
+        rpc_messages::InstallSnapshot {
+            request_id: Uuid::parse_str(&install_snapshot_request.request_id)
+                .expect("GRPC CONVERT: Invalid UUID!"),
+            from: ServerId(install_snapshot_request.from),
+            to: ServerId(install_snapshot_request.to),
+            term: TermIndex(install_snapshot_request.term),
+            snapshot: Snapshot {
+                last_included_index: LogIndex(install_snapshot_request.last_included_index),
+                last_included_term: TermIndex(install_snapshot_request.last_included_term),
+                members: install_snapshot_request
+                    .members
+                    .into_iter()
+                    .map(ServerId)
+                    .collect(),
+                learners: install_snapshot_request
+                    .learners
+                    .into_iter()
+                    .map(ServerId)
+                    .collect(),
+                sessions: install_snapshot_request
+                    .sessions
+                    .into_iter()
+                    .map(|(client_id, sequence)| {
+                        (
+                            ClientId(
+                                Uuid::parse_str(&client_id)
+                                    .expect("GRPC CONVERT: Invalid client id!"),
+                            ),
+                            sequence,
+                        )
+                    })
+                    .collect(),
+                data: install_snapshot_request.data,
+            },
+            offset: install_snapshot_request.offset,
+            done: install_snapshot_request.done,
+        }
+    }
+}
+impl From<InstallSnapshotResponse> for rpc_messages::InstallSnapshotAck {
+    fn from(install_snapshot_response: InstallSnapshotResponse) -> Self {
+        rpc_messages::InstallSnapshotAck {
+            request_id: Uuid::parse_str(&install_snapshot_response.request_id)
+                .expect("GRPC CONVERT: Invalid UUID!"),
+            from: ServerId(install_snapshot_response.from),
+            to: ServerId(install_snapshot_response.to),
+            term: TermIndex(install_snapshot_response.term),
+            next_offset: install_snapshot_response.next_offset,
+        }
+    }
+}
+impl From<TimeoutNowRequest> for rpc_messages::TimeoutNow {
+    fn from(timeout_now_request: TimeoutNowRequest) -> Self {
+        rpc_messages::TimeoutNow {
+            request_id: Uuid::parse_str(&timeout_now_request.request_id)
+                .expect("GRPC CONVERT: Invalid UUID!"),
+            from: ServerId(timeout_now_request.from),
+            to: ServerId(timeout_now_request.to),
+            term: TermIndex(timeout_now_request.term),
+        }
+    }
+}
+impl From<TimeoutNowResponse> for rpc_messages::TimeoutNowAck {
+    fn from(timeout_now_response: TimeoutNowResponse) -> Self {
+        rpc_messages::TimeoutNowAck {
+            request_id: Uuid::parse_str(&timeout_now_response.request_id)
+                .expect("GRPC CONVERT: Invalid UUID!"),
+            from: ServerId(timeout_now_response.from),
+            to: ServerId(timeout_now_response.to),
+            term: TermIndex(timeout_now_response.term),
+        }
+    }
+}
+impl From<ForwardProposalsRequest> for rpc_messages::ForwardProposals<u64> {
+    fn from(forward_proposals_request: ForwardProposalsRequest) -> Self {
+        rpc_messages::ForwardProposals {
+            request_id: Uuid::parse_str(&forward_proposals_request.request_id)
+                .expect("GRPC CONVERT: Invalid UUID!"),
+            from: ServerId(forward_proposals_request.from),
+            to: ServerId(forward_proposals_request.to),
+            term: TermIndex(forward_proposals_request.term),
+            proposals: forward_proposals_request
+                .proposals
+                .into_iter()
+                .map(|proposal| ClientProposal {
+                    command: decode_command(&proposal.serialized),
+                    session: (!proposal.client_id.is_empty()).then(|| ClientSession {
+                        client_id: ClientId(
+                            Uuid::parse_str(&proposal.client_id)
+                                .expect("GRPC CONVERT: Invalid client id!"),
+                        ),
+                        sequence: proposal.sequence,
+                    }),
+                })
+                .collect(),
+            hops: forward_proposals_request.hops,
+        }
+    }
+}
+impl From<ForwardProposalsResponse> for rpc_messages::ForwardProposalsAck {
+    fn from(forward_proposals_response: ForwardProposalsResponse) -> Self {
+        rpc_messages::ForwardProposalsAck {
+            request_id: Uuid::parse_str(&forward_proposals_response.request_id)
+                .expect("GRPC CONVERT: Invalid UUID!"),
+            from: ServerId(forward_proposals_response.from),
+            to: ServerId(forward_proposals_response.to),
+            term: TermIndex(forward_proposals_response.term),
+            outcome: match forward_proposals_response
+                .outcome
+                .expect("GRPC CONVERT: No outcome")
+            {
+                forward_proposals_response::Outcome::Appended(appended) => {
+                    rpc_messages::ForwardedProposalsOutcome::Appended {
+                        first_index: LogIndex(appended.first_index),
+                        term: TermIndex(appended.term),
+                    }
+                }
+                forward_proposals_response::Outcome::NotLeader(_) => {
+                    rpc_messages::ForwardedProposalsOutcome::NotLeader
+                }
+                forward_proposals_response::Outcome::Busy(_) => {
+                    rpc_messages::ForwardedProposalsOutcome::Busy
+                }
+            },
         }
     }
 }
@@ -90,6 +271,7 @@ impl From<rpc_messages::RequestVote> for VoteRequest {
             term: vote_request.term.0,
             last_log_index: vote_request.last_log_index.0,
             last_log_term: vote_request.last_log_term.0,
+            leadership_transfer: vote_request.leadership_transfer,
         }
     }
 }
@@ -115,13 +297,45 @@ impl From<rpc_messages::AppendEntries<u64>> for AppendEntriesRequest {
             term: append_entries_request.term.0,
             entries: append_entries_request
                 .entries
-                .into_iter()
+                .iter()
                 .map(|entry| LogEntry {
                     term: entry.term.0,
                     log_index: entry.index.0,
-                    command: Some(log_entry::Command::ApplicationCommand(ApplicationCommand {
-                        serialized: entry.command.to_be_bytes().to_vec(),
-                    })),
+                    command: Some(match &entry.command {
+                        LogEntryCommand::Application(command) => {
+                            log_entry::Command::ApplicationCommand(ApplicationCommand {
+                                serialized: encode_command(command),
+                            })
+                        }
+                        LogEntryCommand::MembershipChange(change) => {
+                            let change_type = match change {
+                                MembershipChange::AddServer(_) => {
+                                    cluster_membership_change::ChangeType::Add
+                                }
+                                MembershipChange::RemoveServer(_) => {
+                                    cluster_membership_change::ChangeType::Remove
+                                }
+                                MembershipChange::AddLearner(_) => {
+                                    cluster_membership_change::ChangeType::AddLearner
+                                }
+                                MembershipChange::PromoteLearner(_) => {
+                                    cluster_membership_change::ChangeType::PromoteLearner
+                                }
+                            };
+                            log_entry::Command::ClusterMembershipChange(ClusterMembershipChange {
+                                node_id: change.server_id().0,
+                                change_type: change_type as i32,
+                            })
+                        }
+                        LogEntryCommand::NoOp => log_entry::Command::NoOp(NoOp {}),
+                        LogEntryCommand::SessionCommand(session, command) => {
+                            log_entry::Command::SessionCommand(SessionCommand {
+                                client_id: session.client_id.0.to_string(),
+                                sequence: session.sequence,
+                                serialized: encode_command(command),
+                            })
+                        }
+                    }),
                 })
                 .collect(),
             prev_log_index: append_entries_request.prev_log_index.0,
@@ -139,6 +353,196 @@ impl From<rpc_messages::AppendEntriesAck> for AppendEntriesResponse {
             to: append_entries_response.to.0,
             term: append_entries_response.term.0,
             added_entries_successfully: append_entries_response.success,
+            conflict: append_entries_response
+                .conflict
+                .map(|conflict| LogConflict {
+                    term: conflict.term.map(|term| term.0).unwrap_or(0),
+                    first_index: conflict.first_index.0,
+                }),
+        }
+    }
+}
+
+impl From<rpc_messages::InstallSnapshot> for InstallSnapshotRequest {
+    fn from(install_snapshot_request: rpc_messages::InstallSnapshot) -> Self {
+        InstallSnapshotRequest {
+            request_id: install_snapshot_request.request_id.to_string(),
+            from: install_snapshot_request.from.0,
+            to: install_snapshot_request.to.0,
+            term: install_snapshot_request.term.0,
+            last_included_index: install_snapshot_request.snapshot.last_included_index.0,
+            last_included_term: install_snapshot_request.snapshot.last_included_term.0,
+            members: install_snapshot_request
+                .snapshot
+                .members
+                .iter()
+                .map(|member| member.0)
+                .collect(),
+            learners: install_snapshot_request
+                .snapshot
+                .learners
+                .iter()
+                .map(|learner| learner.0)
+                .collect(),
+            sessions: install_snapshot_request
+                .snapshot
+                .sessions
+                .iter()
+                .map(|(client_id, sequence)| (client_id.0.to_string(), *sequence))
+                .collect(),
+            data: install_snapshot_request.snapshot.data,
+            offset: install_snapshot_request.offset,
+            done: install_snapshot_request.done,
+        }
+    }
+}
+
+impl From<rpc_messages::InstallSnapshotAck> for InstallSnapshotResponse {
+    fn from(install_snapshot_response: rpc_messages::InstallSnapshotAck) -> Self {
+        InstallSnapshotResponse {
+            request_id: install_snapshot_response.request_id.to_string(),
+            from: install_snapshot_response.from.0,
+            to: install_snapshot_response.to.0,
+            term: install_snapshot_response.term.0,
+            next_offset: install_snapshot_response.next_offset,
+        }
+    }
+}
+
+impl From<rpc_messages::TimeoutNow> for TimeoutNowRequest {
+    fn from(timeout_now_request: rpc_messages::TimeoutNow) -> Self {
+        TimeoutNowRequest {
+            request_id: timeout_now_request.request_id.to_string(),
+            from: timeout_now_request.from.0,
+            to: timeout_now_request.to.0,
+            term: timeout_now_request.term.0,
+        }
+    }
+}
+
+impl From<rpc_messages::TimeoutNowAck> for TimeoutNowResponse {
+    fn from(timeout_now_response: rpc_messages::TimeoutNowAck) -> Self {
+        TimeoutNowResponse {
+            request_id: timeout_now_response.request_id.to_string(),
+            from: timeout_now_response.from.0,
+            to: timeout_now_response.to.0,
+            term: timeout_now_response.term.0,
+        }
+    }
+}
+
+impl From<rpc_messages::ForwardProposals<u64>> for ForwardProposalsRequest {
+    fn from(forward_proposals_request: rpc_messages::ForwardProposals<u64>) -> Self {
+        ForwardProposalsRequest {
+            request_id: forward_proposals_request.request_id.to_string(),
+            from: forward_proposals_request.from.0,
+            to: forward_proposals_request.to.0,
+            term: forward_proposals_request.term.0,
+            proposals: forward_proposals_request
+                .proposals
+                .into_iter()
+                .map(|proposal| ForwardedProposal {
+                    serialized: encode_command(&proposal.command),
+                    client_id: proposal
+                        .session
+                        .map(|session| session.client_id.0.to_string())
+                        .unwrap_or_default(),
+                    sequence: proposal
+                        .session
+                        .map(|session| session.sequence)
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            hops: forward_proposals_request.hops,
+        }
+    }
+}
+
+impl From<rpc_messages::ForwardProposalsAck> for ForwardProposalsResponse {
+    fn from(forward_proposals_response: rpc_messages::ForwardProposalsAck) -> Self {
+        ForwardProposalsResponse {
+            request_id: forward_proposals_response.request_id.to_string(),
+            from: forward_proposals_response.from.0,
+            to: forward_proposals_response.to.0,
+            term: forward_proposals_response.term.0,
+            outcome: Some(match forward_proposals_response.outcome {
+                rpc_messages::ForwardedProposalsOutcome::Appended { first_index, term } => {
+                    forward_proposals_response::Outcome::Appended(ProposalsAppended {
+                        first_index: first_index.0,
+                        term: term.0,
+                    })
+                }
+                rpc_messages::ForwardedProposalsOutcome::NotLeader => {
+                    forward_proposals_response::Outcome::NotLeader(NotLeader {})
+                }
+                rpc_messages::ForwardedProposalsOutcome::Busy => {
+                    forward_proposals_response::Outcome::Busy(Busy {})
+                }
+            }),
+        }
+    }
+}
+
+impl From<NodeDiagnostics> for InspectResponse {
+    fn from(diagnostics: NodeDiagnostics) -> Self {
+        let state = diagnostics.state;
+        let uptime = diagnostics.status().uptime;
+        let mut peers: Vec<u64> = diagnostics.peers.iter().map(|peer| peer.0).collect();
+        peers.sort_unstable();
+        let mut replication_progress: Vec<PeerProgress> = diagnostics
+            .replication_progress
+            .into_iter()
+            .map(|(server_id, progress)| PeerProgress {
+                server_id: server_id.0,
+                next_index: progress.next_index.0,
+                match_index: progress.match_index.0,
+                append_entries_batch_limit: progress.append_entries_batch_limit as u64,
+                since_last_reply: progress.last_reply.map(|last_reply| ElapsedValue {
+                    ms: last_reply.elapsed().as_millis() as u64,
+                }),
+                in_flight: progress.in_flight as u64,
+            })
+            .collect();
+        replication_progress.sort_unstable_by_key(|progress| progress.server_id);
+
+        InspectResponse {
+            server_id: state.server_id.0,
+            role: match state.current_state {
+                RaftNodeState::Follower => "follower",
+                RaftNodeState::PreCandidate => "pre_candidate",
+                RaftNodeState::Candidate => "candidate",
+                RaftNodeState::Leader => "leader",
+            }
+            .to_string(),
+            term: state.current_term.0,
+            voted_for: state.voted_for.map(|server_id| ServerIdValue {
+                server_id: server_id.0,
+            }),
+            leader_id: state.leader_for_term.map(|server_id| ServerIdValue {
+                server_id: server_id.0,
+            }),
+            commit_index: state.commit_index.0,
+            last_applied: state.last_applied.0,
+            last_log_index: state.last_log_index.0,
+            last_log_term: state.last_log_term.0,
+            peers,
+            replication_progress,
+            leader_heartbeat_interval_ms: diagnostics.config.leader_heartbeat_interval.0.as_millis()
+                as u64,
+            min_election_timeout_ms: diagnostics.config.election_timeout.min.as_millis() as u64,
+            max_election_timeout_ms: diagnostics.config.election_timeout.max.as_millis() as u64,
+            pending_proposals: diagnostics.pending_proposals as u64,
+            rpc_timeout_ms: diagnostics.config.rpc_timeout.0.as_millis() as u64,
+            uptime_ms: uptime.as_millis() as u64,
+            elections: Some(ElectionStats {
+                elections_started: diagnostics.elections.elections_started,
+                split_votes: diagnostics.elections.split_votes,
+                pre_vote_rejections: diagnostics.elections.pre_vote_rejections,
+                votes_granted: diagnostics.elections.votes_granted,
+                election_duration_count: diagnostics.elections.election_duration.count,
+                election_duration_sum_ms: diagnostics.elections.election_duration.sum.as_millis()
+                    as u64,
+            }),
         }
     }
 }
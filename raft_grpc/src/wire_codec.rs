@@ -0,0 +1,89 @@
+use crate::proto::rpc_envelope::Message as Envelope;
+use crate::proto::RpcEnvelope;
+use prost::Message as _;
+use raft_consensus::rpc_messages::{ReplyTo, Request, RpcMessage};
+use raft_consensus::{CodecError, WireCodec};
+
+/// Messages as the `RpcEnvelope` protobuf, so transports other than gRPC can talk to tooling that
+/// speaks the same `.proto`. Commands are encoded like the gRPC transport encodes them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtobufWireCodec;
+impl WireCodec<u64> for ProtobufWireCodec {
+    fn encode(&self, message: &RpcMessage<u64>) -> Result<Vec<u8>, CodecError> {
+        let message = match message.clone() {
+            RpcMessage::Request(Request::RequestVote(request_vote)) => {
+                Envelope::RequestVote(request_vote.into())
+            }
+            RpcMessage::Request(Request::PreVote(pre_vote)) => Envelope::PreVote(pre_vote.into()),
+            RpcMessage::Request(Request::AppendEntries(append_entries)) => {
+                Envelope::AppendEntries(append_entries.into())
+            }
+            RpcMessage::Request(Request::InstallSnapshot(install_snapshot)) => {
+                Envelope::InstallSnapshot(install_snapshot.into())
+            }
+            RpcMessage::Request(Request::TimeoutNow(timeout_now)) => {
+                Envelope::TimeoutNow(timeout_now.into())
+            }
+            RpcMessage::Request(Request::ForwardProposals(forward_proposals)) => {
+                Envelope::ForwardProposals(forward_proposals.into())
+            }
+            RpcMessage::Reply(ReplyTo::RequestVote(vote)) => {
+                Envelope::RequestVoteReply(vote.into())
+            }
+            RpcMessage::Reply(ReplyTo::PreVote(vote)) => Envelope::PreVoteReply(vote.into()),
+            RpcMessage::Reply(ReplyTo::AppendEntries(ack)) => {
+                Envelope::AppendEntriesReply(ack.into())
+            }
+            RpcMessage::Reply(ReplyTo::InstallSnapshot(ack)) => {
+                Envelope::InstallSnapshotReply(ack.into())
+            }
+            RpcMessage::Reply(ReplyTo::TimeoutNow(ack)) => Envelope::TimeoutNowReply(ack.into()),
+            RpcMessage::Reply(ReplyTo::ForwardProposals(ack)) => {
+                Envelope::ForwardProposalsReply(ack.into())
+            }
+        };
+        Ok(RpcEnvelope {
+            message: Some(message),
+        }
+        .encode_to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<RpcMessage<u64>, CodecError> {
+        let envelope = RpcEnvelope::decode(bytes).map_err(|error| CodecError(error.to_string()))?;
+        let message = envelope
+            .message
+            .ok_or_else(|| CodecError("envelope holds no message".to_string()))?;
+        Ok(match message {
+            Envelope::RequestVote(request_vote) => RpcMessage::request_vote(request_vote.into()),
+            Envelope::PreVote(pre_vote) => RpcMessage::pre_vote(pre_vote.into()),
+            Envelope::AppendEntries(append_entries) => {
+                RpcMessage::append_entries(append_entries.into())
+            }
+            Envelope::InstallSnapshot(install_snapshot) => {
+                RpcMessage::install_snapshot(install_snapshot.into())
+            }
+            Envelope::TimeoutNow(timeout_now) => RpcMessage::timeout_now(timeout_now.into()),
+            Envelope::ForwardProposals(forward_proposals) => {
+                RpcMessage::forward_proposals(forward_proposals.into())
+            }
+            Envelope::RequestVoteReply(vote) => {
+                RpcMessage::Reply(ReplyTo::RequestVote(vote.into()))
+            }
+            Envelope::PreVoteReply(vote) => RpcMessage::Reply(ReplyTo::PreVote(vote.into())),
+            Envelope::AppendEntriesReply(ack) => {
+                RpcMessage::Reply(ReplyTo::AppendEntries(ack.into()))
+            }
+            Envelope::InstallSnapshotReply(ack) => {
+                RpcMessage::Reply(ReplyTo::InstallSnapshot(ack.into()))
+            }
+            Envelope::TimeoutNowReply(ack) => RpcMessage::Reply(ReplyTo::TimeoutNow(ack.into())),
+            Envelope::ForwardProposalsReply(ack) => {
+                RpcMessage::Reply(ReplyTo::ForwardProposals(ack.into()))
+            }
+        })
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/x-protobuf"
+    }
+}
@@ -0,0 +1,130 @@
+/// Tests the HTTP health and metrics endpoints a node serves
+use raft_consensus::{
+    ApplicationThatNeedsConsensus, LogIndex, RaftConfig, RaftDiagnostics, RaftMetrics,
+    RaftNodeBuilder, ServerId, Snapshot, TcpTransport,
+};
+use raft_grpc::health_http::serve_health_endpoints;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Application that only keeps track of how far it got
+struct TrackingApplication {
+    last_applied_index: LogIndex,
+}
+impl ApplicationThatNeedsConsensus for TrackingApplication {
+    type Command = u64;
+    type Output = ();
+    type Error = ();
+
+    fn apply(&mut self, log_index: LogIndex, _command: u64) -> Result<(), ()> {
+        self.last_applied_index = log_index;
+        Ok(())
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied_index
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.last_applied_index = snapshot.last_included_index;
+    }
+}
+
+/// Serves the endpoints on a port of their own, returning where
+fn serve(diagnostics: RaftDiagnostics, metrics: RaftMetrics) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _server = tokio::spawn(serve_health_endpoints(addr, diagnostics, metrics));
+    addr
+}
+
+/// GETs `path` from `addr`, returning the response's status code and body
+fn get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(error) => {
+                assert!(Instant::now() < deadline, "never served: {}", error);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    )
+    .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).unwrap();
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .unwrap_or(0);
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+    (status, body)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn should_be_neither_live_nor_ready_before_the_node_runs() {
+    let addr = serve(RaftDiagnostics::default(), RaftMetrics::new());
+
+    tokio::task::spawn_blocking(move || {
+        assert_eq!(get(addr, "/healthz").0, 503);
+        assert_eq!(get(addr, "/readyz").0, 503);
+        assert_eq!(get(addr, "/health/live").0, 404);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn should_be_live_and_ready_once_a_member_applied_what_it_committed() {
+    let storage_dir = TempDir::new().unwrap();
+    let node = RaftNodeBuilder::new(
+        ServerId(1),
+        TrackingApplication {
+            last_applied_index: LogIndex(0),
+        },
+    )
+    .storage_path(storage_dir.path().to_str().unwrap())
+    .transport(
+        TcpTransport::<u64>::new(TcpListener::bind("127.0.0.1:0").unwrap(), HashMap::new())
+            .unwrap(),
+    )
+    .config(RaftConfig::builder().build().unwrap())
+    .start()
+    .unwrap();
+    let addr = serve(node.diagnostics(), node.metrics());
+
+    tokio::task::spawn_blocking(move || {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while get(addr, "/readyz") != (200, "ready".to_string()) {
+            assert!(Instant::now() < deadline, "node never got ready");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(get(addr, "/healthz"), (200, "live".to_string()));
+        let (status, metrics) = get(addr, "/metrics");
+        assert_eq!(status, 200);
+        assert!(metrics.contains("raft_current_term"));
+        assert!(metrics.contains("raft_elections_started_total"));
+
+        assert_eq!(node.shutdown(Duration::from_secs(5)), Ok(()));
+    })
+    .await
+    .unwrap();
+}
@@ -1,3 +1,6 @@
+use std::convert::Infallible;
+
+use raft_consensus::{ApplicationThatNeedsConsensus, LogIndex, Snapshot};
 use single_value_store_proto::single_value_store;
 use single_value_store_proto::single_value_store::single_value_store_server::SingleValueStore;
 use tracing::info;
@@ -24,3 +27,50 @@ impl SingleValueStore for SingleValueStoreImpl {
         Ok(tonic::Response::new(single_value_store::SetResponse {}))
     }
 }
+
+/// The replicated value, each committed command overwrites it.
+/// Kept in memory only, so it is restored from the snapshot and the rest of the log on restart.
+pub(crate) struct SingleValue {
+    value: u64,
+    last_applied: LogIndex,
+}
+
+impl Default for SingleValue {
+    fn default() -> Self {
+        Self {
+            value: 0,
+            last_applied: LogIndex(0),
+        }
+    }
+}
+
+impl ApplicationThatNeedsConsensus for SingleValue {
+    type Command = u64;
+    type Output = u64;
+    type Error = Infallible;
+
+    fn apply(&mut self, log_index: LogIndex, command: u64) -> Result<u64, Infallible> {
+        self.value = command;
+        self.last_applied = log_index;
+        Ok(self.value)
+    }
+
+    fn last_applied_index(&self) -> LogIndex {
+        self.last_applied
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.value.to_be_bytes().to_vec()
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.value = u64::from_be_bytes(
+            snapshot
+                .data
+                .as_slice()
+                .try_into()
+                .expect("SNAPSHOT: Invalid single value snapshot!"),
+        );
+        self.last_applied = snapshot.last_included_index;
+    }
+}
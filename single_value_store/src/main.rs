@@ -2,16 +2,19 @@ mod app;
 
 use std::{collections::HashMap, net::SocketAddr, time::Duration};
 
-use crate::app::SingleValueStoreImpl;
-use raft_consensus::{start_raft_in_new_thread, NoOpRaftEventCollector, RaftConfig, ServerId};
+use crate::app::{SingleValue, SingleValueStoreImpl};
+use raft_consensus::{
+    ElectionTimeoutRange, HeartbeatInterval, RaftConfig, RaftNodeBuilder, ServerId,
+};
+use raft_grpc::grpc_admin::RaftAdminServerImpl;
 use raft_grpc::grpc_transport::RaftGrpcTransport;
+use raft_grpc::health_http::serve_health_endpoints;
+use raft_grpc::proto::raft_admin_server::RaftAdminServer;
 use raft_grpc::proto::raft_consensus_server::RaftConsensusServer;
 use single_value_store_proto::single_value_store::single_value_store_server::SingleValueStoreServer;
 use tokio::select;
 use tonic::transport::Server;
-
-use rand::SeedableRng;
-use rand_chacha::ChaCha8Rng;
+use tracing::error;
 
 use clap::Parser;
 
@@ -41,6 +44,16 @@ struct Args {
     /// Leader heartbeat interval in milliseconds
     #[arg(short, long)]
     leader_heartbeat_ms: u64,
+
+    /// Port to serve the /healthz, /readyz and /metrics HTTP endpoints on, disabled if not set
+    #[arg(long)]
+    health_port: Option<u16>,
+
+    /// Comma delimited IDs of the cluster members that are witnesses, the same on every server
+    /// Ex:
+    /// 3
+    #[arg(long, value_delimiter = ',')]
+    witnesses: Vec<u64>,
 }
 
 fn parse_cluster_members(cluster_members: &str) -> HashMap<ServerId, SocketAddr> {
@@ -83,27 +96,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map(|(id, _)| *id)
         .collect();
 
+    let mut config_builder = RaftConfig::builder()
+        .leader_heartbeat_interval(HeartbeatInterval(Duration::from_millis(
+            args.leader_heartbeat_ms,
+        )))
+        .election_timeout(ElectionTimeoutRange::from_millis(150, 300));
+    for witness in &args.witnesses {
+        config_builder = config_builder.witness(ServerId(*witness));
+    }
+    let config = config_builder.build()?;
     let mut raft_grpc_transport =
-        RaftGrpcTransport::start_grpc_transport(server_id.clone(), server_id_to_addr).await;
-    let config = RaftConfig {
-        leader_heartbeat_interval: Duration::from_millis(args.leader_heartbeat_ms),
-        min_election_timeout_ms: 150,
-        max_election_timeout_ms: 300,
-    };
-    let rng = ChaCha8Rng::from_entropy();
-    let event_collector = NoOpRaftEventCollector {};
-    let raft_thread = start_raft_in_new_thread(
-        server_id.clone(),
-        other_servers,
-        args.wal_log_dir,
-        config,
-        rng,
-        raft_grpc_transport.transport_bridge,
-        event_collector,
-    );
+        RaftGrpcTransport::start_grpc_transport(server_id, server_id_to_addr, &config).await;
+    let raft_node = RaftNodeBuilder::new(server_id, SingleValue::default())
+        .peers(other_servers)
+        .storage_path(args.wal_log_dir)
+        .transport(raft_grpc_transport.transport_bridge)
+        .config(config)
+        .start()?;
     raft_grpc_transport
         .grpc_server
-        .register_raft_thread(raft_thread);
+        .register_raft_thread(raft_node.thread().clone());
+    let admin = RaftAdminServerImpl::new(raft_node.diagnostics());
+
+    if let Some(health_port) = args.health_port {
+        let health_addr = SocketAddr::new(addr.ip(), health_port);
+        let diagnostics = raft_node.diagnostics();
+        let metrics = raft_node.metrics();
+        tokio::spawn(async move {
+            if let Err(e) = serve_health_endpoints(health_addr, diagnostics, metrics).await {
+                error!("Health endpoint server failed: {:?}", e);
+            }
+        });
+    }
 
     let app = SingleValueStoreImpl {};
 
@@ -111,6 +135,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ = raft_grpc_transport.message_sender_task => {},
         _ = Server::builder()
             .add_service(RaftConsensusServer::new(raft_grpc_transport.grpc_server))
+            .add_service(RaftAdminServer::new(admin))
             .add_service(SingleValueStoreServer::new(app))
             .serve(addr) => {},
     }
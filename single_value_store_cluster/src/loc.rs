@@ -14,6 +14,7 @@ lazy_static! {
     static ref ROOT: Loc = Loc::find_root();
 }
 
+#[allow(dead_code)]
 impl Loc {
     pub fn root() -> Self {
         ROOT.to_owned()
@@ -36,6 +37,7 @@ impl Loc {
     }
 }
 
+#[allow(dead_code)]
 impl Loc {
     // Using `Cargo.lock` as a marker of a root directory of the project
     // If user runs a cli from subdirectory, we traverse up the directory tree
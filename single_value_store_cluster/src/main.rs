@@ -12,7 +12,7 @@ use steward::{Env, Process, ProcessPool};
 async fn main() -> steward::Result<()> {
     let num_raft_nodes = 5;
 
-    let server_tags = vec![
+    let server_tags = [
         "server-node(0)",
         "server-node(1)",
         "server-node(2)",
@@ -40,7 +40,7 @@ fn cluster_members_arg(num_servers: u16, starting_port: u16) -> String {
     let mut cluster_members = String::new();
     for i in 0..num_servers {
         if i > 0 {
-            cluster_members.push_str(",");
+            cluster_members.push(',');
         }
         cluster_members.push_str(&format!("{},127.0.0.1:{}", i, starting_port + i));
     }
@@ -62,10 +62,12 @@ fn raft_node(
     let wal_root_loc_cloned = wal_root_loc.clone();
     let wal_path = wal_root_loc_cloned.to_str().unwrap();
 
-    std::fs::create_dir_all(wal_root_loc).expect(&format!(
-        "CLUSTER INIT: Could not create wal log dir for server ID {:?}",
-        server_id
-    ));
+    std::fs::create_dir_all(wal_root_loc).unwrap_or_else(|_| {
+        panic!(
+            "CLUSTER INIT: Could not create wal log dir for server ID {:?}",
+            server_id
+        )
+    });
 
     process! {
       tag: server_tag,